@@ -55,3 +55,11 @@ pub extern "C" fn destroy_i16_array_mut(arr: ArrayMut<i16>) {
   }
   drop(unsafe { arr.into_boxed_slice() });
 }
+
+#[no_mangle]
+pub extern "C" fn destroy_u16_array(arr: Array<u16>) {
+  if arr.data.is_null() {
+    return;
+  }
+  drop(unsafe { arr.into_boxed_slice() });
+}
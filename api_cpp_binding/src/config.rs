@@ -1,4 +1,4 @@
-use crate::{Either, Maybe, Unit, Utf8String};
+use crate::{Array, Either, Maybe, Unit, Utf8String};
 use std::mem::MaybeUninit;
 
 #[repr(C)]
@@ -23,6 +23,14 @@ pub struct GvbSimulatorConfig {
   pub pixel_scale: u32,
   pub foreground: u32,
   pub background: u32,
+  pub key_bindings: Array<GvbKeyBinding>,
+}
+
+#[repr(C)]
+pub struct GvbKeyBinding {
+  pub host_key: Utf8String,
+  /// Code of the key of the machine.
+  pub key: u8,
 }
 
 impl From<::config::Config> for Config {
@@ -57,6 +65,17 @@ impl From<::config::GvbSimulatorConfig> for GvbSimulatorConfig {
       pixel_scale: c.pixel_scale,
       foreground: c.foreground,
       background: c.background,
+      key_bindings: unsafe {
+        Array::new(
+          c.key_bindings
+            .into_iter()
+            .map(|b| GvbKeyBinding {
+              host_key: Utf8String::new(b.host_key),
+              key: b.key,
+            })
+            .collect(),
+        )
+      },
     }
   }
 }
@@ -1,9 +1,15 @@
+pub mod builder;
+pub mod builtin;
 pub mod device;
 pub mod diagnostic;
 pub mod document;
+pub mod keys;
 pub mod vm;
 
+pub use self::builder::*;
+pub use self::builtin::*;
 pub use self::device::*;
 pub use self::diagnostic::*;
 pub use self::document::*;
+pub use self::keys::*;
 pub use self::vm::*;
@@ -0,0 +1,170 @@
+use crate::{Either, GvbPrintFlushPolicy, Maybe, Utf8Str, Utf8String};
+use gvb_interp as gvb;
+use std::mem;
+
+pub struct GvbVmBuilder(pub(crate) gvb::VmBuilder);
+
+#[repr(C)]
+pub enum GvbVmPreset {
+  Default,
+  Authentic,
+  Development,
+  Ci,
+}
+
+#[repr(C)]
+pub enum GvbSpeedMode {
+  Host,
+  Authentic,
+  Fast,
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_new_vm_builder(
+  preset: GvbVmPreset,
+) -> *mut GvbVmBuilder {
+  let builder = match preset {
+    GvbVmPreset::Default => gvb::VmBuilder::new(),
+    GvbVmPreset::Authentic => gvb::VmBuilder::authentic(),
+    GvbVmPreset::Development => gvb::VmBuilder::development(),
+    GvbVmPreset::Ci => gvb::VmBuilder::ci(),
+  };
+  Box::into_raw(box GvbVmBuilder(builder))
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_vm_builder(builder: *mut GvbVmBuilder) {
+  drop(unsafe { Box::from_raw(builder) });
+}
+
+pub type GvbLoadVmBuilderResult = Either<Utf8String, *mut GvbVmBuilder>;
+
+/// Creates a builder from the options saved by `gvb_vm_builder_to_yaml`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_from_yaml(
+  content: Utf8Str,
+) -> GvbLoadVmBuilderResult {
+  match gvb::VmOptions::from_yaml(unsafe { content.as_str() }) {
+    Ok(options) => Either::Right(Box::into_raw(box GvbVmBuilder(
+      gvb::VmBuilder::with_options(options),
+    ))),
+    Err(err) => Either::Left(unsafe {
+      Utf8String::new(match err {
+        gvb::ParseVmOptionsError::Yaml(err) => {
+          format!("解析运行选项失败：{}", err)
+        }
+        gvb::ParseVmOptionsError::Other(err) => {
+          format!("运行选项错误：{}", err)
+        }
+      })
+    }),
+  }
+}
+
+/// The string must be freed by `destroy_string`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_to_yaml(
+  builder: *const GvbVmBuilder,
+) -> Utf8String {
+  unsafe { Utf8String::new((*builder).0.options().to_yaml()) }
+}
+
+/// Returns the error message if the options are invalid.
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_validate(
+  builder: *const GvbVmBuilder,
+) -> Maybe<Utf8String> {
+  match unsafe { (*builder).0.options().validate() } {
+    Ok(()) => Maybe::Nothing,
+    Err(err) => Maybe::Just(unsafe { Utf8String::new(err.to_string()) }),
+  }
+}
+
+fn update(
+  builder: *mut GvbVmBuilder,
+  f: impl FnOnce(gvb::VmBuilder) -> gvb::VmBuilder,
+) {
+  let builder = unsafe { &mut (*builder).0 };
+  *builder = f(mem::take(builder));
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_read_only(
+  builder: *mut GvbVmBuilder,
+  read_only: bool,
+) {
+  update(builder, |b| b.read_only(read_only));
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_selector_warning(
+  builder: *mut GvbVmBuilder,
+  enabled: bool,
+) {
+  update(builder, |b| b.selector_warning(enabled));
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_soft_limits(
+  builder: *mut GvbVmBuilder,
+  enabled: bool,
+) {
+  let limits = if enabled {
+    Some(gvb::SoftLimits::default())
+  } else {
+    None
+  };
+  update(builder, |b| b.soft_limits(limits));
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_print_flush_policy(
+  builder: *mut GvbVmBuilder,
+  policy: GvbPrintFlushPolicy,
+) {
+  update(builder, |b| b.print_flush_policy(policy.into()));
+}
+
+/// 0 means unlimited, same as `gvb_vm_set_output_quota`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_output_quota(
+  builder: *mut GvbVmBuilder,
+  per_exec: usize,
+  total: usize,
+) {
+  let quota = if per_exec == 0 && total == 0 {
+    None
+  } else {
+    Some(gvb::OutputQuota {
+      per_exec: Some(per_exec).filter(|&n| n != 0),
+      total: Some(total).filter(|&n| n != 0),
+    })
+  };
+  update(builder, |b| b.output_quota(quota));
+}
+
+/// The random number generator is seeded randomly if `seed` is `Nothing`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_rng_seed(
+  builder: *mut GvbVmBuilder,
+  seed: Maybe<u64>,
+) {
+  let seed = match seed {
+    Maybe::Just(seed) => Some(seed),
+    Maybe::Nothing => None,
+  };
+  update(builder, |b| b.rng_seed(seed));
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_builder_set_speed_mode(
+  builder: *mut GvbVmBuilder,
+  mode: GvbSpeedMode,
+) {
+  let mode = match mode {
+    GvbSpeedMode::Host => gvb::SpeedMode::Host,
+    GvbSpeedMode::Authentic => gvb::SpeedMode::Authentic,
+    GvbSpeedMode::Fast => gvb::SpeedMode::Fast,
+  };
+  update(builder, |b| b.speed_mode(mode));
+}
@@ -0,0 +1,62 @@
+use crate::array::Array;
+use crate::string::{destroy_string, Utf16Str, Utf8Str, Utf8String};
+use crate::Maybe;
+use gvb_interp as gvb;
+
+#[repr(C)]
+pub enum GvbBuiltinKind {
+  Statement,
+  Keyword,
+  Function,
+}
+
+#[repr(C)]
+pub struct GvbBuiltin {
+  pub name: Utf8Str,
+  pub kind: GvbBuiltinKind,
+  /// Syntax of statements, or signature of functions.
+  pub usage: Utf8String,
+  pub description: Utf8Str,
+}
+
+/// Looks up a keyword or system function by name, case-insensitively.
+#[no_mangle]
+pub extern "C" fn gvb_lookup_builtin(name: Utf16Str) -> Maybe<GvbBuiltin> {
+  let name = match unsafe { name.to_string() } {
+    Ok(name) => name,
+    Err(_) => return Maybe::Nothing,
+  };
+  gvb::builtin::lookup(&name)
+    .map(|b| GvbBuiltin {
+      name: unsafe { Utf8Str::new(b.name) },
+      kind: match b.kind {
+        gvb::builtin::BuiltinKind::Statement { .. } => {
+          GvbBuiltinKind::Statement
+        }
+        gvb::builtin::BuiltinKind::Keyword => GvbBuiltinKind::Keyword,
+        gvb::builtin::BuiltinKind::Function(_) => GvbBuiltinKind::Function,
+      },
+      usage: unsafe { Utf8String::new(b.usage()) },
+      description: unsafe { Utf8Str::new(b.description) },
+    })
+    .into()
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_builtin(builtin: GvbBuiltin) {
+  destroy_string(builtin.usage);
+}
+
+/// Returns names of all keywords and system functions, sorted. The array
+/// must be freed by `destroy_str_array`.
+#[no_mangle]
+pub extern "C" fn gvb_builtin_names() -> Array<Utf8Str> {
+  unsafe {
+    Array::new(
+      gvb::builtin::builtins()
+        .iter()
+        .map(|b| Utf8Str::new(b.name))
+        .collect(),
+    )
+  }
+}
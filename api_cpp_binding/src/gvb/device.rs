@@ -1,11 +1,25 @@
-use crate::{Array, Either, Maybe, Rect, Unit, Utf8Str, Utf8String};
+use crate::{
+  Array, ArrayMut, Either, Maybe, Rect, Unit, Utf8Str, Utf8String,
+};
 use gvb_interp as gvb;
+use gvb_interp::device::memory_watch::MemoryWatchId;
+use gvb_interp::device::Device;
+use gvb_interp::device::transliterate::Transliterator;
 use gvb_interp::machine::{self, InitError};
+use super::GvbLocation;
+use std::ffi::c_void;
 
 pub type GvbInitMachineResult = Either<Utf8String, Unit>;
 
 pub struct GvbDevice(pub(crate) gvb::device::default::DefaultDevice);
 
+/// Half-open range `[start, end)` of addresses.
+#[repr(C)]
+pub struct GvbMemoryRange {
+  pub start: usize,
+  pub end: usize,
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_init_machines() -> GvbInitMachineResult {
   match machine::init_machines() {
@@ -67,6 +81,94 @@ pub extern "C" fn gvb_device_blink_cursor(dev: *mut GvbDevice) {
   }
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum GvbTextFont {
+  Normal,
+  Small,
+}
+
+/// Dimensions of the text mode screen in characters.
+#[repr(C)]
+pub struct GvbTextGrid {
+  pub columns: u8,
+  pub rows: u8,
+  /// Width of half-width characters in pixels.
+  pub char_width: u8,
+  /// Height of a row in pixels.
+  pub char_height: u8,
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_device_text_grid(dev: *const GvbDevice) -> GvbTextGrid {
+  let grid = unsafe { (*dev).0.text_grid() };
+  GvbTextGrid {
+    columns: grid.columns,
+    rows: grid.rows,
+    char_width: grid.char_width,
+    char_height: grid.char_height,
+  }
+}
+
+/// Switches the font of the text mode screen, which clears the screen. Does
+/// nothing if the machine has no small font.
+#[no_mangle]
+pub extern "C" fn gvb_device_set_text_font(
+  dev: *mut GvbDevice,
+  font: GvbTextFont,
+) {
+  let mode = match font {
+    GvbTextFont::Normal => gvb::vm::instruction::PrintMode::NormalFont,
+    GvbTextFont::Small => gvb::vm::instruction::PrintMode::SmallFont,
+  };
+  unsafe {
+    (*dev).0.set_print_mode(mode);
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum GvbPresentPolicy {
+  Immediate,
+  Flush,
+  Newline,
+  Statement,
+  Manual,
+}
+
+/// Enables or disables double buffering. The pointer returned by
+/// `gvb_device_graphics_memory` is invalidated when double buffering is
+/// turned on or off.
+#[no_mangle]
+pub extern "C" fn gvb_device_set_present_policy(
+  dev: *mut GvbDevice,
+  policy: GvbPresentPolicy,
+) {
+  use gvb::device::default::PresentPolicy;
+  let policy = match policy {
+    GvbPresentPolicy::Immediate => PresentPolicy::Immediate,
+    GvbPresentPolicy::Flush => PresentPolicy::Flush,
+    GvbPresentPolicy::Newline => PresentPolicy::Newline,
+    GvbPresentPolicy::Statement => PresentPolicy::Statement,
+    GvbPresentPolicy::Manual => PresentPolicy::Manual,
+  };
+  unsafe {
+    (*dev).0.set_present_policy(policy);
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_device_present(dev: *mut GvbDevice) {
+  unsafe {
+    (*dev).0.present();
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_device_present_count(dev: *const GvbDevice) -> usize {
+  unsafe { (*dev).0.present_count() }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_device_screen_dirty_area(
   dev: *mut GvbDevice,
@@ -83,3 +185,125 @@ pub extern "C" fn gvb_device_screen_dirty_area(
     }
   }
 }
+
+#[no_mangle]
+pub extern "C" fn gvb_device_context(
+  dev: *mut GvbDevice,
+) -> Maybe<GvbLocation> {
+  unsafe {
+    match (*dev).0.context() {
+      Some(loc) => Maybe::Just(GvbLocation {
+        line: loc.line,
+        start_column: loc.range.start,
+        end_column: loc.range.end,
+      }),
+      None => Maybe::Nothing,
+    }
+  }
+}
+
+/// Returns a copy of `len` bytes of memory starting at `addr`, truncated to
+/// the 64KB address space. The returned array should be destroyed by
+/// `destroy_byte_string`.
+#[no_mangle]
+pub extern "C" fn gvb_device_dump_memory(
+  dev: *const GvbDevice,
+  addr: u16,
+  len: usize,
+) -> Array<u8> {
+  let start = addr as usize;
+  let bytes = unsafe { (*dev).0.dump_memory(start..start.saturating_add(len)) };
+  unsafe { Array::new(bytes) }
+}
+
+/// memory of `bytes` is not consumed.
+#[no_mangle]
+pub extern "C" fn gvb_device_load_memory(
+  dev: *mut GvbDevice,
+  addr: u16,
+  bytes: Array<u8>,
+) {
+  unsafe {
+    (*dev).0.load_memory(addr, bytes.as_slice());
+  }
+}
+
+/// Starts tracking modifications of `len` bytes of memory starting at `addr`.
+/// Returns the id of the watch.
+#[no_mangle]
+pub extern "C" fn gvb_device_watch_memory(
+  dev: *mut GvbDevice,
+  addr: u16,
+  len: usize,
+) -> usize {
+  let start = addr as usize;
+  unsafe { (*dev).0.watch_memory(start..start.saturating_add(len)).0 }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_device_unwatch_memory(dev: *mut GvbDevice, id: usize) {
+  unsafe {
+    (*dev).0.unwatch_memory(MemoryWatchId(id));
+  }
+}
+
+/// Returns the ranges of bytes modified since the watch is created or the
+/// last call, in ascending order. The returned array should be destroyed by
+/// `gvb_destroy_memory_range_array`.
+#[no_mangle]
+pub extern "C" fn gvb_device_memory_changes(
+  dev: *mut GvbDevice,
+  id: usize,
+) -> Array<GvbMemoryRange> {
+  let changes = unsafe { (*dev).0.memory_changes(MemoryWatchId(id)) };
+  let changes = changes
+    .unwrap_or_default()
+    .into_iter()
+    .map(|range| GvbMemoryRange {
+      start: range.start,
+      end: range.end,
+    })
+    .collect();
+  unsafe { Array::new(changes) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_memory_range_array(arr: Array<GvbMemoryRange>) {
+  drop(unsafe { arr.into_boxed_slice() });
+}
+
+/// Synthesizes the tones played by SOUND and PLAY statements since the last
+/// call, as mono signed 16-bit samples at `sample_rate` Hz. The returned array
+/// should be destroyed by `destroy_i16_array_mut`.
+#[no_mangle]
+pub extern "C" fn gvb_device_take_audio(
+  dev: *mut GvbDevice,
+  sample_rate: u32,
+) -> ArrayMut<i16> {
+  unsafe { ArrayMut::new((*dev).0.take_audio(sample_rate)) }
+}
+
+pub type GvbPrintCallback =
+  extern "C" fn(user_data: *mut c_void, text: Utf8Str, row: u8, column: u8);
+
+/// Registers `callback` to be called with the text printed by programs,
+/// decoded as UTF-8, and the 0-based screen position of its first character,
+/// e.g. to feed a screen reader. Replaces the previous callback, or
+/// unregisters the callback if `callback` is null. The text is only valid
+/// during the call.
+#[no_mangle]
+pub extern "C" fn gvb_device_on_print(
+  dev: *mut GvbDevice,
+  callback: Option<GvbPrintCallback>,
+  user_data: *mut c_void,
+) {
+  let transliterator = callback.map(|callback| {
+    Box::new(move |text: &str, row, column| {
+      callback(user_data, unsafe { Utf8Str::new(text) }, row, column);
+      None
+    }) as Box<dyn Transliterator>
+  });
+  unsafe {
+    (*dev).0.set_transliterator(transliterator);
+  }
+}
@@ -1,5 +1,6 @@
 use crate::array::Array;
-use crate::string::{destroy_string, Utf8Str, Utf8String};
+use crate::string::{destroy_string, Utf16Str, Utf8Str, Utf8String};
+use gvb_interp as gvb;
 
 #[repr(C)]
 pub enum GvbSeverity {
@@ -7,6 +8,50 @@ pub enum GvbSeverity {
   Error,
 }
 
+#[repr(C)]
+pub enum GvbDiagnosticPhase {
+  Lex,
+  Parse,
+  Compile,
+  Runtime,
+}
+
+impl From<gvb::DiagnosticPhase> for GvbDiagnosticPhase {
+  fn from(phase: gvb::DiagnosticPhase) -> Self {
+    match phase {
+      gvb::DiagnosticPhase::Lex => Self::Lex,
+      gvb::DiagnosticPhase::Parse => Self::Parse,
+      gvb::DiagnosticPhase::Compile => Self::Compile,
+      gvb::DiagnosticPhase::Runtime => Self::Runtime,
+    }
+  }
+}
+
+#[repr(C)]
+pub enum GvbExpectedSymbolKind {
+  Ident,
+  Label,
+  Real,
+  String,
+  Punc,
+  Keyword,
+  SysFunc,
+  Eol,
+  Expr,
+  Stmt,
+  Array,
+}
+
+/// `text` is the text of punctuations, keywords and system functions, and is
+/// empty for other kinds.
+#[repr(C)]
+pub struct GvbExpectedSymbol<M> {
+  pub kind: GvbExpectedSymbolKind,
+  pub text: M,
+}
+
+/// Diagnostics of a line are ordered by range, then by phase. `expected` is
+/// empty unless the diagnostic is a syntax error.
 #[repr(C)]
 pub struct GvbDiagnostic<M> {
   pub line: usize,
@@ -14,14 +59,73 @@ pub struct GvbDiagnostic<M> {
   pub end: usize,
   pub message: M,
   pub severity: GvbSeverity,
+  pub phase: GvbDiagnosticPhase,
+  pub expected: Array<GvbExpectedSymbol<M>>,
+}
+
+fn split_expected_symbol(
+  sym: &gvb::ExpectedSymbol,
+) -> (GvbExpectedSymbolKind, &str) {
+  match sym {
+    gvb::ExpectedSymbol::Ident => (GvbExpectedSymbolKind::Ident, ""),
+    gvb::ExpectedSymbol::Label => (GvbExpectedSymbolKind::Label, ""),
+    gvb::ExpectedSymbol::Real => (GvbExpectedSymbolKind::Real, ""),
+    gvb::ExpectedSymbol::String => (GvbExpectedSymbolKind::String, ""),
+    gvb::ExpectedSymbol::Punc(text) => (GvbExpectedSymbolKind::Punc, text),
+    gvb::ExpectedSymbol::Keyword(text) => {
+      (GvbExpectedSymbolKind::Keyword, text)
+    }
+    gvb::ExpectedSymbol::SysFunc(text) => {
+      (GvbExpectedSymbolKind::SysFunc, text)
+    }
+    gvb::ExpectedSymbol::Eol => (GvbExpectedSymbolKind::Eol, ""),
+    gvb::ExpectedSymbol::Expr => (GvbExpectedSymbolKind::Expr, ""),
+    gvb::ExpectedSymbol::Stmt => (GvbExpectedSymbolKind::Stmt, ""),
+    gvb::ExpectedSymbol::Array => (GvbExpectedSymbolKind::Array, ""),
+  }
+}
+
+pub(crate) fn expected_symbols_str(
+  expected: &[gvb::ExpectedSymbol],
+) -> Array<GvbExpectedSymbol<Utf8Str>> {
+  let symbols = expected
+    .iter()
+    .map(|sym| {
+      let (kind, text) = split_expected_symbol(sym);
+      GvbExpectedSymbol {
+        kind,
+        text: unsafe { Utf8Str::new(text) },
+      }
+    })
+    .collect();
+  unsafe { Array::new(symbols) }
+}
+
+pub(crate) fn expected_symbols_string(
+  expected: &[gvb::ExpectedSymbol],
+) -> Array<GvbExpectedSymbol<Utf8String>> {
+  let symbols = expected
+    .iter()
+    .map(|sym| {
+      let (kind, text) = split_expected_symbol(sym);
+      GvbExpectedSymbol {
+        kind,
+        text: unsafe { Utf8String::new(text.to_owned()) },
+      }
+    })
+    .collect();
+  unsafe { Array::new(symbols) }
 }
 
 #[no_mangle]
 pub extern "C" fn gvb_destroy_string_diagnostic_array(
   arr: Array<GvbDiagnostic<Utf8String>>,
 ) {
-  for diag in unsafe { arr.into_boxed_slice() }.iter() {
-    destroy_string(diag.message.clone());
+  for diag in unsafe { arr.into_boxed_slice() }.into_vec() {
+    destroy_string(diag.message);
+    for sym in unsafe { diag.expected.into_boxed_slice() }.into_vec() {
+      destroy_string(sym.text);
+    }
   }
 }
 
@@ -29,5 +133,25 @@ pub extern "C" fn gvb_destroy_string_diagnostic_array(
 pub extern "C" fn gvb_destroy_str_diagnostic_array(
   arr: Array<GvbDiagnostic<Utf8Str>>,
 ) {
-  drop(unsafe { arr.into_boxed_slice() });
+  for diag in unsafe { arr.into_boxed_slice() }.into_vec() {
+    drop(unsafe { diag.expected.into_boxed_slice() });
+  }
+}
+
+/// Converts `column`, an offset in UTF-16 code units into `line`, to the
+/// offset in characters.
+#[no_mangle]
+pub extern "C" fn gvb_char_column(line: Utf16Str, column: usize) -> usize {
+  gvb::Range::empty(column)
+    .to_char_range(unsafe { line.as_slice() })
+    .start
+}
+
+/// Converts `column`, an offset in UTF-16 code units into `line`, to the
+/// offset in bytes of the UTF-8 encoding of `line`.
+#[no_mangle]
+pub extern "C" fn gvb_utf8_column(line: Utf16Str, column: usize) -> usize {
+  gvb::Range::empty(column)
+    .to_utf8_range(unsafe { line.as_slice() })
+    .start
 }
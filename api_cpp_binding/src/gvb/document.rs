@@ -1,11 +1,19 @@
 use crate::{
-  destroy_string, Array, Either, GvbDevice, GvbDiagnostic, GvbSeverity,
-  GvbVirtualMachine, Maybe, Unit, Utf16Str, Utf8Str, Utf8String,
+  destroy_byte_string, destroy_str_array, destroy_string,
+  expected_symbols_str, gvb_destroy_str_diagnostic_array, Array, Either,
+  GvbDevice, GvbDiagnostic, GvbSeverity, GvbVirtualMachine, GvbVmBuilder,
+  Maybe, Unit, Utf16Str, Utf8Str, Utf8String,
 };
-use gvb_interp::{self as gvb, ContainsErrors};
+use gvb_interp as gvb;
+use std::ffi::c_void;
 use std::io;
 
-pub struct GvbDocument(gvb::Document);
+pub struct GvbDocument(gvb::Document, Option<DiagnosticsListener>);
+
+struct DiagnosticsListener {
+  callback: GvbDiagnosticsCallback,
+  user_data: *mut c_void,
+}
 
 #[repr(C)]
 pub struct GvbInsertText<S> {
@@ -25,7 +33,7 @@ pub type GvbLoadDocumentResult = Either<Utf8String, *mut GvbDocument>;
 pub extern "C" fn gvb_load_document(path: Utf16Str) -> GvbLoadDocumentResult {
   let path = unsafe { path.to_string() }.unwrap();
   match gvb::Document::load_file(path) {
-    Ok(doc) => Either::Right(Box::into_raw(box GvbDocument(doc))),
+    Ok(doc) => Either::Right(Box::into_raw(box GvbDocument(doc, None))),
     Err(err) => {
       let msg = match err {
         gvb::LoadDocumentError::Io(err) => io_error_to_string(err),
@@ -49,7 +57,7 @@ pub extern "C" fn gvb_load_document(path: Utf16Str) -> GvbLoadDocumentResult {
 
 #[no_mangle]
 pub extern "C" fn gvb_create_document() -> *mut GvbDocument {
-  Box::into_raw(box GvbDocument(gvb::Document::new()))
+  Box::into_raw(box GvbDocument(gvb::Document::new(), None))
 }
 
 #[repr(C)]
@@ -99,14 +107,102 @@ pub extern "C" fn gvb_document_device(
   Box::into_raw(box GvbDevice(unsafe { (*doc).0.create_device(data_dir) }))
 }
 
+/// Stores files opened by the program in a subdirectory of the data
+/// directory specific to the program if `enabled` is true.
+#[no_mangle]
+pub extern "C" fn gvb_document_set_device_storage_namespace(
+  doc: *mut GvbDocument,
+  device: *mut GvbDevice,
+  enabled: bool,
+) {
+  let namespace = if enabled {
+    Some(unsafe { (*doc).0.storage_namespace() })
+  } else {
+    None
+  };
+  unsafe { (*device).0.set_storage_namespace(namespace) }
+}
+
+#[repr(C)]
+pub struct GvbFileReference {
+  pub line: usize,
+  pub start_column: usize,
+  pub end_column: usize,
+  /// Nothing if the name is computed at runtime.
+  pub name: Maybe<Utf8String>,
+  /// True if the file must exist before the program runs.
+  pub required: bool,
+}
+
+/// Returns the files opened by the OPEN statements of the program. The array
+/// must be freed by `gvb_destroy_file_reference_array`.
+#[no_mangle]
+pub extern "C" fn gvb_document_file_references(
+  doc: *mut GvbDocument,
+) -> Array<GvbFileReference> {
+  let refs = unsafe { (*doc).0.file_references() }
+    .into_iter()
+    .map(|r| GvbFileReference {
+      line: r.line,
+      start_column: r.range.start,
+      end_column: r.range.end,
+      required: r.is_required(),
+      name: r.name.map(|name| unsafe { Utf8String::new(name) }).into(),
+    })
+    .collect();
+  unsafe { Array::new(refs) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_file_reference_array(
+  arr: Array<GvbFileReference>,
+) {
+  for r in unsafe { arr.into_boxed_slice() }.iter() {
+    if let Maybe::Just(name) = &r.name {
+      destroy_string(name.clone());
+    }
+  }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_document_vm(
   doc: *mut GvbDocument,
   device: *mut GvbDevice,
 ) -> Maybe<*mut GvbVirtualMachine> {
-  match unsafe { (*doc).0.create_vm(&mut (*device).0) } {
+  let builder = gvb::VmBuilder::new();
+  match unsafe { builder.build(&mut (*doc).0, &mut (*device).0) } {
     Ok(vm) => Maybe::Just(Box::into_raw(box GvbVirtualMachine(vm))),
-    Err(ContainsErrors) => Maybe::Nothing,
+    Err(gvb::BuildVmError::ContainsErrors) => Maybe::Nothing,
+    Err(gvb::BuildVmError::InvalidOptions(_)) => unreachable!(),
+  }
+}
+
+#[repr(C)]
+pub enum GvbBuildVmResult {
+  Vm(*mut GvbVirtualMachine),
+  ContainsErrors(Unit),
+  /// The string must be freed by `destroy_string`.
+  InvalidOptions(Utf8String),
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_document_vm_with_builder(
+  doc: *mut GvbDocument,
+  device: *mut GvbDevice,
+  builder: *const GvbVmBuilder,
+) -> GvbBuildVmResult {
+  match unsafe { (*builder).0.build(&mut (*doc).0, &mut (*device).0) } {
+    Ok(vm) => {
+      GvbBuildVmResult::Vm(Box::into_raw(box GvbVirtualMachine(vm)))
+    }
+    Err(gvb::BuildVmError::ContainsErrors) => {
+      GvbBuildVmResult::ContainsErrors(Unit::new())
+    }
+    Err(gvb::BuildVmError::InvalidOptions(err)) => {
+      GvbBuildVmResult::InvalidOptions(unsafe {
+        Utf8String::new(err.to_string())
+      })
+    }
   }
 }
 
@@ -138,6 +234,7 @@ pub extern "C" fn gvb_document_apply_edit(
   };
   unsafe {
     (*doc).0.apply_edit(edit);
+    notify_diagnostics(&mut *doc);
   }
 }
 
@@ -146,13 +243,20 @@ pub extern "C" fn gvb_document_diagnostics(
   doc: *mut GvbDocument,
 ) -> Array<GvbDiagnostic<Utf8Str>> {
   let line_diags = unsafe { (*doc).0.diagnostics() };
+  convert_diagnostics(0, line_diags)
+}
+
+fn convert_diagnostics(
+  first_line: usize,
+  line_diags: &[gvb::LineDiagnosis],
+) -> Array<GvbDiagnostic<Utf8Str>> {
   let diags = line_diags
-    .into_iter()
+    .iter()
     .enumerate()
     .flat_map(|(line, line_diag)| {
       let line_start = line_diag.line_start;
       line_diag.diagnostics.iter().map(move |diag| GvbDiagnostic {
-        line,
+        line: first_line + line,
         start: line_start + diag.range.start,
         end: line_start + diag.range.end,
         message: unsafe { Utf8Str::new(&diag.message) },
@@ -160,12 +264,79 @@ pub extern "C" fn gvb_document_diagnostics(
           gvb::Severity::Warning => GvbSeverity::Warning,
           gvb::Severity::Error => GvbSeverity::Error,
         },
+        phase: diag.phase.into(),
+        expected: expected_symbols_str(&diag.expected),
       })
     })
     .collect();
   unsafe { Array::new(diags) }
 }
 
+/// Diagnostics of a run of lines changed since the last update. The lines
+/// `start..start + removed` of the last update are replaced by `inserted`
+/// lines, whose diagnostics are in `diagnostics`. The diagnostics of the
+/// lines after them are unchanged, but moved by `shift` UTF-16 code units.
+#[repr(C)]
+pub struct GvbDiagnosticsUpdate {
+  pub start: usize,
+  pub removed: usize,
+  pub inserted: usize,
+  pub diagnostics: Array<GvbDiagnostic<Utf8Str>>,
+  pub shift: isize,
+}
+
+pub type GvbDiagnosticsCallback =
+  extern "C" fn(user_data: *mut c_void, update: *const GvbDiagnosticsUpdate);
+
+/// Registers `callback` to be called with the diagnostics of the lines
+/// whose diagnostics are changed, replacing the previous callback, or
+/// unregisters the callback if `callback` is null.
+///
+/// The callback is called at once with the diagnostics of all lines, as
+/// the first update. It is called synchronously on the thread calling
+/// `gvb_document_apply_edit`, `gvb_document_set_strict`,
+/// `gvb_document_set_never_assigned_var_lint` or
+/// `gvb_document_sync_machine_name`, and not called if no diagnostics are
+/// changed. Lines merely shifted by edits are not reported, but described by
+/// `shift`.
+/// The update and strings in it are only valid during the call, and the
+/// callback must not call any function on the document.
+#[no_mangle]
+pub extern "C" fn gvb_document_on_diagnostics(
+  doc: *mut GvbDocument,
+  callback: Option<GvbDiagnosticsCallback>,
+  user_data: *mut c_void,
+) {
+  let doc = unsafe { &mut *doc };
+  doc.1 = callback.map(|callback| DiagnosticsListener {
+    callback,
+    user_data,
+  });
+  if doc.1.is_some() {
+    // start over with the full diagnostics
+    doc.0.reset_diagnostics_update();
+    notify_diagnostics(doc);
+  }
+}
+
+fn notify_diagnostics(doc: &mut GvbDocument) {
+  let listener = match &doc.1 {
+    Some(listener) => listener,
+    None => return,
+  };
+  if let Some(update) = doc.0.diagnostics_update() {
+    let update = GvbDiagnosticsUpdate {
+      start: update.start,
+      removed: update.removed,
+      inserted: update.lines.len(),
+      diagnostics: convert_diagnostics(update.start, &update.lines),
+      shift: update.shift,
+    };
+    (listener.callback)(listener.user_data, &update);
+    gvb_destroy_str_diagnostic_array(update.diagnostics);
+  }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_destroy_document(doc: *mut GvbDocument) {
   drop(unsafe { Box::from_raw(doc) });
@@ -179,11 +350,58 @@ pub extern "C" fn gvb_document_text(doc: *mut GvbDocument) -> Utf8Str {
   }
 }
 
+/// In strict mode, extensions of the emulator which are not supported by the
+/// real machine are reported as errors.
+#[no_mangle]
+pub extern "C" fn gvb_document_set_strict(doc: *mut GvbDocument, strict: bool) {
+  unsafe {
+    (*doc).0.set_strict(strict);
+    notify_diagnostics(&mut *doc);
+  }
+}
+
+/// Reports variables read but never assigned with the severity, or disables
+/// the lint if `severity` is Nothing.
+#[no_mangle]
+pub extern "C" fn gvb_document_set_never_assigned_var_lint(
+  doc: *mut GvbDocument,
+  severity: Maybe<GvbSeverity>,
+) {
+  let severity = match severity {
+    Maybe::Just(GvbSeverity::Warning) => Some(gvb::Severity::Warning),
+    Maybe::Just(GvbSeverity::Error) => Some(gvb::Severity::Error),
+    Maybe::Nothing => None,
+  };
+  unsafe {
+    (*doc).0.set_never_assigned_var_lint(severity);
+    notify_diagnostics(&mut *doc);
+  }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_document_machine_name(doc: *mut GvbDocument) -> Utf8Str {
   unsafe { Utf8Str::new((*doc).0.machine_name()) }
 }
 
+/// Returns names of keywords and system functions supported by the machine of
+/// the document, sorted, for completion. The array must be freed by
+/// `destroy_str_array`.
+#[no_mangle]
+pub extern "C" fn gvb_document_builtin_names(
+  doc: *mut GvbDocument,
+) -> Array<Utf8Str> {
+  unsafe {
+    Array::new(
+      (*doc)
+        .0
+        .dialect()
+        .builtins()
+        .map(|b| Utf8Str::new(b.name))
+        .collect(),
+    )
+  }
+}
+
 #[repr(C)]
 pub struct GvbReplaceChar {
   pub start: usize,
@@ -207,12 +425,14 @@ pub type GvbDocSyncMachResult = Either<Utf8String, Array<GvbReplaceChar>>;
 pub extern "C" fn gvb_document_sync_machine_name(
   doc: *mut GvbDocument,
 ) -> GvbDocSyncMachResult {
-  match unsafe { (*doc).0.sync_machine_name() } {
+  let result = match unsafe { (*doc).0.sync_machine_name() } {
     Ok(edits) => Either::Right(unsafe {
       Array::new(edits.into_iter().map(From::from).collect())
     }),
     Err(err) => Either::Left(mach_prop_error_to_string(err)),
-  }
+  };
+  unsafe { notify_diagnostics(&mut *doc) };
+  result
 }
 
 fn mach_prop_error_to_string(err: gvb::MachinePropError) -> Utf8String {
@@ -272,6 +492,24 @@ pub extern "C" fn gvb_destroy_replace_char_array(reps: Array<GvbReplaceChar>) {
   drop(unsafe { reps.into_boxed_slice() });
 }
 
+/// Returns the replacements of full-width characters in numeric DATA items.
+/// The result must be destroyed by `gvb_destroy_replace_char_array`.
+#[no_mangle]
+pub extern "C" fn gvb_document_full_width_data_fixes(
+  doc: *mut GvbDocument,
+) -> Array<GvbReplaceChar> {
+  let fixes = unsafe { (*doc).0.compute_full_width_data_fixes() };
+  let fixes = fixes
+    .into_iter()
+    .map(|fix| GvbReplaceChar {
+      start: fix.range.start,
+      end: fix.range.end,
+      ch: fix.to,
+    })
+    .collect();
+  unsafe { Array::new(fixes) }
+}
+
 #[repr(C)]
 pub enum GvbLabelTarget {
   CurLine,
@@ -366,9 +604,184 @@ pub extern "C" fn gvb_document_relabel_edits(
   }
 }
 
+#[repr(C)]
+pub struct GvbPossibleLabelRef {
+  pub start: usize,
+  pub end: usize,
+  pub label: u16,
+  pub new_label: u16,
+  pub message: Utf8String,
+}
+
+/// Returns the numbers in DATA items and string literals which may be line
+/// references not updated by renumbering. Returns an empty array if the
+/// program cannot be renumbered, see `gvb_document_relabel_edits`.
+#[no_mangle]
+pub extern "C" fn gvb_document_possible_label_refs(
+  doc: *mut GvbDocument,
+  start: u16,
+  inc: u16,
+) -> Array<GvbPossibleLabelRef> {
+  let refs = match unsafe { (*doc).0.compute_relabel_report(start, inc) } {
+    Ok(report) => report.possible_label_refs,
+    Err(_) => vec![],
+  };
+  let refs = refs
+    .into_iter()
+    .map(|r| GvbPossibleLabelRef {
+      start: r.range.start,
+      end: r.range.end,
+      label: r.label,
+      new_label: r.new_label,
+      message: unsafe { Utf8String::new(r.message()) },
+    })
+    .collect();
+  unsafe { Array::new(refs) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_possible_label_ref_array(
+  refs: Array<GvbPossibleLabelRef>,
+) {
+  for r in unsafe { refs.into_boxed_slice() }.iter() {
+    destroy_string(r.message.clone());
+  }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_destroy_replace_text_array(edits: Array<GvbReplaceText>) {
   for edit in unsafe { edits.into_boxed_slice() }.iter() {
     destroy_string(edit.str.clone());
   }
 }
+
+#[repr(C)]
+pub struct GvbFeatures {
+  /// Names of statements and commands, sorted.
+  pub statements: Array<Utf8Str>,
+  /// Names of system functions, sorted.
+  pub functions: Array<Utf8Str>,
+  pub files: bool,
+  pub binary_files: bool,
+  pub call: bool,
+  pub peek: bool,
+  pub poke: bool,
+  pub graphics: bool,
+  pub sound: bool,
+  pub small_font: bool,
+  pub secondary_storage: bool,
+  pub memory_map: bool,
+  pub key_buffer_quit: bool,
+  pub sound_extension: bool,
+}
+
+/// Returns the features supported by the dialect and the machine profile of
+/// the document. The result must be destroyed by `gvb_destroy_features`.
+#[no_mangle]
+pub extern "C" fn gvb_document_features(
+  doc: *const GvbDocument,
+) -> GvbFeatures {
+  let features = unsafe { (*doc).0.features() };
+  let names = |builtins: &[&'static gvb::builtin::Builtin]| unsafe {
+    Array::new(builtins.iter().map(|b| Utf8Str::new(b.name)).collect())
+  };
+  GvbFeatures {
+    statements: names(&features.statements),
+    functions: names(&features.functions),
+    files: features.available.files,
+    binary_files: features.available.binary_files,
+    call: features.available.call,
+    peek: features.available.peek,
+    poke: features.available.poke,
+    graphics: features.available.graphics,
+    sound: features.available.sound,
+    small_font: features.small_font,
+    secondary_storage: features.secondary_storage_prefix.is_some(),
+    memory_map: features.memory_map,
+    key_buffer_quit: features.key_buffer_quit,
+    sound_extension: features.sound_extension,
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_features(features: GvbFeatures) {
+  destroy_str_array(features.statements);
+  destroy_str_array(features.functions);
+}
+
+#[repr(C)]
+pub struct GvbDataBlock {
+  pub start_line: usize,
+  /// Inclusive.
+  pub end_line: usize,
+  pub bytes: Array<u8>,
+}
+
+/// The result must be destroyed by `gvb_destroy_data_blocks`.
+#[no_mangle]
+pub extern "C" fn gvb_document_data_blocks(
+  doc: *mut GvbDocument,
+) -> Array<GvbDataBlock> {
+  let blocks = unsafe { (*doc).0.data_blocks() };
+  let blocks = blocks
+    .into_iter()
+    .map(|block| GvbDataBlock {
+      start_line: block.start_line,
+      end_line: block.end_line,
+      bytes: unsafe { Array::new(block.bytes) },
+    })
+    .collect();
+  unsafe { Array::new(blocks) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_data_blocks(blocks: Array<GvbDataBlock>) {
+  if blocks.data.is_null() {
+    return;
+  }
+  for block in unsafe { blocks.into_boxed_slice() }.into_vec() {
+    drop(unsafe { block.bytes.into_boxed_slice() });
+  }
+}
+
+/// 8-bit grayscale bitmap.
+#[repr(C)]
+pub struct GvbBitmap {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Array<u8>,
+}
+
+/// Decodes the 8x8 or 16x16 tiles packed in `bytes` and lays them out in
+/// `columns` columns. If `tile_size` is not 8 or 16, an empty bitmap is
+/// returned. The result must be destroyed by `gvb_destroy_bitmap`.
+#[no_mangle]
+pub extern "C" fn gvb_tile_sheet(
+  bytes: Array<u8>,
+  tile_size: usize,
+  columns: usize,
+) -> GvbBitmap {
+  let size = match tile_size {
+    8 => gvb::tile::TileSize::Size8,
+    16 => gvb::tile::TileSize::Size16,
+    _ => {
+      return GvbBitmap {
+        width: 0,
+        height: 0,
+        pixels: unsafe { Array::new(vec![]) },
+      }
+    }
+  };
+  let tiles = gvb::tile::decode_tiles(unsafe { bytes.as_slice() }, size);
+  let sheet = gvb::tile::sprite_sheet(&tiles, columns);
+  GvbBitmap {
+    width: sheet.width,
+    height: sheet.height,
+    pixels: unsafe { Array::new(sheet.to_luma8()) },
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_bitmap(bitmap: GvbBitmap) {
+  destroy_byte_string(bitmap.pixels);
+}
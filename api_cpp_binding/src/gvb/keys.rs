@@ -0,0 +1,72 @@
+use crate::array::Array;
+use crate::string::{Utf16Str, Utf8Str};
+use crate::{GvbDevice, Maybe};
+use gvb_interp::device::keys;
+
+#[repr(C)]
+pub struct GvbKey {
+  pub name: Utf8Str,
+  pub label: Utf8Str,
+  pub code: u8,
+}
+
+impl From<&'static keys::Key> for GvbKey {
+  fn from(key: &'static keys::Key) -> Self {
+    Self {
+      name: unsafe { Utf8Str::new(key.name) },
+      label: unsafe { Utf8Str::new(key.label) },
+      code: key.code,
+    }
+  }
+}
+
+/// Looks up a key of the machine by name, case-insensitively.
+#[no_mangle]
+pub extern "C" fn gvb_key_by_name(name: Utf16Str) -> Maybe<GvbKey> {
+  let name = match unsafe { name.to_string() } {
+    Ok(name) => name,
+    Err(_) => return Maybe::Nothing,
+  };
+  keys::key_by_name(&name).map(GvbKey::from).into()
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_key_by_code(code: u8) -> Maybe<GvbKey> {
+  keys::key_by_code(code).map(GvbKey::from).into()
+}
+
+/// Returns all keys with names. The array must be freed by
+/// `gvb_destroy_key_array`.
+#[no_mangle]
+pub extern "C" fn gvb_keys() -> Array<GvbKey> {
+  unsafe { Array::new(keys::KEYS.iter().map(GvbKey::from).collect()) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_key_array(arr: Array<GvbKey>) {
+  if arr.data.is_null() {
+    return;
+  }
+  drop(unsafe { arr.into_boxed_slice() });
+}
+
+#[repr(C)]
+pub struct GvbKeyPosition {
+  pub addr: u16,
+  pub bit: u8,
+}
+
+/// Returns the position of the key in the keyboard matrix of the machine of
+/// the device.
+#[no_mangle]
+pub extern "C" fn gvb_device_key_position(
+  dev: *const GvbDevice,
+  code: u8,
+) -> Maybe<GvbKeyPosition> {
+  unsafe { (*dev).0.key_position(code) }
+    .map(|pos| GvbKeyPosition {
+      addr: pos.addr,
+      bit: pos.bit,
+    })
+    .into()
+}
@@ -1,6 +1,7 @@
 use crate::{
-  destroy_byte_string, destroy_string, Array, ArrayMut, Either, GvbDevice,
-  GvbDiagnostic, GvbSeverity, Maybe, Unit, Utf16Str, Utf8Str, Utf8String,
+  destroy_byte_string, destroy_string, expected_symbols_string, Array,
+  ArrayMut, Either, GvbDevice, GvbDiagnostic, GvbSeverity, Maybe, Unit,
+  Utf16Str, Utf8Str, Utf8String,
 };
 use gvb_interp as gvb;
 use std::convert::TryInto;
@@ -50,14 +51,38 @@ pub enum GvbExecResult {
     prompt: Maybe<Utf8String>,
     /// Fields may be empty. If so, resume execution immediately.
     fields: Array<GvbKeyboardInputType>,
+    /// Values entered the last time, same length as `fields`.
+    defaults: Array<Maybe<Utf8String>>,
   },
   InKey,
   Error {
     location: GvbLocation,
     message: Utf8String,
+    /// Location of the FOR or WHILE statement of the loop which likely
+    /// causes the error.
+    loop_location: Maybe<GvbLocation>,
+  },
+  Breakpoint {
+    location: GvbLocation,
+  },
+  Yield,
+  Warning {
+    location: GvbLocation,
+    message: Utf8String,
+  },
+  OutputQuotaExceeded {
+    location: GvbLocation,
+    kind: GvbOutputQuotaKind,
+    printed: usize,
   },
 }
 
+#[repr(C)]
+pub enum GvbOutputQuotaKind {
+  PerExec,
+  Total,
+}
+
 #[repr(C)]
 pub struct GvbLocation {
   pub line: usize,
@@ -102,6 +127,8 @@ pub extern "C" fn gvb_compile_fn_body(
         gvb::Severity::Warning => GvbSeverity::Warning,
         gvb::Severity::Error => GvbSeverity::Error,
       },
+      phase: diag.phase.into(),
+      expected: expected_symbols_string(&diag.expected),
     })
     .collect();
   let diagnostics = unsafe { Array::new(diags) };
@@ -148,7 +175,11 @@ pub extern "C" fn gvb_vm_exec(
     gvb::ExecResult::End => GvbExecResult::End,
     gvb::ExecResult::Continue => GvbExecResult::Continue,
     gvb::ExecResult::Sleep(d) => GvbExecResult::Sleep(d.as_nanos() as u64),
-    gvb::ExecResult::KeyboardInput { prompt, fields } => {
+    gvb::ExecResult::KeyboardInput {
+      prompt,
+      fields,
+      defaults,
+    } => {
       GvbExecResult::KeyboardInput {
         prompt: match prompt {
           Some(prompt) => Maybe::Just(unsafe { Utf8String::new(prompt) }),
@@ -174,10 +205,42 @@ pub extern "C" fn gvb_vm_exec(
               .collect(),
           )
         },
+        defaults: unsafe {
+          Array::new(
+            defaults
+              .into_iter()
+              .map(|value| match value {
+                Some(value) => Maybe::Just(Utf8String::new(value)),
+                None => Maybe::Nothing,
+              })
+              .collect(),
+          )
+        },
       }
     }
     gvb::ExecResult::InKey => GvbExecResult::InKey,
-    gvb::ExecResult::Error { location, message } => GvbExecResult::Error {
+    gvb::ExecResult::Error {
+      location,
+      message,
+      loop_context,
+    } => GvbExecResult::Error {
+      location: GvbLocation {
+        line: location.line,
+        start_column: location.range.start,
+        end_column: location.range.end,
+      },
+      message: unsafe { Utf8String::new(message) },
+      loop_location: match loop_context {
+        Some(ctx) => Maybe::Just(GvbLocation {
+          line: ctx.location.line,
+          start_column: ctx.location.range.start,
+          end_column: ctx.location.range.end,
+        }),
+        None => Maybe::Nothing,
+      },
+    },
+    gvb::ExecResult::Yield => GvbExecResult::Yield,
+    gvb::ExecResult::Warning { location, message } => GvbExecResult::Warning {
       location: GvbLocation {
         line: location.line,
         start_column: location.range.start,
@@ -185,6 +248,29 @@ pub extern "C" fn gvb_vm_exec(
       },
       message: unsafe { Utf8String::new(message) },
     },
+    gvb::ExecResult::OutputQuotaExceeded {
+      location,
+      kind,
+      printed,
+    } => GvbExecResult::OutputQuotaExceeded {
+      location: GvbLocation {
+        line: location.line,
+        start_column: location.range.start,
+        end_column: location.range.end,
+      },
+      kind: match kind {
+        gvb::OutputQuotaKind::PerExec => GvbOutputQuotaKind::PerExec,
+        gvb::OutputQuotaKind::Total => GvbOutputQuotaKind::Total,
+      },
+      printed,
+    },
+    gvb::ExecResult::Breakpoint { location } => GvbExecResult::Breakpoint {
+      location: GvbLocation {
+        line: location.line,
+        start_column: location.range.start,
+        end_column: location.range.end,
+      },
+    },
   }
 }
 
@@ -194,14 +280,125 @@ pub type GvbStopVmResult = Either<Utf8String, Unit>;
 pub extern "C" fn gvb_vm_stop(vm: *mut GvbVirtualMachine) -> GvbStopVmResult {
   match unsafe { (*vm).0.stop() } {
     Ok(()) => Either::Right(Unit::new()),
-    Err(gvb::ExecResult::Error {
-      location: _,
-      message,
-    }) => Either::Left(unsafe { Utf8String::new(message) }),
+    Err(gvb::ExecResult::Error { message, .. }) => {
+      Either::Left(unsafe { Utf8String::new(message) })
+    }
     Err(_) => unreachable!(),
   }
 }
 
+/// Returns false if there is no statement at `offset`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_run_to(
+  vm: *mut GvbVirtualMachine,
+  offset: usize,
+) -> bool {
+  unsafe { (*vm).0.run_to(offset) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_clear_run_to(vm: *mut GvbVirtualMachine) {
+  unsafe {
+    (*vm).0.clear_run_to();
+  }
+}
+
+/// Returns false if the current statement cannot be skipped.
+#[no_mangle]
+pub extern "C" fn gvb_vm_skip_current_statement(
+  vm: *mut GvbVirtualMachine,
+) -> bool {
+  unsafe { (*vm).0.skip_current_statement() }.is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_set_read_only(
+  vm: *mut GvbVirtualMachine,
+  read_only: bool,
+) {
+  unsafe {
+    (*vm).0.set_read_only(read_only);
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_vm_set_selector_warning(
+  vm: *mut GvbVirtualMachine,
+  enabled: bool,
+) {
+  unsafe {
+    (*vm).0.set_selector_warning(enabled);
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum GvbPrintFlushPolicy {
+  Immediate,
+  PerNewline,
+  PerStatement,
+  Explicit,
+}
+
+/// Sets when text printed by PRINT statements is passed to the device.
+#[no_mangle]
+pub extern "C" fn gvb_vm_set_print_flush_policy(
+  vm: *mut GvbVirtualMachine,
+  policy: GvbPrintFlushPolicy,
+) {
+  unsafe {
+    (*vm).0.set_print_flush_policy(policy.into());
+  }
+}
+
+impl From<GvbPrintFlushPolicy> for gvb::PrintFlushPolicy {
+  fn from(policy: GvbPrintFlushPolicy) -> Self {
+    match policy {
+      GvbPrintFlushPolicy::Immediate => Self::Immediate,
+      GvbPrintFlushPolicy::PerNewline => Self::PerNewline,
+      GvbPrintFlushPolicy::PerStatement => Self::PerStatement,
+      GvbPrintFlushPolicy::Explicit => Self::Explicit,
+    }
+  }
+}
+
+/// Enables warnings of resources close to the budgets of the real machine.
+#[no_mangle]
+pub extern "C" fn gvb_vm_set_soft_limits(
+  vm: *mut GvbVirtualMachine,
+  enabled: bool,
+) {
+  let limits = if enabled {
+    Some(gvb::SoftLimits::default())
+  } else {
+    None
+  };
+  unsafe {
+    (*vm).0.set_soft_limits(limits);
+  }
+}
+
+/// Limits the bytes printed by the program in each `gvb_vm_exec` call and in
+/// total, beyond which `OutputQuotaExceeded` is returned. 0 means unlimited.
+#[no_mangle]
+pub extern "C" fn gvb_vm_set_output_quota(
+  vm: *mut GvbVirtualMachine,
+  per_exec: usize,
+  total: usize,
+) {
+  let quota = if per_exec == 0 && total == 0 {
+    None
+  } else {
+    Some(gvb::OutputQuota {
+      per_exec: Some(per_exec).filter(|&n| n != 0),
+      total: Some(total).filter(|&n| n != 0),
+    })
+  };
+  unsafe {
+    (*vm).0.set_output_quota(quota);
+  }
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_vm_reset(vm: *mut GvbVirtualMachine) {
   unsafe {
@@ -209,16 +406,74 @@ pub extern "C" fn gvb_vm_reset(vm: *mut GvbVirtualMachine) {
   }
 }
 
+#[repr(C)]
+pub enum GvbVmEvent {
+  Created,
+  Started,
+  Cleared(GvbLocation),
+  Restarted(GvbLocation),
+  Finished,
+  Errored(GvbLocation),
+}
+
+fn to_gvb_location(location: gvb::Location) -> GvbLocation {
+  GvbLocation {
+    line: location.line,
+    start_column: location.range.start,
+    end_column: location.range.end,
+  }
+}
+
+/// Returns the lifecycle events happened since the last call. The array must
+/// be freed by `gvb_destroy_vm_event_array`.
+#[no_mangle]
+pub extern "C" fn gvb_vm_take_events(
+  vm: *mut GvbVirtualMachine,
+) -> Array<GvbVmEvent> {
+  let events = unsafe { (*vm).0.take_events() }
+    .into_iter()
+    .map(|event| match event {
+      gvb::VmEvent::Created => GvbVmEvent::Created,
+      gvb::VmEvent::Started => GvbVmEvent::Started,
+      gvb::VmEvent::Cleared { location } => {
+        GvbVmEvent::Cleared(to_gvb_location(location))
+      }
+      gvb::VmEvent::Restarted { location } => {
+        GvbVmEvent::Restarted(to_gvb_location(location))
+      }
+      gvb::VmEvent::Finished => GvbVmEvent::Finished,
+      gvb::VmEvent::Errored { location } => {
+        GvbVmEvent::Errored(to_gvb_location(location))
+      }
+    })
+    .collect();
+  unsafe { Array::new(events) }
+}
+
+#[no_mangle]
+pub extern "C" fn gvb_destroy_vm_event_array(arr: Array<GvbVmEvent>) {
+  drop(unsafe { arr.into_boxed_slice() });
+}
+
 #[no_mangle]
 pub extern "C" fn gvb_reset_exec_result(result: *mut GvbExecResult) {
   match std::mem::replace(unsafe { &mut *result }, GvbExecResult::Continue) {
     GvbExecResult::End => {}
     GvbExecResult::Continue => {}
     GvbExecResult::Sleep(_) => {}
-    GvbExecResult::KeyboardInput { prompt, fields } => {
+    GvbExecResult::KeyboardInput {
+      prompt,
+      fields,
+      defaults,
+    } => {
       if let Maybe::Just(s) = prompt {
         destroy_string(s);
       }
+      for value in unsafe { defaults.into_boxed_slice() }.iter() {
+        if let Maybe::Just(s) = value {
+          destroy_string(s.clone());
+        }
+      }
       for field in unsafe { fields.into_boxed_slice() }.iter() {
         match field {
           GvbKeyboardInputType::Integer => {}
@@ -232,7 +487,13 @@ pub extern "C" fn gvb_reset_exec_result(result: *mut GvbExecResult) {
       }
     }
     GvbExecResult::InKey => {}
-    GvbExecResult::Error {
+    GvbExecResult::Error { message, .. } => {
+      destroy_string(message);
+    }
+    GvbExecResult::Breakpoint { location: _ } => {}
+    GvbExecResult::Yield => {}
+    GvbExecResult::OutputQuotaExceeded { .. } => {}
+    GvbExecResult::Warning {
       location: _,
       message,
     } => {
@@ -341,6 +602,18 @@ pub extern "C" fn gvb_byte_string_to_utf8_lossy(
   unsafe { Utf8String::new((*vm).0.string_from_byte_string_lossy(s)) }
 }
 
+/// Decodes to UTF-16 in batch, e.g. the text collected from print calls.
+/// Memory of `s` is not consumed. The result must be destroyed with
+/// `destroy_u16_array`.
+#[no_mangle]
+pub extern "C" fn gvb_byte_string_to_utf16_lossy(
+  vm: *const GvbVirtualMachine,
+  s: Array<u8>,
+) -> Array<u16> {
+  let s = unsafe { s.as_slice() };
+  unsafe { Array::new((*vm).0.byte_string_to_utf16_lossy(s)) }
+}
+
 #[repr(C)]
 #[derive(Clone)]
 pub enum GvbBinding {
@@ -3,6 +3,7 @@
 pub mod array;
 pub mod config;
 pub mod gvb;
+pub mod state;
 pub mod string;
 pub mod types;
 pub mod version;
@@ -10,6 +11,7 @@ pub mod version;
 pub use self::array::*;
 pub use self::config::*;
 pub use self::gvb::*;
+pub use self::state::*;
 pub use self::string::*;
 pub use self::types::*;
 pub use self::version::*;
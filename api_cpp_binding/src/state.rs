@@ -0,0 +1,144 @@
+use crate::{Array, Either, Maybe, Unit, Utf16Str, Utf8Str, Utf8String};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WindowGeometry {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+  pub maximized: bool,
+}
+
+impl From<::config::WindowGeometry> for WindowGeometry {
+  fn from(g: ::config::WindowGeometry) -> Self {
+    Self {
+      x: g.x,
+      y: g.y,
+      width: g.width,
+      height: g.height,
+      maximized: g.maximized,
+    }
+  }
+}
+
+impl From<WindowGeometry> for ::config::WindowGeometry {
+  fn from(g: WindowGeometry) -> Self {
+    Self {
+      x: g.x,
+      y: g.y,
+      width: g.width,
+      height: g.height,
+      maximized: g.maximized,
+    }
+  }
+}
+
+/// cbindgen:ignore
+static mut STATE: Option<::config::State> = None;
+
+fn state() -> &'static mut ::config::State {
+  unsafe { STATE.get_or_insert_with(Default::default) }
+}
+
+/// Returns the error message if the state file is corrupted. The state is
+/// reset in that case.
+#[no_mangle]
+pub extern "C" fn load_state() -> Maybe<Utf8String> {
+  use config::ConfigError;
+  let (s, err) = config::load_state();
+  *state() = s;
+  match err {
+    None => Maybe::Nothing,
+    Some(err) => Maybe::Just(unsafe {
+      Utf8String::new(match err {
+        ConfigError::Io(err) => format!("读取状态文件失败：{}", err),
+        ConfigError::Yaml(err) => format!("解析状态文件失败：{}", err),
+        ConfigError::Other(err) => format!("状态文件错误：{}", err),
+      })
+    }),
+  }
+}
+
+pub type SaveStateResult = Either<Utf8String, Unit>;
+
+#[no_mangle]
+pub extern "C" fn save_state() -> SaveStateResult {
+  match config::save_state(state()) {
+    Ok(()) => Either::Right(Unit::new()),
+    Err(err) => Either::Left(unsafe {
+      Utf8String::new(format!("保存状态文件失败：{}", err))
+    }),
+  }
+}
+
+/// The strings are valid until the recent files are modified. The array
+/// must be freed by `destroy_str_array`.
+#[no_mangle]
+pub extern "C" fn state_recent_files() -> Array<Utf8Str> {
+  unsafe {
+    Array::new(
+      state()
+        .recent_files
+        .iter()
+        .map(|file| Utf8Str::new(file))
+        .collect(),
+    )
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn state_add_recent_file(path: Utf16Str) {
+  state().add_recent_file(unsafe { path.to_string() }.unwrap());
+}
+
+#[no_mangle]
+pub extern "C" fn state_remove_recent_file(path: Utf16Str) {
+  state().remove_recent_file(&unsafe { path.to_string() }.unwrap());
+}
+
+#[no_mangle]
+pub extern "C" fn state_window_geometry(
+  name: Utf8Str,
+) -> Maybe<WindowGeometry> {
+  let name = unsafe { name.as_str() };
+  state().window_geometry(name).map(|&g| g.into()).into()
+}
+
+#[no_mangle]
+pub extern "C" fn state_set_window_geometry(
+  name: Utf8Str,
+  geometry: WindowGeometry,
+) {
+  let name = unsafe { name.as_str() };
+  state().set_window_geometry(name, geometry.into());
+}
+
+#[no_mangle]
+pub extern "C" fn state_machine() -> Maybe<Utf8Str> {
+  state()
+    .machine
+    .as_deref()
+    .map(|name| unsafe { Utf8Str::new(name) })
+    .into()
+}
+
+#[no_mangle]
+pub extern "C" fn state_set_machine(name: Utf8Str) {
+  state().machine = Some(unsafe { name.as_str() }.to_owned());
+}
+
+/// The layout is an opaque string, e.g. base64-encoded `QMainWindow` state.
+#[no_mangle]
+pub extern "C" fn state_debugger_layout() -> Maybe<Utf8Str> {
+  state()
+    .debugger_layout
+    .as_deref()
+    .map(|layout| unsafe { Utf8Str::new(layout) })
+    .into()
+}
+
+#[no_mangle]
+pub extern "C" fn state_set_debugger_layout(layout: Utf8Str) {
+  state().debugger_layout = Some(unsafe { layout.as_str() }.to_owned());
+}
@@ -59,6 +59,10 @@ impl Utf8Str {
 }
 
 impl Utf16Str {
+  pub(crate) unsafe fn as_slice<'a>(&self) -> &'a [u16] {
+    std::slice::from_raw_parts(self.data as *const _, self.len)
+  }
+
   pub(crate) unsafe fn to_string(&self) -> Result<String, FromUtf16Error> {
     String::from_utf16(std::slice::from_raw_parts(
       self.data as *const _,
@@ -0,0 +1,325 @@
+//! Checking of GVBASIC programs from the command line.
+//!
+//! Diagnostics are printed as text by default:
+//!
+//! ```text
+//! prog.txt:1:7: error: 缺少匹配的右括号
+//! ```
+//!
+//! Lines and columns are 1-based. Columns count UTF-16 code units.
+//!
+//! With `--format json`, a single JSON object is printed instead, for
+//! editors and CI systems:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "files": [
+//!     {
+//!       "file": "prog.txt",
+//!       "error": null,
+//!       "diagnostics": [
+//!         {
+//!           "range": {
+//!             "start": { "line": 1, "column": 7 },
+//!             "end": { "line": 1, "column": 8 }
+//!           },
+//!           "severity": "error",
+//!           "phase": "parse",
+//!           "message": "缺少匹配的右括号"
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! - `version` is the version of the schema, [`JSON_SCHEMA_VERSION`], which
+//!   is increased on incompatible changes.
+//! - `error` is the message of the error of loading the file, or null. The
+//!   diagnostics are empty if the file cannot be loaded.
+//! - `range` uses the same lines and columns as the text format. `end` is
+//!   exclusive.
+//! - `severity` is `error` or `warning`.
+//! - `phase` is the phase which produces the diagnostic: `lex`, `parse` or
+//!   `compile`.
+
+use gvb_interp::{
+  DiagnosticPhase, Document, LineDiagnosis, LoadDocumentError, Severity,
+};
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use util::json::to_string as json_string;
+
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Text,
+  Json,
+}
+
+/// Result of checking a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+  pub file: String,
+  /// Diagnostics of the file, or the message of the error of loading it.
+  pub result: Result<Vec<Diagnostic>, String>,
+}
+
+/// A diagnostic with 0-based lines and columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub line: usize,
+  pub start_column: usize,
+  pub end_column: usize,
+  pub severity: Severity,
+  pub phase: DiagnosticPhase,
+  pub message: String,
+}
+
+impl FileReport {
+  pub fn has_errors(&self) -> bool {
+    match &self.result {
+      Ok(diags) => diags.iter().any(|d| d.severity == Severity::Error),
+      Err(_) => true,
+    }
+  }
+}
+
+/// Checks a `.bas` or `.txt` file.
+pub fn check_file<P>(path: P) -> FileReport
+where
+  P: AsRef<Path>,
+{
+  let path = path.as_ref();
+  let result = Document::load_file(path)
+    .map_err(load_error_to_string)
+    .map(|mut doc| collect_diagnostics(doc.diagnostics()));
+  FileReport {
+    file: path.display().to_string(),
+    result,
+  }
+}
+
+/// Checks the content of a `.bas` or `.txt` file named `file`.
+pub fn check_data(file: &str, data: &[u8], is_bas: bool) -> FileReport {
+  let result = Document::load(data, is_bas)
+    .map_err(load_error_to_string)
+    .map(|mut doc| collect_diagnostics(doc.diagnostics()));
+  FileReport {
+    file: file.to_owned(),
+    result,
+  }
+}
+
+fn collect_diagnostics(line_diags: &[LineDiagnosis]) -> Vec<Diagnostic> {
+  line_diags
+    .iter()
+    .enumerate()
+    .flat_map(|(line, line_diag)| {
+      line_diag.diagnostics.iter().map(move |diag| Diagnostic {
+        line,
+        start_column: diag.range.start,
+        end_column: diag.range.end,
+        severity: diag.severity,
+        phase: diag.phase,
+        message: diag.message.clone(),
+      })
+    })
+    .collect()
+}
+
+fn load_error_to_string(err: LoadDocumentError) -> String {
+  match err {
+    LoadDocumentError::Io(err) => match err.kind() {
+      io::ErrorKind::PermissionDenied => "无权限".to_owned(),
+      io::ErrorKind::NotFound => "文件不存在".to_owned(),
+      _ => err.to_string(),
+    },
+    LoadDocumentError::LoadBas(err) => {
+      format!("文件偏移: {}, 错误信息: {}", err.location, err.message)
+    }
+    LoadDocumentError::LoadTxt(err) => {
+      format!("第 {} 行，错误信息: {}", err.location.0 + 1, err.message)
+    }
+    LoadDocumentError::UnknownExt(Some(_)) => "无法识别的后缀名".to_owned(),
+    LoadDocumentError::UnknownExt(None) => "文件缺少后缀名".to_owned(),
+  }
+}
+
+pub fn render(reports: &[FileReport], format: Format) -> String {
+  match format {
+    Format::Text => render_text(reports),
+    Format::Json => render_json(reports),
+  }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+  match severity {
+    Severity::Error => "error",
+    Severity::Warning => "warning",
+  }
+}
+
+fn phase_name(phase: DiagnosticPhase) -> &'static str {
+  match phase {
+    DiagnosticPhase::Lex => "lex",
+    DiagnosticPhase::Parse => "parse",
+    DiagnosticPhase::Compile => "compile",
+    DiagnosticPhase::Runtime => "runtime",
+  }
+}
+
+fn render_text(reports: &[FileReport]) -> String {
+  let mut out = String::new();
+  for report in reports {
+    match &report.result {
+      Ok(diags) => {
+        for diag in diags {
+          writeln!(
+            out,
+            "{}:{}:{}: {}: {}",
+            report.file,
+            diag.line + 1,
+            diag.start_column + 1,
+            severity_name(diag.severity),
+            diag.message
+          )
+          .unwrap();
+        }
+      }
+      Err(err) => writeln!(out, "{}: error: {}", report.file, err).unwrap(),
+    }
+  }
+  out
+}
+
+fn render_json(reports: &[FileReport]) -> String {
+  let mut out = String::new();
+  writeln!(out, "{{").unwrap();
+  writeln!(out, "  \"version\": {},", JSON_SCHEMA_VERSION).unwrap();
+  write!(out, "  \"files\": [").unwrap();
+  for (i, report) in reports.iter().enumerate() {
+    out.push_str(if i == 0 { "\n" } else { ",\n" });
+    writeln!(out, "    {{").unwrap();
+    writeln!(out, "      \"file\": {},", json_string(&report.file)).unwrap();
+    let diags = match &report.result {
+      Ok(diags) => {
+        writeln!(out, "      \"error\": null,").unwrap();
+        &diags[..]
+      }
+      Err(err) => {
+        writeln!(out, "      \"error\": {},", json_string(err)).unwrap();
+        &[]
+      }
+    };
+    write!(out, "      \"diagnostics\": [").unwrap();
+    for (j, diag) in diags.iter().enumerate() {
+      out.push_str(if j == 0 { "\n" } else { ",\n" });
+      writeln!(out, "        {{").unwrap();
+      writeln!(out, "          \"range\": {{").unwrap();
+      writeln!(
+        out,
+        "            \"start\": {{ \"line\": {}, \"column\": {} }},",
+        diag.line + 1,
+        diag.start_column + 1
+      )
+      .unwrap();
+      writeln!(
+        out,
+        "            \"end\": {{ \"line\": {}, \"column\": {} }}",
+        diag.line + 1,
+        diag.end_column + 1
+      )
+      .unwrap();
+      writeln!(out, "          }},").unwrap();
+      writeln!(
+        out,
+        "          \"severity\": \"{}\",",
+        severity_name(diag.severity)
+      )
+      .unwrap();
+      writeln!(out, "          \"phase\": \"{}\",", phase_name(diag.phase))
+        .unwrap();
+      writeln!(out, "          \"message\": {}", json_string(&diag.message))
+        .unwrap();
+      write!(out, "        }}").unwrap();
+    }
+    if diags.is_empty() {
+      writeln!(out, "]").unwrap();
+    } else {
+      writeln!(out, "\n      ]").unwrap();
+    }
+    write!(out, "    }}").unwrap();
+  }
+  if reports.is_empty() {
+    writeln!(out, "]").unwrap();
+  } else {
+    writeln!(out, "\n  ]").unwrap();
+  }
+  writeln!(out, "}}").unwrap();
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use insta::assert_snapshot;
+  use pretty_assertions::assert_eq;
+  use std::sync::Once;
+
+  static INIT: Once = Once::new();
+
+  fn check(file: &str, text: &str) -> FileReport {
+    INIT.call_once(|| {
+      gvb_interp::machine::init_machines_from_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../gvb_interp/machines.yaml"
+      ))
+      .unwrap();
+    });
+    check_data(file, text.as_bytes(), false)
+  }
+
+  fn reports() -> Vec<FileReport> {
+    vec![
+      check(
+        "a.txt",
+        "10 print \"a\\b\";(1\r\n20 goto 30\r\n30 a=1:print a$+1",
+      ),
+      check("b.txt", "10 end"),
+      FileReport {
+        file: "c.bas".to_owned(),
+        result: Err("文件不存在".to_owned()),
+      },
+    ]
+  }
+
+  #[test]
+  fn text_format() {
+    assert_snapshot!(render(&reports(), Format::Text));
+  }
+
+  #[test]
+  fn json_format() {
+    assert_snapshot!(render(&reports(), Format::Json));
+  }
+
+  #[test]
+  fn empty_json() {
+    assert_eq!(
+      render(&[], Format::Json),
+      "{\n  \"version\": 1,\n  \"files\": []\n}\n"
+    );
+  }
+
+  #[test]
+  fn has_errors() {
+    let reports = reports();
+    assert!(reports[0].has_errors());
+    assert!(!reports[1].has_errors());
+    assert!(reports[2].has_errors());
+  }
+}
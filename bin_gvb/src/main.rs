@@ -0,0 +1,55 @@
+use bin_gvb::Format;
+use clap::{crate_version, Arg, ArgAction, Command};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+  let matches = Command::new("gvb")
+    .version(crate_version!())
+    .about("GVBASIC tools")
+    .subcommand_required(true)
+    .subcommand(
+      Command::new("check")
+        .about("Check programs for errors and warnings")
+        .arg(
+          Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .help("output format of diagnostics"),
+        )
+        .arg(
+          Arg::new("FILE")
+            .help("source .bas or .txt files")
+            .required(true)
+            .action(ArgAction::Append),
+        ),
+    )
+    .get_matches();
+
+  let Some(("check", matches)) = matches.subcommand() else {
+    unreachable!()
+  };
+
+  if let Err(err) = gvb_interp::machine::init_machines() {
+    eprintln!("failed to load machine profiles: {:?}", err);
+    return ExitCode::from(2);
+  }
+
+  let format = match matches.get_one::<String>("format").unwrap().as_str() {
+    "json" => Format::Json,
+    _ => Format::Text,
+  };
+  let reports: Vec<_> = matches
+    .get_many::<String>("FILE")
+    .unwrap()
+    .map(bin_gvb::check_file)
+    .collect();
+  print!("{}", bin_gvb::render(&reports, format));
+
+  if reports.iter().any(|r| r.has_errors()) {
+    ExitCode::FAILURE
+  } else {
+    ExitCode::SUCCESS
+  }
+}
@@ -1,10 +1,15 @@
 #![feature(stmt_expr_attributes)]
 
+use gvb_interp::device::keys;
 use linked_hash_map::LinkedHashMap;
 use std::io;
 use util::config;
 use yaml_rust::{Yaml, YamlLoader};
 
+pub use self::state::*;
+
+mod state;
+
 #[derive(Clone)]
 pub struct Config {
   pub gvb: GvbConfig,
@@ -27,6 +32,17 @@ pub struct GvbSimulatorConfig {
   pub pixel_scale: u32,
   pub foreground: u32,
   pub background: u32,
+  pub key_bindings: Vec<KeyBinding>,
+}
+
+/// Binds a key of the host to a key of the machine, in addition to the
+/// default bindings of the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+  /// Name of the key of the host, which is interpreted by the host.
+  pub host_key: String,
+  /// Code of the key of the machine.
+  pub key: u8,
 }
 
 const DEFAULT_CONFIG: Config = Config {
@@ -40,6 +56,7 @@ const DEFAULT_CONFIG: Config = Config {
       foreground: #[allow(clippy::mistyped_literal_suffixes)]
       0x31_31_32,
       background: 0x7a_88_70,
+      key_bindings: vec![],
     },
   },
 };
@@ -77,7 +94,11 @@ impl From<&str> for ConfigError {
 
 pub fn load_config() -> Result<Config, ConfigError> {
   let content = config::load_config_file("config.yaml")?;
-  let mut docs = YamlLoader::load_from_str(&content)?;
+  parse_config(&content)
+}
+
+fn parse_config(content: &str) -> Result<Config, ConfigError> {
+  let mut docs = YamlLoader::load_from_str(content)?;
   let mut config = DEFAULT_CONFIG.clone();
   if docs.is_empty() {
     return Ok(config);
@@ -179,6 +200,12 @@ fn load_gvb_config(
         gvb_config.simulator.background = c;
       }
 
+      if let Some(bindings) =
+        simulator.remove(&Yaml::String("key-bindings".into()))
+      {
+        gvb_config.simulator.key_bindings = read_key_bindings(bindings)?;
+      }
+
       if let Some((key, _)) = simulator.pop_front() {
         return Err(
           format!(
@@ -234,6 +261,43 @@ fn read_rgb(
   }
 }
 
+fn read_key_bindings(bindings: Yaml) -> Result<Vec<KeyBinding>, ConfigError> {
+  if bindings.is_null() {
+    return Ok(vec![]);
+  }
+  let bindings = bindings
+    .into_hash()
+    .ok_or("gvbasic.simulator.key-bindings is not object")?;
+  let mut result = vec![];
+  for (host_key, key) in bindings {
+    let host_key = match host_key {
+      Yaml::String(s) => s,
+      _ => {
+        return Err(
+          format!(
+            "key {} in gvbasic.simulator.key-bindings is not string",
+            yaml_to_string(&host_key)
+          )
+          .into(),
+        )
+      }
+    };
+    let name = key.as_str().ok_or_else(|| {
+      format!("gvbasic.simulator.key-bindings.{host_key} is not string")
+    })?;
+    let key = keys::key_by_name(name).ok_or_else(|| {
+      format!(
+        "gvbasic.simulator.key-bindings.{host_key} is unknown key '{name}'"
+      )
+    })?;
+    result.push(KeyBinding {
+      host_key,
+      key: key.code,
+    });
+  }
+  Ok(result)
+}
+
 fn yaml_to_string(yaml: &Yaml) -> String {
   match yaml {
     Yaml::Null => "~".to_owned(),
@@ -247,3 +311,49 @@ fn yaml_to_string(yaml: &Yaml) -> String {
     _ => panic!(),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn key_bindings() {
+    let config = parse_config(
+      r#"
+gvbasic:
+  simulator:
+    key-bindings:
+      F5: f1
+      Ctrl+H: Help
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+      config.gvb.simulator.key_bindings,
+      vec![
+        KeyBinding {
+          host_key: "F5".to_owned(),
+          key: 28,
+        },
+        KeyBinding {
+          host_key: "Ctrl+H".to_owned(),
+          key: 25,
+        },
+      ]
+    );
+
+    let err = parse_config(
+      r#"
+gvbasic:
+  simulator:
+    key-bindings:
+      F5: foo
+"#,
+    );
+    assert!(matches!(
+      err,
+      Err(ConfigError::Other(msg))
+        if msg == "gvbasic.simulator.key-bindings.F5 is unknown key 'foo'"
+    ));
+  }
+}
@@ -0,0 +1,279 @@
+//! UI state persisted across sessions in state.yaml, e.g. recently opened
+//! files and window geometry.
+//!
+//! Unlike config.yaml, state.yaml is written by the program and is not meant
+//! to be edited by hand, so invalid entries are dropped instead of reported.
+
+use linked_hash_map::LinkedHashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use util::config;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use crate::ConfigError;
+
+const STATE_FILE: &str = "state.yaml";
+
+/// The corrupted state file is renamed to this file when loading.
+const BACKUP_FILE: &str = "state.yaml.bak";
+
+pub const MAX_RECENT_FILES: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+  /// Most recently opened first.
+  pub recent_files: Vec<String>,
+  /// Geometry of windows, keyed by window name.
+  pub windows: LinkedHashMap<String, WindowGeometry>,
+  /// Name of the last selected machine profile.
+  pub machine: Option<String>,
+  /// Layout of debugger panels, opaque to this crate.
+  pub debugger_layout: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowGeometry {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+  pub maximized: bool,
+}
+
+impl State {
+  /// Moves `path` to the front of recent files. The oldest file is dropped if
+  /// there are more than `MAX_RECENT_FILES` files.
+  pub fn add_recent_file(&mut self, path: impl Into<String>) {
+    let path = path.into();
+    self.recent_files.retain(|p| p != &path);
+    self.recent_files.insert(0, path);
+    self.recent_files.truncate(MAX_RECENT_FILES);
+  }
+
+  pub fn remove_recent_file(&mut self, path: &str) {
+    self.recent_files.retain(|p| p != path);
+  }
+
+  /// Removes recent files which no longer exist.
+  pub fn prune_recent_files(&mut self) {
+    self.recent_files.retain(|p| Path::new(p).is_file());
+  }
+
+  pub fn window_geometry(&self, name: &str) -> Option<&WindowGeometry> {
+    self.windows.get(name)
+  }
+
+  pub fn set_window_geometry(
+    &mut self,
+    name: impl Into<String>,
+    geometry: WindowGeometry,
+  ) {
+    self.windows.insert(name.into(), geometry);
+  }
+}
+
+/// Loads state.yaml and prunes recent files which no longer exist. A missing
+/// file yields the default state.
+///
+/// If the file is corrupted, it is renamed to state.yaml.bak, and the default
+/// state is returned along with the error, so that the state can be saved
+/// again without losing the corrupted file.
+pub fn load_state() -> (State, Option<ConfigError>) {
+  let path = match config::config_file_path(STATE_FILE) {
+    Ok(path) => path,
+    Err(err) => return (State::default(), Some(err.into())),
+  };
+  let content = match fs::read_to_string(&path) {
+    Ok(content) => content,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+      return (State::default(), None)
+    }
+    Err(err) => return (State::default(), Some(err.into())),
+  };
+  match parse_state(&content) {
+    Ok(mut state) => {
+      state.prune_recent_files();
+      (state, None)
+    }
+    Err(err) => {
+      let _ = fs::rename(&path, path.with_file_name(BACKUP_FILE));
+      (State::default(), Some(err))
+    }
+  }
+}
+
+/// Saves state.yaml. The file is written to a temporary file first, so that
+/// it is not corrupted if the program is killed while saving.
+pub fn save_state(state: &State) -> io::Result<()> {
+  let path = config::config_file_path(STATE_FILE)?;
+  let tmp_path = path.with_extension("yaml.tmp");
+  fs::write(&tmp_path, emit_state(state))?;
+  fs::rename(tmp_path, path)
+}
+
+fn parse_state(content: &str) -> Result<State, ConfigError> {
+  let mut docs = YamlLoader::load_from_str(content)?;
+  let mut state = State::default();
+  let doc = match docs.pop() {
+    Some(Yaml::Null) | None => return Ok(state),
+    Some(doc) => doc,
+  };
+  let obj = doc.into_hash().ok_or("toplevel is not object")?;
+
+  if let Some(files) = obj.get(&key("recent-files")).and_then(Yaml::as_vec) {
+    state.recent_files = files
+      .iter()
+      .filter_map(|file| file.as_str().map(str::to_owned))
+      .take(MAX_RECENT_FILES)
+      .collect();
+  }
+
+  if let Some(windows) = obj.get(&key("windows")).and_then(Yaml::as_hash) {
+    for (name, geometry) in windows {
+      if let (Some(name), Some(geometry)) =
+        (name.as_str(), parse_window_geometry(geometry))
+      {
+        state.windows.insert(name.to_owned(), geometry);
+      }
+    }
+  }
+
+  state.machine = obj
+    .get(&key("machine"))
+    .and_then(Yaml::as_str)
+    .map(str::to_owned);
+  state.debugger_layout = obj
+    .get(&key("debugger-layout"))
+    .and_then(Yaml::as_str)
+    .map(str::to_owned);
+
+  Ok(state)
+}
+
+fn parse_window_geometry(geometry: &Yaml) -> Option<WindowGeometry> {
+  let geometry = geometry.as_hash()?;
+  let int = |name| geometry.get(&key(name)).and_then(Yaml::as_i64);
+  Some(WindowGeometry {
+    x: int("x")?.try_into().ok()?,
+    y: int("y")?.try_into().ok()?,
+    width: int("width")?.try_into().ok()?,
+    height: int("height")?.try_into().ok()?,
+    maximized: geometry
+      .get(&key("maximized"))
+      .and_then(Yaml::as_bool)
+      .unwrap_or(false),
+  })
+}
+
+fn emit_state(state: &State) -> String {
+  let mut obj = LinkedHashMap::new();
+  obj.insert(
+    key("recent-files"),
+    Yaml::Array(
+      state
+        .recent_files
+        .iter()
+        .map(|file| Yaml::String(file.clone()))
+        .collect(),
+    ),
+  );
+
+  let mut windows = LinkedHashMap::new();
+  for (name, geometry) in &state.windows {
+    let mut obj = LinkedHashMap::new();
+    obj.insert(key("x"), Yaml::Integer(geometry.x as _));
+    obj.insert(key("y"), Yaml::Integer(geometry.y as _));
+    obj.insert(key("width"), Yaml::Integer(geometry.width as _));
+    obj.insert(key("height"), Yaml::Integer(geometry.height as _));
+    obj.insert(key("maximized"), Yaml::Boolean(geometry.maximized));
+    windows.insert(Yaml::String(name.clone()), Yaml::Hash(obj));
+  }
+  obj.insert(key("windows"), Yaml::Hash(windows));
+
+  if let Some(machine) = &state.machine {
+    obj.insert(key("machine"), Yaml::String(machine.clone()));
+  }
+  if let Some(layout) = &state.debugger_layout {
+    obj.insert(key("debugger-layout"), Yaml::String(layout.clone()));
+  }
+
+  let mut out = String::new();
+  YamlEmitter::new(&mut out).dump(&Yaml::Hash(obj)).unwrap();
+  out.push('\n');
+  out
+}
+
+fn key(name: &str) -> Yaml {
+  Yaml::String(name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn recent_files() {
+    let mut state = State::default();
+    for i in 0..12 {
+      state.add_recent_file(format!("{i}.bas"));
+    }
+    state.add_recent_file("5.bas");
+    assert_eq!(state.recent_files.len(), MAX_RECENT_FILES);
+    assert_eq!(&state.recent_files[..3], ["5.bas", "11.bas", "10.bas"]);
+    assert_eq!(state.recent_files.last().unwrap(), "2.bas");
+
+    state.remove_recent_file("11.bas");
+    assert_eq!(&state.recent_files[..2], ["5.bas", "10.bas"]);
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut state = State {
+      recent_files: vec!["/a/b.bas".to_owned(), "c:\\d.txt".to_owned()],
+      machine: Some("PC1000A".to_owned()),
+      debugger_layout: Some("AAAA/w==".to_owned()),
+      ..State::default()
+    };
+    state.set_window_geometry(
+      "gvbsim",
+      WindowGeometry {
+        x: -10,
+        y: 20,
+        width: 640,
+        height: 480,
+        maximized: true,
+      },
+    );
+    assert_eq!(parse_state(&emit_state(&state)).unwrap(), state);
+  }
+
+  #[test]
+  fn invalid_entries() {
+    let state = parse_state(
+      r#"
+recent-files: [a.bas, 1, [b], c.bas]
+windows:
+  main: {x: 1, y: 2, width: 3, height: 4}
+  sim: {x: 1, y: 2, width: -3, height: 4}
+  edit: 5
+machine: [x]
+foo: bar
+"#,
+    )
+    .unwrap();
+    assert_eq!(state.recent_files, ["a.bas", "c.bas"]);
+    assert_eq!(
+      state.windows.keys().collect::<Vec<_>>(),
+      vec![&"main".to_owned()]
+    );
+    assert_eq!(state.machine, None);
+    assert_eq!(parse_state("").unwrap(), State::default());
+  }
+
+  #[test]
+  fn corrupted() {
+    assert!(parse_state("recent-files: [a.bas").is_err());
+    assert!(parse_state("- a.bas").is_err());
+  }
+}
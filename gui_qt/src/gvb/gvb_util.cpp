@@ -39,6 +39,8 @@ std::optional<api::GvbValue> inputString(
   types[0].tag = api::GvbKeyboardInputType::Tag::String;
   res.keyboard_input.fields.data = types;
   res.keyboard_input.fields.len = 1;
+  res.keyboard_input.defaults.data = nullptr;
+  res.keyboard_input.defaults.len = 0;
 
   api::GvbKeyboardInput initial[1];
   initial[0].tag = api::GvbKeyboardInput::Tag::String;
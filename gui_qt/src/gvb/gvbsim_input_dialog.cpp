@@ -257,7 +257,11 @@ void GvbSimInputDialog::initUi(
             }
             if (error) {
               msg->setText(QString("错误(第 %1 列)：%2")
-                             .arg(firstError.start + 1)
+                             .arg(
+                               api::gvb_char_column(
+                                 {s.utf16(), static_cast<size_t>(s.size())},
+                                 firstError.start)
+                               + 1)
                              .arg(QString::fromUtf8(
                                firstError.message.data,
                                firstError.message.len)));
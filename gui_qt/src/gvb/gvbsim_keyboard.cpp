@@ -3,10 +3,13 @@
 #include <QFont>
 #include <QGridLayout>
 #include <QHash>
+#include <QKeySequence>
 #include <QPushButton>
 #include <QString>
 #include <QTimer>
 
+#include "api.h"
+
 static const QHash<int, uint8_t> KEY_MAPPINGS {
   {Qt::Key_F1, 28},        {Qt::Key_F2, 29},       {Qt::Key_F3, 30},
   {Qt::Key_F4, 31},
@@ -42,6 +45,17 @@ static const QHash<int, uint8_t> KEY_MAPPINGS {
 };
 
 uint8_t qtKeyToWqxKey(int key) {
+  const auto &bindings = api::config()->gvb.simulator.key_bindings;
+  if (bindings.len) {
+    auto name = QKeySequence(key).toString();
+    for (size_t i = 0; i < bindings.len; i++) {
+      const auto &b = bindings.data[i];
+      auto hostKey = QString::fromUtf8(b.host_key.data, b.host_key.len);
+      if (hostKey.compare(name, Qt::CaseInsensitive) == 0) {
+        return b.key;
+      }
+    }
+  }
   return KEY_MAPPINGS[key];
 }
 
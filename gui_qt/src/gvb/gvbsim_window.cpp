@@ -15,6 +15,7 @@
 #include "gvbsim_screen.h"
 
 const size_t EXEC_STEPS = 50;
+const size_t OUTPUT_QUOTA = 1 << 20;
 
 GvbSimWindow::GvbSimWindow(QWidget *parent, GvbEditor *editor) :
   QMainWindow(parent),
@@ -70,6 +71,8 @@ void GvbSimWindow::reset(
     api::gvb_destroy_vm(m_vm);
   }
   m_vm = vm;
+  api::gvb_vm_set_soft_limits(vm, true);
+  api::gvb_vm_set_output_quota(vm, 0, OUTPUT_QUOTA);
   m_bindingModel.setVm(vm);
   if (m_device) {
     api::gvb_destroy_device(m_device);
@@ -263,6 +266,7 @@ void GvbSimWindow::execLater() {
         emit m_editor->stop();
         return;
       case api::GvbExecResult::Tag::Continue:
+      case api::GvbExecResult::Tag::Yield:
         break;
       case api::GvbExecResult::Tag::Sleep:
         sleep(m_execResult.sleep._0);
@@ -309,16 +313,59 @@ void GvbSimWindow::execLater() {
         emit m_editor->stop();
         return;
       }
+      case api::GvbExecResult::Tag::Breakpoint:
+        m_execResult.tag = api::GvbExecResult::Tag::Continue;
+        emit m_editor->pause();
+        return;
+      case api::GvbExecResult::Tag::Warning: {
+        auto msg = QString::fromUtf8(
+          m_execResult.warning.message.data,
+          m_execResult.warning.message.len);
+        m_message.setValue(QString("警告：") + msg);
+        break;
+      }
+      case api::GvbExecResult::Tag::OutputQuotaExceeded: {
+        auto result = QMessageBox::question(
+          this,
+          "提示",
+          QString("程序已输出 %1 字节，是否继续运行？")
+            .arg(m_execResult.output_quota_exceeded.printed));
+        if (result != QMessageBox::StandardButton::Yes) {
+          emit m_editor->stop();
+          return;
+        }
+        break;
+      }
     }
 
     api::gvb_reset_exec_result(&m_execResult);
     m_execResult = api::gvb_vm_exec(m_vm, m_execInput, EXEC_STEPS);
     api::gvb_reset_exec_input(&m_execInput);
+    handleVmEvents();
 
     execLater();
   });
 }
 
+void GvbSimWindow::handleVmEvents() {
+  auto events = api::gvb_vm_take_events(m_vm);
+  for (size_t i = 0; i < events.len; i++) {
+    auto tag = events.data[i].tag;
+    if (tag == api::GvbVmEvent::Tag::Restarted) {
+      m_message.setValue("程序已重新运行");
+    }
+    if (
+      tag == api::GvbVmEvent::Tag::Restarted ||
+      tag == api::GvbVmEvent::Tag::Cleared) {
+      // the program cleared the variables by itself
+      if (m_bindingView->isEnabled()) {
+        m_bindingModel.enable();
+      }
+    }
+  }
+  api::gvb_destroy_vm_event_array(events);
+}
+
 void GvbSimWindow::sleep(std::uint64_t ns) {
   QTimer::singleShot((ns + 500'000) / 1'000'000, this, [this] {
     m_execResult.tag = api::GvbExecResult::Tag::Continue;
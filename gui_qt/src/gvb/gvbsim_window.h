@@ -39,6 +39,7 @@ private:
   void reset();
   void execLater();
   void sleep(std::uint64_t ns);
+  void handleVmEvents();
   void startCursorTimer();
   void startRepaintTimer();
   void stopCursorTimer();
@@ -2,6 +2,7 @@
 
 #include <QApplication>
 #include <QCloseEvent>
+#include <QDir>
 #include <QDragEnterEvent>
 #include <QDropEvent>
 #include <QFileDialog>
@@ -123,6 +124,13 @@ void MainWindow::initMenu() {
     });
   }
 
+  m_mnuRecent = mnuFile->addMenu("最近打开的文件(&R)");
+  connect(
+    m_mnuRecent,
+    &QMenu::aboutToShow,
+    this,
+    &MainWindow::updateRecentFiles);
+
   mnuFile->addSeparator();
 
   m_actSave = mnuFile->addAction("保存(&S)");
@@ -231,6 +239,22 @@ void MainWindow::openFile() {
   openFileByPath(path, screen());
 }
 
+void MainWindow::updateRecentFiles() {
+  m_mnuRecent->clear();
+  auto files = api::state_recent_files();
+  for (size_t i = 0; i < files.len; i++) {
+    auto path = QString::fromUtf8(files.data[i].data, files.data[i].len);
+    auto act = m_mnuRecent->addAction(QDir::toNativeSeparators(path));
+    connect(act, &QAction::triggered, this, [path, this] {
+      openFileByPath(path);
+    });
+  }
+  if (files.len == 0) {
+    m_mnuRecent->addAction("（无）")->setEnabled(false);
+  }
+  api::destroy_str_array(files);
+}
+
 void MainWindow::openFileByPath(const QString &path) {
   if (!m_actOpen->isEnabled()) {
     return;
@@ -287,13 +311,22 @@ void MainWindow::openFileByPath(const QString &path, QScreen *screen) {
 
   QTimer::singleShot(0, widget, [widget, path, this] {
     auto result = widget->load(path);
+    auto absPath = QFileInfo(path).absoluteFilePath();
     if (auto err = std::get_if<QString>(&result)) {
       QMessageBox::critical(this, "文件打开失败", *err);
       replaceTool(nullptr);
       m_openFilePath.setValue(QString());
       m_loaded.setValue(false);
+      api::state_remove_recent_file(
+        {absPath.utf16(), static_cast<size_t>(absPath.size())});
     } else {
       m_loaded.setValue(true);
+      api::state_add_recent_file(
+        {absPath.utf16(), static_cast<size_t>(absPath.size())});
+    }
+    auto saveResult = api::save_state();
+    if (saveResult.tag == api::SaveStateResult::Tag::Left) {
+      api::destroy_string(saveResult.left._0);
     }
   });
 
@@ -626,6 +659,18 @@ ActionResult MainWindow::loadConfig(QWidget *parent) {
     }
   }
 
+  {
+    auto err = api::load_state();
+    if (err.tag == api::Maybe<api::Utf8String>::Tag::Just) {
+      QMessageBox::warning(
+        parent,
+        "警告",
+        QString("状态文件加载失败，最近打开的文件等记录已重置：%1")
+          .arg(QString::fromUtf8(err.just._0.data, err.just._0.len)));
+      api::destroy_string(err.just._0);
+    }
+  }
+
   emit Config::instance()->configChanged();
 
   return ActionResult::Succeed;
@@ -40,6 +40,7 @@ private:
 
 private slots:
   void openFile();
+  void updateRecentFiles();
   void createFile(const Tool &);
   ActionResult saveFile();
   ActionResult saveFileAs(bool save = false);
@@ -63,6 +64,7 @@ private:
   QNetworkAccessManager *m_networkMan;
   QMenu *m_mnuEdit;
   QMenu *m_mnuNew;
+  QMenu *m_mnuRecent;
 
   QAction *m_actOpen;
   QAction *m_actSave;
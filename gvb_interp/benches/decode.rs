@@ -0,0 +1,54 @@
+#![feature(test)]
+
+extern crate test;
+
+use gvb_interp::device::default::DefaultDevice;
+use gvb_interp::vm::r#type::ByteString;
+use gvb_interp::{machine, Document};
+use std::sync::Once;
+use test::Bencher;
+
+static INIT: Once = Once::new();
+
+/// Text as printed by a typical program: mostly ASCII, with some GB2312
+/// characters.
+fn printed_text() -> Vec<u8> {
+  let mut bytes = vec![];
+  for i in 0..1000 {
+    bytes.extend_from_slice(b"SCORE: ");
+    bytes.extend_from_slice(i.to_string().as_bytes());
+    bytes.extend_from_slice(b" \xb5\xc3\xb7\xd6 ");
+    bytes.extend_from_slice(b"PRESS ANY KEY TO CONTINUE\r");
+  }
+  bytes
+}
+
+fn load_doc() -> (Document, DefaultDevice) {
+  INIT.call_once(|| machine::init_machines().unwrap());
+  let doc = Document::load("10 END", false).unwrap();
+  let device = doc.create_device(".");
+  (doc, device)
+}
+
+#[bench]
+fn per_line(b: &mut Bencher) {
+  let bytes = printed_text();
+  let (mut doc, mut device) = load_doc();
+  let vm = doc.create_vm(&mut device).ok().unwrap();
+  b.iter(|| {
+    let mut out: Vec<u16> = vec![];
+    for line in bytes.split_inclusive(|&b| b == b'\r') {
+      let s = vm.string_from_byte_string_lossy(ByteString::from(line.to_vec()));
+      out.extend(s.encode_utf16());
+    }
+    out
+  });
+}
+
+#[bench]
+fn batch(b: &mut Bencher) {
+  let bytes = printed_text();
+  let (mut doc, mut device) = load_doc();
+  let vm = doc.create_vm(&mut device).ok().unwrap();
+  b.iter(|| vm.byte_string_to_utf16_lossy(&bytes));
+}
@@ -0,0 +1,72 @@
+#![feature(test)]
+
+extern crate test;
+
+use gvb_interp::{machine, Document, ExecInput, ExecResult};
+use std::sync::Once;
+use test::Bencher;
+
+static INIT: Once = Once::new();
+
+fn bench_program(b: &mut Bencher, text: &str) {
+  INIT.call_once(|| machine::init_machines().unwrap());
+  let mut doc = Document::load(text.replace('\n', "\r\n"), false).unwrap();
+  let mut device = doc.create_device(".");
+  b.iter(|| {
+    let mut vm = match doc.create_vm(&mut device) {
+      Ok(vm) => vm,
+      Err(_) => panic!("program contains errors"),
+    };
+    vm.start();
+    loop {
+      match vm.exec(ExecInput::None, usize::MAX) {
+        ExecResult::End => break,
+        ExecResult::Continue => {}
+        result => panic!("unexpected result: {result:?}"),
+      }
+    }
+  });
+}
+
+#[bench]
+fn dim_empty(b: &mut Bencher) {
+  bench_program(b, "10 DIM A$(1000),B$(100,100)");
+}
+
+#[bench]
+fn fill(b: &mut Bencher) {
+  bench_program(
+    b,
+    r#"
+10 DIM A$(1000)
+20 FOR I=0 TO 1000:A$(I)=STR$(I)+"ABC":NEXT
+"#
+    .trim(),
+  );
+}
+
+#[bench]
+fn grow(b: &mut Bencher) {
+  bench_program(
+    b,
+    r#"
+10 DIM A$(1000)
+20 FOR J=1 TO 5:FOR I=0 TO 1000:A$(I)=A$(I)+"AB":NEXT:NEXT
+30 FOR I=0 TO 1000:A$(I)=LEFT$(A$(I),3):NEXT
+"#
+    .trim(),
+  );
+}
+
+#[bench]
+fn read(b: &mut Bencher) {
+  bench_program(
+    b,
+    r#"
+10 DIM A$(1000)
+20 FOR I=0 TO 1000:A$(I)="ABCDEFGH":NEXT
+30 FOR I=0 TO 1000:L=L+LEN(A$(I)):NEXT
+"#
+    .trim(),
+  );
+}
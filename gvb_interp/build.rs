@@ -8,6 +8,7 @@ use std::path::Path;
 fn main() -> Result<(), Box<dyn Error>> {
   build_gb2312_mapping()?;
   build_gvb_keyword_mapping()?;
+  build_gwbasic_keyword_mapping()?;
 
   Ok(())
 }
@@ -56,6 +57,25 @@ fn build_gb2312_mapping() -> Result<(), Box<dyn Error>> {
   }
   writeln!(&mut file, "}};")?;
 
+  // dense table for batch decoding, indexed by
+  // (high byte - 0xA1) * 94 + (low byte - 0xA1), 0 for unmapped codes
+  let mut table = vec![0u16; 94 * 94];
+  for &(gbcode, unicode) in &mapping {
+    let hi = (gbcode >> 8) as usize - 0xa1;
+    let lo = (gbcode & 0xff) as usize - 0xa1;
+    table[hi * 94 + lo] = unicode;
+  }
+  writeln!(
+    &mut file,
+    "pub(crate) static GB2312_TABLE: [u16; {}] = [",
+    table.len()
+  )?;
+  for row in table.chunks(16) {
+    let row = row.iter().map(|u| format!("{u}")).collect::<Vec<_>>();
+    writeln!(&mut file, "  {},", row.join(", "))?;
+  }
+  writeln!(&mut file, "];")?;
+
   Ok(())
 }
 
@@ -117,3 +137,41 @@ fn build_gvb_keyword_mapping() -> Result<(), Box<dyn Error>> {
 
   Ok(())
 }
+
+fn build_gwbasic_keyword_mapping() -> Result<(), Box<dyn Error>> {
+  println!("cargo:rerun-if-changed=data/gwbasic_keyword.txt");
+
+  let file = fs::read_to_string("data/gwbasic_keyword.txt")?;
+
+  let mut mapping: Vec<(u16, &str)> = vec![];
+
+  for line in file.lines() {
+    if line.is_empty() {
+      continue;
+    }
+    let segments = line.split_whitespace().collect::<Vec<_>>();
+    let token = u16::from_str_radix(segments[0], 16)?;
+    mapping.push((token, segments[1]));
+  }
+
+  let out_dir = env::var("OUT_DIR")?;
+
+  let mut file = OpenOptions::new()
+    .create(true)
+    .write(true)
+    .truncate(true)
+    .open(Path::new(&out_dir).join("gwbasic_keyword.rs"))?;
+
+  writeln!(&mut file, "use phf::phf_map;")?;
+  writeln!(&mut file)?;
+  writeln!(
+    &mut file,
+    "pub(crate) static GWBASIC_TOKEN_TO_KEYWORD: ::phf::Map<u16, &'static str> = phf_map! {{"
+  )?;
+  for (token, str) in &mapping {
+    writeln!(&mut file, "  {token}u16 => {str:?},")?;
+  }
+  writeln!(&mut file, "}};")?;
+
+  Ok(())
+}
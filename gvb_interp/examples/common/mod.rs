@@ -0,0 +1,18 @@
+//! Setup shared by the examples.
+
+use std::path::Path;
+
+/// Initializes the machine profiles. `machines.yaml` is looked up in the
+/// working directory, so the examples run in the manifest directory.
+pub fn init() {
+  std::env::set_current_dir(env!("CARGO_MANIFEST_DIR")).unwrap();
+  gvb_interp::machine::init_machines().unwrap();
+}
+
+/// Reads a bundled sample program in `examples/programs`.
+pub fn program(name: &str) -> String {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("examples/programs")
+    .join(name);
+  std::fs::read_to_string(path).unwrap()
+}
@@ -0,0 +1,77 @@
+//! Drives a VM with the debugger API: runs to a statement, skips a statement,
+//! and inspects and modifies variables while the program is paused.
+//!
+//! ```sh
+//! cargo run --example debugger
+//! ```
+
+mod common;
+
+use gvb_interp::device::default::DefaultDevice;
+use gvb_interp::util::mbf5::Mbf5;
+use gvb_interp::{
+  Binding, Document, ExecInput, ExecResult, Value, VirtualMachine,
+};
+
+/// Returns the offset of the first statement of the line with the label.
+fn stmt_offset(doc: &Document, label: &str) -> usize {
+  let label = format!("{label} ");
+  let text = doc.text().to_string();
+  text.find(&label).unwrap() + label.len()
+}
+
+fn real_var(vm: &VirtualMachine<DefaultDevice>, name: &str) -> f64 {
+  match vm.bindings().get(name) {
+    Some(Binding::Var {
+      value: Value::Real(value),
+    }) => f64::from(*value),
+    _ => panic!("{name} is not assigned"),
+  }
+}
+
+fn run_until_paused(vm: &mut VirtualMachine<DefaultDevice>) -> ExecResult {
+  loop {
+    match vm.exec(ExecInput::None, 10_000) {
+      ExecResult::Continue => {}
+      result => return result,
+    }
+  }
+}
+
+fn main() {
+  common::init();
+
+  let mut doc =
+    Document::load(common::program("double.txt").replace('\n', "\r\n"), false)
+      .unwrap();
+  let mut device = doc.create_device(std::env::temp_dir());
+  let line30 = stmt_offset(&doc, "30");
+  let line40 = stmt_offset(&doc, "40");
+  let mut vm = doc.create_vm(&mut device).unwrap();
+  vm.start();
+
+  // Do not print A.
+  assert!(vm.run_to(line30));
+  let result = run_until_paused(&mut vm);
+  println!("paused: {result:?}");
+  assert!(matches!(result, ExecResult::Breakpoint { .. }));
+  vm.skip_current_statement().unwrap();
+
+  assert!(vm.run_to(line40));
+  let result = run_until_paused(&mut vm);
+  assert!(matches!(result, ExecResult::Breakpoint { .. }));
+  println!("A = {}", real_var(&vm, "A"));
+  assert_eq!(real_var(&vm, "A"), 32.0);
+  vm.modify_var("A", Value::Real(Mbf5::from(7u8)));
+
+  assert_eq!(run_until_paused(&mut vm), ExecResult::End);
+  println!("B = {}", real_var(&vm, "B"));
+  assert_eq!(real_var(&vm, "B"), 8.0);
+  drop(vm);
+  assert_eq!(device.text_lines()[0], "DONE");
+}
+
+#[test]
+fn example() {
+  main();
+}
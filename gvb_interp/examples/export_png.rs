@@ -0,0 +1,115 @@
+//! Runs a graphics program and exports the screen, as well as the sprites
+//! embedded in its DATA statements, as PNG images.
+//!
+//! ```sh
+//! cargo run --example export_png [OUTPUT_DIR]
+//! ```
+
+mod common;
+
+use gvb_interp::tile::{self, Bitmap, TileSize};
+use gvb_interp::{Document, ExecResult, Interpreter};
+use std::path::{Path, PathBuf};
+
+fn main() {
+  common::init();
+
+  let out_dir = std::env::args()
+    .nth(1)
+    .map_or_else(std::env::temp_dir, PathBuf::from);
+  let text = common::program("sprites.txt");
+
+  let mut interp = Interpreter::new(std::env::temp_dir());
+  interp.load(&text);
+  loop {
+    match interp.run(100_000).unwrap() {
+      ExecResult::End => break,
+      ExecResult::Continue => {}
+      result => panic!("unexpected result: {result:?}"),
+    }
+  }
+  let screen = Bitmap {
+    width: 160,
+    height: 80,
+    data: interp.screen().to_vec(),
+  };
+  assert!(screen.pixel(0, 0));
+  write_png(&out_dir.join("screen.png"), &screen);
+
+  let mut doc = Document::load(text.replace('\n', "\r\n"), false).unwrap();
+  let blocks = doc.data_blocks();
+  assert_eq!(blocks.len(), 1);
+  let tiles = blocks[0].tiles(TileSize::Size8);
+  assert_eq!(tiles.len(), 2);
+  let sheet = tile::sprite_sheet(&tiles, 2);
+  write_png(&out_dir.join("sprites.png"), &sheet);
+}
+
+/// Writes an 8-bit grayscale PNG image, with the image data stored in
+/// uncompressed deflate blocks.
+fn write_png(path: &Path, bitmap: &Bitmap) {
+  let luma = bitmap.to_luma8();
+  let mut raw = vec![];
+  for row in luma.chunks(bitmap.width) {
+    raw.push(0); // filter type: none
+    raw.extend_from_slice(row);
+  }
+
+  let mut zlib = vec![0x78, 0x01];
+  let mut blocks = raw.chunks(0xffff).peekable();
+  while let Some(block) = blocks.next() {
+    zlib.push(blocks.peek().is_none() as u8);
+    let len = block.len() as u16;
+    zlib.extend_from_slice(&len.to_le_bytes());
+    zlib.extend_from_slice(&(!len).to_le_bytes());
+    zlib.extend_from_slice(block);
+  }
+  zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+  let mut ihdr = vec![];
+  ihdr.extend_from_slice(&(bitmap.width as u32).to_be_bytes());
+  ihdr.extend_from_slice(&(bitmap.height as u32).to_be_bytes());
+  ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+  let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+  for (kind, data) in [(b"IHDR", ihdr), (b"IDAT", zlib), (b"IEND", vec![])] {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(&data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+  }
+
+  std::fs::write(path, png).unwrap();
+  println!("wrote {}", path.display());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  b << 16 | a
+}
+
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &byte in data {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        0xedb8_8320 ^ (crc >> 1)
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+#[test]
+fn example() {
+  main();
+}
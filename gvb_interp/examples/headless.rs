@@ -0,0 +1,44 @@
+//! Runs a program without a screen, answering its INPUT statement, and
+//! prints the text on the screen when it ends.
+//!
+//! ```sh
+//! cargo run --example headless
+//! ```
+
+mod common;
+
+use gvb_interp::vm::r#type::ByteString;
+use gvb_interp::{ExecInput, ExecResult, Interpreter, KeyboardInput};
+
+fn main() {
+  common::init();
+
+  let mut interp = Interpreter::new(std::env::temp_dir());
+  interp.load(&common::program("greet.txt"));
+  loop {
+    match interp.run(10_000).unwrap() {
+      ExecResult::End => break,
+      ExecResult::Continue | ExecResult::Sleep(_) => {}
+      ExecResult::KeyboardInput { fields, .. } => {
+        let input = fields
+          .iter()
+          .map(|_| KeyboardInput::String(ByteString::from(b"WQX".to_vec())))
+          .collect();
+        interp.provide_input(ExecInput::KeyboardInput(input));
+      }
+      result => panic!("unexpected result: {result:?}"),
+    }
+  }
+
+  let lines = interp.device().text_lines();
+  for line in &lines {
+    println!("{line}");
+  }
+  assert!(lines.iter().any(|line| line == "HELLO, WQX"));
+  assert!(lines.iter().any(|line| line == "SUM=55"));
+}
+
+#[test]
+fn example() {
+  main();
+}
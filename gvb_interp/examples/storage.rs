@@ -0,0 +1,81 @@
+//! Routes the files of a program to a custom storage: files whose names start
+//! with `B:` are opened through a storage which logs the opened files and
+//! keeps them in a directory of its own.
+//!
+//! ```sh
+//! cargo run --example storage
+//! ```
+
+mod common;
+
+use gvb_interp::device::default::{DirStorage, Storage, StorageFile};
+use gvb_interp::{ExecResult, Interpreter};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+struct LoggingStorage {
+  inner: DirStorage,
+  opened: Rc<RefCell<Vec<String>>>,
+}
+
+impl Storage for LoggingStorage {
+  fn open(
+    &mut self,
+    name: &str,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<Box<dyn StorageFile>> {
+    let mode = if write { "write" } else { "read" };
+    self.opened.borrow_mut().push(format!("{name} ({mode})"));
+    self.inner.open(name, write, truncate)
+  }
+}
+
+fn main() {
+  common::init();
+
+  let dir = std::env::temp_dir()
+    .join(format!("gvb_storage_example_{}", std::process::id()));
+  let sd_dir = dir.join("sdcard");
+  std::fs::create_dir_all(&sd_dir).unwrap();
+
+  let mut interp = Interpreter::new(&dir);
+  interp.load(&common::program("scores.txt"));
+  let opened = Rc::new(RefCell::new(vec![]));
+  interp.device_mut().set_secondary_storage(
+    "B:",
+    Box::new(LoggingStorage {
+      inner: DirStorage::new(&sd_dir),
+      opened: opened.clone(),
+    }),
+  );
+  loop {
+    match interp.run(10_000).unwrap() {
+      ExecResult::End => break,
+      ExecResult::Continue => {}
+      result => panic!("unexpected result: {result:?}"),
+    }
+  }
+
+  for name in opened.borrow().iter() {
+    println!("opened {name}");
+  }
+  let lines = interp.device().text_lines();
+  println!("{}", lines[0]);
+  assert_eq!(lines[0], "TOTAL=60");
+  assert_eq!(
+    &opened.borrow()[..],
+    &["SCORES.DAT (write)", "SCORES.DAT (read)"]
+  );
+  assert!(sd_dir.join("SCORES.DAT").exists());
+  assert!(!dir.join("B:SCORES.DAT").exists());
+
+  drop(interp);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn example() {
+  main();
+}
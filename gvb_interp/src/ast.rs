@@ -130,6 +130,39 @@ impl Range {
   pub fn range(&self) -> std::ops::Range<usize> {
     self.start..self.end
   }
+
+  /// Converts the range in UTF-16 code units of `line` to the range in
+  /// characters. Offsets past the end of `line` are clamped.
+  pub fn to_char_range(&self, line: impl AsRef<[u16]>) -> Self {
+    let line = line.as_ref();
+    Self::new(
+      utf16_to_column(line, self.start, |_| 1),
+      utf16_to_column(line, self.end, |_| 1),
+    )
+  }
+
+  /// Converts the range in UTF-16 code units of `line` to the range in bytes
+  /// of the UTF-8 encoding of `line`. Offsets past the end of `line` are
+  /// clamped.
+  pub fn to_utf8_range(&self, line: impl AsRef<[u16]>) -> Self {
+    let line = line.as_ref();
+    Self::new(
+      utf16_to_column(line, self.start, char::len_utf8),
+      utf16_to_column(line, self.end, char::len_utf8),
+    )
+  }
+}
+
+/// Unpaired surrogates are counted as U+FFFD.
+fn utf16_to_column(
+  line: &[u16],
+  offset: usize,
+  char_len: fn(char) -> usize,
+) -> usize {
+  let offset = offset.min(line.len());
+  char::decode_utf16(line[..offset].iter().copied())
+    .map(|c| char_len(c.unwrap_or(char::REPLACEMENT_CHARACTER)))
+    .sum()
 }
 
 impl Debug for Range {
@@ -137,3 +170,19 @@ impl Debug for Range {
     f.pad(&format!("{}..{}", self.start, self.end))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use widestring::utf16str;
+
+  #[test]
+  fn column_conversion() {
+    let line = utf16str!("10 PRINT \"中文😀\";A");
+    let range = Range::new(10, 14);
+    assert_eq!(range.to_char_range(line), Range::new(10, 13));
+    assert_eq!(range.to_utf8_range(line), Range::new(10, 20));
+    assert_eq!(Range::new(16, 30).to_char_range(line), Range::new(15, 16));
+  }
+}
@@ -191,6 +191,20 @@ pub enum StmtKind {
   DebugPrint {
     value: ExprId,
   },
+  /// ON TIMER(interval) GOSUB label
+  OnTimer {
+    interval: ExprId,
+    label: Option<(Range, Label)>,
+  },
+  /// TIMER ON / TIMER OFF
+  Timer {
+    enabled: bool,
+  },
+  /// SOUND frequency, duration. An extension of the emulator.
+  Sound {
+    frequency: ExprId,
+    duration: ExprId,
+  },
   NoOp,
 }
 
@@ -798,6 +812,33 @@ fn print_stmt(
       expr_arena[*value].print(expr_arena, text, f)?;
       writeln!(f)
     }
+    StmtKind::OnTimer { interval, label } => {
+      write!(f, "ON TIMER(")?;
+      expr_arena[*interval].print(expr_arena, text, f)?;
+      write!(f, ") GOSUB")?;
+      if let Some((range, label)) = label {
+        assert_eq!(
+          text[range.range()].to_string().parse::<Label>(),
+          Ok(*label)
+        );
+        writeln!(f, " {}", label.0)
+      } else {
+        writeln!(f)
+      }
+    }
+    StmtKind::Timer { enabled } => {
+      writeln!(f, "TIMER {}", if *enabled { "ON" } else { "OFF" })
+    }
+    StmtKind::Sound {
+      frequency,
+      duration,
+    } => {
+      write!(f, "SOUND ")?;
+      expr_arena[*frequency].print(expr_arena, text, f)?;
+      write!(f, ", ")?;
+      expr_arena[*duration].print(expr_arena, text, f)?;
+      writeln!(f)
+    }
     StmtKind::NoOp => writeln!(f, ":"),
   }
 }
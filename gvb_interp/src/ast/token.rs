@@ -102,6 +102,8 @@ pub enum Keyword {
   Fwrite,
   Fseek,
   DebugPrint,
+  Timer,
+  Sound,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, FromPrimitive)]
@@ -203,6 +205,8 @@ static STR_TO_KEYWORD: phf::Map<&str, Keyword> = phf::phf_map! {
   "fwrite" => Keyword::Fwrite,
   "fseek" => Keyword::Fseek,
   "debugprint" => Keyword::DebugPrint,
+  "timer" => Keyword::Timer,
+  "sound" => Keyword::Sound,
 };
 
 impl FromStr for Keyword {
@@ -305,7 +309,7 @@ impl Debug for Keyword {
 
       Then => "THEN",
       Else => "ELSE",
-      To => "TU",
+      To => "TO",
       Step => "STEP",
       Fn => "FN",
       And => "AND",
@@ -319,6 +323,8 @@ impl Debug for Keyword {
       Fwrite => "FWRITE",
       Fseek => "FSEEK",
       DebugPrint => "DEBUGPRINT",
+      Timer => "TIMER",
+      Sound => "SOUND",
     };
     write!(f, "{kw}")
   }
@@ -0,0 +1,410 @@
+//! Metadata of keywords and system functions, for editor features like hover
+//! tooltips, completion and signature help.
+//!
+//! Descriptions are written in Chinese. Hosts which need other languages may
+//! use `Builtin::name` as the key to look up their own translations.
+
+use crate::ast::SysFuncKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Builtin {
+  /// Upper case name as written in programs, e.g. `MID$`.
+  pub name: &'static str,
+  pub kind: BuiltinKind,
+  pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinKind {
+  /// A keyword which starts a statement or a command.
+  Statement {
+    /// e.g. `LOCATE 行, 列`.
+    syntax: &'static str,
+  },
+  /// A keyword which is part of a statement or an expression, e.g. `THEN`.
+  Keyword,
+  Function(Signature),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+  pub params: &'static [Param],
+  /// Parameters after the first `min_arity` ones are optional.
+  pub min_arity: usize,
+  pub ret: ValueType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+  pub name: &'static str,
+  pub ty: ValueType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+  Real,
+  String,
+}
+
+impl Builtin {
+  /// Returns the syntax of statements, or the signature of functions, e.g.
+  /// `MID$(X$, N[, M])`. Returns the name for other keywords.
+  pub fn usage(&self) -> String {
+    match &self.kind {
+      BuiltinKind::Statement { syntax } => syntax.to_string(),
+      BuiltinKind::Keyword => self.name.to_owned(),
+      BuiltinKind::Function(sig) if sig.params.is_empty() => {
+        self.name.to_owned()
+      }
+      BuiltinKind::Function(sig) => {
+        let mut usage = format!("{}(", self.name);
+        for (i, param) in sig.params.iter().enumerate() {
+          if i == sig.min_arity {
+            usage.push('[');
+          }
+          if i != 0 {
+            usage.push_str(", ");
+          }
+          usage.push_str(param.name);
+        }
+        if sig.min_arity < sig.params.len() {
+          usage.push(']');
+        }
+        usage.push(')');
+        usage
+      }
+    }
+  }
+}
+
+/// Returns all keywords and system functions, sorted by name.
+pub fn builtins() -> &'static [Builtin] {
+  BUILTINS
+}
+
+/// Looks up a keyword or system function by name, case-insensitively.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+  BUILTINS
+    .binary_search_by(|b| cmp_ignore_case(b.name, name))
+    .ok()
+    .map(|i| &BUILTINS[i])
+}
+
+fn cmp_ignore_case(a: &str, b: &str) -> std::cmp::Ordering {
+  a.bytes()
+    .map(|c| c.to_ascii_uppercase())
+    .cmp(b.bytes().map(|c| c.to_ascii_uppercase()))
+}
+
+pub(crate) const fn sys_func_signature(kind: SysFuncKind) -> Signature {
+  match kind {
+    SysFuncKind::Abs
+    | SysFuncKind::Atn
+    | SysFuncKind::Cos
+    | SysFuncKind::Exp
+    | SysFuncKind::Int
+    | SysFuncKind::Log
+    | SysFuncKind::Rnd
+    | SysFuncKind::Sgn
+    | SysFuncKind::Sin
+    | SysFuncKind::Sqr
+    | SysFuncKind::Tan
+    | SysFuncKind::Pos => sig(&[X], 1, ValueType::Real),
+    SysFuncKind::Peek => sig(&[ADDR], 1, ValueType::Real),
+    SysFuncKind::CheckKey => sig(&[KEY], 1, ValueType::Real),
+    SysFuncKind::Eof
+    | SysFuncKind::Lof
+    | SysFuncKind::Fopen
+    | SysFuncKind::Fgetc
+    | SysFuncKind::Ftell => sig(&[FILENUM], 1, ValueType::Real),
+    SysFuncKind::Point => sig(&[X, Y], 2, ValueType::Real),
+    SysFuncKind::Asc
+    | SysFuncKind::Cvi
+    | SysFuncKind::Cvs
+    | SysFuncKind::Len
+    | SysFuncKind::Val => sig(&[X_STR], 1, ValueType::Real),
+    SysFuncKind::Mki
+    | SysFuncKind::Mks
+    | SysFuncKind::Chr
+    | SysFuncKind::Str => sig(&[X], 1, ValueType::String),
+    SysFuncKind::Left | SysFuncKind::Right => {
+      sig(&[X_STR, N], 2, ValueType::String)
+    }
+    SysFuncKind::Mid => sig(&[X_STR, N, M], 2, ValueType::String),
+    SysFuncKind::Tab | SysFuncKind::Spc => sig(&[N], 1, ValueType::Real),
+  }
+}
+
+const fn sig(
+  params: &'static [Param],
+  min_arity: usize,
+  ret: ValueType,
+) -> Signature {
+  Signature {
+    params,
+    min_arity,
+    ret,
+  }
+}
+
+const X: Param = real("X");
+const Y: Param = real("Y");
+const N: Param = real("N");
+const M: Param = real("M");
+const ADDR: Param = real("地址");
+const KEY: Param = real("键码");
+const FILENUM: Param = real("文件号");
+const X_STR: Param = Param {
+  name: "X$",
+  ty: ValueType::String,
+};
+
+const fn real(name: &'static str) -> Param {
+  Param {
+    name,
+    ty: ValueType::Real,
+  }
+}
+
+const fn stmt(
+  name: &'static str,
+  syntax: &'static str,
+  description: &'static str,
+) -> Builtin {
+  Builtin {
+    name,
+    kind: BuiltinKind::Statement { syntax },
+    description,
+  }
+}
+
+const fn kw(name: &'static str, description: &'static str) -> Builtin {
+  Builtin {
+    name,
+    kind: BuiltinKind::Keyword,
+    description,
+  }
+}
+
+const fn func(
+  name: &'static str,
+  kind: SysFuncKind,
+  description: &'static str,
+) -> Builtin {
+  Builtin {
+    name,
+    kind: BuiltinKind::Function(sys_func_signature(kind)),
+    description,
+  }
+}
+
+/// Sorted by name.
+static BUILTINS: &[Builtin] = &[
+  func("ABS", SysFuncKind::Abs, "返回 X 的绝对值"),
+  kw("AND", "逻辑与运算"),
+  func("ASC", SysFuncKind::Asc, "返回字符串 X$ 第一个字节的编码"),
+  kw("AT", "DRAW 等语句中用于指定坐标"),
+  func("ATN", SysFuncKind::Atn, "返回 X 的反正切值（弧度）"),
+  stmt("AUTO", "AUTO", "自动生成行号（命令）"),
+  stmt("BEEP", "BEEP", "发出蜂鸣声"),
+  stmt("BOX", "BOX X1, Y1, X2, Y2[, 填充[, 模式]]", "画矩形"),
+  stmt("CALL", "CALL 地址", "调用机器码子程序"),
+  func(
+    "CHECKKEY",
+    SysFuncKind::CheckKey,
+    "检查键码对应的按键是否被按下",
+  ),
+  func("CHR$", SysFuncKind::Chr, "返回编码为 X 的字符"),
+  stmt("CIRCLE", "CIRCLE X, Y, 半径[, 填充[, 模式]]", "画圆"),
+  stmt("CLEAR", "CLEAR", "清除所有变量和数组"),
+  stmt("CLOSE", "CLOSE #文件号", "关闭文件"),
+  stmt("CLS", "CLS", "清除屏幕"),
+  stmt("CONT", "CONT", "继续运行程序（命令）"),
+  stmt("COPY", "COPY", "复制程序行（命令）"),
+  func("COS", SysFuncKind::Cos, "返回 X（弧度）的余弦值"),
+  func("CVI$", SysFuncKind::Cvi, "把长度为 2 的字符串转换为整数"),
+  func("CVS$", SysFuncKind::Cvs, "把长度为 5 的字符串转换为实数"),
+  stmt("DATA", "DATA 数据, ...", "定义供 READ 语句读取的数据"),
+  stmt(
+    "DEBUGPRINT",
+    "DEBUGPRINT 表达式, ...",
+    "向调试输出打印表达式的值",
+  ),
+  stmt("DEF", "DEF FN 函数名(参数) = 表达式", "定义自定义函数"),
+  stmt("DEL", "DEL 行号", "删除程序行（命令）"),
+  stmt("DIM", "DIM 数组(下标, ...), ...", "定义数组"),
+  stmt("DRAW", "DRAW X, Y[, 模式]", "画点"),
+  stmt("EDIT", "EDIT 行号", "编辑程序行（命令）"),
+  stmt(
+    "ELLIPSE",
+    "ELLIPSE X, Y, 横半径, 纵半径[, 填充[, 模式]]",
+    "画椭圆",
+  ),
+  kw("ELSE", "IF 语句中条件不成立时执行的分支"),
+  stmt("END", "END", "结束程序"),
+  func("EOF", SysFuncKind::Eof, "文件是否已经读到末尾"),
+  func("EXP", SysFuncKind::Exp, "返回 e 的 X 次方"),
+  func("FGETC", SysFuncKind::Fgetc, "从文件中读取一个字节"),
+  stmt(
+    "FIELD",
+    "FIELD #文件号, 长度 AS 变量$, ...",
+    "定义随机文件的记录字段",
+  ),
+  stmt("FILES", "FILES", "列出文件（命令）"),
+  stmt("FLASH", "FLASH", "之后输出的文字闪烁显示"),
+  kw("FN", "调用自定义函数"),
+  func("FOPEN", SysFuncKind::Fopen, "文件是否已经打开"),
+  stmt("FOR", "FOR 变量 = 初值 TO 终值 [STEP 步长]", "循环开始"),
+  stmt("FPUTC", "FPUTC #文件号, 字符串", "向文件写入一个字节"),
+  stmt("FREAD", "FREAD #文件号, 地址, 长度", "从文件读取数据到内存"),
+  stmt("FSEEK", "FSEEK #文件号, 位置", "设置文件读写位置"),
+  func("FTELL", SysFuncKind::Ftell, "返回文件读写位置"),
+  stmt(
+    "FWRITE",
+    "FWRITE #文件号, 地址, 长度",
+    "把内存中的数据写入文件",
+  ),
+  stmt("GET", "GET #文件号, 记录号", "从随机文件读取记录"),
+  stmt("GOSUB", "GOSUB 行号", "调用子程序"),
+  stmt("GOTO", "GOTO 行号", "跳转到指定行"),
+  stmt("GRAPH", "GRAPH", "切换到图形模式"),
+  stmt("IF", "IF 条件 THEN 语句 [ELSE 语句]", "条件语句"),
+  stmt("INKEY$", "INKEY$", "等待按键。在表达式中返回按下的键"),
+  stmt("INPUT", "INPUT [提示;] 变量, ...", "从键盘或文件输入数据"),
+  func("INT", SysFuncKind::Int, "返回不大于 X 的最大整数"),
+  stmt("INVERSE", "INVERSE", "之后输出的文字反色显示"),
+  stmt("KILL", "KILL 文件名", "删除文件"),
+  func("LEFT$", SysFuncKind::Left, "返回字符串 X$ 左边 N 个字节"),
+  func("LEN", SysFuncKind::Len, "返回字符串 X$ 的长度"),
+  stmt("LET", "[LET] 变量 = 表达式", "赋值"),
+  stmt("LINE", "LINE X1, Y1, X2, Y2[, 模式]", "画直线"),
+  stmt("LIST", "LIST", "列出程序（命令）"),
+  stmt("LOAD", "LOAD 文件名", "载入程序（命令）"),
+  stmt("LOCATE", "LOCATE [行][, 列]", "设置光标位置"),
+  func("LOF", SysFuncKind::Lof, "返回文件长度"),
+  func("LOG", SysFuncKind::Log, "返回 X 的自然对数"),
+  stmt("LSET", "LSET 变量$ = 字符串", "向记录字段左对齐写入字符串"),
+  func(
+    "MID$",
+    SysFuncKind::Mid,
+    "返回字符串 X$ 从第 N 个字节开始的 M 个字节",
+  ),
+  func("MKI$", SysFuncKind::Mki, "把整数转换为长度为 2 的字符串"),
+  func("MKS$", SysFuncKind::Mks, "把实数转换为长度为 5 的字符串"),
+  stmt("NEW", "NEW", "清除程序（命令）"),
+  stmt("NEXT", "NEXT [变量, ...]", "循环结束"),
+  stmt("NORMAL", "NORMAL", "之后输出的文字正常显示"),
+  kw("NOT", "逻辑非运算"),
+  stmt("NOTRACE", "NOTRACE", "关闭跟踪"),
+  stmt("ON", "ON 表达式 GOTO|GOSUB 行号, ...", "根据表达式的值跳转"),
+  stmt("OPEN", "OPEN 文件名 FOR 模式 AS #文件号", "打开文件"),
+  kw("OR", "逻辑或运算"),
+  func("PEEK", SysFuncKind::Peek, "返回内存地址中的字节"),
+  stmt("PLAY", "PLAY 字符串", "播放音乐"),
+  func("POINT", SysFuncKind::Point, "返回屏幕上点 (X, Y) 的颜色"),
+  stmt("POKE", "POKE 地址, 值", "向内存地址写入字节"),
+  stmt("POP", "POP", "丢弃最近一次 GOSUB 的返回地址"),
+  func("POS", SysFuncKind::Pos, "返回光标所在的列"),
+  stmt("PRINT", "PRINT [表达式, ...]", "输出到屏幕或文件"),
+  stmt("PUT", "PUT #文件号, 记录号", "向随机文件写入记录"),
+  stmt("READ", "READ 变量, ...", "读取 DATA 中的数据"),
+  stmt("REM", "REM 注释", "注释"),
+  stmt("RENAME", "RENAME 旧文件名, 新文件名", "重命名文件"),
+  stmt("RESTORE", "RESTORE [行号]", "重置 DATA 读取位置"),
+  stmt("RETURN", "RETURN", "从子程序返回"),
+  func("RIGHT$", SysFuncKind::Right, "返回字符串 X$ 右边 N 个字节"),
+  func("RND", SysFuncKind::Rnd, "返回 0 到 1 之间的随机数"),
+  stmt("RSET", "RSET 变量$ = 字符串", "向记录字段右对齐写入字符串"),
+  stmt("RUN", "RUN", "运行程序（命令）"),
+  stmt("SAVE", "SAVE 文件名", "保存程序（命令）"),
+  func("SGN", SysFuncKind::Sgn, "返回 X 的符号"),
+  func("SIN", SysFuncKind::Sin, "返回 X（弧度）的正弦值"),
+  stmt("SLEEP", "SLEEP 时长", "暂停执行"),
+  stmt(
+    "SOUND",
+    "SOUND 频率, 时长",
+    "以指定频率（赫兹）发声指定时长（毫秒）",
+  ),
+  func("SPC", SysFuncKind::Spc, "在 PRINT 语句中输出 N 个空格"),
+  func("SQR", SysFuncKind::Sqr, "返回 X 的平方根"),
+  kw("STEP", "FOR 语句中的步长"),
+  stmt("STOP", "STOP", "暂停程序"),
+  func("STR$", SysFuncKind::Str, "把数值转换为字符串"),
+  stmt("SWAP", "SWAP 变量, 变量", "交换两个变量的值"),
+  stmt("SYSTEM", "SYSTEM", "退出到系统"),
+  func("TAB", SysFuncKind::Tab, "在 PRINT 语句中把光标移到第 N 列"),
+  func("TAN", SysFuncKind::Tan, "返回 X（弧度）的正切值"),
+  stmt("TEXT", "TEXT", "切换到文本模式"),
+  kw("THEN", "IF 语句中条件成立时执行的分支"),
+  stmt("TIMER", "TIMER ON|OFF", "启用或停用 ON TIMER 设置的定时器"),
+  kw("TO", "FOR 语句中的终值"),
+  stmt("TRACE", "TRACE", "开启跟踪"),
+  func("VAL", SysFuncKind::Val, "把字符串转换为数值"),
+  stmt("WEND", "WEND", "WHILE 循环结束"),
+  stmt("WHILE", "WHILE 条件", "WHILE 循环开始"),
+  stmt(
+    "WRITE",
+    "WRITE [#文件号,] 表达式, ...",
+    "输出带引号和逗号分隔的数据",
+  ),
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::Keyword;
+  use num_traits::FromPrimitive;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn sorted() {
+    for w in BUILTINS.windows(2) {
+      assert!(w[0].name < w[1].name, "{} >= {}", w[0].name, w[1].name);
+    }
+  }
+
+  /// The table must contain every keyword and system function the parser
+  /// recognizes, and nothing else.
+  #[test]
+  fn complete() {
+    let mut names = vec![];
+    for i in 0.. {
+      match Keyword::from_usize(i) {
+        Some(kw) => names.push(format!("{kw:?}").to_ascii_uppercase()),
+        None => break,
+      }
+    }
+    for i in 0.. {
+      match SysFuncKind::from_usize(i) {
+        Some(kind) => {
+          let name = format!("{kind:?}");
+          let builtin = lookup(&name).unwrap();
+          assert_eq!(
+            builtin.kind,
+            BuiltinKind::Function(sys_func_signature(kind)),
+            "{name}"
+          );
+          names.push(name);
+        }
+        None => break,
+      }
+    }
+    names.sort();
+    assert_eq!(
+      names,
+      BUILTINS
+        .iter()
+        .map(|b| b.name.to_owned())
+        .collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn usage() {
+    assert_eq!(lookup("mid$").unwrap().usage(), "MID$(X$, N[, M])");
+    assert_eq!(lookup("Point").unwrap().usage(), "POINT(X, Y)");
+    assert_eq!(lookup("then").unwrap().usage(), "THEN");
+    assert_eq!(lookup("locate").unwrap().usage(), "LOCATE [行][, 列]");
+    assert_eq!(lookup("mid"), None);
+  }
+}
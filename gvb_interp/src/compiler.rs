@@ -1,7 +1,8 @@
+use crate::builtin::{sys_func_signature, ValueType};
 use crate::parser::ParseResult;
 use crate::util::mbf5::{Mbf5, ParseRealError};
 use crate::util::utf16str_ext::Utf16StrExt;
-use crate::{ast::*, diagnostic::*, HashMap};
+use crate::{ast::*, diagnostic::*, HashMap, HashSet};
 use smallvec::SmallVec;
 use std::fmt::{self, Display, Formatter};
 use std::num::NonZeroUsize;
@@ -12,7 +13,12 @@ pub trait CodeEmitter {
   type Addr: Copy;
   type DatumIndex: Copy;
 
-  fn begin_line(&mut self, linenum: usize);
+  fn begin_line(&mut self, linenum: usize, line_start: usize);
+
+  /// Called before compiling a statement. Statements may be nested, e.g.
+  /// statements in IF branches.
+  fn begin_stmt(&mut self, range: Range);
+  fn end_stmt(&mut self);
 
   fn emit_no_op(&mut self, range: Range);
 
@@ -83,6 +89,8 @@ pub trait CodeEmitter {
 
   fn emit_on(&mut self, range: Range, labels: NonZeroUsize);
 
+  fn emit_on_timer(&mut self, range: Range) -> Self::Addr;
+
   fn emit_set_row(&mut self, range: Range);
   fn emit_set_column(&mut self, range: Range);
 
@@ -127,6 +135,9 @@ pub trait CodeEmitter {
   fn emit_string(&mut self, range: Range, str: Utf16String) -> usize;
 
   fn emit_inkey(&mut self, range: Range);
+
+  /// Strings longer than this are reported.
+  fn max_string_len(&self) -> usize;
   fn emit_index(
     &mut self,
     range: Range,
@@ -160,6 +171,8 @@ pub fn compile_prog<E: CodeEmitter>(
     pending_datum_indices: vec![],
     data_start: HashMap::default(),
     label_addrs: HashMap::default(),
+    defined_fns: HashSet::default(),
+    pending_fn_calls: vec![],
     parsed: std::ptr::null_mut(),
     linenum: 0,
   };
@@ -180,6 +193,8 @@ pub(crate) fn compile_fn_body<E: CodeEmitter>(
     pending_datum_indices: vec![],
     data_start: HashMap::default(),
     label_addrs: HashMap::default(),
+    defined_fns: HashSet::default(),
+    pending_fn_calls: vec![],
     parsed: expr as *mut _,
     linenum: 0,
   };
@@ -211,6 +226,12 @@ struct PendingDatumIndex<E: CodeEmitter> {
   target_label: Label,
 }
 
+struct PendingFnCall {
+  source_line: usize,
+  source_range: Range,
+  name: String,
+}
+
 struct CompileState<'a, 'b, E: CodeEmitter, T> {
   text: &'b Utf16Str,
   code_emitter: &'a mut E,
@@ -218,6 +239,9 @@ struct CompileState<'a, 'b, E: CodeEmitter, T> {
   pending_datum_indices: Vec<PendingDatumIndex<E>>,
   data_start: HashMap<Label, E::DatumIndex>,
   label_addrs: HashMap<Label, E::Addr>,
+  /// Names of functions defined by DEF FN.
+  defined_fns: HashSet<String>,
+  pending_fn_calls: Vec<PendingFnCall>,
   parsed: *mut ParseResult<T>,
   linenum: usize,
 }
@@ -230,6 +254,15 @@ enum Type {
   Error,
 }
 
+impl From<ValueType> for Type {
+  fn from(ty: ValueType) -> Self {
+    match ty {
+      ValueType::Real => Self::Real,
+      ValueType::String => Self::String,
+    }
+  }
+}
+
 impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
   fn add_error(&mut self, range: Range, message: impl ToString) {
     unsafe { &mut *self.parsed }
@@ -237,6 +270,13 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
       .push(Diagnostic::new_error(range, message));
   }
 
+  fn check_string_len(&mut self, range: Range, len: usize) {
+    let max = self.code_emitter.max_string_len();
+    if len > max {
+      self.add_error(range, format!("字符串太长，长度超出 {max}"));
+    }
+  }
+
   fn add_warning(&mut self, range: Range, message: impl ToString) {
     unsafe { &mut *self.parsed }
       .diagnostics
@@ -275,7 +315,7 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
         last_label = l.0 as i32;
       }
 
-      self.code_emitter.begin_line(i);
+      self.code_emitter.begin_line(i, text_offset);
 
       for &stmt in &line.content.stmts {
         self.compile_stmt(stmt);
@@ -286,6 +326,7 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
 
     self.resolve_labels(prog);
     self.resolve_datum_indices(prog);
+    self.resolve_fn_calls(prog);
     for (line, diag) in self.code_emitter.clean_up() {
       prog.lines[line].diagnostics.push(diag);
     }
@@ -315,6 +356,22 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
     }
   }
 
+  fn resolve_fn_calls(&mut self, prog: &mut Program) {
+    for PendingFnCall {
+      source_line: line,
+      source_range: range,
+      name,
+    } in std::mem::take(&mut self.pending_fn_calls)
+    {
+      if !self.defined_fns.contains(&name) {
+        prog.lines[line].diagnostics.push(Diagnostic::new_warning(
+          range,
+          format!("程序中没有用 DEF FN 或 INPUT 定义自定义函数 FN {name}"),
+        ));
+      }
+    }
+  }
+
   fn resolve_datum_indices(&mut self, prog: &mut Program) {
     for PendingDatumIndex {
       source_addr: addr,
@@ -335,6 +392,13 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
   }
 
   fn compile_stmt(&mut self, stmt: StmtId) {
+    let range = self.stmt_node(stmt).range.clone();
+    self.code_emitter.begin_stmt(range);
+    self.do_compile_stmt(stmt);
+    self.code_emitter.end_stmt();
+  }
+
+  fn do_compile_stmt(&mut self, stmt: StmtId) {
     macro_rules! compile_draw_stmt {
       (
         $stmt:expr,
@@ -523,9 +587,13 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
         "PLAY",
         "参数",
       ),
-      StmtKind::Poke { addr, value } => {
-        self.compile_poke(range, &stmt.kind, *addr, *value)
-      }
+      StmtKind::Poke { addr, value } => self.compile_binary_stmt(
+        range,
+        &stmt.kind,
+        (*addr, *value),
+        "POKE",
+        ("地址", "值"),
+      ),
       StmtKind::Pop => self.code_emitter.emit_op(range, &stmt.kind, 0),
       StmtKind::Print(elems) => self.compile_print(range, elems),
       StmtKind::Put { filenum, record } => {
@@ -601,6 +669,20 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
         "DEBUGPRINT",
         "参数",
       ),
+      StmtKind::OnTimer { interval, label } => {
+        self.compile_on_timer(range, *interval, label)
+      }
+      StmtKind::Timer { .. } => self.code_emitter.emit_op(range, &stmt.kind, 0),
+      StmtKind::Sound {
+        frequency,
+        duration,
+      } => self.compile_binary_stmt(
+        range,
+        &stmt.kind,
+        (*frequency, *duration),
+        "SOUND",
+        ("频率", "时长"),
+      ),
       StmtKind::NoOp => self.code_emitter.emit_no_op(range),
     }
   }
@@ -645,9 +727,7 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
         text,
         datum.is_quoted,
       );
-      if len > 255 {
-        self.add_error(datum.range.clone(), "字符串太长，长度超出 255");
-      }
+      self.check_string_len(datum.range.clone(), len);
       if data_index.is_none() {
         data_index = Some(index);
       }
@@ -664,11 +744,11 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
   fn compile_def(
     &mut self,
     range: Range,
-    name: &Option<Range>,
-    param: &Option<Range>,
+    name_range: &Option<Range>,
+    param_range: &Option<Range>,
     body: ExprId,
   ) {
-    let name = name.as_ref().map(|name_range| {
+    let name = name_range.as_ref().map(|name_range| {
       let (name, ty) = self.compile_sym(name_range.clone());
       if !ty.exact_matches(Type::Real) {
         self.add_error(
@@ -679,7 +759,7 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
       name
     });
 
-    let param = param.as_ref().map(|param_range| {
+    let param = param_range.as_ref().map(|param_range| {
       let (param, ty) = self.compile_sym(param_range.clone());
       if !ty.exact_matches(Type::Real) {
         self.add_error(
@@ -693,6 +773,13 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
       param
     });
 
+    if let (Some(name_range), Some(param_range)) = (name_range, param_range) {
+      let (name, ..) = self.sym_name(name_range);
+      let (param, ..) = self.sym_name(param_range);
+      self.lint_fn_body(&name, &param, body);
+      self.defined_fns.insert(name);
+    }
+
     let body_range = self.expr_node(body).range.clone();
     if let (Some(name), Some(param)) = (name, param) {
       let def_addr = self.code_emitter.begin_def_fn(range, name, param);
@@ -1070,6 +1157,16 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
       }
 
       let range = self.expr_node(datum.datum).range.clone();
+      if to_file
+        && !ty.matches(Type::Real)
+        && self.contains_lossy_chr(datum.datum)
+      {
+        self.add_warning(
+          range.clone(),
+          "这个字符串包含双引号、空字符或 U+001F 字符，\
+          用 WRITE 语句写入文件后无法用 INPUT 语句原样读回",
+        );
+      }
       if i == data.len().get() - 1 || datum.comma {
         let end = i == data.len().get() - 1;
         if ty.matches(Type::Real) {
@@ -1249,6 +1346,8 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
           );
         } else {
           let func = func.as_ref().map(|func_range| {
+            let (name, ..) = self.sym_name(func_range);
+            self.defined_fns.insert(name);
             let (func, ty) = self.compile_sym(func_range.clone());
             if !ty.exact_matches(Type::Real) {
               self.add_error(
@@ -1296,9 +1395,7 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
         let len = self
           .code_emitter
           .emit_string(prompt.clone(), text.to_owned());
-        if len > 255 {
-          self.add_error(prompt.clone(), "字符串太长，长度超出 255");
-        }
+        self.check_string_len(prompt.clone(), len);
         self
           .code_emitter
           .emit_keyboard_input(range, true, vars.len());
@@ -1398,6 +1495,43 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
     }
   }
 
+  fn compile_on_timer(
+    &mut self,
+    range: Range,
+    interval: ExprId,
+    label: &Option<(Range, Label)>,
+  ) {
+    let ty = self.compile_expr(interval);
+    if !ty.matches(Type::Real) {
+      let range = &self.expr_node(interval).range;
+      self.add_error(
+        range.clone(),
+        format!(
+          "表达式类型错误。ON TIMER 语句的时间间隔必须是{}类型，而这个表达式是{}类型",
+          Type::Real,
+          ty
+        ),
+      );
+    }
+
+    let addr = self.code_emitter.emit_on_timer(range.clone());
+    if let Some((range, label)) = label {
+      self.pending_jump_labels.push(PendingJumpLabel {
+        source_addr: addr,
+        source_line: self.linenum,
+        source_range: range.clone(),
+        target_label: Some(*label),
+      });
+    } else {
+      self.pending_jump_labels.push(PendingJumpLabel {
+        source_addr: addr,
+        source_line: self.linenum,
+        source_range: range,
+        target_label: None,
+      });
+    }
+  }
+
   fn compile_open(
     &mut self,
     range: Range,
@@ -1486,42 +1620,62 @@ impl<'a, 'b, E: CodeEmitter> CompileState<'a, 'b, E, ProgramLine> {
     }
   }
 
-  fn compile_poke(
+  /// Compiles a statement with two numeric arguments.
+  fn compile_binary_stmt(
     &mut self,
     range: Range,
     kind: &StmtKind,
-    addr: ExprId,
-    value: ExprId,
+    (first, second): (ExprId, ExprId),
+    stmt_name: &str,
+    (first_name, second_name): (&str, &str),
   ) {
-    let ty = self.compile_expr(addr);
-    if !ty.matches(Type::Real) {
-      let range = &self.expr_node(addr).range;
-      self.add_error(
-        range.clone(),
-        format!(
-          "表达式类型错误。POKE 语句的地址参数是{}类型，而这个表达式是{}类型",
-          Type::Real,
-          ty
-        ),
-      );
-    }
-
-    let ty = self.compile_expr(value);
-    if !ty.matches(Type::Real) {
-      let range = &self.expr_node(value).range;
-      self.add_error(
-        range.clone(),
-        format!(
-          "表达式类型错误。POKE 语句的值参数是{}类型，而这个表达式是{}类型",
-          Type::Real,
-          ty
-        ),
-      );
+    for (arg, name) in [(first, first_name), (second, second_name)] {
+      let ty = self.compile_expr(arg);
+      if !ty.matches(Type::Real) {
+        let range = &self.expr_node(arg).range;
+        self.add_error(
+          range.clone(),
+          format!(
+            "表达式类型错误。{} 语句的{}参数是{}类型，而这个表达式是{}类型",
+            stmt_name,
+            name,
+            Type::Real,
+            ty
+          ),
+        );
+      }
     }
 
     self.code_emitter.emit_op(range, kind, 2);
   }
 
+  /// Returns true if the string expression contains `CHR$(0)`, `CHR$(31)` or
+  /// `CHR$(34)` with a literal argument. See `WriteLoss`.
+  fn contains_lossy_chr(&self, expr: ExprId) -> bool {
+    match &self.expr_node(expr).kind {
+      ExprKind::Binary { lhs, rhs, .. } => {
+        self.contains_lossy_chr(*lhs) || self.contains_lossy_chr(*rhs)
+      }
+      ExprKind::SysFuncCall {
+        func: (_, SysFuncKind::Chr),
+        args,
+      } => {
+        let arg = self.expr_node(args[0]);
+        if let ExprKind::NumberLit = arg.kind {
+          let mut text = self.text[arg.range.range()].to_string();
+          text.retain(|c| c != ' ');
+          matches!(
+            text.parse::<Mbf5>().map(f64::from),
+            Ok(n) if n == 0.0 || n == 31.0 || n == 34.0
+          )
+        } else {
+          false
+        }
+      }
+      _ => false,
+    }
+  }
+
   fn compile_print(
     &mut self,
     range: Range,
@@ -1622,9 +1776,7 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
         let len = self
           .code_emitter
           .emit_string(range.clone(), text.to_owned());
-        if len > 255 {
-          self.add_error(range, "字符串太长，长度超出 255");
-        }
+        self.check_string_len(range, len);
         Type::String
       }
       ExprKind::NumberLit => {
@@ -1644,6 +1796,11 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
       }
       ExprKind::UserFuncCall { func, arg } => {
         let func = func.as_ref().map(|func_range| {
+          self.pending_fn_calls.push(PendingFnCall {
+            source_line: self.linenum,
+            source_range: func_range.clone(),
+            name: self.sym_name(func_range).0,
+          });
           let (func, ty) = self.compile_sym(func_range.clone());
           if !ty.exact_matches(Type::Real) {
             self.add_error(
@@ -1723,51 +1880,19 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
     func: &(Range, SysFuncKind),
     args: &NonEmptyVec<[ExprId; 1]>,
   ) -> Type {
-    let (min_arity, max_arity, arg_tys, ret_ty): (_, _, &[_], _) = match func.1
-    {
-      SysFuncKind::Abs
-      | SysFuncKind::Atn
-      | SysFuncKind::Cos
-      | SysFuncKind::Exp
-      | SysFuncKind::Int
-      | SysFuncKind::Log
-      | SysFuncKind::Peek
-      | SysFuncKind::Rnd
-      | SysFuncKind::Sgn
-      | SysFuncKind::Sin
-      | SysFuncKind::Sqr
-      | SysFuncKind::Tan
-      | SysFuncKind::Eof
-      | SysFuncKind::Lof
-      | SysFuncKind::Pos
-      | SysFuncKind::CheckKey
-      | SysFuncKind::Fopen
-      | SysFuncKind::Fgetc
-      | SysFuncKind::Ftell => (1, 1, &[Type::Real], Type::Real),
-      SysFuncKind::Point => (2, 2, &[Type::Real, Type::Real], Type::Real),
-      SysFuncKind::Asc
-      | SysFuncKind::Cvi
-      | SysFuncKind::Cvs
-      | SysFuncKind::Len
-      | SysFuncKind::Val => (1, 1, &[Type::String], Type::Real),
-      SysFuncKind::Mki
-      | SysFuncKind::Mks
-      | SysFuncKind::Chr
-      | SysFuncKind::Str => (1, 1, &[Type::Real], Type::String),
-      SysFuncKind::Left | SysFuncKind::Right => {
-        (2, 2, &[Type::String, Type::Real], Type::String)
-      }
-      SysFuncKind::Mid => {
-        (2, 3, &[Type::String, Type::Real, Type::Real], Type::String)
-      }
-      SysFuncKind::Tab | SysFuncKind::Spc => {
-        self.add_error(
-          func.0.clone(),
-          format!("{:?} 函数只能作为 PRINT 语句的参数出现", func.1),
-        );
-        (1, 1, &[Type::Real], Type::Real)
-      }
-    };
+    if let SysFuncKind::Tab | SysFuncKind::Spc = func.1 {
+      self.add_error(
+        func.0.clone(),
+        format!("{:?} 函数只能作为 PRINT 语句的参数出现", func.1),
+      );
+    }
+    let sig = sys_func_signature(func.1);
+    let min_arity = sig.min_arity;
+    let max_arity = sig.params.len();
+    let arg_tys = sig.params.iter().map(|p| Type::from(p.ty));
+    let arg_tys = arg_tys.collect::<SmallVec<[_; 3]>>();
+    let ret_ty = Type::from(sig.ret);
+
     if args.len().get() < min_arity {
       self.add_error(
         range.clone(),
@@ -1934,6 +2059,20 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
 
   #[must_use]
   fn compile_sym(&mut self, range: Range) -> (E::Symbol, Type) {
+    let (name, ty, truncated) = self.sym_name(&range);
+    if truncated {
+      self.add_warning(
+        range,
+        format!("该变量包含空格，空格之后的部分会被省略。该变量等价于 {name}"),
+      );
+    }
+
+    let sym = self.code_emitter.make_symbol(name);
+    (sym, ty)
+  }
+
+  /// Returns (name, type, whether the name is truncated at a space).
+  fn sym_name(&self, range: &Range) -> (String, Type, bool) {
     let mut name = self.text[range.range()].to_string().to_ascii_uppercase();
     let ty = match name.as_bytes().last() {
       Some(b'%') => Type::Integer,
@@ -1941,19 +2080,73 @@ impl<'a, 'b, E: CodeEmitter, T> CompileState<'a, 'b, E, T> {
       _ => Type::Real,
     };
 
+    let mut truncated = false;
     if let Some(i) = name.find(' ') {
       name.truncate(i);
       if !ty.exact_matches(Type::Real) {
         name.push(ty.sigil().unwrap());
       }
-      self.add_warning(
-        range,
-        format!("该变量包含空格，空格之后的部分会被省略。该变量等价于 {name}"),
-      );
+      truncated = true;
     }
+    (name, ty, truncated)
+  }
 
-    let sym = self.code_emitter.make_symbol(name);
-    (sym, ty)
+  /// Reports variables and arrays in the body of DEF FN which look like the
+  /// parameter but are not, and calls of the function itself.
+  fn lint_fn_body(&mut self, name: &str, param: &str, expr: ExprId) {
+    let expr = self.expr_node(expr);
+    match &expr.kind {
+      ExprKind::Ident => {
+        let (var, ..) = self.sym_name(&expr.range);
+        if var != param && var.trim_end_matches(['%', '$']) == param {
+          self.add_warning(
+            expr.range.clone(),
+            format!("变量 {var} 与自定义函数的参数 {param} 不是同一个变量"),
+          );
+        }
+      }
+      ExprKind::Index {
+        name: array,
+        indices,
+      } => {
+        if let Some(array) = array {
+          let (var, ..) = self.sym_name(array);
+          if var.trim_end_matches(['%', '$']) == param {
+            self.add_warning(
+              array.clone(),
+              format!(
+                "数组 {var} 与自定义函数的参数 {param} 同名，但不是同一个变量"
+              ),
+            );
+          }
+        }
+        for &index in indices.iter() {
+          self.lint_fn_body(name, param, index);
+        }
+      }
+      ExprKind::UserFuncCall { func, arg } => {
+        if let Some(func) = func {
+          if self.sym_name(func).0 == name {
+            self.add_warning(
+              func.clone(),
+              format!("自定义函数 FN {name} 调用了自身，执行时会无限递归"),
+            );
+          }
+        }
+        self.lint_fn_body(name, param, *arg);
+      }
+      ExprKind::SysFuncCall { args, .. } => {
+        for &arg in args.iter() {
+          self.lint_fn_body(name, param, arg);
+        }
+      }
+      ExprKind::Binary { lhs, rhs, .. } => {
+        self.lint_fn_body(name, param, *lhs);
+        self.lint_fn_body(name, param, *rhs);
+      }
+      ExprKind::Unary { arg, .. } => self.lint_fn_body(name, param, *arg),
+      _ => {}
+    }
   }
 }
 
@@ -2266,6 +2459,69 @@ mod tests {
     ));
   }
 
+  #[test]
+  fn write_lossy_string() {
+    let text =
+      Utf16String::from(r#"10 write #1,"a"+chr$(34),chr$(3)+a$:write chr$(0)"#);
+    let mut prog = parse_prog(&text);
+    let mut codegen = CodeGen::new(EmojiVersion::V2);
+    compile_prog(text, &mut prog, &mut codegen);
+    assert_eq!(
+      prog.lines[0].diagnostics,
+      vec![Diagnostic::new_warning(
+        Range::new(12, 24),
+        "这个字符串包含双引号、空字符或 U+001F 字符，\
+        用 WRITE 语句写入文件后无法用 INPUT 语句原样读回"
+      )]
+    );
+  }
+
+  #[test]
+  fn fn_body_lint() {
+    let text =
+      Utf16String::from(r#"10 def fn f(x)=x%+len(x$)+x(1)+fn f(x)+fn g(1)"#);
+    let mut prog = parse_prog(&text);
+    let mut codegen = CodeGen::new(EmojiVersion::V2);
+    compile_prog(text, &mut prog, &mut codegen);
+    assert_eq!(
+      prog.lines[0].diagnostics,
+      vec![
+        Diagnostic::new_warning(
+          Range::new(15, 17),
+          "变量 X% 与自定义函数的参数 X 不是同一个变量"
+        ),
+        Diagnostic::new_warning(
+          Range::new(22, 24),
+          "变量 X$ 与自定义函数的参数 X 不是同一个变量"
+        ),
+        Diagnostic::new_warning(
+          Range::new(26, 27),
+          "数组 X 与自定义函数的参数 X 同名，但不是同一个变量"
+        ),
+        Diagnostic::new_warning(
+          Range::new(34, 35),
+          "自定义函数 FN F 调用了自身，执行时会无限递归"
+        ),
+        Diagnostic::new_warning(
+          Range::new(42, 43),
+          "程序中没有用 DEF FN 或 INPUT 定义自定义函数 FN G"
+        ),
+      ]
+    );
+
+    let text = Utf16String::from("10 input fn g(x):print fn g(1)+fn h(1)");
+    let mut prog = parse_prog(&text);
+    let mut codegen = CodeGen::new(EmojiVersion::V2);
+    compile_prog(text, &mut prog, &mut codegen);
+    assert_eq!(
+      prog.lines[0].diagnostics,
+      vec![Diagnostic::new_warning(
+        Range::new(34, 35),
+        "程序中没有用 DEF FN 或 INPUT 定义自定义函数 FN H"
+      )]
+    );
+  }
+
   #[test]
   fn sleep() {
     assert_debug_snapshot!(compile(
@@ -2276,6 +2532,17 @@ mod tests {
     ));
   }
 
+  #[test]
+  fn on_timer() {
+    assert_debug_snapshot!(compile(
+      r#"
+10 on timer(30) gosub 20:timer on:timer off
+20 on timer(n*2) gosub 10:return
+    "#
+      .trim()
+    ));
+  }
+
   #[test]
   fn for_loop_to_sleep() {
     assert_debug_snapshot!(compile(
@@ -0,0 +1,847 @@
+//! Conformance testing of the simulator against captures of real hardware.
+//!
+//! A capture is a text file with one device interaction per line, prefixed
+//! with the time in milliseconds since the program is started. Empty lines
+//! and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # time  event
+//! 0       print "HELLO"
+//! 0       newline
+//! 1500    key 13
+//! 1500    cls
+//! 1520    point 10 20 or
+//! 1520    line 0 0 159 79 xor
+//! 1530    box 0 0 20 20 1 or          # fill: 0 or 1
+//! 1530    circle 80 40 10 0 or
+//! 1540    ellipse 80 40 20 10 1 clear
+//! 1600    beep
+//! 1600    play "\x01\x02"
+//! 1700    sound 440 250               # frequency in Hz, duration in ms
+//! ```
+//!
+//! Strings are quoted, with `\"`, `\\` and `\xHH` escapes. Draw modes are
+//! `clear`, `or`, `xor` and `unknown`.
+//!
+//! Key events are inputs: [`replay`] feeds them to the program, and records
+//! them in the trace when the program consumes them. The other events are
+//! outputs and are compared against the trace of the simulator.
+//!
+//! The simulator does not model the execution speed of the real hardware, so
+//! the simulated time only advances by SLEEP and PAUSE, and by waiting for
+//! keys.
+
+use std::fmt::{self, Display, Formatter, Write};
+use std::io;
+use std::time::Duration;
+
+use crate::device::{AsmExecState, Device, DrawMode};
+use crate::machine::{
+  EofBehavior, IntOverflow, InvalidNotes, SelectorRounding,
+};
+use crate::{
+  ContainsErrors, Document, ExecInput, ExecResult, Location, PrintMode,
+  ScreenMode,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  Key(u8),
+  Print(Vec<u8>),
+  Newline,
+  Cls,
+  Point((u8, u8), DrawMode),
+  Line((u8, u8), (u8, u8), DrawMode),
+  Box((u8, u8), (u8, u8), bool, DrawMode),
+  Circle((u8, u8), u8, bool, DrawMode),
+  Ellipse((u8, u8), (u8, u8), bool, DrawMode),
+  Beep,
+  Play(Vec<u8>),
+  /// Frequency in Hz and duration in milliseconds.
+  Sound(u16, u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+  /// In milliseconds since the program is started.
+  pub time: u64,
+  pub event: Event,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capture {
+  pub events: Vec<TimedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCaptureError {
+  /// 0-based.
+  pub line: usize,
+  pub message: String,
+}
+
+impl Capture {
+  pub fn parse(text: &str) -> Result<Self, ParseCaptureError> {
+    let mut events = vec![];
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let event = parse_event(line)
+        .map_err(|message| ParseCaptureError { line: i, message })?;
+      events.push(event);
+    }
+    Ok(Self { events })
+  }
+
+  /// Merges consecutive prints, so that traces are comparable regardless of
+  /// how strings are split into prints.
+  pub fn normalize(&mut self) {
+    let mut events: Vec<TimedEvent> = Vec::with_capacity(self.events.len());
+    for e in self.events.drain(..) {
+      if let (
+        Some(TimedEvent {
+          event: Event::Print(last),
+          ..
+        }),
+        Event::Print(s),
+      ) = (events.last_mut(), &e.event)
+      {
+        last.extend_from_slice(s);
+        continue;
+      }
+      events.push(e);
+    }
+    self.events = events;
+  }
+}
+
+impl Display for Capture {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    for e in &self.events {
+      writeln!(f, "{} {}", e.time, e.event)?;
+    }
+    Ok(())
+  }
+}
+
+impl Display for Event {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::Key(key) => write!(f, "key {}", key),
+      Self::Print(s) => write!(f, "print {}", QuotedBytes(s)),
+      Self::Newline => write!(f, "newline"),
+      Self::Cls => write!(f, "cls"),
+      Self::Point((x, y), mode) => {
+        write!(f, "point {} {} {}", x, y, mode_name(*mode))
+      }
+      Self::Line((x1, y1), (x2, y2), mode) => {
+        write!(f, "line {} {} {} {} {}", x1, y1, x2, y2, mode_name(*mode))
+      }
+      Self::Box((x1, y1), (x2, y2), fill, mode) => write!(
+        f,
+        "box {} {} {} {} {} {}",
+        x1,
+        y1,
+        x2,
+        y2,
+        *fill as u8,
+        mode_name(*mode)
+      ),
+      Self::Circle((x, y), r, fill, mode) => write!(
+        f,
+        "circle {} {} {} {} {}",
+        x,
+        y,
+        r,
+        *fill as u8,
+        mode_name(*mode)
+      ),
+      Self::Ellipse((x, y), (rx, ry), fill, mode) => write!(
+        f,
+        "ellipse {} {} {} {} {} {}",
+        x,
+        y,
+        rx,
+        ry,
+        *fill as u8,
+        mode_name(*mode)
+      ),
+      Self::Beep => write!(f, "beep"),
+      Self::Play(notes) => write!(f, "play {}", QuotedBytes(notes)),
+      Self::Sound(frequency, duration) => {
+        write!(f, "sound {} {}", frequency, duration)
+      }
+    }
+  }
+}
+
+struct QuotedBytes<'a>(&'a [u8]);
+
+impl<'a> Display for QuotedBytes<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.write_char('"')?;
+    for &b in self.0 {
+      match b {
+        b'"' => f.write_str("\\\"")?,
+        b'\\' => f.write_str("\\\\")?,
+        0x20..=0x7e => f.write_char(b as char)?,
+        _ => write!(f, "\\x{:02X}", b)?,
+      }
+    }
+    f.write_char('"')
+  }
+}
+
+fn mode_name(mode: DrawMode) -> &'static str {
+  match mode {
+    DrawMode::Clear => "clear",
+    DrawMode::Or => "or",
+    DrawMode::Xor => "xor",
+    DrawMode::Unknown => "unknown",
+  }
+}
+
+fn parse_event(line: &str) -> Result<TimedEvent, String> {
+  let (time, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+  let time = time
+    .parse()
+    .map_err(|_| format!("invalid time: {}", time))?;
+  let rest = rest.trim_start();
+  let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+  let args = args.trim();
+
+  let event = match name {
+    "print" => Event::Print(parse_quoted(args)?),
+    "play" => Event::Play(parse_quoted(args)?),
+    _ => {
+      let args: Vec<&str> =
+        args.split('#').next().unwrap().split_whitespace().collect();
+      let arity = match name {
+        "newline" | "cls" | "beep" => 0,
+        "key" => 1,
+        "sound" => 2,
+        "point" => 3,
+        "line" | "circle" => 5,
+        "box" | "ellipse" => 6,
+        _ => return Err(format!("unknown event: {}", name)),
+      };
+      if args.len() != arity {
+        return Err(format!(
+          "{} requires {} arguments, found {}",
+          name,
+          arity,
+          args.len()
+        ));
+      }
+      let num = |i: usize| -> Result<u8, String> {
+        args[i]
+          .parse()
+          .map_err(|_| format!("invalid number: {}", args[i]))
+      };
+      let num16 = |i: usize| -> Result<u16, String> {
+        args[i]
+          .parse()
+          .map_err(|_| format!("invalid number: {}", args[i]))
+      };
+      let fill = |i: usize| -> Result<bool, String> {
+        match args[i] {
+          "0" => Ok(false),
+          "1" => Ok(true),
+          arg => Err(format!("invalid fill: {}", arg)),
+        }
+      };
+      let mode = |i: usize| -> Result<DrawMode, String> {
+        match args[i] {
+          "clear" => Ok(DrawMode::Clear),
+          "or" => Ok(DrawMode::Or),
+          "xor" => Ok(DrawMode::Xor),
+          "unknown" => Ok(DrawMode::Unknown),
+          arg => Err(format!("invalid draw mode: {}", arg)),
+        }
+      };
+      match name {
+        "newline" => Event::Newline,
+        "cls" => Event::Cls,
+        "beep" => Event::Beep,
+        "key" => Event::Key(num(0)?),
+        "sound" => Event::Sound(num16(0)?, num16(1)?),
+        "point" => Event::Point((num(0)?, num(1)?), mode(2)?),
+        "line" => Event::Line((num(0)?, num(1)?), (num(2)?, num(3)?), mode(4)?),
+        "box" => {
+          Event::Box((num(0)?, num(1)?), (num(2)?, num(3)?), fill(4)?, mode(5)?)
+        }
+        "circle" => {
+          Event::Circle((num(0)?, num(1)?), num(2)?, fill(3)?, mode(4)?)
+        }
+        _ => Event::Ellipse(
+          (num(0)?, num(1)?),
+          (num(2)?, num(3)?),
+          fill(4)?,
+          mode(5)?,
+        ),
+      }
+    }
+  };
+  Ok(TimedEvent { time, event })
+}
+
+/// Parses a quoted string, optionally followed by a comment.
+fn parse_quoted(s: &str) -> Result<Vec<u8>, String> {
+  let mut chars = s.strip_prefix('"').ok_or("missing quote")?.chars();
+  let mut bytes = vec![];
+  loop {
+    match chars.next() {
+      None => return Err(format!("unclosed quote")),
+      Some('"') => break,
+      Some('\\') => match chars.next() {
+        Some('"') => bytes.push(b'"'),
+        Some('\\') => bytes.push(b'\\'),
+        Some('x') => {
+          let hex: String = chars.by_ref().take(2).collect();
+          let b = u8::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 2)
+            .ok_or_else(|| format!("invalid escape: \\x{}", hex))?;
+          bytes.push(b);
+        }
+        c => {
+          return Err(format!(
+            "invalid escape: \\{}",
+            c.map(String::from).unwrap_or_default()
+          ))
+        }
+      },
+      Some(c) if c.is_ascii() => bytes.push(c as u8),
+      Some(c) => return Err(format!("non-ASCII character: {}", c)),
+    }
+  }
+  let rest = chars.as_str().trim_start();
+  if !rest.is_empty() && !rest.starts_with('#') {
+    return Err(format!("redundant text: {}", rest));
+  }
+  Ok(bytes)
+}
+
+/// Device which records the interactions with the wrapped device.
+pub struct TracingDevice<D> {
+  inner: D,
+  /// Simulated time in milliseconds.
+  time: u64,
+  trace: Capture,
+  /// Keys to be pressed, with the time they are pressed.
+  keys: Vec<(u64, u8)>,
+  next_key: usize,
+}
+
+impl<D> TracingDevice<D> {
+  pub fn new(inner: D) -> Self {
+    Self {
+      inner,
+      time: 0,
+      trace: Capture::default(),
+      keys: vec![],
+      next_key: 0,
+    }
+  }
+
+  pub fn inner(&self) -> &D {
+    &self.inner
+  }
+
+  pub fn trace(&self) -> &Capture {
+    &self.trace
+  }
+
+  /// Returns the simulated time in milliseconds.
+  pub fn time(&self) -> u64 {
+    self.time
+  }
+
+  pub fn advance(&mut self, duration: Duration) {
+    self.time += duration.as_millis() as u64;
+  }
+
+  /// Sets the keys to be pressed, with the time they are pressed.
+  pub fn set_keys(&mut self, keys: Vec<(u64, u8)>) {
+    self.keys = keys;
+    self.next_key = 0;
+  }
+
+  /// Pops the next key, waiting until it is pressed if `wait` is true.
+  fn pop_key(&mut self, wait: bool) -> Option<u8> {
+    let &(time, key) = self.keys.get(self.next_key)?;
+    if time > self.time {
+      if !wait {
+        return None;
+      }
+      self.time = time;
+    }
+    self.next_key += 1;
+    self.record(Event::Key(key));
+    Some(key)
+  }
+
+  fn record(&mut self, event: Event) {
+    self.trace.events.push(TimedEvent {
+      time: self.time,
+      event,
+    });
+  }
+}
+
+impl<D: Device> Device for TracingDevice<D> {
+  type File = D::File;
+  type AsmState = D::AsmState;
+  type AsmError = D::AsmError;
+
+  fn get_row(&self) -> u8 {
+    self.inner.get_row()
+  }
+
+  fn get_column(&self) -> u8 {
+    self.inner.get_column()
+  }
+
+  fn set_row(&mut self, row: u8) {
+    self.inner.set_row(row)
+  }
+
+  fn set_column(&mut self, column: u8) {
+    self.inner.set_column(column)
+  }
+
+  fn text_size(&self) -> (u8, u8) {
+    self.inner.text_size()
+  }
+
+  fn print(&mut self, str: &[u8]) {
+    self.record(Event::Print(str.to_vec()));
+    self.inner.print(str)
+  }
+
+  fn newline(&mut self) {
+    self.record(Event::Newline);
+    self.inner.newline()
+  }
+
+  fn flush(&mut self) {
+    self.inner.flush()
+  }
+
+  fn draw_point(&mut self, coord: (u8, u8), mode: DrawMode) {
+    self.record(Event::Point(coord, mode));
+    self.inner.draw_point(coord, mode)
+  }
+
+  fn draw_line(&mut self, coord1: (u8, u8), coord2: (u8, u8), mode: DrawMode) {
+    self.record(Event::Line(coord1, coord2, mode));
+    self.inner.draw_line(coord1, coord2, mode)
+  }
+
+  fn draw_box(
+    &mut self,
+    coord1: (u8, u8),
+    coord2: (u8, u8),
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.record(Event::Box(coord1, coord2, fill, mode));
+    self.inner.draw_box(coord1, coord2, fill, mode)
+  }
+
+  fn draw_circle(
+    &mut self,
+    coord: (u8, u8),
+    r: u8,
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.record(Event::Circle(coord, r, fill, mode));
+    self.inner.draw_circle(coord, r, fill, mode)
+  }
+
+  fn draw_ellipse(
+    &mut self,
+    coord: (u8, u8),
+    radius: (u8, u8),
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.record(Event::Ellipse(coord, radius, fill, mode));
+    self.inner.draw_ellipse(coord, radius, fill, mode)
+  }
+
+  fn check_point(&self, coord: (i32, i32)) -> bool {
+    self.inner.check_point(coord)
+  }
+
+  fn check_key(&self, key: u8) -> bool {
+    match self.keys.get(self.next_key) {
+      Some(&(time, k)) if time <= self.time && k == key => true,
+      _ => self.inner.check_key(key),
+    }
+  }
+
+  fn key(&mut self) -> Option<u8> {
+    self.pop_key(false).or_else(|| self.inner.key())
+  }
+
+  fn read_byte(&self, addr: u16) -> u8 {
+    self.inner.read_byte(addr)
+  }
+
+  fn write_byte(&mut self, addr: u16, byte: u8) {
+    self.inner.write_byte(addr, byte)
+  }
+
+  fn is_screen_addr(&self, addr: u16) -> bool {
+    self.inner.is_screen_addr(addr)
+  }
+
+  fn user_quit(&self) -> bool {
+    self.inner.user_quit()
+  }
+
+  fn open_file(
+    &mut self,
+    file: &mut Self::File,
+    name: &[u8],
+    read: bool,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<()> {
+    self.inner.open_file(file, name, read, write, truncate)
+  }
+
+  fn cls(&mut self) {
+    self.record(Event::Cls);
+    self.inner.cls()
+  }
+
+  fn exec_asm(
+    &mut self,
+    steps: &mut usize,
+    state: AsmExecState<Self::AsmState>,
+  ) -> Result<Option<Self::AsmState>, Self::AsmError> {
+    self.inner.exec_asm(steps, state)
+  }
+
+  fn set_screen_mode(&mut self, mode: ScreenMode) {
+    self.inner.set_screen_mode(mode)
+  }
+
+  fn set_print_mode(&mut self, mode: PrintMode) {
+    self.inner.set_print_mode(mode)
+  }
+
+  fn sleep_unit(&self) -> Duration {
+    self.inner.sleep_unit()
+  }
+
+  fn sleep(&mut self, duration: Duration) -> Duration {
+    self.inner.sleep(duration)
+  }
+
+  fn beep(&mut self) {
+    self.record(Event::Beep);
+    self.inner.beep()
+  }
+
+  fn play_notes(&mut self, notes: &[u8]) {
+    self.record(Event::Play(notes.to_vec()));
+    self.inner.play_notes(notes)
+  }
+
+  fn sound(&mut self, frequency: u16, duration: Duration) {
+    self.record(Event::Sound(frequency, duration.as_millis() as u16));
+    self.inner.sound(frequency, duration)
+  }
+
+  fn clear_cursor(&mut self) {
+    self.inner.clear_cursor()
+  }
+
+  fn eof_behavior(&self) -> EofBehavior {
+    self.inner.eof_behavior()
+  }
+
+  fn int_overflow(&self) -> IntOverflow {
+    self.inner.int_overflow()
+  }
+
+  fn invalid_notes(&self) -> InvalidNotes {
+    self.inner.invalid_notes()
+  }
+
+  fn selector_rounding(&self) -> SelectorRounding {
+    self.inner.selector_rounding()
+  }
+
+  fn max_string_len(&self) -> usize {
+    self.inner.max_string_len()
+  }
+
+  fn set_context(&mut self, location: &Location) {
+    self.inner.set_context(location)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+  /// The event occurs in both the capture and the trace. `time` is the time
+  /// in the trace.
+  Same { expected: TimedEvent, time: u64 },
+  /// The event occurs in the capture only.
+  Missing(TimedEvent),
+  /// The event occurs in the trace only.
+  Extra(TimedEvent),
+}
+
+/// Result of comparing the trace of the simulator against a capture.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+  pub diff: Vec<DiffEntry>,
+  /// The last result returned by the VM.
+  pub result: ExecResult,
+}
+
+impl Comparison {
+  /// Compares two traces by their longest common subsequence of events,
+  /// ignoring the time. Traces are normalized first.
+  pub fn new(expected: &Capture, actual: &Capture, result: ExecResult) -> Self {
+    let mut expected = expected.clone();
+    expected.normalize();
+    let mut actual = actual.clone();
+    actual.normalize();
+    let (e, a) = (&expected.events, &actual.events);
+
+    // lcs[i][j] is the length of the LCS of e[i..] and a[j..].
+    let mut lcs = vec![vec![0u32; a.len() + 1]; e.len() + 1];
+    for i in (0..e.len()).rev() {
+      for j in (0..a.len()).rev() {
+        lcs[i][j] = if e[i].event == a[j].event {
+          lcs[i + 1][j + 1] + 1
+        } else {
+          lcs[i + 1][j].max(lcs[i][j + 1])
+        };
+      }
+    }
+
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < e.len() && j < a.len() {
+      if e[i].event == a[j].event {
+        diff.push(DiffEntry::Same {
+          expected: e[i].clone(),
+          time: a[j].time,
+        });
+        i += 1;
+        j += 1;
+      } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+        diff.push(DiffEntry::Missing(e[i].clone()));
+        i += 1;
+      } else {
+        diff.push(DiffEntry::Extra(a[j].clone()));
+        j += 1;
+      }
+    }
+    diff.extend(e[i..].iter().cloned().map(DiffEntry::Missing));
+    diff.extend(a[j..].iter().cloned().map(DiffEntry::Extra));
+
+    Self { diff, result }
+  }
+
+  pub fn matched(&self) -> usize {
+    self
+      .diff
+      .iter()
+      .filter(|d| matches!(d, DiffEntry::Same { .. }))
+      .count()
+  }
+
+  /// Ratio of matched events to all events, in [0, 1]. Two empty traces are
+  /// fully matched.
+  pub fn fidelity(&self) -> f64 {
+    let total = self.diff.len() + self.matched();
+    if total == 0 {
+      1.0
+    } else {
+      (self.matched() * 2) as f64 / total as f64
+    }
+  }
+
+  /// Mean absolute difference of the time of matched events, in
+  /// milliseconds.
+  pub fn timing_error(&self) -> Option<f64> {
+    let errors: Vec<u64> = self
+      .diff
+      .iter()
+      .filter_map(|d| match d {
+        DiffEntry::Same { expected, time } => {
+          Some(expected.time.abs_diff(*time))
+        }
+        _ => None,
+      })
+      .collect();
+    if errors.is_empty() {
+      None
+    } else {
+      Some(errors.iter().sum::<u64>() as f64 / errors.len() as f64)
+    }
+  }
+}
+
+impl Display for Comparison {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    for d in &self.diff {
+      match d {
+        DiffEntry::Same { expected, time } => {
+          writeln!(f, "  {} {} ({})", expected.time, expected.event, time)?
+        }
+        DiffEntry::Missing(e) => writeln!(f, "- {} {}", e.time, e.event)?,
+        DiffEntry::Extra(e) => writeln!(f, "+ {} {}", e.time, e.event)?,
+      }
+    }
+    write!(f, "fidelity: {:.1}%", self.fidelity() * 100.0)?;
+    if let Some(err) = self.timing_error() {
+      write!(f, ", timing error: {:.1}ms", err)?;
+    }
+    Ok(())
+  }
+}
+
+/// Runs the program of `document` on `device`, feeding it the keys in
+/// `capture`, and compares the trace against `capture`.
+///
+/// The replay stops when the program ends, fails, requests keyboard input
+/// which cannot be replayed, waits for a key after all keys are consumed, or
+/// exceeds `max_steps` instructions.
+pub fn replay<D>(
+  document: &mut Document,
+  device: D,
+  capture: &Capture,
+  max_steps: usize,
+) -> Result<Comparison, ContainsErrors>
+where
+  D: Device,
+  D::AsmError: ToString,
+{
+  let mut device = TracingDevice::new(device);
+  device.set_keys(
+    capture
+      .events
+      .iter()
+      .filter_map(|e| match e.event {
+        Event::Key(key) => Some((e.time, key)),
+        _ => None,
+      })
+      .collect(),
+  );
+
+  let mut vm = document.create_vm(&mut device)?;
+  vm.start();
+  let result =
+    vm.run_steps(ExecInput::None, max_steps, |vm, result| match result {
+      ExecResult::Sleep(duration) => {
+        vm.device_mut().advance(*duration);
+        Some(ExecInput::None)
+      }
+      ExecResult::Warning { .. } => Some(ExecInput::None),
+      ExecResult::InKey => vm.device_mut().pop_key(true).map(ExecInput::Key),
+      _ => None,
+    });
+  drop(vm);
+
+  Ok(Comparison::new(capture, device.trace(), result))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use widestring::Utf16String;
+
+  #[test]
+  fn parse() {
+    let text = r#"
+# comment
+0 print "A\"\\\xB0\xA1"  # trailing comment
+10 newline
+20 key 13
+30 box 1 2 3 4 1 xor
+40 ellipse 1 2 3 4 0 unknown
+50 play "\x01"
+60 sound 1000 250
+"#;
+    let capture = Capture::parse(text).unwrap();
+    assert_eq!(
+      capture.events[0].event,
+      Event::Print(b"A\"\\\xb0\xa1".to_vec())
+    );
+    assert_eq!(
+      capture.events[3],
+      TimedEvent {
+        time: 30,
+        event: Event::Box((1, 2), (3, 4), true, DrawMode::Xor)
+      }
+    );
+    assert_eq!(capture.events[6].event, Event::Sound(1000, 250));
+    assert_eq!(Capture::parse(&capture.to_string()).unwrap(), capture);
+
+    assert_eq!(
+      Capture::parse("0 cls\n1 point 1 2").unwrap_err(),
+      ParseCaptureError {
+        line: 1,
+        message: "point requires 3 arguments, found 2".to_owned()
+      }
+    );
+    assert!(Capture::parse("x cls").is_err());
+    assert!(Capture::parse("0 print \"a").is_err());
+    assert!(Capture::parse("0 print \"\\xg0\"").is_err());
+    assert!(Capture::parse("0 draw 1").is_err());
+  }
+
+  #[test]
+  fn compare() {
+    let expected =
+      Capture::parse("0 print \"AB\"\n0 newline\n100 beep\n200 cls\n").unwrap();
+    let actual = Capture::parse(
+      "0 print \"A\"\n0 print \"B\"\n0 newline\n150 cls\n1 beep",
+    )
+    .unwrap();
+    let cmp = Comparison::new(&expected, &actual, ExecResult::End);
+    assert_eq!(cmp.matched(), 3);
+    assert_eq!(cmp.fidelity(), 0.75);
+    assert_eq!(cmp.timing_error(), Some(50.0 / 3.0));
+    assert_eq!(
+      cmp.to_string(),
+      r#"  0 print "AB" (0)
+  0 newline (0)
+- 100 beep
+  200 cls (150)
++ 1 beep
+fidelity: 75.0%, timing error: 16.7ms"#
+    );
+  }
+
+  #[test]
+  fn replay_program() {
+    crate::machine::init_machines_once().unwrap();
+    let mut document = Document::from_text(Utf16String::from_str(
+      "10 print \"HI\":a$=inkey$\r\n20 graph:draw 1,2:sleep 100:cls\r\n30 beep",
+    ));
+    let device = document.create_device("");
+    let capture = Capture::parse(
+      "0 print \"HI\"\n0 newline\n500 key 65\n500 point 1 2 or\n600 cls\n\
+      600 beep",
+    )
+    .unwrap();
+    let cmp = replay(&mut document, device, &capture, 100000)
+      .ok()
+      .unwrap();
+    assert!(matches!(cmp.result, ExecResult::End));
+    assert_eq!(cmp.fidelity(), 1.0, "{}", cmp);
+  }
+}
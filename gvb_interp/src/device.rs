@@ -1,9 +1,19 @@
 use std::io;
+use std::time::Duration;
 
-use super::{PrintMode, ScreenMode};
-use crate::machine::EofBehavior;
+use super::{Location, PrintMode, ScreenMode};
+use crate::machine::{
+  EofBehavior, IntOverflow, InvalidNotes, SelectorRounding,
+};
 
+pub mod audio;
+pub mod clock;
 pub mod default;
+pub mod fault;
+pub mod keys;
+pub mod memory_watch;
+pub mod notes;
+pub mod transliterate;
 
 pub enum KeyCode {
   Enter = 13,
@@ -15,26 +25,41 @@ pub trait Device {
   type AsmState;
   type AsmError;
 
-  /// Range: [0, 4]
+  /// Range: [0, rows - 1]. See `text_size`.
   fn get_row(&self) -> u8;
 
-  /// Range: [0, 19]
+  /// Range: [0, columns - 1]. See `text_size`.
   fn get_column(&self) -> u8;
 
-  /// Range: [0, 4]
+  /// Range: [0, rows - 1]. See `text_size`.
   fn set_row(&mut self, row: u8);
 
-  /// Range: [0, 19]
+  /// Range: [0, columns - 1]. See `text_size`.
   fn set_column(&mut self, column: u8);
 
+  /// Number of rows and columns of the text screen in the current mode, e.g.
+  /// 5 rows and 20 columns in the normal font, or 6 rows and 26 columns in
+  /// the small font. The arguments of LOCATE and TAB are checked against
+  /// them.
+  fn text_size(&self) -> (u8, u8);
+
   fn print(&mut self, str: &[u8]);
 
   fn newline(&mut self);
 
   fn flush(&mut self);
 
+  /// Coordinates of graphics statements are checked to be in 0~255 by the
+  /// VM, but may be outside the screen. Points outside the screen are not
+  /// drawn.
   fn draw_point(&mut self, coord: (u8, u8), mode: DrawMode);
 
+  /// Points of slanted lines outside the screen are not drawn. Horizontal
+  /// lines are clamped to the screen, i.e. a coordinate outside the screen is
+  /// moved to the last column or row. Vertical lines are not drawn if the
+  /// column or the upper end is outside the screen, and the lower end is
+  /// clamped to the last row. Boxes are drawn with horizontal and vertical
+  /// lines.
   fn draw_line(&mut self, coord1: (u8, u8), coord2: (u8, u8), mode: DrawMode);
 
   fn draw_box(
@@ -45,6 +70,11 @@ pub trait Device {
     mode: DrawMode,
   );
 
+  /// The points of circles and ellipses are computed with wrapping byte
+  /// arithmetic, so parts left of or above the screen wrap around to large
+  /// coordinates, and are drawn only if they fall on the screen. The
+  /// horizontal lines of filled shapes start at column 0 if the left end wraps
+  /// around, and are clamped as in `draw_line`.
   fn draw_circle(&mut self, coord: (u8, u8), r: u8, fill: bool, mode: DrawMode);
 
   fn draw_ellipse(
@@ -65,6 +95,14 @@ pub trait Device {
 
   fn write_byte(&mut self, addr: u16, byte: u8);
 
+  /// Returns true if `addr` is in the graphics memory or the text buffer.
+  /// POKE is only allowed to these addresses in read-only mode.
+  ///
+  /// By default no address is considered part of the screen.
+  fn is_screen_addr(&self, _addr: u16) -> bool {
+    false
+  }
+
   /// Returns true if user is pressing ESC.
   fn user_quit(&self) -> bool;
 
@@ -95,13 +133,37 @@ pub trait Device {
 
   fn sleep_unit(&self) -> std::time::Duration;
 
+  /// Called when the program sleeps for `duration`. Returns the duration the
+  /// host should actually wait, which is zero if the time is simulated.
+  fn sleep(&mut self, duration: Duration) -> Duration;
+
   fn beep(&mut self);
 
+  /// Plays the note string of a PLAY statement. See [`notes`] for the
+  /// syntax. Malformed strings are reported by the VM before this is called.
   fn play_notes(&mut self, notes: &[u8]);
 
+  /// Plays a tone of `frequency` Hz for `duration`, without blocking.
+  fn sound(&mut self, frequency: u16, duration: Duration);
+
   fn clear_cursor(&mut self);
 
   fn eof_behavior(&self) -> EofBehavior;
+
+  fn int_overflow(&self) -> IntOverflow;
+
+  /// How PLAY statements with malformed note strings are reported.
+  fn invalid_notes(&self) -> InvalidNotes;
+
+  /// How ON ... GOTO/GOSUB statements round non-integral selectors.
+  fn selector_rounding(&self) -> SelectorRounding;
+
+  /// Maximum length of strings in bytes.
+  fn max_string_len(&self) -> usize;
+
+  /// Called when execution enters a statement. `location` is the location of
+  /// the statement.
+  fn set_context(&mut self, _location: &Location) {}
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
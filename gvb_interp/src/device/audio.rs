@@ -0,0 +1,77 @@
+//! Audio output of the default device. Tones played by SOUND statements are
+//! queued in the device, and synthesized as square waves, like the buzzer of
+//! the real machine, when the host pulls the samples.
+
+use std::time::Duration;
+
+/// Amplitude of the square wave, which leaves headroom for mixing.
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+/// Maximum number of tones queued in the device. Older tones are dropped if
+/// the host does not take them in time, e.g. when the host has no audio
+/// output and a program plays in a loop.
+pub const MAX_TONES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+  /// In Hz. 0 is silence.
+  pub frequency: u16,
+  pub duration: Duration,
+}
+
+/// Synthesizes `tones` one after another as mono signed 16-bit samples.
+pub fn synthesize(tones: &[Tone], sample_rate: u32) -> Vec<i16> {
+  let mut samples = vec![];
+  for tone in tones {
+    let len = (tone.duration.as_secs_f64() * sample_rate as f64).round();
+    if tone.frequency == 0 {
+      samples.extend((0..len as u64).map(|_| 0));
+      continue;
+    }
+    let half_periods_per_sample =
+      2.0 * tone.frequency as f64 / sample_rate as f64;
+    samples.extend((0..len as u64).map(|i| {
+      if (i as f64 * half_periods_per_sample) as u64 & 1 == 0 {
+        AMPLITUDE
+      } else {
+        -AMPLITUDE
+      }
+    }));
+  }
+  samples
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn square_wave() {
+    let tones = [
+      Tone {
+        frequency: 1000,
+        duration: Duration::from_millis(2),
+      },
+      Tone {
+        frequency: 2000,
+        duration: Duration::from_millis(1),
+      },
+      Tone {
+        frequency: 0,
+        duration: Duration::from_millis(1),
+      },
+    ];
+    let samples = synthesize(&tones, 8000);
+    assert_eq!(samples.len(), 32);
+    let a = AMPLITUDE;
+    assert_eq!(
+      samples,
+      vec![
+        a, a, a, a, -a, -a, -a, -a, a, a, a, a, -a, -a, -a, -a, // 1000 Hz
+        a, a, -a, -a, a, a, -a, -a, // 2000 Hz
+        0, 0, 0, 0, 0, 0, 0, 0, // silence
+      ]
+    );
+  }
+}
@@ -0,0 +1,99 @@
+//! Source of the time of the default device, which is read by programs
+//! through the clock addresses of the machine profile.
+
+use chrono::{Local, NaiveDateTime};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+pub trait Clock {
+  /// Returns the current local time.
+  fn now(&self) -> NaiveDateTime;
+
+  /// Called when the program sleeps for `duration`. Returns the duration the
+  /// host should actually wait.
+  fn sleep(&mut self, duration: Duration) -> Duration;
+}
+
+/// The wall clock. Sleeping is left to the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> NaiveDateTime {
+    Local::now().naive_local()
+  }
+
+  fn sleep(&mut self, duration: Duration) -> Duration {
+    duration
+  }
+}
+
+/// A clock which only advances when told to, or when the program sleeps, so
+/// that time-dependent programs can be tested without waiting. Clones share
+/// the same time, so that the harness can keep a clone after handing one to
+/// the device.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+  now: Rc<Cell<NaiveDateTime>>,
+}
+
+impl ManualClock {
+  pub fn new(now: NaiveDateTime) -> Self {
+    Self {
+      now: Rc::new(Cell::new(now)),
+    }
+  }
+
+  pub fn set(&self, now: NaiveDateTime) {
+    self.now.set(now);
+  }
+
+  pub fn advance(&self, duration: Duration) {
+    // durations too long for chrono are not meaningful for programs anyway
+    if let Ok(duration) = chrono::Duration::from_std(duration) {
+      self.now.set(self.now.get() + duration);
+    }
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> NaiveDateTime {
+    self.now.get()
+  }
+
+  fn sleep(&mut self, duration: Duration) -> Duration {
+    self.advance(duration);
+    Duration::ZERO
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::NaiveDate;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn manual_clock() {
+    let start = NaiveDate::from_ymd_opt(2020, 1, 2)
+      .unwrap()
+      .and_hms_opt(3, 4, 5)
+      .unwrap();
+    let clock = ManualClock::new(start);
+    let mut device_clock: Box<dyn Clock> = Box::new(clock.clone());
+
+    assert_eq!(
+      device_clock.sleep(Duration::from_millis(1500)),
+      Duration::ZERO
+    );
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(
+      device_clock.now(),
+      start + chrono::Duration::milliseconds(61500)
+    );
+
+    clock.set(start);
+    assert_eq!(device_clock.now(), start);
+  }
+}
@@ -1,24 +1,44 @@
+use super::audio::{self, Tone, MAX_TONES};
+use super::clock::{Clock, SystemClock};
+use super::keys::KeyPosition;
+use super::memory_watch::{MemoryWatchId, MemoryWatches};
+use super::notes;
+use super::transliterate::{PrintedText, Transliterator};
 use super::*;
-use crate::machine::{AddrProp, BrkKind, EofBehavior, MachineProps};
+use crate::machine::{
+  AddrProp, BrkKind, EofBehavior, IntOverflow, InvalidNotes, MachineProps,
+  SelectorRounding,
+};
+use crate::report::Recording;
 use crate::ByteString;
 use chrono::prelude::*;
 use emulator_6502::{Interface6502, MOS6502};
-use std::fs::{File as FsFile, OpenOptions};
+use std::collections::VecDeque;
+use std::fs::{self, File as FsFile, OpenOptions};
 use std::io::{self, prelude::*, SeekFrom};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 const CHAR_HEIGHT: usize = 16;
+const SMALL_CHAR_WIDTH: usize = 6;
+const SMALL_CHAR_HEIGHT: usize = 12;
 
-const TEXT_COLUMNS: usize = 20;
-const TEXT_ROWS: usize = 5;
+pub(crate) const TEXT_COLUMNS: usize = 20;
+pub(crate) const TEXT_ROWS: usize = 5;
 const TEXT_BYTES: usize = TEXT_COLUMNS * TEXT_ROWS;
+const SMALL_TEXT_COLUMNS: usize = 26;
+const SMALL_TEXT_ROWS: usize = 6;
+const SMALL_TEXT_BYTES: usize = SMALL_TEXT_COLUMNS * SMALL_TEXT_ROWS;
 
 const ASCII_8_DATA: &[u8] = include_bytes!("../../data/ascii_8.dat");
+const ASCII_12_DATA: &[u8] = include_bytes!("../../data/ascii_12.dat");
 const ASCII_16_DATA: &[u8] = include_bytes!("../../data/ascii_16.dat");
+/// Each row of a glyph takes 12 bits.
+const GB2312_12_DATA: &[u8] = include_bytes!("../../data/gb2312_12.dat");
 const GB2312_16_DATA: &[u8] = include_bytes!("../../data/gb2312_16.dat");
 const EMOJI_16_DATA: &[u8] = include_bytes!("../../data/emoji_16.dat");
 
-mod screen {
+pub(crate) mod screen {
   pub const WIDTH: usize = 160;
   pub const HEIGHT: usize = 80;
   pub const WIDTH_IN_BYTE: usize = WIDTH >> 3;
@@ -28,18 +48,110 @@ mod screen {
 pub struct DefaultDevice {
   props: MachineProps,
   memory: [u8; 65536],
-  inverse_text: [bool; TEXT_BYTES],
+  inverse_text: [bool; SMALL_TEXT_BYTES],
+  font: TextFont,
   row: u8,
   column: u8,
   screen_mode: ScreenMode,
   print_mode: PrintMode,
   cursor: CursorState,
+  /// Dirty area of the presented screen, not taken by the host yet.
   graphics_dirty: Option<Rect>,
+  present_policy: PresentPolicy,
+  /// Graphics memory presented to the host, if double buffering is enabled.
+  front_buffer: Option<Box<[u8; screen::BYTES]>>,
+  /// Dirty area of the graphics memory not presented yet.
+  back_dirty: Option<Rect>,
+  present_count: usize,
   data_dir: PathBuf,
+  /// Subdirectory of `data_dir` where files are stored.
+  storage_namespace: Option<PathBuf>,
   /// NOTE key mapping must be zero page address.
   key_mapping_addr_set: [u32; 8],
+  context: Option<Location>,
+  secondary_storage: Option<SecondaryStorage>,
+  recording: Option<Recording>,
+  memory_watches: MemoryWatches,
+  /// Tones played but not taken by the host yet. At most [`MAX_TONES`]
+  /// recent tones are kept.
+  tones: VecDeque<Tone>,
+  clock: Box<dyn Clock>,
+  transliterator: Option<Box<dyn Transliterator>>,
+  /// Transliterated text not taken by the host yet.
+  printed_text: Vec<PrintedText>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextFont {
+  /// 8x16 half-width and 16x16 full-width characters, in 20 columns and 5
+  /// rows.
+  Normal,
+  /// 6x12 half-width and 12x12 full-width characters, in 26 columns and 6
+  /// rows. Emojis are shrunk from the 16x16 glyphs.
+  Small,
+}
+
+/// When the graphics memory is presented to the host. Except for
+/// [`PresentPolicy::Immediate`], drawing is done in a back buffer, and the
+/// host sees the screen only as of the last presentation, which avoids
+/// flickering when the host repaints in the middle of drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentPolicy {
+  /// No double buffering. Drawing is visible to the host immediately.
+  Immediate,
+  /// Presented when the text is flushed to the screen, e.g. at the end of
+  /// PRINT and before INPUT.
+  Flush,
+  /// Presented at each newline of the text.
+  Newline,
+  /// Presented before each statement is executed. The drawing of the last
+  /// statement executed is presented only by [`DefaultDevice::present`],
+  /// e.g. when the program ends.
+  Statement,
+  /// Presented only by [`DefaultDevice::present`].
+  Manual,
+}
+
+/// Dimensions of the text mode screen in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextGrid {
+  pub columns: u8,
+  pub rows: u8,
+  /// Width of half-width characters in pixels.
+  pub char_width: u8,
+  /// Height of a row in pixels.
+  pub char_height: u8,
+}
+
+/// Provider of files on a secondary storage, e.g. the SD card of expanded
+/// hardware. See [`DefaultDevice::set_secondary_storage`].
+pub trait Storage {
+  /// `name` does not include the prefix of the storage.
+  fn open(
+    &mut self,
+    name: &str,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<Box<dyn StorageFile>>;
+}
+
+/// A file opened by a [`Storage`]. The whole file is read when it is opened,
+/// and written back from the start when it is closed.
+pub trait StorageFile: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> StorageFile for T {}
+
+/// Storage whose files are stored in a directory.
+pub struct DirStorage {
+  dir: PathBuf,
+}
+
+struct SecondaryStorage {
+  prefix: Vec<u8>,
+  storage: Box<dyn Storage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Rect {
   pub left: usize,
   pub top: usize,
@@ -61,7 +173,7 @@ pub struct DefaultFileHandle {
 
 enum FileState {
   Open {
-    file: FsFile,
+    file: Box<dyn StorageFile>,
     data: Vec<u8>,
     dirty: bool,
   },
@@ -78,16 +190,36 @@ impl DefaultDevice {
     let mut d = Self {
       props,
       memory: [0; 65536],
-      inverse_text: [false; TEXT_BYTES],
+      inverse_text: [false; SMALL_TEXT_BYTES],
+      font: TextFont::Normal,
       row: 0,
       column: 0,
       screen_mode: ScreenMode::Text,
       print_mode: PrintMode::Normal,
       cursor: CursorState::None,
       graphics_dirty: None,
+      present_policy: PresentPolicy::Immediate,
+      front_buffer: None,
+      back_dirty: None,
+      present_count: 0,
       data_dir: data_dir.into(),
+      storage_namespace: None,
       key_mapping_addr_set: [0; 8],
+      context: None,
+      secondary_storage: None,
+      recording: None,
+      memory_watches: MemoryWatches::default(),
+      tones: VecDeque::new(),
+      clock: Box::new(SystemClock),
+      transliterator: None,
+      printed_text: vec![],
     };
+    if let Some(storage) = &d.props.secondary_storage {
+      d.secondary_storage = Some(SecondaryStorage {
+        prefix: storage.prefix.as_bytes().to_vec(),
+        storage: Box::new(DirStorage::new(d.data_dir.join(&storage.dir))),
+      });
+    }
     for &addr in &d.props.key_mapping_addrs {
       d.key_mapping_addr_set[addr as usize >> 5] |= 1 << (addr & 31);
     }
@@ -103,12 +235,125 @@ impl DefaultDevice {
       self.memory[addr as usize] = 0xff;
     }
     self.inverse_text.fill(false);
+    self.font = TextFont::Normal;
     self.row = 0;
     self.column = 0;
     self.screen_mode = ScreenMode::Text;
     self.print_mode = PrintMode::Normal;
     self.cursor = CursorState::None;
     self.graphics_dirty = None;
+    self.back_dirty = None;
+    if let Some(front) = &mut self.front_buffer {
+      front.fill(0);
+    }
+    self.present_count = 0;
+    self.context = None;
+    self.tones.clear();
+    self.printed_text.clear();
+  }
+
+  /// Files whose names start with `prefix` (case-insensitive) are opened
+  /// through `storage`, instead of the data directory. Replaces the secondary
+  /// storage configured in the machine profile.
+  pub fn set_secondary_storage(
+    &mut self,
+    prefix: impl Into<Vec<u8>>,
+    storage: Box<dyn Storage>,
+  ) {
+    self.secondary_storage = Some(SecondaryStorage {
+      prefix: prefix.into(),
+      storage,
+    });
+  }
+
+  /// Stores files opened by the program in the subdirectory `namespace` of
+  /// the data directory, so that programs using the same file names do not
+  /// overwrite the files of each other. Files not in the subdirectory are
+  /// read from the data directory, and copied to the subdirectory before
+  /// being modified. See [`Document::storage_namespace`].
+  ///
+  /// Files on the secondary storage are not affected.
+  ///
+  /// [`Document::storage_namespace`]: crate::Document::storage_namespace
+  pub fn set_storage_namespace(&mut self, namespace: Option<String>) {
+    self.storage_namespace = namespace.map(PathBuf::from);
+  }
+
+  pub fn storage_namespace(&self) -> Option<&Path> {
+    self.storage_namespace.as_deref()
+  }
+
+  fn resolve_file_path(
+    &self,
+    name: &str,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<PathBuf> {
+    let shared_path = self.data_dir.join(name);
+    let dir = match &self.storage_namespace {
+      Some(namespace) => self.data_dir.join(namespace),
+      None => return Ok(shared_path),
+    };
+    let path = dir.join(name);
+    if path.exists() {
+      return Ok(path);
+    }
+    if !write {
+      return Ok(shared_path);
+    }
+    fs::create_dir_all(&dir)?;
+    if !truncate && shared_path.exists() {
+      fs::copy(&shared_path, &path)?;
+    }
+    Ok(path)
+  }
+
+  /// Replaces the clock read through the clock addresses and advanced by
+  /// SLEEP statements, e.g. with a [`super::clock::ManualClock`] in tests.
+  pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Sets the transliterator applied to the text printed by programs. The
+  /// transliterated text is taken by [`Self::take_printed_text`].
+  pub fn set_transliterator(
+    &mut self,
+    transliterator: Option<Box<dyn Transliterator>>,
+  ) {
+    self.transliterator = transliterator;
+    self.printed_text.clear();
+  }
+
+  /// Returns the transliterated text printed since the last call.
+  pub fn take_printed_text(&mut self) -> Vec<PrintedText> {
+    std::mem::take(&mut self.printed_text)
+  }
+
+  /// Returns the location of the statement being executed.
+  pub fn context(&self) -> Option<&Location> {
+    self.context.as_ref()
+  }
+
+  /// Starts recording executed lines, screenshots and output for a run
+  /// report. See [`crate::report`]. The recording is kept when the device is
+  /// reset, so that it covers all runs until it is taken.
+  pub fn start_recording(&mut self) {
+    self.recording = Some(Recording::default());
+  }
+
+  pub fn recording(&self) -> Option<&Recording> {
+    self.recording.as_ref()
+  }
+
+  /// Stops recording and returns the recording.
+  pub fn take_recording(&mut self) -> Option<Recording> {
+    self.recording.take()
+  }
+
+  /// Returns the position of the key with `code` in the keyboard matrix of
+  /// the machine, or None if the machine does not have the key.
+  pub fn key_position(&self, code: u8) -> Option<KeyPosition> {
+    self.props.key_position(code)
   }
 
   pub fn fire_key_down(&mut self, key: u8) {
@@ -124,16 +369,17 @@ impl DefaultDevice {
     }
   }
 
+  /// The cursor is presented immediately unless the present policy is
+  /// [`PresentPolicy::Manual`].
   pub fn blink_cursor(&mut self) {
     if self.screen_mode != ScreenMode::Text {
       return;
     }
 
     if self.cursor == CursorState::None {
-      let char_addr = self.props.text_buffer_base_addr as usize
-        + self.row as usize * TEXT_COLUMNS
-        + self.column as usize;
-      self.cursor = if self.memory[char_addr] >= 128 {
+      let offset =
+        self.row as usize * self.text_columns() + self.column as usize;
+      self.cursor = if self.text()[offset] >= 128 {
         CursorState::FullWidth
       } else {
         CursorState::HalfWidth
@@ -143,101 +389,549 @@ impl DefaultDevice {
       self.inverse_cursor(self.cursor);
       self.cursor = CursorState::None;
     }
+
+    if self.front_buffer.is_some()
+      && self.present_policy != PresentPolicy::Manual
+    {
+      self.present();
+    }
   }
 
-  #[cfg(test)]
-  fn text_buffer(&self) -> &[u8] {
-    &self.memory[self.props.text_buffer_base_addr as usize
-      ..self.props.text_buffer_base_addr as usize + TEXT_ROWS * TEXT_COLUMNS]
+  pub fn text_font(&self) -> TextFont {
+    self.font
+  }
+
+  /// Switches the font of the text mode screen, which clears the screen.
+  fn set_text_font(&mut self, font: TextFont) {
+    if font != self.font {
+      self.text_mut().fill(0);
+      self.font = font;
+      self.cls();
+    }
+  }
+
+  pub fn text_grid(&self) -> TextGrid {
+    TextGrid {
+      columns: self.text_columns() as u8,
+      rows: self.text_rows() as u8,
+      char_width: self.char_width() as u8,
+      char_height: self.char_height() as u8,
+    }
+  }
+
+  fn text_columns(&self) -> usize {
+    match self.font {
+      TextFont::Normal => TEXT_COLUMNS,
+      TextFont::Small => SMALL_TEXT_COLUMNS,
+    }
+  }
+
+  fn text_rows(&self) -> usize {
+    match self.font {
+      TextFont::Normal => TEXT_ROWS,
+      TextFont::Small => SMALL_TEXT_ROWS,
+    }
   }
 
+  fn char_width(&self) -> usize {
+    match self.font {
+      TextFont::Normal => 8,
+      TextFont::Small => SMALL_CHAR_WIDTH,
+    }
+  }
+
+  fn char_height(&self) -> usize {
+    match self.font {
+      TextFont::Normal => CHAR_HEIGHT,
+      TextFont::Small => SMALL_CHAR_HEIGHT,
+    }
+  }
+
+  /// The text buffer of both fonts starts at the text buffer address.
+  fn text_bytes(&self) -> usize {
+    match self.font {
+      TextFont::Normal => TEXT_BYTES,
+      TextFont::Small => SMALL_TEXT_BYTES,
+    }
+  }
+
+  fn text(&self) -> &[u8] {
+    let base_addr = self.props.text_buffer_base_addr as usize;
+    &self.memory[base_addr..base_addr + self.text_bytes()]
+  }
+
+  fn text_mut(&mut self) -> &mut [u8] {
+    let base_addr = self.props.text_buffer_base_addr as usize;
+    let text_bytes = self.text_bytes();
+    &mut self.memory[base_addr..base_addr + text_bytes]
+  }
+
+  #[cfg(test)]
+  fn text_buffer(&self) -> &[u8] {
+    self.text()
+  }
+
+  /// Returns the rows of the text buffer, with trailing blanks removed.
+  pub fn text_lines(&self) -> Vec<String> {
+    self
+      .text()
+      .chunks(self.text_columns())
+      .map(|row| {
+        let row: Vec<u8> =
+          row.iter().map(|&b| if b == 0 { b' ' } else { b }).collect();
+        let row =
+          ByteString::from(row).to_string_lossy(self.props.emoji_version);
+        row.trim_end().to_owned()
+      })
+      .collect()
+  }
+
+  /// Returns the graphics memory presented to the host. The address of the
+  /// returned memory changes when double buffering is turned on or off by
+  /// [`Self::set_present_policy`].
   pub fn graphic_memory(&self) -> &[u8] {
+    if let Some(front) = &self.front_buffer {
+      return &front[..];
+    }
     let base_addr = self.props.graphics_base_addr as usize;
     &self.memory[base_addr..base_addr + screen::BYTES]
   }
 
+  pub fn present_policy(&self) -> PresentPolicy {
+    self.present_policy
+  }
+
+  /// Switches the present policy. The back buffer is presented when double
+  /// buffering is turned off.
+  pub fn set_present_policy(&mut self, policy: PresentPolicy) {
+    self.present_policy = policy;
+    if policy == PresentPolicy::Immediate {
+      self.present();
+      self.front_buffer = None;
+    } else if self.front_buffer.is_none() {
+      let mut front = Box::new([0; screen::BYTES]);
+      front.copy_from_slice(self.graphic_memory());
+      self.front_buffer = Some(front);
+    }
+  }
+
+  /// Presents the back buffer to the host, i.e. copies the dirty area of the
+  /// graphics memory to the front buffer, and adds it to the dirty area taken
+  /// by [`Self::take_dirty_area`]. Does nothing but counting if double
+  /// buffering is not enabled.
+  pub fn present(&mut self) {
+    self.present_count += 1;
+    let Some(front) = &mut self.front_buffer else {
+      return;
+    };
+    let Some(rect) = self.back_dirty.take() else {
+      return;
+    };
+    let base_addr = self.props.graphics_base_addr as usize;
+    let end = rect.bottom.min(screen::HEIGHT) * screen::WIDTH_IN_BYTE;
+    let start = (rect.top * screen::WIDTH_IN_BYTE).min(end);
+    front[start..end]
+      .copy_from_slice(&self.memory[base_addr + start..base_addr + end]);
+    merge_dirty_area(&mut self.graphics_dirty, rect);
+  }
+
+  /// Number of times the screen is presented since the device is created or
+  /// reset, including presentations without changes.
+  pub fn present_count(&self) -> usize {
+    self.present_count
+  }
+
+  fn present_at(&mut self, boundary: PresentPolicy) {
+    if self.present_policy == boundary {
+      self.present();
+    }
+  }
+
+  /// Returns a copy of the memory in `range`, which is truncated to the
+  /// 64KB address space.
+  pub fn dump_memory(&self, range: Range<usize>) -> Vec<u8> {
+    let end = range.end.min(self.memory.len());
+    let start = range.start.min(end);
+    self.memory[start..end].to_vec()
+  }
+
+  /// Copies `bytes` into the memory starting at `addr`, e.g. machine code
+  /// routines and data tables used by a program. Bytes beyond the 64KB
+  /// address space are discarded. Unlike POKE, any address can be written.
+  pub fn load_memory(&mut self, addr: u16, bytes: &[u8]) {
+    let start = addr as usize;
+    let end = (start + bytes.len()).min(self.memory.len());
+    self.memory[start..end].copy_from_slice(&bytes[..end - start]);
+
+    let g = self.props.graphics_base_addr as usize;
+    let start = start.max(g);
+    let end = end.min(g + screen::BYTES);
+    if start < end {
+      let top = (start - g) / screen::WIDTH_IN_BYTE;
+      let bottom = (end - g - 1) / screen::WIDTH_IN_BYTE + 1;
+      self.update_dirty_area(0, top, screen::WIDTH, bottom);
+    }
+  }
+
+  /// Starts tracking modifications of the memory in `range`, e.g. for a hex
+  /// view. See [`crate::device::memory_watch`].
+  pub fn watch_memory(&mut self, range: Range<usize>) -> MemoryWatchId {
+    self.memory_watches.add(range, &self.memory)
+  }
+
+  /// Returns false if the watch does not exist.
+  pub fn unwatch_memory(&mut self, id: MemoryWatchId) -> bool {
+    self.memory_watches.remove(id)
+  }
+
+  /// Returns the ranges of watched bytes modified since the watch is created
+  /// or the last call, or None if the watch does not exist.
+  pub fn memory_changes(
+    &mut self,
+    id: MemoryWatchId,
+  ) -> Option<Vec<Range<usize>>> {
+    self.memory_watches.changes(id, &self.memory)
+  }
+
+  /// Returns the tones played since the last call. Only the last
+  /// [`MAX_TONES`] tones are returned.
+  pub fn take_tones(&mut self) -> Vec<Tone> {
+    std::mem::take(&mut self.tones).into()
+  }
+
+  fn push_tone(&mut self, tone: Tone) {
+    if self.tones.len() == MAX_TONES {
+      self.tones.pop_front();
+    }
+    self.tones.push_back(tone);
+  }
+
+  /// Synthesizes the tones played since the last call, as mono signed 16-bit
+  /// samples at `sample_rate` Hz.
+  pub fn take_audio(&mut self, sample_rate: u32) -> Vec<i16> {
+    audio::synthesize(&self.take_tones(), sample_rate)
+  }
+
   pub fn take_dirty_area(&mut self) -> Option<Rect> {
     self.graphics_dirty.take()
   }
 
   fn inverse_cursor(&mut self, cursor: CursorState) {
-    use screen as s;
-    let mut graph_addr = self.props.graphics_base_addr as usize
-      + self.row as usize * s::WIDTH_IN_BYTE * CHAR_HEIGHT
-      + self.column as usize;
-    for i in (0..s::WIDTH_IN_BYTE * CHAR_HEIGHT).step_by(s::WIDTH_IN_BYTE) {
-      self.memory[graph_addr + i] ^= 0xff;
-    }
-
-    if cursor == CursorState::FullWidth && self.column < 19 {
-      graph_addr += 1;
-      for i in (0..s::WIDTH_IN_BYTE * CHAR_HEIGHT).step_by(s::WIDTH_IN_BYTE) {
-        self.memory[graph_addr + i] ^= 0xff;
-      }
+    let char_width = self.char_width();
+    let char_height = self.char_height();
+    let left = self.column as usize * char_width;
+    let top = self.row as usize * char_height;
+    let width = if cursor == CursorState::FullWidth
+      && (self.column as usize) < self.text_columns() - 1
+    {
+      char_width * 2
+    } else {
+      char_width
+    };
+    for y in top..top + char_height {
+      self.xor_pixels(left, y, width);
     }
 
-    let left = (self.column as usize) << 3;
-    let top = self.row as usize * CHAR_HEIGHT;
     let right = if cursor == CursorState::FullWidth {
-      left + 16
+      left + char_width * 2
     } else {
-      left + 8
+      left + char_width
     };
-    let bottom = top + CHAR_HEIGHT;
+    let bottom = top + char_height;
     self.update_dirty_area(left, top, right, bottom);
   }
 
   fn scroll_text(&mut self) {
+    let char_height = self.char_height();
     if self.screen_mode == ScreenMode::Graph {
       use screen as s;
       let graph_addr = self.props.graphics_base_addr as usize;
       self.memory.copy_within(
-        graph_addr + s::WIDTH_IN_BYTE * CHAR_HEIGHT..graph_addr + s::BYTES,
+        graph_addr + s::WIDTH_IN_BYTE * char_height..graph_addr + s::BYTES,
         graph_addr,
       );
-      self.memory[graph_addr + s::BYTES - s::WIDTH_IN_BYTE * CHAR_HEIGHT
+      self.memory[graph_addr + s::BYTES - s::WIDTH_IN_BYTE * char_height
         ..graph_addr + s::BYTES]
         .fill(0);
     }
 
-    let text_buffer_addr = self.props.text_buffer_base_addr as usize;
-    self.memory.copy_within(
-      text_buffer_addr + TEXT_COLUMNS
-        ..text_buffer_addr + TEXT_COLUMNS * TEXT_ROWS,
-      text_buffer_addr,
-    );
-    self.memory[text_buffer_addr + TEXT_COLUMNS * (TEXT_ROWS - 1)
-      ..text_buffer_addr + TEXT_COLUMNS * TEXT_ROWS]
-      .fill(0);
+    let columns = self.text_columns();
+    let text_bytes = self.text_bytes();
+    let text = self.text_mut();
+    text.copy_within(columns.., 0);
+    text[text_bytes - columns..].fill(0);
+
+    self.inverse_text[..text_bytes].copy_within(columns.., 0);
+    self.inverse_text[text_bytes - columns..text_bytes].fill(false);
+  }
+
+  /// Paints the hexadecimal code of `c` in a cell of half-width character,
+  /// with the digits stacked vertically.
+  fn paint_hex_code(&mut self, row: usize, column: usize, c: u8) {
+    let char_width = self.char_width();
+    let digit_height = self.char_height() / 2;
+    // the 8x8 digits are shrunk to fit in the small font
+    let shift = 8 + (8 - char_width) / 2;
+    let x = column * char_width;
+    let mut y = row * self.char_height();
+    for n in [c >> 4, c & 15] {
+      let code = if n < 10 { 48 + n } else { 65 + n - 10 } as usize;
+      let digit = &ASCII_8_DATA[code << 3..][..8];
+      for i in 0..digit_height {
+        let bits = (digit[i * 8 / digit_height] as u16) << shift;
+        self.set_pixels(x, y, bits, char_width);
+        y += 1;
+      }
+    }
+  }
 
-    self.inverse_text.copy_within(TEXT_COLUMNS.., 0);
-    self.inverse_text[TEXT_COLUMNS * (TEXT_ROWS - 1)..].fill(false);
+  /// Replaces `width` pixels from (`x`, `y`) of the graphics memory with the
+  /// most significant `width` bits of `bits`. The pixels must be in a row of
+  /// the screen.
+  fn set_pixels(&mut self, x: usize, y: usize, bits: u16, width: usize) {
+    let shift = x & 7;
+    let mask = (u32::MAX << (32 - width)) >> shift;
+    let bits = (((bits as u32) << 16) >> shift) & mask;
+    let addr = self.props.graphics_base_addr as usize
+      + y * screen::WIDTH_IN_BYTE
+      + (x >> 3);
+    for i in 0..3 {
+      let m = (mask >> (24 - 8 * i)) as u8;
+      if m != 0 {
+        let b = (bits >> (24 - 8 * i)) as u8;
+        self.memory[addr + i] = self.memory[addr + i] & !m | b;
+      }
+    }
   }
 
-  fn paint_hex_code(&mut self, row: usize, column: usize, mut c: u8) {
-    unsafe {
-      let mut g = self.memory.as_mut_ptr().add(
-        self.props.graphics_base_addr as usize
-          + row * screen::WIDTH_IN_BYTE * CHAR_HEIGHT
-          + column,
-      );
-      for _ in 0..2 {
-        let mut ptr = Self::nibble_to_ascii8_ptr(c >> 4);
-        c <<= 4;
-        for _ in 0..8 {
-          *g = *ptr;
-          ptr = ptr.add(1);
-          g = g.add(screen::WIDTH_IN_BYTE);
+  /// Inverts `width` pixels from (`x`, `y`) of the graphics memory. The
+  /// pixels must be in a row of the screen.
+  fn xor_pixels(&mut self, x: usize, y: usize, width: usize) {
+    let mask = (u32::MAX << (32 - width)) >> (x & 7);
+    let addr = self.props.graphics_base_addr as usize
+      + y * screen::WIDTH_IN_BYTE
+      + (x >> 3);
+    for i in 0..3 {
+      let m = (mask >> (24 - 8 * i)) as u8;
+      if m != 0 {
+        self.memory[addr + i] ^= m;
+      }
+    }
+  }
+
+  fn paint_text(&mut self) {
+    let mut char_ptr = unsafe {
+      self
+        .memory
+        .as_ptr()
+        .add(self.props.text_buffer_base_addr as usize)
+    };
+    let mut inv_ptr = self.inverse_text.as_ptr();
+    let mut graph = unsafe {
+      self
+        .memory
+        .as_mut_ptr()
+        .add(self.props.graphics_base_addr as usize)
+    };
+    let mut row = 0;
+    while row < TEXT_ROWS {
+      let mut col = 0;
+      while col < TEXT_COLUMNS {
+        let c = unsafe { *char_ptr };
+        let inv_mask = if unsafe { *inv_ptr } { 0xff } else { 0 };
+        if c == 0 {
+          char_ptr = unsafe { char_ptr.add(1) };
+          inv_ptr = unsafe { inv_ptr.add(1) };
+          col += 1;
+          continue;
+        }
+
+        if c < 128 {
+          let mut g = unsafe { graph.add(col) };
+          let mut ascii_ptr =
+            unsafe { ASCII_16_DATA.as_ptr().add(c as usize * CHAR_HEIGHT) };
+          for _ in 0..CHAR_HEIGHT {
+            unsafe {
+              *g = *ascii_ptr ^ inv_mask;
+              g = g.add(screen::WIDTH_IN_BYTE);
+              ascii_ptr = ascii_ptr.add(1);
+            }
+          }
+          char_ptr = unsafe { char_ptr.add(1) };
+          inv_ptr = unsafe { inv_ptr.add(1) };
+          col += 1;
+          continue;
+        }
+
+        if row == TEXT_ROWS - 1 && col == TEXT_COLUMNS - 1 {
+          self.paint_hex_code(row, col, c);
+          char_ptr = unsafe { char_ptr.add(1) };
+          inv_ptr = unsafe { inv_ptr.add(1) };
+          col += 1;
+          continue;
+        }
+
+        let c2 = unsafe { *char_ptr.add(1) };
+        let inv_mask2 = if unsafe { *inv_ptr.add(1) } { 0xff } else { 0 };
+
+        let mut data_ptr;
+        if let Some(emoji_index) = self
+          .props
+          .emoji_version
+          .code_to_index((c as u16) << 8 | c2 as u16)
+        {
+          data_ptr = unsafe {
+            EMOJI_16_DATA.as_ptr().add(emoji_index * 2 * CHAR_HEIGHT)
+          };
+        } else if (161..248).contains(&c) && (161..255).contains(&c2) {
+          let mut sec = c as usize - 161;
+          if sec > 8 {
+            sec -= 6;
+          }
+          let gb_offset = (sec * 94 + (c2 as usize - 161)) * 2 * CHAR_HEIGHT;
+          // NOTE shouldn't happen
+          // if gb_offset + 2 * CHAR_HEIGHT > GB2312_16_DATA.len() {
+          //   unreachable!();
+          // }
+
+          data_ptr = unsafe { GB2312_16_DATA.as_ptr().add(gb_offset) };
+        } else if let Some(&offset) =
+          self.props.extra_symbols.get((c as u64) << 8 | c2 as u64)
+        {
+          data_ptr =
+            unsafe { self.props.extra_symbol_data.as_ptr().add(offset) };
+        } else {
+          self.paint_hex_code(row, col, c);
+          self.paint_hex_code(row, col + 1, c2);
+          char_ptr = unsafe { char_ptr.add(2) };
+          inv_ptr = unsafe { inv_ptr.add(2) };
+          col += 2;
+          continue;
+        }
+
+        let mut g = unsafe { graph.add(col) };
+        if col == TEXT_COLUMNS - 1 {
+          // 汉字位于行尾时分成两半显示...
+          for _ in 0..CHAR_HEIGHT {
+            unsafe {
+              *g = *data_ptr ^ inv_mask;
+              *g.add(screen::WIDTH_IN_BYTE * (CHAR_HEIGHT - 1) + 1) =
+                *data_ptr.add(1) ^ inv_mask2;
+              g = g.add(screen::WIDTH_IN_BYTE);
+              data_ptr = data_ptr.add(2);
+            }
+          }
+        } else {
+          for _ in 0..CHAR_HEIGHT {
+            unsafe {
+              *g = *data_ptr ^ inv_mask;
+              *g.add(1) = *data_ptr.add(1) ^ inv_mask2;
+              g = g.add(screen::WIDTH_IN_BYTE);
+              data_ptr = data_ptr.add(2);
+            }
+          }
+        }
+
+        char_ptr = unsafe { char_ptr.add(2) };
+        inv_ptr = unsafe { inv_ptr.add(2) };
+        col += 2;
+      }
+      row += 1;
+      graph = unsafe { graph.add(screen::WIDTH_IN_BYTE * CHAR_HEIGHT) };
+    }
+  }
+
+  fn paint_small_text(&mut self) {
+    let w = SMALL_CHAR_WIDTH;
+    let h = SMALL_CHAR_HEIGHT;
+    for row in 0..SMALL_TEXT_ROWS {
+      let mut col = 0;
+      while col < SMALL_TEXT_COLUMNS {
+        let offset = row * SMALL_TEXT_COLUMNS + col;
+        let c = self.text()[offset];
+        let inv_mask = if self.inverse_text[offset] { 0xffff } else { 0 };
+        let (x, y) = (col * w, row * h);
+        if c == 0 {
+          col += 1;
+          continue;
+        }
+
+        if c < 128 {
+          for i in 0..h {
+            let bits = (ASCII_12_DATA[c as usize * h + i] as u16) << 8;
+            self.set_pixels(x, y + i, bits ^ inv_mask, w);
+          }
+          col += 1;
+          continue;
+        }
+
+        if row == SMALL_TEXT_ROWS - 1 && col == SMALL_TEXT_COLUMNS - 1 {
+          self.paint_hex_code(row, col, c);
+          col += 1;
+          continue;
+        }
+
+        let c2 = self.text()[offset + 1];
+        let inv_mask2 = if self.inverse_text[offset + 1] {
+          0xffff
+        } else {
+          0
+        };
+        let Some(glyph) = self.small_glyph(c, c2) else {
+          self.paint_hex_code(row, col, c);
+          self.paint_hex_code(row, col + 1, c2);
+          col += 2;
+          continue;
+        };
+
+        // 汉字位于行尾时分成两半显示，右半边显示在下一行的行首
+        let (x2, y2) = if col == SMALL_TEXT_COLUMNS - 1 {
+          (0, y + h)
+        } else {
+          (x + w, y)
+        };
+        for (i, bits) in glyph.into_iter().enumerate() {
+          self.set_pixels(x, y + i, bits ^ inv_mask, w);
+          self.set_pixels(x2, y2 + i, (bits << w) ^ inv_mask2, w);
         }
+        col += 2;
       }
     }
   }
 
-  unsafe fn nibble_to_ascii8_ptr(n: u8) -> *const u8 {
-    if n < 10 {
-      ASCII_8_DATA.as_ptr().add((48 + n as usize) << 3)
+  /// Returns the 12x12 glyph of the full-width character `c1 c2`, or None if
+  /// the character has no glyph. Each row is in the most significant 12 bits.
+  fn small_glyph(&self, c1: u8, c2: u8) -> Option<[u16; SMALL_CHAR_HEIGHT]> {
+    if let Some(emoji_index) = self
+      .props
+      .emoji_version
+      .code_to_index((c1 as u16) << 8 | c2 as u16)
+    {
+      let offset = emoji_index * 2 * CHAR_HEIGHT;
+      Some(shrink_glyph(
+        &EMOJI_16_DATA[offset..offset + 2 * CHAR_HEIGHT],
+      ))
+    } else if (161..248).contains(&c1) && (161..255).contains(&c2) {
+      let mut sec = c1 as usize - 161;
+      if sec > 8 {
+        sec -= 6;
+      }
+      let glyph_bytes = SMALL_CHAR_HEIGHT * 12 / 8;
+      let offset = (sec * 94 + (c2 as usize - 161)) * glyph_bytes;
+      let data = &GB2312_12_DATA[offset..offset + glyph_bytes];
+      let mut glyph = [0; SMALL_CHAR_HEIGHT];
+      for (i, row) in glyph.iter_mut().enumerate() {
+        let bit = i * 12;
+        *row = u16::from_be_bytes([data[bit >> 3], data[(bit >> 3) + 1]])
+          << (bit & 7)
+          & 0xfff0;
+      }
+      Some(glyph)
     } else {
-      ASCII_8_DATA.as_ptr().add((65 + n as usize - 10) << 3)
+      let &offset =
+        self.props.extra_symbols.get((c1 as u64) << 8 | c2 as u64)?;
+      let data = &self.props.extra_symbol_data[offset..];
+      Some(shrink_glyph(&data[..2 * CHAR_HEIGHT]))
     }
   }
 
@@ -248,26 +942,16 @@ impl DefaultDevice {
     right: usize,
     bottom: usize,
   ) {
-    if let Some(dirty) = self.graphics_dirty.as_mut() {
-      if left < dirty.left {
-        dirty.left = left;
-      }
-      if top < dirty.top {
-        dirty.top = top;
-      }
-      if right > dirty.right {
-        dirty.right = right;
-      }
-      if bottom > dirty.bottom {
-        dirty.bottom = bottom;
-      }
+    let rect = Rect {
+      left,
+      top,
+      right,
+      bottom,
+    };
+    if self.front_buffer.is_some() {
+      merge_dirty_area(&mut self.back_dirty, rect);
     } else {
-      self.graphics_dirty = Some(Rect {
-        left,
-        top,
-        right,
-        bottom,
-      });
+      merge_dirty_area(&mut self.graphics_dirty, rect);
     }
   }
 
@@ -448,198 +1132,109 @@ impl Device for DefaultDevice {
     self.column = column;
   }
 
-  fn print(&mut self, str: &[u8]) {
-    let inversed = self.print_mode != PrintMode::Normal;
-    let text_buffer = unsafe {
-      self
-        .memory
-        .as_mut_ptr()
-        .add(self.props.text_buffer_base_addr as usize)
-    };
-    let inv_buffer = self.inverse_text.as_mut_ptr();
-    let mut i = 0;
-    while i < str.len() {
-      let c = str[i];
-      if c >= 128 && self.column as usize == TEXT_COLUMNS - 1 {
-        let i = self.row as usize * TEXT_COLUMNS + self.column as usize;
-        unsafe {
-          *text_buffer.add(i) = b' ';
-          *inv_buffer.add(i) = inversed;
-        }
-        self.newline();
-      }
-      unsafe {
-        let offset = self.row as usize * TEXT_COLUMNS + self.column as usize;
-        *text_buffer.add(offset) = c;
-        *inv_buffer.add(offset) = inversed;
-        if c >= 128 && i < str.len() - 1 {
-          let offset = offset + 1;
-          *text_buffer.add(offset) = str[i + 1];
-          *inv_buffer.add(offset) = inversed;
-          self.column += 2;
-          i += 2;
-        } else {
-          self.column += 1;
-          i += 1;
-        }
-      }
-      if self.column as usize == TEXT_COLUMNS {
-        self.newline();
-      }
-    }
-
-    let mut i = self.row as usize * TEXT_COLUMNS + self.column as usize;
-    unsafe {
-      while i < 100 {
-        *text_buffer.add(i) = 0;
-        i += 1;
-        if *text_buffer.add(i) == 0 {
-          break;
-        }
-      }
-    }
+  fn text_size(&self) -> (u8, u8) {
+    (self.text_rows() as u8, self.text_columns() as u8)
   }
 
-  fn newline(&mut self) {
-    if self.column == 0 {
-      return;
-    }
-    if self.row as usize == TEXT_ROWS - 1 {
-      self.scroll_text();
-    } else {
-      self.row += 1;
-    }
-    self.column = 0;
-  }
-
-  fn flush(&mut self) {
-    if self.screen_mode == ScreenMode::Text {
-      let graph_addr = self.props.graphics_base_addr as usize;
-      self.memory[graph_addr..graph_addr + screen::BYTES].fill(0);
+  fn print(&mut self, str: &[u8]) {
+    if let Some(recording) = &mut self.recording {
+      recording.output.push_str(
+        &ByteString::from(str).to_string_lossy(self.props.emoji_version),
+      );
     }
-
-    let mut char_ptr = unsafe {
-      self
-        .memory
-        .as_ptr()
-        .add(self.props.text_buffer_base_addr as usize)
-    };
-    let mut inv_ptr = self.inverse_text.as_ptr();
-    let mut graph = unsafe {
-      self
-        .memory
-        .as_mut_ptr()
-        .add(self.props.graphics_base_addr as usize)
-    };
-    let mut row = 0;
-    while row < TEXT_ROWS {
-      let mut col = 0;
-      while col < TEXT_COLUMNS {
-        let c = unsafe { *char_ptr };
-        let inv_mask = if unsafe { *inv_ptr } { 0xff } else { 0 };
-        if c == 0 {
-          char_ptr = unsafe { char_ptr.add(1) };
-          inv_ptr = unsafe { inv_ptr.add(1) };
-          col += 1;
-          continue;
-        }
-
-        if c < 128 {
-          let mut g = unsafe { graph.add(col) };
-          let mut ascii_ptr =
-            unsafe { ASCII_16_DATA.as_ptr().add(c as usize * CHAR_HEIGHT) };
-          for _ in 0..CHAR_HEIGHT {
-            unsafe {
-              *g = *ascii_ptr ^ inv_mask;
-              g = g.add(screen::WIDTH_IN_BYTE);
-              ascii_ptr = ascii_ptr.add(1);
-            }
-          }
-          char_ptr = unsafe { char_ptr.add(1) };
-          inv_ptr = unsafe { inv_ptr.add(1) };
-          col += 1;
-          continue;
-        }
-
-        if row == TEXT_ROWS - 1 && col == TEXT_COLUMNS - 1 {
-          self.paint_hex_code(row, col, c);
-          char_ptr = unsafe { char_ptr.add(1) };
-          inv_ptr = unsafe { inv_ptr.add(1) };
-          col += 1;
-          continue;
-        }
-
-        let c2 = unsafe { *char_ptr.add(1) };
-        let inv_mask2 = if unsafe { *inv_ptr.add(1) } { 0xff } else { 0 };
-
-        let mut data_ptr;
-        if let Some(emoji_index) = self
-          .props
-          .emoji_version
-          .code_to_index((c as u16) << 8 | c2 as u16)
-        {
-          data_ptr = unsafe {
-            EMOJI_16_DATA.as_ptr().add(emoji_index * 2 * CHAR_HEIGHT)
-          };
-        } else if (161..248).contains(&c) && (161..255).contains(&c2) {
-          let mut sec = c as usize - 161;
-          if sec > 8 {
-            sec -= 6;
-          }
-          let gb_offset = (sec * 94 + (c2 as usize - 161)) * 2 * CHAR_HEIGHT;
-          // NOTE shouldn't happen
-          // if gb_offset + 2 * CHAR_HEIGHT > GB2312_16_DATA.len() {
-          //   unreachable!();
-          // }
-
-          data_ptr = unsafe { GB2312_16_DATA.as_ptr().add(gb_offset) };
-        } else if let Some(&offset) =
-          self.props.extra_symbols.get((c as u64) << 8 | c2 as u64)
+    if let Some(transliterator) = &mut self.transliterator {
+      if !str.is_empty() {
+        let text =
+          ByteString::from(str).to_string_lossy(self.props.emoji_version);
+        if let Some(text) =
+          transliterator.transliterate(&text, self.row, self.column)
         {
-          data_ptr =
-            unsafe { self.props.extra_symbol_data.as_ptr().add(offset) };
-        } else {
-          self.paint_hex_code(row, col, c);
-          self.paint_hex_code(row, col + 1, c2);
-          char_ptr = unsafe { char_ptr.add(2) };
-          inv_ptr = unsafe { inv_ptr.add(2) };
-          col += 2;
-          continue;
+          self.printed_text.push(PrintedText {
+            text,
+            row: self.row,
+            column: self.column,
+          });
         }
-
-        let mut g = unsafe { graph.add(col) };
-        if col == TEXT_COLUMNS - 1 {
-          // 汉字位于行尾时分成两半显示...
-          for _ in 0..CHAR_HEIGHT {
-            unsafe {
-              *g = *data_ptr ^ inv_mask;
-              *g.add(screen::WIDTH_IN_BYTE * (CHAR_HEIGHT - 1) + 1) =
-                *data_ptr.add(1) ^ inv_mask2;
-              g = g.add(screen::WIDTH_IN_BYTE);
-              data_ptr = data_ptr.add(2);
-            }
-          }
+      }
+    }
+    let inversed = self.print_mode != PrintMode::Normal;
+    let columns = self.text_columns();
+    let text_bytes = self.text_bytes();
+    let text_buffer = self.text_mut().as_mut_ptr();
+    let inv_buffer = self.inverse_text.as_mut_ptr();
+    let mut i = 0;
+    while i < str.len() {
+      let c = str[i];
+      if c >= 128 && self.column as usize == columns - 1 {
+        let i = self.row as usize * columns + self.column as usize;
+        unsafe {
+          *text_buffer.add(i) = b' ';
+          *inv_buffer.add(i) = inversed;
+        }
+        self.newline();
+      }
+      unsafe {
+        let offset = self.row as usize * columns + self.column as usize;
+        *text_buffer.add(offset) = c;
+        *inv_buffer.add(offset) = inversed;
+        if c >= 128 && i < str.len() - 1 {
+          let offset = offset + 1;
+          *text_buffer.add(offset) = str[i + 1];
+          *inv_buffer.add(offset) = inversed;
+          self.column += 2;
+          i += 2;
         } else {
-          for _ in 0..CHAR_HEIGHT {
-            unsafe {
-              *g = *data_ptr ^ inv_mask;
-              *g.add(1) = *data_ptr.add(1) ^ inv_mask2;
-              g = g.add(screen::WIDTH_IN_BYTE);
-              data_ptr = data_ptr.add(2);
-            }
-          }
+          self.column += 1;
+          i += 1;
         }
+      }
+      if self.column as usize == columns {
+        self.newline();
+      }
+    }
 
-        char_ptr = unsafe { char_ptr.add(2) };
-        inv_ptr = unsafe { inv_ptr.add(2) };
-        col += 2;
+    let mut i = self.row as usize * columns + self.column as usize;
+    unsafe {
+      while i < text_bytes {
+        *text_buffer.add(i) = 0;
+        i += 1;
+        if i < text_bytes && *text_buffer.add(i) == 0 {
+          break;
+        }
       }
-      row += 1;
-      graph = unsafe { graph.add(screen::WIDTH_IN_BYTE * CHAR_HEIGHT) };
+    }
+  }
+
+  fn newline(&mut self) {
+    if let Some(recording) = &mut self.recording {
+      recording.output.push('\n');
+    }
+    self.present_at(PresentPolicy::Newline);
+    if self.column == 0 {
+      return;
+    }
+    if self.row as usize == self.text_rows() - 1 {
+      self.scroll_text();
+    } else {
+      self.row += 1;
+    }
+    self.column = 0;
+  }
+
+  fn flush(&mut self) {
+    if self.screen_mode == ScreenMode::Text {
+      let graph_addr = self.props.graphics_base_addr as usize;
+      self.memory[graph_addr..graph_addr + screen::BYTES].fill(0);
+    }
+
+    match self.font {
+      TextFont::Normal => self.paint_text(),
+      TextFont::Small => self.paint_small_text(),
     }
 
     // TODO finer grained dirty area
     self.update_dirty_area(0, 0, screen::WIDTH, screen::HEIGHT);
+    self.present_at(PresentPolicy::Flush);
   }
 
   fn check_point(&self, (x, y): (i32, i32)) -> bool {
@@ -962,7 +1557,7 @@ impl Device for DefaultDevice {
 
   fn read_byte(&self, addr: u16) -> u8 {
     if let Some(prop) = self.props.addrs.get(addr as _) {
-      let now = Local::now();
+      let now = self.clock.now();
       match prop {
         AddrProp::Year => (now.year() - 1881) as _,
         AddrProp::Month => now.month0() as _,
@@ -1002,6 +1597,22 @@ impl Device for DefaultDevice {
     }
   }
 
+  fn set_context(&mut self, location: &Location) {
+    self.present_at(PresentPolicy::Statement);
+    if let Some(recording) = &mut self.recording {
+      recording.executed_lines.insert(location.line);
+    }
+    self.context = Some(location.clone());
+  }
+
+  fn is_screen_addr(&self, addr: u16) -> bool {
+    let g = self.props.graphics_base_addr as usize;
+    let t = self.props.text_buffer_base_addr as usize;
+    let addr = addr as usize;
+    (g..g + screen::BYTES).contains(&addr)
+      || (t..t + self.text_bytes()).contains(&addr)
+  }
+
   fn user_quit(&self) -> bool {
     let esc = self.memory[self.props.key_buffer_addr as usize]
       == 128 + KeyCode::Esc as u8;
@@ -1021,20 +1632,29 @@ impl Device for DefaultDevice {
     write: bool,
     truncate: bool,
   ) -> io::Result<()> {
-    let mut options = OpenOptions::new();
-    options
-      .read(true)
-      .write(write)
-      .truncate(truncate)
-      .create(write);
+    if let Some(s) = &mut self.secondary_storage {
+      if name.len() >= s.prefix.len()
+        && name[..s.prefix.len()].eq_ignore_ascii_case(&s.prefix)
+      {
+        let name = ByteString::from(&name[s.prefix.len()..])
+          .to_string_lossy(self.props.emoji_version);
+        let f = s.storage.open(&name, write, truncate)?;
+        return file.open(f);
+      }
+    }
     let name = ByteString::from(name).to_string_lossy(self.props.emoji_version);
-    let f = options.open(self.data_dir.join(name))?;
-    file.open(f)
+    let path = self.resolve_file_path(&name, write, truncate)?;
+    let f = open_fs_file(&path, write, truncate)?;
+    file.open(Box::new(f))
   }
 
   fn cls(&mut self) {
-    let text_buffer_addr = self.props.text_buffer_base_addr as usize;
-    self.memory[text_buffer_addr..text_buffer_addr + TEXT_BYTES].fill(0);
+    if let Some(mut recording) = self.recording.take() {
+      let line = self.context.as_ref().map(|loc| loc.line);
+      recording.screenshot(line, self.graphic_memory());
+      self.recording = Some(recording);
+    }
+    self.text_mut().fill(0);
     let graph_addr = self.props.graphics_base_addr as usize;
     self.memory[graph_addr..graph_addr + screen::BYTES].fill(0);
     self.inverse_text.fill(false);
@@ -1105,22 +1725,50 @@ impl Device for DefaultDevice {
   }
 
   fn set_print_mode(&mut self, mode: PrintMode) {
-    self.print_mode = match (self.print_mode, mode) {
-      (PrintMode::Inverse, PrintMode::Flash) => PrintMode::Normal,
-      _ => mode,
+    let font = match mode {
+      PrintMode::SmallFont => TextFont::Small,
+      PrintMode::NormalFont => TextFont::Normal,
+      _ => {
+        self.print_mode = match (self.print_mode, mode) {
+          (PrintMode::Inverse, PrintMode::Flash) => PrintMode::Normal,
+          _ => mode,
+        };
+        return;
+      }
     };
+    if self.props.small_font {
+      self.set_text_font(font);
+    }
   }
 
   fn sleep_unit(&self) -> std::time::Duration {
     self.props.sleep_unit
   }
 
+  fn sleep(&mut self, duration: std::time::Duration) -> std::time::Duration {
+    self.clock.sleep(duration)
+  }
+
   fn beep(&mut self) {
     // do nothing
   }
 
-  fn play_notes(&mut self, _notes: &[u8]) {
-    // do nothing
+  fn play_notes(&mut self, notes: &[u8]) {
+    if let Ok(notes) = notes::parse_notes(notes) {
+      for note in notes {
+        self.push_tone(Tone {
+          frequency: note.frequency.unwrap_or(0),
+          duration: note.duration,
+        });
+      }
+    }
+  }
+
+  fn sound(&mut self, frequency: u16, duration: std::time::Duration) {
+    self.push_tone(Tone {
+      frequency,
+      duration,
+    });
   }
 
   fn clear_cursor(&mut self) {
@@ -1136,6 +1784,22 @@ impl Device for DefaultDevice {
   fn eof_behavior(&self) -> EofBehavior {
     self.props.eof_behavior
   }
+
+  fn int_overflow(&self) -> IntOverflow {
+    self.props.int_overflow
+  }
+
+  fn invalid_notes(&self) -> InvalidNotes {
+    self.props.invalid_notes
+  }
+
+  fn selector_rounding(&self) -> SelectorRounding {
+    self.props.selector_rounding
+  }
+
+  fn max_string_len(&self) -> usize {
+    self.props.max_string_len
+  }
 }
 
 impl Interface6502 for DefaultDevice {
@@ -1149,7 +1813,7 @@ impl Interface6502 for DefaultDevice {
 }
 
 impl DefaultFileHandle {
-  fn open(&mut self, mut file: FsFile) -> io::Result<()> {
+  fn open(&mut self, mut file: Box<dyn StorageFile>) -> io::Result<()> {
     let mut data = vec![];
     file.read_to_end(&mut data)?;
     if matches!(&self.state, FileState::Open { .. }) {
@@ -1166,6 +1830,52 @@ impl DefaultFileHandle {
   }
 }
 
+impl DirStorage {
+  pub fn new<P>(dir: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    Self { dir: dir.into() }
+  }
+}
+
+impl Storage for DirStorage {
+  fn open(
+    &mut self,
+    name: &str,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<Box<dyn StorageFile>> {
+    let file = open_fs_file(&self.dir.join(name), write, truncate)?;
+    Ok(Box::new(file))
+  }
+}
+
+fn merge_dirty_area(dirty: &mut Option<Rect>, rect: Rect) {
+  if let Some(dirty) = dirty.as_mut() {
+    dirty.left = dirty.left.min(rect.left);
+    dirty.top = dirty.top.min(rect.top);
+    dirty.right = dirty.right.max(rect.right);
+    dirty.bottom = dirty.bottom.max(rect.bottom);
+  } else {
+    *dirty = Some(rect);
+  }
+}
+
+fn open_fs_file(
+  path: &Path,
+  write: bool,
+  truncate: bool,
+) -> io::Result<FsFile> {
+  let mut options = OpenOptions::new();
+  options
+    .read(true)
+    .write(write)
+    .truncate(truncate)
+    .create(write);
+  options.open(path)
+}
+
 impl Default for DefaultFileHandle {
   fn default() -> Self {
     Self {
@@ -1268,6 +1978,22 @@ impl FileHandle for DefaultFileHandle {
   }
 }
 
+/// Shrinks a 16x16 glyph to 12x12 by dropping every fourth row and column.
+/// Each row of the result is in the most significant 12 bits.
+fn shrink_glyph(data: &[u8]) -> [u16; SMALL_CHAR_HEIGHT] {
+  let mut glyph = [0; SMALL_CHAR_HEIGHT];
+  for (i, row) in glyph.iter_mut().enumerate() {
+    let src = i * 4 / 3;
+    let bits = u16::from_be_bytes([data[src * 2], data[src * 2 + 1]]);
+    for j in 0..12 {
+      if bits & (0x8000 >> (j * 4 / 3)) != 0 {
+        *row |= 0x8000 >> j;
+      }
+    }
+  }
+  glyph
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -1275,15 +2001,10 @@ mod tests {
   use crate::vm::ByteString;
   use insta::assert_snapshot;
   use pretty_assertions::assert_eq;
-  use std::sync::Once;
   use widestring::Utf16String;
 
-  static INIT: Once = Once::new();
-
   pub fn initialize() {
-    INIT.call_once(|| {
-      crate::machine::init_machines().unwrap();
-    });
+    crate::machine::init_machines_once().unwrap();
   }
 
   fn new_device() -> DefaultDevice {
@@ -1336,7 +2057,7 @@ mod tests {
   fn inverse_buffer(d: &DefaultDevice) -> String {
     let mut s = String::new();
     let mut i = 0;
-    for &b in &d.inverse_text {
+    for &b in &d.inverse_text[..d.text_buffer().len()] {
       if b {
         s.push('#');
       } else {
@@ -1357,6 +2078,200 @@ mod tests {
                     
 ";
 
+  #[test]
+  fn secondary_storage() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct MockStorage(Rc<RefCell<Vec<(String, bool)>>>);
+
+    impl Storage for MockStorage {
+      fn open(
+        &mut self,
+        name: &str,
+        write: bool,
+        _truncate: bool,
+      ) -> io::Result<Box<dyn StorageFile>> {
+        self.0.borrow_mut().push((name.to_owned(), write));
+        if name == "MEM.DAT" {
+          Ok(Box::new(io::Cursor::new(b"ABC".to_vec())))
+        } else {
+          Err(io::ErrorKind::NotFound.into())
+        }
+      }
+    }
+
+    let mut device = new_device();
+    let opened = Rc::new(RefCell::new(vec![]));
+    device.set_secondary_storage(b"B:", Box::new(MockStorage(opened.clone())));
+
+    let mut file = DefaultFileHandle::default();
+    assert!(device
+      .open_file(&mut file, b"b:FOO.DAT", true, true, true)
+      .is_err());
+    assert!(device
+      .open_file(&mut file, b"B:\xb0\xa1.DAT", true, false, false)
+      .is_err());
+    assert!(device
+      .open_file(&mut file, b"C:BAR.DAT", true, false, false)
+      .is_err());
+    assert_eq!(
+      &opened.borrow()[..],
+      &[("FOO.DAT".to_owned(), true), ("啊.DAT".to_owned(), false)]
+    );
+
+    device
+      .open_file(&mut file, b"B:MEM.DAT", true, false, false)
+      .unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(file.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"ABC");
+    file.close().unwrap();
+  }
+
+  #[test]
+  fn play_notes() {
+    let mut device = new_device();
+    device.play_notes(b"a8 r8");
+    device.play_notes(b"a8 x");
+    assert_eq!(
+      device.take_tones(),
+      vec![
+        Tone {
+          frequency: 440,
+          duration: std::time::Duration::from_millis(250),
+        },
+        Tone {
+          frequency: 0,
+          duration: std::time::Duration::from_millis(250),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn tone_queue_is_bounded() {
+    let mut device = new_device();
+    for frequency in 0..MAX_TONES as u16 + 10 {
+      device.sound(frequency, std::time::Duration::from_millis(1));
+    }
+    let tones = device.take_tones();
+    assert_eq!(tones.len(), MAX_TONES);
+    assert_eq!(tones[0].frequency, 10);
+    assert!(device.take_tones().is_empty());
+  }
+
+  #[test]
+  fn storage_namespace() {
+    let dir = std::env::temp_dir()
+      .join(format!("gvb_storage_namespace_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("A.DAT"), b"shared").unwrap();
+    fs::write(dir.join("B.DAT"), b"shared").unwrap();
+
+    let mut device = new_device();
+    device.data_dir = dir.clone();
+    device.set_storage_namespace(Some("ns".to_owned()));
+    let ns_dir = dir.join("ns");
+
+    // read from the data directory
+    let mut file = DefaultFileHandle::default();
+    device
+      .open_file(&mut file, b"A.DAT", true, false, false)
+      .unwrap();
+    assert_eq!(file.len().unwrap(), 6);
+    file.close().unwrap();
+    assert!(!ns_dir.exists());
+
+    // copied before being modified
+    device
+      .open_file(&mut file, b"A.DAT", true, true, false)
+      .unwrap();
+    file.close().unwrap();
+    assert_eq!(fs::read(ns_dir.join("A.DAT")).unwrap(), b"shared");
+
+    // truncated
+    device
+      .open_file(&mut file, b"B.DAT", true, true, true)
+      .unwrap();
+    file.close().unwrap();
+    assert_eq!(fs::read(ns_dir.join("B.DAT")).unwrap(), b"");
+    assert_eq!(fs::read(dir.join("B.DAT")).unwrap(), b"shared");
+
+    // read from the subdirectory
+    device
+      .open_file(&mut file, b"B.DAT", true, false, false)
+      .unwrap();
+    assert_eq!(file.len().unwrap(), 0);
+    file.close().unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn manual_clock() {
+    use crate::device::clock::ManualClock;
+    use std::time::Duration;
+
+    let mut device = new_device();
+    let clock = ManualClock::new(
+      NaiveDate::from_ymd_opt(2020, 1, 2)
+        .unwrap()
+        .and_hms_opt(3, 4, 5)
+        .unwrap(),
+    );
+    device.set_clock(Box::new(clock.clone()));
+
+    assert_eq!((device.read_byte(1019), device.read_byte(1016)), (139, 3));
+    assert_eq!((device.read_byte(1017), device.read_byte(1018)), (4, 10));
+
+    assert_eq!(device.sleep(Duration::from_secs(3)), Duration::ZERO);
+    assert_eq!((device.read_byte(1017), device.read_byte(1018)), (4, 16));
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!((device.read_byte(1017), device.read_byte(1018)), (5, 16));
+  }
+
+  #[test]
+  fn transliterator() {
+    use crate::device::transliterate::PrintedText;
+
+    let gb2312 = |s: &str| {
+      ByteString::from_utf16str(Utf16String::from(s), EmojiVersion::V2, false).0
+    };
+    let mut device = new_device();
+    device.print(b"abc");
+    device.set_transliterator(Some(Box::new(|text: &str, _, _| {
+      if text.starts_with('#') {
+        None
+      } else {
+        Some(text.replace("中文", "zhong wen"))
+      }
+    })));
+    device.print(&gb2312("中文"));
+    device.newline();
+    device.print(b"#skip");
+    device.set_column(17);
+    device.print(&gb2312("x中"));
+
+    assert_eq!(
+      device.take_printed_text(),
+      vec![
+        PrintedText {
+          text: "zhong wen".to_owned(),
+          row: 0,
+          column: 3,
+        },
+        PrintedText {
+          text: "x中".to_owned(),
+          row: 1,
+          column: 17,
+        },
+      ]
+    );
+    assert_eq!(device.take_printed_text(), vec![]);
+  }
+
   #[test]
   fn newline_at_first_column() {
     let mut device = new_device();
@@ -1415,6 +2330,127 @@ mod tests {
     assert_eq!(device.text_buffer(), str.as_slice());
   }
 
+  #[test]
+  fn small_font() {
+    let mut device = new_device();
+
+    device.print(b"AB");
+    device.set_print_mode(PrintMode::SmallFont);
+    assert_eq!(device.text_font(), TextFont::Normal);
+    assert_eq!(&device.text_buffer()[..3], b"AB\0");
+
+    device.props.small_font = true;
+    device.set_print_mode(PrintMode::SmallFont);
+    assert_eq!(device.text_font(), TextFont::Small);
+    assert_eq!(
+      device.text_grid(),
+      TextGrid {
+        columns: 26,
+        rows: 6,
+        char_width: 6,
+        char_height: 12,
+      }
+    );
+    assert_eq!((device.row, device.column), (0, 0));
+    assert_eq!(device.text_buffer().len(), 26 * 6);
+    assert_eq!(device.text_lines(), vec![""; 6]);
+
+    let mut str = string("CD哈0\u{e050}");
+    str.drop_0x1f();
+    device.print(&str[..2]);
+    device.set_print_mode(PrintMode::Inverse);
+    device.print(&str[2..]);
+    device.set_print_mode(PrintMode::Normal);
+    let base_addr = device.props.text_buffer_base_addr as usize;
+    assert_eq!(&device.memory[base_addr..base_addr + 4], b"CD\xb9\xfe");
+    device.set_column(25);
+    device.print(b"X");
+    assert_eq!(device.read_byte(base_addr as u16 + 25), b'X');
+    for i in 0..5 {
+      device.newline();
+      device.print(format!("{}", i).as_bytes());
+    }
+    assert_eq!(device.row, 5);
+    let lines = device.text_lines();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], format!("CD哈0\u{e050}{}X", " ".repeat(18)));
+    assert_eq!(lines[5], "4");
+    device.flush();
+    assert_snapshot!(device_screen_braille(&device));
+
+    device.newline();
+    device.print(b"5");
+    assert_eq!(device.row, 5);
+    let lines = device.text_lines();
+    assert_eq!(lines[0], "0");
+    assert_eq!(lines[5], "5");
+
+    device.set_print_mode(PrintMode::SmallFont);
+    assert_eq!(device.text_lines()[5], "5");
+
+    device.set_print_mode(PrintMode::NormalFont);
+    assert_eq!(device.text_font(), TextFont::Normal);
+    assert_eq!(device.text_grid().rows, 5);
+    assert_eq!((device.row, device.column), (0, 0));
+    assert_eq!(device.text_lines(), vec![""; 5]);
+  }
+
+  #[test]
+  fn locate_after_switching_font() {
+    use crate::{Document, ExecInput, ExecResult};
+
+    let mut device = new_device();
+    let mut doc = Document::from_text(Utf16String::from(
+      "10 locate 6,3:print \"A\";\n20 locate 8",
+    ));
+    device.props.small_font = true;
+    device.set_print_mode(PrintMode::SmallFont);
+    let line20 = doc.text().to_string().find("20 ").unwrap() + 3;
+    let mut vm = doc.create_vm(&mut device).unwrap();
+    vm.start();
+    assert!(vm.run_to(line20));
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Breakpoint { .. }
+    ));
+    assert_eq!(vm.device().text_size(), (6, 26));
+    assert_eq!(vm.device().text_lines()[5], "  A");
+    vm.device_mut().set_print_mode(PrintMode::NormalFont);
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Error { message, .. }
+        if message == "参数超出范围 1~5。运算结果为：8"
+    ));
+    drop(vm);
+    assert_eq!(device.text_size(), (5, 20));
+    assert_eq!((device.row, device.column), (0, 0));
+  }
+
+  #[test]
+  fn text_size_in_small_font() {
+    use crate::{Document, ExecInput, ExecResult};
+
+    let mut device = new_device();
+    let mut doc = Document::from_text(Utf16String::from(
+      "10 locate 6,25:print \"A\";:locate 1,1:print tab(24);\"B\";:locate 1,27",
+    ));
+    device.props.small_font = true;
+    assert_eq!(device.text_size(), (5, 20));
+    device.set_print_mode(PrintMode::SmallFont);
+    assert_eq!(device.text_size(), (6, 26));
+    let mut vm = doc.create_vm(&mut device).unwrap();
+    vm.start();
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Error { message, .. }
+        if message == "参数超出范围 1~26。运算结果为：27"
+    ));
+    drop(vm);
+    let lines = device.text_lines();
+    assert_eq!(lines[0], format!("{}B", " ".repeat(23)));
+    assert_eq!(lines[5], format!("{}A", " ".repeat(24)));
+  }
+
   #[test]
   fn locate() {
     let mut device = new_device();
@@ -1986,4 +3022,130 @@ mod tests {
     assert_eq!(device.check_point((20, 10)), true);
     assert_eq!(device.check_point((180, 10)), false);
   }
+
+  #[test]
+  fn dump_and_load_memory() {
+    let mut device = new_device();
+
+    device.load_memory(0x2000, &[0xa9, 0x01, 0x60]);
+    assert_eq!(
+      device.dump_memory(0x1fff..0x2004),
+      vec![0, 0xa9, 1, 0x60, 0]
+    );
+    assert_eq!(device.read_byte(0x2001), 1);
+
+    device.load_memory(0xfffe, &[1, 2, 3]);
+    assert_eq!(device.dump_memory(0xfffd..0x10010), vec![0, 1, 2]);
+    assert_eq!(device.dump_memory(0x10000..0x10010), vec![]);
+
+    assert!(device.take_dirty_area().is_none());
+    let g = device.props.graphics_base_addr;
+    device.load_memory(g + 20 * 3 + 5, &[0xff; 21]);
+    let dirty = device.take_dirty_area().unwrap();
+    assert_eq!(
+      (dirty.left, dirty.top, dirty.right, dirty.bottom),
+      (0, 3, 160, 5)
+    );
+    assert_eq!(device.graphic_memory()[20 * 4 + 5], 0xff);
+  }
+
+  #[test]
+  fn memory_watch() {
+    let mut device = new_device();
+    let g = device.props.graphics_base_addr as usize;
+    let graphics = device.watch_memory(g..g + 1600);
+    let ram = device.watch_memory(0x2000..0x2100);
+
+    device.write_byte(0x2010, 1);
+    device.load_memory(0x2080, &[1, 2]);
+    device.draw_point((16, 1), DrawMode::Or);
+    device.draw_point((100, 1), DrawMode::Or);
+    assert_eq!(
+      device.memory_changes(ram),
+      Some(vec![0x2010..0x2011, 0x2080..0x2082])
+    );
+    assert_eq!(
+      device.memory_changes(graphics),
+      Some(vec![g + 22..g + 23, g + 32..g + 33])
+    );
+    assert_eq!(device.memory_changes(ram), Some(vec![]));
+
+    assert!(device.unwatch_memory(ram));
+    assert_eq!(device.memory_changes(ram), None);
+  }
+  #[test]
+  fn double_buffering() {
+    let mut device = new_device();
+    let loc = Location {
+      line: 0,
+      range: crate::ast::Range::new(0, 1),
+    };
+    device.set_screen_mode(ScreenMode::Graph);
+    device.draw_point((0, 0), DrawMode::Or);
+    device.set_present_policy(PresentPolicy::Flush);
+    assert_eq!(device.graphic_memory()[0], 0x80);
+
+    device.draw_point((8, 2), DrawMode::Or);
+    assert_eq!(device.graphic_memory()[41], 0);
+    assert!(device.check_point((8, 2)));
+    assert!(device.take_dirty_area().is_some());
+    assert!(device.take_dirty_area().is_none());
+
+    device.newline();
+    device.set_context(&loc);
+    assert_eq!(device.present_count(), 0);
+    assert!(device.take_dirty_area().is_none());
+
+    device.flush();
+    assert_eq!(device.present_count(), 1);
+    assert_eq!(device.graphic_memory()[41], 0x80);
+    assert_eq!(
+      device.take_dirty_area(),
+      Some(Rect {
+        left: 0,
+        top: 0,
+        right: 160,
+        bottom: 80
+      })
+    );
+
+    device.set_present_policy(PresentPolicy::Statement);
+    device.draw_point((16, 3), DrawMode::Or);
+    device.flush();
+    device.newline();
+    assert_eq!(device.graphic_memory()[62], 0);
+    device.set_context(&loc);
+    assert_eq!(device.present_count(), 2);
+    assert_eq!(device.graphic_memory()[62], 0x80);
+
+    device.set_present_policy(PresentPolicy::Manual);
+    device.draw_point((24, 4), DrawMode::Or);
+    device.set_context(&loc);
+    assert_eq!(device.graphic_memory()[83], 0);
+    device.present();
+    assert_eq!(device.present_count(), 3);
+    assert_eq!(device.graphic_memory()[83], 0x80);
+
+    device.set_present_policy(PresentPolicy::Newline);
+    device.draw_point((0, 7), DrawMode::Or);
+    device.newline();
+    assert_eq!(device.present_count(), 4);
+    assert_eq!(device.graphic_memory()[140], 0x80);
+
+    device.draw_point((32, 5), DrawMode::Or);
+    device.take_dirty_area();
+    device.set_present_policy(PresentPolicy::Immediate);
+    assert_eq!(device.graphic_memory()[104], 0x80);
+    assert_eq!(
+      device.take_dirty_area(),
+      Some(Rect {
+        left: 32,
+        top: 5,
+        right: 33,
+        bottom: 6
+      })
+    );
+    device.draw_point((40, 6), DrawMode::Or);
+    assert_eq!(device.graphic_memory()[125], 0x80);
+  }
 }
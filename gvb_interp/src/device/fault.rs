@@ -0,0 +1,499 @@
+//! A device wrapper which fails file operations on demand, for testing how
+//! the VM and hosts handle I/O errors.
+//!
+//! ```ignore
+//! let mut device = FaultDevice::new(doc.create_device(data_dir));
+//! device.inject(Fault::Write { nth: 2 }, io::ErrorKind::StorageFull);
+//! ```
+
+use std::cell::{RefCell, RefMut};
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{AsmExecState, Device, DrawMode, FileHandle};
+use crate::machine::{
+  EofBehavior, IntOverflow, InvalidNotes, SelectorRounding,
+};
+use crate::{Location, PrintMode, ScreenMode};
+
+/// A file operation to fail. Operations are counted from 1, across all files
+/// opened through the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+  /// Opening the file named `name`.
+  Open { name: Vec<u8> },
+  /// The `nth` write.
+  Write { nth: usize },
+  /// The `nth` read.
+  Read { nth: usize },
+  /// Seeking to a position greater than `pos`.
+  SeekPast { pos: u64 },
+  /// The `nth` close. The file is closed even though the error is returned,
+  /// as data may be lost when it is flushed.
+  Close { nth: usize },
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+  faults: Vec<(Fault, io::ErrorKind)>,
+  triggered: Vec<Fault>,
+  writes: usize,
+  reads: usize,
+  closes: usize,
+  open_files: usize,
+}
+
+impl FaultState {
+  fn check<F>(&mut self, matches: F) -> io::Result<()>
+  where
+    F: Fn(&Fault) -> bool,
+  {
+    if let Some((fault, kind)) = self.faults.iter().find(|(f, _)| matches(f)) {
+      self.triggered.push(fault.clone());
+      return Err(io::Error::new(*kind, "injected fault"));
+    }
+    Ok(())
+  }
+
+  fn write(&mut self) -> io::Result<()> {
+    self.writes += 1;
+    let n = self.writes;
+    self.check(|f| matches!(f, &Fault::Write { nth } if nth == n))
+  }
+
+  fn read(&mut self) -> io::Result<()> {
+    self.reads += 1;
+    let n = self.reads;
+    self.check(|f| matches!(f, &Fault::Read { nth } if nth == n))
+  }
+
+  fn close(&mut self) -> io::Result<()> {
+    self.closes += 1;
+    let n = self.closes;
+    self.check(|f| matches!(f, &Fault::Close { nth } if nth == n))
+  }
+}
+
+/// Wraps a device, and fails the file operations matching the injected
+/// faults. Other operations are passed to the inner device.
+pub struct FaultDevice<D> {
+  inner: D,
+  state: Rc<RefCell<FaultState>>,
+}
+
+impl<D> FaultDevice<D> {
+  pub fn new(inner: D) -> Self {
+    Self {
+      inner,
+      state: Rc::new(RefCell::new(FaultState::default())),
+    }
+  }
+
+  pub fn inner(&self) -> &D {
+    &self.inner
+  }
+
+  pub fn inner_mut(&mut self) -> &mut D {
+    &mut self.inner
+  }
+
+  /// Fails the operations matching `fault` with an error of `kind`. The
+  /// fault is triggered every time it matches, e.g. opening the same file
+  /// again fails again.
+  pub fn inject(&mut self, fault: Fault, kind: io::ErrorKind) {
+    self.state.borrow_mut().faults.push((fault, kind));
+  }
+
+  pub fn clear_faults(&mut self) {
+    self.state.borrow_mut().faults.clear();
+  }
+
+  /// Returns the faults triggered so far, in order.
+  pub fn triggered(&self) -> Vec<Fault> {
+    self.state.borrow().triggered.clone()
+  }
+
+  /// Number of files opened through the device and not closed yet.
+  pub fn open_files(&self) -> usize {
+    self.state.borrow().open_files
+  }
+}
+
+#[derive(Default)]
+pub struct FaultFile<F> {
+  inner: F,
+  /// Set when the file is opened.
+  state: Option<Rc<RefCell<FaultState>>>,
+}
+
+impl<F> FaultFile<F> {
+  pub fn inner(&self) -> &F {
+    &self.inner
+  }
+
+  fn state(&self) -> Option<RefMut<'_, FaultState>> {
+    self.state.as_ref().map(|state| state.borrow_mut())
+  }
+}
+
+impl<F: FileHandle> FileHandle for FaultFile<F> {
+  fn len(&self) -> io::Result<u64> {
+    self.inner.len()
+  }
+
+  fn seek(&mut self, pos: u64) -> io::Result<()> {
+    if let Some(mut state) = self.state() {
+      state.check(|f| matches!(f, &Fault::SeekPast { pos: p } if pos > p))?;
+    }
+    self.inner.seek(pos)
+  }
+
+  fn pos(&self) -> io::Result<u64> {
+    self.inner.pos()
+  }
+
+  fn write(&mut self, data: &[u8]) -> io::Result<()> {
+    if let Some(mut state) = self.state() {
+      state.write()?;
+    }
+    self.inner.write(data)
+  }
+
+  fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+    if let Some(mut state) = self.state() {
+      state.read()?;
+    }
+    self.inner.read(data)
+  }
+
+  fn close(&mut self) -> io::Result<()> {
+    let was_open = self.inner.is_open();
+    let result = self.inner.close();
+    let is_open = self.inner.is_open();
+    if let Some(mut state) = self.state() {
+      if was_open && !is_open {
+        state.open_files -= 1;
+      }
+      state.close()?;
+    }
+    result
+  }
+
+  fn is_open(&self) -> bool {
+    self.inner.is_open()
+  }
+}
+
+impl<D: Device> Device for FaultDevice<D> {
+  type File = FaultFile<D::File>;
+  type AsmState = D::AsmState;
+  type AsmError = D::AsmError;
+
+  fn get_row(&self) -> u8 {
+    self.inner.get_row()
+  }
+
+  fn get_column(&self) -> u8 {
+    self.inner.get_column()
+  }
+
+  fn set_row(&mut self, row: u8) {
+    self.inner.set_row(row)
+  }
+
+  fn set_column(&mut self, column: u8) {
+    self.inner.set_column(column)
+  }
+
+  fn text_size(&self) -> (u8, u8) {
+    self.inner.text_size()
+  }
+
+  fn print(&mut self, str: &[u8]) {
+    self.inner.print(str)
+  }
+
+  fn newline(&mut self) {
+    self.inner.newline()
+  }
+
+  fn flush(&mut self) {
+    self.inner.flush()
+  }
+
+  fn draw_point(&mut self, coord: (u8, u8), mode: DrawMode) {
+    self.inner.draw_point(coord, mode)
+  }
+
+  fn draw_line(&mut self, coord1: (u8, u8), coord2: (u8, u8), mode: DrawMode) {
+    self.inner.draw_line(coord1, coord2, mode)
+  }
+
+  fn draw_box(
+    &mut self,
+    coord1: (u8, u8),
+    coord2: (u8, u8),
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.inner.draw_box(coord1, coord2, fill, mode)
+  }
+
+  fn draw_circle(
+    &mut self,
+    coord: (u8, u8),
+    r: u8,
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.inner.draw_circle(coord, r, fill, mode)
+  }
+
+  fn draw_ellipse(
+    &mut self,
+    coord: (u8, u8),
+    radius: (u8, u8),
+    fill: bool,
+    mode: DrawMode,
+  ) {
+    self.inner.draw_ellipse(coord, radius, fill, mode)
+  }
+
+  fn check_point(&self, coord: (i32, i32)) -> bool {
+    self.inner.check_point(coord)
+  }
+
+  fn check_key(&self, key: u8) -> bool {
+    self.inner.check_key(key)
+  }
+
+  fn key(&mut self) -> Option<u8> {
+    self.inner.key()
+  }
+
+  fn read_byte(&self, addr: u16) -> u8 {
+    self.inner.read_byte(addr)
+  }
+
+  fn write_byte(&mut self, addr: u16, byte: u8) {
+    self.inner.write_byte(addr, byte)
+  }
+
+  fn is_screen_addr(&self, addr: u16) -> bool {
+    self.inner.is_screen_addr(addr)
+  }
+
+  fn user_quit(&self) -> bool {
+    self.inner.user_quit()
+  }
+
+  fn open_file(
+    &mut self,
+    file: &mut Self::File,
+    name: &[u8],
+    read: bool,
+    write: bool,
+    truncate: bool,
+  ) -> io::Result<()> {
+    self
+      .state
+      .borrow_mut()
+      .check(|f| matches!(f, Fault::Open { name: n } if n == name))?;
+    let was_open = file.inner.is_open();
+    self
+      .inner
+      .open_file(&mut file.inner, name, read, write, truncate)?;
+    if !was_open && file.inner.is_open() {
+      self.state.borrow_mut().open_files += 1;
+    }
+    file.state = Some(self.state.clone());
+    Ok(())
+  }
+
+  fn cls(&mut self) {
+    self.inner.cls()
+  }
+
+  fn exec_asm(
+    &mut self,
+    steps: &mut usize,
+    state: AsmExecState<Self::AsmState>,
+  ) -> Result<Option<Self::AsmState>, Self::AsmError> {
+    self.inner.exec_asm(steps, state)
+  }
+
+  fn set_screen_mode(&mut self, mode: ScreenMode) {
+    self.inner.set_screen_mode(mode)
+  }
+
+  fn set_print_mode(&mut self, mode: PrintMode) {
+    self.inner.set_print_mode(mode)
+  }
+
+  fn sleep_unit(&self) -> Duration {
+    self.inner.sleep_unit()
+  }
+
+  fn sleep(&mut self, duration: Duration) -> Duration {
+    self.inner.sleep(duration)
+  }
+
+  fn beep(&mut self) {
+    self.inner.beep()
+  }
+
+  fn play_notes(&mut self, notes: &[u8]) {
+    self.inner.play_notes(notes)
+  }
+
+  fn sound(&mut self, frequency: u16, duration: Duration) {
+    self.inner.sound(frequency, duration)
+  }
+
+  fn clear_cursor(&mut self) {
+    self.inner.clear_cursor()
+  }
+
+  fn eof_behavior(&self) -> EofBehavior {
+    self.inner.eof_behavior()
+  }
+
+  fn int_overflow(&self) -> IntOverflow {
+    self.inner.int_overflow()
+  }
+
+  fn invalid_notes(&self) -> InvalidNotes {
+    self.inner.invalid_notes()
+  }
+
+  fn selector_rounding(&self) -> SelectorRounding {
+    self.inner.selector_rounding()
+  }
+
+  fn max_string_len(&self) -> usize {
+    self.inner.max_string_len()
+  }
+
+  fn set_context(&mut self, location: &Location) {
+    self.inner.set_context(location)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::device::default::DefaultDevice;
+  use crate::{Document, ExecInput, ExecResult};
+  use pretty_assertions::assert_eq;
+  use std::fs;
+  use std::path::PathBuf;
+  use widestring::Utf16String;
+
+  /// Runs `text` with the faults in a fresh data directory containing
+  /// `files`, and returns the error message and the device after the VM is
+  /// stopped.
+  fn run(
+    name: &str,
+    text: &str,
+    files: &[(&str, &[u8])],
+    faults: Vec<(Fault, io::ErrorKind)>,
+  ) -> (Option<String>, FaultDevice<DefaultDevice>, PathBuf) {
+    crate::machine::init_machines_once().unwrap();
+    let dir = std::env::temp_dir()
+      .join(format!("gvb_fault_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, data) in files {
+      fs::write(dir.join(name), data).unwrap();
+    }
+
+    let mut doc = Document::from_text(Utf16String::from(text));
+    let mut device = FaultDevice::new(doc.create_device(&dir));
+    for (fault, kind) in faults {
+      device.inject(fault, kind);
+    }
+    let mut vm = doc.create_vm(&mut device).unwrap();
+    vm.start();
+    let message = match vm.exec(ExecInput::None, usize::MAX) {
+      ExecResult::End => None,
+      ExecResult::Error { message, .. } => Some(message),
+      result => panic!("unexpected result: {result:?}"),
+    };
+    vm.stop().unwrap();
+    drop(vm);
+    (message, device, dir)
+  }
+
+  #[test]
+  fn open() {
+    let (message, device, dir) = run(
+      "open",
+      r#"10 open "A.DAT" for output as 1"#,
+      &[],
+      vec![(
+        Fault::Open {
+          name: b"A.DAT".to_vec(),
+        },
+        io::ErrorKind::PermissionDenied,
+      )],
+    );
+    assert_eq!(message.as_deref(), Some("打开文件时发生错误：没有权限"));
+    assert_eq!(device.open_files(), 0);
+    assert!(!dir.join("A.DAT").exists());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn nth_write() {
+    let (message, device, dir) = run(
+      "write",
+      r#"10 open "A.DAT" for output as 1:write #1,1:write #1,2"#,
+      &[],
+      vec![(Fault::Write { nth: 3 }, io::ErrorKind::StorageFull)],
+    );
+    assert_eq!(message.as_deref(), Some("写入文件时发生错误：存储空间已满"));
+    assert_eq!(device.triggered(), vec![Fault::Write { nth: 3 }]);
+    assert_eq!(device.open_files(), 0);
+    assert_eq!(fs::read(dir.join("A.DAT")).unwrap(), b"1\xff");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn read_and_close() {
+    let (message, device, dir) = run(
+      "read",
+      r#"10 open "A.DAT" for input as 1:input #1,a"#,
+      &[("A.DAT", b"12\xff")],
+      vec![(Fault::Read { nth: 1 }, io::ErrorKind::NotFound)],
+    );
+    assert_eq!(message.as_deref(), Some("读取文件时发生错误：文件不存在"));
+    assert_eq!(device.open_files(), 0);
+    fs::remove_dir_all(dir).unwrap();
+
+    let (message, device, dir) = run(
+      "close",
+      r#"10 open "A.DAT" for output as 1:close 1:open "A.DAT" input as 1"#,
+      &[],
+      vec![(Fault::Close { nth: 1 }, io::ErrorKind::PermissionDenied)],
+    );
+    assert_eq!(message.as_deref(), Some("关闭文件时发生错误：没有权限"));
+    assert_eq!(device.open_files(), 0);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn append_seek_closes_file() {
+    let (message, device, dir) = run(
+      "seek",
+      r#"10 open "A.DAT" for append as 1"#,
+      &[("A.DAT", b"1234")],
+      vec![(Fault::SeekPast { pos: 0 }, io::ErrorKind::PermissionDenied)],
+    );
+    assert_eq!(message.as_deref(), Some("设置文件指针时发生错误：没有权限"));
+    assert_eq!(device.triggered(), vec![Fault::SeekPast { pos: 0 }]);
+    assert_eq!(device.open_files(), 0);
+    assert_eq!(fs::read(dir.join("A.DAT")).unwrap(), b"1234");
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,147 @@
+//! Names of the keys of WQX machines.
+//!
+//! INKEY$, CHECKKEY and the key buffer use byte codes of keys. This table
+//! is the single place mapping the codes to names, so that hosts binding
+//! their keys to the machine, and configurations, refer to keys in the same
+//! way. Positions of keys in the keyboard matrix differ among machines, and
+//! are given by [`DefaultDevice::key_position`].
+//!
+//! [`DefaultDevice::key_position`]: super::default::DefaultDevice::key_position
+
+use crate::machine::MachineProps;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+  /// Unique ASCII name, which is looked up case-insensitively.
+  pub name: &'static str,
+  /// Text printed on the key.
+  pub label: &'static str,
+  pub code: u8,
+}
+
+/// Address and bit of a key in the keyboard matrix of a machine. The bit is
+/// cleared when the key is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPosition {
+  pub addr: u16,
+  /// Range: [0, 7]
+  pub bit: u8,
+}
+
+macro_rules! keys {
+  ($($name:literal $label:literal $code:literal,)*) => {
+    /// All keys with names, in the order of the layout of the keyboard.
+    pub const KEYS: &[Key] = &[
+      $(Key { name: $name, label: $label, code: $code },)*
+    ];
+  };
+}
+
+keys! {
+  "Power" "关机" 24,
+  "F1" "F1" 28,
+  "F2" "F2" 29,
+  "F3" "F3" 30,
+  "F4" "F4" 31,
+  "Q" "Q" 113,
+  "W" "W" 119,
+  "E" "E" 101,
+  "R" "R" 114,
+  "T" "T" 116,
+  "Y" "Y" 121,
+  "U" "U" 117,
+  "I" "I" 105,
+  "O" "O" 111,
+  "P" "P" 112,
+  "A" "A" 97,
+  "S" "S" 115,
+  "D" "D" 100,
+  "F" "F" 102,
+  "G" "G" 103,
+  "H" "H" 104,
+  "J" "J" 106,
+  "K" "K" 107,
+  "L" "L" 108,
+  "Enter" "输入" 13,
+  "Z" "Z" 122,
+  "X" "X" 120,
+  "C" "C" 99,
+  "V" "V" 118,
+  "B" "B" 98,
+  "N" "N" 110,
+  "M" "M" 109,
+  "PageUp" "上翻页" 19,
+  "Up" "↑" 20,
+  "PageDown" "下翻页" 14,
+  "Help" "求助" 25,
+  "Shift" "中英数" 26,
+  "Caps" "输入法" 18,
+  "Esc" "跳出" 27,
+  "Zero" "符号" 48,
+  "Dot" "." 46,
+  "Space" "空格" 32,
+  "Left" "←" 23,
+  "Down" "↓" 21,
+  "Right" "→" 22,
+}
+
+/// Looks up a key by name, case-insensitively.
+pub fn key_by_name(name: &str) -> Option<&'static Key> {
+  KEYS.iter().find(|key| key.name.eq_ignore_ascii_case(name))
+}
+
+pub fn key_by_code(code: u8) -> Option<&'static Key> {
+  KEYS.iter().find(|key| key.code == code)
+}
+
+impl MachineProps {
+  /// Returns the position of the key with `code` in the keyboard matrix, or
+  /// None if the machine does not have the key.
+  pub(crate) fn key_position(&self, code: u8) -> Option<KeyPosition> {
+    self.key_masks[code as usize].map(|(addr, mask)| KeyPosition {
+      addr,
+      bit: mask.trailing_zeros() as u8,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::HashMap;
+  use pretty_assertions::assert_eq;
+  use widestring::utf16str;
+
+  #[test]
+  fn lookup() {
+    assert_eq!(key_by_name("enter").unwrap().code, 13);
+    assert_eq!(key_by_name("PAGEUP").unwrap().label, "上翻页");
+    assert_eq!(key_by_name("foo"), None);
+    assert_eq!(key_by_code(27).unwrap().name, "Esc");
+    assert_eq!(key_by_code(0), None);
+
+    let mut names = HashMap::default();
+    let mut codes = HashMap::default();
+    for key in KEYS {
+      assert!(key.name.is_ascii());
+      assert_eq!(names.insert(key.name.to_ascii_lowercase(), key), None);
+      assert_eq!(codes.insert(key.code, key), None);
+    }
+  }
+
+  #[test]
+  fn positions() {
+    crate::machine::init_machines_once().unwrap();
+    for props in crate::machine::machines().values() {
+      for key in KEYS.iter().filter(|key| key.name != "Power") {
+        assert!(props.key_position(key.code).is_some(), "{}", key.name);
+      }
+    }
+
+    let props = &crate::machine::machines()[utf16str!("PC1000A")];
+    assert_eq!(
+      props.key_position(key_by_name("F3").unwrap().code),
+      Some(KeyPosition { addr: 200, bit: 4 })
+    );
+  }
+}
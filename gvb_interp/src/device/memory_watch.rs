@@ -0,0 +1,132 @@
+//! Change tracking of the memory, for auto-refreshing views like a hex viewer.
+//!
+//! A watch keeps a copy of a range of the memory, and reports the bytes
+//! differing from the copy when queried. Thus modifications are tracked no
+//! matter whether they are made by POKE, machine code or the device itself,
+//! while only the watched ranges are compared, instead of copying the whole
+//! memory to the view each frame.
+
+use std::ops::Range;
+
+/// Ids are never reused, so that ids of removed watches held by hosts do not
+/// refer to watches added later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemoryWatchId(pub usize);
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryWatches {
+  /// Sorted by ids.
+  watches: Vec<(MemoryWatchId, Watch)>,
+  next_id: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Watch {
+  start: usize,
+  shadow: Vec<u8>,
+}
+
+impl MemoryWatches {
+  pub fn add(&mut self, range: Range<usize>, memory: &[u8]) -> MemoryWatchId {
+    let end = range.end.min(memory.len());
+    let start = range.start.min(end);
+    let watch = Watch {
+      start,
+      shadow: memory[start..end].to_vec(),
+    };
+    let id = MemoryWatchId(self.next_id);
+    self.next_id += 1;
+    self.watches.push((id, watch));
+    id
+  }
+
+  /// Returns false if the watch does not exist.
+  pub fn remove(&mut self, id: MemoryWatchId) -> bool {
+    match self.find(id) {
+      Some(i) => {
+        self.watches.remove(i);
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn find(&self, id: MemoryWatchId) -> Option<usize> {
+    self.watches.binary_search_by_key(&id, |&(id, _)| id).ok()
+  }
+
+  /// Returns the ranges of bytes modified since the last query, in ascending
+  /// order, or None if the watch does not exist.
+  pub fn changes(
+    &mut self,
+    id: MemoryWatchId,
+    memory: &[u8],
+  ) -> Option<Vec<Range<usize>>> {
+    let i = self.find(id)?;
+    let watch = &mut self.watches[i].1;
+    let current = &memory[watch.start..watch.start + watch.shadow.len()];
+    let mut changes: Vec<Range<usize>> = vec![];
+    for (i, (old, &new)) in watch.shadow.iter_mut().zip(current).enumerate() {
+      if *old == new {
+        continue;
+      }
+      *old = new;
+      let addr = watch.start + i;
+      match changes.last_mut() {
+        Some(last) if last.end == addr => last.end += 1,
+        _ => changes.push(addr..addr + 1),
+      }
+    }
+    Some(changes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn changes() {
+    let mut memory = vec![0u8; 100];
+    let mut watches = MemoryWatches::default();
+    let a = watches.add(10..20, &memory);
+    let b = watches.add(50..200, &memory);
+    assert_eq!(watches.changes(a, &memory), Some(vec![]));
+
+    memory[9] = 1;
+    memory[10] = 1;
+    memory[11] = 1;
+    memory[15] = 1;
+    memory[19] = 1;
+    memory[20] = 1;
+    memory[50] = 1;
+    memory[99] = 1;
+    assert_eq!(
+      watches.changes(a, &memory),
+      Some(vec![10..12, 15..16, 19..20])
+    );
+    assert_eq!(watches.changes(a, &memory), Some(vec![]));
+    assert_eq!(watches.changes(b, &memory), Some(vec![50..51, 99..100]));
+
+    // changed back
+    memory[15] = 2;
+    memory[15] = 1;
+    assert_eq!(watches.changes(a, &memory), Some(vec![]));
+
+    assert!(watches.remove(a));
+    assert!(!watches.remove(a));
+    assert_eq!(watches.changes(a, &memory), None);
+    assert_eq!(watches.changes(MemoryWatchId(5), &memory), None);
+
+    // ids of removed watches are not reused
+    let c = watches.add(0..5, &memory);
+    assert_ne!(c, a);
+    assert_eq!(watches.changes(a, &memory), None);
+    assert!(!watches.remove(a));
+    memory[0] = 3;
+    memory[4] = 3;
+    assert_eq!(watches.changes(c, &memory), Some(vec![0..1, 4..5]));
+    assert_eq!(watches.changes(b, &memory), Some(vec![]));
+  }
+}
@@ -0,0 +1,311 @@
+//! Parsing of note strings of PLAY statements.
+//!
+//! The syntax is a subset of the music macro language, case-insensitive, with
+//! spaces ignored:
+//!
+//! - `A`~`G`: a note, optionally followed by `#` or `+` (sharp) or `-`
+//!   (flat), then an optional length and dots.
+//! - `R` or `P`: a rest, optionally followed by a length and dots.
+//! - `O`n: sets the octave, 0~6. The default is 4.
+//! - `<`, `>`: moves down or up an octave.
+//! - `L`n: sets the default length, 1~64, where 4 is a quarter note. The
+//!   default is 4.
+//! - `T`n: sets the tempo in quarter notes per minute, 32~255. The default is
+//!   120.
+//!
+//! Each dot after a note or a rest extends it by half of the last extension.
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+  /// In Hz, or None for rests.
+  pub frequency: Option<u16>,
+  pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotesError {
+  /// Offset of the malformed part in bytes.
+  pub offset: usize,
+  pub kind: NotesErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotesErrorKind {
+  InvalidChar(u8),
+  /// The command is not followed by a number.
+  MissingNumber(u8),
+  NumberOutOfRange {
+    command: u8,
+    min: u32,
+    max: u32,
+  },
+  OctaveOutOfRange,
+}
+
+impl Display for NotesErrorKind {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::InvalidChar(c) if c.is_ascii_graphic() => {
+        write!(f, "无法识别的字符 {}", *c as char)
+      }
+      Self::InvalidChar(c) => write!(f, "无法识别的字节 {}", c),
+      Self::MissingNumber(c) => {
+        write!(f, "{} 后面缺少数字", c.to_ascii_uppercase() as char)
+      }
+      Self::NumberOutOfRange { command, min, max } => write!(
+        f,
+        "{} 后面的数字必须在 {}~{} 之间",
+        command.to_ascii_uppercase() as char,
+        min,
+        max
+      ),
+      Self::OctaveOutOfRange => write!(f, "八度超出了 0~6 的范围"),
+    }
+  }
+}
+
+const MAX_OCTAVE: u32 = 6;
+
+/// Semitones of C~B relative to A.
+const SEMITONES: [i32; 7] = [0, 2, -9, -7, -5, -4, -2];
+
+pub fn parse_notes(notes: &[u8]) -> Result<Vec<Note>, NotesError> {
+  let mut parser = Parser {
+    input: notes,
+    offset: 0,
+  };
+  let mut octave = 4;
+  let mut length = 4;
+  let mut tempo = 120;
+  let mut result = vec![];
+  loop {
+    parser.skip_spaces();
+    let start = parser.offset;
+    let raw = match parser.next() {
+      Some(c) => c,
+      None => break,
+    };
+    let c = raw.to_ascii_lowercase();
+    match c {
+      b'a'..=b'g' => {
+        let mut semitone = SEMITONES[(c - b'a') as usize];
+        parser.skip_spaces();
+        match parser.peek() {
+          Some(b'#' | b'+') => {
+            parser.offset += 1;
+            semitone += 1;
+          }
+          Some(b'-') => {
+            parser.offset += 1;
+            semitone -= 1;
+          }
+          _ => {}
+        }
+        let duration = parser.duration(c, length, tempo)?;
+        let exp = (octave as i32 - 4) as f64 + semitone as f64 / 12.0;
+        let frequency = (440.0 * exp.exp2()).round() as u16;
+        result.push(Note {
+          frequency: Some(frequency),
+          duration,
+        });
+      }
+      b'r' | b'p' => {
+        let duration = parser.duration(c, length, tempo)?;
+        result.push(Note {
+          frequency: None,
+          duration,
+        });
+      }
+      b'o' => octave = parser.number_in(c, 0, MAX_OCTAVE)?,
+      b'l' => length = parser.number_in(c, 1, 64)?,
+      b't' => tempo = parser.number_in(c, 32, 255)?,
+      b'<' | b'>' => {
+        if c == b'<' && octave > 0 {
+          octave -= 1;
+        } else if c == b'>' && octave < MAX_OCTAVE {
+          octave += 1;
+        } else {
+          return Err(NotesError {
+            offset: start,
+            kind: NotesErrorKind::OctaveOutOfRange,
+          });
+        }
+      }
+      _ => {
+        return Err(NotesError {
+          offset: start,
+          kind: NotesErrorKind::InvalidChar(raw),
+        })
+      }
+    }
+  }
+  Ok(result)
+}
+
+struct Parser<'a> {
+  input: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.offset).copied()
+  }
+
+  fn next(&mut self) -> Option<u8> {
+    let c = self.peek()?;
+    self.offset += 1;
+    Some(c)
+  }
+
+  fn skip_spaces(&mut self) {
+    while self.peek() == Some(b' ') {
+      self.offset += 1;
+    }
+  }
+
+  fn number(&mut self) -> Option<(usize, u32)> {
+    self.skip_spaces();
+    let start = self.offset;
+    let mut value = 0u32;
+    while let Some(c @ b'0'..=b'9') = self.peek() {
+      self.offset += 1;
+      value = value.saturating_mul(10).saturating_add((c - b'0') as u32);
+    }
+    if self.offset == start {
+      None
+    } else {
+      Some((start, value))
+    }
+  }
+
+  fn number_in(
+    &mut self,
+    command: u8,
+    min: u32,
+    max: u32,
+  ) -> Result<u32, NotesError> {
+    match self.number() {
+      Some((_, value)) if (min..=max).contains(&value) => Ok(value),
+      Some((offset, _)) => Err(NotesError {
+        offset,
+        kind: NotesErrorKind::NumberOutOfRange { command, min, max },
+      }),
+      None => Err(NotesError {
+        offset: self.offset,
+        kind: NotesErrorKind::MissingNumber(command),
+      }),
+    }
+  }
+
+  /// Parses the optional length and dots after a note or a rest.
+  fn duration(
+    &mut self,
+    command: u8,
+    default_length: u32,
+    tempo: u32,
+  ) -> Result<Duration, NotesError> {
+    let length = match self.number() {
+      Some((_, value)) if (1..=64).contains(&value) => value,
+      Some((offset, _)) => {
+        return Err(NotesError {
+          offset,
+          kind: NotesErrorKind::NumberOutOfRange {
+            command,
+            min: 1,
+            max: 64,
+          },
+        })
+      }
+      None => default_length,
+    };
+    let mut secs = 240.0 / tempo as f64 / length as f64;
+    let mut extension = secs;
+    loop {
+      self.skip_spaces();
+      if self.peek() != Some(b'.') {
+        break;
+      }
+      self.offset += 1;
+      extension /= 2.0;
+      secs += extension;
+    }
+    Ok(Duration::from_secs_f64(secs))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  fn note(frequency: u16, millis: u64) -> Note {
+    Note {
+      frequency: Some(frequency),
+      duration: Duration::from_millis(millis),
+    }
+  }
+
+  #[test]
+  fn notes() {
+    assert_eq!(
+      parse_notes(b"t120 a c8 E-2. o5 g# < r16 l2 b").unwrap(),
+      vec![
+        note(440, 500),
+        note(262, 250),
+        note(311, 1500),
+        note(831, 500),
+        Note {
+          frequency: None,
+          duration: Duration::from_millis(125),
+        },
+        note(494, 1000),
+      ]
+    );
+    assert_eq!(parse_notes(b"  ").unwrap(), vec![]);
+  }
+
+  #[test]
+  fn errors() {
+    let err = |offset, kind| Err(NotesError { offset, kind });
+    assert_eq!(
+      parse_notes(b"cde h"),
+      err(4, NotesErrorKind::InvalidChar(b'h'))
+    );
+    assert_eq!(
+      parse_notes(b"cdo"),
+      err(3, NotesErrorKind::MissingNumber(b'o'))
+    );
+    assert_eq!(
+      parse_notes(b"c t 20"),
+      err(
+        4,
+        NotesErrorKind::NumberOutOfRange {
+          command: b't',
+          min: 32,
+          max: 255,
+        }
+      )
+    );
+    assert_eq!(
+      parse_notes(b"o6 c >c"),
+      err(5, NotesErrorKind::OctaveOutOfRange)
+    );
+    assert_eq!(
+      NotesErrorKind::NumberOutOfRange {
+        command: b'l',
+        min: 1,
+        max: 64
+      }
+      .to_string(),
+      "L 后面的数字必须在 1~64 之间"
+    );
+    assert_eq!(
+      NotesErrorKind::InvalidChar(1).to_string(),
+      "无法识别的字节 1"
+    );
+  }
+}
@@ -0,0 +1,39 @@
+//! Transliteration of the text printed by programs, for accessibility tools
+//! built on top of the simulator, e.g. converting Chinese characters to
+//! pinyin, or feeding a screen reader.
+
+/// Text printed by a PRINT statement, after transliteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintedText {
+  pub text: String,
+  /// Screen row of the first character, 0-based.
+  pub row: u8,
+  /// Screen column of the first character, 0-based.
+  pub column: u8,
+}
+
+pub trait Transliterator {
+  /// Transliterates `text`, which is decoded from the bytes printed at
+  /// `row` and `column`. Returns None if the text should be dropped, e.g. when
+  /// it has been consumed by the transliterator itself.
+  fn transliterate(
+    &mut self,
+    text: &str,
+    row: u8,
+    column: u8,
+  ) -> Option<String>;
+}
+
+impl<F> Transliterator for F
+where
+  F: FnMut(&str, u8, u8) -> Option<String>,
+{
+  fn transliterate(
+    &mut self,
+    text: &str,
+    row: u8,
+    column: u8,
+  ) -> Option<String> {
+    self(text, row, column)
+  }
+}
@@ -1,12 +1,16 @@
-use std::fmt::{self, Debug, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 
-use crate::ast::Range;
+pub use crate::ast::Range;
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct Diagnostic {
   pub severity: Severity,
   pub message: String,
   pub range: Range,
+  pub phase: DiagnosticPhase,
+  /// Symbols expected at a syntax error, which are also listed in the
+  /// message. Empty for other diagnostics.
+  pub expected: Vec<ExpectedSymbol>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,22 +19,111 @@ pub enum Severity {
   Error,
 }
 
+/// The phase in which a diagnostic is produced. Diagnostics of the same
+/// range are ordered by phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiagnosticPhase {
+  Lex,
+  Parse,
+  Compile,
+  /// Diagnostics produced while the program is running, e.g. of function
+  /// bodies entered in INPUT statements.
+  Runtime,
+}
+
+/// A symbol expected by the parser at a syntax error, e.g. an expression or
+/// a keyword.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedSymbol {
+  Ident,
+  Label,
+  Real,
+  String,
+  /// Text of a punctuation, e.g. `,`.
+  Punc(String),
+  /// Name of a keyword, e.g. `GOTO`.
+  Keyword(String),
+  /// Name of a system function, e.g. `SIN`.
+  SysFunc(String),
+  Eol,
+  Expr,
+  Stmt,
+  Array,
+}
+
+impl Display for ExpectedSymbol {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::Ident => write!(f, "标识符"),
+      Self::Label => write!(f, "行号"),
+      Self::Real => write!(f, "实数"),
+      Self::String => write!(f, "字符串"),
+      Self::Punc(p) => write!(f, "\"{p}\""),
+      Self::Keyword(name) | Self::SysFunc(name) => write!(f, "{name}"),
+      Self::Eol => write!(f, "行尾"),
+      Self::Expr => write!(f, "表达式"),
+      Self::Stmt => write!(f, "语句"),
+      Self::Array => write!(f, "数组"),
+    }
+  }
+}
+
+/// Returns the message of a syntax error listing the expected symbols, e.g.
+/// `语法错误。期望是 ":" 或 行尾`.
+pub fn expected_symbols_message(symbols: &[ExpectedSymbol]) -> String {
+  let mut msg = "语法错误。期望是 ".to_owned();
+  let len = symbols.len();
+  for (i, sym) in symbols.iter().enumerate() {
+    if i != 0 {
+      if i == len - 1 {
+        msg += " 或 "
+      } else {
+        msg += "，";
+      }
+    }
+    msg += &sym.to_string();
+  }
+  msg
+}
+
 impl Diagnostic {
+  /// Creates an error of the compile phase.
   pub fn new_error(range: Range, message: impl ToString) -> Self {
     Self {
       severity: Severity::Error,
       range,
       message: message.to_string(),
+      phase: DiagnosticPhase::Compile,
+      expected: vec![],
     }
   }
 
+  /// Creates a warning of the compile phase.
   pub fn new_warning(range: Range, message: impl ToString) -> Self {
     Self {
       severity: Severity::Warning,
       range,
       message: message.to_string(),
+      phase: DiagnosticPhase::Compile,
+      expected: vec![],
     }
   }
+
+  pub fn with_phase(mut self, phase: DiagnosticPhase) -> Self {
+    self.phase = phase;
+    self
+  }
+
+  pub fn with_expected(mut self, expected: Vec<ExpectedSymbol>) -> Self {
+    self.expected = expected;
+    self
+  }
+}
+
+/// Sorts diagnostics of a line by range, then by phase. Diagnostics of the
+/// same range and phase are kept in the order they are produced.
+pub(crate) fn sort_diagnostics(diags: &mut [Diagnostic]) {
+  diags.sort_by_key(|diag| (diag.range.start, diag.range.end, diag.phase));
 }
 
 pub(crate) fn contains_errors(diags: &[Diagnostic]) -> bool {
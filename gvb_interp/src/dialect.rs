@@ -0,0 +1,181 @@
+//! Keywords and system functions recognized by the firmware of a machine.
+//!
+//! Firmwares differ in the keywords they support, e.g. some lack SLEEP and
+//! PLAY. A dialect is a subset of the keyword table of the parser, selected by
+//! the `disabled-keywords` field of the machine profile. Words not in the
+//! dialect are parsed as identifiers, as the firmware does.
+//!
+//! Keywords which are extensions of the emulator, e.g. TIMER, are not in any
+//! dialect unless enabled by the `extensions` field of the machine profile,
+//! so that programs using them as variable names are still valid.
+//!
+//! The parser, and editor features like completion which are based on
+//! [`crate::builtin`], should all consult the dialect of the document.
+
+use crate::ast::{Keyword, SysFuncKind};
+use crate::builtin::{self, Builtin};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+  /// Bit `i` is set if the keyword whose discriminant is `i` is enabled.
+  keywords: u128,
+  /// Bit `i` is set if the system function whose discriminant is `i` is
+  /// enabled.
+  sys_funcs: u64,
+}
+
+/// Keywords which are extensions of the emulator.
+const EXTENSIONS: &[Keyword] = &[Keyword::Timer, Keyword::Sound];
+
+const fn extension_bits() -> u128 {
+  let mut bits = 0;
+  let mut i = 0;
+  while i < EXTENSIONS.len() {
+    bits |= 1 << EXTENSIONS[i] as u32;
+    i += 1;
+  }
+  bits
+}
+
+impl Default for Dialect {
+  fn default() -> Self {
+    Self::FIRMWARE
+  }
+}
+
+impl Dialect {
+  /// The dialect supporting all keywords and system functions, including
+  /// the extensions of the emulator.
+  pub const FULL: Self = Self {
+    keywords: u128::MAX,
+    sys_funcs: u64::MAX,
+  };
+
+  /// The dialect supporting all keywords and system functions of the
+  /// firmware, without the extensions of the emulator.
+  pub const FIRMWARE: Self = Self {
+    keywords: !extension_bits(),
+    sys_funcs: u64::MAX,
+  };
+
+  /// Creates a dialect of the firmware without the keywords or system
+  /// functions named `names`, case-insensitively. Returns the first unknown
+  /// name as the error.
+  pub fn without<'a, I>(names: I) -> Result<Self, &'a str>
+  where
+    I: IntoIterator<Item = &'a str>,
+  {
+    let mut dialect = Self::FIRMWARE;
+    for name in names {
+      let lower = name.to_ascii_lowercase();
+      if let Ok(kw) = lower.parse::<Keyword>() {
+        dialect.keywords &= !(1 << kw as u32);
+      } else if let Ok(kind) = lower.parse::<SysFuncKind>() {
+        dialect.sys_funcs &= !(1 << kind as u32);
+      } else {
+        return Err(name);
+      }
+    }
+    Ok(dialect)
+  }
+
+  /// Enables the extensions of the emulator named `names`,
+  /// case-insensitively. Returns the first name which is not an extension as
+  /// the error.
+  pub fn with_extensions<'a, I>(mut self, names: I) -> Result<Self, &'a str>
+  where
+    I: IntoIterator<Item = &'a str>,
+  {
+    for name in names {
+      match name.to_ascii_lowercase().parse::<Keyword>() {
+        Ok(kw) if EXTENSIONS.contains(&kw) => {
+          self.keywords |= 1 << kw as u32;
+        }
+        _ => return Err(name),
+      }
+    }
+    Ok(self)
+  }
+
+  /// `name` must be in lower case.
+  pub(crate) fn keyword(&self, name: &str) -> Option<Keyword> {
+    name
+      .parse::<Keyword>()
+      .ok()
+      .filter(|&kw| self.keywords & (1 << kw as u32) != 0)
+  }
+
+  /// `name` must be in lower case.
+  pub(crate) fn sys_func(&self, name: &str) -> Option<SysFuncKind> {
+    name
+      .parse::<SysFuncKind>()
+      .ok()
+      .filter(|&kind| self.sys_funcs & (1 << kind as u32) != 0)
+  }
+
+  /// Returns whether the keyword or system function named `name` is in the
+  /// dialect, case-insensitively.
+  pub fn contains(&self, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    self.keyword(&name).is_some() || self.sys_func(&name).is_some()
+  }
+
+  /// Returns the metadata of keywords and system functions in the dialect,
+  /// sorted by name.
+  pub fn builtins(&self) -> impl Iterator<Item = &'static Builtin> + '_ {
+    builtin::builtins().iter().filter(|b| self.contains(b.name))
+  }
+
+  /// Looks up a keyword or system function in the dialect by name,
+  /// case-insensitively.
+  pub fn lookup(&self, name: &str) -> Option<&'static Builtin> {
+    builtin::lookup(name).filter(|b| self.contains(b.name))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn full() {
+    let dialect = Dialect::FULL;
+    // AT is not recognized by the parser.
+    for b in builtin::builtins().iter().filter(|b| b.name != "AT") {
+      assert!(dialect.contains(b.name), "{}", b.name);
+    }
+    assert_eq!(dialect.builtins().count(), builtin::builtins().len() - 1);
+    assert!(!dialect.contains("foo"));
+  }
+
+  #[test]
+  fn without() {
+    let dialect = Dialect::without(["Sleep", "PLAY", "fopen"]).unwrap();
+    assert_eq!(dialect.keyword("sleep"), None);
+    assert!(dialect.keyword("print") == Some(Keyword::Print));
+    assert!(dialect.sys_func("fopen").is_none());
+    assert!(dialect.sys_func("fgetc").is_some());
+    assert!(!dialect.contains("play"));
+    assert_eq!(dialect.lookup("PLAY"), None);
+    assert_eq!(dialect.lookup("beep").unwrap().name, "BEEP");
+    assert!(!dialect.contains("timer"));
+    assert!(!dialect.contains("sound"));
+    assert_eq!(dialect.builtins().count(), builtin::builtins().len() - 6);
+
+    assert_eq!(Dialect::without(["sleep", "foo"]), Err("foo"));
+  }
+
+  #[test]
+  fn extensions() {
+    let dialect = Dialect::default();
+    assert_eq!(dialect.keyword("timer"), None);
+    let dialect = dialect.with_extensions(["Timer"]).unwrap();
+    assert!(dialect.keyword("timer") == Some(Keyword::Timer));
+    assert_eq!(dialect.keyword("sound"), None);
+    let dialect = dialect.with_extensions(["SOUND"]).unwrap();
+    assert_eq!(dialect, Dialect::FULL);
+
+    assert_eq!(Dialect::default().with_extensions(["print"]), Err("print"));
+  }
+}
@@ -8,15 +8,40 @@ use crate::ast::{Eol, Label, Program, ProgramLine, Range, StmtKind};
 use crate::compiler::compile_prog;
 use crate::device::default::DefaultDevice;
 use crate::device::Device;
+use crate::diagnostic::sort_diagnostics;
+use crate::dialect::Dialect;
 use crate::machine::EmojiVersion;
 use crate::machine::MachineProps;
-use crate::parser::{parse_line, ParseResult};
+use crate::parser::canonical::canonicalize_line;
+use crate::parser::{parse_line_in_dialect, ParseResult};
 use crate::util::ascii_ext::AsciiExt;
 use crate::util::utf16str_ext::Utf16StrExt;
 use crate::HashMap;
-use crate::{CodeGen, Diagnostic, VirtualMachine};
+use crate::{CodeGen, Diagnostic, DiagnosticPhase, Severity, VirtualMachine};
 
+mod addrs;
 mod binary;
+mod coords;
+mod data;
+mod features;
+mod files;
+mod fingerprint;
+mod gwbasic;
+mod labels;
+mod merge;
+mod metadata;
+mod normalize;
+
+pub use self::addrs::AddrInfo;
+pub use self::data::DataBlock;
+pub use self::features::Features;
+pub use self::files::{FileOpenMode, FileReference};
+pub use self::fingerprint::{Fingerprint, ProgramStats, RequiredFeatures};
+pub use self::gwbasic::ImportWarning;
+pub use self::labels::{PossibleLabelRef, RenameLabelError};
+pub use self::merge::{MergeConflict, MergeIssue};
+pub use self::metadata::ProgramMetadata;
+pub use self::normalize::FullWidthFix;
 
 const DEFAULT_TEXT: &Utf16Str = utf16str!("10 ");
 
@@ -28,6 +53,15 @@ pub struct Document {
   lines: Vec<DocLine>,
   version: DocVer,
   compile_cache: Option<CompileCache>,
+  /// Reports extensions of the emulator as errors.
+  strict: bool,
+  /// Severity of variables which are read but never assigned, or None if
+  /// they are not reported.
+  never_assigned_var_lint: Option<Severity>,
+  /// Diagnostics of each line last returned by `diagnostics_update`.
+  reported_diagnostics: Vec<LineDiagnosis>,
+  /// Length of the text when `diagnostics_update` is last called.
+  reported_text_len: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,6 +85,19 @@ pub struct LineDiagnosis {
   pub diagnostics: Vec<Diagnostic>,
 }
 
+/// Changes of diagnostics since the last call of
+/// [`Document::diagnostics_update`]. The diagnostics of `removed` lines
+/// starting at line `start` are replaced by `lines`. The diagnostics of the
+/// lines after them are unchanged, but the lines are moved by `shift`
+/// characters, in UTF-16 code units.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsUpdate {
+  pub start: usize,
+  pub removed: usize,
+  pub lines: Vec<LineDiagnosis>,
+  pub shift: isize,
+}
+
 #[derive(Debug)]
 pub enum LoadDocumentError {
   Io(io::Error),
@@ -115,6 +162,14 @@ pub enum RelabelError {
   LabelOverflow(u32),
 }
 
+/// The result of renumbering, including the numbers which may be line
+/// references not updated by the edits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelabelReport {
+  pub edits: Vec<ReplaceText>,
+  pub possible_label_refs: Vec<PossibleLabelRef>,
+}
+
 impl From<io::Error> for LoadDocumentError {
   fn from(err: io::Error) -> Self {
     Self::Io(err)
@@ -176,10 +231,15 @@ impl Default for Document {
       lines: text_to_doc_lines(DEFAULT_TEXT),
       version: DocVer(0),
       compile_cache: None,
+      strict: false,
+      never_assigned_var_lint: None,
+      reported_diagnostics: vec![],
+      reported_text_len: 0,
     }
   }
 }
 
+#[derive(Debug)]
 pub struct ContainsErrors;
 
 impl Document {
@@ -187,10 +247,58 @@ impl Document {
     Self::default()
   }
 
+  /// Creates a document from program text. The machine is detected from the
+  /// text as in `load`, and defaults to the default machine of emoji version
+  /// 2.
+  pub fn from_text(text: impl AsRef<Utf16Str>) -> Self {
+    let text = Utf16String::from(text.as_ref());
+    let machine_props = detect_machine_props(&text)
+      .and_then(|p| p.1.ok())
+      .unwrap_or_else(|| {
+        crate::machine::machines()[EmojiVersion::V2.default_machine_name()]
+          .clone()
+      });
+    Document {
+      base_addr: binary::DEFAULT_BASE_ADDR,
+      emoji_version: machine_props.emoji_version,
+      machine_props,
+      lines: text_to_doc_lines(&text),
+      text,
+      version: DocVer(0),
+      compile_cache: None,
+      strict: false,
+      never_assigned_var_lint: None,
+      reported_diagnostics: vec![],
+      reported_text_len: 0,
+    }
+  }
+
+  /// Assembles a program from parts of a listing as the MERGE command does:
+  /// lines are ordered by line numbers, and lines of later parts replace the
+  /// lines of the same numbers in earlier parts. If different lines share a
+  /// line number, or some lines have no line numbers, the merged document is
+  /// returned in `MergeConflict` along with the issues.
+  pub fn merge_parts(parts: Vec<&str>) -> Result<Self, MergeConflict> {
+    let (text, issues) = merge::merge_parts(&parts);
+    let document = Self::from_text(Utf16String::from(text));
+    if issues.is_empty() {
+      Ok(document)
+    } else {
+      Err(MergeConflict {
+        issues,
+        document: Box::new(document),
+      })
+    }
+  }
+
   pub fn load<D>(data: D, is_bas: bool) -> Result<Self, LoadDocumentError>
   where
     D: AsRef<[u8]>,
   {
+    if is_bas && gwbasic::is_gwbasic(&data) {
+      return Ok(Self::import_gwbasic(data)?.0);
+    }
+
     let mut doc = if is_bas {
       binary::load_bas(&data, None)?
     } else {
@@ -225,9 +333,54 @@ impl Document {
       lines,
       version: DocVer(0),
       compile_cache: None,
+      strict: false,
+      never_assigned_var_lint: None,
+      reported_diagnostics: vec![],
+      reported_text_len: 0,
     })
   }
 
+  /// Imports a GW-BASIC tokenized `.bas` file. Statements which cannot be
+  /// translated to GVBASIC are kept as is and reported in the returned
+  /// warnings. The warnings are also reported as diagnostics of their lines,
+  /// until the lines are edited.
+  pub fn import_gwbasic<D>(
+    data: D,
+  ) -> Result<(Self, Vec<ImportWarning>), LoadDocumentError>
+  where
+    D: AsRef<[u8]>,
+  {
+    let doc = gwbasic::load_gwbasic(data)?;
+    let emoji_version = EmojiVersion::V2;
+    let lines = text_to_doc_lines(&doc.text);
+    let mut document = Document {
+      base_addr: binary::DEFAULT_BASE_ADDR,
+      emoji_version,
+      machine_props: crate::machine::machines()
+        [emoji_version.default_machine_name()]
+      .clone(),
+      text: doc.text,
+      lines,
+      version: DocVer(0),
+      compile_cache: None,
+      strict: false,
+      never_assigned_var_lint: None,
+      reported_diagnostics: vec![],
+      reported_text_len: 0,
+    };
+    for warning in &doc.warnings {
+      let i = warning.line;
+      document.ensure_line_parsed(i);
+      let len = document.line_text(i).len();
+      let parsed = document.lines[i].parsed.as_mut().unwrap();
+      parsed.diagnostics.push(Diagnostic::new_warning(
+        Range::new(0, len),
+        warning.message.clone(),
+      ));
+    }
+    Ok((document, doc.warnings))
+  }
+
   /// Load a `.bas` or `.txt` file.
   pub fn load_file<P>(path: P) -> Result<Self, LoadDocumentError>
   where
@@ -302,15 +455,46 @@ impl Document {
       prog.lines.push(self.ensure_line_parsed(i).clone());
     }
     let mut codegen = CodeGen::new(self.emoji_version);
+    codegen.set_max_string_len(self.machine_props.max_string_len);
     compile_prog(&self.text, &mut prog, &mut codegen);
+    if self.strict {
+      for line in &mut prog.lines {
+        report_extensions(line);
+      }
+    }
+    if let Some(severity) = self.never_assigned_var_lint {
+      for (loc, name) in codegen.never_assigned_var_reads() {
+        let value = if name.ends_with('$') {
+          "空字符串"
+        } else {
+          "0"
+        };
+        prog.lines[loc.line].diagnostics.push(Diagnostic {
+          severity,
+          message: format!("变量 {} 从未被赋值，它的值总是{}", name, value),
+          range: loc.range,
+          phase: DiagnosticPhase::Compile,
+          expected: vec![],
+        });
+      }
+    }
+
+    for (i, line) in prog.lines.iter_mut().enumerate() {
+      let text = &self.text[self.line_range(i)];
+      addrs::lint_addrs(text, line, &self.machine_props);
+      coords::lint_coords(text, line, &self.machine_props);
+    }
 
     let diagnostics = prog
       .lines
       .into_iter()
       .zip(&self.lines)
-      .map(|(line, doc_line)| LineDiagnosis {
-        line_start: doc_line.line_start,
-        diagnostics: line.diagnostics,
+      .map(|(mut line, doc_line)| {
+        sort_diagnostics(&mut line.diagnostics);
+        LineDiagnosis {
+          line_start: doc_line.line_start,
+          diagnostics: line.diagnostics,
+        }
       })
       .collect();
 
@@ -323,17 +507,129 @@ impl Document {
     &self.compile_cache.as_ref().unwrap().diagnostics
   }
 
-  fn ensure_line_parsed(&mut self, i: usize) -> &ParseResult<ProgramLine> {
-    if let Some(p) = self.lines[i].parsed.as_ref() {
-      // TODO remove unsafe after Polonius is done
-      return unsafe { &*(p as *const _) };
+  /// Returns the lines whose diagnostics are changed since the last call, or
+  /// None if no diagnostics are changed. The first call returns all lines.
+  ///
+  /// Only the smallest run of lines covering all changes is reported, which
+  /// is usually the edited lines. Lines after the run which are merely
+  /// shifted by the edits are not reported, but described by
+  /// [`DiagnosticsUpdate::shift`]. Inserted lines are reported even if they
+  /// have no diagnostics.
+  pub fn diagnostics_update(&mut self) -> Option<DiagnosticsUpdate> {
+    let mut reported = std::mem::take(&mut self.reported_diagnostics);
+    let shift = self.text.len() as isize - self.reported_text_len as isize;
+    self.reported_text_len = self.text.len();
+    let lines = self.diagnostics();
+    // Lines without diagnostics can be moved freely.
+    let same = |old: &LineDiagnosis, new: &LineDiagnosis, shift: isize| {
+      old.diagnostics == new.diagnostics
+        && (old.diagnostics.is_empty()
+          || old.line_start as isize + shift == new.line_start as isize)
+    };
+    let prefix = reported
+      .iter()
+      .zip(lines)
+      .take_while(|(old, new)| same(old, new, 0))
+      .count();
+    let suffix = reported[prefix..]
+      .iter()
+      .rev()
+      .zip(lines[prefix..].iter().rev())
+      .take_while(|(old, new)| same(old, new, shift))
+      .count();
+    let removed = reported.len() - prefix - suffix;
+    let changed = &lines[prefix..lines.len() - suffix];
+    let moved = shift != 0
+      && lines[prefix..]
+        .iter()
+        .any(|line| !line.diagnostics.is_empty());
+    let update = if removed == 0 && changed.is_empty() && !moved {
+      None
+    } else {
+      Some(DiagnosticsUpdate {
+        start: prefix,
+        removed,
+        lines: changed.to_vec(),
+        shift,
+      })
+    };
+    reported.splice(prefix..prefix + removed, changed.iter().cloned());
+    for line in &mut reported[prefix + changed.len()..] {
+      line.line_start = (line.line_start as isize + shift) as usize;
     }
-    let start = self.lines[i].line_start;
+    self.reported_diagnostics = reported;
+    update
+  }
+
+  /// Forgets the diagnostics reported, so that the next call of
+  /// [`diagnostics_update`](Self::diagnostics_update) returns all lines.
+  pub fn reset_diagnostics_update(&mut self) {
+    self.reported_diagnostics.clear();
+    self.reported_text_len = 0;
+  }
+
+  /// In strict mode, extensions of the emulator which are not supported by
+  /// the real machine, e.g. the SOUND statement, are reported as errors.
+  pub fn set_strict(&mut self, strict: bool) {
+    if self.strict != strict {
+      self.strict = strict;
+      self.compile_cache = None;
+    }
+  }
+
+  pub fn is_strict(&self) -> bool {
+    self.strict
+  }
+
+  /// Reports variables which are read but never assigned anywhere in the
+  /// program, usually misspelled names, with `severity`. Whether a read
+  /// happens before the assignments is not checked. Variables assigned
+  /// by INPUT or READ statements are not reported. Disabled if `severity` is
+  /// None, which is the default.
+  pub fn set_never_assigned_var_lint(&mut self, severity: Option<Severity>) {
+    if self.never_assigned_var_lint != severity {
+      self.never_assigned_var_lint = severity;
+      self.compile_cache = None;
+    }
+  }
+
+  pub fn never_assigned_var_lint(&self) -> Option<Severity> {
+    self.never_assigned_var_lint
+  }
+
+  /// Returns the range of line `i` in the text, including the newline.
+  fn line_range(&self, i: usize) -> std::ops::Range<usize> {
     let end = self
       .lines
       .get(i + 1)
       .map_or(self.text.len(), |line| line.line_start);
-    let p = parse_line(&self.text[start..end]).0;
+    self.lines[i].line_start..end
+  }
+
+  /// Returns the text of line `i` without the newline. The line must have
+  /// been parsed.
+  fn line_text(&self, i: usize) -> &Utf16Str {
+    let range = self.line_range(i);
+    let eol = self.lines[i]
+      .parsed
+      .as_ref()
+      .unwrap()
+      .content
+      .eol
+      .byte_len();
+    &self.text[range.start..range.end - eol]
+  }
+
+  fn ensure_line_parsed(&mut self, i: usize) -> &ParseResult<ProgramLine> {
+    if let Some(p) = self.lines[i].parsed.as_ref() {
+      // TODO remove unsafe after Polonius is done
+      return unsafe { &*(p as *const _) };
+    }
+    let p = parse_line_in_dialect(
+      &self.text[self.line_range(i)],
+      &self.machine_props.dialect,
+    )
+    .0;
     self.lines[i].parsed = Some(p);
     self.lines[i].parsed.as_ref().unwrap()
   }
@@ -347,10 +643,173 @@ impl Document {
     &self.text
   }
 
+  /// Returns the text in canonical form, which is suitable for diffing and
+  /// committing into version control systems. Keywords and identifiers are
+  /// upper-cased, redundant spaces are removed and all newlines are CRLF.
+  /// Lines containing syntax errors are kept as they are.
+  ///
+  /// The document itself is not modified, `save` still writes the original
+  /// text.
+  pub fn to_canonical_text(&mut self) -> Utf16String {
+    let mut text = Utf16String::new();
+    for i in 0..self.lines.len() {
+      let parsed = self.ensure_line_parsed(i);
+      let eol = parsed.content.eol.clone();
+      let has_errors = crate::contains_errors(&parsed.diagnostics);
+      let line = self.line_text(i);
+      let canonical = if has_errors {
+        None
+      } else {
+        canonicalize_line(line, &self.machine_props.dialect)
+      };
+      text.push_utfstr(canonical.as_deref().unwrap_or(line));
+      if eol != Eol::None {
+        text.push_str("\r\n");
+      }
+    }
+    text
+  }
+
+  /// Computes the fingerprint of the program. See [`Fingerprint`].
+  pub fn fingerprint(&mut self) -> Fingerprint {
+    let mut labels = HashMap::default();
+    for i in 0..self.lines.len() {
+      if let Some((_, label)) = &self.ensure_line_parsed(i).content.label {
+        labels.entry(label.0).or_insert(i);
+      }
+    }
+
+    let mut builder = fingerprint::FingerprintBuilder::new();
+    for (i, line) in self.lines.iter().enumerate() {
+      let parsed = line.parsed.as_ref().unwrap();
+      builder.add_line(
+        i,
+        self.line_text(i),
+        parsed,
+        &labels,
+        &self.machine_props.dialect,
+      );
+    }
+    builder.finish()
+  }
+
+  /// Returns the files opened by the OPEN statements of the program, in the
+  /// order they appear.
+  pub fn file_references(&mut self) -> Vec<FileReference> {
+    let mut refs = vec![];
+    for i in 0..self.lines.len() {
+      self.ensure_line_parsed(i);
+    }
+    for (i, line) in self.lines.iter().enumerate() {
+      let parsed = line.parsed.as_ref().unwrap();
+      files::add_file_references(&mut refs, i, self.line_text(i), parsed);
+    }
+    refs
+  }
+
+  /// Returns the files which must exist before the program runs but are not
+  /// found in `data_dir`, as well as files to be read whose names cannot be
+  /// resolved statically.
+  pub fn missing_files<P>(&mut self, data_dir: P) -> Vec<FileReference>
+  where
+    P: AsRef<Path>,
+  {
+    let data_dir = data_dir.as_ref();
+    self
+      .file_references()
+      .into_iter()
+      .filter(|r| r.is_required())
+      .filter(|r| match &r.name {
+        Some(name) => !data_dir.join(name).is_file(),
+        None => true,
+      })
+      .collect()
+  }
+
+  /// Returns the blocks of DATA statements consisting of bytes, in the order
+  /// they appear, e.g. for previewing the tiles embedded in the program. See
+  /// [`DataBlock`].
+  pub fn data_blocks(&mut self) -> Vec<DataBlock> {
+    let mut blocks: Vec<DataBlock> = vec![];
+    let mut in_block = false;
+    for i in 0..self.lines.len() {
+      self.ensure_line_parsed(i);
+    }
+    for (i, line) in self.lines.iter().enumerate() {
+      let parsed = line.parsed.as_ref().unwrap();
+      match data::data_bytes(self.line_text(i), parsed) {
+        Some(bytes) => {
+          if in_block {
+            let block = blocks.last_mut().unwrap();
+            block.end_line = i;
+            block.bytes.extend(bytes);
+          } else {
+            blocks.push(DataBlock {
+              start_line: i,
+              end_line: i,
+              bytes,
+            });
+          }
+          in_block = true;
+        }
+        None => in_block = false,
+      }
+    }
+    blocks
+  }
+
+  /// Computes the replacements of full-width digits, commas and other
+  /// characters in numeric DATA items, which are often pasted from web pages
+  /// and silently change the values read. Quoted items are left unchanged.
+  /// The edits are not applied, so that they can be reviewed first.
+  pub fn compute_full_width_data_fixes(&mut self) -> Vec<FullWidthFix> {
+    let mut fixes = vec![];
+    for i in 0..self.lines.len() {
+      self.ensure_line_parsed(i);
+    }
+    for (i, line) in self.lines.iter().enumerate() {
+      let parsed = line.parsed.as_ref().unwrap();
+      normalize::add_full_width_fixes(
+        &mut fixes,
+        i,
+        line.line_start,
+        self.line_text(i),
+        parsed,
+      );
+    }
+    fixes
+  }
+
+  /// Returns the name of the subdirectory storing the files of the program,
+  /// derived from the fingerprint, so that reformatted or renumbered
+  /// programs share the files. See
+  /// [`DefaultDevice::set_storage_namespace`].
+  pub fn storage_namespace(&mut self) -> String {
+    format!("{:016x}", self.fingerprint().hash)
+  }
+
+  /// Returns the metadata declared in the REM header. See
+  /// [`ProgramMetadata`].
+  pub fn metadata(&self) -> ProgramMetadata {
+    metadata::parse_metadata(&self.text).0
+  }
+
   pub fn machine_name(&self) -> &str {
     &self.machine_props.name
   }
 
+  /// Returns the keywords and system functions supported by the machine of
+  /// the document.
+  pub fn dialect(&self) -> &Dialect {
+    &self.machine_props.dialect
+  }
+
+  /// Returns the features supported by the dialect and the machine profile
+  /// of the document. See [`Features`].
+  pub fn features(&self) -> Features {
+    features::features(&self.machine_props, self.strict)
+  }
+
   pub fn sync_machine_name(
     &mut self,
   ) -> Result<Vec<ReplaceChar>, MachinePropError> {
@@ -612,56 +1071,18 @@ impl Document {
 
     for i in 0..self.lines.len() {
       let line_start = self.lines[i].line_start as isize;
-      macro_rules! add_label_ref {
-        ($range:ident, $label:ident) => {{
-          match label_refs.entry($label) {
-            hash_map::Entry::Vacant(_) => {
-              return Err(RelabelError::LabelNotFound {
-                label: $label.0,
-                range: $range.offset(line_start),
-              });
-            }
-            hash_map::Entry::Occupied(mut refs) => {
-              refs.get_mut().push($range.offset(line_start));
-            }
-          }
-        }};
-      }
-
       let parsed = self.ensure_line_parsed(i);
-      for (_, stmt) in &parsed.stmt_arena {
-        match &stmt.kind {
-          StmtKind::GoTo { label, .. } | StmtKind::GoSub(label) => {
-            let range;
-            let l;
-            if let Some((r1, l1)) = label {
-              range = r1.clone();
-              l = *l1;
-            } else {
-              range = Range::empty(stmt.range.end);
-              l = Label(0);
-            }
-            add_label_ref!(range, l);
-          }
-          StmtKind::Restore(Some((range, label))) => {
-            let range = range.clone();
-            let label = *label;
-            add_label_ref!(range, label);
-          }
-          StmtKind::On { labels, .. } => {
-            for (range, label) in &labels.0 {
-              let range = range.clone();
-              let l;
-              if let Some(l1) = label {
-                l = *l1;
-              } else {
-                l = Label(0);
-              }
-              add_label_ref!(range, l);
-            }
+      for (range, label) in labels::label_refs(parsed) {
+        let label = label.unwrap_or(Label(0));
+        match label_refs.entry(label) {
+          hash_map::Entry::Vacant(_) => {
+            return Err(RelabelError::LabelNotFound {
+              label: label.0,
+              range: range.offset(line_start),
+            });
           }
-          _ => {
-            // do nothing
+          hash_map::Entry::Occupied(mut refs) => {
+            refs.get_mut().push(range.offset(line_start));
           }
         }
       }
@@ -718,6 +1139,174 @@ impl Document {
     Ok(edits)
   }
 
+  /// Computes the renumbering edits like [`Self::compute_relabel_edits`],
+  /// and collects the numbers in DATA items and string literals which equal
+  /// existing labels. Programs may read line numbers from DATA or build them
+  /// from strings, which renumbering cannot update, so such numbers are
+  /// reported for review.
+  pub fn compute_relabel_report(
+    &mut self,
+    start: u16,
+    inc: u16,
+  ) -> Result<RelabelReport, RelabelError> {
+    let edits = self.compute_relabel_edits(start, inc)?;
+
+    let mut labels = HashMap::default();
+    for i in 0..self.lines.len() {
+      let new_label = start + i as u16 * inc;
+      if let Some((_, Label(label))) = &self.ensure_line_parsed(i).content.label
+      {
+        labels.insert(*label, new_label);
+      }
+    }
+
+    let mut possible_label_refs = vec![];
+    for (i, line) in self.lines.iter().enumerate() {
+      let parsed = line.parsed.as_ref().unwrap();
+      labels::add_possible_label_refs(
+        &mut possible_label_refs,
+        i,
+        line.line_start,
+        self.line_text(i),
+        parsed,
+        &labels,
+      );
+    }
+
+    Ok(RelabelReport {
+      edits,
+      possible_label_refs,
+    })
+  }
+
+  /// Returns the line label at `offset`, or the label referenced by a
+  /// statement at `offset`, with its range.
+  fn label_at(&mut self, offset: usize) -> Option<(Range, Label)> {
+    let i = find_line_by_position(&self.lines, offset);
+    let line_start = self.lines[i].line_start as isize;
+    let parsed = self.ensure_line_parsed(i);
+    parsed
+      .content
+      .label
+      .iter()
+      .cloned()
+      .chain(labels::present_label_refs(parsed))
+      .map(|(range, label)| (range.offset(line_start), label))
+      .find(|(range, _)| (range.start..=range.end).contains(&offset))
+  }
+
+  /// Returns the range of the label of the line which the label at `offset`
+  /// refers to. The label at `offset` may be a line label, or a label
+  /// referenced by a statement, e.g. GOTO. Returns None if there is no label
+  /// at `offset`, or no line has the label.
+  pub fn label_definition_at(&mut self, offset: usize) -> Option<Range> {
+    let (_, label) = self.label_at(offset)?;
+    for i in 0..self.lines.len() {
+      let line_start = self.lines[i].line_start as isize;
+      if let Some((range, l)) = &self.ensure_line_parsed(i).content.label {
+        if *l == label {
+          return Some(range.offset(line_start));
+        }
+      }
+    }
+    None
+  }
+
+  /// Returns the constant address of PEEK, POKE or CALL at `offset`, along
+  /// with the known regions of the machine containing it, for hover tooltips.
+  pub fn addr_info_at(&mut self, offset: usize) -> Option<AddrInfo> {
+    let i = find_line_by_position(&self.lines, offset);
+    let start = self.lines[i].line_start;
+    self.ensure_line_parsed(i);
+    let mut info = addrs::addr_at(
+      &self.text[self.line_range(i)],
+      self.lines[i].parsed.as_ref().unwrap(),
+      &self.machine_props,
+      offset - start,
+    )?;
+    info.range = info.range.offset(start as isize);
+    Some(info)
+  }
+
+  /// Computes the edits which change the label at `offset` to `new_label`,
+  /// along with the label of the line it refers to and all the references
+  /// to that line. The edits are sorted in descending order of positions.
+  pub fn compute_rename_label_edits(
+    &mut self,
+    offset: usize,
+    new_label: u16,
+  ) -> Result<Vec<ReplaceText>, RenameLabelError> {
+    let (_, label) = self.label_at(offset).ok_or(RenameLabelError::NoLabel)?;
+    if new_label > 9999 {
+      return Err(RenameLabelError::InvalidLabel(new_label));
+    }
+    if label.0 == new_label {
+      return Ok(vec![]);
+    }
+
+    let mut edits = vec![];
+    for i in 0..self.lines.len() {
+      let line_start = self.lines[i].line_start as isize;
+      let parsed = self.ensure_line_parsed(i);
+      if let Some((_, Label(l))) = &parsed.content.label {
+        if *l == new_label {
+          return Err(RenameLabelError::LabelExists(new_label));
+        }
+      }
+      for (range, l) in parsed
+        .content
+        .label
+        .iter()
+        .cloned()
+        .chain(labels::present_label_refs(parsed))
+      {
+        if l == label {
+          edits.push(ReplaceText {
+            range: range.offset(line_start),
+            str: new_label.to_string().into(),
+          });
+        }
+      }
+    }
+
+    let line_labels = (0..self.lines.len())
+      .filter_map(|i| {
+        let parsed = self.ensure_line_parsed(i);
+        parsed.content.label.as_ref().map(|(_, Label(l))| *l)
+      })
+      .collect::<Vec<_>>();
+    for (i, &l) in line_labels.iter().enumerate() {
+      if l == label.0 {
+        let prev = i.checked_sub(1).map(|i| line_labels[i]);
+        let next = line_labels.get(i + 1).copied();
+        if prev.is_some_and(|prev| prev > new_label)
+          || next.is_some_and(|next| next < new_label)
+        {
+          return Err(RenameLabelError::NotAscending { prev, next });
+        }
+      }
+    }
+
+    edits.sort_by_key(|edit| !edit.range.start);
+    Ok(edits)
+  }
+
+  /// Returns addresses of instructions compiled from the innermost statement
+  /// at `offset`, in ascending order.
+  pub fn instr_addrs_at(&mut self, offset: usize) -> Vec<usize> {
+    self.diagnostics();
+    let codegen = &self.compile_cache.as_ref().unwrap().codegen;
+    codegen.source_map.stmt_addrs_at(offset)
+  }
+
+  /// Returns the range of the statement which the instruction at `addr` is
+  /// compiled from.
+  pub fn stmt_range_of_instr(&mut self, addr: usize) -> Option<(usize, usize)> {
+    self.diagnostics();
+    let codegen = &self.compile_cache.as_ref().unwrap().codegen;
+    codegen.source_map.stmt_range(addr)
+  }
+
   pub fn create_device<P>(&self, data_dir: P) -> DefaultDevice
   where
     P: Into<PathBuf>,
@@ -733,13 +1322,32 @@ impl Document {
   where
     D: Device,
   {
+    let codegen = self.codegen()?;
+    Ok(VirtualMachine::new(codegen, device))
+  }
+
+  /// Creates a VM owning `device`. If the document contains errors, Err is
+  /// returned along with the device.
+  pub fn create_owned_vm<D>(
+    &mut self,
+    device: D,
+  ) -> Result<VirtualMachine<'static, D>, (ContainsErrors, D)>
+  where
+    D: Device,
+  {
+    match self.codegen() {
+      Ok(codegen) => Ok(VirtualMachine::new_owned(codegen, device)),
+      Err(err) => Err((err, device)),
+    }
+  }
+
+  fn codegen(&mut self) -> Result<CodeGen, ContainsErrors> {
     let diagnostics = self.diagnostics();
     if diagnostics.iter().any(|d| d.contains_errors()) {
       return Err(ContainsErrors);
     }
 
-    let codegen = self.compile_cache.as_ref().unwrap().codegen.clone();
-    Ok(VirtualMachine::new(codegen, device))
+    Ok(self.compile_cache.as_ref().unwrap().codegen.clone())
   }
 }
 
@@ -749,10 +1357,28 @@ impl LineDiagnosis {
   }
 }
 
+fn report_extensions(line: &mut ParseResult<ProgramLine>) {
+  for (_, stmt) in line.stmt_arena.iter() {
+    let name = match stmt.kind {
+      StmtKind::Sound { .. } => "SOUND",
+      StmtKind::OnTimer { .. } => "ON TIMER",
+      StmtKind::Timer { .. } => "TIMER",
+      _ => continue,
+    };
+    line.diagnostics.push(Diagnostic::new_error(
+      stmt.range.clone(),
+      format!("{name} 语句是模拟器的扩展，严格模式下不能使用"),
+    ));
+  }
+}
+
+/// Detects machine name from `{type:NAME}` in the first line, or from the
+/// `#MACHINE` field in the REM header.
 fn detect_machine_props(
   text: impl AsRef<Utf16Str>,
 ) -> Option<((usize, usize), Result<MachineProps, Utf16String>)> {
-  let first_line = text.as_ref().first_line();
+  let text = text.as_ref();
+  let first_line = text.first_line();
   if let Some(start) = first_line.rfind_str(utf16str!("{type:")) {
     let start = start + "{type:".len();
     let first_line = &first_line[start..];
@@ -768,6 +1394,22 @@ fn detect_machine_props(
       }
     }
   }
+
+  if let (
+    ProgramMetadata {
+      machine: Some(name),
+      ..
+    },
+    Some(range),
+  ) = metadata::parse_metadata(text)
+  {
+    let name = Utf16String::from(name);
+    match crate::machine::machines().get(&name) {
+      Some(props) => return Some((range, Ok(props.clone()))),
+      None => return Some((range, Err(name))),
+    }
+  }
+
   None
 }
 
@@ -921,10 +1563,19 @@ mod tests {
   }
 
   fn make_doc(text: &str) -> Document {
+    crate::machine::init_machines_once().unwrap();
     let text = text.replace('\n', "\r\n");
     Document::load(text, false).unwrap()
   }
 
+  fn make_doc_with_extensions(text: &str, extensions: &[&str]) -> Document {
+    let mut doc = make_doc(text);
+    doc.machine_props.dialect = Dialect::default()
+      .with_extensions(extensions.iter().copied())
+      .unwrap();
+    doc
+  }
+
   const INPUT: &str = "\
 abcd
 efg
@@ -1734,6 +2385,74 @@ no";
     );
   }
 
+  #[test]
+  fn fingerprint() {
+    let mut doc1 = make_doc(
+      "10 rem hello\n20 for i=1 to 3:print \"A b\";i:next\n30 if i>2 then 10 \
+       else gosub 40\n40 poke 1000,peek(1001):open \"f\" for binary as 1\n50 \
+       draw 1,2:return",
+    );
+    let mut doc2 = make_doc(
+      "100 REM hello\n110 FOR I=1 TO 3 : PRINT \"A b\"; I : NEXT\n120 IF I>2 \
+       THEN 100 ELSE GOSUB 130\n130 POKE 1000,PEEK(1001):OPEN \"f\" FOR \
+       BINARY AS 1\n140 DRAW 1,2:RETURN",
+    );
+    let mut doc3 = make_doc(
+      "10 rem hello\n20 for i=1 to 3:print \"A B\";i:next\n30 if i>2 then 10 \
+       else gosub 40\n40 poke 1000,peek(1001):open \"f\" for binary as 1\n50 \
+       draw 1,2:return",
+    );
+    let fp1 = doc1.fingerprint();
+    let fp2 = doc2.fingerprint();
+    let fp3 = doc3.fingerprint();
+    assert_eq!(fp1, fp2);
+    assert_ne!(fp1.hash, fp3.hash);
+    assert_eq!(
+      fp1.stats,
+      ProgramStats {
+        line_count: 5,
+        keywords: [
+          "DRAW", "ELSE", "FOR", "GOSUB", "IF", "NEXT", "OPEN", "PEEK", "POKE",
+          "PRINT", "REM", "RETURN", "THEN", "TO",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+        features: RequiredFeatures {
+          files: true,
+          binary_files: true,
+          call: false,
+          peek: true,
+          poke: true,
+          graphics: true,
+          sound: false,
+        },
+      }
+    );
+  }
+
+  #[test]
+  fn load_gwbasic() {
+    crate::machine::init_machines_once().unwrap();
+    let data = b"\xff\x0a\x12\x0a\x00 \x91 \x12\xe9\x13\x00\x00\x00";
+    let mut doc = Document::load(data, true).unwrap();
+    assert_eq!(doc.text().to_string(), "10 PRINT 1+2");
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    let data = b"\xff\x0a\x12\x0a\x00 \xbf \x12\x00\x00\x00";
+    let mut doc = Document::load(data, true).unwrap();
+    assert_eq!(doc.text().to_string(), "10 COLOR 1");
+    assert!(doc.diagnostics()[0].diagnostics.iter().any(|d| d.message
+      == "GVBASIC 不支持 COLOR"
+      && d.range == Range::new(0, 10)));
+
+    doc.apply_edit(Edit {
+      pos: 3,
+      kind: EditKind::Insert(utf16str!("REM ")),
+    });
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+  }
+
   mod add_label {
     use super::*;
     use pretty_assertions::assert_eq;
@@ -2400,6 +3119,301 @@ cls
     );
   }
 
+  #[test]
+  fn label_definition() {
+    let mut doc = make_doc(
+      r#"
+10 goto 30:gosub 20
+20 on a goto 10,,40:return
+30 restore 20
+"#
+      .trim(),
+    );
+    assert_eq!(doc.label_definition_at(8), Some(Range::new(49, 51)));
+    assert_eq!(doc.label_definition_at(18), Some(Range::new(21, 23)));
+    assert_eq!(doc.label_definition_at(21), Some(Range::new(21, 23)));
+    assert_eq!(doc.label_definition_at(34), Some(Range::new(0, 2)));
+    assert_eq!(doc.label_definition_at(39), None);
+    assert_eq!(doc.label_definition_at(4), None);
+  }
+
+  #[test]
+  fn rename_label() {
+    let mut doc = make_doc(
+      r#"
+10 goto 30:gosub 20
+20 on a goto 10,,20:return
+30 restore 20
+"#
+      .trim(),
+    );
+    let edits = doc.compute_rename_label_edits(60, 25).unwrap();
+    assert_eq!(
+      edits,
+      vec![
+        ReplaceText {
+          range: Range::new(60, 62),
+          str: "25".into(),
+        },
+        ReplaceText {
+          range: Range::new(38, 40),
+          str: "25".into(),
+        },
+        ReplaceText {
+          range: Range::new(21, 23),
+          str: "25".into(),
+        },
+        ReplaceText {
+          range: Range::new(17, 19),
+          str: "25".into(),
+        },
+      ]
+    );
+    assert_eq!(doc.compute_rename_label_edits(17, 20), Ok(vec![]));
+    assert_eq!(
+      doc.compute_rename_label_edits(17, 30),
+      Err(RenameLabelError::LabelExists(30))
+    );
+    assert_eq!(
+      doc.compute_rename_label_edits(17, 10000),
+      Err(RenameLabelError::InvalidLabel(10000))
+    );
+    assert_eq!(
+      doc.compute_rename_label_edits(4, 25),
+      Err(RenameLabelError::NoLabel)
+    );
+    assert_eq!(
+      doc.compute_rename_label_edits(17, 35),
+      Err(RenameLabelError::NotAscending {
+        prev: Some(10),
+        next: Some(30)
+      })
+    );
+    assert_eq!(
+      doc.compute_rename_label_edits(0, 25),
+      Err(RenameLabelError::NotAscending {
+        prev: None,
+        next: Some(20)
+      })
+    );
+    assert!(doc.compute_rename_label_edits(0, 5).is_ok());
+  }
+
+  #[test]
+  fn features() {
+    let doc = make_doc("10 end");
+    let features = doc.features();
+    assert!(features.statements.iter().any(|b| b.name == "LOCATE"));
+    assert!(features.keywords.iter().any(|b| b.name == "THEN"));
+    assert!(features.functions.iter().any(|b| b.name == "MID$"));
+    assert!(!features.statements.iter().any(|b| b.name == "MID$"));
+    assert_eq!(
+      features.available,
+      RequiredFeatures {
+        files: true,
+        binary_files: true,
+        call: true,
+        peek: true,
+        poke: true,
+        graphics: true,
+        sound: true,
+      }
+    );
+    assert_eq!(features.secondary_storage_prefix, None);
+    assert!(features.memory_map);
+    assert!(!features.sound_extension);
+
+    let mut doc = make_doc_with_extensions("10 end", &["SOUND"]);
+    assert!(doc.features().sound_extension);
+    doc.set_strict(true);
+    assert!(!doc.features().sound_extension);
+
+    doc.machine_props.dialect =
+      Dialect::without(["fputc", "fread", "fwrite", "fseek", "play"]).unwrap();
+    let features = doc.features();
+    assert!(!features.statements.iter().any(|b| b.name == "PLAY"));
+    assert!(!features.available.binary_files);
+    assert!(features.available.sound);
+
+    let required = RequiredFeatures {
+      files: true,
+      binary_files: true,
+      peek: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      features.missing(&required),
+      RequiredFeatures {
+        binary_files: true,
+        ..Default::default()
+      }
+    );
+    assert!(!features.supports(&required));
+    assert!(features.supports(&RequiredFeatures {
+      binary_files: false,
+      ..required
+    }));
+  }
+
+  fn make_doc_with_memory_map(text: &str) -> Document {
+    use crate::machine::{MemoryKind, MemoryRegion};
+
+    let mut doc = make_doc(text);
+    doc.machine_props.memory_map = vec![
+      MemoryRegion {
+        start: 0,
+        end: 0x7fff,
+        kind: MemoryKind::Ram,
+        name: None,
+      },
+      MemoryRegion {
+        start: 0xc000,
+        end: 0xfffe,
+        kind: MemoryKind::Rom,
+        name: Some("系统 ROM".to_owned()),
+      },
+    ];
+    doc
+  }
+
+  #[test]
+  fn addr_lint() {
+    let mut doc = make_doc_with_memory_map(
+      r#"
+10 poke 704+21,1:poke 40000,0:a=peek(199)+peek(65535)
+20 call -1:call x:poke 60000,1
+"#
+      .trim(),
+    );
+    let diags = doc.diagnostics();
+    assert_eq!(
+      diags[0].diagnostics,
+      vec![Diagnostic::new_warning(
+        Range::new(22, 27),
+        "地址 40000 不在 TC808 机型的内存映射中"
+      )]
+    );
+    assert_eq!(
+      diags[1].diagnostics,
+      vec![
+        Diagnostic::new_warning(
+          Range::new(8, 10),
+          "地址 65535 不在 TC808 机型的内存映射中"
+        ),
+        Diagnostic::new_warning(
+          Range::new(23, 28),
+          "地址 60000 位于 ROM 中，POKE 语句无法修改它的值"
+        ),
+      ]
+    );
+
+    // the memory map is unknown
+    let mut doc = make_doc("10 poke 40000,0");
+    assert_eq!(doc.diagnostics()[0].diagnostics, vec![]);
+  }
+
+  #[test]
+  fn coord_lint() {
+    let mut doc = make_doc(
+      r#"
+10 draw 159,79:draw 160,10:line 0,0,159,80:draw 159.5,79.9
+20 box 0,0,300-1,10:circle 200,40,100:ellipse 10,10,200,200:draw -1,x
+30 circle 170,40,10:circle 200,40,r:ellipse 10,100,5,21:ellipse 10,85,5,4
+"#
+      .trim(),
+    );
+    let diags = doc.diagnostics();
+    assert_eq!(
+      diags[0].diagnostics,
+      vec![
+        Diagnostic::new_warning(
+          Range::new(20, 23),
+          "横坐标 160 超出了 TC808 机型的屏幕范围 0~159，超出屏幕的部分会被裁剪"
+        ),
+        Diagnostic::new_warning(
+          Range::new(40, 42),
+          "纵坐标 80 超出了 TC808 机型的屏幕范围 0~79，超出屏幕的部分会被裁剪"
+        ),
+      ]
+    );
+    assert_eq!(
+      diags[1].diagnostics,
+      vec![
+        Diagnostic::new_warning(
+          Range::new(11, 16),
+          "横坐标 299 超出范围 0~255，运行时会出错"
+        ),
+        Diagnostic::new_warning(
+          Range::new(65, 67),
+          "横坐标 -1 超出范围 0~255，运行时会出错"
+        ),
+      ]
+    );
+    assert_eq!(
+      diags[2].diagnostics,
+      vec![
+        Diagnostic::new_warning(
+          Range::new(10, 13),
+          "横坐标 170 超出了 TC808 机型的屏幕范围 0~159，超出屏幕的部分会被裁剪"
+        ),
+        Diagnostic::new_warning(
+          Range::new(67, 69),
+          "纵坐标 85 超出了 TC808 机型的屏幕范围 0~79，超出屏幕的部分会被裁剪"
+        ),
+      ]
+    );
+
+    let mut doc = make_doc("10 draw 127,0:draw 128,0");
+    doc.machine_props.screen_width = 128;
+    assert_eq!(
+      doc.diagnostics()[0].diagnostics,
+      vec![Diagnostic::new_warning(
+        Range::new(19, 22),
+        "横坐标 128 超出了 TC808 机型的屏幕范围 0~127，超出屏幕的部分会被裁剪"
+      )]
+    );
+  }
+
+  #[test]
+  fn addr_info() {
+    let mut doc = make_doc_with_memory_map(
+      r#"
+10 poke 704+21,1:a=peek(199)
+20 call 40000:call x
+"#
+      .trim(),
+    );
+    assert_eq!(
+      doc.addr_info_at(9),
+      Some(AddrInfo {
+        range: Range::new(8, 14),
+        addr: 725,
+        regions: vec![
+          "文字缓冲区，第 2 行第 2 列".to_owned(),
+          "RAM".to_owned()
+        ],
+      })
+    );
+    assert_eq!(
+      doc.addr_info_at(26),
+      Some(AddrInfo {
+        range: Range::new(24, 27),
+        addr: 199,
+        regions: vec!["按键缓冲区".to_owned(), "RAM".to_owned()],
+      })
+    );
+    assert_eq!(
+      doc.addr_info_at(40),
+      Some(AddrInfo {
+        range: Range::new(38, 43),
+        addr: 40000,
+        regions: vec!["不在内存映射中".to_owned()],
+      })
+    );
+    assert_eq!(doc.addr_info_at(48), None);
+    assert_eq!(doc.addr_info_at(3), None);
+  }
+
   #[test]
   fn relabel() {
     let mut doc = make_doc(
@@ -2428,4 +3442,549 @@ ab
 1140 ::\r
 1160 ".trim_start());
   }
+
+  #[test]
+  fn relabel_report() {
+    let mut doc = make_doc(
+      r#"
+10 data 30, 25 ,"40",abc,"  10 ":read a
+20 a$="30":print "30 ";10;"1.5":goto 40
+30 b$=" 40"+"x":data 3
+40 end
+"#
+      .trim_start(),
+    );
+    let report = doc.compute_relabel_report(100, 10).unwrap();
+    assert_eq!(report.edits, doc.compute_relabel_edits(100, 10).unwrap());
+    assert_eq!(
+      report
+        .possible_label_refs
+        .iter()
+        .map(|r| (r.line, r.range.start, r.label, r.new_label))
+        .collect::<Vec<_>>(),
+      vec![
+        (0, 8, 30, 120),
+        (0, 17, 40, 130),
+        (0, 28, 10, 100),
+        (1, 48, 30, 120),
+        (1, 59, 30, 120),
+        (2, 90, 40, 130),
+      ]
+    );
+    assert_eq!(
+      report.possible_label_refs[0].message(),
+      "可能引用了行号 30，重新编号后为 120，请检查"
+    );
+  }
+
+  #[test]
+  fn canonical_text() {
+    let mut doc = make_doc(
+      r#"
+10 cls :  print"hello",a b ;:rem  keep  THIS
+20 data 1, 2 ,"a:b":goto 10
+30 print 1@2
+40 if a then 20
+"#
+      .trim_start(),
+    );
+    doc.apply_edit(Edit {
+      pos: doc.text.len(),
+      kind: EditKind::Insert(utf16str!("50 end\n")),
+    });
+    assert_eq!(
+      doc.to_canonical_text(),
+      "
+10 CLS:PRINT \"hello\",A B;:REM keep  THIS\r
+20 DATA 1,2 ,\"a:b\":GOTO 10\r
+30 print 1@2\r
+40 IF A THEN 20\r
+50 END\r
+"
+      .trim_start()
+    );
+    assert!(doc.text.as_slice().contains(&(b'@' as u16)));
+    assert!(doc.text().to_string().starts_with("10 cls :  print"));
+  }
+
+  #[test]
+  fn machine_name_from_metadata() {
+    let doc = make_doc(
+      r#"
+10 rem #title foo
+20 rem #machine pc1000a
+30 cls
+"#
+      .trim(),
+    );
+    assert_eq!(doc.machine_name(), "PC1000A");
+    assert_eq!(
+      doc.metadata(),
+      ProgramMetadata {
+        title: Some("foo".to_owned()),
+        author: None,
+        machine: Some("PC1000A".to_owned()),
+      }
+    );
+    assert_eq!(
+      doc.compute_machine_name_edit(utf16str!("nc1020")),
+      Ok(ReplaceText {
+        range: Range::new(35, 42),
+        str: "NC1020".into(),
+      })
+    );
+  }
+
+  #[test]
+  fn source_map() {
+    let mut doc = make_doc(
+      r#"
+10 a=1:if a then print 2:b=3
+20 end
+"#
+      .trim_start(),
+    );
+
+    let addrs = doc.instr_addrs_at(4);
+    assert!(!addrs.is_empty());
+    for addr in addrs {
+      assert_eq!(doc.stmt_range_of_instr(addr), Some((3, 6)));
+    }
+
+    let addrs = doc.instr_addrs_at(19);
+    assert!(!addrs.is_empty());
+    for &addr in &addrs {
+      assert_eq!(doc.stmt_range_of_instr(addr), Some((17, 24)));
+    }
+
+    let if_addrs = doc.instr_addrs_at(7);
+    assert!(if_addrs[0] < addrs[0]);
+    for addr in if_addrs {
+      assert_eq!(doc.stmt_range_of_instr(addr), Some((7, 28)));
+    }
+
+    let addrs = doc.instr_addrs_at(34);
+    assert_eq!(addrs.len(), 1);
+    assert_eq!(doc.stmt_range_of_instr(addrs[0]), Some((33, 36)));
+    assert_eq!(doc.stmt_range_of_instr(addrs[0] + 1), None);
+  }
+
+  #[test]
+  fn strict() {
+    let mut doc = make_doc("10 sound 440,100:if 1 then sound 880,100\n20 beep");
+    assert!(!doc.diagnostics()[0].diagnostics.is_empty());
+
+    let mut doc = make_doc_with_extensions(
+      "10 sound 440,100:if 1 then sound 880,100\n20 beep",
+      &["SOUND"],
+    );
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    doc.set_strict(true);
+    let diagnostics = &doc.diagnostics()[0].diagnostics;
+    assert_eq!(
+      diagnostics
+        .iter()
+        .map(|d| (d.severity, d.range.clone()))
+        .collect::<Vec<_>>(),
+      vec![
+        (crate::Severity::Error, Range::new(3, 16)),
+        (crate::Severity::Error, Range::new(27, 40)),
+      ]
+    );
+    let mut device = doc.create_device("");
+    assert!(doc.create_vm(&mut device).is_err());
+
+    doc.set_strict(false);
+    assert!(doc.diagnostics()[0].diagnostics.is_empty());
+  }
+
+  #[test]
+  fn timer_extension() {
+    let mut doc = make_doc("10 timer=1:print timer");
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    let mut doc = make_doc("10 on timer(10) gosub 20:timer on\n20 return");
+    assert!(!doc.diagnostics()[0].diagnostics.is_empty());
+    let mut doc = make_doc_with_extensions(
+      "10 on timer(10) gosub 20:timer on\n20 return",
+      &["TIMER"],
+    );
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    doc.set_strict(true);
+    assert_eq!(
+      doc.diagnostics()[0]
+        .diagnostics
+        .iter()
+        .map(|d| (d.range.clone(), d.message.as_str()))
+        .collect::<Vec<_>>(),
+      vec![
+        (
+          Range::new(3, 24),
+          "ON TIMER 语句是模拟器的扩展，严格模式下不能使用"
+        ),
+        (
+          Range::new(25, 33),
+          "TIMER 语句是模拟器的扩展，严格模式下不能使用"
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn never_assigned_var_lint() {
+    let mut doc = make_doc(
+      r#"
+10 input a:read b$:for i=1 to 3:c=c+1:next
+20 def fn f(x)=x+y:print a;b$;nmae$;fn f(i)
+30 swap d,e:print d;x
+40 data 1
+      "#
+      .trim(),
+    );
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    doc.set_never_assigned_var_lint(Some(Severity::Warning));
+    let diagnostics = doc
+      .diagnostics()
+      .iter()
+      .enumerate()
+      .flat_map(|(i, line)| {
+        line.diagnostics.iter().map(move |d| (i, d.clone()))
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      diagnostics,
+      vec![
+        (
+          1,
+          Diagnostic::new_warning(
+            Range::new(17, 18),
+            "变量 Y 从未被赋值，它的值总是0"
+          )
+        ),
+        (
+          1,
+          Diagnostic::new_warning(
+            Range::new(30, 35),
+            "变量 NMAE$ 从未被赋值，它的值总是空字符串"
+          )
+        ),
+        (
+          2,
+          Diagnostic::new_warning(
+            Range::new(20, 21),
+            "变量 X 从未被赋值，它的值总是0"
+          )
+        ),
+      ]
+    );
+
+    doc.set_never_assigned_var_lint(Some(Severity::Error));
+    let mut device = doc.create_device("");
+    assert!(doc.create_vm(&mut device).is_err());
+
+    doc.set_never_assigned_var_lint(None);
+    assert!(doc.diagnostics().iter().all(|d| d.diagnostics.is_empty()));
+
+    let mut doc = make_doc("10 print z:z=1");
+    doc.set_never_assigned_var_lint(Some(Severity::Warning));
+    assert!(doc.diagnostics()[0].diagnostics.is_empty());
+  }
+
+  #[test]
+  fn storage_namespace() {
+    let mut a = make_doc("10 open \"A\" for output as 1\n20 close 1");
+    let mut b = make_doc("100 OPEN \"A\"   FOR OUTPUT AS 1\n110 CLOSE 1");
+    let mut c = make_doc("10 open \"B\" for output as 1\n20 close 1");
+    let ns = a.storage_namespace();
+    assert_eq!(ns.len(), 16);
+    assert_eq!(b.storage_namespace(), ns);
+    assert_ne!(c.storage_namespace(), ns);
+  }
+
+  #[test]
+  fn file_references() {
+    let mut doc = make_doc(
+      r#"10 open "a" for input as 1:open "b"+"c.dat" for output as 2
+20 open ("d"+"e") for append as 3
+30 input a$:open a$+"x" for input as 1
+40 open "scores.DAT" for input as 4"#,
+    );
+    let refs = doc
+      .file_references()
+      .into_iter()
+      .map(|r| (r.line, r.range.start, r.mode, r.name))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      refs,
+      vec![
+        (0, 8, FileOpenMode::Input, Some("a.DAT".to_owned())),
+        (0, 32, FileOpenMode::Output, Some("bc.dat".to_owned())),
+        (1, 9, FileOpenMode::Append, Some("de.DAT".to_owned())),
+        (2, 17, FileOpenMode::Input, None),
+        (3, 8, FileOpenMode::Input, Some("scores.DAT".to_owned())),
+      ]
+    );
+
+    let dir = std::env::temp_dir()
+      .join(format!("gvb_missing_files_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("scores.DAT"), b"").unwrap();
+    let missing = doc
+      .missing_files(&dir)
+      .into_iter()
+      .map(|r| (r.line, r.name))
+      .collect::<Vec<_>>();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(missing, vec![(0, Some("a.DAT".to_owned())), (2, None)]);
+  }
+
+  #[test]
+  fn data_blocks() {
+    let mut doc = make_doc(
+      r#"10 data 24,60,126,255
+20 DATA 24, 24 ,24,24:data 1
+30 print "x"
+40 data 1,2,"3"
+50 data 256
+60 data 0,0,0,0,0,0,0,128:rem sprite
+70 data"#,
+    );
+    let blocks = doc.data_blocks();
+    assert_eq!(
+      blocks,
+      vec![
+        DataBlock {
+          start_line: 0,
+          end_line: 1,
+          bytes: vec![24, 60, 126, 255, 24, 24, 24, 24, 1],
+        },
+        DataBlock {
+          start_line: 5,
+          end_line: 5,
+          bytes: vec![0, 0, 0, 0, 0, 0, 0, 128],
+        },
+      ]
+    );
+    let tiles = blocks[0].tiles(crate::tile::TileSize::Size8);
+    assert_eq!(tiles.len(), 1);
+    assert_eq!(tiles[0].data, vec![24, 60, 126, 255, 24, 24, 24, 24]);
+  }
+
+  #[test]
+  fn full_width_data_fixes() {
+    crate::machine::init_machines_once().unwrap();
+    let mut doc = Document::from_text(Utf16String::from(
+      "10 data １２，－３．５,\"１２\",ＡＢ,１２Ａ\r\n\
+      20 print \"１\":data ４　５ ，６,１ｅ２\r\n\
+      30 data ９，Ｘ:rem ９",
+    ));
+    let fixes = doc
+      .compute_full_width_data_fixes()
+      .into_iter()
+      .map(|f| (f.line, f.range.start, f.from, f.to))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      fixes,
+      vec![
+        (0, 8, '１', '1'),
+        (0, 9, '２', '2'),
+        (0, 10, '，', ','),
+        (0, 11, '－', '-'),
+        (0, 12, '３', '3'),
+        (0, 13, '．', '.'),
+        (0, 14, '５', '5'),
+        (1, 47, '４', '4'),
+        (1, 48, '\u{3000}', ' '),
+        (1, 49, '５', '5'),
+        (1, 51, '，', ','),
+        (1, 52, '６', '6'),
+        (1, 54, '１', '1'),
+        (1, 55, 'ｅ', 'e'),
+        (1, 56, '２', '2'),
+      ]
+    );
+  }
+
+  #[test]
+  fn merge_parts() {
+    crate::machine::init_machines_once().unwrap();
+
+    let doc = Document::merge_parts(vec![
+      "10 cls\n30 print 3\n20 print 2\n",
+      "\n  30 print 3\r\n40 end  \r\n",
+    ])
+    .unwrap();
+    assert_eq!(
+      doc.text().to_string(),
+      "10 cls\r\n20 print 2\r\n  30 print 3\r\n40 end"
+    );
+
+    let conflict = match Document::merge_parts(vec![
+      "10 cls\n20 print 2\n30 goto 20",
+      "20 print \"two\"\nprint\n",
+      "30 end",
+    ]) {
+      Err(conflict) => conflict,
+      Ok(_) => panic!("no conflict"),
+    };
+    assert_eq!(
+      conflict.issues,
+      vec![
+        MergeIssue::Overridden {
+          number: 20,
+          part: 1,
+          replaced_part: 0
+        },
+        MergeIssue::MissingLineNumber { part: 1, line: 1 },
+        MergeIssue::Overridden {
+          number: 30,
+          part: 2,
+          replaced_part: 0
+        },
+      ]
+    );
+    assert_eq!(
+      conflict.document.text().to_string(),
+      "10 cls\r\n20 print \"two\"\r\n30 end"
+    );
+  }
+
+  #[test]
+  fn diagnostic_phases() {
+    let mut doc = make_doc("10 a$=1:print @+(");
+    let diagnostics = doc.diagnostics()[0]
+      .diagnostics
+      .iter()
+      .map(|d| (d.range.start, d.range.end, d.phase))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      diagnostics,
+      vec![
+        (3, 7, DiagnosticPhase::Compile),
+        (14, 15, DiagnosticPhase::Lex),
+        (16, 17, DiagnosticPhase::Parse),
+        (17, 17, DiagnosticPhase::Parse),
+      ]
+    );
+  }
+
+  #[test]
+  fn diagnostics_update() {
+    let mut doc = make_doc("10 print 1\n20 print 2\n30 print 3");
+    let update = doc.diagnostics_update().unwrap();
+    assert_eq!(
+      (update.start, update.removed, update.lines.len()),
+      (0, 0, 3)
+    );
+    assert!(doc.diagnostics_update().is_none());
+    doc.reset_diagnostics_update();
+    let update = doc.diagnostics_update().unwrap();
+    assert_eq!(
+      (update.start, update.removed, update.lines.len()),
+      (0, 0, 3)
+    );
+
+    // insert a line with errors
+    let pos = doc.lines[1].line_start;
+    doc.apply_edit(Edit {
+      pos,
+      kind: EditKind::Insert(utf16str!("15 print (\r\n")),
+    });
+    let update = doc.diagnostics_update().unwrap();
+    assert_eq!(
+      (update.start, update.removed, update.lines.len()),
+      (1, 0, 1)
+    );
+    assert!(!update.lines[0].diagnostics.is_empty());
+
+    // lines shifted are not reported, even if they have diagnostics
+    doc.apply_edit(Edit {
+      pos: 0,
+      kind: EditKind::Insert(utf16str!("5 beep\r\n")),
+    });
+    let update = doc.diagnostics_update().unwrap();
+    assert_eq!(
+      (
+        update.start,
+        update.removed,
+        update.lines.len(),
+        update.shift
+      ),
+      (1, 0, 1, 8)
+    );
+    assert!(update.lines[0].diagnostics.is_empty());
+
+    doc.apply_edit(Edit {
+      pos: doc.lines[2].line_start + 10,
+      kind: EditKind::Insert(utf16str!("1)")),
+    });
+    let update = doc.diagnostics_update().unwrap();
+    assert_eq!(
+      (
+        update.start,
+        update.removed,
+        update.lines.len(),
+        update.shift
+      ),
+      (2, 1, 1, 2)
+    );
+    assert!(update.lines[0].diagnostics.is_empty());
+  }
+
+  #[test]
+  fn diagnostics_update_stress() {
+    let mut text = String::new();
+    for i in 0..5000 {
+      text += &format!("{} a{}=a{}+1:print a{};\n", i + 1, i % 100, i % 7, i);
+    }
+    let mut doc = make_doc(text.trim_end());
+    // diagnostics with absolute ranges, as shown by the editor
+    let absolute = |line: &LineDiagnosis| {
+      line
+        .diagnostics
+        .iter()
+        .map(|d| (line.line_start + d.range.start, d.message.clone()))
+        .collect::<Vec<_>>()
+    };
+    let mut mirror = vec![];
+
+    let mut seed = 12345u32;
+    let mut rand = |n: usize| {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      (seed >> 8) as usize % n
+    };
+    for i in 0..60 {
+      if i > 0 {
+        let line = &doc.lines[rand(doc.lines.len())];
+        let pos = line.line_start + 2 + rand(4);
+        let edit = match rand(3) {
+          0 => EditKind::Insert(utf16str!("(")),
+          1 => EditKind::Insert(utf16str!(":")),
+          _ => EditKind::Delete(1),
+        };
+        doc.apply_edit(Edit { pos, kind: edit });
+      }
+      if let Some(update) = doc.diagnostics_update() {
+        if i > 0 {
+          // only the edited line is reported
+          assert_eq!(update.lines.len(), update.removed);
+          assert!(update.removed <= 1, "edit {}", i);
+        }
+        mirror.splice(
+          update.start..update.start + update.removed,
+          update.lines.iter().map(absolute),
+        );
+        for line in &mut mirror[update.start + update.lines.len()..] {
+          for (pos, _) in line {
+            *pos = (*pos as isize + update.shift) as usize;
+          }
+        }
+      }
+      let expected = doc.diagnostics().iter().map(absolute).collect::<Vec<_>>();
+      assert!(mirror == expected, "edit {}", i);
+    }
+  }
 }
@@ -0,0 +1,214 @@
+use crate::ast::{
+  BinaryOpKind, ExprId, ExprKind, ProgramLine, Range, StmtKind, SysFuncKind,
+  UnaryOpKind,
+};
+use crate::device::default::{screen, TEXT_COLUMNS, TEXT_ROWS};
+use crate::machine::{AddrProp, MachineProps, MemoryKind};
+use crate::parser::ParseResult;
+use crate::util::mbf5::Mbf5;
+use crate::vm::POLICY_PEEK_ADDR;
+use crate::Diagnostic;
+use widestring::Utf16Str;
+
+/// A constant address used by PEEK, POKE or CALL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrInfo {
+  /// Range of the address expression.
+  pub range: Range,
+  pub addr: u16,
+  /// Descriptions of the known regions containing the address, e.g. the
+  /// screen buffer.
+  pub regions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddrUse {
+  Peek,
+  Poke,
+  Call,
+}
+
+/// Returns the address expressions of PEEK, POKE and CALL in a line.
+fn addr_exprs(parsed: &ParseResult<ProgramLine>) -> Vec<(AddrUse, ExprId)> {
+  let mut exprs = vec![];
+  for (_, stmt) in &parsed.stmt_arena {
+    match &stmt.kind {
+      StmtKind::Poke { addr, .. } => exprs.push((AddrUse::Poke, *addr)),
+      StmtKind::Call(addr) => exprs.push((AddrUse::Call, *addr)),
+      _ => {}
+    }
+  }
+  for (_, expr) in &parsed.expr_arena {
+    if let ExprKind::SysFuncCall {
+      func: (_, SysFuncKind::Peek),
+      args,
+    } = &expr.kind
+    {
+      exprs.push((AddrUse::Peek, args[0]));
+    }
+  }
+  exprs
+}
+
+/// Evaluates a numeric expression consisting of literals and arithmetic
+/// operators. `text` is the text of the line.
+pub(super) fn eval_const<T>(
+  text: &Utf16Str,
+  parsed: &ParseResult<T>,
+  expr: ExprId,
+) -> Option<f64> {
+  let expr = &parsed.expr_arena[expr];
+  match &expr.kind {
+    ExprKind::NumberLit => {
+      let mut text = text[expr.range.range()].to_string();
+      text.retain(|c| c != ' ');
+      text.parse::<Mbf5>().ok().map(f64::from)
+    }
+    ExprKind::Unary {
+      op: (_, UnaryOpKind::Neg),
+      arg,
+    } => eval_const(text, parsed, *arg).map(|n| -n),
+    ExprKind::Unary {
+      op: (_, UnaryOpKind::Pos),
+      arg,
+    } => eval_const(text, parsed, *arg),
+    ExprKind::Binary { lhs, op, rhs } => {
+      let lhs = eval_const(text, parsed, *lhs)?;
+      let rhs = eval_const(text, parsed, *rhs)?;
+      let value = match op.1 {
+        BinaryOpKind::Add => lhs + rhs,
+        BinaryOpKind::Sub => lhs - rhs,
+        BinaryOpKind::Mul => lhs * rhs,
+        BinaryOpKind::Div if rhs != 0.0 => lhs / rhs,
+        BinaryOpKind::Pow => lhs.powf(rhs),
+        _ => return None,
+      };
+      Some(value).filter(|n| n.is_finite())
+    }
+    _ => None,
+  }
+}
+
+/// Converts a number to an address as PEEK, POKE and CALL do. Returns None if
+/// the number is out of range, which is reported at runtime.
+fn to_addr(n: f64) -> Option<u16> {
+  if n <= -65536.0 || n >= 65536.0 {
+    None
+  } else {
+    Some(n as i32 as u16)
+  }
+}
+
+/// Reports constant addresses of PEEK, POKE and CALL which are not in the
+/// memory map of the machine, and POKE into ROM. Nothing is reported if the
+/// memory map is unknown.
+pub(super) fn lint_addrs(
+  text: &Utf16Str,
+  line: &mut ParseResult<ProgramLine>,
+  props: &MachineProps,
+) {
+  if props.memory_map.is_empty() {
+    return;
+  }
+  for (addr_use, expr) in addr_exprs(line) {
+    let Some(addr) = eval_const(text, line, expr).and_then(to_addr) else {
+      continue;
+    };
+    if addr_use == AddrUse::Peek && addr == POLICY_PEEK_ADDR {
+      continue;
+    }
+    let range = line.expr_arena[expr].range.clone();
+    match props.memory_region(addr) {
+      None => line.diagnostics.push(Diagnostic::new_warning(
+        range,
+        format!("地址 {addr} 不在 {} 机型的内存映射中", props.name),
+      )),
+      Some(region)
+        if region.kind == MemoryKind::Rom && addr_use == AddrUse::Poke =>
+      {
+        line.diagnostics.push(Diagnostic::new_warning(
+          range,
+          format!("地址 {addr} 位于 ROM 中，POKE 语句无法修改它的值"),
+        ))
+      }
+      Some(_) => {}
+    }
+  }
+}
+
+/// Returns the constant address of PEEK, POKE or CALL at `offset`, which is
+/// relative to the line.
+pub(super) fn addr_at(
+  text: &Utf16Str,
+  line: &ParseResult<ProgramLine>,
+  props: &MachineProps,
+  offset: usize,
+) -> Option<AddrInfo> {
+  addr_exprs(line).into_iter().find_map(|(_, expr)| {
+    let range = &line.expr_arena[expr].range;
+    if !(range.start..=range.end).contains(&offset) {
+      return None;
+    }
+    let addr = eval_const(text, line, expr).and_then(to_addr)?;
+    Some(AddrInfo {
+      range: range.clone(),
+      addr,
+      regions: describe_addr(props, addr),
+    })
+  })
+}
+
+fn describe_addr(props: &MachineProps, addr: u16) -> Vec<String> {
+  let mut regions = vec![];
+  let offset = addr.wrapping_sub(props.graphics_base_addr) as usize;
+  if offset < screen::BYTES {
+    regions.push(format!(
+      "屏幕缓冲区，第 {} 行像素的第 {} 个字节",
+      offset / screen::WIDTH_IN_BYTE + 1,
+      offset % screen::WIDTH_IN_BYTE + 1
+    ));
+  }
+  let offset = addr.wrapping_sub(props.text_buffer_base_addr) as usize;
+  if offset < TEXT_COLUMNS * TEXT_ROWS {
+    regions.push(format!(
+      "文字缓冲区，第 {} 行第 {} 列",
+      offset / TEXT_COLUMNS + 1,
+      offset % TEXT_COLUMNS + 1
+    ));
+  }
+  if addr == props.key_buffer_addr {
+    regions.push("按键缓冲区".to_owned());
+  }
+  if props.key_mapping_addrs.contains(&addr) {
+    regions.push("键盘矩阵".to_owned());
+  }
+  if let Some(prop) = props.addrs.get(addr as _) {
+    let prop = match prop {
+      AddrProp::Year => "年份-1881",
+      AddrProp::Month => "月份，0~11",
+      AddrProp::Day => "日期，0~30",
+      AddrProp::WeekDay => "星期，0~6，0 为星期日",
+      AddrProp::Hour => "小时，0~23",
+      AddrProp::Minute => "分钟，0~59",
+      AddrProp::HalfSecond => "半秒，0~119",
+      AddrProp::SecondMult2 => "秒*2，0~118",
+    };
+    regions.push(format!("时钟：{prop}"));
+  }
+  if !props.memory_map.is_empty() {
+    match props.memory_region(addr) {
+      Some(region) => {
+        let kind = match region.kind {
+          MemoryKind::Ram => "RAM",
+          MemoryKind::Rom => "ROM",
+        };
+        match &region.name {
+          Some(name) => regions.push(format!("{kind}：{name}")),
+          None => regions.push(kind.to_owned()),
+        }
+      }
+      None => regions.push("不在内存映射中".to_owned()),
+    }
+  }
+  regions
+}
@@ -0,0 +1,98 @@
+use crate::ast::{ExprId, ProgramLine, StmtKind};
+use crate::machine::MachineProps;
+use crate::parser::ParseResult;
+use crate::Diagnostic;
+use widestring::Utf16Str;
+
+use super::addrs::eval_const;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+  X,
+  Y,
+}
+
+/// A coordinate expression of a graphics statement.
+struct Coord {
+  axis: Axis,
+  expr: ExprId,
+  /// The radius along the axis, if the coordinate is the center of a circle
+  /// or an ellipse.
+  radius: Option<ExprId>,
+}
+
+/// Returns the coordinate expressions of DRAW, LINE, BOX, CIRCLE and ELLIPSE
+/// in a line. Radii are not checked themselves, since circles may be partly
+/// outside the screen.
+fn coord_exprs(parsed: &ParseResult<ProgramLine>) -> Vec<Coord> {
+  let mut exprs = vec![];
+  for (_, stmt) in &parsed.stmt_arena {
+    let (args, len, radii) = match &stmt.kind {
+      StmtKind::Draw(args) => (args.as_slice(), 2, None),
+      StmtKind::Circle(args) => {
+        (args.as_slice(), 2, args.get(2).map(|&r| [r, r]))
+      }
+      StmtKind::Ellipse(args) => {
+        (args.as_slice(), 2, args.get(2..4).map(|r| [r[0], r[1]]))
+      }
+      StmtKind::Line(args) | StmtKind::Box(args) => (args.as_slice(), 4, None),
+      _ => continue,
+    };
+    for (i, &arg) in args.iter().take(len).enumerate() {
+      let axis = if i % 2 == 0 { Axis::X } else { Axis::Y };
+      exprs.push(Coord {
+        axis,
+        expr: arg,
+        radius: radii.map(|radii| radii[i % 2]),
+      });
+    }
+  }
+  exprs
+}
+
+/// Reports constant coordinates of graphics statements which are outside
+/// the screen. Coordinates outside 0~255 cause runtime errors, and the
+/// others are clipped by the device, see [`Device::draw_line`]. The center
+/// of a circle or an ellipse is reported only if the whole shape is known
+/// to be outside the screen.
+///
+/// [`Device::draw_line`]: crate::device::Device::draw_line
+pub(super) fn lint_coords(
+  text: &Utf16Str,
+  line: &mut ParseResult<ProgramLine>,
+  props: &MachineProps,
+) {
+  for coord in coord_exprs(line) {
+    let Some(n) = eval_const(text, line, coord.expr) else {
+      continue;
+    };
+    let (name, size) = match coord.axis {
+      Axis::X => ("横坐标", props.screen_width),
+      Axis::Y => ("纵坐标", props.screen_height),
+    };
+    // the nearest point of the shape to the screen
+    let near = match coord.radius {
+      Some(radius) => match eval_const(text, line, radius) {
+        Some(radius) => n - radius.abs(),
+        None => 0.0,
+      },
+      None => n,
+    };
+    let range = line.expr_arena[coord.expr].range.clone();
+    if n <= -1.0 || n >= 256.0 {
+      line.diagnostics.push(Diagnostic::new_warning(
+        range,
+        format!("{name} {n} 超出范围 0~255，运行时会出错"),
+      ));
+    } else if near >= size as f64 {
+      line.diagnostics.push(Diagnostic::new_warning(
+        range,
+        format!(
+          "{name} {n} 超出了 {} 机型的屏幕范围 0~{}，超出屏幕的部分会被裁剪",
+          props.name,
+          size - 1
+        ),
+      ));
+    }
+  }
+}
@@ -0,0 +1,46 @@
+use crate::ast::{ProgramLine, StmtKind};
+use crate::parser::ParseResult;
+use crate::tile::{self, Bitmap, TileSize};
+use widestring::Utf16Str;
+
+/// Consecutive lines of DATA statements whose items are all bytes, i.e.
+/// unquoted integers in 0~255, e.g. graphics assets embedded in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataBlock {
+  /// Index of the first line.
+  pub start_line: usize,
+  /// Index of the last line, inclusive.
+  pub end_line: usize,
+  pub bytes: Vec<u8>,
+}
+
+impl DataBlock {
+  /// Decodes the bytes as tiles. See [`tile::decode_tiles`].
+  pub fn tiles(&self, size: TileSize) -> Vec<Bitmap> {
+    tile::decode_tiles(&self.bytes, size)
+  }
+}
+
+/// Returns the bytes of the DATA statements of a line, or None if the line
+/// has no DATA statements or any item is not a byte.
+pub(super) fn data_bytes(
+  text: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+) -> Option<Vec<u8>> {
+  let mut bytes = vec![];
+  let mut has_data = false;
+  for &stmt in &parsed.content.stmts {
+    if let StmtKind::Data(data) = &parsed.stmt_arena[stmt].kind {
+      has_data = true;
+      for datum in data.iter() {
+        if datum.is_quoted {
+          return None;
+        }
+        let mut text = text[datum.range.range()].to_string();
+        text.retain(|c| c != ' ');
+        bytes.push(text.parse::<u8>().ok()?);
+      }
+    }
+  }
+  has_data.then_some(bytes)
+}
@@ -0,0 +1,101 @@
+use super::RequiredFeatures;
+use crate::builtin::{Builtin, BuiltinKind};
+use crate::machine::MachineProps;
+
+/// Features supported by the dialect and the machine profile of a document,
+/// e.g. for greying out unavailable snippets in editors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Features {
+  /// Statements and commands, sorted by name.
+  pub statements: Vec<&'static Builtin>,
+  /// Keywords which are part of statements or expressions, e.g. `THEN`,
+  /// sorted by name.
+  pub keywords: Vec<&'static Builtin>,
+  /// System functions, sorted by name.
+  pub functions: Vec<&'static Builtin>,
+  /// The categories of [`RequiredFeatures`] which are available. A category
+  /// is available if any of its statements or functions is.
+  pub available: RequiredFeatures,
+  /// Whether the text mode screen can be switched to the 12x12 font, with 26
+  /// columns and 6 rows.
+  pub small_font: bool,
+  /// Prefix of the names of files on the secondary storage, if any.
+  pub secondary_storage_prefix: Option<String>,
+  /// Whether the memory map of the machine is known, so that constant
+  /// addresses of PEEK, POKE and CALL are checked.
+  pub memory_map: bool,
+  /// Whether `POKE 199,155` quits the program like pressing the quit key.
+  pub key_buffer_quit: bool,
+  /// Whether the SOUND statement, an extension of the simulator, is allowed.
+  /// It is disallowed in strict mode.
+  pub sound_extension: bool,
+}
+
+impl Features {
+  /// Returns the features required by a program which are not available,
+  /// e.g. for categorizing programs which cannot run on the machine.
+  pub fn missing(&self, required: &RequiredFeatures) -> RequiredFeatures {
+    let available = &self.available;
+    RequiredFeatures {
+      files: required.files && !available.files,
+      binary_files: required.binary_files && !available.binary_files,
+      call: required.call && !available.call,
+      peek: required.peek && !available.peek,
+      poke: required.poke && !available.poke,
+      graphics: required.graphics && !available.graphics,
+      sound: required.sound && !available.sound,
+    }
+  }
+
+  /// Returns whether all features required by a program are available.
+  pub fn supports(&self, required: &RequiredFeatures) -> bool {
+    self.missing(required) == RequiredFeatures::default()
+  }
+
+  fn has(&self, name: &str) -> bool {
+    self
+      .statements
+      .iter()
+      .chain(&self.functions)
+      .any(|b| b.name == name)
+  }
+}
+
+pub(super) fn features(props: &MachineProps, strict: bool) -> Features {
+  let mut features = Features {
+    statements: vec![],
+    keywords: vec![],
+    functions: vec![],
+    available: RequiredFeatures::default(),
+    small_font: props.small_font,
+    secondary_storage_prefix: props
+      .secondary_storage
+      .as_ref()
+      .map(|storage| storage.prefix.clone()),
+    memory_map: !props.memory_map.is_empty(),
+    key_buffer_quit: props.key_buffer_quit,
+    sound_extension: false,
+  };
+  for builtin in props.dialect.builtins() {
+    match builtin.kind {
+      BuiltinKind::Statement { .. } => features.statements.push(builtin),
+      BuiltinKind::Keyword => features.keywords.push(builtin),
+      BuiltinKind::Function(_) => features.functions.push(builtin),
+    }
+  }
+
+  let has_any = |names: &[&str]| names.iter().any(|name| features.has(name));
+  let available = RequiredFeatures {
+    files: has_any(&["OPEN"]),
+    binary_files: has_any(&["OPEN"])
+      && has_any(&["FPUTC", "FREAD", "FWRITE", "FSEEK"]),
+    call: has_any(&["CALL"]),
+    peek: has_any(&["PEEK"]),
+    poke: has_any(&["POKE"]),
+    graphics: has_any(&["BOX", "CIRCLE", "DRAW", "ELLIPSE", "LINE", "POINT"]),
+    sound: has_any(&["BEEP", "PLAY", "SOUND"]),
+  };
+  features.available = available;
+  features.sound_extension = !strict && features.has("SOUND");
+  features
+}
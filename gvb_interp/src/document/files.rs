@@ -0,0 +1,98 @@
+use widestring::Utf16Str;
+
+use crate::ast::{
+  BinaryOpKind, ExprId, ExprKind, FileMode, ProgramLine, Range, StmtKind,
+};
+use crate::parser::ParseResult;
+
+/// A file opened by an OPEN statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReference {
+  pub line: usize,
+  /// Range of the file name expression in the line.
+  pub range: Range,
+  pub mode: FileOpenMode,
+  /// Name of the file, with `.DAT` appended as the VM does, or None if the
+  /// name is computed at runtime. Names made of string literals joined by `+`
+  /// are resolved.
+  pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOpenMode {
+  Input,
+  Output,
+  Append,
+  Random,
+  Binary,
+}
+
+impl FileReference {
+  /// Returns true if the file must exist before the program runs, i.e. it is
+  /// opened for INPUT. Files opened in other modes are created if missing.
+  pub fn is_required(&self) -> bool {
+    self.mode == FileOpenMode::Input
+  }
+}
+
+/// Collects the files opened by the OPEN statements of a line. `line` does
+/// not include newline.
+pub(super) fn add_file_references(
+  refs: &mut Vec<FileReference>,
+  index: usize,
+  line: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+) {
+  for (_, stmt) in &parsed.stmt_arena {
+    if let StmtKind::Open { filename, mode, .. } = &stmt.kind {
+      let mode = match mode {
+        FileMode::Input => FileOpenMode::Input,
+        FileMode::Output => FileOpenMode::Output,
+        FileMode::Append => FileOpenMode::Append,
+        FileMode::Random => FileOpenMode::Random,
+        FileMode::Binary => FileOpenMode::Binary,
+        FileMode::Error => continue,
+      };
+      let name = resolve_name(line, parsed, *filename).map(|mut name| {
+        if !name.to_ascii_uppercase().ends_with(".DAT") {
+          name.push_str(".DAT");
+        }
+        name
+      });
+      refs.push(FileReference {
+        line: index,
+        range: parsed.expr_arena[*filename].range.clone(),
+        mode,
+        name,
+      });
+    }
+  }
+}
+
+fn resolve_name(
+  line: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+  expr: ExprId,
+) -> Option<String> {
+  let expr = &parsed.expr_arena[expr];
+  match &expr.kind {
+    ExprKind::StringLit => {
+      let mut text =
+        line.get(expr.range.start + 1..expr.range.end)?.to_string();
+      if text.ends_with('"') {
+        text.pop();
+      }
+      Some(text)
+    }
+    ExprKind::Binary {
+      lhs,
+      op: (_, BinaryOpKind::Add),
+      rhs,
+    } => {
+      let mut name = resolve_name(line, parsed, *lhs)?;
+      name.push_str(&resolve_name(line, parsed, *rhs)?);
+      Some(name)
+    }
+    _ => None,
+  }
+}
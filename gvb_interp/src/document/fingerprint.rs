@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+use widestring::{Utf16Str, Utf16String};
+
+use super::labels::present_label_refs;
+use crate::ast::{
+  ExprKind, FileMode, ProgramLine, Range, StmtKind, SysFuncKind, TokenKind,
+};
+use crate::dialect::Dialect;
+use crate::parser::canonical::render_line;
+use crate::parser::ParseResult;
+use crate::util::ascii_ext::AsciiExt;
+use crate::HashMap;
+
+/// Fingerprint of a program, for finding variants of the same program and
+/// indexing programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+  /// Hash of the normalized program. Spaces and letter case outside string
+  /// literals are ignored, and line numbers are replaced by line indices, so
+  /// that renumbered programs have the same hash.
+  pub hash: u64,
+  pub stats: ProgramStats,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramStats {
+  pub line_count: usize,
+  /// Keywords and system functions used in the program, in alphabetical
+  /// order.
+  pub keywords: Vec<String>,
+  pub features: RequiredFeatures,
+}
+
+/// Machine features required by a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequiredFeatures {
+  pub files: bool,
+  /// Files opened in BINARY mode, or FPUTC/FREAD/FWRITE/FSEEK statements.
+  pub binary_files: bool,
+  pub call: bool,
+  pub peek: bool,
+  pub poke: bool,
+  pub graphics: bool,
+  pub sound: bool,
+}
+
+pub(super) struct FingerprintBuilder {
+  hash: u64,
+  keywords: BTreeSet<String>,
+  stats: ProgramStats,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl FingerprintBuilder {
+  pub fn new() -> Self {
+    Self {
+      hash: FNV_OFFSET_BASIS,
+      keywords: BTreeSet::new(),
+      stats: ProgramStats::default(),
+    }
+  }
+
+  /// `labels` maps line numbers to line indices. `line` does not include
+  /// newline.
+  pub fn add_line(
+    &mut self,
+    index: usize,
+    line: &Utf16Str,
+    parsed: &ParseResult<ProgramLine>,
+    labels: &HashMap<u16, usize>,
+    dialect: &Dialect,
+  ) {
+    let line = replace_labels(index, line, parsed, labels);
+    let (text, tokens) = render_line(&line, dialect).unwrap_or((line, vec![]));
+
+    let mut in_string = false;
+    for c in text.as_slice() {
+      if *c == b'"' as u16 {
+        in_string = !in_string;
+      } else if !in_string && *c == b' ' as u16 {
+        continue;
+      }
+      let c = if in_string {
+        *c
+      } else {
+        c.to_ascii_uppercase()
+      };
+      self.write(c);
+    }
+    self.write(b'\n' as u16);
+
+    for token in tokens {
+      match token {
+        TokenKind::Keyword(kw) => self.keywords.insert(format!("{kw:?}")),
+        TokenKind::SysFunc(f) => self.keywords.insert(format!("{f:?}")),
+        _ => continue,
+      };
+    }
+
+    self.add_features(parsed);
+    self.stats.line_count += 1;
+  }
+
+  fn add_features(&mut self, parsed: &ParseResult<ProgramLine>) {
+    let features = &mut self.stats.features;
+    for (_, stmt) in &parsed.stmt_arena {
+      match &stmt.kind {
+        StmtKind::Open { mode, .. } => {
+          features.files = true;
+          features.binary_files |= *mode == FileMode::Binary;
+        }
+        StmtKind::Fputc { .. }
+        | StmtKind::Fread { .. }
+        | StmtKind::Fwrite { .. }
+        | StmtKind::Fseek { .. } => {
+          features.files = true;
+          features.binary_files = true;
+        }
+        StmtKind::Call(_) => features.call = true,
+        StmtKind::Poke { .. } => features.poke = true,
+        StmtKind::Box(_)
+        | StmtKind::Circle(_)
+        | StmtKind::Draw(_)
+        | StmtKind::Ellipse(_)
+        | StmtKind::Line(_) => features.graphics = true,
+        StmtKind::Beep | StmtKind::Play(_) | StmtKind::Sound { .. } => {
+          features.sound = true
+        }
+        _ => {}
+      }
+    }
+    for (_, expr) in &parsed.expr_arena {
+      if let ExprKind::SysFuncCall { func: (_, f), .. } = &expr.kind {
+        match f {
+          SysFuncKind::Peek => features.peek = true,
+          SysFuncKind::Point => features.graphics = true,
+          _ => {}
+        }
+      }
+    }
+  }
+
+  fn write(&mut self, c: u16) {
+    for b in c.to_le_bytes() {
+      self.hash ^= b as u64;
+      self.hash = self.hash.wrapping_mul(FNV_PRIME);
+    }
+  }
+
+  pub fn finish(mut self) -> Fingerprint {
+    self.stats.keywords = self.keywords.into_iter().collect();
+    Fingerprint {
+      hash: self.hash,
+      stats: self.stats,
+    }
+  }
+}
+
+/// Replaces the line number and label references in `line` with line
+/// indices. References to nonexistent lines are kept as they are.
+fn replace_labels(
+  index: usize,
+  line: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+  labels: &HashMap<u16, usize>,
+) -> Utf16String {
+  let mut replacements: Vec<(Range, usize)> = vec![];
+  if let Some((range, _)) = &parsed.content.label {
+    replacements.push((range.clone(), index));
+  }
+  for (range, label) in present_label_refs(parsed) {
+    if let Some(&i) = labels.get(&label.0) {
+      if !range.is_empty() {
+        replacements.push((range, i));
+      }
+    }
+  }
+  replacements.sort_by_key(|(range, _)| range.start);
+
+  let mut text = Utf16String::new();
+  let mut last = 0;
+  for (range, i) in replacements {
+    text.push_utfstr(&line[last..range.start]);
+    text.push_str(&i.to_string());
+    last = range.end;
+  }
+  text.push_utfstr(&line[last..]);
+  text
+}
@@ -0,0 +1,468 @@
+use super::binary::{LoadError, KEYWORD_TO_BYTE};
+use std::fmt::Write;
+use widestring::Utf16String;
+
+include!(concat!(env!("OUT_DIR"), "/gwbasic_keyword.rs"));
+
+/// Keywords whose syntax in GW-BASIC is different from GVBASIC.
+const SYNTAX_DIFFERS: &[&str] = &["LINE", "CIRCLE", "DRAW", "PLAY", "SOUND"];
+
+pub struct GwBasicDocument {
+  pub text: Utf16String,
+  pub warnings: Vec<ImportWarning>,
+}
+
+/// A statement or expression which cannot be translated to GVBASIC. The
+/// original text is kept in the imported document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportWarning {
+  /// 0-based
+  pub line: usize,
+  pub message: String,
+}
+
+/// Returns whether `content` looks like a GW-BASIC tokenized file, which
+/// starts with 0xFF, or 0xFE if the file is protected.
+pub fn is_gwbasic(content: impl AsRef<[u8]>) -> bool {
+  matches!(content.as_ref().first(), Some(0xff | 0xfe))
+}
+
+/// Detokenizes a GW-BASIC tokenized file and translates it to GVBASIC where
+/// possible.
+pub fn load_gwbasic(
+  content: impl AsRef<[u8]>,
+) -> Result<GwBasicDocument, LoadError<usize>> {
+  let content = content.as_ref();
+  match content.first() {
+    Some(0xff) => {}
+    Some(0xfe) => {
+      return Err(LoadError {
+        location: 0,
+        message: format!("不支持加密的 GW-BASIC 文件"),
+      })
+    }
+    Some(&b) => {
+      return Err(LoadError {
+        location: 0,
+        message: format!("文件损坏：expected 0xFF, found 0x{b:02X}"),
+      })
+    }
+    None => {
+      return Err(LoadError {
+        location: 0,
+        message: format!("文件损坏：unexpected EOF"),
+      })
+    }
+  }
+
+  let mut reader = LineReader {
+    content,
+    offset: 1,
+    line: 0,
+    out: String::new(),
+    warnings: vec![],
+    plain_alnum: false,
+  };
+  let mut text = Utf16String::new();
+
+  loop {
+    let next_line_addr = reader.read_u16()?;
+    if next_line_addr == 0 {
+      break;
+    }
+    if reader.line > 0 {
+      text.push_str("\r\n");
+    }
+    reader.read_line()?;
+    text.push_str(&reader.out);
+    reader.line += 1;
+  }
+
+  Ok(GwBasicDocument {
+    text,
+    warnings: reader.warnings,
+  })
+}
+
+struct LineReader<'a> {
+  content: &'a [u8],
+  offset: usize,
+  /// 0-based index of the line being read.
+  line: usize,
+  out: String,
+  warnings: Vec<ImportWarning>,
+  /// Whether the last character written is a letter or digit of a variable
+  /// name, rather than part of a keyword.
+  plain_alnum: bool,
+}
+
+impl<'a> LineReader<'a> {
+  fn read_line(&mut self) -> Result<(), LoadError<usize>> {
+    self.out.clear();
+    let label = self.read_u16()?;
+    if label > 9999 {
+      self.warn(format!("行号 {label} 超出了 GVBASIC 的范围（0~9999）"));
+    }
+    write!(&mut self.out, "{label} ").unwrap();
+    // GW-BASIC stores the space after line number as part of the line.
+    if self.peek() == Some(b' ') {
+      self.offset += 1;
+    }
+    let body_start = self.out.len();
+
+    loop {
+      let b = self.read_u8()?;
+      match b {
+        0 => return Ok(()),
+        b'"' => {
+          self.out.push('"');
+          self.read_literal(|b| b == b'"', true);
+        }
+        b':' if self.peek_bytes(2) == Some(&[0x8f, 0xd9]) => {
+          // `'` is stored as `:REM'`.
+          self.offset += 2;
+          if self.out.len() > body_start {
+            self.out.push(':');
+          }
+          self.out.push_str("REM");
+          self.read_literal(|_| false, false);
+        }
+        b':' if self.peek() == Some(0xa1) => {
+          // ELSE is stored as `:ELSE`.
+        }
+        // Octal and hexadecimal constants, which are signed 16-bit integers,
+        // e.g. &HFFFF is -1. GVBASIC only supports decimal constants.
+        // Negative numbers are parenthesized so that they can follow any
+        // operator.
+        0x0b | 0x0c => {
+          let n = self.read_u16()? as i16;
+          if n < 0 {
+            write!(&mut self.out, "({n})").unwrap();
+          } else {
+            write!(&mut self.out, "{n}").unwrap();
+          }
+        }
+        // Line numbers.
+        0x0e => {
+          let n = self.read_u16()?;
+          write!(&mut self.out, "{n}").unwrap();
+        }
+        0x0d => {
+          return Err(LoadError {
+            location: self.offset - 1,
+            message: format!("文件损坏：unexpected line pointer"),
+          })
+        }
+        0x0f => {
+          let n = self.read_u8()?;
+          write!(&mut self.out, "{n}").unwrap();
+        }
+        0x11..=0x1b => {
+          write!(&mut self.out, "{}", b - 0x11).unwrap();
+        }
+        0x1c => {
+          let n = self.read_u16()? as i16;
+          write!(&mut self.out, "{n}").unwrap();
+        }
+        0x1d => {
+          let bytes = self.read_bytes(4)?;
+          write_real(&mut self.out, mbf_to_f64(bytes));
+        }
+        0x1f => {
+          let bytes = self.read_bytes(8)?;
+          write_real(&mut self.out, mbf_to_f64(bytes));
+        }
+        0x80..=0xff => {
+          let token = if b >= 0xfd {
+            ((b as u16) << 8) + self.read_u8()? as u16
+          } else {
+            b as u16
+          };
+          let kw = match GWBASIC_TOKEN_TO_KEYWORD.get(&token) {
+            Some(&kw) => kw,
+            None => {
+              return Err(LoadError {
+                location: self.offset - 1,
+                message: format!("文件损坏：unrecognized token 0x{token:02x}"),
+              })
+            }
+          };
+          self.write_keyword(kw);
+          self.plain_alnum = false;
+          match kw {
+            "REM" | "'" => self.read_literal(|_| false, false),
+            "DATA" => self.read_data(),
+            // WHILE is stored as `WHILE+`.
+            "WHILE" if self.peek() == Some(0xe9) => self.offset += 1,
+            _ => {}
+          }
+          continue;
+        }
+        b'!' | b'#' if self.plain_alnum => {
+          self.warn(format!("GVBASIC 不支持 ! 和 # 类型后缀"));
+          self.out.push(b as char);
+        }
+        0x20..=0x7f => self.out.push(b as char),
+        _ => {
+          return Err(LoadError {
+            location: self.offset - 1,
+            message: format!("文件损坏：unrecognized bytecode 0x{b:02x}"),
+          })
+        }
+      }
+      self.plain_alnum = b.is_ascii_alphanumeric();
+    }
+  }
+
+  fn write_keyword(&mut self, kw: &str) {
+    let translated = match kw {
+      "'" => "REM",
+      "CVI" => "CVI$",
+      "CVS" => "CVS$",
+      kw => kw,
+    };
+    let name = translated.trim_end_matches('(');
+    if SYNTAX_DIFFERS.contains(&name) {
+      self.warn(format!("{name} 语句的语法与 GVBASIC 不同，需要手动修改"));
+    } else if !KEYWORD_TO_BYTE.contains_key(name) && name != "POINT" {
+      self.warn(format!("GVBASIC 不支持 {name}"));
+    }
+    self.out.push_str(translated);
+  }
+
+  /// Copies bytes until `end` returns true or the end of line. The byte for
+  /// which `end` returns true is also copied if `include_end` is true.
+  fn read_literal(&mut self, end: impl Fn(u8) -> bool, include_end: bool) {
+    while let Some(b) = self.peek() {
+      if b == 0 {
+        break;
+      }
+      if end(b) {
+        if include_end {
+          self.out.push(b as char);
+          self.offset += 1;
+        }
+        break;
+      }
+      if b >= 0x80 {
+        self.warn(format!("无法转换字符 0x{b:02X}"));
+        self.out.push('?');
+      } else {
+        self.out.push(b as char);
+      }
+      self.offset += 1;
+    }
+  }
+
+  /// Data items are not tokenized until the next colon.
+  fn read_data(&mut self) {
+    while let Some(b) = self.peek() {
+      if b == b'"' {
+        self.out.push('"');
+        self.offset += 1;
+        self.read_literal(|b| b == b'"', true);
+      } else {
+        self.read_literal(|b| b == b'"' || b == b':', false);
+        if self.peek() != Some(b'"') {
+          break;
+        }
+      }
+    }
+  }
+
+  fn warn(&mut self, message: String) {
+    let line = self.line;
+    if !self
+      .warnings
+      .iter()
+      .any(|w| w.line == line && w.message == message)
+    {
+      self.warnings.push(ImportWarning { line, message });
+    }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.content.get(self.offset).copied()
+  }
+
+  fn peek_bytes(&self, len: usize) -> Option<&'a [u8]> {
+    self.content.get(self.offset..self.offset + len)
+  }
+
+  fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError<usize>> {
+    match self.peek_bytes(len) {
+      Some(bytes) => {
+        self.offset += len;
+        Ok(bytes)
+      }
+      None => Err(LoadError {
+        location: self.content.len(),
+        message: format!("文件损坏：unexpected EOF"),
+      }),
+    }
+  }
+
+  fn read_u8(&mut self) -> Result<u8, LoadError<usize>> {
+    Ok(self.read_bytes(1)?[0])
+  }
+
+  fn read_u16(&mut self) -> Result<u16, LoadError<usize>> {
+    let bytes = self.read_bytes(2)?;
+    Ok(bytes[0] as u16 + ((bytes[1] as u16) << 8))
+  }
+}
+
+/// Converts a 4-byte or 8-byte Microsoft Binary Format number to f64.
+fn mbf_to_f64(bytes: &[u8]) -> f64 {
+  let (&exp, mantissa) = bytes.split_last().unwrap();
+  if exp == 0 {
+    return 0.0;
+  }
+  let mut m = 0u64;
+  for &b in mantissa.iter().rev() {
+    m = (m << 8) + b as u64;
+  }
+  let bits = mantissa.len() as i32 * 8;
+  let sign_bit = 1 << (bits - 1);
+  let negative = m & sign_bit != 0;
+  let m = (m | sign_bit) as f64;
+  let num = m * 2f64.powi(exp as i32 - 128 - bits);
+  if negative {
+    -num
+  } else {
+    num
+  }
+}
+
+fn write_real(out: &mut String, num: f64) {
+  // GVBASIC reals have about 9 significant digits.
+  let num = format!("{num:.8e}").parse::<f64>().unwrap();
+  if num != 0.0 && (num.abs() >= 1e10 || num.abs() < 1e-4) {
+    write!(out, "{num:E}").unwrap();
+  } else {
+    write!(out, "{num}").unwrap();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  fn line(label: u16, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x01, 0x12];
+    bytes.extend_from_slice(&label.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes.push(0);
+    bytes
+  }
+
+  fn load(lines: &[Vec<u8>]) -> GwBasicDocument {
+    let mut bytes = vec![0xff];
+    for l in lines {
+      bytes.extend_from_slice(l);
+    }
+    bytes.extend_from_slice(&[0, 0, 0x1a]);
+    load_gwbasic(bytes).unwrap()
+  }
+
+  #[test]
+  fn detokenize() {
+    let doc = load(&[
+      // 10 PRINT "HI";A ' COMMENT
+      line(10, b" \x91 \"HI\";A :\x8f\xd9 COMMENT"),
+      // 20 IF A>1 THEN GOTO 10 ELSE END
+      line(20, b" \x8b A\xe6\x12 \xcd \x89 \x0e\x0a\x00 \x3a\xa1 \x81"),
+      // 30 A=&H7000+1.5:B=100
+      line(
+        30,
+        b" A\xe7\x0c\x00\x70\xe9\x1d\x00\x00\x40\x81:B\xe7\x0f\x64",
+      ),
+      // 40 DATA 1,"A:B",2:WHILE A:WEND
+      line(40, b" \x84 1,\"A:B\",2:\xb1\xe9 A:\xb2"),
+      // 50 X=CVI(A$)+LEFT$(B$,1)
+      line(50, b" X\xe7\xfd\x81(A$)\xe9\xff\x81(B$,\x12)"),
+    ]);
+    assert_eq!(
+      doc.text.to_string(),
+      "10 PRINT \"HI\";A :REM COMMENT\r\n\
+       20 IF A>1 THEN GOTO 10 ELSE END\r\n\
+       30 A=28672+1.5:B=100\r\n\
+       40 DATA 1,\"A:B\",2:WHILE A:WEND\r\n\
+       50 X=CVI$(A$)+LEFT$(B$,1)"
+    );
+    assert_eq!(doc.warnings, vec![]);
+  }
+
+  #[test]
+  fn signed_constants() {
+    let doc = load(&[
+      // 10 A=&HFFFF:B=2^&O177777:C=&H7FFF
+      line(
+        10,
+        b" A\xe7\x0c\xff\xff:B\xe7\x13\xed\x0b\xff\xff:C\xe7\x0c\xff\x7f",
+      ),
+    ]);
+    assert_eq!(doc.text.to_string(), "10 A=(-1):B=2^(-1):C=32767");
+    assert_eq!(doc.warnings, vec![]);
+  }
+
+  #[test]
+  fn untranslatable() {
+    let doc = load(&[
+      // 10 COLOR 1:A#=B MOD 2
+      line(10, b" \xbf \x12:A#\xe7B \xf3 \x13"),
+      // 20 LINE (0,0)-(1,1)
+      line(20, b" \xb0 (\x11,\x11)\xea(\x12,\x12)"),
+      // 65535 REM
+      line(65535, b" \x8f"),
+    ]);
+    assert_eq!(
+      doc.text.to_string(),
+      "10 COLOR 1:A#=B MOD 2\r\n20 LINE (0,0)-(1,1)\r\n65535 REM"
+    );
+    assert_eq!(
+      doc.warnings,
+      vec![
+        ImportWarning {
+          line: 0,
+          message: format!("GVBASIC 不支持 COLOR"),
+        },
+        ImportWarning {
+          line: 0,
+          message: format!("GVBASIC 不支持 ! 和 # 类型后缀"),
+        },
+        ImportWarning {
+          line: 0,
+          message: format!("GVBASIC 不支持 MOD"),
+        },
+        ImportWarning {
+          line: 1,
+          message: format!("LINE 语句的语法与 GVBASIC 不同，需要手动修改"),
+        },
+        ImportWarning {
+          line: 2,
+          message: format!("行号 65535 超出了 GVBASIC 的范围（0~9999）"),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn protected() {
+    assert_eq!(
+      load_gwbasic([0xfe, 0x12, 0x34]).err(),
+      Some(LoadError {
+        location: 0,
+        message: format!("不支持加密的 GW-BASIC 文件"),
+      })
+    );
+  }
+
+  #[test]
+  fn mbf() {
+    assert_eq!(mbf_to_f64(&[0, 0, 0x40, 0x81]), 1.5);
+    assert_eq!(mbf_to_f64(&[0, 0, 0xc0, 0x81]), -1.5);
+    assert_eq!(mbf_to_f64(&[0, 0, 0, 0, 0, 0, 0, 0x84]), 8.0);
+  }
+}
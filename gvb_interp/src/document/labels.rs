@@ -0,0 +1,154 @@
+use crate::ast::{ExprKind, Label, ProgramLine, Range, StmtKind};
+use crate::parser::ParseResult;
+use crate::HashMap;
+use widestring::Utf16Str;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameLabelError {
+  /// There is no line label or label reference at the position.
+  NoLabel,
+  /// The new label is greater than 9999.
+  InvalidLabel(u16),
+  /// Another line already has the new label.
+  LabelExists(u16),
+  /// The new label of the line is not greater than the label of the
+  /// previous line, or not less than the label of the next line.
+  NotAscending {
+    prev: Option<u16>,
+    next: Option<u16>,
+  },
+}
+
+/// Returns the labels referenced by the statements of a line, e.g. GOTO and
+/// RESTORE statements, with ranges relative to the line. Omitted labels of
+/// GOTO, GOSUB, ON ... GOTO and ON TIMER are None, and those of GOTO, GOSUB
+/// and ON TIMER have an empty range at the end of the statement. RESTORE
+/// without a label is skipped.
+pub(super) fn label_refs(
+  parsed: &ParseResult<ProgramLine>,
+) -> Vec<(Range, Option<Label>)> {
+  let mut refs = vec![];
+  for (_, stmt) in &parsed.stmt_arena {
+    match &stmt.kind {
+      StmtKind::GoTo { label, .. }
+      | StmtKind::GoSub(label)
+      | StmtKind::OnTimer { label, .. } => match label {
+        Some((range, label)) => refs.push((range.clone(), Some(*label))),
+        None => refs.push((Range::empty(stmt.range.end), None)),
+      },
+      StmtKind::Restore(Some((range, label))) => {
+        refs.push((range.clone(), Some(*label)));
+      }
+      StmtKind::On { labels, .. } => {
+        for (range, label) in &labels.0 {
+          refs.push((range.clone(), *label));
+        }
+      }
+      _ => {}
+    }
+  }
+  refs
+}
+
+/// Like [`label_refs`], but omitted labels are skipped.
+pub(super) fn present_label_refs(
+  parsed: &ParseResult<ProgramLine>,
+) -> impl Iterator<Item = (Range, Label)> {
+  label_refs(parsed)
+    .into_iter()
+    .filter_map(|(range, label)| Some((range, label?)))
+}
+
+/// A number in a DATA item or a string literal which equals the label of a
+/// line, e.g. `DATA 100` read by a program building GOTO targets from data.
+/// Renumbering does not change such numbers, so they may need to be fixed
+/// manually.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PossibleLabelRef {
+  /// Index of the line.
+  pub line: usize,
+  /// Range of the number in the document.
+  pub range: Range,
+  pub label: u16,
+  /// The label of the line after renumbering.
+  pub new_label: u16,
+}
+
+impl PossibleLabelRef {
+  pub fn message(&self) -> String {
+    format!(
+      "可能引用了行号 {}，重新编号后为 {}，请检查",
+      self.label, self.new_label
+    )
+  }
+}
+
+/// Collects the numbers in the DATA items and string literals of a line
+/// which equal a key of `labels`, the map from old labels to new labels.
+/// Spaces around the numbers are ignored. `line` does not include newline,
+/// and starts at `line_start` in the document.
+pub(super) fn add_possible_label_refs(
+  refs: &mut Vec<PossibleLabelRef>,
+  index: usize,
+  line_start: usize,
+  line: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+  labels: &HashMap<u16, u16>,
+) {
+  let mut ranges = vec![];
+  for &stmt in &parsed.content.stmts {
+    if let StmtKind::Data(data) = &parsed.stmt_arena[stmt].kind {
+      for datum in data.iter() {
+        if datum.is_quoted {
+          ranges.push(string_content(line, &datum.range));
+        } else {
+          ranges.push(datum.range.clone());
+        }
+      }
+    }
+  }
+  for (_, expr) in &parsed.expr_arena {
+    if let ExprKind::StringLit = expr.kind {
+      ranges.push(string_content(line, &expr.range));
+    }
+  }
+  ranges.sort_by_key(|range| range.start);
+
+  for range in ranges {
+    let Some(range) = trim_spaces(line, range) else {
+      continue;
+    };
+    let text = &line[range.range()];
+    if text.len() > 4 || !text.chars().all(|c| c.is_ascii_digit()) {
+      continue;
+    }
+    let Ok(label) = text.to_string().parse::<u16>() else {
+      continue;
+    };
+    if let Some(&new_label) = labels.get(&label) {
+      refs.push(PossibleLabelRef {
+        line: index,
+        range: range.offset(line_start as isize),
+        label,
+        new_label,
+      });
+    }
+  }
+}
+
+/// Returns the range of a quoted string without quotes. The closing quote
+/// may be missing.
+fn string_content(line: &Utf16Str, range: &Range) -> Range {
+  let mut end = range.end;
+  if end > range.start + 1 && line.as_slice()[end - 1] == b'"' as u16 {
+    end -= 1;
+  }
+  Range::new(range.start + 1, end)
+}
+
+fn trim_spaces(line: &Utf16Str, range: Range) -> Option<Range> {
+  let text = &line.as_slice()[range.range()];
+  let start = text.iter().position(|&c| c != b' ' as u16)?;
+  let end = text.iter().rposition(|&c| c != b' ' as u16)? + 1;
+  Some(Range::new(range.start + start, range.start + end))
+}
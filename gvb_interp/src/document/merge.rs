@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+
+use super::Document;
+
+/// Problems found by [`Document::merge_parts`], along with the merged
+/// document, which may be accepted anyway.
+pub struct MergeConflict {
+  pub issues: Vec<MergeIssue>,
+  pub document: Box<Document>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeIssue {
+  /// Line `number` of part `part` replaces a different line of the same
+  /// number in the earlier part `replaced_part`. Parts are 0-based.
+  Overridden {
+    number: u32,
+    part: usize,
+    replaced_part: usize,
+  },
+  /// Line `line` of part `part` does not start with a line number, and is
+  /// dropped. Both are 0-based.
+  MissingLineNumber { part: usize, line: usize },
+}
+
+impl Debug for MergeConflict {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_struct("MergeConflict")
+      .field("issues", &self.issues)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Merges lines of `parts` by line numbers. Blank lines are ignored.
+pub(super) fn merge_parts(parts: &[&str]) -> (String, Vec<MergeIssue>) {
+  let mut lines = BTreeMap::new();
+  let mut issues = vec![];
+  for (part, text) in parts.iter().enumerate() {
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim_end();
+      let content = line.trim_start();
+      if content.is_empty() {
+        continue;
+      }
+      let digits = content.bytes().take_while(|c| c.is_ascii_digit()).count();
+      let number = match content[..digits].parse::<u32>() {
+        Ok(number) => number,
+        Err(_) => {
+          issues.push(MergeIssue::MissingLineNumber { part, line: i });
+          continue;
+        }
+      };
+      if let Some((replaced_part, old)) = lines.insert(number, (part, line)) {
+        if old.trim_start() != content {
+          issues.push(MergeIssue::Overridden {
+            number,
+            part,
+            replaced_part,
+          });
+        }
+      }
+    }
+  }
+
+  let text = lines
+    .into_values()
+    .map(|(_, line)| line)
+    .collect::<Vec<_>>()
+    .join("\r\n");
+  (text, issues)
+}
@@ -0,0 +1,172 @@
+use crate::ast::StmtKind;
+use crate::diagnostic::contains_errors;
+use crate::parser::parse_line;
+use crate::util::utf16str_ext::Utf16StrExt;
+use widestring::Utf16Str;
+
+/// Metadata declared in the REM header of a program, i.e. the leading lines
+/// which only contain REM statements:
+///
+/// ```text
+/// 10 REM #TITLE 猜数字
+/// 20 REM #AUTHOR 张三
+/// 30 REM #MACHINE NC1020
+/// ```
+///
+/// Field names are case-insensitive. If a field occurs more than once, the
+/// first one is used.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramMetadata {
+  pub title: Option<String>,
+  pub author: Option<String>,
+  /// Upper-cased machine name.
+  pub machine: Option<String>,
+}
+
+/// Returns the metadata and the range of machine name in `text`.
+pub(super) fn parse_metadata(
+  text: &Utf16Str,
+) -> (ProgramMetadata, Option<(usize, usize)>) {
+  let mut metadata = ProgramMetadata::default();
+  let mut machine_range = None;
+  let mut line_start = 0;
+
+  'lines: while line_start < text.len() {
+    let line_end = text[line_start..]
+      .find_char('\n')
+      .map_or(text.len(), |i| line_start + i + 1);
+    let line = &text[line_start..line_end];
+    let (parsed, _) = parse_line(line);
+    if contains_errors(&parsed.diagnostics) {
+      break;
+    }
+
+    for &stmt in parsed.content.stmts.iter() {
+      let range = match &parsed.stmt_arena[stmt].kind {
+        StmtKind::Rem(range) => range.clone(),
+        _ => break 'lines,
+      };
+
+      let content = &line[range.range()];
+      let units = content.as_slice();
+      let is_space = |c: &&u16| **c == b' ' as u16;
+      let mut i = units.iter().take_while(is_space).count();
+      if units.get(i) != Some(&(b'#' as u16)) {
+        continue;
+      }
+      i += 1;
+      let name_start = i;
+      i += units[i..].iter().take_while(|c| !is_space(c)).count();
+      let name = content[name_start..i].to_string().to_ascii_uppercase();
+      i += units[i..].iter().take_while(is_space).count();
+      let value_end =
+        units.len() - units[i..].iter().rev().take_while(is_space).count();
+      if i == value_end {
+        continue;
+      }
+      let mut value = content[i..value_end].to_string();
+      if name == "MACHINE" {
+        value.make_ascii_uppercase();
+      }
+
+      let field = match name.as_str() {
+        "TITLE" => &mut metadata.title,
+        "AUTHOR" => &mut metadata.author,
+        "MACHINE" => {
+          if metadata.machine.is_none() {
+            let start = line_start + range.start;
+            machine_range = Some((start + i, start + value_end));
+          }
+          &mut metadata.machine
+        }
+        _ => continue,
+      };
+      if field.is_none() {
+        *field = Some(value);
+      }
+    }
+
+    line_start = line_end;
+  }
+
+  (metadata, machine_range)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use widestring::Utf16String;
+
+  fn parse(text: &str) -> (ProgramMetadata, Option<(usize, usize)>) {
+    parse_metadata(&Utf16String::from(text.replace('\n', "\r\n")))
+  }
+
+  #[test]
+  fn header() {
+    assert_eq!(
+      parse(
+        r#"
+10 rem #title  猜数字 
+20 REM #Author 张三
+30 rem #machine nc1020
+40 REM 注释
+50 rem #machine tc808
+60 rem #title foo
+"#
+        .trim_start()
+      ),
+      (
+        ProgramMetadata {
+          title: Some("猜数字".to_owned()),
+          author: Some("张三".to_owned()),
+          machine: Some("NC1020".to_owned()),
+        },
+        Some((56, 62))
+      )
+    );
+  }
+
+  #[test]
+  fn header_ends_at_code() {
+    assert_eq!(
+      parse(
+        r#"
+10 rem #title foo
+20 cls:rem #author bar
+30 rem #machine nc1020
+"#
+        .trim_start()
+      ),
+      (
+        ProgramMetadata {
+          title: Some("foo".to_owned()),
+          ..Default::default()
+        },
+        None
+      )
+    );
+  }
+
+  #[test]
+  fn empty_value() {
+    assert_eq!(
+      parse(
+        r#"
+10 rem #title  
+20 rem #
+30 rem #foo bar
+40 rem#author x
+"#
+        .trim_start()
+      ),
+      (
+        ProgramMetadata {
+          author: Some("x".to_owned()),
+          ..Default::default()
+        },
+        None
+      )
+    );
+  }
+}
@@ -0,0 +1,86 @@
+use crate::ast::{ProgramLine, Range, StmtKind};
+use crate::parser::ParseResult;
+use crate::util::mbf5::Mbf5;
+use widestring::Utf16Str;
+
+/// A full-width character in a numeric DATA item, which is replaced by its
+/// half-width counterpart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullWidthFix {
+  /// Index of the line.
+  pub line: usize,
+  /// Range of the character in the document.
+  pub range: Range,
+  pub from: char,
+  pub to: char,
+}
+
+fn half_width(c: char) -> Option<char> {
+  match c {
+    '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32),
+    '，' => Some(','),
+    '．' => Some('.'),
+    '＋' => Some('+'),
+    '－' => Some('-'),
+    'Ｅ' => Some('E'),
+    'ｅ' => Some('e'),
+    '\u{3000}' => Some(' '),
+    _ => None,
+  }
+}
+
+/// Returns true if `part` is a number after converting full-width
+/// characters and removing spaces.
+fn is_numeric(part: &[char]) -> bool {
+  let mut text = String::new();
+  for &c in part {
+    match half_width(c).unwrap_or(c) {
+      ' ' => {}
+      c => text.push(c),
+    }
+  }
+  text.chars().any(|c| c.is_ascii_digit()) && text.parse::<Mbf5>().is_ok()
+}
+
+/// Collects the full-width characters of the unquoted DATA items of a line
+/// which become numbers when converted to half-width. A full-width comma
+/// splits an item into several numbers. Items which are not numbers as a
+/// whole, e.g. `１Ａ` or `１，Ａ`, are left unchanged, as are quoted items.
+/// `line` does not include newline, and starts at `line_start` in the
+/// document.
+pub(super) fn add_full_width_fixes(
+  fixes: &mut Vec<FullWidthFix>,
+  index: usize,
+  line_start: usize,
+  line: &Utf16Str,
+  parsed: &ParseResult<ProgramLine>,
+) {
+  for &stmt in &parsed.content.stmts {
+    let StmtKind::Data(data) = &parsed.stmt_arena[stmt].kind else {
+      continue;
+    };
+    for datum in data.iter().filter(|datum| !datum.is_quoted) {
+      let chars: Vec<(usize, char)> = line[datum.range.range()]
+        .char_indices()
+        .map(|(i, c)| (datum.range.start + i, c))
+        .collect();
+      if chars.iter().all(|&(_, c)| half_width(c).is_none()) {
+        continue;
+      }
+      let text: Vec<char> = chars.iter().map(|&(_, c)| c).collect();
+      if !text.split(|&c| c == '，').all(is_numeric) {
+        continue;
+      }
+      for (i, c) in chars {
+        if let Some(to) = half_width(c) {
+          fixes.push(FullWidthFix {
+            line: index,
+            range: Range::new(line_start + i, line_start + i + c.len_utf16()),
+            from: c,
+            to,
+          });
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,238 @@
+//! Golden screen fixtures, for end-to-end regression tests of the VM.
+//!
+//! A fixture is a program (`.bas` or `.txt`) together with a golden file of
+//! the same name with the `.screen` extension, which holds the screen of the
+//! default device after the program is run, rendered as text. The program
+//! must not wait for keyboard input. Files opened by the program are in the
+//! directory of the program.
+//!
+//! Fixtures are checked by
+//! [`assert_program_screen!`](crate::assert_program_screen), with paths
+//! relative to the manifest directory of the calling crate:
+//!
+//! ```ignore
+//! #[test]
+//! fn snake() {
+//!   gvb_interp::assert_program_screen!("test/fixtures/screens/snake.txt");
+//! }
+//! ```
+//!
+//! Golden files are created or overwritten when the environment variable
+//! `GVB_UPDATE_FIXTURES` is set to `1`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::device::default::{screen, DefaultDevice};
+use crate::{Document, ExecInput, ExecResult, Severity};
+
+/// The default number of instructions a fixture may execute.
+pub const DEFAULT_MAX_STEPS: usize = 10_000_000;
+
+const UPDATE_ENV: &str = "GVB_UPDATE_FIXTURES";
+
+/// Runs the program at `path` and compares the rendered screen with the
+/// golden file. Returns an error message describing the first difference.
+///
+/// Machine profiles are initialized on the first call if they have not been.
+pub fn check_program_screen<P>(path: P, max_steps: usize) -> Result<(), String>
+where
+  P: AsRef<Path>,
+{
+  let path = path.as_ref();
+  let actual = render_program_screen(path, max_steps)?;
+  let golden_path = path.with_extension("screen");
+
+  if std::env::var(UPDATE_ENV).is_ok_and(|v| v == "1") {
+    return fs::write(&golden_path, &actual).map_err(|err| {
+      format!("failed to write {}: {}", golden_path.display(), err)
+    });
+  }
+
+  let expected = fs::read_to_string(&golden_path).map_err(|err| {
+    format!(
+      "failed to read {}: {}. Set {}=1 to create it",
+      golden_path.display(),
+      err,
+      UPDATE_ENV
+    )
+  })?;
+  let expected = expected.replace("\r\n", "\n");
+  let mut expected_lines = expected.trim_end().lines();
+  let mut actual_lines = actual.trim_end().lines();
+  for i in 1.. {
+    match (expected_lines.next(), actual_lines.next()) {
+      (None, None) => return Ok(()),
+      (e, a) if e == a => {}
+      (e, a) => {
+        return Err(format!(
+          "screen of {} differs from {} at line {}\n\
+          expected: {}\n  actual: {}\n\
+          Set {}=1 to update the golden file",
+          path.display(),
+          golden_path.display(),
+          i,
+          e.unwrap_or("<EOF>"),
+          a.unwrap_or("<EOF>"),
+          UPDATE_ENV
+        ))
+      }
+    }
+  }
+  unreachable!()
+}
+
+/// Runs the program at `path` for at most `max_steps` instructions, and
+/// renders the result and the final screen as text.
+pub fn render_program_screen<P>(
+  path: P,
+  max_steps: usize,
+) -> Result<String, String>
+where
+  P: AsRef<Path>,
+{
+  crate::machine::init_machines_once()?;
+
+  let path = path.as_ref();
+  let mut document = Document::load_file(path)
+    .map_err(|err| format!("failed to load {}: {:?}", path.display(), err))?;
+  if let Some(err) = first_error(path, &mut document) {
+    return Err(err);
+  }
+  let data_dir = path.parent().unwrap_or(Path::new("")).to_owned();
+  let mut device = document.create_device(data_dir);
+  let mut vm = document
+    .create_vm(&mut device)
+    .map_err(|_| format!("{} contains errors", path.display()))?;
+  vm.start();
+
+  let result =
+    vm.run_steps(ExecInput::None, max_steps, |_, result| match result {
+      ExecResult::Sleep(_) | ExecResult::Warning { .. } => {
+        Some(ExecInput::None)
+      }
+      _ => None,
+    });
+  drop(vm);
+
+  let mut text = String::new();
+  match result {
+    ExecResult::End => text.push_str("result: end\n"),
+    ExecResult::Continue => {
+      writeln!(text, "result: not finished in {} steps", max_steps).unwrap()
+    }
+    ExecResult::Error {
+      location, message, ..
+    } => writeln!(
+      text,
+      "result: error at line {} ({}..{}): {}",
+      location.line + 1,
+      location.range.start,
+      location.range.end,
+      message
+    )
+    .unwrap(),
+    ExecResult::InKey | ExecResult::KeyboardInput { .. } => {
+      return Err(format!("{} waits for keyboard input", path.display()))
+    }
+    result => writeln!(text, "result: {:?}", result).unwrap(),
+  }
+  render_screen(&device, &mut text);
+  Ok(text)
+}
+
+/// Renders the text buffer, followed by the graphic memory with 2 pixel rows
+/// per line, using half blocks.
+fn render_screen(device: &DefaultDevice, text: &mut String) {
+  text.push_str("text:\n");
+  for line in device.text_lines() {
+    if !line.is_empty() {
+      text.push_str("  ");
+    }
+    writeln!(text, "{}", line).unwrap();
+  }
+
+  text.push_str("screen:\n");
+  let pixels = device.graphic_memory();
+  let pixel = |x: usize, y: usize| {
+    pixels[y * screen::WIDTH_IN_BYTE + x / 8] & (0x80 >> (x & 7)) != 0
+  };
+  for y in (0..screen::HEIGHT).step_by(2) {
+    let mut line = String::from("  ");
+    for x in 0..screen::WIDTH {
+      line.push(match (pixel(x, y), pixel(x, y + 1)) {
+        (false, false) => ' ',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (true, true) => '█',
+      });
+    }
+    writeln!(text, "{}", line.trim_end()).unwrap();
+  }
+}
+
+fn first_error(path: &Path, document: &mut Document) -> Option<String> {
+  for (i, line) in document.diagnostics().iter().enumerate() {
+    for diag in &line.diagnostics {
+      if diag.severity == Severity::Error {
+        return Some(format!(
+          "{} contains errors. The first error is at line {}: {}",
+          path.display(),
+          i + 1,
+          diag.message
+        ));
+      }
+    }
+  }
+  None
+}
+
+/// Asserts that the screen of the program at `path`, relative to the
+/// manifest directory of the calling crate, matches its golden file. See
+/// [`fixture`](crate::fixture).
+///
+/// The maximum number of instructions can be given as the second argument,
+/// which defaults to [`DEFAULT_MAX_STEPS`](crate::fixture::DEFAULT_MAX_STEPS).
+#[macro_export]
+macro_rules! assert_program_screen {
+  ($path:expr) => {
+    $crate::assert_program_screen!($path, $crate::fixture::DEFAULT_MAX_STEPS)
+  };
+  ($path:expr, $max_steps:expr) => {
+    if let Err(err) = $crate::fixture::check_program_screen(
+      ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($path),
+      $max_steps,
+    ) {
+      panic!("{}", err);
+    }
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  #[test]
+  fn hello() {
+    assert_program_screen!("test/fixtures/screens/hello.txt");
+  }
+
+  #[test]
+  fn graphics() {
+    assert_program_screen!("test/fixtures/screens/graphics.txt");
+  }
+
+  #[test]
+  fn error() {
+    assert_program_screen!("test/fixtures/screens/error.txt", 100_000);
+  }
+
+  #[test]
+  fn input() {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("test/fixtures/screens/input.txt");
+    assert_eq!(
+      super::render_program_screen(&path, 1000),
+      Err(format!("{} waits for keyboard input", path.display()))
+    );
+  }
+}
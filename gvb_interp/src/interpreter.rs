@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+use widestring::Utf16String;
+
+use crate::device::default::DefaultDevice;
+use crate::report::RunReport;
+use crate::{
+  BuildVmError, ContainsErrors, Document, ExecInput, ExecResult, LineDiagnosis,
+  VirtualMachine, VmBuilder, VmOptionsError,
+};
+
+/// Bundles a document, a default device and a VM for the common workflow of
+/// loading a program, checking diagnostics and running it.
+///
+/// Machine profiles must be initialized by `machine::init_machines` before
+/// creating an interpreter.
+pub struct Interpreter {
+  /// Owns the device while a program is running.
+  vm: Option<VirtualMachine<'static, DefaultDevice>>,
+  /// The device when no program is running. Exactly one of `vm` and
+  /// `device` is Some.
+  device: Option<DefaultDevice>,
+  document: Document,
+  data_dir: PathBuf,
+  builder: VmBuilder,
+  input: Option<ExecInput>,
+  /// The last result requesting input, which is returned again if the
+  /// program is run before the input is provided.
+  awaiting_input: Option<ExecResult>,
+}
+
+impl Interpreter {
+  /// Files opened by programs are in `data_dir`.
+  pub fn new<P>(data_dir: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    let data_dir = data_dir.into();
+    let document = Document::new();
+    let device = document.create_device(data_dir.clone());
+    Self {
+      vm: None,
+      device: Some(device),
+      document,
+      data_dir,
+      builder: VmBuilder::default(),
+      input: None,
+      awaiting_input: None,
+    }
+  }
+
+  /// Replaces the program with `text`. The running program is stopped, and
+  /// the device is recreated for the machine of the new program.
+  pub fn load(&mut self, text: &str) {
+    self.load_document(Document::from_text(Utf16String::from(text)));
+  }
+
+  pub fn load_document(&mut self, document: Document) {
+    self.vm = None;
+    self.input = None;
+    self.awaiting_input = None;
+    self.device = Some(document.create_device(self.data_dir.clone()));
+    self.document = document;
+  }
+
+  /// Sets the options of the VMs created by later runs. The options are
+  /// validated first.
+  pub fn set_vm_builder(
+    &mut self,
+    builder: VmBuilder,
+  ) -> Result<(), VmOptionsError> {
+    builder.options().validate()?;
+    self.builder = builder;
+    Ok(())
+  }
+
+  pub fn document(&self) -> &Document {
+    &self.document
+  }
+
+  pub fn diagnostics(&mut self) -> &[LineDiagnosis] {
+    self.document.diagnostics()
+  }
+
+  /// Executes at most `budget` instructions. The program is started on the
+  /// first call after loading, and Err is returned if it contains errors.
+  ///
+  /// After `ExecResult::InKey` or `ExecResult::KeyboardInput` is returned,
+  /// the input must be provided by `provide_input` before execution can be
+  /// resumed.
+  pub fn run(&mut self, budget: usize) -> Result<ExecResult, ContainsErrors> {
+    if self.vm.is_none() {
+      let device = self.device.take().unwrap();
+      let mut vm = match self.builder.build_owned(&mut self.document, device) {
+        Ok(vm) => vm,
+        Err((err, device)) => {
+          self.device = Some(device);
+          match err {
+            BuildVmError::ContainsErrors => return Err(ContainsErrors),
+            BuildVmError::InvalidOptions(_) => {
+              unreachable!("options are validated by set_vm_builder")
+            }
+          }
+        }
+      };
+      vm.start();
+      self.vm = Some(vm);
+    }
+    let vm = self.vm.as_mut().unwrap();
+
+    let input = match (self.input.take(), &self.awaiting_input) {
+      (Some(input), _) => input,
+      (None, Some(result)) => return Ok(result.clone()),
+      (None, None) => ExecInput::None,
+    };
+    let result = vm.run_steps(input, budget, |_, _| None);
+    self.awaiting_input = match &result {
+      ExecResult::InKey | ExecResult::KeyboardInput { .. } => {
+        Some(result.clone())
+      }
+      _ => None,
+    };
+    Ok(result)
+  }
+
+  /// Provides the input requested by the last call to `run`.
+  pub fn provide_input(&mut self, input: ExecInput) {
+    self.awaiting_input = None;
+    self.input = Some(input);
+  }
+
+  /// Stops the running program. The next call to `run` restarts it.
+  pub fn stop(&mut self) {
+    if let Some(mut vm) = self.vm.take() {
+      let _ = vm.stop();
+      self.device = vm.into_device();
+    }
+    self.input = None;
+    self.awaiting_input = None;
+  }
+
+  pub fn device(&self) -> &DefaultDevice {
+    match &self.vm {
+      Some(vm) => vm.device(),
+      None => self.device.as_ref().unwrap(),
+    }
+  }
+
+  pub fn device_mut(&mut self) -> &mut DefaultDevice {
+    match &mut self.vm {
+      Some(vm) => vm.device_mut(),
+      None => self.device.as_mut().unwrap(),
+    }
+  }
+
+  /// Starts recording the run for `report_html`. The recording is discarded
+  /// when another program is loaded.
+  pub fn start_recording(&mut self) {
+    self.device_mut().start_recording();
+  }
+
+  /// Renders the recorded run as an HTML page, with `result` as the last
+  /// result returned by `run`. Returns None if the recording is not started.
+  pub fn report_html(&self, result: &ExecResult) -> Option<String> {
+    let device = self.device();
+    let report = RunReport {
+      text: self.document.text(),
+      recording: device.recording()?,
+      screen: device.graphic_memory(),
+      result,
+    };
+    Some(report.to_html())
+  }
+
+  /// Returns the screen bitmap, 160x80 pixels with 1 bit per pixel, in
+  /// row-major order. The most significant bit of each byte is the leftmost
+  /// pixel.
+  pub fn screen(&self) -> &[u8] {
+    self.device().graphic_memory()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::KeyboardInput;
+
+  #[test]
+  fn run() {
+    crate::machine::init_machines_once().unwrap();
+    let mut interp = Interpreter::new("");
+    interp.load("10 input a$\r\n20 print a$;\r\n30 end");
+    assert!(interp
+      .diagnostics()
+      .iter()
+      .all(|d| d.diagnostics.is_empty()));
+    assert!(matches!(
+      interp.run(usize::MAX),
+      Ok(ExecResult::KeyboardInput { .. })
+    ));
+    assert!(matches!(
+      interp.run(usize::MAX),
+      Ok(ExecResult::KeyboardInput { .. })
+    ));
+    interp.provide_input(ExecInput::KeyboardInput(vec![
+      KeyboardInput::String(b"A".to_vec().into()),
+    ]));
+    assert!(matches!(interp.run(usize::MAX), Ok(ExecResult::End)));
+    assert!(interp.screen().iter().any(|&b| b != 0));
+    interp.stop();
+    assert!(interp.screen().iter().any(|&b| b != 0));
+
+    interp.load("10 print 1+");
+    assert!(interp.run(usize::MAX).is_err());
+  }
+
+  #[test]
+  fn report() {
+    crate::machine::init_machines_once().unwrap();
+    let mut interp = Interpreter::new("");
+    interp.load("10 print \"A\"\r\n20 cls:print \"B\";\r\n30 goto 50\r\n40 end\r\n50 x=1/0");
+    let result = interp.run(usize::MAX).ok().unwrap();
+    assert!(interp.report_html(&result).is_none());
+
+    interp.load("10 print \"A\"\r\n20 cls:print \"B\";\r\n30 goto 50\r\n40 end\r\n50 x=1/0");
+    interp.start_recording();
+    let result = interp.run(usize::MAX).ok().unwrap();
+    assert!(matches!(result, ExecResult::Error { .. }));
+    let recording = interp.device().recording().unwrap();
+    assert_eq!(
+      recording.executed_lines.iter().copied().collect::<Vec<_>>(),
+      vec![0, 1, 2, 4]
+    );
+    assert_eq!(recording.output, "A\nB");
+    assert_eq!(recording.screenshots.len(), 1);
+    assert_eq!(recording.screenshots[0].line, Some(1));
+
+    let html = interp.report_html(&result).unwrap();
+    assert!(html.contains("<span>40 end</span>"));
+    assert!(
+      html.contains("<span class=\"error-line\">50 x=<mark>1/0</mark></span>")
+    );
+  }
+}
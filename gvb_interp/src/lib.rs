@@ -7,6 +7,7 @@
   const_maybe_uninit_assume_init,
   iter_order_by
 )]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 #![allow(
   clippy::needless_late_init,
   clippy::useless_format,
@@ -16,16 +17,24 @@
 #[macro_use]
 pub mod util;
 mod ast;
+pub mod builtin;
 mod compiler;
+pub mod conformance;
 pub mod device;
 pub mod diagnostic;
+pub mod dialect;
 pub mod document;
+pub mod fixture;
+mod interpreter;
 pub mod machine;
 mod parser;
+pub mod report;
+pub mod tile;
 pub mod vm;
 
 pub use self::diagnostic::*;
 pub use self::document::*;
+pub use self::interpreter::Interpreter;
 pub use self::vm::*;
 
 mod gb2312 {
@@ -35,8 +44,22 @@ mod gb2312 {
 use std::hash;
 
 type HashMap<K, V> = std::collections::HashMap<K, V, BuildSeaHasher>;
+type HashSet<T> = std::collections::HashSet<T, BuildSeaHasher>;
+#[cfg(not(feature = "ordered-store"))]
 type HashMapEntry<'a, K, V> = std::collections::hash_map::Entry<'a, K, V>;
 
+/// Map storing the variables, arrays and user-defined functions of the VM.
+/// With the `ordered-store` feature, it iterates in the order of symbols, i.e.
+/// the order names are interned, which is stable across runs and platforms.
+#[cfg(feature = "ordered-store")]
+type StoreMap<K, V> = std::collections::BTreeMap<K, V>;
+#[cfg(feature = "ordered-store")]
+type StoreMapEntry<'a, K, V> = std::collections::btree_map::Entry<'a, K, V>;
+#[cfg(not(feature = "ordered-store"))]
+type StoreMap<K, V> = HashMap<K, V>;
+#[cfg(not(feature = "ordered-store"))]
+type StoreMapEntry<'a, K, V> = HashMapEntry<'a, K, V>;
+
 #[derive(Default)]
 pub struct BuildSeaHasher;
 
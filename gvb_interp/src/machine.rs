@@ -1,9 +1,13 @@
+use crate::device::default::screen;
+use crate::dialect::Dialect;
 use crate::{util::utf16str_ext::Utf16StrExt, HashMap};
 use intmap::IntMap;
 use std::collections::BTreeMap;
 use std::io;
 use std::mem::MaybeUninit;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::OnceLock;
 use std::time::Duration;
 use util::config;
 use widestring::{Utf16Str, Utf16String};
@@ -25,11 +29,50 @@ pub(crate) struct MachineProps {
   pub key_masks: [Option<(u16, u8)>; 256],
   pub key_buffer_quit: bool,
   pub eof_behavior: EofBehavior,
+  pub int_overflow: IntOverflow,
+  pub invalid_notes: InvalidNotes,
+  pub selector_rounding: SelectorRounding,
+  /// Maximum length of strings in bytes.
+  pub max_string_len: usize,
+  /// Whether the text mode screen can be switched to the 12x12 font.
+  pub small_font: bool,
+  /// Width of the screen in pixels.
+  pub screen_width: usize,
+  /// Height of the screen in pixels.
+  pub screen_height: usize,
+  pub dialect: Dialect,
   pub addrs: IntMap<AddrProp>,
   pub extra_symbol_data: Vec<u8>,
   /// symbol code -> index of extra_symbol_data
   pub extra_symbols: IntMap<usize>,
   pub brks: IntMap<BrkKind>,
+  pub secondary_storage: Option<SecondaryStorageProps>,
+  /// Valid RAM and ROM regions. Empty if the memory map is unknown.
+  pub memory_map: Vec<MemoryRegion>,
+}
+
+/// A region of the memory map. `end` is inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MemoryRegion {
+  pub start: u16,
+  pub end: u16,
+  pub kind: MemoryKind,
+  pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MemoryKind {
+  Ram,
+  Rom,
+}
+
+/// Secondary storage of expanded hardware, e.g. SD card. Files whose names
+/// start with `prefix` are stored in the subdirectory `dir` of the data
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SecondaryStorageProps {
+  pub prefix: String,
+  pub dir: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -38,6 +81,39 @@ pub enum EofBehavior {
   Inverse,
 }
 
+/// Behavior of assigning numbers out of the range of integers to integer
+/// variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntOverflow {
+  Error,
+  /// Keeps the lower 16 bits of the integer part.
+  Wrap,
+  /// Clamps the integer part to -32768~32767.
+  Saturate,
+}
+
+/// Behavior of PLAY statements with malformed note strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidNotes {
+  Error,
+  /// Returns `ExecResult::Warning`, and passes the notes to the device.
+  Warning,
+  /// Passes the notes to the device, which plays nothing. The default.
+  Ignore,
+}
+
+/// How ON ... GOTO/GOSUB statements convert non-integral selectors to branch
+/// numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectorRounding {
+  /// Discards the fractional part, e.g. 2.7 selects the 2nd branch.
+  Truncate,
+  /// Rounds half up, e.g. 2.5 selects the 3rd branch.
+  Round,
+}
+
+pub const DEFAULT_MAX_STRING_LEN: usize = 255;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddrProp {
   Year,
@@ -68,14 +144,34 @@ impl Default for MachineProps {
       key_masks: [None; 256],
       key_buffer_quit: false,
       eof_behavior: EofBehavior::Normal,
+      int_overflow: IntOverflow::Error,
+      invalid_notes: InvalidNotes::Ignore,
+      selector_rounding: SelectorRounding::Truncate,
+      max_string_len: DEFAULT_MAX_STRING_LEN,
+      small_font: false,
+      screen_width: screen::WIDTH,
+      screen_height: screen::HEIGHT,
+      dialect: Dialect::FIRMWARE,
       addrs: IntMap::new(),
       extra_symbol_data: vec![],
       extra_symbols: IntMap::new(),
       brks: IntMap::new(),
+      secondary_storage: None,
+      memory_map: vec![],
     }
   }
 }
 
+impl MachineProps {
+  /// Returns the region of the memory map containing `addr`.
+  pub fn memory_region(&self, addr: u16) -> Option<&MemoryRegion> {
+    self
+      .memory_map
+      .iter()
+      .find(|region| (region.start..=region.end).contains(&addr))
+  }
+}
+
 impl FromStr for AddrProp {
   type Err = ();
   fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -152,8 +248,32 @@ impl From<&str> for InitError {
   }
 }
 
+/// Calls [`init_machines`] once for the whole process, and returns the
+/// result of that call afterwards. Unlike `init_machines`, it never replaces
+/// the machine profiles, which may be in use by other threads, e.g. tests
+/// running in parallel.
+pub(crate) fn init_machines_once() -> Result<(), String> {
+  static INIT: OnceLock<Result<(), String>> = OnceLock::new();
+  INIT
+    .get_or_init(|| {
+      init_machines()
+        .map_err(|err| format!("failed to initialize machines: {:?}", err))
+    })
+    .clone()
+}
+
+/// Loads the machine profiles from `machines.yaml`, which is searched in the
+/// same order as other config files.
 pub fn init_machines() -> Result<(), InitError> {
-  let content = config::load_config_file("machines.yaml")?;
+  init_machines_from_file(config::config_file_path("machines.yaml")?)
+}
+
+/// Loads the machine profiles from the file `path`.
+pub fn init_machines_from_file<P>(path: P) -> Result<(), InitError>
+where
+  P: AsRef<Path>,
+{
+  let content = std::fs::read_to_string(path)?;
   let mut docs = YamlLoader::load_from_str(&content)?;
   unsafe {
     if MACHINES_INITED {
@@ -401,6 +521,142 @@ pub fn init_machines() -> Result<(), InitError> {
       }
     }
 
+    // int-overflow
+    if let Some(int_overflow) = obj.remove(&Yaml::String("int-overflow".into()))
+    {
+      let int_overflow = int_overflow
+        .as_str()
+        .ok_or_else(|| format!("{mach_name}.int-overflow is not string"))?;
+      props.int_overflow = match int_overflow {
+        "error" => IntOverflow::Error,
+        "wrap" => IntOverflow::Wrap,
+        "saturate" => IntOverflow::Saturate,
+        _ => {
+          return Err(
+            format!("invalid int-overflow value in '{mach_name}'").into(),
+          );
+        }
+      };
+    }
+
+    // invalid-notes
+    if let Some(invalid_notes) =
+      obj.remove(&Yaml::String("invalid-notes".into()))
+    {
+      let invalid_notes = invalid_notes
+        .as_str()
+        .ok_or_else(|| format!("{mach_name}.invalid-notes is not string"))?;
+      props.invalid_notes = match invalid_notes {
+        "error" => InvalidNotes::Error,
+        "warning" => InvalidNotes::Warning,
+        "ignore" => InvalidNotes::Ignore,
+        _ => {
+          return Err(
+            format!("invalid invalid-notes value in '{mach_name}'").into(),
+          );
+        }
+      };
+    }
+
+    // selector-rounding
+    if let Some(rounding) =
+      obj.remove(&Yaml::String("selector-rounding".into()))
+    {
+      let rounding = rounding.as_str().ok_or_else(|| {
+        format!("{mach_name}.selector-rounding is not string")
+      })?;
+      props.selector_rounding = match rounding {
+        "truncate" => SelectorRounding::Truncate,
+        "round" => SelectorRounding::Round,
+        _ => {
+          return Err(
+            format!("invalid selector-rounding value in '{mach_name}'").into(),
+          );
+        }
+      };
+    }
+
+    // max-string-length
+    if let Some(max_len) = obj.remove(&Yaml::String("max-string-length".into()))
+    {
+      let max_len = max_len.as_i64().ok_or_else(|| {
+        format!("{mach_name}.max-string-length is not integer")
+      })?;
+      if !(1..=65535).contains(&max_len) {
+        return Err(
+          format!(
+            "{mach_name}.max-string-length is not within the range 1~65535"
+          )
+          .into(),
+        );
+      }
+      props.max_string_len = max_len as usize;
+    }
+
+    // small-font
+    if let Some(small_font) = obj.remove(&Yaml::String("small-font".into())) {
+      props.small_font = small_font
+        .as_bool()
+        .ok_or_else(|| format!("{mach_name}.small-font is not boolean"))?;
+    }
+
+    // screen-width, screen-height
+    for (key, size) in [
+      ("screen-width", &mut props.screen_width),
+      ("screen-height", &mut props.screen_height),
+    ] {
+      if let Some(value) = obj.remove(&Yaml::String(key.into())) {
+        let value = value
+          .as_i64()
+          .ok_or_else(|| format!("{mach_name}.{key} is not integer"))?;
+        if !(1..=256).contains(&value) {
+          return Err(
+            format!("{mach_name}.{key} is not within the range 1~256").into(),
+          );
+        }
+        *size = value as usize;
+      }
+    }
+
+    // disabled-keywords
+    if let Some(disabled) =
+      obj.remove(&Yaml::String("disabled-keywords".into()))
+    {
+      let disabled = disabled
+        .into_vec()
+        .ok_or_else(|| format!("{mach_name}.disabled-keywords is not array"))?;
+      let mut names = vec![];
+      for name in disabled {
+        names.push(name.into_string().ok_or_else(|| {
+          format!("{mach_name}.disabled-keywords contains non-string")
+        })?);
+      }
+      props.dialect = Dialect::without(names.iter().map(String::as_str))
+        .map_err(|name| {
+          format!("unknown keyword {name} in {mach_name}.disabled-keywords")
+        })?;
+    }
+
+    // extensions
+    if let Some(extensions) = obj.remove(&Yaml::String("extensions".into())) {
+      let extensions = extensions
+        .into_vec()
+        .ok_or_else(|| format!("{mach_name}.extensions is not array"))?;
+      let mut names = vec![];
+      for name in extensions {
+        names.push(name.into_string().ok_or_else(|| {
+          format!("{mach_name}.extensions contains non-string")
+        })?);
+      }
+      props.dialect = props
+        .dialect
+        .clone()
+        .with_extensions(names.iter().map(String::as_str))
+        .map_err(|name| {
+          format!("unknown extension {name} in {mach_name}.extensions")
+        })?;
+    }
+
     // addrs
     let addrs = obj
       .remove(&Yaml::String("addrs".to_owned()))
@@ -514,6 +770,120 @@ pub fn init_machines() -> Result<(), InitError> {
       }
     }
 
+    // memory-map
+    if let Some(regions) = obj.remove(&Yaml::String("memory-map".to_owned())) {
+      let regions = regions
+        .into_vec()
+        .ok_or_else(|| format!("{mach_name}.memory-map is not array"))?;
+      for (i, region) in regions.into_iter().enumerate() {
+        let context = format!("{mach_name}.memory-map[{i}]");
+        let mut region = region
+          .into_hash()
+          .ok_or_else(|| format!("{context} is not object"))?;
+
+        let start = region
+          .remove(&Yaml::String("start".to_owned()))
+          .ok_or_else(|| format!("missing field 'start' in {context}"))?;
+        let start = get_addr(&context, "start", start)?;
+
+        let end = region
+          .remove(&Yaml::String("end".to_owned()))
+          .ok_or_else(|| format!("missing field 'end' in {context}"))?;
+        let end = get_addr(&context, "end", end)?;
+        if end < start {
+          return Err(format!("{context}.end is less than start").into());
+        }
+
+        let kind = region
+          .remove(&Yaml::String("kind".to_owned()))
+          .ok_or_else(|| format!("missing field 'kind' in {context}"))?;
+        let kind = kind
+          .into_string()
+          .ok_or_else(|| format!("{context}.kind is not string"))?;
+        let kind = match kind.as_str() {
+          "ram" => MemoryKind::Ram,
+          "rom" => MemoryKind::Rom,
+          _ => {
+            return Err(
+              format!("unrecognized value {kind} in {context}.kind").into(),
+            )
+          }
+        };
+
+        let name = region
+          .remove(&Yaml::String("name".to_owned()))
+          .map(|name| {
+            name
+              .into_string()
+              .ok_or_else(|| format!("{context}.name is not string"))
+          })
+          .transpose()?;
+
+        if let Some((k, _)) = region.pop_front() {
+          return Err(
+            format!("superfluous field {} in {}", yaml_to_string(&k), context)
+              .into(),
+          );
+        }
+
+        props.memory_map.push(MemoryRegion {
+          start,
+          end,
+          kind,
+          name,
+        });
+      }
+    }
+
+    // secondary-storage
+    if let Some(storage) =
+      obj.remove(&Yaml::String("secondary-storage".to_owned()))
+    {
+      let mut storage = storage.into_hash().ok_or_else(|| {
+        format!("{mach_name}.secondary-storage is not object")
+      })?;
+
+      let prefix = storage
+        .remove(&Yaml::String("prefix".to_owned()))
+        .ok_or_else(|| {
+          format!("missing field 'prefix' in {mach_name}.secondary-storage")
+        })?;
+      let prefix = prefix.into_string().ok_or_else(|| {
+        format!("{mach_name}.secondary-storage.prefix is not string")
+      })?;
+      if prefix.is_empty() || !prefix.is_ascii() {
+        return Err(
+          format!(
+            "{mach_name}.secondary-storage.prefix is empty or not ASCII string"
+          )
+          .into(),
+        );
+      }
+
+      let dir =
+        storage
+          .remove(&Yaml::String("dir".to_owned()))
+          .ok_or_else(|| {
+            format!("missing field 'dir' in {mach_name}.secondary-storage")
+          })?;
+      let dir = dir.into_string().ok_or_else(|| {
+        format!("{mach_name}.secondary-storage.dir is not string")
+      })?;
+
+      if let Some((k, _)) = storage.pop_front() {
+        return Err(
+          format!(
+            "superfluous field {} in {}.secondary-storage",
+            yaml_to_string(&k),
+            mach_name
+          )
+          .into(),
+        );
+      }
+
+      props.secondary_storage = Some(SecondaryStorageProps { prefix, dir });
+    }
+
     if let Some((key, _)) = obj.pop_front() {
       return Err(
         format!(
@@ -7,14 +7,19 @@ use crate::ast::{
   PrintElement, ProgramLine, Punc, Range, Stmt, StmtId, StmtKind, SysFuncKind,
   TokenKind, UnaryOpKind, WriteElement,
 };
-use crate::diagnostic::Diagnostic;
+use crate::diagnostic::{
+  expected_symbols_message, Diagnostic, DiagnosticPhase, ExpectedSymbol,
+};
+use crate::dialect::Dialect;
 use crate::util::ascii_ext::AsciiExt;
 use crate::util::utf16str_ext::Utf16StrExt;
 use id_arena::Arena;
 use smallvec::{smallvec, Array, SmallVec};
+#[cfg(test)]
 use std::fmt::Write;
 use widestring::{utf16str, Utf16Str};
 
+pub mod canonical;
 pub mod symbol;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,7 +51,7 @@ pub fn parse_expr(
     stmt_arena: Arena::new(),
     expr_arena: Arena::new(),
   };
-  let mut parser = LineParser::new(input, node_builder);
+  let mut parser = LineParser::new(input, node_builder, &Dialect::FULL);
 
   parser.read_token(false);
   let expr = parser.parse_expr();
@@ -63,6 +68,15 @@ pub fn parse_expr(
 /// `line_with_eol` may contain newline.
 pub fn parse_line(
   line_with_eol: &Utf16Str,
+) -> (ParseResult<ProgramLine>, Option<SymbolSet>) {
+  parse_line_in_dialect(line_with_eol, &Dialect::FULL)
+}
+
+/// Parses a line, recognizing only the keywords and system functions in
+/// `dialect`.
+pub fn parse_line_in_dialect(
+  line_with_eol: &Utf16Str,
+  dialect: &Dialect,
 ) -> (ParseResult<ProgramLine>, Option<SymbolSet>) {
   let code_units = line_with_eol.as_slice();
   let line;
@@ -85,7 +99,7 @@ pub fn parse_line(
     stmt_arena: Arena::new(),
     expr_arena: Arena::new(),
   };
-  let mut parser = LineParser::new(line, node_builder);
+  let mut parser = LineParser::new(line, node_builder, dialect);
 
   let mut label = None;
   if !match_u16c!(line.as_slice().first(), b' ') {
@@ -154,6 +168,7 @@ struct LineParser<'a, T: NodeBuilder> {
   first_symbols: SymbolSet,
   /// Only contains terminals.
   follow_symbols: SymbolSet,
+  dialect: &'a Dialect,
 }
 
 macro_rules! extend_symbol {
@@ -210,7 +225,7 @@ macro_rules! setup_follow {
 }
 
 impl<'a, T: NodeBuilder> LineParser<'a, T> {
-  fn new(input: &'a Utf16Str, node_builder: T) -> Self {
+  fn new(input: &'a Utf16Str, node_builder: T, dialect: &'a Dialect) -> Self {
     Self {
       offset: 0,
       input,
@@ -222,11 +237,20 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
       expected_symbols_at_eof: None,
       first_symbols: SymbolSet::new(),
       follow_symbols: SymbolSet::new(),
+      dialect,
     }
   }
 
   fn add_error(&mut self, range: Range, message: impl ToString) {
-    self.diagnostics.push(Diagnostic::new_error(range, message));
+    self.diagnostics.push(
+      Diagnostic::new_error(range, message).with_phase(DiagnosticPhase::Parse),
+    );
+  }
+
+  fn add_lex_error(&mut self, range: Range, message: impl ToString) {
+    self.diagnostics.push(
+      Diagnostic::new_error(range, message).with_phase(DiagnosticPhase::Lex),
+    );
   }
 
   fn advance(&mut self, count: usize) {
@@ -328,9 +352,9 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
             let mut str = self.input[..i].to_string();
             str.make_ascii_lowercase();
             self.advance(i);
-            if let Ok(kw) = str.parse::<Keyword>() {
+            if let Some(kw) = self.dialect.keyword(&str) {
               return self.set_token(start, TokenKind::Keyword(kw));
-            } else if let Ok(f) = str.parse::<SysFuncKind>() {
+            } else if let Some(f) = self.dialect.sys_func(&str) {
               return self.set_token(start, TokenKind::SysFunc(f));
             } else if sigil {
               return self.set_token(start, TokenKind::Ident);
@@ -356,8 +380,8 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
                   if in_seg {
                     let mut str = self.input[seg_start..i].to_string();
                     str.make_ascii_lowercase();
-                    if str.parse::<Keyword>().is_ok()
-                      || str.parse::<SysFuncKind>().is_ok()
+                    if self.dialect.keyword(&str).is_some()
+                      || self.dialect.sys_func(&str).is_some()
                     {
                       i = seg_start;
                     }
@@ -369,8 +393,8 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
                     in_seg = false;
                     let mut str = self.input[seg_start..i].to_string();
                     str.make_ascii_lowercase();
-                    if str.parse::<Keyword>().is_ok()
-                      || str.parse::<SysFuncKind>().is_ok()
+                    if self.dialect.keyword(&str).is_some()
+                      || self.dialect.sys_func(&str).is_some()
                     {
                       i = seg_start;
                       break;
@@ -395,7 +419,7 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
           _ => {
             let start = self.offset;
             self.advance(1);
-            self.add_error(
+            self.add_lex_error(
               Range::new(start, self.offset),
               format!("非法字符：U+{:04X}", c as u32),
             );
@@ -407,7 +431,7 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
         let start = self.offset;
         let c = self.input.chars().next().unwrap();
         self.advance(c.len_utf16());
-        self.add_error(
+        self.add_lex_error(
           Range::new(start, self.offset),
           // TODO check if c is printable
           if (c as u32) < 0x10000 {
@@ -460,36 +484,17 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
   }
 
   fn report_mismatch_token_error(&mut self) {
-    let mut msg = "语法错误。期望是 ".to_owned();
-    let symbols = self.first_symbols.iter().collect::<Vec<_>>();
-    let len = symbols.len();
-    for (i, sym) in symbols.into_iter().enumerate() {
-      if i != 0 {
-        if i == len - 1 {
-          msg += " 或 "
-        } else {
-          msg += "，";
-        }
-      }
-      match sym {
-        Symbol::Term(token) => match token {
-          TokenKind::Ident => msg += "标识符",
-          TokenKind::Label => msg += "行号",
-          TokenKind::Float => msg += "实数",
-          TokenKind::String => msg += "字符串",
-          TokenKind::Punc(p) => write!(&mut msg, "\"{p:?}\"").unwrap(),
-          TokenKind::Keyword(p) => write!(&mut msg, "{p:?}").unwrap(),
-          TokenKind::SysFunc(p) => write!(&mut msg, "{p:?}").unwrap(),
-          TokenKind::Eof => msg += "行尾",
-        },
-        Symbol::Nonterm(n) => match n {
-          Nonterminal::Expr => msg += "表达式",
-          Nonterminal::Stmt => msg += "语句",
-          Nonterminal::Array => msg += "数组",
-        },
-      }
-    }
-    self.add_error(self.token.0.clone(), msg);
+    let expected = self
+      .first_symbols
+      .iter()
+      .map(ExpectedSymbol::from)
+      .collect::<Vec<_>>();
+    let msg = expected_symbols_message(&expected);
+    self.diagnostics.push(
+      Diagnostic::new_error(self.token.0.clone(), msg)
+        .with_phase(DiagnosticPhase::Parse)
+        .with_expected(expected),
+    );
   }
 
   fn recover(&mut self, read_label: bool) {
@@ -669,6 +674,8 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
       Keyword(Kw::Fwrite) => self.parse_fread_fwrite_stmt(true),
       Keyword(Kw::Fseek) => self.parse_fseek_stmt(),
       Keyword(Kw::DebugPrint) => self.parse_debug_stmt(),
+      Keyword(Kw::Timer) => self.parse_timer_stmt(),
+      Keyword(Kw::Sound) => self.parse_sound_stmt(),
       Label => match self.label_value.take().unwrap() {
         Ok(label) => {
           let range = self.token.0.clone();
@@ -704,6 +711,44 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
     }
   }
 
+  fn parse_timer_stmt(&mut self) -> StmtId {
+    let start = self.token.0.start;
+    let input = self.input;
+    let offset = self.offset;
+    self.read_token(false);
+
+    let enabled = match self.token.1 {
+      TokenKind::Keyword(Keyword::On) => true,
+      TokenKind::Ident => {
+        let range = &self.token.0;
+        let name = input[range.start - offset..range.end - offset].to_string();
+        if name.eq_ignore_ascii_case("off") {
+          false
+        } else {
+          self.add_error(range.clone(), "TIMER 之后必须是 ON 或 OFF");
+          true
+        }
+      }
+      _ => {
+        self.add_error(
+          Range::new(start, self.token.0.end),
+          "TIMER 之后必须是 ON 或 OFF",
+        );
+        self.recover(false);
+        return self.node_builder.new_stmt(Stmt {
+          kind: StmtKind::NoOp,
+          range: Range::new(start, self.last_token_end),
+        });
+      }
+    };
+    self.read_token(false);
+
+    self.node_builder.new_stmt(Stmt {
+      kind: StmtKind::Timer { enabled },
+      range: Range::new(start, self.last_token_end),
+    })
+  }
+
   fn parse_unary_cmd(&mut self, ctor: fn(ExprId) -> StmtKind) -> StmtId {
     let _first_symbols = self.first_symbols.backup();
 
@@ -1408,6 +1453,10 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
     let start = self.token.0.start;
     self.read_token(false);
 
+    if self.token.1 == TokenKind::Keyword(Keyword::Timer) {
+      return self.parse_on_timer_stmt(start);
+    }
+
     setup_first! { self : }
     setup_follow! { self, old_follow : (kw Gosub Goto) }
     let cond = self.parse_expr();
@@ -1474,6 +1523,44 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
     })
   }
 
+  fn parse_on_timer_stmt(&mut self, start: usize) -> StmtId {
+    let _first_symbols = self.first_symbols.backup();
+    let old_follow = self.follow_symbols.backup();
+    self.read_token(false);
+
+    setup_first! { self : (punc LParen) }
+    setup_follow! { self, old_follow : (t Expr) (punc RParen) (kw Gosub) }
+    let _ = self.match_token(TokenKind::Punc(Punc::LParen), false, true);
+
+    setup_first! { self : }
+    setup_follow! { self, old_follow : (punc RParen) (kw Gosub) }
+    let interval = self.parse_expr();
+
+    setup_first! { self : (punc RParen) }
+    setup_follow! { self, old_follow : (kw Gosub) (label) }
+    let _ = self.match_token(TokenKind::Punc(Punc::RParen), false, true);
+
+    setup_first! { self : (kw Gosub) }
+    setup_follow! { self, old_follow : (label) }
+    let _ = self.match_token(TokenKind::Keyword(Keyword::Gosub), true, true);
+
+    let mut label = None;
+    if self.token.1 == TokenKind::Label {
+      match self.label_value.take().unwrap() {
+        Ok(l) => {
+          label = Some((self.token.0.clone(), l));
+        }
+        Err(err) => self.report_label_error(err, self.token.0.clone()),
+      }
+      self.read_token(false);
+    }
+
+    self.node_builder.new_stmt(Stmt {
+      kind: StmtKind::OnTimer { interval, label },
+      range: Range::new(start, self.last_token_end),
+    })
+  }
+
   fn parse_open_stmt(&mut self) -> StmtId {
     let _first_symbols = self.first_symbols.backup();
     let old_follow = self.follow_symbols.backup();
@@ -1568,6 +1655,28 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
   }
 
   fn parse_poke_stmt(&mut self) -> StmtId {
+    self.parse_binary_cmd(
+      |addr, value| StmtKind::Poke { addr, value },
+      "地址表达式之后缺少逗号",
+    )
+  }
+
+  fn parse_sound_stmt(&mut self) -> StmtId {
+    self.parse_binary_cmd(
+      |frequency, duration| StmtKind::Sound {
+        frequency,
+        duration,
+      },
+      "频率表达式之后缺少逗号",
+    )
+  }
+
+  /// Parses a statement with two arguments separated by a comma.
+  fn parse_binary_cmd(
+    &mut self,
+    ctor: fn(ExprId, ExprId) -> StmtKind,
+    missing_comma: &str,
+  ) -> StmtId {
     let _first_symbols = self.first_symbols.backup();
     let old_follow = self.follow_symbols.backup();
     let start = self.token.0.start;
@@ -1575,7 +1684,7 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
 
     setup_first! { self : }
     setup_follow! { self, old_follow : (punc Comma) }
-    let addr = self.parse_expr();
+    let first = self.parse_expr();
 
     setup_first! { self : (punc Comma) }
     setup_follow! { self, old_follow : (t Expr) }
@@ -1583,19 +1692,19 @@ impl<'a, T: NodeBuilder> LineParser<'a, T> {
       .match_token(TokenKind::Punc(Punc::Comma), false, false)
       .is_err()
     {
-      let addr = self.node_builder.expr_node(addr);
-      if !matches!(&addr.kind, ExprKind::Error) {
-        let range = addr.range.clone();
-        self.add_error(range, "地址表达式之后缺少逗号");
+      let first = self.node_builder.expr_node(first);
+      if !matches!(&first.kind, ExprKind::Error) {
+        let range = first.range.clone();
+        self.add_error(range, missing_comma);
       }
     }
 
     setup_first! { self : }
     setup_follow! { self, old_follow : }
-    let value = self.parse_expr();
+    let second = self.parse_expr();
 
     self.node_builder.new_stmt(Stmt {
-      kind: StmtKind::Poke { addr, value },
+      kind: ctor(first, second),
       range: Range::new(start, self.last_token_end),
     })
   }
@@ -2470,7 +2579,7 @@ mod lex_tests {
 
   fn read_tokens(input: &str) -> Vec<(Range, TokenKind)> {
     let input = Utf16String::from(input);
-    let mut parser = LineParser::new(&input, DummyNodeBuilder);
+    let mut parser = LineParser::new(&input, DummyNodeBuilder, &Dialect::FULL);
     let mut tokens = vec![];
     loop {
       parser.read_token(false);
@@ -2700,6 +2809,19 @@ mod parser_tests {
     assert_snapshot!(parse_line(line).0.to_string(line));
   }
 
+  #[test]
+  fn dialect() {
+    let line = utf16str!(r#"10 sleep=1:print fopen"#);
+    assert!(!parse_line(line).0.diagnostics.is_empty());
+
+    let dialect = Dialect::without(["sleep", "fopen"]).unwrap();
+    let (parsed, _) = parse_line_in_dialect(line, &dialect);
+    assert_eq!(parsed.diagnostics, vec![]);
+    let parsed = parsed.to_string(line);
+    assert!(parsed.contains("LET <ID: sleep> = <NUM: 1>"), "{parsed}");
+    assert!(parsed.contains("PRINT <ID: fopen>"), "{parsed}");
+  }
+
   #[test]
   fn blank_line() {
     let line = utf16str!(r#"    "#);
@@ -2868,6 +2990,26 @@ mod parser_tests {
     assert_snapshot!(parse_line(line).0.to_string(line));
   }
 
+  #[test]
+  fn on_timer() {
+    let line = utf16str!(
+      r#"10 ON timer(k+2) goSub 100:on timer (1)gosub:timer on:TIMER OFF"#
+    );
+    assert_snapshot!(parse_line(line).0.to_string(line));
+  }
+
+  #[test]
+  fn timer_without_on_off() {
+    let line = utf16str!(r#"10 timer x:timer"#);
+    assert_snapshot!(parse_line(line).0.to_string(line));
+  }
+
+  #[test]
+  fn sound() {
+    let line = utf16str!(r#"10 sound 440*k,  100 :SOUND f 20:sound"#);
+    assert_snapshot!(parse_line(line).0.to_string(line));
+  }
+
   #[test]
   fn open1() {
     let line =
@@ -2933,6 +3075,18 @@ mod parser_tests {
     assert_snapshot!(parse_line(line).0.to_string(line));
   }
 
+  #[test]
+  fn expected_symbols() {
+    let line = utf16str!(r#"."#);
+    let diags = parse_line(line).0.diagnostics;
+    let diag = diags.iter().find(|diag| !diag.expected.is_empty()).unwrap();
+    assert_eq!(
+      diag.expected,
+      vec![ExpectedSymbol::Punc(":".to_owned()), ExpectedSymbol::Stmt]
+    );
+    assert_eq!(diag.message, expected_symbols_message(&diag.expected));
+  }
+
   #[test]
   fn program() {
     let prog = utf16str!(
@@ -0,0 +1,236 @@
+use super::{ArenaNodeBuilder, LineParser};
+use crate::ast::{Keyword, NodeBuilder, Punc, StmtKind, TokenKind};
+use crate::dialect::Dialect;
+use id_arena::Arena;
+use widestring::{Utf16Str, Utf16String};
+
+/// Renders a line (without newline) in canonical form: keywords, function
+/// names and identifiers are upper-cased, and tokens are separated by at most
+/// one space. String literals, DATA and REM contents are kept as they are.
+///
+/// Returns `None` if the line cannot be rendered without changing its
+/// meaning, e.g. the line contains illegal characters.
+pub fn canonicalize_line(
+  line: &Utf16Str,
+  dialect: &Dialect,
+) -> Option<Utf16String> {
+  let (text, tokens) = render_line(line, dialect)?;
+  let (text2, tokens2) = render_line(&text, dialect)?;
+  if tokens == tokens2 && text == text2 {
+    Some(text)
+  } else {
+    None
+  }
+}
+
+pub(crate) fn render_line(
+  line: &Utf16Str,
+  dialect: &Dialect,
+) -> Option<(Utf16String, Vec<TokenKind>)> {
+  let node_builder = ArenaNodeBuilder {
+    stmt_arena: Arena::new(),
+    expr_arena: Arena::new(),
+  };
+  let mut parser = LineParser::new(line, node_builder, dialect);
+  let mut text = Utf16String::new();
+  let mut tokens: Vec<TokenKind> = vec![];
+
+  parser.read_token(true);
+  while parser.token.1 != TokenKind::Eof {
+    let (range, kind) = parser.token.clone();
+    if tokens.len() == 1 || need_space(tokens.last().copied(), kind) {
+      text.push(' ');
+    }
+    tokens.push(kind);
+
+    match kind {
+      TokenKind::Keyword(Keyword::Data) => {
+        push_upper(&mut text, &line[range.range()]);
+        let stmt = parser.parse_data_stmt();
+        let mut data = Utf16String::new();
+        if let StmtKind::Data(datum) = &parser.node_builder.stmt_node(stmt).kind
+        {
+          for (i, datum) in datum.iter().enumerate() {
+            if i != 0 {
+              data.push(',');
+            }
+            data.push_utfstr(&line[datum.range.range()]);
+          }
+        }
+        if !data.is_empty() {
+          text.push(' ');
+          text.push_utfstr(&data);
+        }
+        // the token following DATA statement has been read
+        continue;
+      }
+      TokenKind::Keyword(kw) if is_rem_like(kw) => {
+        push_upper(&mut text, &line[range.range()]);
+        parser.skip_space();
+        let content = parser.input.as_slice();
+        let len = content.len()
+          - content
+            .iter()
+            .rev()
+            .take_while(|&&c| c == b' ' as u16)
+            .count();
+        if len != 0 {
+          text.push(' ');
+          text.push_utfstr(&parser.input[..len]);
+        }
+        parser.skip_line();
+      }
+      TokenKind::String => text.push_utfstr(&line[range.range()]),
+      TokenKind::Float | TokenKind::Label => {
+        for c in line[range.range()].chars() {
+          if c != ' ' {
+            text.push(c.to_ascii_uppercase());
+          }
+        }
+      }
+      _ => push_upper(&mut text, &line[range.range()]),
+    }
+
+    parser.read_token(false);
+  }
+
+  if parser.diagnostics.is_empty() {
+    Some((text, tokens))
+  } else {
+    None
+  }
+}
+
+/// Pushes the upper-cased `str`, collapsing consecutive spaces into one.
+fn push_upper(text: &mut Utf16String, str: &Utf16Str) {
+  let mut last_is_space = false;
+  for c in str.chars() {
+    let is_space = c == ' ';
+    if !(is_space && last_is_space) {
+      text.push(c.to_ascii_uppercase());
+    }
+    last_is_space = is_space;
+  }
+}
+
+fn is_rem_like(kw: Keyword) -> bool {
+  use Keyword::*;
+  matches!(
+    kw,
+    Auto
+      | Copy
+      | Del
+      | Edit
+      | Files
+      | Kill
+      | List
+      | Load
+      | New
+      | Rem
+      | Rename
+      | Run
+      | Save
+      | Stop
+  )
+}
+
+/// INKEY$ is used like a variable, so it is not separated by spaces.
+fn is_spaced_keyword(kind: TokenKind) -> bool {
+  matches!(kind, TokenKind::Keyword(kw) if kw != Keyword::Inkey)
+}
+
+fn is_word(kind: TokenKind) -> bool {
+  !matches!(kind, TokenKind::Punc(_) | TokenKind::Eof)
+}
+
+fn need_space(prev: Option<TokenKind>, next: TokenKind) -> bool {
+  let prev = match prev {
+    Some(prev) => prev,
+    None => return false,
+  };
+  if is_spaced_keyword(prev) {
+    !matches!(
+      next,
+      TokenKind::Punc(
+        Punc::Colon | Punc::RParen | Punc::Comma | Punc::Semicolon
+      )
+    )
+  } else if is_spaced_keyword(next) {
+    !matches!(prev, TokenKind::Punc(p) if p != Punc::RParen)
+  } else {
+    is_word(prev) && is_word(next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use widestring::utf16str;
+
+  fn canonicalize(line: &str) -> Option<String> {
+    canonicalize_line(&Utf16String::from(line), &Dialect::FULL)
+      .map(|s| s.to_string())
+  }
+
+  #[test]
+  fn keywords_and_spaces() {
+    assert_eq!(
+      canonicalize("10 for  i=1to 10 step-1:print i;chr$( 65 ),:next i"),
+      Some("10 FOR I=1 TO 10 STEP -1:PRINT I;CHR$(65),:NEXT I".to_owned())
+    );
+    assert_eq!(
+      canonicalize("20 if a>1 and(b<2)then 30 else goto 40"),
+      Some("20 IF A>1 AND (B<2) THEN 30 ELSE GOTO 40".to_owned())
+    );
+    assert_eq!(
+      canonicalize("30 a$=inkey$:def fn f(x)=x*x:y=fn f( 2)"),
+      Some("30 A$=INKEY$:DEF FN F(X)=X*X:Y=FN F(2)".to_owned())
+    );
+    assert_eq!(
+      canonicalize(r#"40 open "a"for input as#1:input #1,a$"#),
+      Some(r#"40 OPEN "a" FOR INPUT AS#1:INPUT #1,A$"#.to_owned())
+    );
+  }
+
+  #[test]
+  fn verbatim_parts() {
+    assert_eq!(
+      canonicalize(r#"10 print "a  b":rem  Hello,  World  "#),
+      Some(r#"10 PRINT "a  b":REM Hello,  World"#.to_owned())
+    );
+    assert_eq!(
+      canonicalize(r#"20 data  1, "a:b" ,x  y:print"#),
+      Some(r#"20 DATA 1,"a:b",x  y:PRINT"#.to_owned())
+    );
+    assert_eq!(canonicalize("30 data"), Some("30 DATA".to_owned()));
+    assert_eq!(
+      canonicalize(r#"40 print "abc  "#),
+      Some(r#"40 PRINT "abc  "#.to_owned())
+    );
+  }
+
+  #[test]
+  fn numbers() {
+    assert_eq!(
+      canonicalize("0010 a=1 0.5e-3+.5"),
+      Some("0010 A=10.5E-3+.5".to_owned())
+    );
+  }
+
+  #[test]
+  fn illegal_character() {
+    assert_eq!(canonicalize("10 print 1@2"), None);
+  }
+
+  #[test]
+  fn idempotent() {
+    let line = utf16str!("10 locate 2 ,3:print  a b  ;:draw 1,2 ,1");
+    let canonical = canonicalize_line(line, &Dialect::FULL).unwrap();
+    assert_eq!(canonical.to_string(), "10 LOCATE 2,3:PRINT A B;:DRAW 1,2,1");
+    assert_eq!(
+      canonicalize_line(&canonical, &Dialect::FULL),
+      Some(canonical)
+    );
+  }
+}
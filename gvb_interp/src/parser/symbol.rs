@@ -1,4 +1,5 @@
 use super::super::ast::*;
+use crate::diagnostic::ExpectedSymbol;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use std::{
@@ -125,6 +126,28 @@ impl From<Nonterminal> for Symbol {
   }
 }
 
+impl From<Symbol> for ExpectedSymbol {
+  fn from(sym: Symbol) -> Self {
+    match sym {
+      Symbol::Term(token) => match token {
+        TokenKind::Ident => Self::Ident,
+        TokenKind::Label => Self::Label,
+        TokenKind::Float => Self::Real,
+        TokenKind::String => Self::String,
+        TokenKind::Punc(p) => Self::Punc(format!("{p:?}")),
+        TokenKind::Keyword(kw) => Self::Keyword(format!("{kw:?}")),
+        TokenKind::SysFunc(f) => Self::SysFunc(format!("{f:?}")),
+        TokenKind::Eof => Self::Eol,
+      },
+      Symbol::Nonterm(n) => match n {
+        Nonterminal::Expr => Self::Expr,
+        Nonterminal::Stmt => Self::Stmt,
+        Nonterminal::Array => Self::Array,
+      },
+    }
+  }
+}
+
 impl Nonterminal {
   pub const EXPR_FIRST_SYMBOLS: SymbolSet = {
     let mut set = SymbolSet::new();
@@ -0,0 +1,246 @@
+//! Run reports, which render a headless run of a program as a standalone HTML
+//! page, so that it can be shared without screenshotting the simulator.
+//!
+//! A report consists of the program listing with executed lines highlighted,
+//! screenshots taken before each CLS and at the end, the text output, and the
+//! error if the program fails.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use widestring::Utf16Str;
+
+use crate::device::default::screen;
+use crate::{ExecResult, Location};
+
+/// Events recorded by the device during a run. See
+/// [`DefaultDevice::start_recording`](crate::device::default::DefaultDevice::start_recording).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+  /// Indices of lines containing executed statements.
+  pub executed_lines: BTreeSet<usize>,
+  pub screenshots: Vec<Screenshot>,
+  /// Text printed to the screen. A newline is recorded for each line break,
+  /// even if the cursor is already at the first column.
+  pub output: String,
+}
+
+/// Contents of the screen right before it is cleared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+  /// Index of the line of the statement clearing the screen.
+  pub line: Option<usize>,
+  /// 160x80 pixels with 1 bit per pixel, in the same layout as
+  /// `DefaultDevice::graphic_memory`.
+  pub pixels: Vec<u8>,
+}
+
+pub struct RunReport<'a> {
+  pub text: &'a Utf16Str,
+  pub recording: &'a Recording,
+  /// Contents of the screen when the report is made.
+  pub screen: &'a [u8],
+  /// The last result returned by the VM.
+  pub result: &'a ExecResult,
+}
+
+impl Recording {
+  pub(crate) fn screenshot(&mut self, line: Option<usize>, pixels: &[u8]) {
+    // Consecutive CLS statements yield blank screens, which are not worth
+    // showing.
+    if pixels.iter().all(|&b| b == 0) {
+      return;
+    }
+    self.screenshots.push(Screenshot {
+      line,
+      pixels: pixels.to_vec(),
+    });
+  }
+}
+
+impl<'a> RunReport<'a> {
+  pub fn to_html(&self) -> String {
+    let mut html = String::new();
+    html.push_str(
+      "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n\
+      <meta charset=\"utf-8\">\n<title>运行报告</title>\n<style>\n\
+      pre { font-family: monospace; background: #f8f8f8; padding: 8px; }\n\
+      .listing span { display: block; }\n\
+      .executed { background: #e0f0ff; }\n\
+      .error-line { background: #ffe0e0; }\n\
+      mark { background: #ff8080; }\n\
+      figure { display: inline-block; margin: 8px; }\n\
+      svg { border: 1px solid #888; image-rendering: pixelated; }\n\
+      </style>\n</head>\n<body>\n<h1>运行报告</h1>\n",
+    );
+
+    let error = match self.result {
+      ExecResult::Error {
+        location, message, ..
+      } => Some((location, message)),
+      _ => None,
+    };
+
+    html.push_str("<h2>结果</h2>\n<p class=\"result\">");
+    match error {
+      Some((location, message)) => {
+        write!(html, "第 {} 行出错：", location.line + 1).unwrap();
+        push_escaped(&mut html, message);
+      }
+      None if matches!(self.result, ExecResult::End) => {
+        html.push_str("程序运行结束")
+      }
+      None => html.push_str("程序尚未运行结束"),
+    }
+    html.push_str("</p>\n");
+
+    html.push_str("<h2>程序</h2>\n<pre class=\"listing\">");
+    self.write_listing(&mut html, error.map(|(loc, _)| loc));
+    html.push_str("</pre>\n");
+
+    html.push_str("<h2>屏幕</h2>\n");
+    for shot in &self.recording.screenshots {
+      let caption = match shot.line {
+        Some(line) => format!("第 {} 行清屏前", line + 1),
+        None => format!("清屏前"),
+      };
+      write_screen(&mut html, &shot.pixels, &caption);
+    }
+    write_screen(&mut html, self.screen, "最终屏幕");
+    html.push('\n');
+
+    html.push_str("<h2>输出</h2>\n<pre class=\"output\">");
+    push_escaped(&mut html, &self.recording.output);
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+  }
+
+  fn write_listing(&self, html: &mut String, error: Option<&Location>) {
+    let mut lines: Vec<&[u16]> =
+      self.text.as_slice().split(|&c| c == b'\n' as u16).collect();
+    if lines.last().is_some_and(|line| line.is_empty()) {
+      lines.pop();
+    }
+    for (i, line) in lines.into_iter().enumerate() {
+      let line = line.strip_suffix(&[b'\r' as u16]).unwrap_or(line);
+      let error = error.filter(|loc| loc.line == i);
+      let class = if error.is_some() {
+        " class=\"error-line\""
+      } else if self.recording.executed_lines.contains(&i) {
+        " class=\"executed\""
+      } else {
+        ""
+      };
+      write!(html, "<span{}>", class).unwrap();
+      match error {
+        Some(loc) => {
+          let start = loc.range.start.min(line.len());
+          let end = loc.range.end.clamp(start, line.len());
+          push_escaped(html, &String::from_utf16_lossy(&line[..start]));
+          html.push_str("<mark>");
+          push_escaped(html, &String::from_utf16_lossy(&line[start..end]));
+          html.push_str("</mark>");
+          push_escaped(html, &String::from_utf16_lossy(&line[end..]));
+        }
+        None => push_escaped(html, &String::from_utf16_lossy(line)),
+      }
+      html.push_str("</span>");
+    }
+  }
+}
+
+/// Renders the screen as an SVG image, with each horizontal run of pixels as
+/// a subpath.
+fn write_screen(html: &mut String, pixels: &[u8], caption: &str) {
+  write!(
+    html,
+    "<figure><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+    height=\"{}\" viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">\
+    <rect width=\"100%\" height=\"100%\" fill=\"#c0d0a0\"/><path d=\"",
+    screen::WIDTH * 2,
+    screen::HEIGHT * 2,
+    screen::WIDTH,
+    screen::HEIGHT
+  )
+  .unwrap();
+  let pixel = |x: usize, y: usize| {
+    pixels[y * screen::WIDTH_IN_BYTE + x / 8] & (0x80 >> (x & 7)) != 0
+  };
+  for y in 0..screen::HEIGHT {
+    let mut x = 0;
+    while x < screen::WIDTH {
+      if !pixel(x, y) {
+        x += 1;
+        continue;
+      }
+      let start = x;
+      while x < screen::WIDTH && pixel(x, y) {
+        x += 1;
+      }
+      write!(html, "M{} {}h{}v1h-{}z", start, y, x - start, x - start).unwrap();
+    }
+  }
+  html.push_str("\" fill=\"#000\"/></svg><figcaption>");
+  push_escaped(html, caption);
+  html.push_str("</figcaption></figure>");
+}
+
+fn push_escaped(html: &mut String, s: &str) {
+  for c in s.chars() {
+    match c {
+      '<' => html.push_str("&lt;"),
+      '>' => html.push_str("&gt;"),
+      '&' => html.push_str("&amp;"),
+      '"' => html.push_str("&quot;"),
+      _ => html.push(c),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::Range;
+  use pretty_assertions::assert_eq;
+  use widestring::Utf16String;
+
+  #[test]
+  fn html() {
+    let text =
+      Utf16String::from_str("10 cls\r\n20 print \"<a>\"\r\n30 x=1/0\n");
+    let mut pixels = vec![0; screen::BYTES];
+    pixels[0] = 0b0110_0000;
+    let mut recording = Recording {
+      executed_lines: [0, 1, 2].into_iter().collect(),
+      output: "<a>\n".to_owned(),
+      ..Default::default()
+    };
+    recording.screenshot(Some(0), &vec![0; pixels.len()]);
+    recording.screenshot(Some(0), &pixels);
+    let result = ExecResult::Error {
+      location: Location {
+        line: 2,
+        range: Range::new(3, 8),
+      },
+      message: "除以0".to_owned(),
+      loop_context: None,
+    };
+    let html = RunReport {
+      text: &text,
+      recording: &recording,
+      screen: &pixels,
+      result: &result,
+    }
+    .to_html();
+
+    assert!(html.contains("<p class=\"result\">第 3 行出错：除以0</p>"));
+    assert!(html.contains(
+      "<pre class=\"listing\"><span class=\"executed\">10 cls</span>\
+      <span class=\"executed\">20 print &quot;&lt;a&gt;&quot;</span>\
+      <span class=\"error-line\">30 <mark>x=1/0</mark></span></pre>"
+    ));
+    assert_eq!(html.matches("<figure>").count(), 2);
+    assert!(html.contains("<figcaption>第 1 行清屏前</figcaption>"));
+    assert!(html.contains("M1 0h2v1h-2z\""));
+    assert!(html.contains("<pre class=\"output\">&lt;a&gt;\n</pre>"));
+  }
+}
@@ -0,0 +1,228 @@
+//! Decoding of monochrome tiles packed in bytes, the format commonly used by
+//! games to embed sprites in DATA statements or arrays.
+//!
+//! Each row of a tile is packed into bytes, with the most significant bit
+//! being the leftmost pixel, as in the screen buffer. An 8x8 tile takes 8
+//! bytes, and a 16x16 tile takes 32 bytes, two bytes per row.
+
+use crate::vm::DimensionValues;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileSize {
+  Size8,
+  Size16,
+}
+
+impl TileSize {
+  /// Width and height of the tile in pixels.
+  pub fn pixels(self) -> usize {
+    match self {
+      Self::Size8 => 8,
+      Self::Size16 => 16,
+    }
+  }
+
+  /// Number of bytes of a packed tile.
+  pub fn bytes(self) -> usize {
+    self.pixels() * self.pixels() / 8
+  }
+}
+
+/// A monochrome bitmap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+  pub width: usize,
+  pub height: usize,
+  /// Rows of pixels, one bit per pixel. Each row is padded to whole bytes.
+  pub data: Vec<u8>,
+}
+
+impl Bitmap {
+  pub fn new(width: usize, height: usize) -> Self {
+    Self {
+      width,
+      height,
+      data: vec![0; width.div_ceil(8) * height],
+    }
+  }
+
+  pub fn bytes_per_row(&self) -> usize {
+    self.width.div_ceil(8)
+  }
+
+  pub fn pixel(&self, x: usize, y: usize) -> bool {
+    self.data[y * self.bytes_per_row() + x / 8] & (0x80 >> (x % 8)) != 0
+  }
+
+  pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+    let i = y * self.bytes_per_row() + x / 8;
+    if on {
+      self.data[i] |= 0x80 >> (x % 8);
+    } else {
+      self.data[i] &= !(0x80 >> (x % 8));
+    }
+  }
+
+  /// Converts the bitmap to 8-bit grayscale pixels, black for pixels which
+  /// are on and white for the others, e.g. for exporting to PNG.
+  pub fn to_luma8(&self) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(self.width * self.height);
+    for y in 0..self.height {
+      for x in 0..self.width {
+        pixels.push(if self.pixel(x, y) { 0 } else { 255 });
+      }
+    }
+    pixels
+  }
+}
+
+/// Decodes the tiles packed in `bytes`. Trailing bytes which do not make up
+/// a whole tile are ignored.
+pub fn decode_tiles(bytes: &[u8], size: TileSize) -> Vec<Bitmap> {
+  let pixels = size.pixels();
+  bytes
+    .chunks_exact(size.bytes())
+    .map(|chunk| Bitmap {
+      width: pixels,
+      height: pixels,
+      data: chunk.to_vec(),
+    })
+    .collect()
+}
+
+/// Lays out tiles of the same size in a grid of `columns` columns, e.g. for
+/// dumping a sprite sheet.
+pub fn sprite_sheet(tiles: &[Bitmap], columns: usize) -> Bitmap {
+  let (tile_width, tile_height) = tiles
+    .first()
+    .map_or((0, 0), |tile| (tile.width, tile.height));
+  let columns = columns.clamp(1, tiles.len().max(1));
+  let rows = tiles.len().div_ceil(columns);
+  let mut sheet = Bitmap::new(tile_width * columns, tile_height * rows);
+  for (i, tile) in tiles.iter().enumerate() {
+    let left = i % columns * tile_width;
+    let top = i / columns * tile_height;
+    for y in 0..tile_height.min(tile.height) {
+      for x in 0..tile_width.min(tile.width) {
+        sheet.set_pixel(left + x, top + y, tile.pixel(x, y));
+      }
+    }
+  }
+  sheet
+}
+
+/// Converts the values of an array dimension to bytes. Returns None if the
+/// array is a string array, or any value is not an integer in 0~255.
+pub fn bytes_from_values(values: &DimensionValues) -> Option<Vec<u8>> {
+  match values {
+    DimensionValues::Integer(values) => {
+      values.iter().map(|&n| u8::try_from(n).ok()).collect()
+    }
+    DimensionValues::Real(values) => values
+      .iter()
+      .map(|&n| {
+        let n = f64::from(n);
+        if n.fract() == 0.0 && (0.0..=255.0).contains(&n) {
+          Some(n as u8)
+        } else {
+          None
+        }
+      })
+      .collect(),
+    DimensionValues::String(_) => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::mbf5::Mbf5;
+  use pretty_assertions::assert_eq;
+
+  fn render(bitmap: &Bitmap) -> String {
+    let mut s = String::new();
+    for y in 0..bitmap.height {
+      for x in 0..bitmap.width {
+        s.push(if bitmap.pixel(x, y) { '#' } else { '.' });
+      }
+      s.push('\n');
+    }
+    s
+  }
+
+  #[test]
+  fn tile8() {
+    let bytes = [0x18, 0x3c, 0x7e, 0xff, 0x18, 0x18, 0x18, 0x18, 0xff];
+    let tiles = decode_tiles(&bytes, TileSize::Size8);
+    assert_eq!(tiles.len(), 1);
+    assert_eq!(
+      render(&tiles[0]),
+      "\
+...##...
+..####..
+.######.
+########
+...##...
+...##...
+...##...
+...##...
+"
+    );
+  }
+
+  #[test]
+  fn tile16() {
+    let mut bytes = vec![0; 32];
+    bytes[0] = 0x80;
+    bytes[3] = 0x01;
+    bytes[31] = 0xf0;
+    let tiles = decode_tiles(&bytes, TileSize::Size16);
+    assert_eq!(tiles.len(), 1);
+    let tile = &tiles[0];
+    assert!(tile.pixel(0, 0));
+    assert!(tile.pixel(15, 1));
+    assert!((8..12).all(|x| tile.pixel(x, 15)));
+    assert_eq!(tile.data.iter().map(|b| b.count_ones()).sum::<u32>(), 6);
+  }
+
+  #[test]
+  fn sheet() {
+    let bytes = [
+      0xff, 0, 0, 0, 0, 0, 0, 0, //
+      0, 0xff, 0, 0, 0, 0, 0, 0, //
+      0, 0, 0xff, 0, 0, 0, 0, 0,
+    ];
+    let tiles = decode_tiles(&bytes, TileSize::Size8);
+    let sheet = sprite_sheet(&tiles, 2);
+    assert_eq!((sheet.width, sheet.height), (16, 16));
+    assert_eq!(sheet.bytes_per_row(), 2);
+    assert_eq!(&sheet.data[..6], &[0xff, 0, 0, 0xff, 0, 0]);
+    assert_eq!(&sheet.data[16..22], &[0, 0, 0, 0, 0xff, 0]);
+    assert_eq!(sheet.to_luma8()[..9], [0, 0, 0, 0, 0, 0, 0, 0, 255]);
+  }
+
+  #[test]
+  fn values() {
+    assert_eq!(
+      bytes_from_values(&DimensionValues::Integer(vec![0, 255, 16])),
+      Some(vec![0, 255, 16])
+    );
+    assert_eq!(
+      bytes_from_values(&DimensionValues::Integer(vec![0, 256])),
+      None
+    );
+    assert_eq!(
+      bytes_from_values(&DimensionValues::Real(vec![
+        Mbf5::from(3u8),
+        Mbf5::from(128u8)
+      ])),
+      Some(vec![3, 128])
+    );
+    assert_eq!(
+      bytes_from_values(&DimensionValues::Real(vec![
+        Mbf5::try_from(1.5).unwrap()
+      ])),
+      None
+    );
+  }
+}
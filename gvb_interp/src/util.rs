@@ -2,4 +2,5 @@ pub mod mbf5;
 #[macro_use]
 pub mod utf16str_ext;
 
-pub mod ascii_ext;
\ No newline at end of file
+pub mod ascii_ext;
+pub(crate) mod decode;
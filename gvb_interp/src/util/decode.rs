@@ -0,0 +1,138 @@
+//! Batch decoding of byte strings to UTF-16, for hosts converting the text
+//! printed by programs in large chunks.
+//!
+//! The result is the same as [`ByteString::to_string_lossy`] encoded in
+//! UTF-16, but GB2312 characters are looked up in a dense table, and runs of
+//! ASCII characters are copied in bulk, with SIMD if the `simd` feature is
+//! enabled.
+//!
+//! [`ByteString::to_string_lossy`]: crate::vm::r#type::ByteString::to_string_lossy
+
+use crate::gb2312::GB2312_TABLE;
+use crate::machine::EmojiVersion;
+
+/// Returns the Unicode character of a GB2312 code, e.g. `0xB0A1`.
+pub(crate) fn gb2312_to_unicode(code: u16) -> Option<u16> {
+  let hi = (code >> 8) as usize;
+  let lo = (code & 0xff) as usize;
+  if (0xa1..=0xfe).contains(&hi) && (0xa1..=0xfe).contains(&lo) {
+    Some(GB2312_TABLE[(hi - 0xa1) * 94 + lo - 0xa1]).filter(|&u| u != 0)
+  } else {
+    None
+  }
+}
+
+/// Decodes `bytes` and appends the UTF-16 code units to `out`. Characters
+/// which cannot be decoded are replaced by U+FFFD.
+pub(crate) fn decode_to_utf16(
+  bytes: &[u8],
+  emoji_version: EmojiVersion,
+  out: &mut Vec<u16>,
+) {
+  out.reserve(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let ascii_len = ascii_prefix(&bytes[i..], out);
+    i += ascii_len;
+    if i >= bytes.len() {
+      break;
+    }
+    if i + 1 < bytes.len() {
+      let code = ((bytes[i] as u16) << 8) + bytes[i + 1] as u16;
+      i += 2;
+      if let Some(u) = gb2312_to_unicode(code) {
+        out.push(u);
+      } else {
+        let c = emoji_version
+          .code_to_char(code)
+          .or_else(|| EmojiVersion::fallback_code_to_char(code))
+          .unwrap_or(char::REPLACEMENT_CHARACTER);
+        out.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+      }
+    } else {
+      out.push(char::REPLACEMENT_CHARACTER as u16);
+      i += 1;
+    }
+  }
+}
+
+/// Copies the ASCII characters at the start of `bytes` to `out`, and returns
+/// the number of characters copied.
+#[cfg(not(feature = "simd"))]
+fn ascii_prefix(bytes: &[u8], out: &mut Vec<u16>) -> usize {
+  let len = bytes.iter().position(|&b| b >= 0x80).unwrap_or(bytes.len());
+  out.extend(bytes[..len].iter().map(|&b| b as u16));
+  len
+}
+
+#[cfg(feature = "simd")]
+fn ascii_prefix(bytes: &[u8], out: &mut Vec<u16>) -> usize {
+  use std::simd::cmp::SimdPartialOrd;
+  use std::simd::num::SimdUint;
+  use std::simd::{u16x16, u8x16};
+
+  let mut len = 0;
+  let mut chunks = bytes.chunks_exact(16);
+  for chunk in &mut chunks {
+    let chunk = u8x16::from_slice(chunk);
+    if chunk.simd_ge(u8x16::splat(0x80)).any() {
+      break;
+    }
+    let wide: u16x16 = chunk.cast();
+    out.extend_from_slice(wide.as_array());
+    len += 16;
+  }
+  let rest = bytes[len..]
+    .iter()
+    .position(|&b| b >= 0x80)
+    .unwrap_or(bytes.len() - len);
+  out.extend(bytes[len..len + rest].iter().map(|&b| b as u16));
+  len + rest
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vm::r#type::ByteString;
+  use pretty_assertions::assert_eq;
+  use quickcheck_macros::quickcheck;
+
+  fn decode(bytes: &[u8], emoji_version: EmojiVersion) -> Vec<u16> {
+    let mut out = vec![];
+    decode_to_utf16(bytes, emoji_version, &mut out);
+    out
+  }
+
+  fn reference(bytes: &[u8], emoji_version: EmojiVersion) -> Vec<u16> {
+    ByteString::from(bytes.to_vec())
+      .to_string_lossy(emoji_version)
+      .encode_utf16()
+      .collect()
+  }
+
+  #[test]
+  fn decode_mixed() {
+    let bytes =
+      b"HELLO, \xb0\xa1\xb0\xa2 WORLD 0123456789 ABCDEFGH\xf8\xa1\xa1";
+    assert_eq!(
+      decode(bytes, EmojiVersion::V2),
+      reference(bytes, EmojiVersion::V2)
+    );
+    assert_eq!(
+      String::from_utf16(&decode(b"A\xb0\xa1B\xb0", EmojiVersion::V2)).unwrap(),
+      "A啊B\u{fffd}"
+    );
+    assert_eq!(gb2312_to_unicode(0xb0a1), Some('啊' as u16));
+    assert_eq!(gb2312_to_unicode(0xa0a1), None);
+  }
+
+  #[quickcheck]
+  fn same_as_per_string(bytes: Vec<u8>, v1: bool) -> bool {
+    let emoji_version = if v1 {
+      EmojiVersion::V1
+    } else {
+      EmojiVersion::V2
+    };
+    decode(&bytes, emoji_version) == reference(&bytes, emoji_version)
+  }
+}
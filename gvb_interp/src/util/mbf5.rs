@@ -104,9 +104,9 @@ fn f64_to_array(value: &mut f64) -> Result<[u8; 5], RealError> {
   if mant & ROUND_BIT != 0 && mant & LOWEST_BIT != 0 {
     mant >>= MANTISSA_BITS_DIFF;
     mant += 1;
-    // handle carry
+    // handle carry, the mantissa becomes 0.1 (with hidden bit)
     if mant & (1 << EXPONENT_BITS) != 0 {
-      mant >>= 1;
+      mant = 0;
       exp += 1;
     }
   } else {
@@ -497,6 +497,12 @@ mod tests {
     assert_eq!(Ok(17.625), Mbf5::try_from(17.625).map(|x| x.0));
   }
 
+  #[test]
+  fn f64_to_mbf5_accum_round_carry() {
+    let mut x = f64::from_bits(0x3fff_ffff_fff0_0000);
+    assert_eq!(Ok([0x82, 0, 0, 0, 0]), f64_to_array(&mut x));
+  }
+
   #[test]
   fn f64_to_mbf5_accum_max() {
     assert_eq!(
@@ -1018,4 +1024,293 @@ mod tests {
       Err(ParseRealError::Infinite)
     );
   }
+
+  mod reference {
+    //! Property tests checking arithmetic against a reference, which computes
+    //! results exactly with integers and then rounds them, first to f64 to
+    //! nearest with ties to even, then to MBF5 in the way of
+    //! `f64_to_array`.
+
+    use super::*;
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+    const TESTS: u64 = 20_000;
+
+    /// Bytes of an MBF5 number. Exponents are mostly picked near 0x80, so
+    /// that results of arithmetic rarely overflow or underflow.
+    #[derive(Debug, Clone, Copy)]
+    struct Bytes([u8; 5]);
+
+    impl Arbitrary for Bytes {
+      fn arbitrary(g: &mut Gen) -> Self {
+        let exp = match u8::arbitrary(g) % 16 {
+          0 => 0,
+          1..=3 => u8::arbitrary(g),
+          _ => 0x60 + u8::arbitrary(g) % 0x40,
+        };
+        Self([
+          exp,
+          u8::arbitrary(g),
+          u8::arbitrary(g),
+          u8::arbitrary(g),
+          u8::arbitrary(g),
+        ])
+      }
+    }
+
+    /// `(-1)^neg * mant * 2^exp`. `sticky` is true if the value has nonzero
+    /// bits below `mant`.
+    #[derive(Debug, Clone, Copy)]
+    struct Exact {
+      neg: bool,
+      mant: u128,
+      exp: i32,
+      sticky: bool,
+    }
+
+    impl From<Bytes> for Exact {
+      fn from(Bytes([exp, m1, m2, m3, m4]): Bytes) -> Self {
+        let mant = if exp == 0 {
+          0
+        } else {
+          u32::from_be_bytes([m1 | 0x80, m2, m3, m4]) as u128
+        };
+        Self {
+          neg: m1 & 0x80 != 0,
+          mant,
+          exp: exp as i32 - 128 - 32,
+          sticky: false,
+        }
+      }
+    }
+
+    fn exact_add(a: Exact, b: Exact) -> Exact {
+      if a.mant == 0 {
+        return b;
+      } else if b.mant == 0 {
+        return a;
+      }
+      let (hi, lo) = if a.exp >= b.exp { (a, b) } else { (b, a) };
+      let shift = hi.exp - lo.exp;
+      if shift <= 90 {
+        let signed = |x: Exact, mant: u128| {
+          if x.neg {
+            -(mant as i128)
+          } else {
+            mant as i128
+          }
+        };
+        let sum = signed(hi, hi.mant << shift) + signed(lo, lo.mant);
+        Exact {
+          neg: sum < 0,
+          mant: sum.unsigned_abs(),
+          exp: lo.exp,
+          sticky: false,
+        }
+      } else {
+        // `lo` is less than the lowest bit of `hi.mant << 58`.
+        let mant = hi.mant << 58;
+        Exact {
+          neg: hi.neg,
+          mant: if hi.neg == lo.neg { mant } else { mant - 1 },
+          exp: hi.exp - 58,
+          sticky: true,
+        }
+      }
+    }
+
+    fn exact_neg(a: Exact) -> Exact {
+      Exact { neg: !a.neg, ..a }
+    }
+
+    fn exact_mul(a: Exact, b: Exact) -> Exact {
+      Exact {
+        neg: a.neg != b.neg,
+        mant: a.mant * b.mant,
+        exp: a.exp + b.exp,
+        sticky: false,
+      }
+    }
+
+    fn exact_div(a: Exact, b: Exact) -> Exact {
+      Exact {
+        neg: a.neg != b.neg,
+        mant: (a.mant << 90) / b.mant,
+        exp: a.exp - 90 - b.exp,
+        sticky: (a.mant << 90) % b.mant != 0,
+      }
+    }
+
+    /// Returns the rounded value and its bytes.
+    fn round(x: Exact) -> Result<(f64, [u8; 5]), RealError> {
+      if x.mant == 0 {
+        return Ok((0.0, [0; 5]));
+      }
+
+      let bits = 128 - x.mant.leading_zeros() as i32;
+      let (mut mant, mut exp) = if bits > 53 {
+        let shift = bits - 53;
+        let kept = x.mant >> shift;
+        let rest = x.mant & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let up = rest > half || rest == half && (x.sticky || kept & 1 != 0);
+        (kept + up as u128, x.exp + shift)
+      } else {
+        assert!(!x.sticky);
+        (x.mant << (53 - bits), x.exp - (53 - bits))
+      };
+      if mant >> 53 != 0 {
+        mant >>= 1;
+        exp += 1;
+      }
+      let value = mant as f64 * 2f64.powi(exp);
+
+      let mut mbf_mant = mant >> 21;
+      let mut mbf_exp = exp + 21 + 32 + 128;
+      if mant & (1 << 20) != 0 && mant & (1 << 21) != 0 {
+        mbf_mant += 1;
+        if mbf_mant >> 32 != 0 {
+          mbf_mant >>= 1;
+          mbf_exp += 1;
+        }
+      }
+      if mbf_exp > 0xff {
+        return Err(RealError::Infinite);
+      } else if mbf_exp <= 0 {
+        return Ok((0.0, [0; 5]));
+      }
+
+      let [m1, m2, m3, m4] = (mbf_mant as u32).to_be_bytes();
+      Ok((
+        if x.neg { -value } else { value },
+        [mbf_exp as u8, m1 & 0x7f | (x.neg as u8) << 7, m2, m3, m4],
+      ))
+    }
+
+    fn check(actual: CalcResult, expected: Exact) -> TestResult {
+      let actual = actual.map(|x| (x.into(), x.into()));
+      let expected = round(expected);
+      if actual == expected {
+        TestResult::passed()
+      } else {
+        TestResult::error(format!("{:?} != {:?}", actual, expected))
+      }
+    }
+
+    #[test]
+    fn add() {
+      fn prop(a: Bytes, b: Bytes) -> TestResult {
+        check(
+          Mbf5::from(a.0) + Mbf5::from(b.0),
+          exact_add(a.into(), b.into()),
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn sub() {
+      fn prop(a: Bytes, b: Bytes) -> TestResult {
+        check(
+          Mbf5::from(a.0) - Mbf5::from(b.0),
+          exact_add(a.into(), exact_neg(b.into())),
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn mul() {
+      fn prop(a: Bytes, b: Bytes) -> TestResult {
+        check(
+          Mbf5::from(a.0) * Mbf5::from(b.0),
+          exact_mul(a.into(), b.into()),
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn div() {
+      fn prop(a: Bytes, b: Bytes) -> TestResult {
+        if b.0[0] == 0 {
+          return TestResult::discard();
+        }
+        check(
+          Mbf5::from(a.0) / Mbf5::from(b.0),
+          exact_div(a.into(), b.into()),
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _) -> _);
+    }
+
+    /// `powf` is not correctly rounded, so the result is only checked to be
+    /// within one unit in the last place of f64 of the reference.
+    #[test]
+    fn pow() {
+      fn prop(a: Bytes, cube: bool) -> TestResult {
+        if !(0x60..0xa0).contains(&a.0[0]) {
+          return TestResult::discard();
+        }
+        let x = Exact::from(a);
+        let (n, exact) = if cube {
+          (3u8, exact_mul(exact_mul(x, x), x))
+        } else {
+          (2, exact_mul(x, x))
+        };
+        let actual = f64::from(Mbf5::from(a.0).pow(Mbf5::from(n)).unwrap());
+        let (expected, _) = round(exact).unwrap();
+        TestResult::from_bool(
+          (actual - expected).abs() <= expected.abs() * f64::EPSILON,
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+      fn prop(a: Bytes) -> bool {
+        let bytes = <[u8; 5]>::from(Mbf5::from(a.0));
+        if a.0[0] == 0 {
+          bytes == [0; 5]
+        } else {
+          bytes == a.0
+        }
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_) -> _);
+    }
+
+    #[test]
+    fn from_f64() {
+      fn prop(neg: bool, exp: u8, mant: u64) -> TestResult {
+        let exp = 1023 - 0x100 + exp as u64 * 2;
+        let bits = (neg as u64) << 63 | exp << 52 | mant & ((1 << 52) - 1);
+        let value = f64::from_bits(bits);
+        check(
+          Mbf5::try_from(value),
+          Exact {
+            neg,
+            mant: (bits & ((1 << 52) - 1) | 1 << 52) as u128,
+            exp: exp as i32 - 1023 - 52,
+            sticky: false,
+          },
+        )
+      }
+      QuickCheck::new()
+        .tests(TESTS)
+        .quickcheck(prop as fn(_, _, _) -> _);
+    }
+  }
 }
@@ -1,29 +1,65 @@
-use bstr::{ByteSlice, ByteVec};
-use nanorand::{Rng, SeedableRng, WyRand};
+use nanorand::{Rng, WyRand};
 use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::num::{NonZeroU16, NonZeroUsize};
+use std::ops::{Deref, DerefMut};
 use std::time::Duration;
 use widestring::Utf16Str;
 
-use crate::ast::{self, Range, SysFuncKind};
+use crate::ast::Range;
 use crate::compiler::compile_fn_body;
-use crate::device::{AsmExecState, Device, DrawMode, FileHandle, KeyCode};
-use crate::diagnostic::{contains_errors, Diagnostic};
-use crate::machine::{EmojiVersion, EofBehavior};
-use crate::parser::{parse_expr, read_number};
-use crate::util::mbf5::{Mbf5, ParseRealError, RealError};
+use crate::device::{Device, DrawMode, FileHandle};
+use crate::diagnostic::{contains_errors, Diagnostic, DiagnosticPhase};
+use crate::machine::{
+  EmojiVersion, IntOverflow, InvalidNotes, SelectorRounding,
+};
+use crate::parser::parse_expr;
+use crate::util::mbf5::Mbf5;
 use crate::util::utf16str_ext::Utf16StrExt;
-use crate::{HashMap, HashMapEntry};
+use crate::{HashMap, StoreMap, StoreMapEntry};
 
+pub use self::builder::{
+  BuildVmError, ParseVmOptionsError, ProfileOverrides, SpeedMode, VmBuilder,
+  VmOptions, VmOptionsError,
+};
 pub(crate) use self::codegen::*;
+use self::coerce::*;
+use self::event::EventQueue;
+pub use self::event::{VmEvent, MAX_EVENTS};
+pub use self::fault::*;
+pub use self::group::{
+  Divergence, DivergenceKind, GroupInput, GroupReport, VmGroup,
+};
 pub(crate) use self::instruction::*;
+pub use self::instruction::{Addr, DatumIndex, Instr, InstrKind, Location};
+use self::output_quota::OutputQuotaState;
+pub use self::output_quota::{OutputQuota, OutputQuotaKind};
+use self::print_buffer::PrintBuffer;
+pub use self::print_buffer::PrintFlushPolicy;
 pub(crate) use self::r#type::*;
+use self::soft_limit::SoftLimitState;
+pub use self::soft_limit::{Resource, ResourceUsage, SoftLimits};
+pub use self::source_map::SourceMap;
+use self::string_array::StringArray;
+pub use self::write_loss::*;
 
+mod builder;
 pub(crate) mod codegen;
-pub(crate) mod instruction;
+mod coerce;
+mod event;
+mod exec;
+mod fault;
+mod group;
+mod input;
+pub mod instruction;
+mod output_quota;
+mod print_buffer;
+mod soft_limit;
+mod source_map;
+mod string_array;
 pub mod r#type;
+mod write_loss;
 
 use string_interner::DefaultSymbol as Symbol;
 use string_interner::StringInterner;
@@ -37,6 +73,38 @@ pub(crate) struct Datum {
 
 const NUM_FILES: usize = 3;
 
+/// PEEK of this address returns the policy flags of the VM instead of the
+/// memory content in every mode, so that programs can reliably detect the
+/// mode. Bit 0 is set if the VM is in read-only mode, and the other bits are
+/// always clear.
+pub const POLICY_PEEK_ADDR: u16 = 0xffff;
+
+/// The device of a VM, which is either borrowed or owned by the VM.
+enum DeviceSlot<'d, D> {
+  Borrowed(&'d mut D),
+  Owned(Box<D>),
+}
+
+impl<D> Deref for DeviceSlot<'_, D> {
+  type Target = D;
+
+  fn deref(&self) -> &D {
+    match self {
+      Self::Borrowed(device) => device,
+      Self::Owned(device) => device,
+    }
+  }
+}
+
+impl<D> DerefMut for DeviceSlot<'_, D> {
+  fn deref_mut(&mut self) -> &mut D {
+    match self {
+      Self::Borrowed(device) => device,
+      Self::Owned(device) => device,
+    }
+  }
+}
+
 pub struct VirtualMachine<'d, D: Device> {
   emoji_version: EmojiVersion,
   data: Vec<Datum>,
@@ -44,6 +112,12 @@ pub struct VirtualMachine<'d, D: Device> {
   pc: usize,
   code: Vec<Instr>,
   code_len: usize,
+  source_map: SourceMap,
+  /// Addresses of the temporary breakpoint set by `run_to`, in ascending
+  /// order.
+  run_to_addrs: Vec<usize>,
+  /// Index of the statement last passed to `Device::set_context`.
+  context_stmt: Option<usize>,
   control_stack: Vec<ControlRecord>,
   num_stack: Vec<(Location, Mbf5)>,
   str_stack: Vec<(Location, ByteString)>,
@@ -51,18 +125,70 @@ pub struct VirtualMachine<'d, D: Device> {
   interner: StringInterner,
   bindings: Bindings,
   fn_call_stack: Vec<FnCallRecord>,
-  device: &'d mut D,
+  device: DeviceSlot<'d, D>,
   files: [VmFile<D::File>; NUM_FILES],
   rng: WyRand,
+  /// The RNG is seeded randomly if None.
+  rng_seed: Option<u64>,
   current_rand: u32,
   state: ExecState<D::AsmState>,
+  last_arith_fault: Option<ArithFault>,
+  arith_fault_stats: ArithFaultStats,
+  lossy_writes: Vec<LossyWrite>,
+  /// Values last entered for each INPUT statement, keyed by the address of
+  /// the instruction. Kept across runs.
+  input_history: HashMap<usize, Vec<Option<String>>>,
+  /// Set by ON TIMER statement.
+  timer: Option<Timer>,
+  soft_limits: SoftLimitState,
+  read_only: bool,
+  /// Reports non-integral selectors of ON statements.
+  selector_warning: bool,
+  /// Warning raised by the instruction being executed, returned after the
+  /// instruction is done.
+  pending_warning: Option<(Location, String)>,
+  speed_mode: SpeedMode,
+  /// Time the program has run in `SpeedMode::Authentic` since the host last
+  /// waited.
+  pacing_debt: Duration,
+  profile_overrides: ProfileOverrides,
+  step_hook: Option<StepHookState<'d>>,
+  print_buffer: PrintBuffer,
+  output_quota: OutputQuotaState,
+  events: EventQueue,
+}
+
+/// Hook called periodically during `exec`, so that hosts running the VM on a
+/// single thread can handle UI events in time.
+pub trait StepHook {
+  /// Returns true to make `exec` return `ExecResult::Yield` immediately.
+  fn on_steps(&mut self) -> bool;
+}
+
+impl<F> StepHook for F
+where
+  F: FnMut() -> bool,
+{
+  fn on_steps(&mut self) -> bool {
+    self()
+  }
+}
+
+struct StepHookState<'d> {
+  interval: NonZeroUsize,
+  counter: usize,
+  hook: Box<dyn StepHook + 'd>,
 }
 
 #[derive(Default)]
 struct Bindings {
-  vars: HashMap<Symbol, Value>,
-  arrays: HashMap<Symbol, Array>,
-  user_funcs: HashMap<Symbol, UserFunc>,
+  vars: StoreMap<Symbol, Value>,
+  arrays: StoreMap<Symbol, Array>,
+  user_funcs: StoreMap<Symbol, UserFunc>,
+  /// Total length of strings in `vars` and `arrays`.
+  string_bytes: usize,
+  /// Total memory size of `arrays`. See [`ResourceUsage`].
+  array_bytes: usize,
 }
 
 pub enum Binding {
@@ -98,6 +224,20 @@ enum ExecState<S> {
   },
 }
 
+/// A FOR or WHILE loop being executed when an error occurs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopContext {
+  pub kind: LoopKind,
+  /// Location of the FOR or WHILE statement.
+  pub location: Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+  For,
+  While,
+}
+
 #[derive(Debug, Clone)]
 enum ControlRecord {
   ForLoop(ForLoopRecord),
@@ -113,6 +253,19 @@ struct ForLoopRecord {
   step: Mbf5,
 }
 
+/// Timer set by ON TIMER statement. The time is measured in executed
+/// instructions, so that timer events are deterministic.
+#[derive(Debug, Clone)]
+struct Timer {
+  interval: u32,
+  handler: Addr,
+  enabled: bool,
+  /// Instructions executed since the last timer event.
+  elapsed: u32,
+  /// Length of the control stack before the running handler is called.
+  handler_depth: Option<usize>,
+}
+
 #[derive(Debug, Clone)]
 struct FnCallRecord {
   param: Symbol,
@@ -151,7 +304,7 @@ struct Dimension {
 enum ArrayData {
   Integer(Vec<i16>),
   Real(Vec<Mbf5>),
-  String(Vec<ByteString>),
+  String(StringArray),
 }
 
 #[derive(Debug, Clone)]
@@ -171,14 +324,78 @@ pub enum ExecResult {
     prompt: Option<String>,
     /// Fields may be empty. If so, resume execution immediately.
     fields: Vec<KeyboardInputType>,
+    /// Values entered for the fields the last time this INPUT statement was
+    /// executed in the VM, as suggested defaults. Same length as `fields`.
+    /// Values of function fields are not remembered.
+    defaults: Vec<Option<String>>,
   },
   InKey,
   Error {
     location: Location,
     message: String,
+    /// The innermost loop being executed, if the error is likely caused by
+    /// the loop, e.g. a string growing in each iteration.
+    loop_context: Option<LoopContext>,
+  },
+  /// The usage of a resource reaches the threshold of its soft limit. See
+  /// [`SoftLimits`]. Execution can be resumed by calling `exec` with
+  /// `ExecInput::None`.
+  Warning {
+    location: Location,
+    message: String,
+  },
+  /// The step hook requests to return early. Execution can be resumed by
+  /// calling `exec` with `ExecInput::None`.
+  Yield,
+  /// The program has printed as much text as allowed by the
+  /// [`OutputQuota`]. `location` is where the text printed last comes from.
+  /// Execution can be resumed by calling `exec` with `ExecInput::None`.
+  OutputQuotaExceeded {
+    location: Location,
+    kind: OutputQuotaKind,
+    /// Bytes counted against the quota.
+    printed: usize,
+  },
+  /// The temporary breakpoint set by `run_to` is reached. The statement at
+  /// `location` has not been executed yet.
+  Breakpoint {
+    location: Location,
   },
 }
 
+impl ExecResult {
+  /// Converts runtime errors and warnings to diagnostics of the runtime
+  /// phase, along with the line numbers, so that they can be shown with other
+  /// diagnostics of the document.
+  pub fn diagnostic(&self) -> Option<(usize, Diagnostic)> {
+    let (location, diag) = match self {
+      Self::Error {
+        location, message, ..
+      } => (
+        location,
+        Diagnostic::new_error(location.range.clone(), message),
+      ),
+      Self::Warning { location, message } => (
+        location,
+        Diagnostic::new_warning(location.range.clone(), message),
+      ),
+      _ => return None,
+    };
+    Some((location.line, diag.with_phase(DiagnosticPhase::Runtime)))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipStatementError {
+  /// The program is not running, or it is waiting for input.
+  NotRunning,
+  /// The current instruction does not belong to any statement.
+  NoStatement,
+  /// The current statement is partially executed, e.g. a user-defined
+  /// function is being called.
+  UnbalancedStack,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyboardInputType {
   String,
@@ -194,6 +411,7 @@ pub enum ExecInput {
 }
 
 pub enum KeyboardInput {
+  /// Truncated to the maximum length of strings.
   String(ByteString),
   Integer(i16),
   Real(Mbf5),
@@ -251,6 +469,16 @@ where
   D: Device,
 {
   pub fn new(g: CodeGen, device: &'d mut D) -> Self {
+    Self::with_device(g, DeviceSlot::Borrowed(device))
+  }
+
+  /// Creates a VM owning `device`, which can be taken back by
+  /// [`Self::into_device`].
+  pub fn new_owned(g: CodeGen, device: D) -> Self {
+    Self::with_device(g, DeviceSlot::Owned(Box::new(device)))
+  }
+
+  fn with_device(g: CodeGen, device: DeviceSlot<'d, D>) -> Self {
     let mut vm = Self {
       emoji_version: g.emoji_version,
       data: g.data,
@@ -258,6 +486,9 @@ where
       pc: 0,
       code_len: g.code.len(),
       code: g.code,
+      source_map: g.source_map,
+      run_to_addrs: vec![],
+      context_stmt: None,
       control_stack: vec![],
       num_stack: vec![],
       str_stack: vec![],
@@ -268,8 +499,25 @@ where
       device,
       files: [Default::default(), Default::default(), Default::default()],
       rng: WyRand::new(),
+      rng_seed: None,
       current_rand: 0,
       state: ExecState::Done,
+      last_arith_fault: None,
+      arith_fault_stats: ArithFaultStats::default(),
+      lossy_writes: vec![],
+      input_history: HashMap::default(),
+      timer: None,
+      soft_limits: SoftLimitState::default(),
+      read_only: false,
+      selector_warning: false,
+      pending_warning: None,
+      speed_mode: SpeedMode::default(),
+      pacing_debt: Duration::ZERO,
+      profile_overrides: ProfileOverrides::default(),
+      step_hook: None,
+      print_buffer: PrintBuffer::default(),
+      output_quota: OutputQuotaState::default(),
+      events: EventQueue::new(),
     };
     vm.current_rand = vm.rng.generate();
     vm
@@ -285,6 +533,7 @@ where
         true,
       )
       .unwrap();
+    self.events.push(VmEvent::Started);
   }
 
   pub fn stop(&mut self) -> Result<()> {
@@ -293,10 +542,241 @@ where
       line: 0,
       range: Range { start: 0, end: 0 },
     })?;
+    if !matches!(self.state, ExecState::Done) {
+      self.events.push(VmEvent::Finished);
+    }
     self.state = ExecState::Done;
+    self.run_to_addrs.clear();
+    Ok(())
+  }
+
+  /// Sets a temporary breakpoint at the innermost statement containing
+  /// `offset`. When the statement is reached, `exec` returns
+  /// `ExecResult::Breakpoint` and the breakpoint is removed.
+  ///
+  /// Returns false if there is no statement at `offset`.
+  pub fn run_to(&mut self, offset: usize) -> bool {
+    let addrs = self.source_map.stmt_addrs_at(offset);
+    if addrs.is_empty() {
+      return false;
+    }
+    self.run_to_addrs = addrs;
+    true
+  }
+
+  pub fn clear_run_to(&mut self) {
+    self.run_to_addrs.clear();
+  }
+
+  /// Moves pc past the current statement without executing it. If the current
+  /// statement is an IF statement, its branches are skipped as well.
+  ///
+  /// The program must be paused between `exec` calls, and no intermediate
+  /// values of the current statement are left on stacks.
+  pub fn skip_current_statement(
+    &mut self,
+  ) -> std::result::Result<(), SkipStatementError> {
+    if !matches!(self.state, ExecState::Normal) {
+      return Err(SkipStatementError::NotRunning);
+    }
+    if !self.num_stack.is_empty()
+      || !self.str_stack.is_empty()
+      || !self.lval_stack.is_empty()
+      || !self.fn_call_stack.is_empty()
+    {
+      return Err(SkipStatementError::UnbalancedStack);
+    }
+
+    let cur = self
+      .source_map
+      .stmt_location(self.pc)
+      .ok_or(SkipStatementError::NoStatement)?;
+    let mut pc = self.pc + 1;
+    while let Some(loc) = self.source_map.stmt_location(pc) {
+      let is_nested = loc.line == cur.line
+        && cur.range.start <= loc.range.start
+        && loc.range.end <= cur.range.end;
+      if !is_nested {
+        break;
+      }
+      pc += 1;
+    }
+    self.pc = pc;
     Ok(())
   }
 
+  /// Returns the last arithmetic fault since the program is started.
+  pub fn last_arith_fault(&self) -> Option<&ArithFault> {
+    self.last_arith_fault.as_ref()
+  }
+
+  pub fn arith_fault_stats(&self) -> ArithFaultStats {
+    self.arith_fault_stats
+  }
+
+  /// Returns WRITE statements which have written strings to files that
+  /// cannot be read back unchanged since the program is started, in the
+  /// order they are first executed. Each statement is reported once. See
+  /// [`WriteLoss`].
+  pub fn lossy_writes(&self) -> &[LossyWrite] {
+    &self.lossy_writes
+  }
+
+  /// In read-only mode, files cannot be opened in OUTPUT, APPEND or RANDOM
+  /// mode, nor written in BINARY mode, CALL statements are forbidden, and
+  /// writes to memory other than the screen are ignored.
+  pub fn set_read_only(&mut self, read_only: bool) {
+    self.read_only = read_only;
+  }
+
+  pub fn is_read_only(&self) -> bool {
+    self.read_only
+  }
+
+  /// If enabled, ON ... GOTO/GOSUB statements with non-integral selectors
+  /// return `ExecResult::Warning` after jumping, since machines differ in
+  /// how they round the selectors. See [`SelectorRounding`]. Execution can
+  /// be resumed by calling `exec` with `ExecInput::None`.
+  ///
+  /// [`SelectorRounding`]: crate::machine::SelectorRounding
+  pub fn set_selector_warning(&mut self, enabled: bool) {
+    self.selector_warning = enabled;
+  }
+
+  /// Seeds the RNG used by RND with `seed` when the program is started, so
+  /// that runs are reproducible. The RNG is seeded randomly if `seed` is
+  /// None, which is the default.
+  pub fn set_rng_seed(&mut self, seed: Option<u64>) {
+    self.rng_seed = seed;
+  }
+
+  pub fn rng_seed(&self) -> Option<u64> {
+    self.rng_seed
+  }
+
+  /// See [`SpeedMode`].
+  pub fn set_speed_mode(&mut self, mode: SpeedMode) {
+    self.speed_mode = mode;
+  }
+
+  pub fn speed_mode(&self) -> SpeedMode {
+    self.speed_mode
+  }
+
+  /// Overrides the behaviors of the machine profile of the device. See
+  /// [`ProfileOverrides`].
+  pub fn set_profile_overrides(&mut self, overrides: ProfileOverrides) {
+    self.profile_overrides = overrides;
+  }
+
+  pub fn profile_overrides(&self) -> ProfileOverrides {
+    self.profile_overrides
+  }
+
+  fn int_overflow(&self) -> IntOverflow {
+    self
+      .profile_overrides
+      .int_overflow
+      .unwrap_or_else(|| self.device.int_overflow())
+  }
+
+  fn invalid_notes(&self) -> InvalidNotes {
+    self
+      .profile_overrides
+      .invalid_notes
+      .unwrap_or_else(|| self.device.invalid_notes())
+  }
+
+  fn selector_rounding(&self) -> SelectorRounding {
+    self
+      .profile_overrides
+      .selector_rounding
+      .unwrap_or_else(|| self.device.selector_rounding())
+  }
+
+  /// Sets the budgets of resources, beyond which the program may fail on the
+  /// real machine. No warnings are raised if `limits` is None, which is the
+  /// default.
+  pub fn set_soft_limits(&mut self, limits: Option<SoftLimits>) {
+    self.soft_limits.limits = limits;
+  }
+
+  pub fn soft_limits(&self) -> Option<&SoftLimits> {
+    self.soft_limits.limits.as_ref()
+  }
+
+  pub fn resource_usage(&self) -> ResourceUsage {
+    ResourceUsage {
+      string_space: self.bindings.string_bytes,
+      array_memory: self.bindings.array_bytes,
+      stack_depth: self.control_stack.len() + self.fn_call_stack.len(),
+    }
+  }
+
+  /// Sets when text printed to the screen is passed to the device. The
+  /// buffered text, if any, is flushed first.
+  pub fn set_print_flush_policy(&mut self, policy: PrintFlushPolicy) {
+    self.flush_print_buffer();
+    self.print_buffer.policy = policy;
+  }
+
+  pub fn print_flush_policy(&self) -> PrintFlushPolicy {
+    self.print_buffer.policy
+  }
+
+  /// Sets the caps on the text printed by the program. There are no caps if
+  /// `quota` is None, which is the default.
+  pub fn set_output_quota(&mut self, quota: Option<OutputQuota>) {
+    self.output_quota.quota = quota;
+  }
+
+  pub fn output_quota(&self) -> Option<&OutputQuota> {
+    self.output_quota.quota.as_ref()
+  }
+
+  /// Takes the lifecycle events happened since the last call.
+  pub fn take_events(&mut self) -> Vec<VmEvent> {
+    self.events.take()
+  }
+
+  /// Passes the text buffered according to the print flush policy to the
+  /// device.
+  pub fn flush_print_buffer(&mut self) {
+    self.print_buffer.flush(&mut *self.device);
+  }
+
+  pub fn device(&self) -> &D {
+    &self.device
+  }
+
+  pub fn device_mut(&mut self) -> &mut D {
+    &mut self.device
+  }
+
+  /// Returns the device if it is owned by the VM, see [`Self::new_owned`].
+  pub fn into_device(self) -> Option<D> {
+    match self.device {
+      DeviceSlot::Borrowed(_) => None,
+      DeviceSlot::Owned(device) => Some(*device),
+    }
+  }
+
+  /// Sets a hook which is called every `interval` instructions.
+  pub fn set_step_hook<H>(&mut self, interval: NonZeroUsize, hook: H)
+  where
+    H: StepHook + 'd,
+  {
+    self.step_hook = Some(StepHookState {
+      interval,
+      counter: 0,
+      hook: Box::new(hook),
+    });
+  }
+
+  pub fn clear_step_hook(&mut self) {
+    self.step_hook = None;
+  }
+
   pub fn byte_string_from_utf16str(
     &self,
     s: &Utf16Str,
@@ -308,6 +788,30 @@ where
     s.to_string_lossy(self.emoji_version)
   }
 
+  /// Same as [`Self::string_from_byte_string_lossy`], but decodes to UTF-16
+  /// in batch, which is faster for long strings, e.g. the text printed by a
+  /// program collected by the host.
+  pub fn byte_string_to_utf16_lossy(&self, bytes: &[u8]) -> Vec<u16> {
+    let mut out = vec![];
+    crate::util::decode::decode_to_utf16(bytes, self.emoji_version, &mut out);
+    out
+  }
+
+  /// Instructions appended at runtime (e.g. by INPUT with function
+  /// definitions) do not belong to any statement.
+  pub fn source_map(&self) -> &SourceMap {
+    &self.source_map
+  }
+
+  /// Returns the compiled instructions of the program, excluding those
+  /// appended at runtime. See [`instruction`] for the stability of the
+  /// instruction set.
+  pub fn instructions(&self) -> &[Instr] {
+    &self.code[..self.code_len]
+  }
+
+  /// Returns the variables and arrays, sorted by name so that listings are
+  /// stable regardless of the order of the underlying maps.
   pub fn bindings(&self) -> BTreeMap<String, Binding> {
     let mut bindings = BTreeMap::new();
     for (sym, value) in &self.bindings.vars {
@@ -335,7 +839,7 @@ where
 
   pub fn modify_var(&mut self, name: &str, val: Value) {
     let sym = self.interner.get(name).unwrap();
-    self.bindings.vars.insert(sym, val);
+    self.bindings.store_value(LValue::Var { name: sym }, val);
   }
 
   pub fn arr_dimension_values(
@@ -367,7 +871,7 @@ where
       ),
       ArrayData::String(vec) => DimensionValues::String(
         (0..bound.get() as usize)
-          .map(|i| vec[offset + i * mult].clone())
+          .map(|i| ByteString::from(vec.get(offset + i * mult)))
           .collect(),
       ),
     }
@@ -397,14 +901,79 @@ where
     self.lval_stack.clear();
     self.bindings.clear();
     self.fn_call_stack.clear();
+    self.context_stmt = None;
     //self.device.clear();
     self.close_files(loc)?;
-    self.rng = WyRand::new();
+    self.rng = match self.rng_seed {
+      Some(seed) => WyRand::new_seed(seed),
+      None => WyRand::new(),
+    };
     self.current_rand = self.rng.generate();
     self.state = ExecState::Normal;
+    self.last_arith_fault = None;
+    self.arith_fault_stats = ArithFaultStats::default();
+    self.lossy_writes.clear();
+    self.timer = None;
+    self.pacing_debt = Duration::ZERO;
+    self.soft_limits.reset();
+    self.output_quota.reset();
+    self.print_buffer.take_printed();
     Ok(())
   }
 
+  fn arith_fault<M: ToString>(
+    &mut self,
+    loc: Location,
+    op: ArithOp,
+    kind: ArithFaultKind,
+    operands: Vec<Mbf5>,
+    message: M,
+  ) -> Result<!> {
+    let fault = ArithFault {
+      location: loc.clone(),
+      op,
+      kind,
+      operands,
+      fatal: true,
+    };
+    self.arith_fault_stats.record(&fault);
+    self.last_arith_fault = Some(fault);
+    self.state.error(loc, message)
+  }
+
+  fn check_soft_limits(&mut self, loc: Location) -> Result<()> {
+    let usage = self.resource_usage();
+    match self.soft_limits.check(&usage) {
+      Some(message) => Err(ExecResult::Warning {
+        location: loc,
+        message,
+      }),
+      None => Ok(()),
+    }
+  }
+
+  fn check_output_quota(&mut self, loc: Location) -> Result<()> {
+    let printed = self.print_buffer.take_printed();
+    if printed == 0 {
+      return Ok(());
+    }
+    match self.output_quota.add(printed) {
+      Some((kind, printed)) => Err(ExecResult::OutputQuotaExceeded {
+        location: loc,
+        kind,
+        printed,
+      }),
+      None => Ok(()),
+    }
+  }
+
+  fn write_byte(&mut self, addr: u16, byte: u8) {
+    if self.read_only && !self.device.is_screen_addr(addr) {
+      return;
+    }
+    self.device.write_byte(addr, byte);
+  }
+
   fn close_files(&mut self, loc: Location) -> Result<()> {
     for file in &mut self.files {
       if file.handle.is_open() {
@@ -420,1906 +989,88 @@ where
 impl<'d, D> VirtualMachine<'d, D>
 where
   D: Device,
-  <D as Device>::AsmError: ToString,
 {
-  pub fn exec(&mut self, input: ExecInput, mut steps: usize) -> ExecResult {
-    match std::mem::replace(&mut self.state, ExecState::Normal) {
-      ExecState::Done => return ExecResult::End,
-      ExecState::WaitForKey => self.assign_key(input),
-      ExecState::WaitForKeyboardInput {
-        lvalues,
-        skip_first,
-      } => self.assign_input(input, lvalues, skip_first),
-      ExecState::AsmSuspend { state, loc } => {
-        match self.device.exec_asm(&mut steps, AsmExecState::Cont(state)) {
-          Ok(Some(s)) => {
-            return self.state.suspend_asm(loc, s).unwrap_err();
-          }
-          Ok(None) => {
-            self.pc += 1;
-          }
-          Err(msg) => return self.state.error(loc, msg).unwrap_err(),
-        }
-      }
-      ExecState::Normal => {
-        if self.device.user_quit() {
-          return self.state.end().unwrap_err();
-        }
-      }
+  fn exec_read(&mut self, loc: Location) -> Result<()> {
+    if self.data_ptr >= self.data.len() {
+      self.state.error(
+        loc,
+        if self.data.is_empty() {
+          "没有 DATA 可供读取"
+        } else {
+          "DATA 已经读取结束，没有更多 DATA 可供读取"
+        },
+      )?;
     }
 
-    self.device.clear_cursor();
+    let datum = &self.data[self.data_ptr];
+    self.data_ptr += 1;
 
-    while steps > 0 {
-      if let Err(result) = self.exec_instr(&mut steps) {
-        return result;
+    let lvalue = self.lval_stack.pop().unwrap().1;
+    match lvalue.get_type(&self.interner) {
+      Type::String => {
+        let str = datum.value.clone();
+        self.bindings.store_value(lvalue, Value::String(str));
       }
-    }
-
-    ExecResult::Continue
-  }
-
-  fn exec_instr(&mut self, steps: &mut usize) -> Result<()> {
-    *steps -= 1;
-    let instr = &self.code[self.pc];
-    let loc = instr.loc.clone();
-    let kind = instr.kind.clone();
-
-    let result = self.do_exec_instr(steps, loc.clone(), kind);
-    if let ExecState::Done = &self.state {
-      result.and(self.close_files(loc))
-    } else {
-      result
-    }
-  }
-
-  fn do_exec_instr(
-    &mut self,
-    steps: &mut usize,
-    loc: Location,
-    kind: InstrKind,
-  ) -> Result<()> {
-    macro_rules! write_file {
-      ($file:ident, $w:expr) => {
-        self.state.io(loc.clone(), "写入文件", $file.write($w))?;
-      };
-    }
+      ty @ (Type::Integer | Type::Real) => {
+        if datum.is_quoted {
+          self.state.error(
+            loc,
+            format!(
+              "读取到的数据：\"{}\"，是用引号括起来的字符串，无法转换为数值",
+              datum.value.to_string_lossy(self.emoji_version)
+            ),
+          )?
+        }
 
-    macro_rules! do_write {
-      (
-        $to_file:ident,
-        $end:ident,
-        $file:ident => $write_file:expr,
-        $write_screen:expr
-      ) => {{
-        if $to_file {
-          let filenum = self.get_filenum($end)?;
-          let file = &mut self.files[filenum as usize];
-          if !file.handle.is_open() {
-            self.state.error(loc, "未打开文件，不能执行 WRITE 操作")?;
-          }
-          match file.mode {
-            FileMode::Output | FileMode::Append => {
-              let $file = &mut file.handle;
-              $write_file;
-              if $end {
-                write_file!($file, &[0xffu8]);
-              } else {
-                write_file!($file, b",");
-              }
-            }
-            _ => {
-              self.state.error(
-                loc,
-                format!(
-                  "WRITE 语句只能用于以 OUTPUT 或 APPEND 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-                  filenum + 1,
-                  file.mode
-                ),
-              )?;
-            }
-          }
+        let mut str = datum.value.clone();
+        str.retain(|&b| b != b' ');
+        if str.is_empty() {
+          let value = if ty == Type::Integer {
+            Value::Integer(0)
+          } else {
+            Value::Real(Mbf5::ZERO)
+          };
+          self.bindings.store_value(lvalue, value);
         } else {
-          $write_screen;
-          if !$end {
-            self.device.print(b",");
+          match parse_num_value(&str, ty, self.int_overflow()) {
+            Ok(value) => self.bindings.store_value(lvalue, value),
+            Err(err) => {
+              let data = datum.value.to_string_lossy(self.emoji_version);
+              self.state.error(loc, err.read_message(data))?;
+            }
           }
-        };
-      }}
+        }
+      }
     }
 
-    macro_rules! do_get_put {
-      (
-        $op:literal,
-        $record_len:ident,
-        $fields:ident,
-        $file:ident => $body:expr
-      ) => {
-        let record_loc = self.num_stack.last().unwrap().0.clone();
-        let record = self.pop_range(-32768, 32767)? as i16;
-        if record == 0 {
-          self.state.error(record_loc, "记录序号不能为 0")?;
-        }
-        let record = record - 1;
+    Ok(())
+  }
 
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        match &file.mode {
-          FileMode::Random { record_len, fields } => {
-            let offset = record as u64 * *record_len as u64;
-            self.state.io(
-              loc.clone(),
-              "设置文件指针",
-              file.handle.seek(offset),
-            )?;
-
-            let $record_len = *record_len;
-            let $fields = &fields[..];
-            let $file = &mut file.handle;
-            $body;
-          }
-          _ => {
-            self.state.error(
-              loc,
-              format!(
-                "{} 语句只能用于以 RANDOM 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-                $op,
-                filenum + 1,
-                file.mode
-              ),
-            )?;
-          }
-        }
-      };
-    }
-
-    match kind {
-      InstrKind::DefFn { name, param, end } => {
-        self.bindings.user_funcs.insert(
-          name,
-          UserFunc {
-            param,
-            body_addr: Addr(self.pc + 1),
-          },
-        );
-        self.pc = end.0;
-        return Ok(());
-      }
-      InstrKind::DimArray {
-        name,
-        dimensions: num_dimensions,
-      } => {
-        if self.bindings.arrays.contains_key(&name) {
-          self.state.error(loc, "重复定义数组")?;
-        }
-        let mut size = 1;
-        let mut multiplier = 1;
-        let mut dimensions = vec![];
-        let start = self.num_stack.len() - num_dimensions.get();
-        self.num_stack[start..].reverse();
-        for _ in 0..num_dimensions.get() {
-          let (loc, value) = self.num_stack.pop().unwrap();
-          let bound = f64::from(value.truncate()) as isize;
-          if bound < 0 {
-            self.state.error(
-              loc,
-              format!("数组下标不能为负数。该下标的值为：{}", f64::from(value)),
-            )?
-          } else if bound > 32767 {
-            self.state.error(
-              loc,
-              format!(
-                "数组下标超出上限 32767。该下标的值为：{}",
-                f64::from(value)
-              ),
-            )?
-          }
-          let bound = bound as usize + 1;
-          size *= bound;
-          dimensions.push(Dimension {
-            bound: unsafe { NonZeroU16::new_unchecked(bound as u16) },
-            multiplier,
-          });
-          multiplier *= bound;
-        }
-        let data = ArrayData::new(symbol_type(&self.interner, name), size);
-        self
-          .bindings
-          .arrays
-          .insert(name, Array { dimensions, data });
-      }
-      InstrKind::PushVarLValue { name } => {
-        self.lval_stack.push((loc, LValue::Var { name }));
-      }
-      InstrKind::PushIndexLValue { name, dimensions } => {
-        let offset = self.calc_array_offset(name, dimensions)?;
-        self.lval_stack.push((loc, LValue::Index { name, offset }));
-      }
-      InstrKind::PushFnLValue { name, param } => {
-        self.lval_stack.push((loc, LValue::Fn { name, param }));
-      }
-      InstrKind::SetRecordFields { fields } => {
-        self.exec_field(loc, fields.get())?
-      }
-      InstrKind::ForLoop { name, has_step } => {
-        self.exec_for(loc, name, has_step)?
-      }
-      InstrKind::NextFor { name } => {
-        return self.exec_next(loc, name);
-      }
-      InstrKind::GoSub(target) => {
-        self.control_stack.push(ControlRecord::Sub {
-          next_addr: Addr(self.pc + 1),
-        });
-        self.pc = target.0;
-        return Ok(());
-      }
-      InstrKind::GoTo(target) => {
-        self.pc = target.0;
-        return Ok(());
-      }
-      InstrKind::JumpIfZero(target) => {
-        let value = self.num_stack.pop().unwrap().1;
-        if value.is_zero() {
-          self.pc = target.0;
-        } else {
-          self.pc += 1;
-        }
-        return Ok(());
-      }
-      InstrKind::CallFn(func) => {
-        if let Some(func) = self.bindings.user_funcs.get(&func).cloned() {
-          let arg = self.num_stack.pop().unwrap().1;
-          let param_org_value = self
-            .bindings
-            .load_value(&self.interner, LValue::Var { name: func.param });
-          self.fn_call_stack.push(FnCallRecord {
-            param: func.param,
-            param_org_value,
-            next_addr: Addr(self.pc + 1),
-          });
-          self.store_real(LValue::Var { name: func.param }, arg)?;
-          self.pc = func.body_addr.0;
-        } else {
-          self.state.error(loc, "自定义函数不存在")?;
-        }
-        return Ok(());
-      }
-      InstrKind::ReturnFn => {
-        let record = self.fn_call_stack.pop().unwrap();
-        self.bindings.store_value(
-          LValue::Var { name: record.param },
-          record.param_org_value,
-        );
-        self.pc = record.next_addr.0;
-        return Ok(());
-      }
-      InstrKind::Switch(branches) => {
-        let value = self.pop_u8(false)? as usize;
-        if value >= 1 && value <= branches.get() {
-          match self.code[self.pc + value].kind.clone() {
-            InstrKind::GoSub(target) => {
-              let next_addr = Addr(self.pc + branches.get() + 1);
-              self.control_stack.push(ControlRecord::Sub { next_addr });
-              self.pc = target.0;
-            }
-            InstrKind::GoTo(target) => {
-              self.pc = target.0;
-            }
-            _ => unreachable!(),
-          }
-        } else {
-          self.pc += branches.get() + 1;
-        }
-        return Ok(());
-      }
-      InstrKind::RestoreDataPtr(ptr) => {
-        self.data_ptr = ptr.0;
-      }
-      InstrKind::Return => {
-        while let Some(record) = self.control_stack.pop() {
-          if let ControlRecord::Sub { next_addr } = record {
-            self.pc = next_addr.0;
-            return Ok(());
-          }
-        }
-        self
-          .state
-          .error(loc, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行")?;
-      }
-      InstrKind::Pop => {
-        while let Some(record) = self.control_stack.pop() {
-          if let ControlRecord::Sub { .. } = record {
-            self.pc += 1;
-            return Ok(());
-          }
-        }
-        self
-          .state
-          .error(loc, "之前没有执行过 GOSUB 语句，POP 语句无法执行")?;
-      }
-      InstrKind::PopNum => {
-        self.num_stack.pop().unwrap();
-      }
-      InstrKind::PopStr => {
-        self.str_stack.pop().unwrap();
-      }
-      InstrKind::PushNum(num) => {
-        self.num_stack.push((loc, num));
-      }
-      InstrKind::PushVar(name) => {
-        match self
-          .bindings
-          .load_value(&self.interner, LValue::Var { name })
-        {
-          Value::Integer(n) => self.num_stack.push((loc, n.into())),
-          Value::Real(n) => self.num_stack.push((loc, n)),
-          Value::String(s) => self.str_stack.push((loc, s)),
-        }
-      }
-      InstrKind::PushStr(str) => {
-        self.str_stack.push((loc, str));
-      }
-      InstrKind::PushInKey => {
-        self.state.inkey()?;
-      }
-      InstrKind::PushIndex { name, dimensions } => {
-        let offset = self.calc_array_offset(name, dimensions)?;
-        match &self.bindings.arrays[&name].data {
-          ArrayData::Integer(arr) => {
-            self.num_stack.push((loc, Mbf5::from(arr[offset])));
-          }
-          ArrayData::Real(arr) => {
-            self.num_stack.push((loc, arr[offset]));
-          }
-          ArrayData::String(arr) => {
-            self.str_stack.push((loc, arr[offset].clone()));
-          }
-        };
-      }
-      InstrKind::Not => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, Mbf5::from(value.is_zero())));
-      }
-      InstrKind::Neg => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, -value));
-      }
-      InstrKind::CmpNum(cmp) => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, Mbf5::from(cmp.cmp(lhs, rhs))));
-      }
-      InstrKind::CmpStr(cmp) => {
-        let rhs = self.str_stack.pop().unwrap().1;
-        let lhs = self.str_stack.pop().unwrap().1;
-        self.num_stack.push((loc, Mbf5::from(cmp.cmp(lhs, rhs))));
-      }
-      InstrKind::Concat => {
-        let mut rhs = self.str_stack.pop().unwrap().1;
-        let mut lhs = self.str_stack.pop().unwrap().1;
-        lhs.append(&mut rhs);
-        if lhs.len() > 255 {
-          self.state.error(
-            loc,
-            format!(
-              "运算结果字符串过长，长度超出 255。字符串长度为：{}",
-              lhs.len()
-            ),
-          )?;
-        }
-        self.str_stack.push((loc, lhs));
-      }
-      InstrKind::Add => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        match lhs + rhs {
-          Ok(result) => self.num_stack.push((loc, result)),
-          Err(RealError::Infinite) => {
-            self.state.error(
-              loc,
-              format!(
-                "运算结果数值过大，超出了实数的表示范围。加法运算的两个运算数分别为：{lhs}，{rhs}"
-              ))?;
-          }
-          Err(RealError::Nan) => unreachable!(),
-        }
-      }
-      InstrKind::Sub => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        match lhs - rhs {
-          Ok(result) => self.num_stack.push((loc, result)),
-          Err(RealError::Infinite) => {
-            self.state.error(
-              loc,
-              format!(
-                "运算结果数值过大，超出了实数的表示范围。减法运算的两个运算数分别为：{lhs}，{rhs}"
-              ))?;
-          }
-          Err(RealError::Nan) => unreachable!(),
-        }
-      }
-      InstrKind::Mul => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        match lhs * rhs {
-          Ok(result) => self.num_stack.push((loc, result)),
-          Err(RealError::Infinite) => {
-            self.state.error(
-              loc,
-              format!(
-                "运算结果数值过大，超出了实数的表示范围。乘法运算的两个运算数分别为：{lhs}，{rhs}"
-              ))?;
-          }
-          Err(RealError::Nan) => unreachable!(),
-        }
-      }
-      InstrKind::Div => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        if rhs.is_zero() {
-          self.state.error(loc, "除以 0")?;
-        }
-        let lhs = self.num_stack.pop().unwrap().1;
-        match lhs / rhs {
-          Ok(result) => self.num_stack.push((loc, result)),
-          Err(RealError::Infinite) => {
-            self.state.error(
-              loc,
-              format!(
-                "运算结果数值过大，超出了实数的表示范围。除法运算的两个运算数分别为：{lhs}，{rhs}"
-              ))?;
-          }
-          Err(RealError::Nan) => unreachable!(),
-        }
-      }
-      InstrKind::Pow => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        match lhs.pow(rhs) {
-          Ok(result) => self.num_stack.push((loc, result)),
-          Err(RealError::Infinite) => {
-            self.state.error(
-              loc,
-              format!(
-                "运算结果数值过大，超出了实数的表示范围。底数为：{lhs}，指数为：{rhs}"
-              ))?;
-          }
-          Err(RealError::Nan) => {
-            self.state.error(
-              loc,
-              format!("超出乘方运算的定义域。底数为：{lhs}，指数为：{rhs}"),
-            )?;
-          }
-        }
-      }
-      InstrKind::And => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        self
-          .num_stack
-          .push((loc, Mbf5::from(!lhs.is_zero() && !rhs.is_zero())));
-      }
-      InstrKind::Or => {
-        let rhs = self.num_stack.pop().unwrap().1;
-        let lhs = self.num_stack.pop().unwrap().1;
-        self
-          .num_stack
-          .push((loc, Mbf5::from(!lhs.is_zero() || !rhs.is_zero())));
-      }
-      InstrKind::SysFuncCall { kind, arity } => {
-        self.exec_sys_func(loc, kind, arity)?;
-      }
-      InstrKind::NewLine => {
-        self.device.newline();
-      }
-      InstrKind::PrintSpc => {
-        let value = self.pop_u8(false)?;
-        self.device.print(&vec![b' '; value as _]);
-      }
-      InstrKind::PrintTab => {
-        let col = self.pop_range(1, 20)? as u8 - 1;
-        let current_col = self.device.get_column();
-        let spc_num = if current_col > col {
-          20 - current_col + col
-        } else {
-          col - current_col
-        };
-        self.device.print(&vec![b' '; spc_num as _]);
-      }
-      InstrKind::PrintNum => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.device.print(value.to_string().as_bytes());
-      }
-      InstrKind::PrintStr => {
-        let mut value = self.str_stack.pop().unwrap().1;
-        value.end_at_null();
-        value.drop_0x1f();
-        self.device.print(&value);
-      }
-      InstrKind::Flush => {
-        self.device.flush();
-      }
-      InstrKind::SetRow => {
-        let row = self.pop_range(1, 5)? as u8 - 1;
-        self.device.set_row(row);
-      }
-      InstrKind::SetColumn => {
-        let col = self.pop_range(1, 20)? as u8 - 1;
-        self.device.set_column(col);
-      }
-      InstrKind::WriteNum { to_file, end } => {
-        let num = self.num_stack.pop().unwrap().1;
-        do_write!(
-          to_file,
-          end,
-          file => {
-            write_file!(file, num.to_string().as_bytes());
-          },
-          {
-            self.device.print(num.to_string().as_bytes());
-          }
-        );
-      }
-      InstrKind::WriteStr { to_file, end } => {
-        let mut str = self.str_stack.pop().unwrap().1;
-        str.push(b'"');
-        str.end_at_null();
-        str.drop_0x1f();
-        do_write!(
-          to_file,
-          end,
-          file => {
-            write_file!(file, b"\"");
-            write_file!(file, &str);
-          },
-          {
-            self.device.print(b"\"");
-            self.device.print(&str);
-          }
-        );
-      }
-      InstrKind::KeyboardInput {
-        has_prompt,
-        fields: num_fields,
-      } => {
-        let prompt = if has_prompt {
-          let mut prompt = self.str_stack.pop().unwrap().1;
-          prompt.end_at_null();
-          prompt.drop_0x1f();
-          Some(prompt)
-        } else {
-          None
-        };
-
-        let mut lvalues = vec![];
-        let mut fields = vec![];
-        for _ in 0..num_fields.get() {
-          let (lval_loc, lvalue) = self.lval_stack.pop().unwrap();
-          match lvalue {
-            LValue::Fn { name, param } => {
-              fields.push(KeyboardInputType::Func {
-                name: self.interner.resolve(name).unwrap().to_owned(),
-                param: self.interner.resolve(param).unwrap().to_owned(),
-              })
-            }
-            _ => match lvalue.get_type(&self.interner) {
-              Type::Integer => fields.push(KeyboardInputType::Integer),
-              Type::Real => fields.push(KeyboardInputType::Real),
-              Type::String => fields.push(KeyboardInputType::String),
-            },
-          }
-          lvalues.push((lval_loc, lvalue));
-        }
-
-        if let Some(prompt) = &prompt {
-          self.device.print(prompt);
-        } else {
-          self.device.print(b"?");
-        }
-        self.device.flush();
-
-        let skip_first;
-        if matches!(self.device.key(), Some(c) if c == KeyCode::Enter as u8)
-          && !matches!(fields.last(), Some(KeyboardInputType::Func { .. }))
-        {
-          fields.pop();
-          skip_first = true;
-        } else {
-          skip_first = false;
-        }
-
-        fields.reverse();
-        lvalues.reverse();
-        self.state.input(
-          lvalues,
-          skip_first,
-          prompt.map(|s| s.to_string_lossy(self.emoji_version)),
-          fields,
-        )?;
-      }
-      InstrKind::FileInput { fields: num_fields } => {
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        let file = if let FileMode::Input = file.mode {
-          &mut file.handle
-        } else {
-          self.state.error(
-            loc,
-            format!(
-              "INPUT 语句只能用于以 INPUT 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        };
-
-        let offset = self.lval_stack.len() - num_fields.get();
-        for (lval_loc, lvalue) in self.lval_stack.drain(offset..) {
-          exec_file_input(
-            &mut self.state,
-            &mut self.bindings,
-            &self.interner,
-            self.emoji_version,
-            lval_loc,
-            lvalue,
-            file,
-          )?;
-        }
-      }
-      InstrKind::ReadData => self.exec_read(loc)?,
-      InstrKind::OpenFile { mode, has_len } => {
-        self.exec_open(loc, mode, has_len)?
-      }
-      InstrKind::Beep => {
-        self.device.beep();
-      }
-      InstrKind::DrawBox { has_fill, has_mode } => {
-        let mode = self.calc_draw_mode(has_mode)?;
-        let fill = if has_fill {
-          self.pop_u8(false)? & 1 != 0
-        } else {
-          false
-        };
-        let y2 = self.pop_u8(false)?;
-        let x2 = self.pop_u8(false)?;
-        let y1 = self.pop_u8(false)?;
-        let x1 = self.pop_u8(false)?;
-        self.device.draw_box((x1, y1), (x2, y2), fill, mode);
-      }
-      InstrKind::Call => {
-        let addr = self.pop_range(-65535, 65535)? as _;
-        match self.device.exec_asm(steps, AsmExecState::Start(addr)) {
-          Ok(Some(state)) => self.state.suspend_asm(loc, state)?,
-          Ok(None) => {
-            // do nothing
-          }
-          Err(msg) => self.state.error(loc, msg)?,
-        }
-      }
-      InstrKind::DrawCircle { has_fill, has_mode } => {
-        let mode = self.calc_draw_mode(has_mode)?;
-        let fill = if has_fill {
-          self.pop_u8(false)? & 1 != 0
-        } else {
-          false
-        };
-        let r = self.pop_u8(false)?;
-        let y = self.pop_u8(false)?;
-        let x = self.pop_u8(false)?;
-        self.device.draw_circle((x, y), r, fill, mode);
-      }
-      InstrKind::Clear => {
-        self.reset(loc, false)?;
-      }
-      InstrKind::CloseFile => {
-        let filenum = self.get_filenum(true)?;
-        self.state.io(
-          loc,
-          "关闭文件",
-          self.files[filenum as usize].handle.close(),
-        )?;
-      }
-      InstrKind::Cls => {
-        self.device.cls();
-      }
-      InstrKind::NoOp => {
-        // do nothing
-      }
-      InstrKind::DrawPoint { has_mode } => {
-        let mode = self.calc_draw_mode(has_mode)?;
-        let y = self.pop_u8(false)?;
-        let x = self.pop_u8(false)?;
-        self.device.draw_point((x, y), mode);
-      }
-      InstrKind::DrawEllipse { has_fill, has_mode } => {
-        let mode = self.calc_draw_mode(has_mode)?;
-        let fill = if has_fill {
-          self.pop_u8(false)? & 1 != 0
-        } else {
-          false
-        };
-        let ry = self.pop_u8(false)?;
-        let rx = self.pop_u8(false)?;
-        let y = self.pop_u8(false)?;
-        let x = self.pop_u8(false)?;
-        self.device.draw_ellipse((x, y), (rx, ry), fill, mode);
-      }
-      InstrKind::End => {
-        self.state.end()?;
-      }
-      InstrKind::ReadRecord => {
-        do_get_put!("GET", record_len, fields, file => {
-          let mut buf = vec![0; record_len as _];
-          let read_len =
-            self
-              .state
-              .io(loc.clone(), "读取文件", file.read(&mut buf))?;
-          if read_len == 0 {
-            self.state.error(loc, "不能在文件末尾读取记录")?;
-          }
-          if read_len < record_len as usize {
-            self.state.error(loc, "文件大小不是记录长度的整数倍")?;
-          }
-
-          let mut offset = 0;
-          for field in fields {
-            self.bindings.store_value(
-              field.lvalue.clone(),
-              Value::String(
-                buf[offset..offset + field.len as usize].to_owned().into(),
-              ),
-            );
-            offset += field.len as usize;
-          }
-        });
-      }
-      InstrKind::WriteRecord => {
-        do_get_put!("PUT", record_len, fields, file => {
-          let mut buf = vec![0u8; record_len as _];
-          let mut offset = 0;
-          for field in fields {
-            let str = self
-              .bindings
-              .load_value(&self.interner, field.lvalue.clone())
-              .unwrap_string();
-            if str.len() == field.len as usize {
-              buf[offset..offset + field.len as usize].clone_from_slice(&str);
-            }
-            offset += field.len as usize;
-          }
-
-          self.state.io(loc, "写入文件", file.write(&buf))?;
-        });
-      }
-      InstrKind::AssignInt => {
-        let (_, lvalue) = self.lval_stack.pop().unwrap();
-        let num = self.num_stack.pop().unwrap();
-        self.store_int(lvalue, num)?;
-      }
-      InstrKind::AssignReal => {
-        let (_, lvalue) = self.lval_stack.pop().unwrap();
-        let num = self.num_stack.pop().unwrap().1;
-        self.store_real(lvalue, num)?;
-      }
-      InstrKind::AssignStr => {
-        let (_, lvalue) = self.lval_stack.pop().unwrap();
-        let str = self.str_stack.pop().unwrap().1;
-        self.bindings.store_value(lvalue, Value::String(str));
-      }
-      InstrKind::DrawLine { has_mode } => {
-        let mode = self.calc_draw_mode(has_mode)?;
-        let y2 = self.pop_u8(false)?;
-        let x2 = self.pop_u8(false)?;
-        let y1 = self.pop_u8(false)?;
-        let x1 = self.pop_u8(false)?;
-        self.device.draw_line((x1, y1), (x2, y2), mode);
-      }
-      InstrKind::AlignedAssign(align) => self.exec_set(loc, align)?,
-      InstrKind::SetTrace(_) => {
-        // do nothing
-      }
-      InstrKind::SetScreenMode(mode) => {
-        self.device.set_screen_mode(mode);
-      }
-      InstrKind::PlayNotes => {
-        let value = self.str_stack.pop().unwrap().1;
-        self.device.play_notes(&value);
-      }
-      InstrKind::Poke => {
-        let byte = self.pop_u8(false)?;
-        let addr = self.pop_range(-65535, 65535)? as _;
-        self.device.write_byte(addr, byte);
-      }
-      InstrKind::Swap => {
-        let lvalue2 = self.lval_stack.pop().unwrap().1;
-        let lvalue1 = self.lval_stack.pop().unwrap().1;
-        let value1 = self.bindings.load_value(&self.interner, lvalue1.clone());
-        let value2 = self.bindings.load_value(&self.interner, lvalue2.clone());
-        self.bindings.store_value(lvalue2, value1);
-        self.bindings.store_value(lvalue1, value2);
-      }
-      InstrKind::Restart => {
-        self.device.set_screen_mode(ScreenMode::Text);
-        self.device.cls();
-        self.reset(loc, true)?;
-        return Ok(());
-      }
-      InstrKind::SetPrintMode(mode) => {
-        self.device.set_print_mode(mode);
-      }
-      InstrKind::Wend => {
-        let mut found = None;
-        while let Some(record) = self.control_stack.pop() {
-          if let ControlRecord::WhileLoop { addr } = record {
-            found = Some(addr);
-            break;
-          }
-        }
-
-        if let Some(addr) = found {
-          self.pc = addr.0;
-        } else {
-          self.state.error(loc, "WEND 语句找不到匹配的 WHILE 语句")?;
-        }
-
-        return Ok(());
-      }
-      InstrKind::WhileLoop { start, end } => {
-        let value = self.num_stack.pop().unwrap().1;
-        if value.is_zero() {
-          self.pc = end.0;
-        } else {
-          self
-            .control_stack
-            .push(ControlRecord::WhileLoop { addr: start });
-          self.pc += 1;
-        }
-
-        return Ok(());
-      }
-      InstrKind::Sleep => {
-        let value = self.num_stack.pop().unwrap().1;
-        if value.is_positive() {
-          self.pc += 1;
-          let ns = (self.device.sleep_unit().as_nanos() as f64
-            * f64::from(value)) as u64;
-          self.state.sleep(Duration::from_nanos(ns))?;
-        }
-      }
-      InstrKind::Fputc => {
-        let (value_loc, value) = self.str_stack.pop().unwrap();
-        if value.is_empty() {
-          self
-            .state
-            .error(value_loc, "FPUTC 语句的数据参数不能为空字符串")?;
-        }
-
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          self
-            .state
-            .io(loc, "写入文件", file.handle.write(&value[..1]))?;
-        } else {
-          self.state.error(
-            loc,
-            format!(
-              "FPUTC 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-      }
-      InstrKind::Fread => {
-        let size = self.pop_range(1, 65535)? as u16;
-        let mut addr = self.pop_range(0, 65535)? as u16;
-
-        if addr.checked_add(size - 1).is_none() {
-          self
-            .state
-            .error(loc, "试图写入内存的数据超出了内存的地址范围")?;
-        }
-
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          let mut buf = vec![0; size as usize];
-          let read_len = self.state.io(
-            loc.clone(),
-            "读取文件",
-            file.handle.read(&mut buf),
-          )?;
-          if read_len < size as usize {
-            self.state.error(loc, "文件中没有足够的数据可供读取")?;
-          }
-          for b in buf {
-            self.device.write_byte(addr, b);
-            addr += 1;
-          }
-        } else {
-          self.state.error(
-            loc,
-            format!(
-              "FREAD 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-      }
-      InstrKind::Fwrite => {
-        let size = self.pop_range(1, 65535)? as u16;
-        let addr = self.pop_range(0, 65535)? as u16;
-
-        if addr.checked_add(size - 1).is_none() {
-          self
-            .state
-            .error(loc, "试图从内存读取的数据超出了内存的地址范围")?;
-        }
-
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          let mut buf = vec![0; size as usize];
-          for i in 0..size {
-            buf[i as usize] = self.device.read_byte(addr + i);
-          }
-          self.state.io(loc, "写入文件", file.handle.write(&buf))?;
-        } else {
-          self.state.error(
-            loc,
-            format!(
-              "FWRITE 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-      }
-      InstrKind::Fseek => {
-        let offset = self.pop_range(0, 65535)? as u16;
-
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          self.state.io(
-            loc,
-            "设置文件指针",
-            file.handle.seek(offset as u64),
-          )?;
-        } else {
-          self.state.error(
-            loc,
-            format!(
-              "FSEEK 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                  但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-      }
-      InstrKind::Debug => {
-        let (_, value) = self.str_stack.pop().unwrap();
-        println!(
-          "DEBUG: line {}: {}",
-          loc.line + 1,
-          value.to_string_lossy(self.emoji_version)
-        );
-      }
-    }
-    self.pc += 1;
-    Ok(())
-  }
-}
-
-impl<'d, D> VirtualMachine<'d, D>
-where
-  D: Device,
-{
-  fn exec_sys_func(
-    &mut self,
-    loc: Location,
-    kind: SysFuncKind,
-    arity: NonZeroUsize,
-  ) -> Result<()> {
-    match kind {
-      SysFuncKind::Abs => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, value.abs()));
-        Ok(())
-      }
-      SysFuncKind::Asc => {
-        let (arg_loc, value) = self.str_stack.pop().unwrap();
-        if value.is_empty() {
-          self.state.error(arg_loc, "ASC 函数的参数不能为空字符串")?;
-        }
-        self.num_stack.push((loc, Mbf5::from(value[0])));
-        Ok(())
-      }
-      SysFuncKind::Atn => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, value.atan()));
-        Ok(())
-      }
-      SysFuncKind::Chr => {
-        let value = self.pop_u8(false)?;
-        self.str_stack.push((loc, ByteString::from(vec![value])));
-        Ok(())
-      }
-      SysFuncKind::Cos => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, value.cos()));
-        Ok(())
-      }
-      SysFuncKind::Cvi => {
-        let (arg_loc, value) = self.str_stack.pop().unwrap();
-        if value.len() != 2 {
-          self.state.error(
-            arg_loc,
-            format!(
-              "CVI$ 函数的参数字符串长度不等于 2。参数字符串长度为：{}",
-              value.len()
-            ),
-          )?;
-        }
-        let lo = value[0] as u16;
-        let hi = value[1] as u16;
-        self
-          .num_stack
-          .push((loc, Mbf5::from((lo + (hi << 8)) as i16)));
-        Ok(())
-      }
-      SysFuncKind::Cvs => {
-        let (arg_loc, value) = self.str_stack.pop().unwrap();
-        if value.len() != 5 {
-          self.state.error(
-            arg_loc,
-            format!(
-              "CVS$ 函数的参数字符串长度不等于 5。参数字符串长度为：{}",
-              value.len()
-            ),
-          )?;
-        }
-        self.num_stack.push((
-          loc,
-          Mbf5::from([value[0], value[1], value[2], value[3], value[4]]),
-        ));
-        Ok(())
-      }
-      SysFuncKind::Eof => {
-        let filenum = self.get_filenum(true)?;
-        let file = &self.files[filenum as usize];
-        match file.mode {
-          FileMode::Input => {
-            let len =
-              self
-                .state
-                .io(loc.clone(), "获取文件大小", file.handle.len())?;
-            let pos =
-              self
-                .state
-                .io(loc.clone(), "获取文件指针", file.handle.pos())?;
-            let mut eof_reached = pos >= len;
-            if self.device.eof_behavior() == EofBehavior::Inverse {
-              eof_reached = !eof_reached;
-            }
-            self.num_stack.push((loc, Mbf5::from(eof_reached)));
-            Ok(())
-          }
-          FileMode::None => {
-            self.state.error(loc, "未打开文件")?;
-          }
-          _ => {
-            self.state.error(
-              loc,
-              format!(
-                "EOF 函数只能用于以 INPUT 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
-                filenum + 1,
-                file.mode
-              ))?;
-          }
-        }
-      }
-      SysFuncKind::Exp => {
-        let value = self.num_stack.pop().unwrap().1;
-        match value.exp() {
-          Ok(value) => {
-            self.num_stack.push((loc, value));
-            Ok(())
-          }
-          Err(RealError::Infinite) => self.state.error(
-            loc,
-            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
-          )?,
-          Err(RealError::Nan) => unreachable!(),
-        }
-      }
-      SysFuncKind::Int => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, value.floor()));
-        Ok(())
-      }
-      SysFuncKind::Left => {
-        let len = self.pop_u8(true)? as usize;
-        let value = self.str_stack.pop().unwrap().1;
-        let len = len.min(value.len());
-        self
-          .str_stack
-          .push((loc, ByteString::from(value[..len].to_vec())));
-        Ok(())
-      }
-      SysFuncKind::Len => {
-        let value = self.str_stack.pop().unwrap().1;
-        self.num_stack.push((loc, Mbf5::from(value.len() as u32)));
-        Ok(())
-      }
-      SysFuncKind::Lof => {
-        let filenum = self.get_filenum(true)?;
-        let file = &self.files[filenum as usize];
-        match file.mode {
-          FileMode::Random { .. } => {
-            let len =
-              self
-                .state
-                .io(loc.clone(), "获取文件大小", file.handle.len())?;
-            self.num_stack.push((loc, Mbf5::from(len)));
-            Ok(())
-          }
-          FileMode::None => {
-            self.state.error(loc, "未打开文件")?;
-          }
-          _ => {
-            self.state.error(
-              loc,
-              format!(
-                "LOF 函数只能用于以 RANDOM 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
-                filenum + 1,
-                file.mode
-              ))?;
-          }
-        }
-      }
-      SysFuncKind::Log => {
-        let (arg_loc, value) = self.num_stack.pop().unwrap();
-        match value.ln() {
-          Ok(value) => {
-            self.num_stack.push((loc, value));
-            Ok(())
-          }
-          Err(RealError::Infinite) => self.state.error(
-            loc,
-            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
-          )?,
-          Err(RealError::Nan) => self.state.error(
-            arg_loc,
-            format!("超出 LOG 函数的定义域。参数值是：{value}"),
-          )?,
-        }
-      }
-      SysFuncKind::Mid => {
-        let len = if arity.get() == 3 {
-          self.pop_u8(false)? as usize
-        } else {
-          255
-        };
-        let pos = (self.pop_u8(true)? - 1) as usize;
-        let value = self.str_stack.pop().unwrap().1;
-        let start = pos.min(value.len());
-        let end = (start + len).min(value.len());
-        self
-          .str_stack
-          .push((loc, ByteString::from(value[start..end].to_vec())));
-        Ok(())
-      }
-      SysFuncKind::Mki => {
-        let value = self.pop_range(-32768, 32767)? as i16;
-        let lo = (value & 0xff) as u8;
-        let hi = (value >> 8) as u8;
-        self.str_stack.push((loc, ByteString::from(vec![lo, hi])));
-        Ok(())
-      }
-      SysFuncKind::Mks => {
-        let value = self.num_stack.pop().unwrap().1;
-        self
-          .str_stack
-          .push((loc, ByteString::from(<[u8; 5]>::from(value).to_vec())));
-        Ok(())
-      }
-      SysFuncKind::Peek => {
-        let addr = self.pop_range(-65535, 65535)? as _;
-        let byte = self.device.read_byte(addr);
-        self.num_stack.push((loc, Mbf5::from(byte)));
-        Ok(())
-      }
-      SysFuncKind::Pos => {
-        self.num_stack.pop().unwrap();
-        self
-          .num_stack
-          .push((loc, Mbf5::from(self.device.get_column())));
-        Ok(())
-      }
-      SysFuncKind::Right => {
-        let len = self.pop_u8(true)? as usize;
-        let value = self.str_stack.pop().unwrap().1;
-        let len = len.min(value.len());
-        self
-          .str_stack
-          .push((loc, ByteString::from(value[value.len() - len..].to_vec())));
-        Ok(())
-      }
-      SysFuncKind::Rnd => {
-        let value = self.num_stack.pop().unwrap().1;
-        if value.is_zero() {
-          self
-            .num_stack
-            .push((loc, u32_to_random_number(self.current_rand)));
-          return Ok(());
-        }
-        if value.is_negative() {
-          let mut seed = [0u8; 8];
-          seed[..5].copy_from_slice(&<[u8; 5]>::from(value));
-          self.rng.reseed(seed);
-        }
-        let value: u32 = self.rng.generate();
-        self.current_rand = value;
-        self.num_stack.push((loc, u32_to_random_number(value)));
-        Ok(())
-      }
-      SysFuncKind::Sgn => {
-        let value = self.num_stack.pop().unwrap().1;
-        let num = if value.is_positive() {
-          Mbf5::ONE
-        } else if value.is_negative() {
-          Mbf5::NEG_ONE
-        } else {
-          Mbf5::ZERO
-        };
-        self.num_stack.push((loc, num));
-        Ok(())
-      }
-      SysFuncKind::Sin => {
-        let value = self.num_stack.pop().unwrap().1;
-        self.num_stack.push((loc, value.sin()));
-        Ok(())
-      }
-      SysFuncKind::Sqr => {
-        let (arg_loc, value) = self.num_stack.pop().unwrap();
-        match value.sqrt() {
-          Ok(value) => {
-            self.num_stack.push((loc, value));
-            Ok(())
-          }
-          Err(RealError::Nan) => self.state.error(
-            arg_loc,
-            format!("超出 SQR 函数的定义域。参数值是：{value}"),
-          )?,
-          Err(RealError::Infinite) => unreachable!(),
-        }
-      }
-      SysFuncKind::Str => {
-        let value = self.num_stack.pop().unwrap().1;
-        self
-          .str_stack
-          .push((loc, ByteString::from(value.to_string().into_bytes())));
-        Ok(())
-      }
-      SysFuncKind::Tan => {
-        let (arg_loc, value) = self.num_stack.pop().unwrap();
-        match value.tan() {
-          Ok(value) => {
-            self.num_stack.push((loc, value));
-            Ok(())
-          }
-          Err(RealError::Infinite) => self.state.error(
-            loc,
-            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
-          )?,
-          Err(RealError::Nan) => self.state.error(
-            arg_loc,
-            format!("超出 TAN 函数的定义域。参数值是：{value}"),
-          )?,
-        }
-      }
-      SysFuncKind::Val => {
-        let mut value = self.str_stack.pop().unwrap().1;
-        value.retain(|&b| b != b' ');
-        let u16_value = value.iter().map(|&c| c as u16).collect::<Vec<_>>();
-        let (len, _) = read_number(&u16_value, false, false);
-        let num = unsafe { std::str::from_utf8_unchecked(&value[..len]) }
-          .parse::<Mbf5>()
-          .unwrap_or(Mbf5::ZERO);
-        self.num_stack.push((loc, num));
-        Ok(())
-      }
-      SysFuncKind::Tab | SysFuncKind::Spc => unreachable!(),
-      SysFuncKind::Point => {
-        let y = self.pop_range(-32768, 32767)?;
-        let x = self.pop_range(-32768, 32767)?;
-        let p = Mbf5::from(self.device.check_point((x, y)));
-        self.num_stack.push((loc, p));
-        Ok(())
-      }
-      SysFuncKind::CheckKey => {
-        let key = self.pop_u8(false)?;
-        let p = Mbf5::from(self.device.check_key(key));
-        self.num_stack.push((loc, p));
-        Ok(())
-      }
-      SysFuncKind::Fopen => {
-        let filenum = self.get_filenum(true)?;
-        self.num_stack.push((
-          loc,
-          Mbf5::from(self.files[filenum as usize].handle.is_open()),
-        ));
-        Ok(())
-      }
-      SysFuncKind::Fgetc => {
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if !matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          self.state.error(
-            loc,
-            format!(
-              "FGETC 函数只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-        let mut buf = [0];
-        let read_len =
-          self
-            .state
-            .io(loc.clone(), "读取文件", file.handle.read(&mut buf))?;
-        if read_len == 0 {
-          self.state.error(loc, "不能在文件末尾读取数据")?;
-        }
-        self.num_stack.push((loc, Mbf5::from(buf[0])));
-        Ok(())
-      }
-      SysFuncKind::Ftell => {
-        let filenum = self.get_filenum(true)?;
-        let file = &mut self.files[filenum as usize];
-        if !file.handle.is_open() {
-          self.state.error(loc, "未打开文件")?;
-        }
-        if !matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
-          self.state.error(
-            loc,
-            format!(
-              "FTELL 函数只能用于以 BINARY 或 RANDOM 模式打开的文件，\
-                但 {} 号文件是以 {} 模式打开的",
-              filenum + 1,
-              file.mode
-            ),
-          )?;
-        }
-        let pos =
-          self
-            .state
-            .io(loc.clone(), "获取文件指针", file.handle.pos())?;
-        self.num_stack.push((loc, Mbf5::from(pos)));
-        Ok(())
-      }
-    }
-  }
-
-  fn exec_open(
-    &mut self,
-    loc: Location,
-    mode: ast::FileMode,
-    has_len: bool,
-  ) -> Result<()> {
-    let len = if has_len {
-      let mut len = self.pop_u8(false)?;
-      if len == 0 || len > 128 {
-        len = 32;
-      }
-      len
-    } else {
-      32
-    };
-
-    let filenum = self.get_filenum(true)?;
-    let (name_loc, mut filename) = self.str_stack.pop().unwrap();
-    filename.end_at_null();
-    filename.drop_0x1f();
-
-    if self.files[filenum as usize].handle.is_open() {
-      self
-        .state
-        .error(loc, format!("重复打开 {} 号文件", filenum + 1))?;
-    }
-
-    if filename.is_empty() {
-      self.state.error(name_loc, "文件名不能为空")?;
-    } else if let Some(i) = filename.find_byteset(b"/\\") {
-      self.state.error(
-        name_loc,
-        format!("文件名中不能包含\"{}\"字符", filename[i] as char),
-      )?;
-    }
-
-    if !filename.to_ascii_uppercase().ends_with(b".DAT") {
-      filename.push_str(b".DAT");
-    }
-
-    let (mode, read, write, truncate) = match mode {
-      ast::FileMode::Input => (FileMode::Input, true, false, false),
-      ast::FileMode::Output => (FileMode::Output, false, true, true),
-      ast::FileMode::Append => (FileMode::Append, false, true, false),
-      ast::FileMode::Random => (
-        FileMode::Random {
-          record_len: len,
-          fields: vec![],
-        },
-        true,
-        true,
-        false,
-      ),
-      ast::FileMode::Binary => (FileMode::Binary, true, true, false),
-      ast::FileMode::Error => unreachable!(),
-    };
-
-    let file = &mut self.files[filenum as usize];
-    match self.device.open_file(
-      &mut file.handle,
-      &filename,
-      read,
-      write,
-      truncate,
-    ) {
-      Ok(()) => {
-        if let FileMode::Append = &mode {
-          let len =
-            self
-              .state
-              .io(loc.clone(), "获取文件大小", file.handle.len())?;
-          self.state.io(loc, "设置文件指针", file.handle.seek(len))?;
-        }
-
-        file.mode = mode;
-      }
-      Err(err) => {
-        if matches!(&mode, FileMode::Binary) {
-          return Ok(());
-        } else {
-          self.state.io(loc, "打开文件", Err(err))?;
-        }
-      }
-    };
-
-    Ok(())
-  }
-
-  fn exec_read(&mut self, loc: Location) -> Result<()> {
-    if self.data_ptr >= self.data.len() {
-      self.state.error(
-        loc,
-        if self.data.is_empty() {
-          "没有 DATA 可供读取"
-        } else {
-          "DATA 已经读取结束，没有更多 DATA 可供读取"
-        },
-      )?;
-    }
-
-    let datum = &self.data[self.data_ptr];
-    self.data_ptr += 1;
-
-    let lvalue = self.lval_stack.pop().unwrap().1;
-    match lvalue.get_type(&self.interner) {
-      Type::String => {
-        let str = datum.value.clone();
-        self.bindings.store_value(lvalue, Value::String(str));
-      }
-      ty @ (Type::Integer | Type::Real) => {
-        if datum.is_quoted {
-          self.state.error(
-            loc,
-            format!(
-              "读取到的数据：\"{}\"，是用引号括起来的字符串，无法转换为数值",
-              datum.value.to_string_lossy(self.emoji_version)
-            ),
-          )?
-        }
-
-        let mut str = datum.value.clone();
-        str.retain(|&b| b != b' ');
-        if str.is_empty() {
-          let value = if ty == Type::Integer {
-            Value::Integer(0)
-          } else {
-            Value::Real(Mbf5::ZERO)
-          };
-          self.bindings.store_value(lvalue, value);
-        } else {
-          match unsafe { std::str::from_utf8_unchecked(&str) }.parse::<Mbf5>() {
-            Ok(num) => {
-              if ty == Type::Integer {
-                let int = f64::from(num.truncate());
-                if int <= -32769.0 || int >= 32768.0 {
-                  self.state.error(
-                    loc,
-                    format!(
-                      "读取到的数据：{}，超出了整数的表示范围（-32768~32767），\
-                        无法赋值给整数变量",
-                      f64::from(num),
-                    ),
-                  )?;
-                } else {
-                  self.bindings.store_value(lvalue, Value::Integer(int as _));
-                }
-              } else {
-                self.bindings.store_value(lvalue, Value::Real(num));
-              }
-            }
-            Err(ParseRealError::Malformed) => {
-              self.state.error(
-                loc,
-                format!(
-                  "读取到的数据：{}，不符合实数的格式",
-                  datum.value.to_string_lossy(self.emoji_version)
-                ),
-              )?;
-            }
-            Err(ParseRealError::Infinite) => {
-              self.state.error(
-                loc,
-                format!(
-                  "读取到的数据：{}，数值过大，超出了实数的表示范围",
-                  datum.value.to_string_lossy(self.emoji_version)
-                ),
-              )?;
-            }
-          }
-        }
-      }
-    }
-
-    Ok(())
-  }
-
-  fn exec_field(&mut self, loc: Location, num_fields: usize) -> Result<()> {
-    let filenum = self.get_filenum(true)?;
-    let record_len;
-    let file = &self.files[filenum as usize];
-    if !file.handle.is_open() {
-      self.state.error(loc, "未打开文件")?;
-    }
-    if let FileMode::Random {
-      record_len: len, ..
-    } = &file.mode
-    {
-      record_len = *len as u32;
-    } else {
-      self.state.error(
-          loc,
-          format!(
-            "FIELD 语句只能用于以 RANDOM 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
-            filenum + 1,
-            file.mode
-          )
-        )?;
-    }
-
-    let mut fields = vec![];
-    let mut total_len = 0u32;
-    for _ in 0..num_fields {
-      let lvalue = self.lval_stack.pop().unwrap().1;
-      let len = self.pop_u8(false)?;
-      self
-        .bindings
-        .store_value(lvalue.clone(), Value::String(vec![0u8; len as _].into()));
-      fields.push(RecordField { len, lvalue });
-      total_len += len as u32;
-    }
-    fields.reverse();
-
-    if total_len > record_len {
-      self.state.error(
-        loc,
-        format!(
-          "FIELD 语句定义的字段总长度 {total_len} 超出了打开文件时所指定的记录长度 {record_len}"
-        ),
-      )?;
-    }
-
-    match &mut self.files[filenum as usize].mode {
-      FileMode::Random { fields: f, .. } => {
-        *f = fields;
-      }
-      _ => unreachable!(),
-    }
-
-    Ok(())
-  }
-
-  fn exec_for(
-    &mut self,
-    _loc: Location,
-    name: Symbol,
-    has_step: bool,
-  ) -> Result<()> {
-    let step = if has_step {
-      self.num_stack.pop().unwrap().1
-    } else {
-      Mbf5::ONE
-    };
-    let end = self.num_stack.pop().unwrap().1;
-    let start = self.num_stack.pop().unwrap().1;
-
-    let mut prev_loop = None;
-    for (i, item) in self.control_stack.iter().enumerate().rev() {
-      if let ControlRecord::ForLoop(ForLoopRecord { var: prev_var, .. }) = item
-      {
-        if name == *prev_var {
-          prev_loop = Some(i);
-          break;
-        }
-      }
-    }
-    if let Some(i) = prev_loop {
-      self.control_stack.truncate(i);
-    }
-
-    self
-      .control_stack
-      .push(ControlRecord::ForLoop(ForLoopRecord {
-        addr: Addr(self.pc),
-        var: name,
-        target: end,
-        step,
-      }));
-
-    self.store_real(LValue::Var { name }, start)?;
-
-    Ok(())
-  }
-
-  fn exec_next(&mut self, loc: Location, name: Option<Symbol>) -> Result<()> {
-    let mut found = None;
-    if let Some(name) = name {
-      while let Some(record) = self.control_stack.pop() {
-        if let ControlRecord::ForLoop(record) = record {
-          if record.var == name {
-            found = Some(record);
-            break;
-          }
-        }
-      }
-    } else {
-      while let Some(record) = self.control_stack.pop() {
-        if let ControlRecord::ForLoop(record) = record {
-          found = Some(record);
-          break;
-        }
-      }
-    }
-
-    if let Some(record) = found {
-      let value = self
-        .bindings
-        .load_value(&self.interner, LValue::Var { name: record.var })
-        .unwrap_real();
-      let loc = self.code[record.addr.0].loc.clone();
-      let new_value = match value + record.step {
-        Ok(new_value) => new_value,
-        Err(RealError::Infinite) => {
-          self
-            .state
-            .error(loc, "计数器数值过大，超出了实数的表示范围。")?;
-        }
-        Err(_) => unreachable!(),
-      };
-
-      self.store_real(LValue::Var { name: record.var }, new_value)?;
-
-      let end_loop = if record.step.is_positive() {
-        new_value > record.target
-      } else if record.step.is_negative() {
-        new_value < record.target
-      } else {
-        new_value == record.target
-      };
-
-      if end_loop {
-        self.pc += 1;
-      } else {
-        self.pc = record.addr.0 + 1;
-        self.control_stack.push(ControlRecord::ForLoop(record));
-      }
-    } else {
-      self.state.error(loc, "NEXT 语句找不到匹配的 FOR 语句")?;
-    }
-
-    Ok(())
-  }
-
-  fn exec_set(&mut self, _loc: Location, align: Alignment) -> Result<()> {
-    let mut value = self.str_stack.pop().unwrap().1;
-    let lvalue = self.lval_stack.pop().unwrap().1;
-
-    let mut dest = self
-      .bindings
-      .load_value(&self.interner, lvalue.clone())
-      .unwrap_string();
-    if value.len() > dest.len() {
-      value.truncate(dest.len());
-      dest = value;
-    } else {
-      match align {
-        Alignment::Left => {
-          dest[..value.len()].clone_from_slice(&value);
-        }
-        Alignment::Right => {
-          let padding = dest.len() - value.len();
-          dest[padding..].clone_from_slice(&value);
-          dest[..padding].fill(b' ');
-        }
-      }
-    }
-    self.bindings.store_value(lvalue, Value::String(dest));
-
-    Ok(())
-  }
-
-  fn assign_key(&mut self, input: ExecInput) {
-    match input {
-      ExecInput::Key(key) => {
-        self
-          .str_stack
-          .push((self.code[self.pc].loc.clone(), ByteString::from(vec![key])));
-      }
-      _ => unreachable!(),
-    }
-    self.pc += 1;
-  }
-
-  fn assign_input(
-    &mut self,
-    input: ExecInput,
-    lvalues: Vec<(Location, LValue)>,
-    skip_first: bool,
-  ) {
-    if let ExecInput::KeyboardInput(mut values) = input {
-      let mut comma = false;
-      let mut lvalues = lvalues.into_iter().peekable();
-      if skip_first {
-        comma = true;
-        match &lvalues.peek().unwrap().1 {
-          LValue::Var { name } | LValue::Index { name, .. } => {
-            match symbol_type(&self.interner, *name) {
-              Type::Integer => values.insert(0, KeyboardInput::Integer(0)),
-              Type::Real => values.insert(0, KeyboardInput::Real(Mbf5::ZERO)),
-              Type::String => {
-                values.insert(0, KeyboardInput::String(ByteString::new()))
-              }
-            }
-          }
-          LValue::Fn { .. } => unreachable!(),
-        }
-      }
-      for ((lval_loc, lvalue), value) in lvalues.zip(values) {
-        if comma {
-          self.device.print(b",");
-        }
-        comma = true;
-        match value {
-          KeyboardInput::Integer(num) => {
-            self.device.print(num.to_string().as_bytes());
-            self.bindings.store_value(lvalue, Value::Integer(num));
-          }
-          KeyboardInput::Real(num) => {
-            self.device.print(num.to_string().as_bytes());
-            self.bindings.store_value(lvalue, Value::Real(num));
-          }
-          KeyboardInput::String(s) => {
-            self.device.print(&s);
-            self.bindings.store_value(lvalue, Value::String(s));
-          }
-          KeyboardInput::Func { body } => {
-            let (name, param) = match &lvalue {
-              LValue::Fn { name, param } => {
-                self.device.print(
-                  format!(
-                    "FN {}({})",
-                    self.interner.resolve(*name).unwrap(),
-                    self.interner.resolve(*param).unwrap()
-                  )
-                  .as_bytes(),
-                );
-                (*name, *param)
-              }
-              _ => unreachable!(),
-            };
-
-            let mut sym_map = HashMap::default();
-            for (sym, name) in &body.interner {
-              let new_sym = self.interner.get_or_intern(name);
-              sym_map.insert(sym, new_sym);
-            }
-
-            let body_addr = Addr(self.code.len());
-            self.code.extend(body.code.into_iter().map(|instr| Instr {
-              loc: lval_loc.clone(),
-              kind: instr.kind.map_symbol(&sym_map),
-            }));
-            self.code.push(Instr {
-              loc: lval_loc,
-              kind: InstrKind::ReturnFn,
-            });
-
-            self
-              .bindings
-              .user_funcs
-              .insert(name, UserFunc { param, body_addr });
-          }
-        };
-      }
-    } else {
-      unreachable!()
-    }
-    self.device.newline();
-    self.device.flush();
-    self.pc += 1;
-  }
-
-  fn calc_array_offset(
-    &mut self,
-    name: Symbol,
-    dimensions: NonZeroUsize,
-  ) -> Result<usize> {
-    let dimensions = dimensions.get();
-
-    if let HashMapEntry::Vacant(e) = self.bindings.arrays.entry(name) {
-      let data = ArrayData::new(
-        symbol_type(&self.interner, name),
-        11usize.pow(dimensions as _),
-      );
-      e.insert(Array {
-        dimensions: (0..dimensions)
-          .fold((vec![], 1), |(mut d, mult), _| {
-            d.push(Dimension {
-              bound: unsafe { NonZeroU16::new_unchecked(11) },
-              multiplier: mult,
-            });
-            (d, mult * 11)
-          })
-          .0,
-        data,
-      });
+  fn calc_array_offset(
+    &mut self,
+    name: Symbol,
+    dimensions: NonZeroUsize,
+  ) -> Result<usize> {
+    let dimensions = dimensions.get();
+
+    if let StoreMapEntry::Vacant(e) = self.bindings.arrays.entry(name) {
+      let data = ArrayData::new(
+        symbol_type(&self.interner, name),
+        11usize.pow(dimensions as _),
+      );
+      self.bindings.array_bytes += data.mem_size();
+      e.insert(Array {
+        dimensions: (0..dimensions)
+          .fold((vec![], 1), |(mut d, mult), _| {
+            d.push(Dimension {
+              bound: unsafe { NonZeroU16::new_unchecked(11) },
+              multiplier: mult,
+            });
+            (d, mult * 11)
+          })
+          .0,
+        data,
+      });
     }
 
     let array = &self.bindings.arrays[&name];
@@ -2367,6 +1118,13 @@ where
     }
   }
 
+  /// Pops a length or a position in strings, which is at most the maximum
+  /// length of strings.
+  fn pop_string_len(&mut self, nonzero: bool) -> Result<usize> {
+    let max = self.device.max_string_len();
+    Ok(self.pop_range(nonzero as _, max as _)? as _)
+  }
+
   fn pop_u8(&mut self, nonzero: bool) -> Result<u8> {
     Ok(self.pop_range(nonzero as _, 255)? as _)
   }
@@ -2384,40 +1142,26 @@ where
     Ok(value as _)
   }
 
-  /// Returns [0, 2].
-  fn get_filenum(&mut self, pop: bool) -> Result<u8> {
-    let (loc, value) = if pop {
-      self.num_stack.pop().unwrap()
-    } else {
-      self.num_stack.last().cloned().unwrap()
-    };
-    let int = f64::from(value) as i64;
-    if (1..=3).contains(&int) {
-      Ok(int as u8 - 1)
-    } else {
-      self.state.error(loc, "文件号超出范围 1~3")?
-    }
-  }
-
   fn store_int(
     &mut self,
     lvalue: LValue,
     (loc, num): (Location, Mbf5),
   ) -> Result<()> {
     assert_eq!(lvalue.get_type(&self.interner), Type::Integer);
-    let int = f64::from(num.truncate());
-    if int <= -32769.0 || int >= 32768.0 {
-      self.state.error(
+    match real_to_int(num, self.int_overflow()) {
+      Ok(int) => {
+        self.bindings.store_value(lvalue, Value::Integer(int));
+        Ok(())
+      }
+      Err(_) => self.state.error(
         loc,
         format!(
           "运算结果数值过大，超出了整数的表示范围（-32768~32767），\
               无法赋值给整数变量。运算结果为：{}",
           f64::from(num),
         ),
-      )?;
+      )?,
     }
-    self.bindings.store_value(lvalue, Value::Integer(int as _));
-    Ok(())
   }
 
   fn store_real(&mut self, lvalue: LValue, num: Mbf5) -> Result<()> {
@@ -2430,13 +1174,14 @@ where
     &self,
     input: &Utf16Str,
   ) -> (Option<InputFuncBody>, Vec<Diagnostic>) {
-    compile_fn(input, self.emoji_version)
+    compile_fn(input, self.emoji_version, self.device.max_string_len())
   }
 }
 
 fn compile_fn(
   input: &Utf16Str,
   emoji_version: EmojiVersion,
+  max_string_len: usize,
 ) -> (Option<InputFuncBody>, Vec<Diagnostic>) {
   if input.is_blank() {
     return (
@@ -2447,6 +1192,7 @@ fn compile_fn(
 
   let (mut expr, _) = parse_expr(input);
   let mut codegen = CodeGen::new(emoji_version);
+  codegen.set_max_string_len(max_string_len);
   compile_fn_body(input, &mut expr, &mut codegen);
   if contains_errors(&expr.diagnostics) {
     (None, expr.diagnostics)
@@ -2455,136 +1201,26 @@ fn compile_fn(
   }
 }
 
-fn exec_file_input<F: FileHandle, S>(
-  state: &mut ExecState<S>,
-  bindings: &mut Bindings,
-  interner: &StringInterner,
-  emoji_version: EmojiVersion,
-  loc: Location,
-  lvalue: LValue,
-  file: &mut F,
-) -> Result<()> {
-  let mut buf = vec![];
-  let mut quoted = false;
-  'read_file: {
-    let mut byte = [0];
-    let len = state.io(loc.clone(), "读取文件", file.read(&mut byte))?;
-    if len == 0 {
-      break 'read_file;
-    }
-
-    if byte[0] == b'"' {
-      quoted = true;
-    } else if byte[0] == 0xff || byte[0] == b',' {
-      break 'read_file;
-    } else {
-      buf.push(byte[0]);
-    }
-
-    let mut str_end = false;
-    loop {
-      let mut byte = [0];
-      let len = state.io(loc.clone(), "读取文件", file.read(&mut byte))?;
-      if len == 0 {
-        if quoted && !str_end {
-          state.error(loc, "读取字符串时遇到未匹配的双引号")?
-        }
-        break;
-      }
-      if quoted {
-        if str_end {
-          if byte[0] == 0xff || byte[0] == b',' {
-            break;
-          } else {
-            state.error(
-              loc,
-              format!(
-                "读取到的数据：\"{}\"，没有以逗号或 U+00FF 字符结尾",
-                ByteString::from(buf).to_string_lossy(emoji_version)
-              ),
-            )?
-          }
-        } else if byte[0] == b'"' {
-          str_end = true;
-          continue;
-        }
-      } else if byte[0] == 0xff || byte[0] == b',' {
-        break;
-      }
-      buf.push(byte[0]);
-    }
-  }
-
-  let value = match lvalue.get_type(interner) {
-    ty @ (Type::Integer | Type::Real) => {
-      if quoted {
-        state.error(
-          loc,
-          format!(
-            "读取到的数据：\"{}\"，是用引号括起来的字符串，无法转换为数值",
-            ByteString::from(buf).to_string_lossy(emoji_version)
-          ),
-        )?
-      }
-
-      match unsafe { std::str::from_utf8_unchecked(&buf) }.parse::<Mbf5>() {
-        Ok(num) => {
-          if ty == Type::Integer {
-            let int = f64::from(num.truncate());
-            if int <= -32769.0 || int >= 32768.0 {
-              state.error(
-                loc,
-                format!(
-                  "读取到的数值：{}，超出了整数的表示范围（-32768~32767），\
-                    无法赋值给整数变量",
-                  f64::from(num),
-                ),
-              )?;
-            } else {
-              Value::Integer(int as _)
-            }
-          } else {
-            Value::Real(num)
-          }
-        }
-        Err(ParseRealError::Malformed) => {
-          state.error(
-            loc,
-            format!(
-              "读取到的数据：{}，不符合实数的格式",
-              ByteString::from(buf).to_string_lossy(emoji_version)
-            ),
-          )?;
-        }
-        Err(ParseRealError::Infinite) => {
-          state.error(
-            loc,
-            format!(
-              "读取到的数据：{}，数值过大，超出了实数的表示范围",
-              ByteString::from(buf).to_string_lossy(emoji_version)
-            ),
-          )?;
-        }
-      }
-    }
-    Type::String => Value::String(buf.into()),
-  };
-
-  bindings.store_value(lvalue, value);
-
-  Ok(())
-}
-
 impl<S> ExecState<S> {
   fn error<M: ToString>(
     &mut self,
     location: Location,
     message: M,
+  ) -> Result<!> {
+    self.error_in_loop(location, message, None)
+  }
+
+  fn error_in_loop<M: ToString>(
+    &mut self,
+    location: Location,
+    message: M,
+    loop_context: Option<LoopContext>,
   ) -> Result<!> {
     *self = Self::Done;
     Err(ExecResult::Error {
       location,
       message: message.to_string(),
+      loop_context,
     })
   }
 
@@ -2599,12 +1235,17 @@ impl<S> ExecState<S> {
     skip_first: bool,
     prompt: Option<String>,
     fields: Vec<KeyboardInputType>,
+    defaults: Vec<Option<String>>,
   ) -> Result<!> {
     *self = Self::WaitForKeyboardInput {
       lvalues,
       skip_first,
     };
-    Err(ExecResult::KeyboardInput { prompt, fields })
+    Err(ExecResult::KeyboardInput {
+      prompt,
+      fields,
+      defaults,
+    })
   }
 
   fn suspend_asm(&mut self, loc: Location, state: S) -> Result<!> {
@@ -2637,6 +1278,7 @@ impl<S> ExecState<S> {
           io::ErrorKind::IsADirectory => "是文件夹".to_owned(),
           io::ErrorKind::PermissionDenied => "没有权限".to_owned(),
           io::ErrorKind::FileTooLarge => "文件大小超出64KB的限制".to_owned(),
+          io::ErrorKind::StorageFull => "存储空间已满".to_owned(),
           _ => err.to_string(),
         };
         self.error(loc, format!("{op}时发生错误：{err}"))?
@@ -2666,33 +1308,7 @@ fn symbol_type(interner: &StringInterner, symbol: Symbol) -> Type {
     b'%' => Type::Integer,
     b'$' => Type::String,
     _ => Type::Real,
-  }
-}
-
-fn u32_to_random_number(x: u32) -> Mbf5 {
-  if x == 0 {
-    return Mbf5::ZERO;
-  }
-  let n = x.leading_zeros();
-  let exponent = (0x80 - n) as _;
-  let x = x << n;
-  let mant1 = (x >> 24) as u8 & 0x7f;
-  let mant2 = (x >> 16) as _;
-  let mant3 = (x >> 8) as _;
-  let mant4 = x as _;
-  Mbf5::from([exponent, mant1, mant2, mant3, mant4])
-}
-
-#[test]
-fn test_u32_to_random_number() {
-  assert_eq!(
-    u32_to_random_number(0x61_00_00_00),
-    Mbf5::from([0x7fu8, 0x42, 0, 0, 0])
-  );
-  assert_eq!(
-    u32_to_random_number(0x00_00_00_01),
-    Mbf5::from([0x61u8, 0, 0, 0, 0])
-  );
+  }
 }
 
 impl ArrayData {
@@ -2700,7 +1316,17 @@ impl ArrayData {
     match ty {
       Type::Integer => ArrayData::Integer(vec![0; size]),
       Type::Real => ArrayData::Real(vec![Mbf5::ZERO; size]),
-      Type::String => ArrayData::String(vec![ByteString::new(); size]),
+      Type::String => ArrayData::String(StringArray::new(size)),
+    }
+  }
+
+  /// Memory size of elements on the real machine, excluding contents of
+  /// strings.
+  fn mem_size(&self) -> usize {
+    match self {
+      ArrayData::Integer(arr) => arr.len() * 2,
+      ArrayData::Real(arr) => arr.len() * 5,
+      ArrayData::String(arr) => arr.len() * 3,
     }
   }
 }
@@ -2734,12 +1360,24 @@ impl Bindings {
     self.vars.clear();
     self.arrays.clear();
     self.user_funcs.clear();
+    self.string_bytes = 0;
+    self.array_bytes = 0;
+  }
+
+  fn insert_array(&mut self, name: Symbol, array: Array) {
+    self.array_bytes += array.data.mem_size();
+    self.arrays.insert(name, array);
   }
 
   fn store_value(&mut self, lvalue: LValue, value: Value) {
     match lvalue {
       LValue::Var { name } => {
-        self.vars.insert(name, value);
+        if let Value::String(str) = &value {
+          self.string_bytes += str.len();
+        }
+        if let Some(Value::String(old)) = self.vars.insert(name, value) {
+          self.string_bytes -= old.len();
+        }
       }
       LValue::Index { name, offset } => {
         match (&mut self.arrays.get_mut(&name).unwrap().data, value) {
@@ -2750,7 +1388,9 @@ impl Bindings {
             arr[offset] = num;
           }
           (ArrayData::String(arr), Value::String(str)) => {
-            arr[offset] = str;
+            self.string_bytes -= arr.get(offset).len();
+            self.string_bytes += str.len();
+            arr.set(offset, &str);
           }
           _ => unreachable!(),
         }
@@ -2776,7 +1416,9 @@ impl Bindings {
       LValue::Index { name, offset } => match &self.arrays[&name].data {
         ArrayData::Integer(arr) => Value::Integer(arr[offset]),
         ArrayData::Real(arr) => Value::Real(arr[offset]),
-        ArrayData::String(arr) => Value::String(arr[offset].clone()),
+        ArrayData::String(arr) => {
+          Value::String(ByteString::from(arr.get(offset)))
+        }
       },
       _ => unreachable!(),
     }
@@ -2788,10 +1430,15 @@ mod tests {
   use super::*;
   use crate::ast::Range;
   use crate::compiler::compile_prog;
+  use crate::device::AsmExecState;
   use crate::diagnostic::Severity;
-  use crate::machine::EmojiVersion;
+  use crate::machine::{
+    EmojiVersion, EofBehavior, IntOverflow, InvalidNotes, SelectorRounding,
+    DEFAULT_MAX_STRING_LEN,
+  };
   use crate::parser::parse_prog;
   use crate::vm::codegen::CodeGen;
+  use bstr::ByteSlice;
   use insta::assert_snapshot;
   use pretty_assertions::assert_eq;
   use std::cell::RefCell;
@@ -2799,9 +1446,14 @@ mod tests {
   use widestring::{utf16str, Utf16String};
 
   fn compile(text: &str) -> CodeGen {
+    compile_with_max_string_len(text, DEFAULT_MAX_STRING_LEN)
+  }
+
+  fn compile_with_max_string_len(text: &str, max: usize) -> CodeGen {
     let text = Utf16String::from(text);
     let mut prog = parse_prog(&text);
     let mut codegen = CodeGen::new(EmojiVersion::V2);
+    codegen.set_max_string_len(max);
     compile_prog(&text, &mut prog, &mut codegen);
     for (i, line) in prog.lines.iter().enumerate() {
       let diags: Vec<_> = line
@@ -2889,6 +1541,7 @@ mod tests {
         range: Range::new(start, end),
       },
       message: msg.to_string(),
+      loop_context: None,
     }
   }
 
@@ -2897,6 +1550,12 @@ mod tests {
     mem: [u8; 65536],
     files: HashMap<Vec<u8>, File>,
     cursor: (u8, u8),
+    text_size: (u8, u8),
+    contexts: Vec<(usize, usize, usize)>,
+    int_overflow: IntOverflow,
+    invalid_notes: InvalidNotes,
+    selector_rounding: SelectorRounding,
+    max_string_len: usize,
   }
 
   #[derive(Debug, Clone, Default)]
@@ -2915,6 +1574,12 @@ mod tests {
         mem: [0; 65536],
         files: HashMap::default(),
         cursor: (0, 0),
+        text_size: (5, 20),
+        contexts: vec![],
+        int_overflow: IntOverflow::Error,
+        invalid_notes: InvalidNotes::Ignore,
+        selector_rounding: SelectorRounding::Truncate,
+        max_string_len: DEFAULT_MAX_STRING_LEN,
       }
     }
 
@@ -2966,6 +1631,10 @@ mod tests {
       self.cursor.1 = column;
     }
 
+    fn text_size(&self) -> (u8, u8) {
+      self.text_size
+    }
+
     fn print(&mut self, str: &[u8]) {
       if str.iter().all(|&b| b < 0x80) {
         add_log(
@@ -3057,6 +1726,22 @@ mod tests {
       EofBehavior::Normal
     }
 
+    fn int_overflow(&self) -> IntOverflow {
+      self.int_overflow
+    }
+
+    fn invalid_notes(&self) -> InvalidNotes {
+      self.invalid_notes
+    }
+
+    fn selector_rounding(&self) -> SelectorRounding {
+      self.selector_rounding
+    }
+
+    fn max_string_len(&self) -> usize {
+      self.max_string_len
+    }
+
     fn read_byte(&self, addr: u16) -> u8 {
       add_log(
         self.log.clone(),
@@ -3065,667 +1750,1563 @@ mod tests {
       self.mem[addr as usize]
     }
 
-    fn write_byte(&mut self, addr: u16, byte: u8) {
-      add_log(self.log.clone(), format!("poke {addr}, {byte}"));
-      self.mem[addr as usize] = byte;
-    }
+    fn write_byte(&mut self, addr: u16, byte: u8) {
+      add_log(self.log.clone(), format!("poke {addr}, {byte}"));
+      self.mem[addr as usize] = byte;
+    }
+
+    fn is_screen_addr(&self, addr: u16) -> bool {
+      addr < 1600
+    }
+
+    fn set_context(&mut self, loc: &Location) {
+      self
+        .contexts
+        .push((loc.line, loc.range.start, loc.range.end));
+    }
+
+    fn check_point(&self, (x, y): (i32, i32)) -> bool {
+      add_log(self.log.clone(), format!("check point ({x}, {y})"));
+      false
+    }
+
+    fn check_key(&self, key: u8) -> bool {
+      add_log(self.log.clone(), format!("check key {key}"));
+      false
+    }
+
+    fn key(&mut self) -> Option<u8> {
+      add_log(self.log.clone(), "take key");
+      None
+    }
+
+    fn user_quit(&self) -> bool {
+      false
+    }
+
+    fn open_file(
+      &mut self,
+      file: &mut Self::File,
+      name: &[u8],
+      read: bool,
+      write: bool,
+      truncate: bool,
+    ) -> io::Result<()> {
+      add_log(
+        self.log.clone(),
+        format!(
+          "open file {}, read: {:?}, write: {:?}, truncate: {:?}",
+          if name.bytes().all(|b| b < 0x80) {
+            format!("\"{}\"", unsafe { std::str::from_utf8_unchecked(name) })
+          } else {
+            format!("{name:?}")
+          },
+          read,
+          write,
+          truncate
+        ),
+      );
+      *file = if let Some(file) = self.files.get(name) {
+        file.clone()
+      } else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+      };
+      if truncate {
+        file.data.borrow_mut().clear();
+      }
+      file.is_open = true;
+      file.pos = 0;
+      Ok(())
+    }
+
+    fn cls(&mut self) {
+      add_log(self.log.clone(), "cls");
+    }
+
+    fn exec_asm(
+      &mut self,
+      steps: &mut usize,
+      state: AsmExecState<()>,
+    ) -> std::result::Result<Option<()>, String> {
+      if let AsmExecState::Start(addr) = state {
+        add_log(
+          self.log.clone(),
+          format!("call Some({addr}), steps: {steps}"),
+        );
+      } else {
+        add_log(self.log.clone(), format!("call None, steps: {steps}"));
+      }
+      Ok(None)
+    }
+
+    fn set_screen_mode(&mut self, mode: ScreenMode) {
+      add_log(self.log.clone(), format!("set screen mode to {mode:?}"));
+    }
+
+    fn set_print_mode(&mut self, mode: PrintMode) {
+      add_log(self.log.clone(), format!("set print mode to {mode:?}"));
+    }
+
+    fn sleep_unit(&self) -> std::time::Duration {
+      std::time::Duration::from_millis(1)
+    }
+
+    fn sleep(&mut self, duration: Duration) -> Duration {
+      duration
+    }
+
+    fn beep(&mut self) {
+      add_log(self.log.clone(), "beep");
+    }
+
+    fn play_notes(&mut self, notes: &[u8]) {
+      add_log(
+        self.log.clone(),
+        format!("play notes \"{}\"", unsafe {
+          std::str::from_utf8_unchecked(notes)
+        }),
+      );
+    }
+
+    fn sound(&mut self, frequency: u16, duration: Duration) {
+      add_log(
+        self.log.clone(),
+        format!("sound {} {}ms", frequency, duration.as_millis()),
+      );
+    }
+  }
+
+  impl FileHandle for File {
+    fn len(&self) -> io::Result<u64> {
+      add_log(
+        self.log.clone(),
+        format!("get file len: {}", self.data.borrow().len()),
+      );
+      Ok(self.data.borrow().len() as _)
+    }
+
+    fn seek(&mut self, pos: u64) -> io::Result<()> {
+      add_log(self.log.clone(), format!("seek file: {pos}"));
+      if pos > self.data.borrow().len() as u64 {
+        Err(io::Error::new(io::ErrorKind::Other, "out of range"))
+      } else {
+        self.pos = pos as _;
+        Ok(())
+      }
+    }
+
+    fn pos(&self) -> io::Result<u64> {
+      add_log(self.log.clone(), format!("get file pos: {}", self.pos));
+      Ok(self.pos as _)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+      add_log(self.log.clone(), format!("write to file: {data:?} "));
+      if self.pos + data.len() > self.data.borrow().len() {
+        self.data.borrow_mut().resize(self.pos + data.len(), 0);
+      }
+      self.data.borrow_mut()[self.pos..self.pos + data.len()]
+        .copy_from_slice(data);
+      self.pos += data.len();
+      Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+      let mut len = data.len();
+      if self.pos + len > self.data.borrow().len() {
+        len = self.data.borrow().len() - self.pos;
+      }
+      data[..len]
+        .copy_from_slice(&self.data.borrow()[self.pos..self.pos + len]);
+      add_log(self.log.clone(), format!("read from file: {data:?} "));
+      self.pos += len;
+      Ok(len)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+      add_log(self.log.clone(), "close file");
+      self.is_open = false;
+      Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+      self.is_open
+    }
+  }
+
+  #[test]
+  fn assign() {
+    assert_snapshot!(run(
+      r#"
+10 let a =1:b=a*3+10:dim c(5):c(0)=10:c(1)=20:c(2)=30:c(3)=40:c(4)=50:c(5)=60:
+20 c=c(a):print a,b,c,"abC",c(3)+c(0)*10
+30 c%=32767+1
+    "#
+      .trim(),
+      vec![(
+        exec_error(
+          2,
+          6,
+          13,
+          "运算结果数值过大，超出了整数的表示范围（-32768~32767），\
+            无法赋值给整数变量。运算结果为：32768"
+        ),
+        ExecInput::None,
+      )],
+    ));
+  }
+
+  #[test]
+  fn bindings_order() {
+    let codegen = compile("10 z=1:dim m(2):a$=\"x\":c%=3:b=z");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(
+      vm.bindings().into_keys().collect::<Vec<_>>(),
+      vec!["A$", "B", "C%", "M", "Z"]
+    );
+    #[cfg(feature = "ordered-store")]
+    {
+      let syms = vm.bindings.vars.keys().copied().collect::<Vec<_>>();
+      let mut sorted = syms.clone();
+      sorted.sort();
+      assert_eq!(syms, sorted);
+    }
+  }
+
+  #[test]
+  fn draw() {
+    assert_snapshot!(run(
+      r#"
+0 x=10:y=20:x1=11:y1=22:x2=33:y2=44:r=6:f=3:m=2
+10 draw x,y+1:draw x1,y1,m
+20 line x1,y1,x2,y2:line x1,y1,x2,y2,0:
+30 box x1,y1,x2,y2:box x,y,x,y%,f:box x1,y1,x2+1,y2,4,m
+40 circle x1,y1,r:circle x,y,r,1:circle x,y,r,0,m
+50 ellipse x,y,7,3:ellipse x,y,7,3,1:ellipse x,y,7,3,f,m
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)],
+    ));
+  }
+
+  #[test]
+  fn nullary_statement() {
+    assert_snapshot!(run(
+      r#"
+10 beep:cls:cont:flash:graph:inkey$:inverse:normal:text
+20 :
+30 end:print 3
+    "#
+      .trim(),
+      vec![
+        (ExecResult::InKey, ExecInput::Key(65)),
+        (ExecResult::End, ExecInput::None),
+      ],
+    ));
+  }
+
+  #[test]
+  fn ppc() {
+    assert_snapshot!(run(
+      r#"
+10 for i=100 to 105:poke i,i-99:next:print peek(101);peek(104):call 1000
+20 call -2
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)],
+    ));
+  }
 
-    fn check_point(&self, (x, y): (i32, i32)) -> bool {
-      add_log(self.log.clone(), format!("check point ({x}, {y})"));
-      false
-    }
+  #[test]
+  fn clear() {
+    assert_snapshot!(run_with_file(
+      r#"
+10 open "foo" input as1:a=100:a(10)=2:read c1$,c2$:print a;a(10);c1$;c2$:clear
+20 open "foo" output as1:read c3$:print a;a(10);c3$:gosub 30
+30 data a 1, "a 2" , a 3:clear:pop
+    "#
+      .trim(),
+      vec![(
+        exec_error(2, 31, 34, "之前没有执行过 GOSUB 语句，POP 语句无法执行"),
+        ExecInput::None,
+      )],
+      b"foo.DAT",
+      File::new(vec![])
+    ));
+  }
 
-    fn check_key(&self, key: u8) -> bool {
-      add_log(self.log.clone(), format!("check key {key}"));
-      false
-    }
+  #[test]
+  fn clear_loop() {
+    assert_snapshot!(run(
+      r#"
+10 for i=1 to 3:clear:next
+    "#
+      .trim(),
+      vec![(
+        exec_error(0, 22, 26, "NEXT 语句找不到匹配的 FOR 语句"),
+        ExecInput::None
+      )]
+    ));
+  }
 
-    fn key(&mut self) -> Option<u8> {
-      add_log(self.log.clone(), "take key");
-      None
-    }
+  #[test]
+  fn r#fn() {
+    assert_snapshot!(run(
+      r#"
+10 def fn pi(x)=atn(1)*4*x:x=3:print x;:print fn pi(1);:print x;:
+20 def fn pi(y)=int(y)*10:print fn pi(3.5);
+30 clear:print fn pi(1)
+    "#
+      .trim(),
+      vec![(exec_error(2, 15, 23, "自定义函数不存在"), ExecInput::None)]
+    ));
+  }
 
-    fn user_quit(&self) -> bool {
-      false
-    }
+  #[test]
+  fn read() {
+    assert_snapshot!(run(
+      r#"
+10 data abc, "123", 1e3
+20 data ,,3e
+30 read a$(10),b$,c%,d:print a$(10);b$;c%;d
+40 restore:read a$,b$,c%,d:print a$;b$;c%;d
+50 restore 20:read a$,b$,c%:print a$;b$;c%:read d
+    "#
+      .trim(),
+      vec![(
+        exec_error(4, 48, 49, "DATA 已经读取结束，没有更多 DATA 可供读取"),
+        ExecInput::None
+      )]
+    ));
+  }
 
-    fn open_file(
-      &mut self,
-      file: &mut Self::File,
-      name: &[u8],
-      read: bool,
-      write: bool,
-      truncate: bool,
-    ) -> io::Result<()> {
-      add_log(
-        self.log.clone(),
-        format!(
-          "open file {}, read: {:?}, write: {:?}, truncate: {:?}",
-          if name.bytes().all(|b| b < 0x80) {
-            format!("\"{}\"", unsafe { std::str::from_utf8_unchecked(name) })
-          } else {
-            format!("{name:?}")
-          },
-          read,
-          write,
-          truncate
+  #[test]
+  fn read_quoted_number() {
+    assert_snapshot!(run(
+      r#"
+10 data "123"
+30 read a
+    "#
+      .trim(),
+      vec![(
+        exec_error(
+          1,
+          8,
+          9,
+          "读取到的数据：\"123\"，是用引号括起来的字符串，无法转换为数值"
         ),
-      );
-      *file = if let Some(file) = self.files.get(name) {
-        file.clone()
-      } else {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "no such file"));
-      };
-      if truncate {
-        file.data.borrow_mut().clear();
-      }
-      file.is_open = true;
-      file.pos = 0;
-      Ok(())
-    }
-
-    fn cls(&mut self) {
-      add_log(self.log.clone(), "cls");
-    }
-
-    fn exec_asm(
-      &mut self,
-      steps: &mut usize,
-      state: AsmExecState<()>,
-    ) -> std::result::Result<Option<()>, String> {
-      if let AsmExecState::Start(addr) = state {
-        add_log(
-          self.log.clone(),
-          format!("call Some({addr}), steps: {steps}"),
-        );
-      } else {
-        add_log(self.log.clone(), format!("call None, steps: {steps}"));
-      }
-      Ok(None)
-    }
+        ExecInput::None
+      )]
+    ));
+  }
 
-    fn set_screen_mode(&mut self, mode: ScreenMode) {
-      add_log(self.log.clone(), format!("set screen mode to {mode:?}"));
-    }
+  #[test]
+  fn dim() {
+    assert_snapshot!(run(
+      r#"
+10 dim a,a,a$(3):a$(4)=a$
+    "#
+      .trim(),
+      vec![(
+        exec_error(
+          0,
+          20,
+          21,
+          "数组下标超出上限。该下标的上限为：3，该下标的值为：4, \
+            取整后的值为：4"
+        ),
+        ExecInput::None
+      )]
+    ));
+  }
 
-    fn set_print_mode(&mut self, mode: PrintMode) {
-      add_log(self.log.clone(), format!("set print mode to {mode:?}"));
-    }
+  #[test]
+  fn redefine_array() {
+    assert_snapshot!(run(
+      r#"
+10 dim a,a,a$(3):dim a$(2,7):
+    "#
+      .trim(),
+      vec![(exec_error(0, 21, 23, "重复定义数组"), ExecInput::None)]
+    ));
+  }
 
-    fn sleep_unit(&self) -> std::time::Duration {
-      std::time::Duration::from_millis(1)
-    }
+  #[test]
+  fn for_loop() {
+    assert_snapshot!(run(
+      r#"
+10 for i=i to i+3:print i;:next i:print i*1e3;
+20 for k=10 to 1 step -2:k=k-0.5:print k;:next:print k*1e3;
+30 for i=1 to 2 step 0:print i;:i=i+1:next:print i*1e3;
+40 for i=1 to 1 step 2:print i;:next:print i*1e3;
+50 for i=1 to 10:for i=-10 to -9:print i;:next:next
+    "#
+      .trim(),
+      vec![(
+        exec_error(4, 47, 51, "NEXT 语句找不到匹配的 FOR 语句"),
+        ExecInput::None,
+      )]
+    ));
+  }
 
-    fn beep(&mut self) {
-      add_log(self.log.clone(), "beep");
-    }
+  #[test]
+  fn jump() {
+    assert_snapshot!(run(
+      r#"
+10 cls:goto 30
+20 print inkey$;:return
+30 gosub 20
+    "#
+      .trim(),
+      vec![
+        (ExecResult::InKey, ExecInput::Key(66)),
+        (ExecResult::End, ExecInput::None)
+      ]
+    ));
+  }
 
-    fn play_notes(&mut self, notes: &[u8]) {
-      add_log(
-        self.log.clone(),
-        format!("play notes \"{}\"", unsafe {
-          std::str::from_utf8_unchecked(notes)
-        }),
-      );
-    }
+  #[test]
+  fn r#if() {
+    assert_snapshot!(run(
+      r#"
+10 a=1:b=2:if a>=b then print "a";:30 else print "b";:40
+20 print "come";:end
+30 graph:end
+40 if a<>b then print "GO";:gosub 20:text:else print "go";:gosub 20:inverse
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)]
+    ));
   }
 
-  impl FileHandle for File {
-    fn len(&self) -> io::Result<u64> {
-      add_log(
-        self.log.clone(),
-        format!("get file len: {}", self.data.borrow().len()),
-      );
-      Ok(self.data.borrow().len() as _)
-    }
+  #[test]
+  fn input() {
+    use std::convert::TryFrom;
 
-    fn seek(&mut self, pos: u64) -> io::Result<()> {
-      add_log(self.log.clone(), format!("seek file: {pos}"));
-      if pos > self.data.borrow().len() as u64 {
-        Err(io::Error::new(io::ErrorKind::Other, "out of range"))
-      } else {
-        self.pos = pos as _;
-        Ok(())
-      }
-    }
+    assert_snapshot!(run(
+      r#"
+10 input "foo"; a$, b
+20 input c%(2), fn f(y)
+30 def fn g(x)=x*x
+40 print a$; b; c%(2); fn f(3);
+    "#
+      .trim(),
+      vec![
+        (
+          ExecResult::KeyboardInput {
+            prompt: Some("foo".to_owned()),
+            fields: vec![KeyboardInputType::String, KeyboardInputType::Real],
+            defaults: vec![None, None],
+          },
+          ExecInput::KeyboardInput(vec![
+            KeyboardInput::String(b"ABc".to_vec().into()),
+            KeyboardInput::Real(Mbf5::try_from(3.5f64).unwrap()),
+          ]),
+        ),
+        (
+          ExecResult::KeyboardInput {
+            prompt: None,
+            fields: vec![
+              KeyboardInputType::Integer,
+              KeyboardInputType::Func {
+                name: "F".to_owned(),
+                param: "Y".to_owned()
+              }
+            ],
+            defaults: vec![None, None],
+          },
+          {
+            let body = compile_fn(
+              utf16str!("fn g(y)+2"),
+              EmojiVersion::V2,
+              DEFAULT_MAX_STRING_LEN,
+            )
+            .0
+            .unwrap();
+            ExecInput::KeyboardInput(vec![
+              KeyboardInput::Integer(37),
+              KeyboardInput::Func { body },
+            ])
+          }
+        ),
+        (ExecResult::End, ExecInput::None)
+      ]
+    ));
+  }
 
-    fn pos(&self) -> io::Result<u64> {
-      add_log(self.log.clone(), format!("get file pos: {}", self.pos));
-      Ok(self.pos as _)
-    }
+  #[test]
+  fn input_defaults() {
+    let codegen = compile("10 input a$, b%, fn f(x)\n20 end");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    let fields = vec![
+      KeyboardInputType::String,
+      KeyboardInputType::Integer,
+      KeyboardInputType::Func {
+        name: "F".to_owned(),
+        param: "X".to_owned(),
+      },
+    ];
+    let input = || {
+      let body =
+        compile_fn(utf16str!("x+1"), EmojiVersion::V2, DEFAULT_MAX_STRING_LEN)
+          .0
+          .unwrap();
+      ExecInput::KeyboardInput(vec![
+        KeyboardInput::String(b"abc".to_vec().into()),
+        KeyboardInput::Integer(-3),
+        KeyboardInput::Func { body },
+      ])
+    };
 
-    fn write(&mut self, data: &[u8]) -> io::Result<()> {
-      add_log(self.log.clone(), format!("write to file: {data:?} "));
-      if self.pos + data.len() > self.data.borrow().len() {
-        self.data.borrow_mut().resize(self.pos + data.len(), 0);
+    vm.start();
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::KeyboardInput {
+        prompt: None,
+        fields: fields.clone(),
+        defaults: vec![None, None, None],
       }
-      self.data.borrow_mut()[self.pos..self.pos + data.len()]
-        .copy_from_slice(data);
-      self.pos += data.len();
-      Ok(())
-    }
+    );
+    assert_eq!(vm.exec(input(), usize::MAX), ExecResult::End);
 
-    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
-      let mut len = data.len();
-      if self.pos + len > self.data.borrow().len() {
-        len = self.data.borrow().len() - self.pos;
+    vm.start();
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::KeyboardInput {
+        prompt: None,
+        fields,
+        defaults: vec![Some("abc".to_owned()), Some("-3".to_owned()), None],
       }
-      data[..len]
-        .copy_from_slice(&self.data.borrow()[self.pos..self.pos + len]);
-      add_log(self.log.clone(), format!("read from file: {data:?} "));
-      self.pos += len;
-      Ok(len)
-    }
-
-    fn close(&mut self) -> io::Result<()> {
-      add_log(self.log.clone(), "close file");
-      self.is_open = false;
-      Ok(())
-    }
+    );
+  }
 
-    fn is_open(&self) -> bool {
-      self.is_open
-    }
+  #[test]
+  fn locate() {
+    assert_snapshot!(run(
+      r#"
+10 locate 3:locate ,10:locate 5,1:locate 6
+    "#
+      .trim(),
+      vec![(
+        exec_error(0, 41, 42, "参数超出范围 1~5。运算结果为：6"),
+        ExecInput::None
+      )]
+    ));
   }
 
   #[test]
-  fn assign() {
+  fn locate_error_column() {
     assert_snapshot!(run(
       r#"
-10 let a =1:b=a*3+10:dim c(5):c(0)=10:c(1)=20:c(2)=30:c(3)=40:c(4)=50:c(5)=60:
-20 c=c(a):print a,b,c,"abC",c(3)+c(0)*10
-30 c%=32767+1
+10 locate 4, 2 0 +1:
     "#
       .trim(),
       vec![(
-        exec_error(
-          2,
-          6,
-          13,
-          "运算结果数值过大，超出了整数的表示范围（-32768~32767），\
-            无法赋值给整数变量。运算结果为：32768"
-        ),
-        ExecInput::None,
-      )],
+        exec_error(0, 13, 19, "参数超出范围 1~20。运算结果为：21"),
+        ExecInput::None
+      )]
     ));
   }
 
   #[test]
-  fn draw() {
+  fn set() {
     assert_snapshot!(run(
       r#"
-0 x=10:y=20:x1=11:y1=22:x2=33:y2=44:r=6:f=3:m=2
-10 draw x,y+1:draw x1,y1,m
-20 line x1,y1,x2,y2:line x1,y1,x2,y2,0:
-30 box x1,y1,x2,y2:box x,y,x,y%,f:box x1,y1,x2+1,y2,4,m
-40 circle x1,y1,r:circle x,y,r,1:circle x,y,r,0,m
-50 ellipse x,y,7,3:ellipse x,y,7,3,1:ellipse x,y,7,3,f,m
+10 a$="12345"
+20 lset a$="":print a$;
+30 lset a$="ab":print a$;
+40 lset a$="abcdefg":print a$;
+45 lset b$(3)="ab":print b$(3);
+50 rset a$="":print a$;
+60 rset a$="ab":print a$;
+70 rset a$="1234567890":print a$;
+80 rset b$(3)="ab":print b$(3);
     "#
       .trim(),
-      vec![(ExecResult::End, ExecInput::None)],
+      vec![(ExecResult::End, ExecInput::None)]
     ));
   }
 
   #[test]
-  fn nullary_statement() {
+  fn on() {
     assert_snapshot!(run(
       r#"
-10 beep:cls:cont:flash:graph:inkey$:inverse:normal:text
-20 :
-30 end:print 3
+10 a=10:gosub 30:a=4:gosub 30:a=7:gosub 30
+20 on 2 gosub 40, 50:end
+30 on (a>5)+(a<10)*2 goto 40, 50:print "A";:return
+40 print "B";:return
+50 print "C";:return
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)]
+    ));
+  }
+
+  #[test]
+  fn print() {
+    assert_snapshot!(run(
+      r#"
+10 print:print;:print "foo":locate ,10:print spc(2)tab(13);
+20 a$="ABCDEFG":a=3:print a$tab(3)a
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)]
+    ));
+  }
+
+  #[test]
+  fn run_stmt() {
+    assert_snapshot!(run_with_file(
+      r#"
+10 open "aaa" for input as 1:a$=a$+"E"
+20 if asc(inkey$) > 40 then print a$;:end else run
     "#
       .trim(),
       vec![
-        (ExecResult::InKey, ExecInput::Key(65)),
-        (ExecResult::End, ExecInput::None),
+        (ExecResult::InKey, ExecInput::Key(30)),
+        (ExecResult::InKey, ExecInput::Key(60)),
+        (ExecResult::End, ExecInput::None)
       ],
+      b"aaa.DAT",
+      File::new(vec![])
     ));
   }
 
   #[test]
-  fn ppc() {
+  fn swap() {
     assert_snapshot!(run(
       r#"
-10 for i=100 to 105:poke i,i-99:next:print peek(101);peek(104):call 1000
-20 call -2
+10 a%=30:a%(2)=555:swap a%,a%(2):print a%;a%(2);
+20 a$="abc":b$="ABC-#":swap b$,a$:print a$;b$;
     "#
       .trim(),
-      vec![(ExecResult::End, ExecInput::None)],
+      vec![(ExecResult::End, ExecInput::None)]
     ));
   }
 
   #[test]
-  fn clear() {
-    assert_snapshot!(run_with_file(
+  fn while_loop() {
+    assert_snapshot!(run(
       r#"
-10 open "foo" input as1:a=100:a(10)=2:read c1$,c2$:print a;a(10);c1$;c2$:clear
-20 open "foo" output as1:read c3$:print a;a(10);c3$:gosub 30
-30 data a 1, "a 2" , a 3:clear:pop
+10 while a<2:a=a+1:print a;:wend:
+20 while a>0:goto 50:wend:
+30 gosub 40:wend
+40 :while a<2:a=a+1:print a;:return:wend
+50 print ".";:a=a-1:wend:print "no"
     "#
       .trim(),
       vec![(
-        exec_error(2, 31, 34, "之前没有执行过 GOSUB 语句，POP 语句无法执行"),
-        ExecInput::None,
-      )],
-      b"foo.DAT",
-      File::new(vec![])
+        exec_error(2, 12, 16, "WEND 语句找不到匹配的 WHILE 语句"),
+        ExecInput::None
+      )]
+    ));
+  }
+
+  #[test]
+  fn write() {
+    assert_snapshot!(run(
+      r#"
+10 a=10:write 1, "foo", "Ab"+chr$(0)+"c": write 1 a*2+1, "a和C",:
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)]
+    ));
+  }
+
+  #[test]
+  fn sleep() {
+    assert_snapshot!(run(
+      r#"
+10 sleep -100:sleep 0:sleep 200:
+    "#
+      .trim(),
+      vec![
+        (
+          ExecResult::Sleep(Duration::from_millis(200)),
+          ExecInput::None
+        ),
+        (ExecResult::End, ExecInput::None)
+      ]
+    ));
+  }
+
+  #[test]
+  fn on_timer() {
+    assert_snapshot!(run(
+      r#"
+10 on timer(15) gosub 100:timer on
+20 for i=1 to 12:print i;:next
+30 timer off:for i=1 to 12:k=i:next:end
+100 print "T";:for j=1 to 5:k=j:next:return
+    "#
+      .trim(),
+      vec![(ExecResult::End, ExecInput::None)]
     ));
   }
 
   #[test]
-  fn clear_loop() {
+  fn sound() {
     assert_snapshot!(run(
       r#"
-10 for i=1 to 3:clear:next
+10 sound 440,250:f=37:sound f,0
+20 sound 36,100
     "#
       .trim(),
       vec![(
-        exec_error(0, 22, 26, "NEXT 语句找不到匹配的 FOR 语句"),
+        exec_error(1, 9, 11, "参数超出范围 37~32767。运算结果为：36"),
         ExecInput::None
       )]
     ));
   }
 
   #[test]
-  fn r#fn() {
+  fn timer_on_without_on_timer() {
     assert_snapshot!(run(
       r#"
-10 def fn pi(x)=atn(1)*4*x:x=3:print x;:print fn pi(1);:print x;:
-20 def fn pi(y)=int(y)*10:print fn pi(3.5);
-30 clear:print fn pi(1)
+10 timer off:timer on
     "#
       .trim(),
-      vec![(exec_error(2, 15, 23, "自定义函数不存在"), ExecInput::None)]
+      vec![(
+        exec_error(
+          0,
+          13,
+          21,
+          "之前没有执行过 ON TIMER 语句，TIMER ON 语句无法执行"
+        ),
+        ExecInput::None
+      )]
     ));
   }
 
   #[test]
-  fn read() {
+  fn for_replaces_while() {
     assert_snapshot!(run(
       r#"
-10 data abc, "123", 1e3
-20 data ,,3e
-30 read a$(10),b$,c%,d:print a$(10);b$;c%;d
-40 restore:read a$,b$,c%,d:print a$;b$;c%;d
-50 restore 20:read a$,b$,c%:print a$;b$;c%:read d
+10 for i=1 to 10:while 1:for i=1 to 2:cls:next i:wend
     "#
       .trim(),
       vec![(
-        exec_error(4, 48, 49, "DATA 已经读取结束，没有更多 DATA 可供读取"),
+        exec_error(0, 49, 53, "WEND 语句找不到匹配的 WHILE 语句"),
         ExecInput::None
       )]
     ));
   }
 
   #[test]
-  fn read_quoted_number() {
+  fn for_replaces_sub() {
     assert_snapshot!(run(
       r#"
-10 data "123"
-30 read a
+10 for i=1 to 2:print i;:gosub 20:
+20 next i:return
     "#
       .trim(),
       vec![(
-        exec_error(
-          1,
-          8,
-          9,
-          "读取到的数据：\"123\"，是用引号括起来的字符串，无法转换为数值"
-        ),
+        exec_error(1, 10, 16, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行"),
         ExecInput::None
       )]
     ));
   }
 
   #[test]
-  fn dim() {
+  fn while_replaces_for() {
     assert_snapshot!(run(
       r#"
-10 dim a,a,a$(3):a$(4)=a$
+10 while i<2:for j=1 to 10:i=i+1:print i;:wend:next j
     "#
       .trim(),
       vec![(
-        exec_error(
-          0,
-          20,
-          21,
-          "数组下标超出上限。该下标的上限为：3，该下标的值为：4, \
-            取整后的值为：4"
-        ),
+        exec_error(0, 47, 53, "NEXT 语句找不到匹配的 FOR 语句"),
         ExecInput::None
       )]
     ));
   }
 
   #[test]
-  fn redefine_array() {
+  fn while_replaces_sub() {
     assert_snapshot!(run(
       r#"
-10 dim a,a,a$(3):dim a$(2,7):
+10 while i<2:i=i+1:print i;:gosub 20
+20 wend:return
     "#
       .trim(),
-      vec![(exec_error(0, 21, 23, "重复定义数组"), ExecInput::None)]
+      vec![(
+        exec_error(1, 8, 14, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行"),
+        ExecInput::None
+      )]
     ));
   }
 
   #[test]
-  fn for_loop() {
+  fn sub_replaces_while() {
     assert_snapshot!(run(
       r#"
-10 for i=i to i+3:print i;:next i:print i*1e3;
-20 for k=10 to 1 step -2:k=k-0.5:print k;:next:print k*1e3;
-30 for i=1 to 2 step 0:print i;:i=i+1:next:print i*1e3;
-40 for i=1 to 1 step 2:print i;:next:print i*1e3;
-50 for i=1 to 10:for i=-10 to -9:print i;:next:next
+10 gosub 20:wend
+20 while 1:return:wend
     "#
       .trim(),
       vec![(
-        exec_error(4, 47, 51, "NEXT 语句找不到匹配的 FOR 语句"),
-        ExecInput::None,
+        exec_error(0, 12, 16, "WEND 语句找不到匹配的 WHILE 语句"),
+        ExecInput::None
       )]
     ));
   }
 
   #[test]
-  fn jump() {
-    assert_snapshot!(run(
+  fn run_to() {
+    let codegen = compile(
       r#"
-10 cls:goto 30
-20 print inkey$;:return
-30 gosub 20
+10 print "a";:print "b";
+20 print "c";
     "#
       .trim(),
-      vec![
-        (ExecResult::InKey, ExecInput::Key(66)),
-        (ExecResult::End, ExecInput::None)
-      ]
-    ));
+    );
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert!(vm.run_to(29));
+    assert!(!vm.run_to(100));
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Breakpoint {
+        location: Location {
+          line: 1,
+          range: Range::new(3, 13),
+        }
+      }
+    );
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    drop(vm);
+    assert_eq!(
+      *device.log.borrow(),
+      "print \"a\"\nflush\nprint \"b\"\nflush\nprint \"c\"\nflush\n"
+    );
   }
 
   #[test]
-  fn r#if() {
-    assert_snapshot!(run(
+  fn run_steps() {
+    let codegen = compile("10 sleep 200:goto 10");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(
+      vm.run_steps(ExecInput::None, usize::MAX, |_, _| None),
+      ExecResult::Sleep(Duration::from_millis(200))
+    );
+    let mut sleeps = 0;
+    assert_eq!(
+      vm.run_steps(ExecInput::None, 25000, |_, result| {
+        assert_eq!(result, &ExecResult::Sleep(Duration::from_millis(200)));
+        sleeps += 1;
+        Some(ExecInput::None)
+      }),
+      ExecResult::Continue
+    );
+    assert!(sleeps > 0);
+  }
+
+  #[test]
+  fn skip_current_statement() {
+    let codegen = compile(
       r#"
-10 a=1:b=2:if a>=b then print "a";:30 else print "b";:40
-20 print "come";:end
-30 graph:end
-40 if a<>b then print "GO";:gosub 20:text:else print "go";:gosub 20:inverse
+10 a=1:if a then print "a";:print "b";
+20 print "c";:print "d";
     "#
       .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
+    );
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert!(vm.run_to(7));
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Breakpoint { .. }
     ));
+    assert_eq!(vm.skip_current_statement(), Ok(()));
+    assert_eq!(vm.exec(ExecInput::None, 1), ExecResult::Continue);
+    assert_eq!(
+      vm.skip_current_statement(),
+      Err(SkipStatementError::UnbalancedStack)
+    );
+    assert_eq!(vm.exec(ExecInput::None, 2), ExecResult::Continue);
+    assert_eq!(vm.skip_current_statement(), Ok(()));
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(
+      vm.skip_current_statement(),
+      Err(SkipStatementError::NotRunning)
+    );
+    drop(vm);
+    assert_eq!(*device.log.borrow(), "print \"c\"\nflush\n");
   }
 
   #[test]
-  fn input() {
-    use std::convert::TryFrom;
+  fn read_only() {
+    let codegen = compile(
+      r#"
+10 poke 100,1:poke 2000,2:print peek(65535);
+20 open "a" for input as 1:close 1:open "b" for binary as 2
+30 open "c" for append as 3
+    "#
+      .trim(),
+    );
+    let mut device = TestDevice::new()
+      .with_file(b"a.DAT".to_vec(), File::new(vec![]))
+      .with_file(b"b.DAT".to_vec(), File::new(vec![]));
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.set_read_only(true);
+    vm.start();
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      exec_error(2, 3, 27, "只读模式下不能以 APPEND 模式打开文件")
+    );
+    drop(vm);
+    assert_snapshot!(device.log.borrow());
+  }
 
-    assert_snapshot!(run(
+  #[test]
+  fn policy_peek_addr() {
+    let codegen = compile("10 print peek(65535);");
+    let mut device = TestDevice::new();
+    device.mem[POLICY_PEEK_ADDR as usize] = 7;
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    vm.set_read_only(true);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    drop(vm);
+    assert_eq!(
+      *device.log.borrow(),
+      "print \"0\"\nflush\nprint \"1\"\nflush\n"
+    );
+  }
+
+  #[test]
+  fn concat_in_loop() {
+    let loop_error = |line, start, end, msg: &str, kind, loop_loc: Location| {
+      ExecResult::Error {
+        location: Location {
+          line,
+          range: Range::new(start, end),
+        },
+        message: msg.to_owned(),
+        loop_context: Some(LoopContext {
+          kind,
+          location: loop_loc,
+        }),
+      }
+    };
+
+    run(
       r#"
-10 input "foo"; a$, b
-20 input c%(2), fn f(y)
-30 def fn g(x)=x*x
-40 print a$; b; c%(2); fn f(3);
+10 a$="":for i=1 to 300
+20 gosub 40:next
+30 end
+40 a$=a$+"x":return
     "#
       .trim(),
-      vec![
-        (
-          ExecResult::KeyboardInput {
-            prompt: Some("foo".to_owned()),
-            fields: vec![KeyboardInputType::String, KeyboardInputType::Real],
-          },
-          ExecInput::KeyboardInput(vec![
-            KeyboardInput::String(b"ABc".to_vec().into()),
-            KeyboardInput::Real(Mbf5::try_from(3.5f64).unwrap()),
-          ]),
-        ),
-        (
-          ExecResult::KeyboardInput {
-            prompt: None,
-            fields: vec![
-              KeyboardInputType::Integer,
-              KeyboardInputType::Func {
-                name: "F".to_owned(),
-                param: "Y".to_owned()
-              }
-            ],
+      vec![(
+        loop_error(
+          3,
+          6,
+          12,
+          "运算结果字符串过长，长度超出 255。字符串长度为：256。\
+          可能是在 FOR 循环中反复拼接字符串导致的",
+          LoopKind::For,
+          Location {
+            line: 0,
+            range: Range::new(9, 23),
           },
-          {
-            let body = compile_fn(utf16str!("fn g(y)+2"), EmojiVersion::V2)
-              .0
-              .unwrap();
-            ExecInput::KeyboardInput(vec![
-              KeyboardInput::Integer(37),
-              KeyboardInput::Func { body },
-            ])
-          }
         ),
-        (ExecResult::End, ExecInput::None)
-      ]
-    ));
-  }
+        ExecInput::None,
+      )],
+    );
 
-  #[test]
-  fn locate() {
-    assert_snapshot!(run(
+    run(
       r#"
-10 locate 3:locate ,10:locate 5,1:locate 6
+10 a$="x":while 1:a$=a$+a$:wend
     "#
       .trim(),
       vec![(
-        exec_error(0, 41, 42, "参数超出范围 1~5。运算结果为：6"),
-        ExecInput::None
-      )]
-    ));
-  }
+        loop_error(
+          0,
+          21,
+          26,
+          "运算结果字符串过长，长度超出 255。字符串长度为：256。\
+          可能是在 WHILE 循环中反复拼接字符串导致的",
+          LoopKind::While,
+          Location {
+            line: 0,
+            range: Range::new(10, 17),
+          },
+        ),
+        ExecInput::None,
+      )],
+    );
 
-  #[test]
-  fn locate_error_column() {
-    assert_snapshot!(run(
+    run(
       r#"
-10 locate 4, 2 0 +1:
+10 a$="xxxxxxxxxx":a$=a$+a$:a$=a$+a$:a$=a$+a$:a$=a$+a$:a$=a$+a$
     "#
       .trim(),
       vec![(
-        exec_error(0, 13, 19, "参数超出范围 1~20。运算结果为：21"),
-        ExecInput::None
-      )]
-    ));
+        exec_error(
+          0,
+          58,
+          63,
+          "运算结果字符串过长，长度超出 255。字符串长度为：320",
+        ),
+        ExecInput::None,
+      )],
+    );
   }
 
-  #[test]
-  fn set() {
-    assert_snapshot!(run(
+  fn run_int_overflow(overflow: IntOverflow) -> String {
+    let codegen = compile(
       r#"
-10 a$="12345"
-20 lset a$="":print a$;
-30 lset a$="ab":print a$;
-40 lset a$="abcdefg":print a$;
-45 lset b$(3)="ab":print b$(3);
-50 rset a$="":print a$;
-60 rset a$="ab":print a$;
-70 rset a$="1234567890":print a$;
-80 rset b$(3)="ab":print b$(3);
+10 a%=32768.5:print a%:a%=-40000:print a%
+20 read b%:print b%
+30 open "f" for input as 1:input #1,c%:print c%:close 1
+40 print cvi$(mki$(-40000))
+50 data 70000
     "#
       .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
-    ));
+    );
+    let mut device = TestDevice::new()
+      .with_file(b"f.DAT".to_vec(), File::new(b"-70000,".to_vec()));
+    device.int_overflow = overflow;
+    let vm = VirtualMachine::new(codegen, &mut device);
+    run_vm(vm, vec![(ExecResult::End, ExecInput::None)]);
+    let log = device.log.borrow();
+    (*log).clone()
   }
 
   #[test]
-  fn on() {
-    assert_snapshot!(run(
+  fn int_overflow_wrap() {
+    assert_snapshot!(run_int_overflow(IntOverflow::Wrap));
+  }
+
+  #[test]
+  fn int_overflow_saturate() {
+    assert_snapshot!(run_int_overflow(IntOverflow::Saturate));
+  }
+
+  #[test]
+  fn soft_limits() {
+    let codegen = compile(
       r#"
-10 a=10:gosub 30:a=4:gosub 30:a=7:gosub 30
-20 on 2 gosub 40, 50:end
-30 on (a>5)+(a<10)*2 goto 40, 50:print "A";:return
-40 print "B";:return
-50 print "C";:return
+10 dim a%(9):a$="abcdef":b$=a$+a$:a$="":gosub 20:dim b%(9):end
+20 gosub 30:return
+30 return
     "#
       .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
-    ));
+    );
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.set_soft_limits(Some(SoftLimits {
+      string_space: 20,
+      array_memory: 20,
+      stack_depth: 3,
+      threshold: 50,
+    }));
+    vm.start();
+    let warning = |line, start, end, message: &str| ExecResult::Warning {
+      location: Location {
+        line,
+        range: Range::new(start, end),
+      },
+      message: message.to_owned(),
+    };
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      warning(
+        0,
+        7,
+        9,
+        "数组占用的内存已达到 20 字节，不低于真机上限（20 字节）的 50%，\
+        程序在真机上运行时可能会因为内存不足而出错"
+      )
+    );
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      warning(
+        0,
+        25,
+        33,
+        "字符串占用的内存已达到 18 字节，不低于真机上限（20 字节）的 50%，\
+        程序在真机上运行时可能会因为内存不足而出错"
+      )
+    );
+    assert_eq!(
+      vm.resource_usage(),
+      ResourceUsage {
+        string_space: 18,
+        array_memory: 20,
+        stack_depth: 0,
+      }
+    );
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      warning(
+        1,
+        3,
+        11,
+        "GOSUB、FOR、WHILE 和自定义函数的嵌套层数已达到 2 层，\
+        不低于真机上限（3 层）的 50%，程序在真机上运行时可能会因为内存不足而出错"
+      )
+    );
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(
+      vm.resource_usage(),
+      ResourceUsage {
+        string_space: 12,
+        array_memory: 40,
+        stack_depth: 0,
+      }
+    );
   }
 
   #[test]
-  fn print() {
-    assert_snapshot!(run(
-      r#"
-10 print:print;:print "foo":locate ,10:print spc(2)tab(13);
-20 a$="ABCDEFG":a=3:print a$tab(3)a
-    "#
-      .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
-    ));
+  fn step_hook() {
+    let calls = std::cell::Cell::new(0);
+    let codegen = compile(r#"10 print "a";:print "b";"#);
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.set_step_hook(NonZeroUsize::new(2).unwrap(), || {
+      calls.set(calls.get() + 1);
+      calls.get() != 2
+    });
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::Yield);
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::Yield);
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    drop(vm);
+    assert_eq!(calls.get(), 3);
+    assert_eq!(
+      *device.log.borrow(),
+      "print \"a\"\nflush\nprint \"b\"\nflush\n"
+    );
   }
 
   #[test]
-  fn run_stmt() {
-    assert_snapshot!(run_with_file(
-      r#"
-10 open "aaa" for input as 1:a$=a$+"E"
-20 if asc(inkey$) > 40 then print a$;:end else run
-    "#
-      .trim(),
-      vec![
-        (ExecResult::InKey, ExecInput::Key(30)),
-        (ExecResult::InKey, ExecInput::Key(60)),
-        (ExecResult::End, ExecInput::None)
-      ],
-      b"aaa.DAT",
-      File::new(vec![])
-    ));
+  fn invalid_notes() {
+    let location = Location {
+      line: 0,
+      range: Range::new(3, 13),
+    };
+    let message = "音符字符串的第 3 个字节有误：无法识别的字符 x";
+    for (behavior, result) in [
+      (
+        InvalidNotes::Error,
+        ExecResult::Error {
+          location: location.clone(),
+          message: message.to_owned(),
+          loop_context: None,
+        },
+      ),
+      (
+        InvalidNotes::Warning,
+        ExecResult::Warning {
+          location: location.clone(),
+          message: message.to_owned(),
+        },
+      ),
+      (InvalidNotes::Ignore, ExecResult::End),
+    ] {
+      let codegen = compile("10 play \"abx\":play \"c\"");
+      let mut device = TestDevice::new();
+      device.invalid_notes = behavior;
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.start();
+      assert_eq!(vm.exec(ExecInput::None, usize::MAX), result);
+      if behavior == InvalidNotes::Warning {
+        assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+      }
+    }
   }
 
   #[test]
-  fn swap() {
-    assert_snapshot!(run(
-      r#"
-10 a%=30:a%(2)=555:swap a%,a%(2):print a%;a%(2);
-20 a$="abc":b$="ABC-#":swap b$,a$:print a$;b$;
-    "#
-      .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
-    ));
+  fn switch_selector() {
+    let text = "10 on x goto 20,30,40\n20 print 2:end\n30 print 3:end\n40 print 4";
+    for (rounding, x, warning, printed) in [
+      (SelectorRounding::Truncate, "2", None, "3"),
+      (SelectorRounding::Round, "3", None, "4"),
+      (
+        SelectorRounding::Truncate,
+        "2.7",
+        Some("ON 语句的选择值 2.7 不是整数，舍去小数部分后为 2"),
+        "3",
+      ),
+      (
+        SelectorRounding::Round,
+        "2.7",
+        Some("ON 语句的选择值 2.7 不是整数，四舍五入后为 3"),
+        "4",
+      ),
+      (
+        SelectorRounding::Round,
+        "1.5",
+        Some("ON 语句的选择值 1.5 不是整数，四舍五入后为 2"),
+        "3",
+      ),
+      (
+        SelectorRounding::Round,
+        "3.5",
+        Some("ON 语句的选择值 3.5 不是整数，四舍五入后为 4"),
+        "2",
+      ),
+    ] {
+      let codegen = compile(&format!("0 x={x}\n{text}"));
+      let mut device = TestDevice::new();
+      device.selector_rounding = rounding;
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.set_selector_warning(true);
+      vm.start();
+      if let Some(message) = warning {
+        assert_eq!(
+          vm.exec(ExecInput::None, usize::MAX),
+          ExecResult::Warning {
+            location: Location {
+              line: 1,
+              range: Range::new(6, 7),
+            },
+            message: message.to_owned(),
+          }
+        );
+      }
+      assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+      drop(vm);
+      assert_eq!(
+        *device.log.borrow(),
+        format!("print \"{printed}\"\nprint newline\nflush\n"),
+        "{rounding:?} {x}"
+      );
+    }
   }
 
   #[test]
-  fn while_loop() {
-    assert_snapshot!(run(
-      r#"
-10 while a<2:a=a+1:print a;:wend:
-20 while a>0:goto 50:wend:
-30 gosub 40:wend
-40 :while a<2:a=a+1:print a;:return:wend
-50 print ".";:a=a-1:wend:print "no"
-    "#
-      .trim(),
-      vec![(
-        exec_error(2, 12, 16, "WEND 语句找不到匹配的 WHILE 语句"),
-        ExecInput::None
-      )]
+  fn locate_bounds_from_device() {
+    let codegen = compile("10 locate 10,3:print tab(2);:locate 11");
+    let mut device = TestDevice::new();
+    device.text_size = (10, 4);
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Error { message, .. }
+        if message == "参数超出范围 1~10。运算结果为：11"
     ));
+    drop(vm);
+    assert_eq!(device.cursor, (9, 2));
+    assert!(device.log.borrow().contains("print \"   \"\n"));
   }
 
   #[test]
-  fn write() {
-    assert_snapshot!(run(
-      r#"
-10 a=10:write 1, "foo", "Ab"+chr$(0)+"c": write 1 a*2+1, "a和C",:
-    "#
-      .trim(),
-      vec![(ExecResult::End, ExecInput::None)]
-    ));
+  fn exec_result_diagnostic() {
+    let codegen = compile("10 print 1:a=1/0");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    let (line, diag) =
+      vm.exec(ExecInput::None, usize::MAX).diagnostic().unwrap();
+    assert_eq!(line, 0);
+    assert_eq!(diag.severity, Severity::Error);
+    assert_eq!(diag.phase, DiagnosticPhase::Runtime);
+    assert_eq!(ExecResult::End.diagnostic(), None);
   }
 
   #[test]
-  fn sleep() {
-    assert_snapshot!(run(
-      r#"
-10 sleep -100:sleep 0:sleep 200:
-    "#
-      .trim(),
-      vec![
-        (
-          ExecResult::Sleep(Duration::from_millis(200)),
-          ExecInput::None
-        ),
-        (ExecResult::End, ExecInput::None)
-      ]
-    ));
+  fn output_quota() {
+    let codegen = compile("10 print \"abc\";\n20 goto 10");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.set_output_quota(Some(OutputQuota {
+      per_exec: Some(10),
+      total: Some(20),
+    }));
+    vm.start();
+    let exceeded = |kind, printed| ExecResult::OutputQuotaExceeded {
+      location: Location {
+        line: 0,
+        range: Range::new(9, 14),
+      },
+      kind,
+      printed,
+    };
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      exceeded(OutputQuotaKind::PerExec, 12)
+    );
+    assert_eq!(vm.exec(ExecInput::None, 5), ExecResult::Continue);
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      exceeded(OutputQuotaKind::Total, 21)
+    );
+    assert_eq!(
+      vm.exec(ExecInput::None, usize::MAX),
+      exceeded(OutputQuotaKind::PerExec, 12)
+    );
+
+    vm.set_output_quota(None);
+    assert_eq!(vm.exec(ExecInput::None, 1000), ExecResult::Continue);
   }
 
   #[test]
-  fn for_replaces_while() {
-    assert_snapshot!(run(
-      r#"
-10 for i=1 to 10:while 1:for i=1 to 2:cls:next i:wend
-    "#
-      .trim(),
-      vec![(
-        exec_error(0, 49, 53, "WEND 语句找不到匹配的 WHILE 语句"),
-        ExecInput::None
-      )]
-    ));
+  fn max_string_len() {
+    fn run(prog: &str, max: usize, input: &[u8]) -> ExecResult {
+      let codegen = compile_with_max_string_len(prog, max);
+      let file = File::new(format!("\"{}\"", "x".repeat(max + 1)).into());
+      let mut device = TestDevice::new().with_file(b"f.DAT".to_vec(), file);
+      device.max_string_len = max;
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.start();
+      let mut result = vm.exec(ExecInput::None, usize::MAX);
+      if let ExecResult::KeyboardInput { .. } = result {
+        let input = KeyboardInput::String(input.to_vec().into());
+        result = vm.exec(ExecInput::KeyboardInput(vec![input]), usize::MAX);
+      }
+      result
+    }
+
+    let message = |result| match result {
+      ExecResult::Error { message, .. } => message,
+      result => panic!("{:?}", result),
+    };
+
+    for max in [10, DEFAULT_MAX_STRING_LEN, 1000] {
+      let concat = |n| format!("10 a$=\"\":for i=1 to {n}:a$=a$+\"x\":next");
+      assert_eq!(run(&concat(max), max, b""), ExecResult::End);
+      assert!(
+        message(run(&concat(max + 1), max, b"")).starts_with(&format!(
+          "运算结果字符串过长，长度超出 {max}。字符串长度为：{}",
+          max + 1
+        ))
+      );
+
+      let substr = |n| {
+        format!(
+          "10 a$=\"abc\":b$=left$(a$,{n})+mid$(a$,{n})+mid$(a$,1,{n})\
+            +right$(a$,{n})"
+        )
+      };
+      assert_eq!(run(&substr(max), max, b""), ExecResult::End);
+      for func in ["left$(a$,", "mid$(a$,", "mid$(a$,1,", "right$(a$,"] {
+        assert_eq!(
+          message(run(
+            &format!("10 a$=\"abc\":b$={func}{})", max + 1),
+            max,
+            b""
+          )),
+          format!("参数超出范围 1~{max}。运算结果为：{}", max + 1)
+            .replace("1~", if func == "mid$(a$,1," { "0~" } else { "1~" })
+        );
+      }
+
+      let read = |n| format!("10 read a$:data {}", "x".repeat(n));
+      assert_eq!(run(&read(max), max, b""), ExecResult::End);
+      let text = Utf16String::from(read(max + 1));
+      let mut prog = parse_prog(&text);
+      let mut codegen = CodeGen::new(EmojiVersion::V2);
+      codegen.set_max_string_len(max);
+      compile_prog(&text, &mut prog, &mut codegen);
+      assert_eq!(
+        prog.lines[0].diagnostics,
+        vec![Diagnostic::new_error(
+          Range::new(16, 17 + max),
+          format!("字符串太长，长度超出 {max}")
+        )]
+      );
+
+      assert_eq!(
+        message(run(
+          "10 open \"f\" for input as 1:input #1,a$",
+          max,
+          b""
+        )),
+        format!(
+          "读取到的数据：{}，字符串过长，长度超出 {max}",
+          "x".repeat(max + 1)
+        )
+      );
+
+      let input = format!("10 input a$:if len(a$)<>{max} then print 1/0");
+      assert_eq!(
+        run(&input, max, "x".repeat(max + 1).as_bytes()),
+        ExecResult::End
+      );
+    }
   }
 
   #[test]
-  fn for_replaces_sub() {
-    assert_snapshot!(run(
-      r#"
-10 for i=1 to 2:print i;:gosub 20:
-20 next i:return
-    "#
-      .trim(),
-      vec![(
-        exec_error(1, 10, 16, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行"),
-        ExecInput::None
-      )]
+  fn lifecycle_events() {
+    let loc = |line, start, end| Location {
+      line,
+      range: Range::new(start, end),
+    };
+
+    let codegen = compile("10 clear:a=1\n20 run");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    assert_eq!(vm.take_events(), vec![VmEvent::Created]);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, 8), ExecResult::Continue);
+    assert_eq!(
+      vm.take_events(),
+      vec![
+        VmEvent::Started,
+        VmEvent::Cleared {
+          location: loc(0, 3, 8)
+        },
+        VmEvent::Restarted {
+          location: loc(1, 3, 6)
+        },
+        VmEvent::Cleared {
+          location: loc(0, 3, 8)
+        },
+      ]
+    );
+    assert_eq!(vm.take_events(), vec![]);
+
+    let codegen = compile("10 print 1/0");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert!(matches!(
+      vm.exec(ExecInput::None, usize::MAX),
+      ExecResult::Error { .. }
     ));
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(
+      vm.take_events(),
+      vec![
+        VmEvent::Created,
+        VmEvent::Started,
+        VmEvent::Errored {
+          location: loc(0, 9, 12)
+        },
+      ]
+    );
+
+    let codegen = compile("10 a=1");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    assert_eq!(
+      vm.take_events(),
+      vec![VmEvent::Created, VmEvent::Started, VmEvent::Finished]
+    );
+
+    let codegen = compile("10 clear:goto 10");
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, 1000), ExecResult::Continue);
+    vm.stop().unwrap();
+    vm.stop().unwrap();
+    let events = vm.take_events();
+    assert_eq!(events.len(), MAX_EVENTS);
+    assert_eq!(
+      events[MAX_EVENTS - 2..],
+      [
+        VmEvent::Cleared {
+          location: loc(0, 3, 8)
+        },
+        VmEvent::Finished
+      ]
+    );
   }
 
   #[test]
-  fn while_replaces_for() {
-    assert_snapshot!(run(
-      r#"
-10 while i<2:for j=1 to 10:i=i+1:print i;:wend:next j
+  fn print_flush_policy() {
+    let text = r#"
+10 print "a";1;:print "b"
+20 print "c":print tab(3);"d";
+30 x=1:print "e"
     "#
-      .trim(),
-      vec![(
-        exec_error(0, 47, 53, "NEXT 语句找不到匹配的 FOR 语句"),
-        ExecInput::None
-      )]
-    ));
+    .trim();
+    let mut logs = vec![];
+    for policy in [
+      PrintFlushPolicy::Immediate,
+      PrintFlushPolicy::PerNewline,
+      PrintFlushPolicy::PerStatement,
+      PrintFlushPolicy::Explicit,
+    ] {
+      let codegen = compile(text);
+      let mut device = TestDevice::new();
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.set_print_flush_policy(policy);
+      vm.start();
+      assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+      drop(vm);
+      let log = device.log.borrow().clone();
+      logs.push(log);
+    }
+    assert_eq!(
+      logs,
+      vec![
+        "print \"a\"\nprint \"1\"\nflush\nprint \"b\"\nprint newline\nflush\n\
+        print \"c\"\nprint newline\nflush\nget column: 0\nprint \"  \"\n\
+        print \"d\"\nflush\nprint \"e\"\nprint newline\nflush\n",
+        "print \"a1b\"\nprint newline\nflush\nprint \"c\"\nprint newline\n\
+        flush\nget column: 0\nprint \"  de\"\nprint newline\nflush\n",
+        "print \"a1\"\nflush\nprint \"b\"\nprint newline\nflush\n\
+        print \"c\"\nprint newline\nflush\nget column: 0\nprint \"  d\"\n\
+        flush\nprint \"e\"\nprint newline\nflush\n",
+        "print \"a1b\"\nprint newline\nprint \"c\"\nprint newline\nflush\n\
+        get column: 0\nprint \"  de\"\nprint newline\nflush\n",
+      ]
+    );
   }
 
   #[test]
-  fn while_replaces_sub() {
-    assert_snapshot!(run(
+  fn device_context() {
+    let codegen = compile(
       r#"
-10 while i<2:i=i+1:print i;:gosub 20
-20 wend:return
+10 a=1:if a then print "a";
+20 for i=1 to 2:a=i:next
+30 end
     "#
       .trim(),
-      vec![(
-        exec_error(1, 8, 14, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行"),
-        ExecInput::None
-      )]
-    ));
+    );
+    let mut device = TestDevice::new();
+    let mut vm = VirtualMachine::new(codegen, &mut device);
+    vm.start();
+    assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+    drop(vm);
+    assert_eq!(
+      device.contexts,
+      vec![
+        (0, 3, 6),
+        (0, 7, 27),
+        (0, 17, 27),
+        (1, 3, 15),
+        (1, 16, 19),
+        (1, 20, 24),
+        (1, 16, 19),
+        (1, 20, 24),
+        (2, 3, 6),
+      ]
+    );
   }
 
   #[test]
-  fn sub_replaces_while() {
-    assert_snapshot!(run(
-      r#"
-10 gosub 20:wend
-20 while 1:return:wend
-    "#
-      .trim(),
-      vec![(
-        exec_error(0, 12, 16, "WEND 语句找不到匹配的 WHILE 语句"),
-        ExecInput::None
-      )]
-    ));
+  fn instructions() {
+    let codegen = compile("10 goto 30\n20 end\n30 print 1");
+    let len = codegen.code.len();
+    let mut device = TestDevice::new();
+    let vm = VirtualMachine::new(codegen, &mut device);
+    let code = vm.instructions();
+    assert_eq!(code.len(), len);
+    let target = match &code[0].kind {
+      InstrKind::GoTo(addr) => addr.0,
+      _ => panic!("expected GOTO"),
+    };
+    assert_eq!(code[target].loc.line, 2);
   }
 
   mod file {
@@ -4164,6 +3745,73 @@ mod tests {
       ));
     }
 
+    #[test]
+    fn write_input_round_trip() {
+      use pretty_assertions::assert_eq;
+      let codegen = compile(
+        r#"
+10 open "f" for output as 1
+20 a$=",x"+chr$(255)+chr$(176)+chr$(161):write #1,a$,1.5:close 1
+30 open "f" input as 1:input #1,b$,c:close 1
+40 if b$=a$ and c=1.5 then print "ok"
+    "#
+        .trim(),
+      );
+      let mut device =
+        TestDevice::new().with_file(b"f.DAT".to_vec(), File::new(vec![]));
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.start();
+      assert_eq!(vm.exec(ExecInput::None, usize::MAX), ExecResult::End);
+      assert_eq!(vm.lossy_writes(), &[]);
+      drop(vm);
+      assert!(device.log.borrow().contains("print \"ok\""));
+    }
+
+    #[test]
+    fn lossy_write() {
+      use pretty_assertions::assert_eq;
+      let codegen = compile(
+        r#"
+10 open "f" for output as 1
+20 for i=1 to 2:write #1,"a"+chr$(34)+"b":next:write #1,"c"+chr$(31)
+30 close 1:open "f" input as 1:input #1,b$
+    "#
+        .trim(),
+      );
+      let mut device =
+        TestDevice::new().with_file(b"f.DAT".to_vec(), File::new(vec![]));
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.start();
+      assert_eq!(
+        vm.exec(ExecInput::None, usize::MAX),
+        exec_error(
+          2,
+          40,
+          42,
+          "读取到的数据：\"a\"，没有以逗号或 U+00FF 字符结尾"
+        )
+      );
+      assert_eq!(
+        vm.lossy_writes(),
+        &[
+          LossyWrite {
+            location: Location {
+              line: 1,
+              range: Range::new(25, 41),
+            },
+            loss: WriteLoss::Quote,
+          },
+          LossyWrite {
+            location: Location {
+              line: 1,
+              range: Range::new(56, 68),
+            },
+            loss: WriteLoss::Char1F,
+          },
+        ]
+      );
+    }
+
     #[test]
     fn append_write() {
       assert_snapshot!(run_with_files(
@@ -4449,6 +4097,48 @@ mod tests {
       ));
     }
 
+    #[test]
+    fn arith_fault() {
+      use pretty_assertions::assert_eq;
+
+      let codegen = compile("10 print (-3.2)^(-5.2)");
+      let mut device = TestDevice::new();
+      let mut vm = VirtualMachine::new(codegen, &mut device);
+      vm.start();
+      assert!(matches!(
+        vm.exec(ExecInput::None, usize::MAX),
+        ExecResult::Error { .. }
+      ));
+      assert_eq!(
+        vm.last_arith_fault(),
+        Some(&ArithFault {
+          location: Location {
+            line: 0,
+            range: Range::new(9, 22),
+          },
+          op: ArithOp::Pow,
+          kind: ArithFaultKind::Domain,
+          operands: vec![
+            Mbf5::try_from(-3.2).unwrap(),
+            Mbf5::try_from(-5.2).unwrap()
+          ],
+          fatal: true,
+        })
+      );
+      assert_eq!(
+        vm.arith_fault_stats(),
+        ArithFaultStats {
+          domain: 1,
+          fatal: 1,
+          ..Default::default()
+        }
+      );
+
+      vm.start();
+      assert_eq!(vm.last_arith_fault(), None);
+      assert_eq!(vm.arith_fault_stats().total(), 0);
+    }
+
     #[test]
     fn logical() {
       assert_snapshot!(run(
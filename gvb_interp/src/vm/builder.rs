@@ -0,0 +1,774 @@
+//! Options of the VM which are set in one go when the VM is created, and can
+//! be persisted by hosts as YAML.
+//!
+//! The machine profile is a property of the document. The options can only
+//! override the behaviors of the profile which do not affect compilation, and
+//! the strictness of the dialect.
+
+use std::fmt::{self, Display, Formatter};
+
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use super::{OutputQuota, PrintFlushPolicy, SoftLimits, VirtualMachine};
+use crate::device::Device;
+use crate::document::{ContainsErrors, Document};
+use crate::machine::{IntOverflow, InvalidNotes, SelectorRounding};
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+/// How fast the host runs programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpeedMode {
+  /// The host decides the pace. Sleeps are returned to the host as
+  /// `ExecResult::Sleep`.
+  #[default]
+  Host,
+  /// The host paces the program like the real machine. Each statement takes
+  /// the sleep unit of the device, and the VM returns `ExecResult::Sleep`
+  /// periodically to let the host wait for the time taken. Sleeps are
+  /// returned to the host as in `Host` mode.
+  Authentic,
+  /// The program runs as fast as possible. Sleeps only advance the clock of
+  /// the device, and are not returned to the host.
+  Fast,
+}
+
+/// Behaviors of the machine profile overridden by the VM. The behaviors of
+/// the profile are used for fields which are None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProfileOverrides {
+  pub int_overflow: Option<IntOverflow>,
+  pub invalid_notes: Option<InvalidNotes>,
+  pub selector_rounding: Option<SelectorRounding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VmOptions {
+  /// See [`VirtualMachine::set_read_only`].
+  pub read_only: bool,
+  /// See [`VirtualMachine::set_selector_warning`].
+  pub selector_warning: bool,
+  /// Unlimited if None.
+  pub soft_limits: Option<SoftLimits>,
+  pub print_flush_policy: PrintFlushPolicy,
+  /// Unlimited if None.
+  pub output_quota: Option<OutputQuota>,
+  /// Seeded randomly if None.
+  pub rng_seed: Option<u64>,
+  pub speed_mode: SpeedMode,
+  /// See [`Document::set_strict`]. The setting of the document is kept if
+  /// None.
+  pub strict: Option<bool>,
+  pub profile_overrides: ProfileOverrides,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmOptionsError {
+  /// Authentic speed without budgets of resources, which would let programs
+  /// run which fail on the real machine.
+  UnlimitedAuthentic,
+  /// The threshold of soft limits is not in 1~100.
+  InvalidThreshold(u8),
+  /// A cap of the output quota is zero, which stops the program at once.
+  ZeroOutputQuota,
+}
+
+impl Display for VmOptionsError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::UnlimitedAuthentic => {
+        write!(f, "以真实速度运行时必须设置资源预算")
+      }
+      Self::InvalidThreshold(threshold) => {
+        write!(f, "资源预算的阈值 {threshold} 不在 1~100 之间")
+      }
+      Self::ZeroOutputQuota => write!(f, "输出配额不能为 0"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum BuildVmError {
+  ContainsErrors,
+  InvalidOptions(VmOptionsError),
+}
+
+#[derive(Debug)]
+pub enum ParseVmOptionsError {
+  Yaml(yaml_rust::ScanError),
+  Other(String),
+}
+
+impl From<yaml_rust::ScanError> for ParseVmOptionsError {
+  fn from(err: yaml_rust::ScanError) -> Self {
+    Self::Yaml(err)
+  }
+}
+
+impl From<String> for ParseVmOptionsError {
+  fn from(err: String) -> Self {
+    Self::Other(err)
+  }
+}
+
+impl VmOptions {
+  pub fn validate(&self) -> Result<(), VmOptionsError> {
+    if let Some(limits) = &self.soft_limits {
+      if !(1..=100).contains(&limits.threshold) {
+        return Err(VmOptionsError::InvalidThreshold(limits.threshold));
+      }
+    } else if self.speed_mode == SpeedMode::Authentic {
+      return Err(VmOptionsError::UnlimitedAuthentic);
+    }
+    if let Some(quota) = &self.output_quota {
+      if quota.per_exec == Some(0) || quota.total == Some(0) {
+        return Err(VmOptionsError::ZeroOutputQuota);
+      }
+    }
+    Ok(())
+  }
+
+  fn apply<D: Device>(&self, vm: &mut VirtualMachine<D>) {
+    vm.set_read_only(self.read_only);
+    vm.set_selector_warning(self.selector_warning);
+    vm.set_soft_limits(self.soft_limits);
+    vm.set_print_flush_policy(self.print_flush_policy);
+    vm.set_output_quota(self.output_quota);
+    vm.set_rng_seed(self.rng_seed);
+    vm.set_speed_mode(self.speed_mode);
+    vm.set_profile_overrides(self.profile_overrides);
+  }
+
+  /// Parses the options emitted by [`Self::to_yaml`]. Missing fields take
+  /// the default values. The options are not validated.
+  pub fn from_yaml(content: &str) -> Result<Self, ParseVmOptionsError> {
+    let mut docs = YamlLoader::load_from_str(content)?;
+    let mut options = Self::default();
+    let mut obj = match docs.pop() {
+      Some(Yaml::Null) | None => return Ok(options),
+      Some(Yaml::Hash(obj)) => obj,
+      Some(_) => return Err("toplevel is not object".to_owned().into()),
+    };
+
+    if let Some(value) = obj.remove(&key("read-only")) {
+      options.read_only = value
+        .as_bool()
+        .ok_or_else(|| "read-only is not boolean".to_owned())?;
+    }
+    if let Some(value) = obj.remove(&key("selector-warning")) {
+      options.selector_warning = value
+        .as_bool()
+        .ok_or_else(|| "selector-warning is not boolean".to_owned())?;
+    }
+    if let Some(value) = obj.remove(&key("soft-limits")) {
+      options.soft_limits = match value {
+        Yaml::Null => None,
+        value => Some(parse_soft_limits(value)?),
+      };
+    }
+    if let Some(value) = obj.remove(&key("print-flush-policy")) {
+      options.print_flush_policy =
+        parse_name(&value, "print-flush-policy", PRINT_FLUSH_POLICIES)?;
+    }
+    if let Some(value) = obj.remove(&key("output-quota")) {
+      options.output_quota = match value {
+        Yaml::Null => None,
+        value => Some(parse_output_quota(value)?),
+      };
+    }
+    if let Some(value) = obj.remove(&key("rng-seed")) {
+      options.rng_seed = match value {
+        Yaml::Null => None,
+        value => Some(
+          parse_seed(value)
+            .ok_or_else(|| "rng-seed is not unsigned integer".to_owned())?,
+        ),
+      };
+    }
+    if let Some(value) = obj.remove(&key("speed-mode")) {
+      options.speed_mode = parse_name(&value, "speed-mode", SPEED_MODES)?;
+    }
+    if let Some(value) = obj.remove(&key("strict")) {
+      options.strict = match value {
+        Yaml::Null => None,
+        value => Some(
+          value
+            .as_bool()
+            .ok_or_else(|| "strict is not boolean".to_owned())?,
+        ),
+      };
+    }
+    if let Some(value) = obj.remove(&key("profile-overrides")) {
+      options.profile_overrides = match value {
+        Yaml::Null => ProfileOverrides::default(),
+        value => parse_profile_overrides(value)?,
+      };
+    }
+
+    if let Some((k, _)) = obj.front() {
+      return Err(format!("superfluous field {k:?}").into());
+    }
+    Ok(options)
+  }
+
+  pub fn to_yaml(&self) -> String {
+    let mut obj = Hash::new();
+    obj.insert(key("read-only"), Yaml::Boolean(self.read_only));
+    obj.insert(
+      key("selector-warning"),
+      Yaml::Boolean(self.selector_warning),
+    );
+    obj.insert(
+      key("soft-limits"),
+      match &self.soft_limits {
+        Some(limits) => {
+          let mut obj = Hash::new();
+          obj.insert(key("string-space"), int(limits.string_space));
+          obj.insert(key("array-memory"), int(limits.array_memory));
+          obj.insert(key("stack-depth"), int(limits.stack_depth));
+          obj.insert(key("threshold"), int(limits.threshold as usize));
+          Yaml::Hash(obj)
+        }
+        None => Yaml::Null,
+      },
+    );
+    obj.insert(
+      key("print-flush-policy"),
+      key(name_of(PRINT_FLUSH_POLICIES, self.print_flush_policy)),
+    );
+    obj.insert(
+      key("output-quota"),
+      match &self.output_quota {
+        Some(quota) => {
+          let mut obj = Hash::new();
+          obj.insert(key("per-exec"), quota.per_exec.map_or(Yaml::Null, int));
+          obj.insert(key("total"), quota.total.map_or(Yaml::Null, int));
+          Yaml::Hash(obj)
+        }
+        None => Yaml::Null,
+      },
+    );
+    // Seeds are written as strings, since YAML integers are 64-bit signed.
+    obj.insert(
+      key("rng-seed"),
+      self
+        .rng_seed
+        .map_or(Yaml::Null, |seed| Yaml::String(seed.to_string())),
+    );
+    obj.insert(
+      key("speed-mode"),
+      key(name_of(SPEED_MODES, self.speed_mode)),
+    );
+    obj.insert(key("strict"), self.strict.map_or(Yaml::Null, Yaml::Boolean));
+    let overrides = &self.profile_overrides;
+    let mut overrides_obj = Hash::new();
+    overrides_obj.insert(
+      key("int-overflow"),
+      name_or_null(INT_OVERFLOWS, overrides.int_overflow),
+    );
+    overrides_obj.insert(
+      key("invalid-notes"),
+      name_or_null(INVALID_NOTES, overrides.invalid_notes),
+    );
+    overrides_obj.insert(
+      key("selector-rounding"),
+      name_or_null(SELECTOR_ROUNDINGS, overrides.selector_rounding),
+    );
+    obj.insert(key("profile-overrides"), Yaml::Hash(overrides_obj));
+
+    let mut out = String::new();
+    YamlEmitter::new(&mut out).dump(&Yaml::Hash(obj)).unwrap();
+    out.push('\n');
+    out
+  }
+}
+
+fn key(name: &str) -> Yaml {
+  Yaml::String(name.to_owned())
+}
+
+fn int(n: usize) -> Yaml {
+  Yaml::Integer(n as i64)
+}
+
+fn parse_seed(value: Yaml) -> Option<u64> {
+  match value {
+    Yaml::String(seed) => seed.parse().ok(),
+    value => value.as_i64().and_then(|seed| u64::try_from(seed).ok()),
+  }
+}
+
+pub(super) const PRINT_FLUSH_POLICIES: &[(PrintFlushPolicy, &str)] = &[
+  (PrintFlushPolicy::Immediate, "immediate"),
+  (PrintFlushPolicy::PerNewline, "per-newline"),
+  (PrintFlushPolicy::PerStatement, "per-statement"),
+  (PrintFlushPolicy::Explicit, "explicit"),
+];
+
+pub(super) const SPEED_MODES: &[(SpeedMode, &str)] = &[
+  (SpeedMode::Host, "host"),
+  (SpeedMode::Authentic, "authentic"),
+  (SpeedMode::Fast, "fast"),
+];
+
+pub(super) const INT_OVERFLOWS: &[(IntOverflow, &str)] = &[
+  (IntOverflow::Error, "error"),
+  (IntOverflow::Wrap, "wrap"),
+  (IntOverflow::Saturate, "saturate"),
+];
+
+pub(super) const INVALID_NOTES: &[(InvalidNotes, &str)] = &[
+  (InvalidNotes::Error, "error"),
+  (InvalidNotes::Warning, "warning"),
+  (InvalidNotes::Ignore, "ignore"),
+];
+
+pub(super) const SELECTOR_ROUNDINGS: &[(SelectorRounding, &str)] = &[
+  (SelectorRounding::Truncate, "truncate"),
+  (SelectorRounding::Round, "round"),
+];
+
+pub(super) fn name_of<T: Copy + PartialEq>(
+  names: &[(T, &'static str)],
+  value: T,
+) -> &'static str {
+  names.iter().find(|&&(v, _)| v == value).unwrap().1
+}
+
+pub(super) fn value_of<T: Copy>(names: &[(T, &str)], name: &str) -> Option<T> {
+  names.iter().find(|&&(_, n)| n == name).map(|&(v, _)| v)
+}
+
+pub(super) fn one_of<T>(field: &str, names: &[(T, &str)]) -> String {
+  let names: Vec<_> = names.iter().map(|(_, name)| *name).collect();
+  format!("{field} must be one of {}", names.join(", "))
+}
+
+fn name_or_null<T: Copy + PartialEq>(
+  names: &[(T, &'static str)],
+  value: Option<T>,
+) -> Yaml {
+  value.map_or(Yaml::Null, |value| key(name_of(names, value)))
+}
+
+fn parse_name<T: Copy>(
+  value: &Yaml,
+  field: &str,
+  names: &[(T, &str)],
+) -> Result<T, ParseVmOptionsError> {
+  value
+    .as_str()
+    .and_then(|name| value_of(names, name))
+    .ok_or_else(|| one_of(field, names).into())
+}
+
+fn parse_usize(
+  obj: &mut Hash,
+  parent: &str,
+  name: &str,
+) -> Result<Option<usize>, ParseVmOptionsError> {
+  match obj.remove(&key(name)) {
+    None | Some(Yaml::Null) => Ok(None),
+    Some(value) => value
+      .as_i64()
+      .and_then(|n| usize::try_from(n).ok())
+      .map(Some)
+      .ok_or_else(|| format!("{parent}.{name} is not unsigned integer").into()),
+  }
+}
+
+fn parse_soft_limits(value: Yaml) -> Result<SoftLimits, ParseVmOptionsError> {
+  let mut obj = value
+    .into_hash()
+    .ok_or_else(|| "soft-limits is not object".to_owned())?;
+  let default = SoftLimits::default();
+  let field = |obj: &mut _, name, default| {
+    parse_usize(obj, "soft-limits", name).map(|n| n.unwrap_or(default))
+  };
+  let limits = SoftLimits {
+    string_space: field(&mut obj, "string-space", default.string_space)?,
+    array_memory: field(&mut obj, "array-memory", default.array_memory)?,
+    stack_depth: field(&mut obj, "stack-depth", default.stack_depth)?,
+    threshold: field(&mut obj, "threshold", default.threshold as usize)?
+      .try_into()
+      .map_err(|_| "soft-limits.threshold is too large".to_owned())?,
+  };
+  if let Some((k, _)) = obj.front() {
+    return Err(format!("superfluous field {k:?} in soft-limits").into());
+  }
+  Ok(limits)
+}
+
+fn parse_output_quota(value: Yaml) -> Result<OutputQuota, ParseVmOptionsError> {
+  let mut obj = value
+    .into_hash()
+    .ok_or_else(|| "output-quota is not object".to_owned())?;
+  let quota = OutputQuota {
+    per_exec: parse_usize(&mut obj, "output-quota", "per-exec")?,
+    total: parse_usize(&mut obj, "output-quota", "total")?,
+  };
+  if let Some((k, _)) = obj.front() {
+    return Err(format!("superfluous field {k:?} in output-quota").into());
+  }
+  Ok(quota)
+}
+
+fn parse_profile_overrides(
+  value: Yaml,
+) -> Result<ProfileOverrides, ParseVmOptionsError> {
+  let mut obj = value
+    .into_hash()
+    .ok_or_else(|| "profile-overrides is not object".to_owned())?;
+  fn field<T: Copy>(
+    obj: &mut Hash,
+    name: &str,
+    names: &[(T, &str)],
+  ) -> Result<Option<T>, ParseVmOptionsError> {
+    match obj.remove(&key(name)) {
+      None | Some(Yaml::Null) => Ok(None),
+      Some(value) => {
+        parse_name(&value, &format!("profile-overrides.{name}"), names)
+          .map(Some)
+      }
+    }
+  }
+  let overrides = ProfileOverrides {
+    int_overflow: field(&mut obj, "int-overflow", INT_OVERFLOWS)?,
+    invalid_notes: field(&mut obj, "invalid-notes", INVALID_NOTES)?,
+    selector_rounding: field(
+      &mut obj,
+      "selector-rounding",
+      SELECTOR_ROUNDINGS,
+    )?,
+  };
+  if let Some((k, _)) = obj.front() {
+    return Err(format!("superfluous field {k:?} in profile-overrides").into());
+  }
+  Ok(overrides)
+}
+
+/// Builds VMs with validated [`VmOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct VmBuilder {
+  options: VmOptions,
+}
+
+impl VmBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_options(options: VmOptions) -> Self {
+    Self { options }
+  }
+
+  /// Behaves like the real machine, warning about programs which exceed its
+  /// budgets of resources.
+  pub fn authentic() -> Self {
+    Self::with_options(VmOptions {
+      soft_limits: Some(SoftLimits::default()),
+      speed_mode: SpeedMode::Authentic,
+      ..VmOptions::default()
+    })
+  }
+
+  /// For running programs being edited, with all warnings enabled.
+  pub fn development() -> Self {
+    Self::with_options(VmOptions {
+      selector_warning: true,
+      soft_limits: Some(SoftLimits::default()),
+      ..VmOptions::default()
+    })
+  }
+
+  /// For running programs unattended, e.g. in tests. Runs are reproducible
+  /// and do not wait, files are not modified, and the output is capped.
+  pub fn ci() -> Self {
+    Self::with_options(VmOptions {
+      read_only: true,
+      print_flush_policy: PrintFlushPolicy::PerNewline,
+      output_quota: Some(OutputQuota {
+        per_exec: None,
+        total: Some(1 << 20),
+      }),
+      rng_seed: Some(0),
+      speed_mode: SpeedMode::Fast,
+      ..VmOptions::default()
+    })
+  }
+
+  pub fn options(&self) -> &VmOptions {
+    &self.options
+  }
+
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.options.read_only = read_only;
+    self
+  }
+
+  pub fn selector_warning(mut self, enabled: bool) -> Self {
+    self.options.selector_warning = enabled;
+    self
+  }
+
+  pub fn soft_limits(mut self, limits: Option<SoftLimits>) -> Self {
+    self.options.soft_limits = limits;
+    self
+  }
+
+  pub fn print_flush_policy(mut self, policy: PrintFlushPolicy) -> Self {
+    self.options.print_flush_policy = policy;
+    self
+  }
+
+  pub fn output_quota(mut self, quota: Option<OutputQuota>) -> Self {
+    self.options.output_quota = quota;
+    self
+  }
+
+  pub fn rng_seed(mut self, seed: Option<u64>) -> Self {
+    self.options.rng_seed = seed;
+    self
+  }
+
+  pub fn speed_mode(mut self, mode: SpeedMode) -> Self {
+    self.options.speed_mode = mode;
+    self
+  }
+
+  pub fn strict(mut self, strict: Option<bool>) -> Self {
+    self.options.strict = strict;
+    self
+  }
+
+  pub fn profile_overrides(mut self, overrides: ProfileOverrides) -> Self {
+    self.options.profile_overrides = overrides;
+    self
+  }
+
+  /// Creates a VM running the program of `doc`. The options are validated
+  /// first.
+  pub fn build<'d, D: Device>(
+    &self,
+    doc: &mut Document,
+    device: &'d mut D,
+  ) -> Result<VirtualMachine<'d, D>, BuildVmError> {
+    self
+      .options
+      .validate()
+      .map_err(BuildVmError::InvalidOptions)?;
+    if let Some(strict) = self.options.strict {
+      doc.set_strict(strict);
+    }
+    let mut vm = doc
+      .create_vm(device)
+      .map_err(|ContainsErrors| BuildVmError::ContainsErrors)?;
+    self.options.apply(&mut vm);
+    Ok(vm)
+  }
+
+  /// Creates a VM owning `device`, like [`Self::build`]. The device is
+  /// returned along with the error if the VM cannot be created.
+  pub fn build_owned<D: Device>(
+    &self,
+    doc: &mut Document,
+    device: D,
+  ) -> Result<VirtualMachine<'static, D>, (BuildVmError, D)> {
+    if let Err(err) = self.options.validate() {
+      return Err((BuildVmError::InvalidOptions(err), device));
+    }
+    if let Some(strict) = self.options.strict {
+      doc.set_strict(strict);
+    }
+    let mut vm =
+      doc
+        .create_owned_vm(device)
+        .map_err(|(ContainsErrors, device)| {
+          (BuildVmError::ContainsErrors, device)
+        })?;
+    self.options.apply(&mut vm);
+    Ok(vm)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ExecResult, Interpreter};
+  use pretty_assertions::assert_eq;
+  use std::time::Duration;
+
+  #[test]
+  fn presets() {
+    for builder in [
+      VmBuilder::new(),
+      VmBuilder::authentic(),
+      VmBuilder::development(),
+      VmBuilder::ci(),
+    ] {
+      assert_eq!(builder.options().validate(), Ok(()));
+    }
+    assert_eq!(
+      VmBuilder::authentic()
+        .soft_limits(None)
+        .options()
+        .validate(),
+      Err(VmOptionsError::UnlimitedAuthentic)
+    );
+    assert_eq!(
+      VmBuilder::new()
+        .soft_limits(Some(SoftLimits {
+          threshold: 0,
+          ..SoftLimits::default()
+        }))
+        .options()
+        .validate(),
+      Err(VmOptionsError::InvalidThreshold(0))
+    );
+    assert_eq!(
+      VmBuilder::ci()
+        .output_quota(Some(OutputQuota {
+          per_exec: Some(0),
+          total: None,
+        }))
+        .options()
+        .validate(),
+      Err(VmOptionsError::ZeroOutputQuota)
+    );
+  }
+
+  #[test]
+  fn yaml() {
+    for builder in [
+      VmBuilder::new(),
+      VmBuilder::authentic(),
+      VmBuilder::development(),
+      VmBuilder::ci(),
+    ] {
+      let yaml = builder.options().to_yaml();
+      assert_eq!(&VmOptions::from_yaml(&yaml).unwrap(), builder.options());
+    }
+    assert_eq!(
+      VmBuilder::ci().options().to_yaml(),
+      "\
+---
+read-only: true
+selector-warning: false
+soft-limits: ~
+print-flush-policy: per-newline
+output-quota:
+  per-exec: ~
+  total: 1048576
+rng-seed: \"0\"
+speed-mode: fast
+strict: ~
+profile-overrides:
+  int-overflow: ~
+  invalid-notes: ~
+  selector-rounding: ~
+"
+    );
+
+    let options = *VmBuilder::new()
+      .rng_seed(Some(u64::MAX))
+      .strict(Some(true))
+      .profile_overrides(ProfileOverrides {
+        int_overflow: Some(IntOverflow::Wrap),
+        ..ProfileOverrides::default()
+      })
+      .options();
+    let yaml = options.to_yaml();
+    assert!(yaml.contains("rng-seed: \"18446744073709551615\"\n"));
+    assert_eq!(VmOptions::from_yaml(&yaml).unwrap(), options);
+    assert_eq!(
+      VmOptions::from_yaml("rng-seed: 10").unwrap().rng_seed,
+      Some(10)
+    );
+
+    assert_eq!(VmOptions::from_yaml("").unwrap(), VmOptions::default());
+    assert_eq!(
+      VmOptions::from_yaml("soft-limits:\n  threshold: 50").unwrap(),
+      VmOptions {
+        soft_limits: Some(SoftLimits {
+          threshold: 50,
+          ..SoftLimits::default()
+        }),
+        ..VmOptions::default()
+      }
+    );
+    for content in [
+      "speed-mode: slow",
+      "rng-seed: -1",
+      "rng-seed: \"-1\"",
+      "profile-overrides:\n  int-overflow: clamp",
+      "profile-overrides:\n  rounding: round",
+      "soft-limits:\n  threshold: 300",
+      "output-quota:\n  limit: 1",
+      "speed: fast",
+    ] {
+      assert!(matches!(
+        VmOptions::from_yaml(content),
+        Err(ParseVmOptionsError::Other(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn build() {
+    crate::machine::init_machines_once().unwrap();
+    let program = "10 sleep 100:print int(rnd(1)*10000)";
+
+    let mut interp = Interpreter::new("");
+    interp.load(program);
+    assert!(matches!(interp.run(usize::MAX), Ok(ExecResult::Sleep(_))));
+
+    assert_eq!(
+      interp.set_vm_builder(VmBuilder::authentic().soft_limits(None)),
+      Err(VmOptionsError::UnlimitedAuthentic)
+    );
+    interp.set_vm_builder(VmBuilder::ci()).unwrap();
+    let mut outputs = vec![];
+    for _ in 0..2 {
+      interp.load(program);
+      assert!(matches!(interp.run(usize::MAX), Ok(ExecResult::End)));
+      outputs.push(interp.device().text_lines());
+    }
+    assert_eq!(outputs[0], outputs[1]);
+
+    let program = "10 a%=40000:print a%";
+    interp.load(program);
+    assert!(matches!(
+      interp.run(usize::MAX),
+      Ok(ExecResult::Error { .. })
+    ));
+    interp
+      .set_vm_builder(VmBuilder::ci().profile_overrides(ProfileOverrides {
+        int_overflow: Some(IntOverflow::Wrap),
+        ..ProfileOverrides::default()
+      }))
+      .unwrap();
+    interp.load(program);
+    assert!(matches!(interp.run(usize::MAX), Ok(ExecResult::End)));
+    assert_eq!(interp.device().text_lines()[0].trim(), "-25536");
+  }
+
+  #[test]
+  fn authentic_speed() {
+    crate::machine::init_machines_once().unwrap();
+    let mut interp = Interpreter::new("");
+    interp.set_vm_builder(VmBuilder::authentic()).unwrap();
+    interp.load("10 a=a+1:goto 10");
+    match interp.run(usize::MAX) {
+      Ok(ExecResult::Sleep(duration)) => {
+        assert!(duration >= Duration::from_millis(20))
+      }
+      result => panic!("{result:?}"),
+    }
+
+    interp.set_vm_builder(VmBuilder::new()).unwrap();
+    interp.load("10 a=a+1:goto 10");
+    assert!(matches!(interp.run(100000), Ok(ExecResult::Continue)));
+  }
+}
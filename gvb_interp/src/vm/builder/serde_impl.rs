@@ -0,0 +1,161 @@
+//! `Serialize` and `Deserialize` for [`VmOptions`], with the same fields as
+//! the YAML form. Missing fields take the default values.
+
+use std::fmt::{self, Formatter};
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use super::{
+  name_of, value_of, ProfileOverrides, SpeedMode, VmOptions, INT_OVERFLOWS,
+  INVALID_NOTES, PRINT_FLUSH_POLICIES, SELECTOR_ROUNDINGS, SPEED_MODES,
+};
+use crate::machine::{IntOverflow, InvalidNotes, SelectorRounding};
+use crate::vm::{OutputQuota, PrintFlushPolicy, SoftLimits};
+
+/// Enums are represented by their names in the YAML form.
+macro_rules! impl_named_enum {
+  ($ty:ty, $names:expr) => {
+    impl Serialize for $ty {
+      fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(name_of($names, *self))
+      }
+    }
+
+    impl<'de> Deserialize<'de> for $ty {
+      fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_str(NameVisitor($names))
+      }
+    }
+  };
+}
+
+impl_named_enum!(PrintFlushPolicy, PRINT_FLUSH_POLICIES);
+impl_named_enum!(SpeedMode, SPEED_MODES);
+impl_named_enum!(IntOverflow, INT_OVERFLOWS);
+impl_named_enum!(InvalidNotes, INVALID_NOTES);
+impl_named_enum!(SelectorRounding, SELECTOR_ROUNDINGS);
+
+struct NameVisitor<T: 'static>(&'static [(T, &'static str)]);
+
+impl<'de, T: Copy> Visitor<'de> for NameVisitor<T> {
+  type Value = T;
+
+  fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+    let names: Vec<_> = self.0.iter().map(|(_, name)| *name).collect();
+    write!(f, "one of {}", names.join(", "))
+  }
+
+  fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+    value_of(self.0, v)
+      .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+  }
+}
+
+/// Structs are represented by maps with kebab-case keys.
+macro_rules! impl_struct {
+  ($ty:ident { $($key:literal => $field:ident),* $(,)? }) => {
+    impl Serialize for $ty {
+      fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        const LEN: usize = [$($key),*].len();
+        let mut st = s.serialize_struct(stringify!($ty), LEN)?;
+        $(st.serialize_field($key, &self.$field)?;)*
+        st.end()
+      }
+    }
+
+    impl<'de> Deserialize<'de> for $ty {
+      fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let visitor = StructVisitor::<$ty>(PhantomData);
+        d.deserialize_struct(stringify!($ty), &[$($key),*], visitor)
+      }
+    }
+
+    impl<'de> Visitor<'de> for StructVisitor<$ty> {
+      type Value = $ty;
+
+      fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "struct {}", stringify!($ty))
+      }
+
+      fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+      ) -> Result<$ty, A::Error> {
+        let mut value = $ty::default();
+        while let Some(key) = map.next_key::<String>()? {
+          match key.as_str() {
+            $($key => value.$field = map.next_value()?,)*
+            _ => return Err(de::Error::unknown_field(&key, &[$($key),*])),
+          }
+        }
+        Ok(value)
+      }
+    }
+  };
+}
+
+struct StructVisitor<T>(PhantomData<T>);
+
+impl_struct!(SoftLimits {
+  "string-space" => string_space,
+  "array-memory" => array_memory,
+  "stack-depth" => stack_depth,
+  "threshold" => threshold,
+});
+
+impl_struct!(OutputQuota {
+  "per-exec" => per_exec,
+  "total" => total,
+});
+
+impl_struct!(ProfileOverrides {
+  "int-overflow" => int_overflow,
+  "invalid-notes" => invalid_notes,
+  "selector-rounding" => selector_rounding,
+});
+
+impl_struct!(VmOptions {
+  "read-only" => read_only,
+  "selector-warning" => selector_warning,
+  "soft-limits" => soft_limits,
+  "print-flush-policy" => print_flush_policy,
+  "output-quota" => output_quota,
+  "rng-seed" => rng_seed,
+  "speed-mode" => speed_mode,
+  "strict" => strict,
+  "profile-overrides" => profile_overrides,
+});
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use serde::de::value::{Error, MapDeserializer, StrDeserializer};
+  use serde::de::IntoDeserializer;
+
+  #[test]
+  fn named_enum() {
+    let d: StrDeserializer<Error> = "saturate".into_deserializer();
+    assert_eq!(IntOverflow::deserialize(d), Ok(IntOverflow::Saturate));
+    let d: StrDeserializer<Error> = "slow".into_deserializer();
+    assert!(SpeedMode::deserialize(d).is_err());
+  }
+
+  #[test]
+  fn missing_fields() {
+    let d: MapDeserializer<_, Error> =
+      MapDeserializer::new([("speed-mode", "fast")].into_iter());
+    assert_eq!(
+      VmOptions::deserialize(d),
+      Ok(VmOptions {
+        speed_mode: SpeedMode::Fast,
+        ..VmOptions::default()
+      })
+    );
+    let d: MapDeserializer<_, Error> =
+      MapDeserializer::new([("speed", "fast")].into_iter());
+    assert!(VmOptions::deserialize(d).is_err());
+  }
+}
@@ -5,16 +5,19 @@ use std::num::NonZeroUsize;
 
 use super::{
   Addr, Alignment, ByteString, CmpKind, DatumIndex, Instr, InstrKind, Location,
-  PrintMode, ScreenMode, StringProblem, Symbol, DUMMY_ADDR, FISRT_DATUM_INDEX,
+  PrintMode, ScreenMode, SourceMap, StringProblem, Symbol, DUMMY_ADDR,
+  FISRT_DATUM_INDEX,
 };
 use crate::ast::{
   BinaryOpKind, FileMode, Range, StmtKind, SysFuncKind, UnaryOpKind,
 };
+use crate::compiler::CodeEmitter;
 use crate::diagnostic::Diagnostic;
+use crate::machine::{EmojiVersion, DEFAULT_MAX_STRING_LEN};
 use crate::util::mbf5::Mbf5;
-use widestring::Utf16String;
-use crate::{compiler::CodeEmitter, machine::EmojiVersion};
+use crate::HashSet;
 use string_interner::StringInterner;
+use widestring::Utf16String;
 
 use super::Datum;
 
@@ -24,8 +27,11 @@ pub struct CodeGen {
   pub(super) interner: StringInterner,
   pub(super) data: Vec<Datum>,
   pub(super) code: Vec<Instr>,
+  pub(crate) source_map: SourceMap,
   cur_line: usize,
+  stmt_stack: Vec<usize>,
   diagnostics: Vec<(usize, Diagnostic)>,
+  max_string_len: usize,
 }
 
 impl CodeGen {
@@ -35,19 +41,69 @@ impl CodeGen {
       interner: StringInterner::new(),
       data: vec![],
       code: vec![],
+      source_map: SourceMap::default(),
       cur_line: 0,
+      stmt_stack: vec![],
       diagnostics: vec![],
+      max_string_len: DEFAULT_MAX_STRING_LEN,
     }
   }
 
+  /// Sets the maximum length of string literals and data, 255 by default.
+  pub fn set_max_string_len(&mut self, len: usize) {
+    self.max_string_len = len;
+  }
+
+  /// Returns the locations and names of variables which are read but never
+  /// assigned anywhere in the program, thus always 0 or empty strings. A
+  /// variable is assigned by assignments, FOR, INPUT, READ, SWAP, etc.
+  /// Parameters of user-defined functions are excluded in the function
+  /// bodies. Control flow is not taken into account, so a read preceding the
+  /// only assignment of a variable is not reported.
+  pub(crate) fn never_assigned_var_reads(&self) -> Vec<(Location, &str)> {
+    let mut assigned = HashSet::default();
+    let mut fn_bodies = vec![];
+    for (i, instr) in self.code.iter().enumerate() {
+      match &instr.kind {
+        InstrKind::PushVarLValue { name } | InstrKind::ForLoop { name, .. } => {
+          assigned.insert(*name);
+        }
+        InstrKind::DefFn { param, end, .. } => {
+          fn_bodies.push((i + 1..end.0, *param));
+        }
+        _ => {}
+      }
+    }
+
+    let mut reads = vec![];
+    for (i, instr) in self.code.iter().enumerate() {
+      if let InstrKind::PushVar(name) = &instr.kind {
+        if assigned.contains(name)
+          || fn_bodies
+            .iter()
+            .any(|(body, param)| body.contains(&i) && param == name)
+        {
+          continue;
+        }
+        reads.push((instr.loc.clone(), self.interner.resolve(*name).unwrap()));
+      }
+    }
+    reads
+  }
+
   fn push_instr(&mut self, range: Range, kind: InstrKind) {
-    self.code.push(Instr {
-      loc: Location {
+    self.push_instr_with_loc(
+      Location {
         line: self.cur_line,
         range,
       },
       kind,
-    })
+    );
+  }
+
+  fn push_instr_with_loc(&mut self, loc: Location, kind: InstrKind) {
+    self.code.push(Instr { loc, kind });
+    self.source_map.add_instr(self.stmt_stack.last().copied());
   }
 
   fn add_error(&mut self, range: Range, message: impl ToString) {
@@ -95,8 +151,25 @@ impl CodeEmitter for CodeGen {
   type Addr = Addr;
   type DatumIndex = DatumIndex;
 
-  fn begin_line(&mut self, line: usize) {
+  fn max_string_len(&self) -> usize {
+    self.max_string_len
+  }
+
+  fn begin_line(&mut self, line: usize, line_start: usize) {
     self.cur_line = line;
+    self.source_map.add_line(line_start);
+  }
+
+  fn begin_stmt(&mut self, range: Range) {
+    let stmt = self.source_map.add_stmt(Location {
+      line: self.cur_line,
+      range,
+    });
+    self.stmt_stack.push(stmt);
+  }
+
+  fn end_stmt(&mut self) {
+    self.stmt_stack.pop();
   }
 
   fn emit_no_op(&mut self, _range: Range) {
@@ -168,6 +241,7 @@ impl CodeEmitter for CodeGen {
       StmtKind::NoTrace => self.push_instr(range, InstrKind::SetTrace(false)),
       StmtKind::Play(_) => self.push_instr(range, InstrKind::PlayNotes),
       StmtKind::Poke { .. } => self.push_instr(range, InstrKind::Poke),
+      StmtKind::Sound { .. } => self.push_instr(range, InstrKind::Sound),
       StmtKind::Pop => self.push_instr(range, InstrKind::Pop),
       StmtKind::Put { .. } => self.push_instr(range, InstrKind::WriteRecord),
       StmtKind::Return => self.push_instr(range, InstrKind::Return),
@@ -187,6 +261,9 @@ impl CodeEmitter for CodeGen {
       StmtKind::Fwrite { .. } => self.push_instr(range, InstrKind::Fwrite),
       StmtKind::Fseek { .. } => self.push_instr(range, InstrKind::Fseek),
       StmtKind::DebugPrint { .. } => self.push_instr(range, InstrKind::Debug),
+      StmtKind::Timer { enabled } => {
+        self.push_instr(range, InstrKind::EnableTimer(*enabled))
+      }
       _ => unreachable!(),
     }
   }
@@ -227,10 +304,7 @@ impl CodeEmitter for CodeGen {
 
   fn end_def_fn(&mut self, def_addr: Self::Addr) {
     let loc = self.code[def_addr.0].loc.clone();
-    self.code.push(Instr {
-      loc,
-      kind: InstrKind::ReturnFn,
-    });
+    self.push_instr_with_loc(loc, InstrKind::ReturnFn);
     let cur_addr = Addr(self.code.len());
     match &mut self.code[def_addr.0].kind {
       InstrKind::DefFn { end, .. } => {
@@ -321,7 +395,8 @@ impl CodeEmitter for CodeGen {
     match &mut self.code[addr.0].kind {
       InstrKind::GoSub(addr)
       | InstrKind::GoTo(addr)
-      | InstrKind::JumpIfZero(addr) => {
+      | InstrKind::JumpIfZero(addr)
+      | InstrKind::SetTimer(addr) => {
         *addr = label_addr;
       }
       _ => unreachable!(),
@@ -342,6 +417,12 @@ impl CodeEmitter for CodeGen {
     self.push_instr(range, InstrKind::Switch(labels));
   }
 
+  fn emit_on_timer(&mut self, range: Range) -> Self::Addr {
+    let addr = Addr(self.code.len());
+    self.push_instr(range, InstrKind::SetTimer(DUMMY_ADDR));
+    addr
+  }
+
   fn emit_set_row(&mut self, range: Range) {
     self.push_instr(range, InstrKind::SetRow);
   }
@@ -445,7 +526,8 @@ impl CodeEmitter for CodeGen {
   }
 
   fn emit_string(&mut self, range: Range, str: Utf16String) -> usize {
-    let (str, problems) = ByteString::from_utf16str(str, self.emoji_version, true);
+    let (str, problems) =
+      ByteString::from_utf16str(str, self.emoji_version, true);
     let range_offset = (range.start + 1) as _;
     self.add_string_problems(problems, range_offset);
     let len = str.len();
@@ -0,0 +1,156 @@
+use super::{Type, Value};
+use crate::machine::IntOverflow;
+use crate::util::mbf5::{Mbf5, ParseRealError};
+
+/// Error of converting data to the type of a variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CoerceError {
+  /// The integer part of the number is out of [-32768, 32767].
+  IntOverflow(Mbf5),
+  Malformed,
+  RealOverflow,
+  /// The string is longer than the maximum length of strings.
+  StringTooLong {
+    max: usize,
+  },
+}
+
+/// Truncates `num` to an integer. Integer parts out of -32768~32767 are
+/// handled according to `overflow`.
+pub(crate) fn real_to_int(
+  num: Mbf5,
+  overflow: IntOverflow,
+) -> Result<i16, CoerceError> {
+  let int = f64::from(num.truncate());
+  if int > -32769.0 && int < 32768.0 {
+    return Ok(int as i16);
+  }
+  match overflow {
+    IntOverflow::Error => Err(CoerceError::IntOverflow(num)),
+    IntOverflow::Wrap => Ok(int.rem_euclid(65536.0) as u16 as i16),
+    IntOverflow::Saturate => Ok(if int < 0.0 { i16::MIN } else { i16::MAX }),
+  }
+}
+
+pub(crate) fn parse_real(str: &[u8]) -> Result<Mbf5, CoerceError> {
+  match String::from_utf8_lossy(str).parse::<Mbf5>() {
+    Ok(num) => Ok(num),
+    Err(ParseRealError::Malformed) => Err(CoerceError::Malformed),
+    Err(ParseRealError::Infinite) => Err(CoerceError::RealOverflow),
+  }
+}
+
+/// Checks the length of a string read by READ or INPUT# statements.
+pub(crate) fn check_string_len(
+  str: &[u8],
+  max_len: usize,
+) -> Result<(), CoerceError> {
+  if str.len() > max_len {
+    Err(CoerceError::StringTooLong { max: max_len })
+  } else {
+    Ok(())
+  }
+}
+
+/// Converts `num` to a value of numeric type `ty`.
+pub(crate) fn num_to_value(
+  num: Mbf5,
+  ty: Type,
+  overflow: IntOverflow,
+) -> Result<Value, CoerceError> {
+  match ty {
+    Type::Integer => real_to_int(num, overflow).map(Value::Integer),
+    Type::Real => Ok(Value::Real(num)),
+    Type::String => unreachable!(),
+  }
+}
+
+/// Parses data read by READ or INPUT# statements into a value of numeric type
+/// `ty`.
+pub(crate) fn parse_num_value(
+  str: &[u8],
+  ty: Type,
+  overflow: IntOverflow,
+) -> Result<Value, CoerceError> {
+  num_to_value(parse_real(str)?, ty, overflow)
+}
+
+impl CoerceError {
+  /// Returns the error message for data read by READ or INPUT# statements.
+  pub fn read_message(&self, data: impl std::fmt::Display) -> String {
+    match self {
+      Self::IntOverflow(num) => format!(
+        "读取到的数据：{}，超出了整数的表示范围（-32768~32767），\
+          无法赋值给整数变量",
+        f64::from(*num)
+      ),
+      Self::Malformed => format!("读取到的数据：{data}，不符合实数的格式"),
+      Self::RealOverflow => {
+        format!("读取到的数据：{data}，数值过大，超出了实数的表示范围")
+      }
+      Self::StringTooLong { max } => {
+        format!("读取到的数据：{data}，字符串过长，长度超出 {max}")
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  fn num(n: f64) -> Mbf5 {
+    Mbf5::try_from(n).unwrap()
+  }
+
+  #[test]
+  fn int_range() {
+    let real_to_int = |n| real_to_int(n, IntOverflow::Error);
+    assert_eq!(real_to_int(num(-32768.9)), Ok(-32768));
+    assert_eq!(real_to_int(num(32767.9)), Ok(32767));
+    assert_eq!(
+      real_to_int(num(32768.0)),
+      Err(CoerceError::IntOverflow(num(32768.0)))
+    );
+    assert_eq!(
+      real_to_int(num(-32769.0)),
+      Err(CoerceError::IntOverflow(num(-32769.0)))
+    );
+  }
+
+  #[test]
+  fn int_wrap() {
+    let real_to_int = |n| real_to_int(n, IntOverflow::Wrap);
+    assert_eq!(real_to_int(num(32767.9)), Ok(32767));
+    assert_eq!(real_to_int(num(32768.5)), Ok(-32768));
+    assert_eq!(real_to_int(num(65537.0)), Ok(1));
+    assert_eq!(real_to_int(num(-32769.5)), Ok(32767));
+    assert_eq!(real_to_int(num(-65536.0)), Ok(0));
+    assert_eq!(real_to_int(num(1e30)), Ok(0));
+  }
+
+  #[test]
+  fn int_saturate() {
+    let real_to_int = |n| real_to_int(n, IntOverflow::Saturate);
+    assert_eq!(real_to_int(num(-12.5)), Ok(-12));
+    assert_eq!(real_to_int(num(40000.0)), Ok(32767));
+    assert_eq!(real_to_int(num(-1e30)), Ok(-32768));
+  }
+
+  #[test]
+  fn parse() {
+    assert!(matches!(
+      parse_num_value(b"12.5", Type::Integer, IntOverflow::Error),
+      Ok(Value::Integer(12))
+    ));
+    assert_eq!(
+      parse_num_value(b"1e40", Type::Real, IntOverflow::Error).err(),
+      Some(CoerceError::RealOverflow)
+    );
+    assert_eq!(
+      parse_num_value(b"a1", Type::Real, IntOverflow::Error).err(),
+      Some(CoerceError::Malformed)
+    );
+  }
+}
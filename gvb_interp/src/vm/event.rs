@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+use super::Location;
+
+/// Maximum number of events kept by the VM. Older events are dropped if the
+/// host does not take them in time, e.g. when a program clears variables in
+/// a loop.
+pub const MAX_EVENTS: usize = 64;
+
+/// Transitions of the lifecycle of a VM, collected by the VM until taken by
+/// [`VirtualMachine::take_events`], so that hosts can reset their views of
+/// the program state, e.g. the variable table, when the program clears the
+/// variables or restarts itself. At most [`MAX_EVENTS`] recent events are
+/// kept.
+///
+/// [`VirtualMachine::take_events`]: super::VirtualMachine::take_events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmEvent {
+  Created,
+  /// The program is started by the host.
+  Started,
+  /// Variables are cleared by a CLEAR statement.
+  Cleared {
+    location: Location,
+  },
+  /// The program is restarted by a RUN statement.
+  Restarted {
+    location: Location,
+  },
+  /// The program ends, is quit by the user, or is stopped by the host.
+  Finished,
+  /// The program stops due to a runtime error.
+  Errored {
+    location: Location,
+  },
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct EventQueue {
+  events: VecDeque<VmEvent>,
+}
+
+impl EventQueue {
+  pub fn new() -> Self {
+    Self {
+      events: VecDeque::from([VmEvent::Created]),
+    }
+  }
+
+  pub fn push(&mut self, event: VmEvent) {
+    if self.events.len() == MAX_EVENTS {
+      self.events.pop_front();
+    }
+    self.events.push_back(event);
+  }
+
+  pub fn take(&mut self) -> Vec<VmEvent> {
+    std::mem::take(&mut self.events).into()
+  }
+}
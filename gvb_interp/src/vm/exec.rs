@@ -0,0 +1,1293 @@
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+use self::files::exec_file_input;
+use super::print_buffer::keeps_print_buffer;
+use super::{
+  symbol_type, Addr, ArithFaultKind, ArithOp, Array, ArrayData, ByteString,
+  ControlRecord, Dimension, ExecInput, ExecResult, ExecState, FileMode,
+  FnCallRecord, InstrKind, KeyboardInputType, LValue, Location, LoopKind,
+  LossyWrite, Result, ScreenMode, SpeedMode, Timer, Type, UserFunc, Value,
+  VirtualMachine, VmEvent, WriteLoss,
+};
+use crate::device::notes::parse_notes;
+use crate::device::{AsmExecState, Device, FileHandle, KeyCode};
+use crate::machine::{InvalidNotes, SelectorRounding};
+use crate::util::mbf5::{Mbf5, RealError};
+
+mod control;
+mod files;
+mod sysfunc;
+
+/// Time the program runs in `SpeedMode::Authentic` between waits of the host.
+const PACING_QUANTUM: Duration = Duration::from_millis(20);
+
+/// Number of instructions `run_steps` executes per call to `exec`.
+const RUN_CHUNK: usize = 10000;
+
+impl<'d, D> VirtualMachine<'d, D>
+where
+  D: Device,
+  <D as Device>::AsmError: ToString,
+{
+  pub fn exec(&mut self, input: ExecInput, steps: usize) -> ExecResult {
+    self.output_quota.start_exec();
+    let running = !matches!(self.state, ExecState::Done);
+    let result = self.exec_steps(input, steps);
+    self.flush_print_buffer();
+    match &result {
+      ExecResult::End if running => self.events.push(VmEvent::Finished),
+      ExecResult::Error { location, .. } => {
+        self.events.push(VmEvent::Errored {
+          location: location.clone(),
+        })
+      }
+      _ => {}
+    }
+    result
+  }
+
+  /// Executes at most `max_steps` instructions by calling `exec` repeatedly,
+  /// starting with `input`. Every result other than `ExecResult::Continue`
+  /// is passed to `respond`, which returns the input to resume with, or None
+  /// to stop and return the result.
+  ///
+  /// Returns `ExecResult::Continue` if all `max_steps` instructions are
+  /// executed.
+  pub fn run_steps<F>(
+    &mut self,
+    mut input: ExecInput,
+    max_steps: usize,
+    mut respond: F,
+  ) -> ExecResult
+  where
+    F: FnMut(&mut Self, &ExecResult) -> Option<ExecInput>,
+  {
+    let mut steps = max_steps;
+    loop {
+      let budget = steps.min(RUN_CHUNK);
+      let result = self.exec(input, budget);
+      steps -= budget;
+      input = match result {
+        ExecResult::Continue if steps > 0 => ExecInput::None,
+        ExecResult::Continue => return result,
+        _ => match respond(self, &result) {
+          Some(input) => input,
+          None => return result,
+        },
+      };
+    }
+  }
+
+  fn exec_steps(&mut self, input: ExecInput, mut steps: usize) -> ExecResult {
+    match std::mem::replace(&mut self.state, ExecState::Normal) {
+      ExecState::Done => return ExecResult::End,
+      ExecState::WaitForKey => self.assign_key(input),
+      ExecState::WaitForKeyboardInput {
+        lvalues,
+        skip_first,
+      } => self.assign_input(input, lvalues, skip_first),
+      ExecState::AsmSuspend { state, loc } => {
+        match self.device.exec_asm(&mut steps, AsmExecState::Cont(state)) {
+          Ok(Some(s)) => {
+            return self.state.suspend_asm(loc, s).unwrap_err();
+          }
+          Ok(None) => {
+            self.pc += 1;
+          }
+          Err(msg) => return self.state.error(loc, msg).unwrap_err(),
+        }
+      }
+      ExecState::Normal => {
+        if self.device.user_quit() {
+          return self.state.end().unwrap_err();
+        }
+      }
+    }
+
+    self.device.clear_cursor();
+
+    while steps > 0 {
+      if self.run_to_addrs.binary_search(&self.pc).is_ok() {
+        self.run_to_addrs.clear();
+        let location = self.source_map.stmt_location(self.pc).unwrap().clone();
+        return ExecResult::Breakpoint { location };
+      }
+      let stmt_start = self.speed_mode == SpeedMode::Authentic
+        && self.source_map.is_stmt_start(self.pc);
+      if let Err(result) = self.exec_instr(&mut steps) {
+        return result;
+      }
+      if stmt_start {
+        if let Err(result) = self.pace() {
+          return result;
+        }
+      }
+      if let Some(h) = &mut self.step_hook {
+        h.counter += 1;
+        if h.counter == h.interval.get() {
+          h.counter = 0;
+          if h.hook.on_steps() {
+            return ExecResult::Yield;
+          }
+        }
+      }
+    }
+
+    ExecResult::Continue
+  }
+
+  /// Charges a statement executed in `SpeedMode::Authentic` with the sleep
+  /// unit of the device, which is about the time the real machine takes to
+  /// execute a simple statement. The host is asked to wait once the charged
+  /// time reaches `PACING_QUANTUM`, so that the program runs no faster than
+  /// on the real machine.
+  fn pace(&mut self) -> Result<()> {
+    self.pacing_debt += self.device.sleep_unit();
+    if self.pacing_debt >= PACING_QUANTUM {
+      let debt = std::mem::take(&mut self.pacing_debt);
+      let duration = self.device.sleep(debt);
+      if !duration.is_zero() {
+        self.state.sleep(duration)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn exec_instr(&mut self, steps: &mut usize) -> Result<()> {
+    *steps -= 1;
+    self.tick_timer();
+    let stmt = self.source_map.stmt_index(self.pc);
+    if stmt.is_some() && stmt != self.context_stmt {
+      self.context_stmt = stmt;
+      let loc = self.source_map.stmt_location(self.pc).unwrap();
+      self.device.set_context(loc);
+    }
+    let instr = &self.code[self.pc];
+    let loc = instr.loc.clone();
+    let kind = instr.kind.clone();
+
+    if !self.print_buffer.is_empty() && !keeps_print_buffer(&kind) {
+      self.flush_print_buffer();
+    }
+
+    let result = self.do_exec_instr(steps, loc.clone(), kind);
+    let warning = self.pending_warning.take();
+    if let ExecState::Done = &self.state {
+      result.and(self.close_files(loc))
+    } else {
+      result?;
+      if let Some((location, message)) = warning {
+        return Err(ExecResult::Warning { location, message });
+      }
+      self.check_soft_limits(loc.clone())?;
+      self.check_output_quota(loc)
+    }
+  }
+
+  fn do_exec_instr(
+    &mut self,
+    steps: &mut usize,
+    loc: Location,
+    kind: InstrKind,
+  ) -> Result<()> {
+    macro_rules! write_file {
+      ($file:ident, $w:expr) => {
+        self.state.io(loc.clone(), "写入文件", $file.write($w))?;
+      };
+    }
+
+    macro_rules! do_write {
+      (
+        $to_file:ident,
+        $end:ident,
+        $file:ident => $write_file:expr,
+        $write_screen:expr
+      ) => {{
+        if $to_file {
+          let filenum = self.get_filenum($end)?;
+          let file = &mut self.files[filenum as usize];
+          if !file.handle.is_open() {
+            self.state.error(loc, "未打开文件，不能执行 WRITE 操作")?;
+          }
+          match file.mode {
+            FileMode::Output | FileMode::Append => {
+              let $file = &mut file.handle;
+              $write_file;
+              if $end {
+                write_file!($file, &[0xffu8]);
+              } else {
+                write_file!($file, b",");
+              }
+            }
+            _ => {
+              self.state.error(
+                loc,
+                format!(
+                  "WRITE 语句只能用于以 OUTPUT 或 APPEND 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+                  filenum + 1,
+                  file.mode
+                ),
+              )?;
+            }
+          }
+        } else {
+          $write_screen;
+          if !$end {
+            self.print_buffer.print(&mut *self.device, b",");
+          }
+        };
+      }}
+    }
+
+    macro_rules! do_get_put {
+      (
+        $op:literal,
+        $record_len:ident,
+        $fields:ident,
+        $file:ident => $body:expr
+      ) => {
+        let record_loc = self.num_stack.last().unwrap().0.clone();
+        let record = self.pop_range(-32768, 32767)? as i16;
+        if record == 0 {
+          self.state.error(record_loc, "记录序号不能为 0")?;
+        }
+        let record = record - 1;
+
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        match &file.mode {
+          FileMode::Random { record_len, fields } => {
+            let offset = record as u64 * *record_len as u64;
+            self.state.io(
+              loc.clone(),
+              "设置文件指针",
+              file.handle.seek(offset),
+            )?;
+
+            let $record_len = *record_len;
+            let $fields = &fields[..];
+            let $file = &mut file.handle;
+            $body;
+          }
+          _ => {
+            self.state.error(
+              loc,
+              format!(
+                "{} 语句只能用于以 RANDOM 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+                $op,
+                filenum + 1,
+                file.mode
+              ),
+            )?;
+          }
+        }
+      };
+    }
+
+    match kind {
+      InstrKind::DefFn { name, param, end } => {
+        self.bindings.user_funcs.insert(
+          name,
+          UserFunc {
+            param,
+            body_addr: Addr(self.pc + 1),
+          },
+        );
+        self.pc = end.0;
+        return Ok(());
+      }
+      InstrKind::DimArray {
+        name,
+        dimensions: num_dimensions,
+      } => {
+        if self.bindings.arrays.contains_key(&name) {
+          self.state.error(loc, "重复定义数组")?;
+        }
+        let mut size = 1;
+        let mut multiplier = 1;
+        let mut dimensions = vec![];
+        let start = self.num_stack.len() - num_dimensions.get();
+        self.num_stack[start..].reverse();
+        for _ in 0..num_dimensions.get() {
+          let (loc, value) = self.num_stack.pop().unwrap();
+          let bound = f64::from(value.truncate()) as isize;
+          if bound < 0 {
+            self.state.error(
+              loc,
+              format!("数组下标不能为负数。该下标的值为：{}", f64::from(value)),
+            )?
+          } else if bound > 32767 {
+            self.state.error(
+              loc,
+              format!(
+                "数组下标超出上限 32767。该下标的值为：{}",
+                f64::from(value)
+              ),
+            )?
+          }
+          let bound = bound as usize + 1;
+          size *= bound;
+          dimensions.push(Dimension {
+            bound: unsafe { NonZeroU16::new_unchecked(bound as u16) },
+            multiplier,
+          });
+          multiplier *= bound;
+        }
+        let data = ArrayData::new(symbol_type(&self.interner, name), size);
+        self.bindings.insert_array(name, Array { dimensions, data });
+      }
+      InstrKind::PushVarLValue { name } => {
+        self.lval_stack.push((loc, LValue::Var { name }));
+      }
+      InstrKind::PushIndexLValue { name, dimensions } => {
+        let offset = self.calc_array_offset(name, dimensions)?;
+        self.lval_stack.push((loc, LValue::Index { name, offset }));
+      }
+      InstrKind::PushFnLValue { name, param } => {
+        self.lval_stack.push((loc, LValue::Fn { name, param }));
+      }
+      InstrKind::SetRecordFields { fields } => {
+        self.exec_field(loc, fields.get())?
+      }
+      InstrKind::ForLoop { name, has_step } => {
+        self.exec_for(loc, name, has_step)?
+      }
+      InstrKind::NextFor { name } => {
+        return self.exec_next(loc, name);
+      }
+      InstrKind::GoSub(target) => {
+        self.control_stack.push(ControlRecord::Sub {
+          next_addr: Addr(self.pc + 1),
+        });
+        self.pc = target.0;
+        return Ok(());
+      }
+      InstrKind::GoTo(target) => {
+        self.pc = target.0;
+        return Ok(());
+      }
+      InstrKind::JumpIfZero(target) => {
+        let value = self.num_stack.pop().unwrap().1;
+        if value.is_zero() {
+          self.pc = target.0;
+        } else {
+          self.pc += 1;
+        }
+        return Ok(());
+      }
+      InstrKind::CallFn(func) => {
+        if let Some(func) = self.bindings.user_funcs.get(&func).cloned() {
+          let arg = self.num_stack.pop().unwrap().1;
+          let param_org_value = self
+            .bindings
+            .load_value(&self.interner, LValue::Var { name: func.param });
+          self.fn_call_stack.push(FnCallRecord {
+            param: func.param,
+            param_org_value,
+            next_addr: Addr(self.pc + 1),
+          });
+          self.store_real(LValue::Var { name: func.param }, arg)?;
+          self.pc = func.body_addr.0;
+        } else {
+          self.state.error(loc, "自定义函数不存在")?;
+        }
+        return Ok(());
+      }
+      InstrKind::ReturnFn => {
+        let record = self.fn_call_stack.pop().unwrap();
+        self.bindings.store_value(
+          LValue::Var { name: record.param },
+          record.param_org_value,
+        );
+        self.pc = record.next_addr.0;
+        return Ok(());
+      }
+      InstrKind::Switch(branches) => {
+        let (value_loc, value) = self.num_stack.last().cloned().unwrap();
+        self.pop_u8(false)?;
+        let value = f64::from(value);
+        let rounding = self.selector_rounding();
+        let selector = match rounding {
+          SelectorRounding::Truncate => value.trunc(),
+          SelectorRounding::Round => (value + 0.5).floor(),
+        } as usize;
+        if self.selector_warning && value.fract() != 0.0 {
+          let rounding = match rounding {
+            SelectorRounding::Truncate => "舍去小数部分",
+            SelectorRounding::Round => "四舍五入",
+          };
+          self.pending_warning = Some((
+            value_loc,
+            format!(
+              "ON 语句的选择值 {value} 不是整数，{rounding}后为 {selector}"
+            ),
+          ));
+        }
+        if selector >= 1 && selector <= branches.get() {
+          match self.code[self.pc + selector].kind.clone() {
+            InstrKind::GoSub(target) => {
+              let next_addr = Addr(self.pc + branches.get() + 1);
+              self.control_stack.push(ControlRecord::Sub { next_addr });
+              self.pc = target.0;
+            }
+            InstrKind::GoTo(target) => {
+              self.pc = target.0;
+            }
+            _ => unreachable!(),
+          }
+        } else {
+          self.pc += branches.get() + 1;
+        }
+        return Ok(());
+      }
+      InstrKind::RestoreDataPtr(ptr) => {
+        self.data_ptr = ptr.0;
+      }
+      InstrKind::Return => {
+        while let Some(record) = self.control_stack.pop() {
+          if let ControlRecord::Sub { next_addr } = record {
+            self.pc = next_addr.0;
+            return Ok(());
+          }
+        }
+        self
+          .state
+          .error(loc, "之前没有执行过 GOSUB 语句，RETURN 语句无法执行")?;
+      }
+      InstrKind::Pop => {
+        while let Some(record) = self.control_stack.pop() {
+          if let ControlRecord::Sub { .. } = record {
+            self.pc += 1;
+            return Ok(());
+          }
+        }
+        self
+          .state
+          .error(loc, "之前没有执行过 GOSUB 语句，POP 语句无法执行")?;
+      }
+      InstrKind::PopNum => {
+        self.num_stack.pop().unwrap();
+      }
+      InstrKind::PopStr => {
+        self.str_stack.pop().unwrap();
+      }
+      InstrKind::PushNum(num) => {
+        self.num_stack.push((loc, num));
+      }
+      InstrKind::PushVar(name) => {
+        match self
+          .bindings
+          .load_value(&self.interner, LValue::Var { name })
+        {
+          Value::Integer(n) => self.num_stack.push((loc, n.into())),
+          Value::Real(n) => self.num_stack.push((loc, n)),
+          Value::String(s) => self.str_stack.push((loc, s)),
+        }
+      }
+      InstrKind::PushStr(str) => {
+        self.str_stack.push((loc, str));
+      }
+      InstrKind::PushInKey => {
+        self.state.inkey()?;
+      }
+      InstrKind::PushIndex { name, dimensions } => {
+        let offset = self.calc_array_offset(name, dimensions)?;
+        match &self.bindings.arrays[&name].data {
+          ArrayData::Integer(arr) => {
+            self.num_stack.push((loc, Mbf5::from(arr[offset])));
+          }
+          ArrayData::Real(arr) => {
+            self.num_stack.push((loc, arr[offset]));
+          }
+          ArrayData::String(arr) => {
+            self
+              .str_stack
+              .push((loc, ByteString::from(arr.get(offset))));
+          }
+        };
+      }
+      InstrKind::Not => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, Mbf5::from(value.is_zero())));
+      }
+      InstrKind::Neg => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, -value));
+      }
+      InstrKind::CmpNum(cmp) => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, Mbf5::from(cmp.cmp(lhs, rhs))));
+      }
+      InstrKind::CmpStr(cmp) => {
+        let rhs = self.str_stack.pop().unwrap().1;
+        let lhs = self.str_stack.pop().unwrap().1;
+        self.num_stack.push((loc, Mbf5::from(cmp.cmp(lhs, rhs))));
+      }
+      InstrKind::Concat => {
+        let mut rhs = self.str_stack.pop().unwrap().1;
+        let mut lhs = self.str_stack.pop().unwrap().1;
+        lhs.append(&mut rhs);
+        let max_len = self.device.max_string_len();
+        if lhs.len() > max_len {
+          let loop_context = self.loop_context();
+          let mut message = format!(
+            "运算结果字符串过长，长度超出 {max_len}。字符串长度为：{}",
+            lhs.len()
+          );
+          if let Some(ctx) = &loop_context {
+            let kind = match ctx.kind {
+              LoopKind::For => "FOR",
+              LoopKind::While => "WHILE",
+            };
+            message.push_str(&format!(
+              "。可能是在 {kind} 循环中反复拼接字符串导致的"
+            ));
+          }
+          self.state.error_in_loop(loc, message, loop_context)?;
+        }
+        self.str_stack.push((loc, lhs));
+      }
+      InstrKind::Add => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        match lhs + rhs {
+          Ok(result) => self.num_stack.push((loc, result)),
+          Err(RealError::Infinite) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Add,
+              ArithFaultKind::Overflow,
+              vec![lhs, rhs],
+              format!(
+                "运算结果数值过大，超出了实数的表示范围。加法运算的两个运算数分别为：{lhs}，{rhs}"
+              ))?;
+          }
+          Err(RealError::Nan) => unreachable!(),
+        }
+      }
+      InstrKind::Sub => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        match lhs - rhs {
+          Ok(result) => self.num_stack.push((loc, result)),
+          Err(RealError::Infinite) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Sub,
+              ArithFaultKind::Overflow,
+              vec![lhs, rhs],
+              format!(
+                "运算结果数值过大，超出了实数的表示范围。减法运算的两个运算数分别为：{lhs}，{rhs}"
+              ))?;
+          }
+          Err(RealError::Nan) => unreachable!(),
+        }
+      }
+      InstrKind::Mul => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        match lhs * rhs {
+          Ok(result) => self.num_stack.push((loc, result)),
+          Err(RealError::Infinite) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Mul,
+              ArithFaultKind::Overflow,
+              vec![lhs, rhs],
+              format!(
+                "运算结果数值过大，超出了实数的表示范围。乘法运算的两个运算数分别为：{lhs}，{rhs}"
+              ))?;
+          }
+          Err(RealError::Nan) => unreachable!(),
+        }
+      }
+      InstrKind::Div => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        if rhs.is_zero() {
+          self.arith_fault(
+            loc,
+            ArithOp::Div,
+            ArithFaultKind::DivisionByZero,
+            vec![lhs, rhs],
+            "除以 0",
+          )?;
+        }
+        match lhs / rhs {
+          Ok(result) => self.num_stack.push((loc, result)),
+          Err(RealError::Infinite) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Div,
+              ArithFaultKind::Overflow,
+              vec![lhs, rhs],
+              format!(
+                "运算结果数值过大，超出了实数的表示范围。除法运算的两个运算数分别为：{lhs}，{rhs}"
+              ))?;
+          }
+          Err(RealError::Nan) => unreachable!(),
+        }
+      }
+      InstrKind::Pow => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        match lhs.pow(rhs) {
+          Ok(result) => self.num_stack.push((loc, result)),
+          Err(RealError::Infinite) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Pow,
+              ArithFaultKind::Overflow,
+              vec![lhs, rhs],
+              format!(
+                "运算结果数值过大，超出了实数的表示范围。底数为：{lhs}，指数为：{rhs}"
+              ))?;
+          }
+          Err(RealError::Nan) => {
+            self.arith_fault(
+              loc,
+              ArithOp::Pow,
+              ArithFaultKind::Domain,
+              vec![lhs, rhs],
+              format!("超出乘方运算的定义域。底数为：{lhs}，指数为：{rhs}"),
+            )?;
+          }
+        }
+      }
+      InstrKind::And => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        self
+          .num_stack
+          .push((loc, Mbf5::from(!lhs.is_zero() && !rhs.is_zero())));
+      }
+      InstrKind::Or => {
+        let rhs = self.num_stack.pop().unwrap().1;
+        let lhs = self.num_stack.pop().unwrap().1;
+        self
+          .num_stack
+          .push((loc, Mbf5::from(!lhs.is_zero() || !rhs.is_zero())));
+      }
+      InstrKind::SysFuncCall { kind, arity } => {
+        self.exec_sys_func(loc, kind, arity)?;
+      }
+      InstrKind::NewLine => {
+        self.print_buffer.newline(&mut *self.device);
+      }
+      InstrKind::PrintSpc => {
+        let value = self.pop_u8(false)?;
+        self
+          .print_buffer
+          .print(&mut *self.device, &vec![b' '; value as _]);
+      }
+      InstrKind::PrintTab => {
+        let (_, columns) = self.device.text_size();
+        let col = self.pop_range(1, columns as _)? as u8 - 1;
+        let current_col = self.device.get_column();
+        let spc_num = if current_col > col {
+          columns - current_col + col
+        } else {
+          col - current_col
+        };
+        self
+          .print_buffer
+          .print(&mut *self.device, &vec![b' '; spc_num as _]);
+      }
+      InstrKind::PrintNum => {
+        let value = self.num_stack.pop().unwrap().1;
+        self
+          .print_buffer
+          .print(&mut *self.device, value.to_string().as_bytes());
+      }
+      InstrKind::PrintStr => {
+        let mut value = self.str_stack.pop().unwrap().1;
+        value.end_at_null();
+        value.drop_0x1f();
+        self.print_buffer.print(&mut *self.device, &value);
+      }
+      InstrKind::Flush => {
+        self.print_buffer.end_stmt(&mut *self.device);
+      }
+      InstrKind::SetRow => {
+        let (rows, _) = self.device.text_size();
+        let row = self.pop_range(1, rows as _)? as u8 - 1;
+        self.device.set_row(row);
+      }
+      InstrKind::SetColumn => {
+        let (_, columns) = self.device.text_size();
+        let col = self.pop_range(1, columns as _)? as u8 - 1;
+        self.device.set_column(col);
+      }
+      InstrKind::WriteNum { to_file, end } => {
+        let num = self.num_stack.pop().unwrap().1;
+        do_write!(
+          to_file,
+          end,
+          file => {
+            write_file!(file, num.to_string().as_bytes());
+          },
+          {
+            self
+              .print_buffer
+              .print(&mut *self.device, num.to_string().as_bytes());
+          }
+        );
+      }
+      InstrKind::WriteStr { to_file, end } => {
+        let mut str = self.str_stack.pop().unwrap().1;
+        if to_file {
+          if let Some(loss) = WriteLoss::of(&str) {
+            if !self.lossy_writes.iter().any(|w| w.location == loc) {
+              self.lossy_writes.push(LossyWrite {
+                location: loc.clone(),
+                loss,
+              });
+            }
+          }
+        }
+        str.push(b'"');
+        str.end_at_null();
+        str.drop_0x1f();
+        do_write!(
+          to_file,
+          end,
+          file => {
+            write_file!(file, b"\"");
+            write_file!(file, &str);
+          },
+          {
+            self.print_buffer.print(&mut *self.device, b"\"");
+            self.print_buffer.print(&mut *self.device, &str);
+          }
+        );
+      }
+      InstrKind::KeyboardInput {
+        has_prompt,
+        fields: num_fields,
+      } => {
+        let prompt = if has_prompt {
+          let mut prompt = self.str_stack.pop().unwrap().1;
+          prompt.end_at_null();
+          prompt.drop_0x1f();
+          Some(prompt)
+        } else {
+          None
+        };
+
+        let mut lvalues = vec![];
+        let mut fields = vec![];
+        for _ in 0..num_fields.get() {
+          let (lval_loc, lvalue) = self.lval_stack.pop().unwrap();
+          match lvalue {
+            LValue::Fn { name, param } => {
+              fields.push(KeyboardInputType::Func {
+                name: self.interner.resolve(name).unwrap().to_owned(),
+                param: self.interner.resolve(param).unwrap().to_owned(),
+              })
+            }
+            _ => match lvalue.get_type(&self.interner) {
+              Type::Integer => fields.push(KeyboardInputType::Integer),
+              Type::Real => fields.push(KeyboardInputType::Real),
+              Type::String => fields.push(KeyboardInputType::String),
+            },
+          }
+          lvalues.push((lval_loc, lvalue));
+        }
+
+        if let Some(prompt) = &prompt {
+          self.device.print(prompt);
+        } else {
+          self.device.print(b"?");
+        }
+        self.device.flush();
+
+        let skip_first;
+        if matches!(self.device.key(), Some(c) if c == KeyCode::Enter as u8)
+          && !matches!(fields.last(), Some(KeyboardInputType::Func { .. }))
+        {
+          fields.pop();
+          skip_first = true;
+        } else {
+          skip_first = false;
+        }
+
+        fields.reverse();
+        lvalues.reverse();
+        let mut defaults = match self.input_history.get(&self.pc) {
+          Some(values) => values.clone(),
+          None => vec![None; lvalues.len()],
+        };
+        if skip_first {
+          defaults.remove(0);
+        }
+        self.state.input(
+          lvalues,
+          skip_first,
+          prompt.map(|s| s.to_string_lossy(self.emoji_version)),
+          fields,
+          defaults,
+        )?;
+      }
+      InstrKind::FileInput { fields: num_fields } => {
+        let filenum = self.get_filenum(true)?;
+        let int_overflow = self.int_overflow();
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        let file = if let FileMode::Input = file.mode {
+          &mut file.handle
+        } else {
+          self.state.error(
+            loc,
+            format!(
+              "INPUT 语句只能用于以 INPUT 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        };
+
+        let max_string_len = self.device.max_string_len();
+        let offset = self.lval_stack.len() - num_fields.get();
+        for (lval_loc, lvalue) in self.lval_stack.drain(offset..) {
+          exec_file_input(
+            &mut self.state,
+            &mut self.bindings,
+            &self.interner,
+            self.emoji_version,
+            int_overflow,
+            max_string_len,
+            lval_loc,
+            lvalue,
+            file,
+          )?;
+        }
+      }
+      InstrKind::ReadData => self.exec_read(loc)?,
+      InstrKind::OpenFile { mode, has_len } => {
+        self.exec_open(loc, mode, has_len)?
+      }
+      InstrKind::Beep => {
+        self.device.beep();
+      }
+      InstrKind::DrawBox { has_fill, has_mode } => {
+        let mode = self.calc_draw_mode(has_mode)?;
+        let fill = if has_fill {
+          self.pop_u8(false)? & 1 != 0
+        } else {
+          false
+        };
+        let y2 = self.pop_u8(false)?;
+        let x2 = self.pop_u8(false)?;
+        let y1 = self.pop_u8(false)?;
+        let x1 = self.pop_u8(false)?;
+        self.device.draw_box((x1, y1), (x2, y2), fill, mode);
+      }
+      InstrKind::Call => {
+        let addr = self.pop_range(-65535, 65535)? as _;
+        if self.read_only {
+          self.state.error(loc, "只读模式下不能执行 CALL 语句")?;
+        }
+        match self.device.exec_asm(steps, AsmExecState::Start(addr)) {
+          Ok(Some(state)) => self.state.suspend_asm(loc, state)?,
+          Ok(None) => {
+            // do nothing
+          }
+          Err(msg) => self.state.error(loc, msg)?,
+        }
+      }
+      InstrKind::DrawCircle { has_fill, has_mode } => {
+        let mode = self.calc_draw_mode(has_mode)?;
+        let fill = if has_fill {
+          self.pop_u8(false)? & 1 != 0
+        } else {
+          false
+        };
+        let r = self.pop_u8(false)?;
+        let y = self.pop_u8(false)?;
+        let x = self.pop_u8(false)?;
+        self.device.draw_circle((x, y), r, fill, mode);
+      }
+      InstrKind::Clear => {
+        self.reset(loc.clone(), false)?;
+        self.events.push(VmEvent::Cleared { location: loc });
+      }
+      InstrKind::CloseFile => {
+        let filenum = self.get_filenum(true)?;
+        self.state.io(
+          loc,
+          "关闭文件",
+          self.files[filenum as usize].handle.close(),
+        )?;
+      }
+      InstrKind::Cls => {
+        self.device.cls();
+      }
+      InstrKind::NoOp => {
+        // do nothing
+      }
+      InstrKind::DrawPoint { has_mode } => {
+        let mode = self.calc_draw_mode(has_mode)?;
+        let y = self.pop_u8(false)?;
+        let x = self.pop_u8(false)?;
+        self.device.draw_point((x, y), mode);
+      }
+      InstrKind::DrawEllipse { has_fill, has_mode } => {
+        let mode = self.calc_draw_mode(has_mode)?;
+        let fill = if has_fill {
+          self.pop_u8(false)? & 1 != 0
+        } else {
+          false
+        };
+        let ry = self.pop_u8(false)?;
+        let rx = self.pop_u8(false)?;
+        let y = self.pop_u8(false)?;
+        let x = self.pop_u8(false)?;
+        self.device.draw_ellipse((x, y), (rx, ry), fill, mode);
+      }
+      InstrKind::End => {
+        self.state.end()?;
+      }
+      InstrKind::ReadRecord => {
+        do_get_put!("GET", record_len, fields, file => {
+          let mut buf = vec![0; record_len as _];
+          let read_len =
+            self
+              .state
+              .io(loc.clone(), "读取文件", file.read(&mut buf))?;
+          if read_len == 0 {
+            self.state.error(loc, "不能在文件末尾读取记录")?;
+          }
+          if read_len < record_len as usize {
+            self.state.error(loc, "文件大小不是记录长度的整数倍")?;
+          }
+
+          let mut offset = 0;
+          for field in fields {
+            self.bindings.store_value(
+              field.lvalue.clone(),
+              Value::String(
+                buf[offset..offset + field.len as usize].to_owned().into(),
+              ),
+            );
+            offset += field.len as usize;
+          }
+        });
+      }
+      InstrKind::WriteRecord => {
+        do_get_put!("PUT", record_len, fields, file => {
+          let mut buf = vec![0u8; record_len as _];
+          let mut offset = 0;
+          for field in fields {
+            let str = self
+              .bindings
+              .load_value(&self.interner, field.lvalue.clone())
+              .unwrap_string();
+            if str.len() == field.len as usize {
+              buf[offset..offset + field.len as usize].clone_from_slice(&str);
+            }
+            offset += field.len as usize;
+          }
+
+          self.state.io(loc, "写入文件", file.write(&buf))?;
+        });
+      }
+      InstrKind::AssignInt => {
+        let (_, lvalue) = self.lval_stack.pop().unwrap();
+        let num = self.num_stack.pop().unwrap();
+        self.store_int(lvalue, num)?;
+      }
+      InstrKind::AssignReal => {
+        let (_, lvalue) = self.lval_stack.pop().unwrap();
+        let num = self.num_stack.pop().unwrap().1;
+        self.store_real(lvalue, num)?;
+      }
+      InstrKind::AssignStr => {
+        let (_, lvalue) = self.lval_stack.pop().unwrap();
+        let str = self.str_stack.pop().unwrap().1;
+        self.bindings.store_value(lvalue, Value::String(str));
+      }
+      InstrKind::DrawLine { has_mode } => {
+        let mode = self.calc_draw_mode(has_mode)?;
+        let y2 = self.pop_u8(false)?;
+        let x2 = self.pop_u8(false)?;
+        let y1 = self.pop_u8(false)?;
+        let x1 = self.pop_u8(false)?;
+        self.device.draw_line((x1, y1), (x2, y2), mode);
+      }
+      InstrKind::AlignedAssign(align) => self.exec_set(loc, align)?,
+      InstrKind::SetTrace(_) => {
+        // do nothing
+      }
+      InstrKind::SetScreenMode(mode) => {
+        self.device.set_screen_mode(mode);
+      }
+      InstrKind::PlayNotes => {
+        let value = self.str_stack.pop().unwrap().1;
+        if let Err(err) = parse_notes(&value) {
+          let message = format!(
+            "音符字符串的第 {} 个字节有误：{}",
+            err.offset + 1,
+            err.kind
+          );
+          match self.invalid_notes() {
+            InvalidNotes::Error => self.state.error(loc, message)?,
+            InvalidNotes::Warning => {
+              self.pending_warning = Some((loc, message));
+            }
+            InvalidNotes::Ignore => {}
+          }
+        }
+        self.device.play_notes(&value);
+      }
+      InstrKind::Poke => {
+        let byte = self.pop_u8(false)?;
+        let addr = self.pop_range(-65535, 65535)? as _;
+        self.write_byte(addr, byte);
+      }
+      InstrKind::Sound => {
+        let duration = self.pop_range(0, 65535)?;
+        let frequency = self.pop_range(37, 32767)?;
+        self
+          .device
+          .sound(frequency as u16, Duration::from_millis(duration as u64));
+      }
+      InstrKind::Swap => {
+        let lvalue2 = self.lval_stack.pop().unwrap().1;
+        let lvalue1 = self.lval_stack.pop().unwrap().1;
+        let value1 = self.bindings.load_value(&self.interner, lvalue1.clone());
+        let value2 = self.bindings.load_value(&self.interner, lvalue2.clone());
+        self.bindings.store_value(lvalue2, value1);
+        self.bindings.store_value(lvalue1, value2);
+      }
+      InstrKind::Restart => {
+        self.device.set_screen_mode(ScreenMode::Text);
+        self.device.cls();
+        self.reset(loc.clone(), true)?;
+        self.events.push(VmEvent::Restarted { location: loc });
+        return Ok(());
+      }
+      InstrKind::SetPrintMode(mode) => {
+        self.device.set_print_mode(mode);
+      }
+      InstrKind::Wend => {
+        let mut found = None;
+        while let Some(record) = self.control_stack.pop() {
+          if let ControlRecord::WhileLoop { addr } = record {
+            found = Some(addr);
+            break;
+          }
+        }
+
+        if let Some(addr) = found {
+          self.pc = addr.0;
+        } else {
+          self.state.error(loc, "WEND 语句找不到匹配的 WHILE 语句")?;
+        }
+
+        return Ok(());
+      }
+      InstrKind::WhileLoop { start, end } => {
+        let value = self.num_stack.pop().unwrap().1;
+        if value.is_zero() {
+          self.pc = end.0;
+        } else {
+          self
+            .control_stack
+            .push(ControlRecord::WhileLoop { addr: start });
+          self.pc += 1;
+        }
+
+        return Ok(());
+      }
+      InstrKind::Sleep => {
+        let value = self.num_stack.pop().unwrap().1;
+        if value.is_positive() {
+          let ns = (self.device.sleep_unit().as_nanos() as f64
+            * f64::from(value)) as u64;
+          let duration = self.device.sleep(Duration::from_nanos(ns));
+          if self.speed_mode != SpeedMode::Fast {
+            self.pc += 1;
+            self.state.sleep(duration)?;
+          }
+        }
+      }
+      InstrKind::SetTimer(handler) => {
+        let interval = self.pop_range(1, 65535)? as u32;
+        let enabled = self.timer.as_ref().is_some_and(|t| t.enabled);
+        self.timer = Some(Timer {
+          interval,
+          handler,
+          enabled,
+          elapsed: 0,
+          handler_depth: None,
+        });
+      }
+      InstrKind::EnableTimer(enabled) => {
+        if let Some(timer) = &mut self.timer {
+          timer.enabled = enabled;
+          timer.elapsed = 0;
+        } else if enabled {
+          self.state.error(
+            loc,
+            "之前没有执行过 ON TIMER 语句，TIMER ON 语句无法执行",
+          )?;
+        }
+      }
+      InstrKind::Fputc => {
+        let (value_loc, value) = self.str_stack.pop().unwrap();
+        if value.is_empty() {
+          self
+            .state
+            .error(value_loc, "FPUTC 语句的数据参数不能为空字符串")?;
+        }
+
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          if self.read_only {
+            self.state.error(loc, "只读模式下不能写入文件")?;
+          }
+          self
+            .state
+            .io(loc, "写入文件", file.handle.write(&value[..1]))?;
+        } else {
+          self.state.error(
+            loc,
+            format!(
+              "FPUTC 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+      }
+      InstrKind::Fread => {
+        let size = self.pop_range(1, 65535)? as u16;
+        let mut addr = self.pop_range(0, 65535)? as u16;
+
+        if addr.checked_add(size - 1).is_none() {
+          self
+            .state
+            .error(loc, "试图写入内存的数据超出了内存的地址范围")?;
+        }
+
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          let mut buf = vec![0; size as usize];
+          let read_len = self.state.io(
+            loc.clone(),
+            "读取文件",
+            file.handle.read(&mut buf),
+          )?;
+          if read_len < size as usize {
+            self.state.error(loc, "文件中没有足够的数据可供读取")?;
+          }
+          for b in buf {
+            self.write_byte(addr, b);
+            addr += 1;
+          }
+        } else {
+          self.state.error(
+            loc,
+            format!(
+              "FREAD 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+      }
+      InstrKind::Fwrite => {
+        let size = self.pop_range(1, 65535)? as u16;
+        let addr = self.pop_range(0, 65535)? as u16;
+
+        if addr.checked_add(size - 1).is_none() {
+          self
+            .state
+            .error(loc, "试图从内存读取的数据超出了内存的地址范围")?;
+        }
+
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          if self.read_only {
+            self.state.error(loc, "只读模式下不能写入文件")?;
+          }
+          let mut buf = vec![0; size as usize];
+          for i in 0..size {
+            buf[i as usize] = self.device.read_byte(addr + i);
+          }
+          self.state.io(loc, "写入文件", file.handle.write(&buf))?;
+        } else {
+          self.state.error(
+            loc,
+            format!(
+              "FWRITE 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+      }
+      InstrKind::Fseek => {
+        let offset = self.pop_range(0, 65535)? as u16;
+
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          self.state.io(
+            loc,
+            "设置文件指针",
+            file.handle.seek(offset as u64),
+          )?;
+        } else {
+          self.state.error(
+            loc,
+            format!(
+              "FSEEK 语句只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                  但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+      }
+      InstrKind::Debug => {
+        let (_, value) = self.str_stack.pop().unwrap();
+        println!(
+          "DEBUG: line {}: {}",
+          loc.line + 1,
+          value.to_string_lossy(self.emoji_version)
+        );
+      }
+    }
+    self.pc += 1;
+    Ok(())
+  }
+}
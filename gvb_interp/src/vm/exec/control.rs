@@ -0,0 +1,181 @@
+use crate::device::Device;
+use crate::util::mbf5::{Mbf5, RealError};
+use crate::vm::{
+  Addr, ArithFaultKind, ArithOp, ControlRecord, ForLoopRecord, LValue,
+  Location, LoopContext, LoopKind, Result, Symbol, VirtualMachine,
+};
+
+impl<'d, D> VirtualMachine<'d, D>
+where
+  D: Device,
+{
+  /// Returns the innermost FOR or WHILE loop on the control stack.
+  pub(super) fn loop_context(&self) -> Option<LoopContext> {
+    let (kind, addr) =
+      self
+        .control_stack
+        .iter()
+        .rev()
+        .find_map(|record| match record {
+          ControlRecord::ForLoop(ForLoopRecord { addr, .. }) => {
+            Some((LoopKind::For, *addr))
+          }
+          ControlRecord::WhileLoop { addr } => Some((LoopKind::While, *addr)),
+          ControlRecord::Sub { .. } => None,
+        })?;
+    let location = self.source_map.stmt_location(addr.0)?.clone();
+    Some(LoopContext { kind, location })
+  }
+
+  pub(super) fn exec_for(
+    &mut self,
+    _loc: Location,
+    name: Symbol,
+    has_step: bool,
+  ) -> Result<()> {
+    let step = if has_step {
+      self.num_stack.pop().unwrap().1
+    } else {
+      Mbf5::ONE
+    };
+    let end = self.num_stack.pop().unwrap().1;
+    let start = self.num_stack.pop().unwrap().1;
+
+    let mut prev_loop = None;
+    for (i, item) in self.control_stack.iter().enumerate().rev() {
+      if let ControlRecord::ForLoop(ForLoopRecord { var: prev_var, .. }) = item
+      {
+        if name == *prev_var {
+          prev_loop = Some(i);
+          break;
+        }
+      }
+    }
+    if let Some(i) = prev_loop {
+      self.control_stack.truncate(i);
+    }
+
+    self
+      .control_stack
+      .push(ControlRecord::ForLoop(ForLoopRecord {
+        addr: Addr(self.pc),
+        var: name,
+        target: end,
+        step,
+      }));
+
+    self.store_real(LValue::Var { name }, start)?;
+
+    Ok(())
+  }
+
+  pub(super) fn exec_next(
+    &mut self,
+    loc: Location,
+    name: Option<Symbol>,
+  ) -> Result<()> {
+    let mut found = None;
+    if let Some(name) = name {
+      while let Some(record) = self.control_stack.pop() {
+        if let ControlRecord::ForLoop(record) = record {
+          if record.var == name {
+            found = Some(record);
+            break;
+          }
+        }
+      }
+    } else {
+      while let Some(record) = self.control_stack.pop() {
+        if let ControlRecord::ForLoop(record) = record {
+          found = Some(record);
+          break;
+        }
+      }
+    }
+
+    if let Some(record) = found {
+      let value = self
+        .bindings
+        .load_value(&self.interner, LValue::Var { name: record.var })
+        .unwrap_real();
+      let loc = self.code[record.addr.0].loc.clone();
+      let new_value = match value + record.step {
+        Ok(new_value) => new_value,
+        Err(RealError::Infinite) => {
+          self.arith_fault(
+            loc,
+            ArithOp::ForStep,
+            ArithFaultKind::Overflow,
+            vec![value, record.step],
+            "计数器数值过大，超出了实数的表示范围。",
+          )?;
+        }
+        Err(_) => unreachable!(),
+      };
+
+      self.store_real(LValue::Var { name: record.var }, new_value)?;
+
+      let end_loop = if record.step.is_positive() {
+        new_value > record.target
+      } else if record.step.is_negative() {
+        new_value < record.target
+      } else {
+        new_value == record.target
+      };
+
+      if end_loop {
+        self.pc += 1;
+      } else {
+        self.pc = record.addr.0 + 1;
+        self.control_stack.push(ControlRecord::ForLoop(record));
+      }
+    } else {
+      self.state.error(loc, "NEXT 语句找不到匹配的 FOR 语句")?;
+    }
+
+    Ok(())
+  }
+
+  /// Counts an executed instruction, and calls the timer handler if the timer
+  /// is due and `pc` is at the start of a statement. The handler is not
+  /// called again until it returns.
+  pub(super) fn tick_timer(&mut self) {
+    let timer = match &mut self.timer {
+      Some(timer) => timer,
+      None => return,
+    };
+    if let Some(depth) = timer.handler_depth {
+      // The time is not counted while the handler is running, so that the
+      // program proceeds even if the handler takes longer than the interval.
+      if self.control_stack.len() <= depth {
+        timer.handler_depth = None;
+        timer.elapsed = 0;
+      }
+      return;
+    }
+    if !timer.enabled {
+      return;
+    }
+    timer.elapsed = timer.elapsed.saturating_add(1);
+    if timer.elapsed < timer.interval {
+      return;
+    }
+
+    if !self.source_map.is_stmt_start(self.pc)
+      || !self.num_stack.is_empty()
+      || !self.str_stack.is_empty()
+      || !self.lval_stack.is_empty()
+      || !self.fn_call_stack.is_empty()
+    {
+      return;
+    }
+
+    let timer = self.timer.as_mut().unwrap();
+    timer.elapsed = 0;
+    timer.handler_depth = Some(self.control_stack.len());
+    self.control_stack.push(ControlRecord::Sub {
+      next_addr: Addr(self.pc),
+    });
+    self.pc = timer.handler.0;
+  }
+}
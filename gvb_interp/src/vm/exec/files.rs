@@ -0,0 +1,320 @@
+use bstr::{ByteSlice, ByteVec};
+use string_interner::StringInterner;
+
+use crate::ast;
+use crate::device::{Device, FileHandle};
+use crate::machine::{EmojiVersion, IntOverflow};
+use crate::vm::coerce::{check_string_len, parse_num_value};
+use crate::vm::{
+  Alignment, Bindings, ByteString, ExecState, FileMode, LValue, Location,
+  RecordField, Result, Type, Value, VirtualMachine,
+};
+
+impl<'d, D> VirtualMachine<'d, D>
+where
+  D: Device,
+{
+  pub(super) fn exec_open(
+    &mut self,
+    loc: Location,
+    mode: ast::FileMode,
+    has_len: bool,
+  ) -> Result<()> {
+    let len = if has_len {
+      let mut len = self.pop_u8(false)?;
+      if len == 0 || len > 128 {
+        len = 32;
+      }
+      len
+    } else {
+      32
+    };
+
+    let filenum = self.get_filenum(true)?;
+    let (name_loc, mut filename) = self.str_stack.pop().unwrap();
+    filename.end_at_null();
+    filename.drop_0x1f();
+
+    if self.files[filenum as usize].handle.is_open() {
+      self
+        .state
+        .error(loc, format!("重复打开 {} 号文件", filenum + 1))?;
+    }
+
+    if filename.is_empty() {
+      self.state.error(name_loc, "文件名不能为空")?;
+    } else if let Some(i) = filename.find_byteset(b"/\\") {
+      self.state.error(
+        name_loc,
+        format!("文件名中不能包含\"{}\"字符", filename[i] as char),
+      )?;
+    }
+
+    if !filename.to_ascii_uppercase().ends_with(b".DAT") {
+      filename.push_str(b".DAT");
+    }
+
+    let (mode, read, write, truncate) = match mode {
+      ast::FileMode::Input => (FileMode::Input, true, false, false),
+      ast::FileMode::Output => (FileMode::Output, false, true, true),
+      ast::FileMode::Append => (FileMode::Append, false, true, false),
+      ast::FileMode::Random => (
+        FileMode::Random {
+          record_len: len,
+          fields: vec![],
+        },
+        true,
+        true,
+        false,
+      ),
+      ast::FileMode::Binary => (FileMode::Binary, true, !self.read_only, false),
+      ast::FileMode::Error => unreachable!(),
+    };
+
+    if self.read_only && write {
+      self
+        .state
+        .error(loc, format!("只读模式下不能以 {mode} 模式打开文件"))?;
+    }
+
+    let file = &mut self.files[filenum as usize];
+    match self.device.open_file(
+      &mut file.handle,
+      &filename,
+      read,
+      write,
+      truncate,
+    ) {
+      Ok(()) => {
+        if let FileMode::Append = &mode {
+          let result = match file.handle.len() {
+            Ok(len) => {
+              file.handle.seek(len).map_err(|err| ("设置文件指针", err))
+            }
+            Err(err) => Err(("获取文件大小", err)),
+          };
+          if let Err((op, err)) = result {
+            // the file is not usable without a mode
+            let _ = file.handle.close();
+            self.state.io(loc, op, Err(err))?;
+          }
+        }
+
+        file.mode = mode;
+      }
+      Err(err) => {
+        if matches!(&mode, FileMode::Binary) {
+          return Ok(());
+        } else {
+          self.state.io(loc, "打开文件", Err(err))?;
+        }
+      }
+    };
+
+    Ok(())
+  }
+
+  pub(super) fn exec_field(
+    &mut self,
+    loc: Location,
+    num_fields: usize,
+  ) -> Result<()> {
+    let filenum = self.get_filenum(true)?;
+    let record_len;
+    let file = &self.files[filenum as usize];
+    if !file.handle.is_open() {
+      self.state.error(loc, "未打开文件")?;
+    }
+    if let FileMode::Random {
+      record_len: len, ..
+    } = &file.mode
+    {
+      record_len = *len as u32;
+    } else {
+      self.state.error(
+          loc,
+          format!(
+            "FIELD 语句只能用于以 RANDOM 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
+            filenum + 1,
+            file.mode
+          )
+        )?;
+    }
+
+    let mut fields = vec![];
+    let mut total_len = 0u32;
+    for _ in 0..num_fields {
+      let lvalue = self.lval_stack.pop().unwrap().1;
+      let len =
+        self.pop_range(0, self.device.max_string_len().min(255) as _)? as u8;
+      self
+        .bindings
+        .store_value(lvalue.clone(), Value::String(vec![0u8; len as _].into()));
+      fields.push(RecordField { len, lvalue });
+      total_len += len as u32;
+    }
+    fields.reverse();
+
+    if total_len > record_len {
+      self.state.error(
+        loc,
+        format!(
+          "FIELD 语句定义的字段总长度 {total_len} 超出了打开文件时所指定的记录长度 {record_len}"
+        ),
+      )?;
+    }
+
+    match &mut self.files[filenum as usize].mode {
+      FileMode::Random { fields: f, .. } => {
+        *f = fields;
+      }
+      _ => unreachable!(),
+    }
+
+    Ok(())
+  }
+
+  pub(super) fn exec_set(
+    &mut self,
+    _loc: Location,
+    align: Alignment,
+  ) -> Result<()> {
+    let mut value = self.str_stack.pop().unwrap().1;
+    let lvalue = self.lval_stack.pop().unwrap().1;
+
+    let mut dest = self
+      .bindings
+      .load_value(&self.interner, lvalue.clone())
+      .unwrap_string();
+    if value.len() > dest.len() {
+      value.truncate(dest.len());
+      dest = value;
+    } else {
+      match align {
+        Alignment::Left => {
+          dest[..value.len()].clone_from_slice(&value);
+        }
+        Alignment::Right => {
+          let padding = dest.len() - value.len();
+          dest[padding..].clone_from_slice(&value);
+          dest[..padding].fill(b' ');
+        }
+      }
+    }
+    self.bindings.store_value(lvalue, Value::String(dest));
+
+    Ok(())
+  }
+
+  /// Returns [0, 2].
+  pub(super) fn get_filenum(&mut self, pop: bool) -> Result<u8> {
+    let (loc, value) = if pop {
+      self.num_stack.pop().unwrap()
+    } else {
+      self.num_stack.last().cloned().unwrap()
+    };
+    let int = f64::from(value) as i64;
+    if (1..=3).contains(&int) {
+      Ok(int as u8 - 1)
+    } else {
+      self.state.error(loc, "文件号超出范围 1~3")?
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn exec_file_input<F: FileHandle, S>(
+  state: &mut ExecState<S>,
+  bindings: &mut Bindings,
+  interner: &StringInterner,
+  emoji_version: EmojiVersion,
+  int_overflow: IntOverflow,
+  max_string_len: usize,
+  loc: Location,
+  lvalue: LValue,
+  file: &mut F,
+) -> Result<()> {
+  let mut buf = vec![];
+  let mut quoted = false;
+  'read_file: {
+    let mut byte = [0];
+    let len = state.io(loc.clone(), "读取文件", file.read(&mut byte))?;
+    if len == 0 {
+      break 'read_file;
+    }
+
+    if byte[0] == b'"' {
+      quoted = true;
+    } else if byte[0] == 0xff || byte[0] == b',' {
+      break 'read_file;
+    } else {
+      buf.push(byte[0]);
+    }
+
+    let mut str_end = false;
+    loop {
+      let mut byte = [0];
+      let len = state.io(loc.clone(), "读取文件", file.read(&mut byte))?;
+      if len == 0 {
+        if quoted && !str_end {
+          state.error(loc, "读取字符串时遇到未匹配的双引号")?
+        }
+        break;
+      }
+      if quoted {
+        if str_end {
+          if byte[0] == 0xff || byte[0] == b',' {
+            break;
+          } else {
+            state.error(
+              loc,
+              format!(
+                "读取到的数据：\"{}\"，没有以逗号或 U+00FF 字符结尾",
+                ByteString::from(buf).to_string_lossy(emoji_version)
+              ),
+            )?
+          }
+        } else if byte[0] == b'"' {
+          str_end = true;
+          continue;
+        }
+      } else if byte[0] == 0xff || byte[0] == b',' {
+        break;
+      }
+      buf.push(byte[0]);
+    }
+  }
+
+  let value = match lvalue.get_type(interner) {
+    ty @ (Type::Integer | Type::Real) => {
+      if quoted {
+        state.error(
+          loc,
+          format!(
+            "读取到的数据：\"{}\"，是用引号括起来的字符串，无法转换为数值",
+            ByteString::from(buf).to_string_lossy(emoji_version)
+          ),
+        )?
+      }
+
+      match parse_num_value(&buf, ty, int_overflow) {
+        Ok(value) => value,
+        Err(err) => {
+          let data = ByteString::from(buf).to_string_lossy(emoji_version);
+          state.error(loc, err.read_message(data))?
+        }
+      }
+    }
+    Type::String => {
+      if let Err(err) = check_string_len(&buf, max_string_len) {
+        let data = ByteString::from(buf).to_string_lossy(emoji_version);
+        state.error(loc, err.read_message(data))?
+      }
+      Value::String(buf.into())
+    }
+  };
+
+  bindings.store_value(lvalue, value);
+
+  Ok(())
+}
@@ -0,0 +1,464 @@
+use nanorand::{Rng, SeedableRng};
+use std::num::NonZeroUsize;
+
+use crate::ast::SysFuncKind;
+use crate::device::{Device, FileHandle};
+use crate::machine::{EofBehavior, IntOverflow};
+use crate::parser::read_number;
+use crate::util::mbf5::{Mbf5, RealError};
+use crate::vm::coerce::real_to_int;
+use crate::vm::{
+  ArithFaultKind, ArithOp, ByteString, FileMode, Location, Result,
+  VirtualMachine, POLICY_PEEK_ADDR,
+};
+
+impl<'d, D> VirtualMachine<'d, D>
+where
+  D: Device,
+{
+  pub(super) fn exec_sys_func(
+    &mut self,
+    loc: Location,
+    kind: SysFuncKind,
+    arity: NonZeroUsize,
+  ) -> Result<()> {
+    match kind {
+      SysFuncKind::Abs => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, value.abs()));
+        Ok(())
+      }
+      SysFuncKind::Asc => {
+        let (arg_loc, value) = self.str_stack.pop().unwrap();
+        if value.is_empty() {
+          self.state.error(arg_loc, "ASC 函数的参数不能为空字符串")?;
+        }
+        self.num_stack.push((loc, Mbf5::from(value[0])));
+        Ok(())
+      }
+      SysFuncKind::Atn => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, value.atan()));
+        Ok(())
+      }
+      SysFuncKind::Chr => {
+        let value = self.pop_u8(false)?;
+        self.str_stack.push((loc, ByteString::from(vec![value])));
+        Ok(())
+      }
+      SysFuncKind::Cos => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, value.cos()));
+        Ok(())
+      }
+      SysFuncKind::Cvi => {
+        let (arg_loc, value) = self.str_stack.pop().unwrap();
+        if value.len() != 2 {
+          self.state.error(
+            arg_loc,
+            format!(
+              "CVI$ 函数的参数字符串长度不等于 2。参数字符串长度为：{}",
+              value.len()
+            ),
+          )?;
+        }
+        let lo = value[0] as u16;
+        let hi = value[1] as u16;
+        self
+          .num_stack
+          .push((loc, Mbf5::from((lo + (hi << 8)) as i16)));
+        Ok(())
+      }
+      SysFuncKind::Cvs => {
+        let (arg_loc, value) = self.str_stack.pop().unwrap();
+        if value.len() != 5 {
+          self.state.error(
+            arg_loc,
+            format!(
+              "CVS$ 函数的参数字符串长度不等于 5。参数字符串长度为：{}",
+              value.len()
+            ),
+          )?;
+        }
+        self.num_stack.push((
+          loc,
+          Mbf5::from([value[0], value[1], value[2], value[3], value[4]]),
+        ));
+        Ok(())
+      }
+      SysFuncKind::Eof => {
+        let filenum = self.get_filenum(true)?;
+        let file = &self.files[filenum as usize];
+        match file.mode {
+          FileMode::Input => {
+            let len =
+              self
+                .state
+                .io(loc.clone(), "获取文件大小", file.handle.len())?;
+            let pos =
+              self
+                .state
+                .io(loc.clone(), "获取文件指针", file.handle.pos())?;
+            let mut eof_reached = pos >= len;
+            if self.device.eof_behavior() == EofBehavior::Inverse {
+              eof_reached = !eof_reached;
+            }
+            self.num_stack.push((loc, Mbf5::from(eof_reached)));
+            Ok(())
+          }
+          FileMode::None => {
+            self.state.error(loc, "未打开文件")?;
+          }
+          _ => {
+            self.state.error(
+              loc,
+              format!(
+                "EOF 函数只能用于以 INPUT 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
+                filenum + 1,
+                file.mode
+              ))?;
+          }
+        }
+      }
+      SysFuncKind::Exp => {
+        let value = self.num_stack.pop().unwrap().1;
+        match value.exp() {
+          Ok(value) => {
+            self.num_stack.push((loc, value));
+            Ok(())
+          }
+          Err(RealError::Infinite) => self.arith_fault(
+            loc,
+            ArithOp::Exp,
+            ArithFaultKind::Overflow,
+            vec![value],
+            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
+          )?,
+          Err(RealError::Nan) => unreachable!(),
+        }
+      }
+      SysFuncKind::Int => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, value.floor()));
+        Ok(())
+      }
+      SysFuncKind::Left => {
+        let len = self.pop_string_len(true)?;
+        let value = self.str_stack.pop().unwrap().1;
+        let len = len.min(value.len());
+        self
+          .str_stack
+          .push((loc, ByteString::from(value[..len].to_vec())));
+        Ok(())
+      }
+      SysFuncKind::Len => {
+        let value = self.str_stack.pop().unwrap().1;
+        self.num_stack.push((loc, Mbf5::from(value.len() as u32)));
+        Ok(())
+      }
+      SysFuncKind::Lof => {
+        let filenum = self.get_filenum(true)?;
+        let file = &self.files[filenum as usize];
+        match file.mode {
+          FileMode::Random { .. } => {
+            let len =
+              self
+                .state
+                .io(loc.clone(), "获取文件大小", file.handle.len())?;
+            self.num_stack.push((loc, Mbf5::from(len)));
+            Ok(())
+          }
+          FileMode::None => {
+            self.state.error(loc, "未打开文件")?;
+          }
+          _ => {
+            self.state.error(
+              loc,
+              format!(
+                "LOF 函数只能用于以 RANDOM 模式打开的文件，但 {} 号文件是以 {} 模式打开的",
+                filenum + 1,
+                file.mode
+              ))?;
+          }
+        }
+      }
+      SysFuncKind::Log => {
+        let (arg_loc, value) = self.num_stack.pop().unwrap();
+        match value.ln() {
+          Ok(value) => {
+            self.num_stack.push((loc, value));
+            Ok(())
+          }
+          Err(RealError::Infinite) => self.arith_fault(
+            loc,
+            ArithOp::Log,
+            ArithFaultKind::Overflow,
+            vec![value],
+            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
+          )?,
+          Err(RealError::Nan) => self.arith_fault(
+            arg_loc,
+            ArithOp::Log,
+            ArithFaultKind::Domain,
+            vec![value],
+            format!("超出 LOG 函数的定义域。参数值是：{value}"),
+          )?,
+        }
+      }
+      SysFuncKind::Mid => {
+        let len = if arity.get() == 3 {
+          self.pop_string_len(false)?
+        } else {
+          self.device.max_string_len()
+        };
+        let pos = self.pop_string_len(true)? - 1;
+        let value = self.str_stack.pop().unwrap().1;
+        let start = pos.min(value.len());
+        let end = (start + len).min(value.len());
+        self
+          .str_stack
+          .push((loc, ByteString::from(value[start..end].to_vec())));
+        Ok(())
+      }
+      SysFuncKind::Mki => {
+        let value = match self.int_overflow() {
+          IntOverflow::Error => self.pop_range(-32768, 32767)? as i16,
+          overflow => {
+            let value = self.num_stack.pop().unwrap().1;
+            real_to_int(value, overflow).unwrap()
+          }
+        };
+        let lo = (value & 0xff) as u8;
+        let hi = (value >> 8) as u8;
+        self.str_stack.push((loc, ByteString::from(vec![lo, hi])));
+        Ok(())
+      }
+      SysFuncKind::Mks => {
+        let value = self.num_stack.pop().unwrap().1;
+        self
+          .str_stack
+          .push((loc, ByteString::from(<[u8; 5]>::from(value).to_vec())));
+        Ok(())
+      }
+      SysFuncKind::Peek => {
+        let addr = self.pop_range(-65535, 65535)? as _;
+        let byte = if addr == POLICY_PEEK_ADDR {
+          self.read_only as u8
+        } else {
+          self.device.read_byte(addr)
+        };
+        self.num_stack.push((loc, Mbf5::from(byte)));
+        Ok(())
+      }
+      SysFuncKind::Pos => {
+        self.num_stack.pop().unwrap();
+        self
+          .num_stack
+          .push((loc, Mbf5::from(self.device.get_column())));
+        Ok(())
+      }
+      SysFuncKind::Right => {
+        let len = self.pop_string_len(true)?;
+        let value = self.str_stack.pop().unwrap().1;
+        let len = len.min(value.len());
+        self
+          .str_stack
+          .push((loc, ByteString::from(value[value.len() - len..].to_vec())));
+        Ok(())
+      }
+      SysFuncKind::Rnd => {
+        let value = self.num_stack.pop().unwrap().1;
+        if value.is_zero() {
+          self
+            .num_stack
+            .push((loc, u32_to_random_number(self.current_rand)));
+          return Ok(());
+        }
+        if value.is_negative() {
+          let mut seed = [0u8; 8];
+          seed[..5].copy_from_slice(&<[u8; 5]>::from(value));
+          self.rng.reseed(seed);
+        }
+        let value: u32 = self.rng.generate();
+        self.current_rand = value;
+        self.num_stack.push((loc, u32_to_random_number(value)));
+        Ok(())
+      }
+      SysFuncKind::Sgn => {
+        let value = self.num_stack.pop().unwrap().1;
+        let num = if value.is_positive() {
+          Mbf5::ONE
+        } else if value.is_negative() {
+          Mbf5::NEG_ONE
+        } else {
+          Mbf5::ZERO
+        };
+        self.num_stack.push((loc, num));
+        Ok(())
+      }
+      SysFuncKind::Sin => {
+        let value = self.num_stack.pop().unwrap().1;
+        self.num_stack.push((loc, value.sin()));
+        Ok(())
+      }
+      SysFuncKind::Sqr => {
+        let (arg_loc, value) = self.num_stack.pop().unwrap();
+        match value.sqrt() {
+          Ok(value) => {
+            self.num_stack.push((loc, value));
+            Ok(())
+          }
+          Err(RealError::Nan) => self.arith_fault(
+            arg_loc,
+            ArithOp::Sqr,
+            ArithFaultKind::Domain,
+            vec![value],
+            format!("超出 SQR 函数的定义域。参数值是：{value}"),
+          )?,
+          Err(RealError::Infinite) => unreachable!(),
+        }
+      }
+      SysFuncKind::Str => {
+        let value = self.num_stack.pop().unwrap().1;
+        self
+          .str_stack
+          .push((loc, ByteString::from(value.to_string().into_bytes())));
+        Ok(())
+      }
+      SysFuncKind::Tan => {
+        let (arg_loc, value) = self.num_stack.pop().unwrap();
+        match value.tan() {
+          Ok(value) => {
+            self.num_stack.push((loc, value));
+            Ok(())
+          }
+          Err(RealError::Infinite) => self.arith_fault(
+            loc,
+            ArithOp::Tan,
+            ArithFaultKind::Overflow,
+            vec![value],
+            format!("运算结果数值过大，超出实数的表示范围。参数值是：{value}"),
+          )?,
+          Err(RealError::Nan) => self.arith_fault(
+            arg_loc,
+            ArithOp::Tan,
+            ArithFaultKind::Domain,
+            vec![value],
+            format!("超出 TAN 函数的定义域。参数值是：{value}"),
+          )?,
+        }
+      }
+      SysFuncKind::Val => {
+        let mut value = self.str_stack.pop().unwrap().1;
+        value.retain(|&b| b != b' ');
+        let u16_value = value.iter().map(|&c| c as u16).collect::<Vec<_>>();
+        let (len, _) = read_number(&u16_value, false, false);
+        let num = unsafe { std::str::from_utf8_unchecked(&value[..len]) }
+          .parse::<Mbf5>()
+          .unwrap_or(Mbf5::ZERO);
+        self.num_stack.push((loc, num));
+        Ok(())
+      }
+      SysFuncKind::Tab | SysFuncKind::Spc => unreachable!(),
+      SysFuncKind::Point => {
+        let y = self.pop_range(-32768, 32767)?;
+        let x = self.pop_range(-32768, 32767)?;
+        let p = Mbf5::from(self.device.check_point((x, y)));
+        self.num_stack.push((loc, p));
+        Ok(())
+      }
+      SysFuncKind::CheckKey => {
+        let key = self.pop_u8(false)?;
+        let p = Mbf5::from(self.device.check_key(key));
+        self.num_stack.push((loc, p));
+        Ok(())
+      }
+      SysFuncKind::Fopen => {
+        let filenum = self.get_filenum(true)?;
+        self.num_stack.push((
+          loc,
+          Mbf5::from(self.files[filenum as usize].handle.is_open()),
+        ));
+        Ok(())
+      }
+      SysFuncKind::Fgetc => {
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if !matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          self.state.error(
+            loc,
+            format!(
+              "FGETC 函数只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+        let mut buf = [0];
+        let read_len =
+          self
+            .state
+            .io(loc.clone(), "读取文件", file.handle.read(&mut buf))?;
+        if read_len == 0 {
+          self.state.error(loc, "不能在文件末尾读取数据")?;
+        }
+        self.num_stack.push((loc, Mbf5::from(buf[0])));
+        Ok(())
+      }
+      SysFuncKind::Ftell => {
+        let filenum = self.get_filenum(true)?;
+        let file = &mut self.files[filenum as usize];
+        if !file.handle.is_open() {
+          self.state.error(loc, "未打开文件")?;
+        }
+        if !matches!(&file.mode, FileMode::Binary | FileMode::Random { .. }) {
+          self.state.error(
+            loc,
+            format!(
+              "FTELL 函数只能用于以 BINARY 或 RANDOM 模式打开的文件，\
+                但 {} 号文件是以 {} 模式打开的",
+              filenum + 1,
+              file.mode
+            ),
+          )?;
+        }
+        let pos =
+          self
+            .state
+            .io(loc.clone(), "获取文件指针", file.handle.pos())?;
+        self.num_stack.push((loc, Mbf5::from(pos)));
+        Ok(())
+      }
+    }
+  }
+}
+
+fn u32_to_random_number(x: u32) -> Mbf5 {
+  if x == 0 {
+    return Mbf5::ZERO;
+  }
+  let n = x.leading_zeros();
+  let exponent = (0x80 - n) as _;
+  let x = x << n;
+  let mant1 = (x >> 24) as u8 & 0x7f;
+  let mant2 = (x >> 16) as _;
+  let mant3 = (x >> 8) as _;
+  let mant4 = x as _;
+  Mbf5::from([exponent, mant1, mant2, mant3, mant4])
+}
+
+#[test]
+fn test_u32_to_random_number() {
+  assert_eq!(
+    u32_to_random_number(0x61_00_00_00),
+    Mbf5::from([0x7fu8, 0x42, 0, 0, 0])
+  );
+  assert_eq!(
+    u32_to_random_number(0x00_00_00_01),
+    Mbf5::from([0x61u8, 0, 0, 0, 0])
+  );
+}
@@ -0,0 +1,66 @@
+use super::Location;
+use crate::util::mbf5::Mbf5;
+
+/// Operation which causes an arithmetic fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Pow,
+  Exp,
+  Log,
+  Sqr,
+  Tan,
+  /// Incrementing the counter of FOR loop in NEXT statement.
+  ForStep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithFaultKind {
+  /// The result is too large to be represented.
+  Overflow,
+  /// The operands are out of the domain of the operation.
+  Domain,
+  DivisionByZero,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithFault {
+  pub location: Location,
+  pub op: ArithOp,
+  pub kind: ArithFaultKind,
+  /// Operands in the order they appear in the source code. For
+  /// `ArithOp::ForStep`, the operands are the counter value and the step.
+  pub operands: Vec<Mbf5>,
+  /// Whether the fault terminated the program.
+  pub fatal: bool,
+}
+
+/// Number of arithmetic faults raised since the program is started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArithFaultStats {
+  pub overflow: usize,
+  pub domain: usize,
+  pub division_by_zero: usize,
+  /// Number of faults which terminated the program.
+  pub fatal: usize,
+}
+
+impl ArithFaultStats {
+  pub fn total(&self) -> usize {
+    self.overflow + self.domain + self.division_by_zero
+  }
+
+  pub(super) fn record(&mut self, fault: &ArithFault) {
+    match fault.kind {
+      ArithFaultKind::Overflow => self.overflow += 1,
+      ArithFaultKind::Domain => self.domain += 1,
+      ArithFaultKind::DivisionByZero => self.division_by_zero += 1,
+    }
+    if fault.fatal {
+      self.fatal += 1;
+    }
+  }
+}
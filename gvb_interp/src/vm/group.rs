@@ -0,0 +1,490 @@
+//! Running several VMs side by side with the same input, e.g. to compare the
+//! behavior of a program across machine profiles, or across versions of the
+//! interpreter during refactoring.
+
+use std::collections::BTreeMap;
+use widestring::Utf16String;
+
+use super::{
+  Binding, ExecInput, ExecResult, KeyboardInput, KeyboardInputType, Value,
+  VirtualMachine,
+};
+use crate::conformance::{Capture, Event, TracingDevice};
+use crate::device::Device;
+use crate::util::mbf5::Mbf5;
+
+/// Number of instructions executed by each VM in a turn.
+const SLICE: usize = 1000;
+
+/// Input shared by the VMs of a group, consumed in order whenever all VMs
+/// wait for input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupInput {
+  /// A key for INKEY$.
+  Key(u8),
+  /// Values of the fields of an INPUT statement, converted to the type of
+  /// each field. Invalid numbers are converted to 0.
+  Values(Vec<String>),
+}
+
+/// The first point where a VM behaves differently from the first VM of the
+/// group, which is the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+  /// Index of the diverging VM.
+  pub member: usize,
+  pub kind: DivergenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+  /// The `index`-th device call differs. Consecutive prints are merged
+  /// before comparison. None if the VM has no such call.
+  Event {
+    index: usize,
+    expected: Option<Event>,
+    actual: Option<Event>,
+  },
+  /// A variable differs when the VMs wait for input or end. Arrays are
+  /// compared by their dimensions. None if the variable does not exist.
+  Var {
+    name: String,
+    expected: Option<String>,
+    actual: Option<String>,
+  },
+  /// The VMs stop for different reasons, e.g. one waits for a key and the
+  /// other ends.
+  Result {
+    expected: ExecResult,
+    actual: ExecResult,
+  },
+}
+
+/// Result of running a group.
+#[derive(Debug, Clone)]
+pub struct GroupReport {
+  /// The last result returned by each VM.
+  pub results: Vec<ExecResult>,
+  /// The first divergence of each diverging VM, in the order they are
+  /// found.
+  pub divergences: Vec<Divergence>,
+  /// Number of shared inputs consumed.
+  pub inputs_consumed: usize,
+}
+
+struct Member<'d, D: Device> {
+  name: String,
+  vm: VirtualMachine<'d, TracingDevice<D>>,
+  steps: usize,
+  /// Fields of the INPUT statement the VM waits for.
+  fields: Vec<KeyboardInputType>,
+}
+
+/// VMs running in lockstep. Each VM runs until it waits for input or stops,
+/// then the VMs are compared, and the next shared input is fed to all of
+/// them.
+pub struct VmGroup<'d, D: Device> {
+  members: Vec<Member<'d, D>>,
+}
+
+impl<'d, D> Default for VmGroup<'d, D>
+where
+  D: Device,
+  D::AsmError: ToString,
+{
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<'d, D> VmGroup<'d, D>
+where
+  D: Device,
+  D::AsmError: ToString,
+{
+  pub fn new() -> Self {
+    Self { members: vec![] }
+  }
+
+  /// Adds a VM, which is started by the group. The first VM added is the
+  /// reference of comparisons.
+  pub fn add(
+    &mut self,
+    name: impl Into<String>,
+    vm: VirtualMachine<'d, TracingDevice<D>>,
+  ) {
+    self.members.push(Member {
+      name: name.into(),
+      vm,
+      steps: 0,
+      fields: vec![],
+    });
+  }
+
+  pub fn len(&self) -> usize {
+    self.members.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.members.is_empty()
+  }
+
+  pub fn name(&self, member: usize) -> &str {
+    &self.members[member].name
+  }
+
+  pub fn vm(&self, member: usize) -> &VirtualMachine<'d, TracingDevice<D>> {
+    &self.members[member].vm
+  }
+
+  /// Runs the VMs from the start with `inputs`. The run stops when a VM
+  /// stops without waiting for input, the VMs wait for different kinds of
+  /// input, all inputs are consumed, or a VM exceeds `max_steps`
+  /// instructions.
+  pub fn run(
+    &mut self,
+    inputs: &[GroupInput],
+    max_steps: usize,
+  ) -> GroupReport {
+    for member in &mut self.members {
+      member.vm.start();
+      member.steps = 0;
+    }
+
+    let mut divergences: Vec<Divergence> = vec![];
+    let mut inputs = inputs.iter();
+    let mut inputs_consumed = 0;
+    let mut input = None;
+    loop {
+      let results: Vec<ExecResult> = self
+        .members
+        .iter_mut()
+        .map(|member| member.run_until_stopped(input, max_steps))
+        .collect();
+
+      for (i, result) in results.iter().enumerate().skip(1) {
+        if divergences.iter().any(|d| d.member == i) {
+          continue;
+        }
+        if let Some(kind) = self.compare(0, i, &results[0], result) {
+          divergences.push(Divergence { member: i, kind });
+        }
+      }
+
+      let waiting = results.iter().all(|result| {
+        matches!(result, ExecResult::InKey | ExecResult::KeyboardInput { .. })
+      });
+      let exceeded = self.members.iter().any(|m| m.steps >= max_steps);
+      let next = inputs.next();
+      match next {
+        Some(next) if waiting && !exceeded && accepts(&results, next) => {
+          inputs_consumed += 1;
+          input = Some(next);
+        }
+        _ => {
+          return GroupReport {
+            results,
+            divergences,
+            inputs_consumed,
+          }
+        }
+      }
+    }
+  }
+
+  fn compare(
+    &self,
+    reference: usize,
+    member: usize,
+    expected: &ExecResult,
+    actual: &ExecResult,
+  ) -> Option<DivergenceKind> {
+    let (reference, member) = (&self.members[reference], &self.members[member]);
+    let expected_trace = normalized_trace(&reference.vm);
+    let actual_trace = normalized_trace(&member.vm);
+    let (e, a) = (&expected_trace.events, &actual_trace.events);
+    for index in 0..e.len().max(a.len()) {
+      let expected = e.get(index).map(|e| e.event.clone());
+      let actual = a.get(index).map(|e| e.event.clone());
+      if expected != actual {
+        return Some(DivergenceKind::Event {
+          index,
+          expected,
+          actual,
+        });
+      }
+    }
+
+    if !same_kind(expected, actual) {
+      return Some(DivergenceKind::Result {
+        expected: expected.clone(),
+        actual: actual.clone(),
+      });
+    }
+
+    let expected_vars = vars(&reference.vm);
+    let actual_vars = vars(&member.vm);
+    let mut names: Vec<&String> =
+      expected_vars.keys().chain(actual_vars.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+      let expected = expected_vars.get(name);
+      let actual = actual_vars.get(name);
+      if expected != actual {
+        return Some(DivergenceKind::Var {
+          name: name.clone(),
+          expected: expected.cloned(),
+          actual: actual.cloned(),
+        });
+      }
+    }
+    None
+  }
+}
+
+impl<'d, D> Member<'d, D>
+where
+  D: Device,
+  D::AsmError: ToString,
+{
+  /// Runs the VM until it waits for input or stops, in slices of
+  /// instructions.
+  fn run_until_stopped(
+    &mut self,
+    input: Option<&GroupInput>,
+    max_steps: usize,
+  ) -> ExecResult {
+    let mut input = match input {
+      Some(GroupInput::Key(key)) => ExecInput::Key(*key),
+      Some(GroupInput::Values(values)) => {
+        ExecInput::KeyboardInput(self.keyboard_input(values))
+      }
+      None => ExecInput::None,
+    };
+    loop {
+      let budget = SLICE.min(max_steps.saturating_sub(self.steps)).max(1);
+      let result = self
+        .vm
+        .exec(std::mem::replace(&mut input, ExecInput::None), budget);
+      self.steps += budget;
+      match result {
+        ExecResult::Continue if self.steps < max_steps => {}
+        ExecResult::Sleep(duration) => self.vm.device_mut().advance(duration),
+        ExecResult::Warning { .. }
+        | ExecResult::Yield
+        | ExecResult::OutputQuotaExceeded { .. } => {}
+        ExecResult::KeyboardInput { ref fields, .. } => {
+          self.fields = fields.clone();
+          return result;
+        }
+        _ => return result,
+      }
+    }
+  }
+
+  fn keyboard_input(&mut self, values: &[String]) -> Vec<KeyboardInput> {
+    let fields = std::mem::take(&mut self.fields);
+    fields
+      .iter()
+      .zip(values)
+      .map(|(field, value)| match field {
+        KeyboardInputType::Integer => KeyboardInput::Integer(
+          value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|n| (-32768.0..32768.0).contains(n))
+            .map_or(0, |n| n as i16),
+        ),
+        KeyboardInputType::Real => KeyboardInput::Real(
+          value.trim().parse::<Mbf5>().unwrap_or(Mbf5::ZERO),
+        ),
+        _ => KeyboardInput::String(
+          self
+            .vm
+            .byte_string_from_utf16str(&Utf16String::from(value.as_str()))
+            .0,
+        ),
+      })
+      .collect()
+  }
+}
+
+fn accepts(results: &[ExecResult], input: &GroupInput) -> bool {
+  results.iter().all(|result| match (result, input) {
+    (ExecResult::InKey, GroupInput::Key(_)) => true,
+    (ExecResult::KeyboardInput { fields, .. }, GroupInput::Values(values)) => {
+      fields.len() == values.len()
+        && fields
+          .iter()
+          .all(|field| !matches!(field, KeyboardInputType::Func { .. }))
+    }
+    _ => false,
+  })
+}
+
+fn same_kind(a: &ExecResult, b: &ExecResult) -> bool {
+  match (a, b) {
+    (
+      ExecResult::KeyboardInput { fields: a, .. },
+      ExecResult::KeyboardInput { fields: b, .. },
+    ) => a == b,
+    (
+      ExecResult::Error { message: a, .. },
+      ExecResult::Error { message: b, .. },
+    ) => a == b,
+    _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+  }
+}
+
+fn normalized_trace<D: Device>(
+  vm: &VirtualMachine<TracingDevice<D>>,
+) -> Capture {
+  let mut trace = vm.device().trace().clone();
+  trace.normalize();
+  trace
+}
+
+fn vars<D: Device>(
+  vm: &VirtualMachine<TracingDevice<D>>,
+) -> BTreeMap<String, String> {
+  vm.bindings()
+    .into_iter()
+    .map(|(name, binding)| {
+      let value = match binding {
+        Binding::Var {
+          value: Value::Integer(n),
+        } => n.to_string(),
+        Binding::Var {
+          value: Value::Real(n),
+        } => n.to_string(),
+        Binding::Var {
+          value: Value::String(s),
+        } => format!("{:?}", vm.string_from_byte_string_lossy(s)),
+        Binding::Array { dimensions } => format!("{dimensions:?}"),
+      };
+      (name, value)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::device::default::DefaultDevice;
+  use crate::Document;
+  use pretty_assertions::assert_eq;
+
+  fn make_doc(text: &str) -> Document {
+    crate::machine::init_machines_once().unwrap();
+    Document::from_text(Utf16String::from(text.replace('\n', "\r\n")))
+  }
+
+  fn run_group(
+    texts: &[&str],
+    inputs: &[GroupInput],
+  ) -> (GroupReport, Vec<Capture>) {
+    let mut docs: Vec<Document> = texts.iter().map(|t| make_doc(t)).collect();
+    let mut devices: Vec<TracingDevice<DefaultDevice>> = docs
+      .iter()
+      .map(|doc| TracingDevice::new(doc.create_device("")))
+      .collect();
+    let mut group = VmGroup::new();
+    for (i, (doc, device)) in docs.iter_mut().zip(&mut devices).enumerate() {
+      group.add(format!("vm{i}"), doc.create_vm(device).ok().unwrap());
+    }
+    assert_eq!(group.len(), texts.len());
+    assert_eq!(group.name(1), "vm1");
+    let report = group.run(inputs, 100000);
+    drop(group);
+    let traces = devices.iter().map(|d| d.trace().clone()).collect();
+    (report, traces)
+  }
+
+  #[test]
+  fn same() {
+    let text = "10 input a,b$\n20 print a*2;b$\n30 k$=inkey$:print k$\n40 end";
+    let inputs = [
+      GroupInput::Values(vec!["12".to_owned(), "X".to_owned()]),
+      GroupInput::Key(65),
+    ];
+    let (report, traces) = run_group(&[text, text], &inputs);
+    assert_eq!(report.divergences, vec![]);
+    assert_eq!(report.inputs_consumed, 2);
+    assert!(report.results.iter().all(|r| *r == ExecResult::End));
+    assert_eq!(traces[0], traces[1]);
+    let mut trace = traces[0].clone();
+    trace.normalize();
+    assert!(trace
+      .events
+      .iter()
+      .any(|e| e.event == Event::Print(b"24X".to_vec())));
+  }
+
+  #[test]
+  fn var_divergence() {
+    let (report, _) = run_group(
+      &[
+        "10 input a:b=a*2:c$=\"Z\"\n20 k$=inkey$",
+        "10 input a:b=a*2+1:c$=\"Z\"\n20 k$=inkey$",
+        "10 input a:b=a*2:c$=\"Z\"\n20 k$=inkey$",
+      ],
+      &[
+        GroupInput::Values(vec!["3".to_owned()]),
+        GroupInput::Key(13),
+      ],
+    );
+    assert_eq!(
+      report.divergences,
+      vec![Divergence {
+        member: 1,
+        kind: DivergenceKind::Var {
+          name: "B".to_owned(),
+          expected: Some("6".to_owned()),
+          actual: Some("7".to_owned()),
+        },
+      }]
+    );
+    assert_eq!(report.inputs_consumed, 2);
+  }
+
+  #[test]
+  fn event_divergence() {
+    let (report, _) = run_group(
+      &[
+        "10 print \"A\";:cls:print \"B\";",
+        "10 print \"A\";:beep:print \"B\";",
+      ],
+      &[],
+    );
+    assert_eq!(
+      report.divergences,
+      vec![Divergence {
+        member: 1,
+        kind: DivergenceKind::Event {
+          index: 1,
+          expected: Some(Event::Cls),
+          actual: Some(Event::Beep),
+        },
+      }]
+    );
+  }
+
+  #[test]
+  fn result_divergence() {
+    let (report, _) =
+      run_group(&["10 k$=inkey$\n20 end", "10 end"], &[GroupInput::Key(13)]);
+    assert_eq!(
+      report.divergences,
+      vec![Divergence {
+        member: 1,
+        kind: DivergenceKind::Result {
+          expected: ExecResult::InKey,
+          actual: ExecResult::End,
+        },
+      }]
+    );
+    assert_eq!(report.inputs_consumed, 0);
+  }
+}
@@ -0,0 +1,129 @@
+use super::{
+  symbol_type, Addr, ByteString, ExecInput, Instr, InstrKind, KeyboardInput,
+  LValue, Location, Type, UserFunc, Value, VirtualMachine,
+};
+use crate::device::Device;
+use crate::util::mbf5::Mbf5;
+use crate::HashMap;
+
+impl<'d, D> VirtualMachine<'d, D>
+where
+  D: Device,
+{
+  pub(super) fn assign_key(&mut self, input: ExecInput) {
+    match input {
+      ExecInput::Key(key) => {
+        self
+          .str_stack
+          .push((self.code[self.pc].loc.clone(), ByteString::from(vec![key])));
+      }
+      _ => unreachable!(),
+    }
+    self.pc += 1;
+  }
+
+  pub(super) fn assign_input(
+    &mut self,
+    input: ExecInput,
+    lvalues: Vec<(Location, LValue)>,
+    skip_first: bool,
+  ) {
+    if let ExecInput::KeyboardInput(mut values) = input {
+      let mut comma = false;
+      let mut lvalues = lvalues.into_iter().peekable();
+      if skip_first {
+        comma = true;
+        match &lvalues.peek().unwrap().1 {
+          LValue::Var { name } | LValue::Index { name, .. } => {
+            match symbol_type(&self.interner, *name) {
+              Type::Integer => values.insert(0, KeyboardInput::Integer(0)),
+              Type::Real => values.insert(0, KeyboardInput::Real(Mbf5::ZERO)),
+              Type::String => {
+                values.insert(0, KeyboardInput::String(ByteString::new()))
+              }
+            }
+          }
+          LValue::Fn { .. } => unreachable!(),
+        }
+      }
+      let mut history: Vec<_> = values
+        .iter()
+        .map(|value| match value {
+          KeyboardInput::Integer(num) => Some(num.to_string()),
+          KeyboardInput::Real(num) => Some(num.to_string()),
+          KeyboardInput::String(s) => {
+            Some(s.to_string_lossy(self.emoji_version))
+          }
+          KeyboardInput::Func { .. } => None,
+        })
+        .collect();
+      if skip_first {
+        history[0] = None;
+      }
+      self.input_history.insert(self.pc, history);
+      for ((lval_loc, lvalue), value) in lvalues.zip(values) {
+        if comma {
+          self.device.print(b",");
+        }
+        comma = true;
+        match value {
+          KeyboardInput::Integer(num) => {
+            self.device.print(num.to_string().as_bytes());
+            self.bindings.store_value(lvalue, Value::Integer(num));
+          }
+          KeyboardInput::Real(num) => {
+            self.device.print(num.to_string().as_bytes());
+            self.bindings.store_value(lvalue, Value::Real(num));
+          }
+          KeyboardInput::String(mut s) => {
+            s.truncate(self.device.max_string_len());
+            self.device.print(&s);
+            self.bindings.store_value(lvalue, Value::String(s));
+          }
+          KeyboardInput::Func { body } => {
+            let (name, param) = match &lvalue {
+              LValue::Fn { name, param } => {
+                self.device.print(
+                  format!(
+                    "FN {}({})",
+                    self.interner.resolve(*name).unwrap(),
+                    self.interner.resolve(*param).unwrap()
+                  )
+                  .as_bytes(),
+                );
+                (*name, *param)
+              }
+              _ => unreachable!(),
+            };
+
+            let mut sym_map = HashMap::default();
+            for (sym, name) in &body.interner {
+              let new_sym = self.interner.get_or_intern(name);
+              sym_map.insert(sym, new_sym);
+            }
+
+            let body_addr = Addr(self.code.len());
+            self.code.extend(body.code.into_iter().map(|instr| Instr {
+              loc: lval_loc.clone(),
+              kind: instr.kind.map_symbol(&sym_map),
+            }));
+            self.code.push(Instr {
+              loc: lval_loc,
+              kind: InstrKind::ReturnFn,
+            });
+
+            self
+              .bindings
+              .user_funcs
+              .insert(name, UserFunc { param, body_addr });
+          }
+        };
+      }
+    } else {
+      unreachable!()
+    }
+    self.device.newline();
+    self.device.flush();
+    self.pc += 1;
+  }
+}
@@ -1,3 +1,9 @@
+//! Instruction set of the virtual machine.
+//!
+//! The instructions are exposed for tools which dump or inspect compiled
+//! programs. This is a semi-public API: instructions may be added, removed or
+//! changed in any release, and they are not a stable bytecode format.
+
 #[cfg(test)]
 use crate::machine::EmojiVersion;
 use std::fmt::{self, Debug, Formatter};
@@ -6,12 +12,11 @@ use std::num::NonZeroUsize;
 use string_interner::StringInterner;
 
 use super::{ByteString, Symbol};
-use crate::{
-  ast::{FileMode, Range, SysFuncKind},
-  util::mbf5::Mbf5,
-  HashMap,
-};
+pub use crate::ast::{FileMode, SysFuncKind};
+use crate::{ast::Range, util::mbf5::Mbf5, HashMap};
 
+/// Location of the statement or expression which an instruction is compiled
+/// from. `range` is relative to the start of the line.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Location {
   pub line: usize,
@@ -24,16 +29,23 @@ pub struct Instr {
   pub kind: InstrKind,
 }
 
+/// Address of an instruction, i.e. the index into the instruction list.
 #[derive(Debug, Clone, Copy)]
-pub struct Addr(pub(crate) usize);
+pub struct Addr(pub usize);
 
-pub const DUMMY_ADDR: Addr = Addr(0);
+pub(crate) const DUMMY_ADDR: Addr = Addr(0);
 
+/// Index into the data list collected from DATA statements.
 #[derive(Debug, Clone, Copy)]
-pub struct DatumIndex(pub(crate) usize);
+pub struct DatumIndex(pub usize);
 
-pub const FISRT_DATUM_INDEX: DatumIndex = DatumIndex(0);
+pub(crate) const FISRT_DATUM_INDEX: DatumIndex = DatumIndex(0);
 
+/// Kind of an instruction.
+///
+/// Fields are the operands known at compile time. Other operands are popped
+/// from the number stack, string stack or lvalue stack of the VM, and results
+/// are pushed onto them.
 #[derive(Clone)]
 pub enum InstrKind {
   DefFn {
@@ -165,6 +177,7 @@ pub enum InstrKind {
   SetScreenMode(ScreenMode),
   PlayNotes,
   Poke,
+  Sound,
   Swap,
   Restart,
   SetPrintMode(PrintMode),
@@ -179,6 +192,8 @@ pub enum InstrKind {
   Fwrite,
   Fseek,
   Debug,
+  SetTimer(Addr),
+  EnableTimer(bool),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -198,6 +213,11 @@ pub enum PrintMode {
   Normal,
   Inverse,
   Flash,
+  /// Switches the text mode screen to the small font, on machines supporting
+  /// it. Not generated by statements, but passed to the device by hosts.
+  SmallFont,
+  /// Switches the text mode screen back to the normal font.
+  NormalFont,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -211,7 +231,7 @@ pub enum CmpKind {
 }
 
 impl InstrKind {
-  pub fn map_symbol(self, sym_map: &HashMap<Symbol, Symbol>) -> Self {
+  pub(crate) fn map_symbol(self, sym_map: &HashMap<Symbol, Symbol>) -> Self {
     match self {
       Self::DefFn { name, param, end } => Self::DefFn {
         name: sym_map[&name],
@@ -429,6 +449,7 @@ impl InstrKind {
       Self::SetScreenMode(mode) => format!("set screen mode: {mode:?}"),
       Self::PlayNotes => format!("play notes"),
       Self::Poke => format!("poke"),
+      Self::Sound => format!("sound"),
       Self::Swap => format!("swap"),
       Self::Restart => format!("restart"),
       Self::SetPrintMode(mode) => format!("set print mode: {mode:?}"),
@@ -443,6 +464,8 @@ impl InstrKind {
       Self::Fwrite => format!("fwrite"),
       Self::Fseek => format!("fseek"),
       Self::Debug => format!("debug"),
+      Self::SetTimer(addr) => format!("set timer, handler addr: {}", addr.0),
+      Self::EnableTimer(enabled) => format!("enable timer: {enabled}"),
     }
   }
 }
@@ -0,0 +1,92 @@
+/// Caps on the number of bytes printed to the screen by PRINT and WRITE
+/// statements, which protect hosts from programs printing in tight loops.
+///
+/// When a cap is reached, the VM returns `ExecResult::OutputQuotaExceeded`
+/// right after printing the text, and the host may ask the user
+/// whether to continue. The count of the exceeded cap starts over when
+/// execution is resumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputQuota {
+  /// In bytes printed during a single call of `exec`. Unlimited if None.
+  pub per_exec: Option<usize>,
+  /// In bytes printed since the program is started. Unlimited if None.
+  pub total: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputQuotaKind {
+  PerExec,
+  Total,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct OutputQuotaState {
+  pub quota: Option<OutputQuota>,
+  exec_bytes: usize,
+  total_bytes: usize,
+}
+
+impl OutputQuotaState {
+  pub fn reset(&mut self) {
+    self.exec_bytes = 0;
+    self.total_bytes = 0;
+  }
+
+  pub fn start_exec(&mut self) {
+    self.exec_bytes = 0;
+  }
+
+  /// Counts `bytes` printed, and returns the cap reached along with the
+  /// bytes counted against it, if any. The count is reset then.
+  pub fn add(&mut self, bytes: usize) -> Option<(OutputQuotaKind, usize)> {
+    let quota = self.quota?;
+    self.exec_bytes += bytes;
+    self.total_bytes += bytes;
+    if quota.total.is_some_and(|max| self.total_bytes >= max) {
+      self.exec_bytes = 0;
+      return Some((
+        OutputQuotaKind::Total,
+        std::mem::take(&mut self.total_bytes),
+      ));
+    }
+    if quota.per_exec.is_some_and(|max| self.exec_bytes >= max) {
+      return Some((
+        OutputQuotaKind::PerExec,
+        std::mem::take(&mut self.exec_bytes),
+      ));
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn add() {
+    let mut state = OutputQuotaState::default();
+    assert_eq!(state.add(1000), None);
+
+    state.quota = Some(OutputQuota {
+      per_exec: Some(10),
+      total: Some(25),
+    });
+    assert_eq!(state.add(6), None);
+    assert_eq!(state.add(6), Some((OutputQuotaKind::PerExec, 12)));
+    assert_eq!(state.add(6), None);
+    state.start_exec();
+    assert_eq!(state.add(6), None);
+    assert_eq!(state.add(1), Some((OutputQuotaKind::Total, 25)));
+    assert_eq!(state.add(9), None);
+
+    state.reset();
+    state.quota = Some(OutputQuota {
+      per_exec: None,
+      total: Some(5),
+    });
+    assert_eq!(state.add(4), None);
+    assert_eq!(state.add(100), Some((OutputQuotaKind::Total, 104)));
+  }
+}
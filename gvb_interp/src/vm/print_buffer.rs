@@ -0,0 +1,172 @@
+use super::InstrKind;
+use crate::device::Device;
+
+/// When text printed to the screen by PRINT and WRITE statements is passed
+/// to the device.
+///
+/// Each piece of a PRINT statement is a separate call of `Device::print`
+/// by default, which is costly for devices implemented across FFI. Other
+/// policies buffer the text in the VM and pass it to the device in fewer
+/// calls. Regardless of the policy, the buffer is flushed before the VM
+/// performs any other operation on the device, e.g. reading the cursor
+/// position, and before `exec` returns, so the screen observed by the device
+/// and the host is the same as with `Immediate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrintFlushPolicy {
+  /// Every piece is printed by a separate call.
+  #[default]
+  Immediate,
+  /// Text is printed at each line break.
+  PerNewline,
+  /// Text and line breaks are passed to the device at the end of each PRINT
+  /// or WRITE statement.
+  PerStatement,
+  /// Text and line breaks are passed to the device only when required, or
+  /// when `flush_print_buffer` is called.
+  Explicit,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct PrintBuffer {
+  pub policy: PrintFlushPolicy,
+  chunks: Vec<Chunk>,
+  /// Set if `Device::flush` is deferred until the buffer is flushed.
+  pending_flush: bool,
+  /// Bytes printed since the last call of `take_printed`, counting each line
+  /// break as one byte.
+  printed: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Chunk {
+  Text(Vec<u8>),
+  NewLine,
+}
+
+impl PrintBuffer {
+  pub fn is_empty(&self) -> bool {
+    self.chunks.is_empty() && !self.pending_flush
+  }
+
+  pub fn take_printed(&mut self) -> usize {
+    std::mem::take(&mut self.printed)
+  }
+
+  pub fn print<D: Device>(&mut self, device: &mut D, str: &[u8]) {
+    self.printed += str.len();
+    if self.policy == PrintFlushPolicy::Immediate {
+      device.print(str);
+      return;
+    }
+    match self.chunks.last_mut() {
+      Some(Chunk::Text(text)) => text.extend_from_slice(str),
+      _ => self.chunks.push(Chunk::Text(str.to_vec())),
+    }
+  }
+
+  pub fn newline<D: Device>(&mut self, device: &mut D) {
+    self.printed += 1;
+    match self.policy {
+      PrintFlushPolicy::Immediate | PrintFlushPolicy::PerNewline => {
+        self.flush_text(device);
+        device.newline();
+      }
+      PrintFlushPolicy::PerStatement | PrintFlushPolicy::Explicit => {
+        self.chunks.push(Chunk::NewLine);
+      }
+    }
+  }
+
+  /// Called at the end of PRINT and WRITE statements.
+  pub fn end_stmt<D: Device>(&mut self, device: &mut D) {
+    match self.policy {
+      PrintFlushPolicy::Immediate | PrintFlushPolicy::PerStatement => {
+        self.flush_text(device);
+        self.pending_flush = false;
+        device.flush();
+      }
+      PrintFlushPolicy::PerNewline | PrintFlushPolicy::Explicit => {
+        if self.chunks.is_empty() {
+          self.pending_flush = false;
+          device.flush();
+        } else {
+          self.pending_flush = true;
+        }
+      }
+    }
+  }
+
+  pub fn flush<D: Device>(&mut self, device: &mut D) {
+    self.flush_text(device);
+    if std::mem::take(&mut self.pending_flush) {
+      device.flush();
+    }
+  }
+
+  fn flush_text<D: Device>(&mut self, device: &mut D) {
+    for chunk in self.chunks.drain(..) {
+      match chunk {
+        Chunk::Text(text) => device.print(&text),
+        Chunk::NewLine => device.newline(),
+      }
+    }
+  }
+}
+
+/// Returns true if the instruction may be executed with text in the print
+/// buffer, i.e. it does not access the device other than printing text.
+pub(super) fn keeps_print_buffer(kind: &InstrKind) -> bool {
+  matches!(
+    kind,
+    InstrKind::DefFn { .. }
+      | InstrKind::DimArray { .. }
+      | InstrKind::PushVarLValue { .. }
+      | InstrKind::PushIndexLValue { .. }
+      | InstrKind::PushFnLValue { .. }
+      | InstrKind::ForLoop { .. }
+      | InstrKind::NextFor { .. }
+      | InstrKind::GoSub(_)
+      | InstrKind::GoTo(_)
+      | InstrKind::JumpIfZero(_)
+      | InstrKind::CallFn(_)
+      | InstrKind::ReturnFn
+      | InstrKind::Switch(_)
+      | InstrKind::RestoreDataPtr(_)
+      | InstrKind::Return
+      | InstrKind::Pop
+      | InstrKind::PopNum
+      | InstrKind::PopStr
+      | InstrKind::PushNum(_)
+      | InstrKind::PushVar(_)
+      | InstrKind::PushStr(_)
+      | InstrKind::PushIndex { .. }
+      | InstrKind::Not
+      | InstrKind::Neg
+      | InstrKind::CmpNum(_)
+      | InstrKind::CmpStr(_)
+      | InstrKind::Add
+      | InstrKind::Sub
+      | InstrKind::Mul
+      | InstrKind::Div
+      | InstrKind::Pow
+      | InstrKind::Concat
+      | InstrKind::And
+      | InstrKind::Or
+      | InstrKind::NewLine
+      | InstrKind::PrintSpc
+      | InstrKind::PrintNum
+      | InstrKind::PrintStr
+      | InstrKind::Flush
+      | InstrKind::WriteNum { .. }
+      | InstrKind::WriteStr { .. }
+      | InstrKind::ReadData
+      | InstrKind::NoOp
+      | InstrKind::AssignInt
+      | InstrKind::AssignReal
+      | InstrKind::AssignStr
+      | InstrKind::AlignedAssign(_)
+      | InstrKind::Swap
+      | InstrKind::Wend
+      | InstrKind::WhileLoop { .. }
+  )
+}
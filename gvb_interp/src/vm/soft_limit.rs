@@ -0,0 +1,190 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Resource of the real machine which a program may run out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+  /// Contents of strings held by variables and arrays.
+  StringSpace,
+  /// Elements of arrays.
+  ArrayMemory,
+  /// Nested GOSUB, FOR, WHILE and user-defined function calls.
+  StackDepth,
+}
+
+/// Resource usage of the running program, measured the same way as
+/// [`SoftLimits`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+  /// In bytes.
+  pub string_space: usize,
+  /// In bytes. Each integer element takes 2 bytes, each real element takes 5
+  /// bytes, and each string element takes 3 bytes besides its content.
+  pub array_memory: usize,
+  pub stack_depth: usize,
+}
+
+/// Budgets of resources of the real machine. When the usage of a resource
+/// reaches `threshold` percent of its budget, the VM returns
+/// `ExecResult::Warning` once per run, so that users learn their program is
+/// close to failing on the real machine.
+///
+/// The VM itself does not fail when the budgets are exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftLimits {
+  pub string_space: usize,
+  pub array_memory: usize,
+  pub stack_depth: usize,
+  /// Percentage of the budgets, in 1~100.
+  pub threshold: u8,
+}
+
+impl Default for SoftLimits {
+  /// Rough estimate of the budgets of the real machine.
+  fn default() -> Self {
+    Self {
+      string_space: 8192,
+      array_memory: 16384,
+      stack_depth: 64,
+      threshold: 80,
+    }
+  }
+}
+
+impl Resource {
+  pub const ALL: [Self; 3] =
+    [Self::StringSpace, Self::ArrayMemory, Self::StackDepth];
+
+  fn index(self) -> usize {
+    self as usize
+  }
+}
+
+impl ResourceUsage {
+  pub fn get(&self, resource: Resource) -> usize {
+    match resource {
+      Resource::StringSpace => self.string_space,
+      Resource::ArrayMemory => self.array_memory,
+      Resource::StackDepth => self.stack_depth,
+    }
+  }
+}
+
+impl SoftLimits {
+  pub fn budget(&self, resource: Resource) -> usize {
+    match resource {
+      Resource::StringSpace => self.string_space,
+      Resource::ArrayMemory => self.array_memory,
+      Resource::StackDepth => self.stack_depth,
+    }
+  }
+
+  /// Returns whether `used` reaches the threshold of the budget of
+  /// `resource`.
+  pub fn is_reached(&self, resource: Resource, used: usize) -> bool {
+    used.saturating_mul(100)
+      >= self
+        .budget(resource)
+        .saturating_mul(self.threshold as usize)
+  }
+}
+
+impl Display for Resource {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::StringSpace => write!(f, "字符串占用的内存"),
+      Self::ArrayMemory => write!(f, "数组占用的内存"),
+      Self::StackDepth => write!(f, "GOSUB、FOR、WHILE 和自定义函数的嵌套层数"),
+    }
+  }
+}
+
+/// Soft limits of a VM, and resources which have been warned about since the
+/// program is started.
+#[derive(Debug, Clone, Default)]
+pub(super) struct SoftLimitState {
+  pub limits: Option<SoftLimits>,
+  warned: [bool; 3],
+}
+
+impl SoftLimitState {
+  pub fn reset(&mut self) {
+    self.warned = [false; 3];
+  }
+
+  /// Returns the warning message of the first resource which reaches the
+  /// threshold and has not been warned about yet.
+  pub fn check(&mut self, usage: &ResourceUsage) -> Option<String> {
+    let limits = self.limits?;
+    for resource in Resource::ALL {
+      let used = usage.get(resource);
+      if self.warned[resource.index()] || !limits.is_reached(resource, used) {
+        continue;
+      }
+      self.warned[resource.index()] = true;
+      let budget = limits.budget(resource);
+      let unit = if resource == Resource::StackDepth {
+        "层"
+      } else {
+        "字节"
+      };
+      return Some(format!(
+        "{resource}已达到 {used} {unit}，不低于真机上限（{budget} {unit}）的 \
+        {}%，程序在真机上运行时可能会因为内存不足而出错",
+        limits.threshold
+      ));
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn warn_once() {
+    let mut state = SoftLimitState {
+      limits: Some(SoftLimits {
+        string_space: 100,
+        array_memory: 1000,
+        stack_depth: 10,
+        threshold: 80,
+      }),
+      ..Default::default()
+    };
+    let mut usage = ResourceUsage {
+      string_space: 79,
+      array_memory: 800,
+      stack_depth: 7,
+    };
+    assert_eq!(
+      state.check(&usage).as_deref(),
+      Some(
+        "数组占用的内存已达到 800 字节，不低于真机上限（1000 字节）的 80%，\
+        程序在真机上运行时可能会因为内存不足而出错"
+      )
+    );
+    assert_eq!(state.check(&usage), None);
+
+    usage.string_space = 80;
+    usage.stack_depth = 8;
+    assert!(state.check(&usage).unwrap().starts_with("字符串占用的内存"));
+    assert!(state.check(&usage).unwrap().starts_with("GOSUB、FOR"));
+    assert_eq!(state.check(&usage), None);
+
+    state.reset();
+    assert!(state.check(&usage).is_some());
+  }
+
+  #[test]
+  fn disabled() {
+    let mut state = SoftLimitState::default();
+    let usage = ResourceUsage {
+      string_space: usize::MAX / 200,
+      array_memory: usize::MAX / 200,
+      stack_depth: usize::MAX / 200,
+    };
+    assert_eq!(state.check(&usage), None);
+  }
+}
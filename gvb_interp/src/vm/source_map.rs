@@ -0,0 +1,157 @@
+use super::Location;
+
+/// Two-way mapping between statements in the source text and addresses of
+/// compiled instructions.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+  /// Offsets of line starts in the source text.
+  line_starts: Vec<usize>,
+  /// Locations of statements, in the order of compilation, which is also the
+  /// order of lines.
+  stmts: Vec<Location>,
+  /// Index into `stmts` of the innermost statement containing each
+  /// instruction.
+  instr_stmts: Vec<Option<usize>>,
+  /// Addresses of the instructions of each statement in `stmts`, in
+  /// ascending order. The reverse of `instr_stmts`.
+  stmt_instrs: Vec<Vec<usize>>,
+}
+
+impl SourceMap {
+  pub(crate) fn add_line(&mut self, line_start: usize) {
+    self.line_starts.push(line_start);
+  }
+
+  pub(crate) fn add_stmt(&mut self, loc: Location) -> usize {
+    self.stmts.push(loc);
+    self.stmt_instrs.push(vec![]);
+    self.stmts.len() - 1
+  }
+
+  pub(crate) fn add_instr(&mut self, stmt: Option<usize>) {
+    if let Some(stmt) = stmt {
+      self.stmt_instrs[stmt].push(self.instr_stmts.len());
+    }
+    self.instr_stmts.push(stmt);
+  }
+
+  pub(crate) fn stmt_index(&self, addr: usize) -> Option<usize> {
+    self.instr_stmts.get(addr).copied().flatten()
+  }
+
+  /// Returns true if the instruction at `addr` is the first one of a
+  /// statement.
+  pub(crate) fn is_stmt_start(&self, addr: usize) -> bool {
+    let stmt = self.stmt_index(addr);
+    stmt.is_some() && (addr == 0 || self.stmt_index(addr - 1) != stmt)
+  }
+
+  /// Returns the location of the innermost statement which the instruction at
+  /// `addr` belongs to.
+  pub fn stmt_location(&self, addr: usize) -> Option<&Location> {
+    self
+      .instr_stmts
+      .get(addr)
+      .copied()
+      .flatten()
+      .map(|i| &self.stmts[i])
+  }
+
+  /// Returns the location of the innermost statement containing `column` of
+  /// `line`. A position at the end of a statement is also considered in the
+  /// statement.
+  pub fn stmt_at(&self, line: usize, column: usize) -> Option<&Location> {
+    self.find_stmt(line, column).map(|i| &self.stmts[i])
+  }
+
+  /// Returns addresses of instructions belonging to the innermost statement
+  /// containing `column` of `line`, in ascending order. Instructions of nested
+  /// statements (e.g. statements in IF branches) are not included.
+  pub fn stmt_addrs(&self, line: usize, column: usize) -> Vec<usize> {
+    match self.find_stmt(line, column) {
+      Some(stmt) => self.stmt_instrs[stmt].clone(),
+      None => vec![],
+    }
+  }
+
+  /// Converts an offset in the source text to line index and column.
+  pub fn position(&self, offset: usize) -> Option<(usize, usize)> {
+    let line = self
+      .line_starts
+      .partition_point(|&start| start <= offset)
+      .checked_sub(1)?;
+    Some((line, offset - self.line_starts[line]))
+  }
+
+  /// Same as `stmt_addrs`, but the position is an offset in the source text.
+  pub fn stmt_addrs_at(&self, offset: usize) -> Vec<usize> {
+    match self.position(offset) {
+      Some((line, column)) => self.stmt_addrs(line, column),
+      None => vec![],
+    }
+  }
+
+  /// Returns the range in the source text of the innermost statement which
+  /// the instruction at `addr` belongs to.
+  pub fn stmt_range(&self, addr: usize) -> Option<(usize, usize)> {
+    let loc = self.stmt_location(addr)?;
+    let line_start = self.line_starts[loc.line];
+    Some((line_start + loc.range.start, line_start + loc.range.end))
+  }
+
+  fn find_stmt(&self, line: usize, column: usize) -> Option<usize> {
+    let start = self.stmts.partition_point(|loc| loc.line < line);
+    let end = self.stmts.partition_point(|loc| loc.line <= line);
+    // Statements containing the position are nested in each other, so the
+    // innermost one is the shortest one.
+    self.stmts[start..end]
+      .iter()
+      .enumerate()
+      .filter(|(_, loc)| loc.range.start <= column && column <= loc.range.end)
+      .min_by_key(|(_, loc)| loc.range.len())
+      .map(|(i, _)| start + i)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::Range;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn stmt_addrs() {
+    let mut map = SourceMap::default();
+    let stmt = |map: &mut SourceMap, line, start, end| {
+      map.add_stmt(Location {
+        line,
+        range: Range::new(start, end),
+      })
+    };
+    // 10 A=1:IF A THEN B=2
+    map.add_line(0);
+    let a = stmt(&mut map, 0, 3, 6);
+    map.add_instr(Some(a));
+    map.add_instr(Some(a));
+    let if_ = stmt(&mut map, 0, 7, 20);
+    map.add_instr(Some(if_));
+    let b = stmt(&mut map, 0, 17, 20);
+    map.add_instr(Some(b));
+    map.add_instr(Some(if_));
+    // 20 END
+    map.add_line(21);
+    let end = stmt(&mut map, 1, 3, 6);
+    map.add_instr(Some(end));
+    map.add_instr(None);
+
+    assert_eq!(map.stmt_addrs(0, 4), vec![0, 1]);
+    assert_eq!(map.stmt_addrs(0, 8), vec![2, 4]);
+    assert_eq!(map.stmt_addrs(0, 18), vec![3]);
+    assert_eq!(map.stmt_addrs(1, 6), vec![5]);
+    assert_eq!(map.stmt_addrs(1, 0), vec![]);
+    assert_eq!(map.stmt_addrs(2, 0), vec![]);
+    assert_eq!(map.stmt_addrs_at(25), vec![5]);
+    assert!(map.is_stmt_start(3));
+    assert!(!map.is_stmt_start(1));
+  }
+}
@@ -0,0 +1,110 @@
+/// Storage of string array elements. All elements share a single buffer, so
+/// that an empty element costs only a few bytes, which matters for programs
+/// like `DIM A$(100, 100)`.
+///
+/// Elements which are replaced by longer strings are appended to the buffer,
+/// and the buffer is compacted when too much of it is unused.
+#[derive(Debug, Clone)]
+pub(crate) struct StringArray {
+  buf: Vec<u8>,
+  slots: Vec<Slot>,
+  /// Number of unused bytes in `buf`.
+  garbage: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+  offset: u32,
+  len: u32,
+}
+
+/// Buffers smaller than this are never compacted.
+const MIN_COMPACT_SIZE: usize = 1024;
+
+impl StringArray {
+  pub fn new(size: usize) -> Self {
+    Self {
+      buf: vec![],
+      slots: vec![Slot::default(); size],
+      garbage: 0,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.slots.len()
+  }
+
+  pub fn get(&self, index: usize) -> &[u8] {
+    let slot = self.slots[index];
+    let offset = slot.offset as usize;
+    &self.buf[offset..offset + slot.len as usize]
+  }
+
+  pub fn set(&mut self, index: usize, value: &[u8]) {
+    let slot = &mut self.slots[index];
+    let len = value.len() as u32;
+    if len <= slot.len {
+      let offset = slot.offset as usize;
+      self.buf[offset..offset + value.len()].copy_from_slice(value);
+      self.garbage += (slot.len - len) as usize;
+      slot.len = len;
+    } else {
+      self.garbage += slot.len as usize;
+      slot.offset = self.buf.len() as u32;
+      slot.len = len;
+      self.buf.extend_from_slice(value);
+      if self.buf.len() >= MIN_COMPACT_SIZE && self.garbage * 2 > self.buf.len()
+      {
+        self.compact();
+      }
+    }
+  }
+
+  fn compact(&mut self) {
+    let mut buf = Vec::with_capacity(self.buf.len() - self.garbage);
+    for slot in &mut self.slots {
+      let offset = slot.offset as usize;
+      let len = slot.len as usize;
+      slot.offset = buf.len() as u32;
+      buf.extend_from_slice(&self.buf[offset..offset + len]);
+    }
+    self.buf = buf;
+    self.garbage = 0;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn get_set() {
+    let mut arr = StringArray::new(3);
+    assert_eq!(arr.get(0), b"");
+    arr.set(1, b"hello");
+    arr.set(0, b"abc");
+    assert_eq!(arr.get(1), b"hello");
+    arr.set(1, b"hi");
+    assert_eq!(arr.get(1), b"hi");
+    arr.set(1, b"world!");
+    assert_eq!(arr.get(0), b"abc");
+    assert_eq!(arr.get(1), b"world!");
+    assert_eq!(arr.get(2), b"");
+    assert_eq!(arr.garbage, 5);
+  }
+
+  #[test]
+  fn compact() {
+    let mut arr = StringArray::new(10);
+    for round in 1..=255 {
+      for i in 0..10 {
+        arr.set(i, &vec![i as u8; round]);
+      }
+    }
+    for i in 0..10 {
+      assert_eq!(arr.get(i), &vec![i as u8; 255][..]);
+    }
+    assert!(arr.buf.len() <= 255 * 10 * 3);
+  }
+}
@@ -83,7 +83,7 @@ impl ByteString {
         let b2 = self[i + 1];
         i += 2;
         let code = ((b as u16) << 8) + b2 as u16;
-        if let Some(&c) = crate::gb2312::GB2312_TO_UNICODE.get(&code) {
+        if let Some(c) = crate::util::decode::gb2312_to_unicode(code) {
           s.push(unsafe { char::from_u32_unchecked(c as _) });
         } else if let Some(c) = emoji_version
           .code_to_char(code)
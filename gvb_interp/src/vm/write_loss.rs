@@ -0,0 +1,58 @@
+use super::Location;
+
+/// Reason why a string written to a file by WRITE statement cannot be read
+/// back unchanged by INPUT statement.
+///
+/// Like the firmware, WRITE statement encloses strings in double quotes
+/// without any escaping, and INPUT statement reads a quoted string until the
+/// next double quote. Commas and U+00FF characters in strings survive the
+/// round trip, but the following characters do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteLoss {
+  /// The string contains a null character. The string is truncated at the
+  /// null character, and the closing double quote is lost as well.
+  Null,
+  /// The string contains a double quote, which ends the string when read
+  /// back.
+  Quote,
+  /// The string contains U+001F characters, which are dropped. Note that
+  /// full-width characters in string literals are prefixed by U+001F.
+  Char1F,
+}
+
+/// A WRITE statement which has written a string that cannot be read back
+/// unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossyWrite {
+  pub location: Location,
+  pub loss: WriteLoss,
+}
+
+impl WriteLoss {
+  /// Returns the loss of writing `str` by WRITE statement, if any.
+  pub fn of(str: &[u8]) -> Option<Self> {
+    if str.contains(&0) {
+      Some(Self::Null)
+    } else if str.contains(&b'"') {
+      Some(Self::Quote)
+    } else if str.contains(&0x1f) {
+      Some(Self::Char1F)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn loss() {
+    assert_eq!(WriteLoss::of(b"a,b\xff\xb0\xa1"), None);
+    assert_eq!(WriteLoss::of(b"a\"b\0c"), Some(WriteLoss::Null));
+    assert_eq!(WriteLoss::of(b"a\"b"), Some(WriteLoss::Quote));
+    assert_eq!(WriteLoss::of(b"\x1fab"), Some(WriteLoss::Char1F));
+  }
+}
@@ -0,0 +1,388 @@
+//! A minimal JSON value, enough for the messages of the language server
+//! protocol.
+
+use std::fmt::{self, Display, Formatter, Write};
+use util::json::write_string;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  /// Members are kept in order, so that the output is deterministic.
+  Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+  /// Offset of the error in bytes.
+  pub offset: usize,
+}
+
+impl Json {
+  /// Creates an object of `members`.
+  pub fn object<const N: usize>(members: [(&str, Json); N]) -> Self {
+    Self::Object(
+      members
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect(),
+    )
+  }
+
+  /// Returns the member `name` of an object, or Null if there is none.
+  pub fn get(&self, name: &str) -> &Json {
+    match self {
+      Self::Object(members) => members
+        .iter()
+        .find(|(n, _)| n == name)
+        .map_or(&Json::Null, |(_, value)| value),
+      _ => &Json::Null,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Self::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn as_usize(&self) -> Option<usize> {
+    match self {
+      Self::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Self::Array(items) => Some(items),
+      _ => None,
+    }
+  }
+
+  pub fn is_null(&self) -> bool {
+    matches!(self, Self::Null)
+  }
+
+  pub fn parse(text: &str) -> Result<Self, ParseError> {
+    let mut parser = Parser {
+      text: text.as_bytes(),
+      offset: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_spaces();
+    if parser.offset < text.len() {
+      return Err(parser.error());
+    }
+    Ok(value)
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Self {
+    Self::String(s.to_owned())
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Self {
+    Self::String(s)
+  }
+}
+
+impl From<bool> for Json {
+  fn from(b: bool) -> Self {
+    Self::Bool(b)
+  }
+}
+
+impl From<usize> for Json {
+  fn from(n: usize) -> Self {
+    Self::Number(n as f64)
+  }
+}
+
+impl From<i64> for Json {
+  fn from(n: i64) -> Self {
+    Self::Number(n as f64)
+  }
+}
+
+impl Display for Json {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::Null => f.write_str("null"),
+      Self::Bool(b) => write!(f, "{}", b),
+      Self::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+        write!(f, "{}", *n as i64)
+      }
+      Self::Number(n) => write!(f, "{}", n),
+      Self::String(s) => write_string(f, s),
+      Self::Array(items) => {
+        f.write_char('[')?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            f.write_char(',')?;
+          }
+          write!(f, "{}", item)?;
+        }
+        f.write_char(']')
+      }
+      Self::Object(members) => {
+        f.write_char('{')?;
+        for (i, (name, value)) in members.iter().enumerate() {
+          if i > 0 {
+            f.write_char(',')?;
+          }
+          write_string(f, name)?;
+          write!(f, ":{}", value)?;
+        }
+        f.write_char('}')
+      }
+    }
+  }
+}
+
+struct Parser<'a> {
+  text: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&self) -> ParseError {
+    ParseError {
+      offset: self.offset,
+    }
+  }
+
+  fn skip_spaces(&mut self) {
+    while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.peek() {
+      self.offset += 1;
+    }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.text.get(self.offset).copied()
+  }
+
+  fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+    self.skip_spaces();
+    if self.peek() == Some(c) {
+      self.offset += 1;
+      Ok(())
+    } else {
+      Err(self.error())
+    }
+  }
+
+  fn literal(&mut self, lit: &str, value: Json) -> Result<Json, ParseError> {
+    if self.text[self.offset..].starts_with(lit.as_bytes()) {
+      self.offset += lit.len();
+      Ok(value)
+    } else {
+      Err(self.error())
+    }
+  }
+
+  fn value(&mut self) -> Result<Json, ParseError> {
+    self.skip_spaces();
+    match self.peek() {
+      Some(b'n') => self.literal("null", Json::Null),
+      Some(b't') => self.literal("true", Json::Bool(true)),
+      Some(b'f') => self.literal("false", Json::Bool(false)),
+      Some(b'"') => Ok(Json::String(self.string()?)),
+      Some(b'[') => {
+        self.offset += 1;
+        let mut items = vec![];
+        self.skip_spaces();
+        if self.peek() == Some(b']') {
+          self.offset += 1;
+          return Ok(Json::Array(items));
+        }
+        loop {
+          items.push(self.value()?);
+          self.skip_spaces();
+          match self.peek() {
+            Some(b',') => self.offset += 1,
+            Some(b']') => {
+              self.offset += 1;
+              return Ok(Json::Array(items));
+            }
+            _ => return Err(self.error()),
+          }
+        }
+      }
+      Some(b'{') => {
+        self.offset += 1;
+        let mut members = vec![];
+        self.skip_spaces();
+        if self.peek() == Some(b'}') {
+          self.offset += 1;
+          return Ok(Json::Object(members));
+        }
+        loop {
+          self.skip_spaces();
+          if self.peek() != Some(b'"') {
+            return Err(self.error());
+          }
+          let name = self.string()?;
+          self.expect(b':')?;
+          members.push((name, self.value()?));
+          self.skip_spaces();
+          match self.peek() {
+            Some(b',') => self.offset += 1,
+            Some(b'}') => {
+              self.offset += 1;
+              return Ok(Json::Object(members));
+            }
+            _ => return Err(self.error()),
+          }
+        }
+      }
+      Some(b'-' | b'0'..=b'9') => self.number(),
+      _ => Err(self.error()),
+    }
+  }
+
+  fn number(&mut self) -> Result<Json, ParseError> {
+    let start = self.offset;
+    while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek()
+    {
+      self.offset += 1;
+    }
+    std::str::from_utf8(&self.text[start..self.offset])
+      .ok()
+      .and_then(|s| s.parse().ok())
+      .map(Json::Number)
+      .ok_or(ParseError { offset: start })
+  }
+
+  fn string(&mut self) -> Result<String, ParseError> {
+    // skip the opening quote
+    self.offset += 1;
+    let mut s = String::new();
+    loop {
+      let start = self.offset;
+      while let Some(c) = self.peek() {
+        if c == b'"' || c == b'\\' || c < 0x20 {
+          break;
+        }
+        self.offset += 1;
+      }
+      s.push_str(
+        std::str::from_utf8(&self.text[start..self.offset])
+          .map_err(|_| ParseError { offset: start })?,
+      );
+      match self.peek() {
+        Some(b'"') => {
+          self.offset += 1;
+          return Ok(s);
+        }
+        Some(b'\\') => {
+          self.offset += 1;
+          let c = self.peek().ok_or_else(|| self.error())?;
+          self.offset += 1;
+          match c {
+            b'"' => s.push('"'),
+            b'\\' => s.push('\\'),
+            b'/' => s.push('/'),
+            b'b' => s.push('\u{8}'),
+            b'f' => s.push('\u{c}'),
+            b'n' => s.push('\n'),
+            b'r' => s.push('\r'),
+            b't' => s.push('\t'),
+            b'u' => {
+              let hi = self.hex4()?;
+              let c = if (0xd800..0xdc00).contains(&hi) {
+                if !self.text[self.offset..].starts_with(b"\\u") {
+                  return Err(self.error());
+                }
+                self.offset += 2;
+                let lo = self.hex4()?;
+                if !(0xdc00..0xe000).contains(&lo) {
+                  return Err(self.error());
+                }
+                0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+              } else {
+                hi
+              };
+              s.push(char::from_u32(c).ok_or_else(|| self.error())?);
+            }
+            _ => return Err(self.error()),
+          }
+        }
+        _ => return Err(self.error()),
+      }
+    }
+  }
+
+  fn hex4(&mut self) -> Result<u32, ParseError> {
+    let hex = self
+      .text
+      .get(self.offset..self.offset + 4)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+      .ok_or_else(|| self.error())?;
+    self.offset += 4;
+    Ok(hex)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  #[test]
+  fn parse() {
+    let json = Json::parse(
+      r#" {"a": [1, -2.5e1, true, null], "b": "x\"\u4e2d\ud83d\ude00\n", "c": {}} "#,
+    )
+    .unwrap();
+    assert_eq!(
+      json,
+      Json::object([
+        (
+          "a",
+          Json::Array(vec![
+            Json::Number(1.0),
+            Json::Number(-25.0),
+            Json::Bool(true),
+            Json::Null
+          ])
+        ),
+        ("b", "x\"中😀\n".into()),
+        ("c", Json::Object(vec![])),
+      ])
+    );
+    assert_eq!(json.get("a").as_array().unwrap()[0].as_usize(), Some(1));
+    assert!(json.get("d").is_null());
+  }
+
+  #[test]
+  fn parse_error() {
+    assert_eq!(Json::parse("[1,]"), Err(ParseError { offset: 3 }));
+    assert_eq!(Json::parse("{\"a\" 1}"), Err(ParseError { offset: 5 }));
+    assert_eq!(Json::parse("1 2"), Err(ParseError { offset: 2 }));
+    assert_eq!(Json::parse("\"\\x\""), Err(ParseError { offset: 3 }));
+  }
+
+  #[test]
+  fn display() {
+    let json = Json::object([
+      ("id", 1usize.into()),
+      ("x", Json::Number(0.5)),
+      ("s", "a\"\\\u{1}中".into()),
+      ("l", Json::Array(vec![Json::Null, false.into()])),
+    ]);
+    assert_eq!(
+      json.to_string(),
+      r#"{"id":1,"x":0.5,"s":"a\"\\\u0001中","l":[null,false]}"#
+    );
+    assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+  }
+}
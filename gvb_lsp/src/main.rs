@@ -0,0 +1,95 @@
+//! A language server of GVBASIC, communicating through stdin and stdout.
+//!
+//! Supported features are diagnostics, completion and hover of keywords and
+//! system functions, going to line labels, renaming line labels and
+//! formatting. Documents are synchronized in full.
+
+mod json;
+mod server;
+
+use json::Json;
+use server::Server;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+  if let Err(err) = gvb_interp::machine::init_machines() {
+    eprintln!("failed to load machine profiles: {:?}", err);
+    return ExitCode::from(2);
+  }
+
+  let stdin = io::stdin();
+  let mut stdin = stdin.lock();
+  let stdout = io::stdout();
+  let mut stdout = stdout.lock();
+  let mut server = Server::new();
+
+  loop {
+    let msg = match read_message(&mut stdin) {
+      Ok(Some(msg)) => msg,
+      Ok(None) => return ExitCode::FAILURE,
+      Err(err) => {
+        eprintln!("failed to read message: {}", err);
+        return ExitCode::FAILURE;
+      }
+    };
+    let msg = match Json::parse(&msg) {
+      Ok(msg) => msg,
+      Err(err) => {
+        eprintln!("invalid message at offset {}", err.offset);
+        continue;
+      }
+    };
+    for reply in server.handle(&msg) {
+      if let Err(err) = write_message(&mut stdout, &reply) {
+        eprintln!("failed to write message: {}", err);
+        return ExitCode::FAILURE;
+      }
+    }
+    if let Some(code) = server.exit_code() {
+      return ExitCode::from(code as u8);
+    }
+  }
+}
+
+/// Reads the content of a message. Returns None at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+  let mut len = None;
+  loop {
+    let mut header = String::new();
+    if input.read_line(&mut header)? == 0 {
+      return Ok(None);
+    }
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      if name.eq_ignore_ascii_case("content-length") {
+        len = value.trim().parse::<usize>().ok();
+      }
+    }
+  }
+  let Some(len) = len else {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "missing Content-Length",
+    ));
+  };
+  let mut content = vec![0; len];
+  input.read_exact(&mut content)?;
+  String::from_utf8(content)
+    .map(Some)
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, msg: &Json) -> io::Result<()> {
+  let content = msg.to_string();
+  write!(
+    output,
+    "Content-Length: {}\r\n\r\n{}",
+    content.len(),
+    content
+  )?;
+  output.flush()
+}
@@ -0,0 +1,672 @@
+use crate::json::Json;
+use gvb_interp::{builtin::BuiltinKind, Document, RenameLabelError, Severity};
+use std::collections::HashMap;
+use widestring::{Utf16Str, Utf16String};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// State of the language server. Messages are handled one by one, and the
+/// messages to send back are returned.
+#[derive(Default)]
+pub struct Server {
+  docs: HashMap<String, Document>,
+  shutdown: bool,
+  exit_code: Option<i32>,
+}
+
+impl Server {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns the exit code of the process after the `exit` notification has
+  /// been received.
+  pub fn exit_code(&self) -> Option<i32> {
+    self.exit_code
+  }
+
+  /// Handles a request or a notification, and returns the response and
+  /// notifications to send to the client.
+  pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+    let id = msg.get("id");
+    let params = msg.get("params");
+    let method = msg.get("method").as_str().unwrap_or("");
+    let mut out = vec![];
+    let result = match method {
+      "initialize" => Ok(initialize_result()),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Json::Null)
+      }
+      "exit" => {
+        self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+        return out;
+      }
+      "textDocument/didOpen" => {
+        let doc = params.get("textDocument");
+        if let (Some(uri), Some(text)) =
+          (doc.get("uri").as_str(), doc.get("text").as_str())
+        {
+          out.push(self.open(uri, text));
+        }
+        return out;
+      }
+      "textDocument/didChange" => {
+        // only full synchronization is supported
+        let uri = params.get("textDocument").get("uri").as_str();
+        let text = params
+          .get("contentChanges")
+          .as_array()
+          .and_then(|changes| changes.last())
+          .and_then(|change| change.get("text").as_str());
+        if let (Some(uri), Some(text)) = (uri, text) {
+          out.push(self.open(uri, text));
+        }
+        return out;
+      }
+      "textDocument/didClose" => {
+        if let Some(uri) = params.get("textDocument").get("uri").as_str() {
+          self.docs.remove(uri);
+          out.push(publish_diagnostics(uri, vec![]));
+        }
+        return out;
+      }
+      "textDocument/completion" => self.completion(params),
+      "textDocument/hover" => self.hover(params),
+      "textDocument/definition" => self.definition(params),
+      "textDocument/rename" => self.rename(params),
+      "textDocument/formatting" => self.formatting(params),
+      _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+    };
+
+    // notifications have no responses
+    if !id.is_null() {
+      let mut response =
+        Json::object([("jsonrpc", "2.0".into()), ("id", id.clone())]);
+      let Json::Object(members) = &mut response else {
+        unreachable!()
+      };
+      match result {
+        Ok(result) => members.push(("result".to_owned(), result)),
+        Err((code, message)) => members.push((
+          "error".to_owned(),
+          Json::object([("code", code.into()), ("message", message.into())]),
+        )),
+      }
+      out.push(response);
+    }
+    out
+  }
+
+  fn open(&mut self, uri: &str, text: &str) -> Json {
+    let mut doc = Document::from_text(Utf16String::from_str(text));
+    let diags = diagnostics_to_json(&mut doc);
+    self.docs.insert(uri.to_owned(), doc);
+    publish_diagnostics(uri, diags)
+  }
+
+  /// Returns the document and the offset of the position in `params`.
+  fn doc_at(
+    &mut self,
+    params: &Json,
+  ) -> Result<(&mut Document, usize), (i64, String)> {
+    let uri = params
+      .get("textDocument")
+      .get("uri")
+      .as_str()
+      .ok_or_else(|| (INVALID_PARAMS, "missing uri".to_owned()))?;
+    let doc = self
+      .docs
+      .get_mut(uri)
+      .ok_or_else(|| (INVALID_PARAMS, format!("unknown document: {}", uri)))?;
+    let pos = params.get("position");
+    let offset =
+      match (pos.get("line").as_usize(), pos.get("character").as_usize()) {
+        (Some(line), Some(character)) => {
+          position_to_offset(doc.text(), line, character)
+        }
+        _ => return Err((INVALID_PARAMS, "missing position".to_owned())),
+      };
+    Ok((doc, offset))
+  }
+
+  fn completion(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+    let (doc, offset) = self.doc_at(params)?;
+    let text = doc.text();
+    let start = word_start(text, offset);
+    let prefix = text[start..offset].to_string().to_ascii_uppercase();
+    let items = doc
+      .dialect()
+      .builtins()
+      .filter(|b| b.name.starts_with(&prefix))
+      .map(|b| {
+        let kind = match b.kind {
+          BuiltinKind::Function(_) => COMPLETION_FUNCTION,
+          _ => COMPLETION_KEYWORD,
+        };
+        Json::object([
+          ("label", b.name.into()),
+          ("kind", kind.into()),
+          ("detail", b.usage().into()),
+          ("documentation", b.description.into()),
+        ])
+      })
+      .collect();
+    Ok(Json::Array(items))
+  }
+
+  fn hover(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+    let (doc, offset) = self.doc_at(params)?;
+    if let Some(info) = doc.addr_info_at(offset) {
+      let mut contents = format!("地址 {}", info.addr);
+      for region in &info.regions {
+        contents.push_str("\n- ");
+        contents.push_str(region);
+      }
+      let lines = line_starts(doc.text());
+      return Ok(Json::object([
+        (
+          "contents",
+          Json::object([
+            ("kind", "markdown".into()),
+            ("value", contents.into()),
+          ]),
+        ),
+        (
+          "range",
+          range_to_json(&lines, info.range.start, info.range.end),
+        ),
+      ]));
+    }
+    let text = doc.text();
+    let start = word_start(text, offset);
+    let end = word_end(text, offset);
+    let word = text[start..end].to_string();
+    let Some(builtin) = doc.dialect().lookup(&word) else {
+      return Ok(Json::Null);
+    };
+    let lines = line_starts(text);
+    let contents =
+      format!("```\n{}\n```\n\n{}", builtin.usage(), builtin.description);
+    Ok(Json::object([
+      (
+        "contents",
+        Json::object([("kind", "markdown".into()), ("value", contents.into())]),
+      ),
+      ("range", range_to_json(&lines, start, end)),
+    ]))
+  }
+
+  fn definition(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+    let uri = params.get("textDocument").get("uri").clone();
+    let (doc, offset) = self.doc_at(params)?;
+    let Some(range) = doc.label_definition_at(offset) else {
+      return Ok(Json::Null);
+    };
+    let lines = line_starts(doc.text());
+    Ok(Json::object([
+      ("uri", uri),
+      ("range", range_to_json(&lines, range.start, range.end)),
+    ]))
+  }
+
+  fn rename(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+    let uri = params.get("textDocument").get("uri").clone();
+    let new_name = params.get("newName").as_str().unwrap_or("").trim();
+    let (doc, offset) = self.doc_at(params)?;
+    let new_label = new_name.parse::<u16>().map_err(|_| {
+      (REQUEST_FAILED, "行号必须是 0~9999 之间的整数".to_owned())
+    })?;
+    let edits =
+      doc
+        .compute_rename_label_edits(offset, new_label)
+        .map_err(|err| {
+          let message = match err {
+            RenameLabelError::NoLabel => "此处没有行号".to_owned(),
+            RenameLabelError::InvalidLabel(_) => {
+              "行号必须是 0~9999 之间的整数".to_owned()
+            }
+            RenameLabelError::LabelExists(label) => {
+              format!("行号 {} 已存在", label)
+            }
+            RenameLabelError::NotAscending { prev, next } => {
+              match (prev, next) {
+                (Some(prev), Some(next)) => {
+                  format!("行号必须在 {} 和 {} 之间", prev, next)
+                }
+                (Some(prev), None) => format!("行号必须大于 {}", prev),
+                (None, Some(next)) => format!("行号必须小于 {}", next),
+                (None, None) => unreachable!(),
+              }
+            }
+          };
+          (REQUEST_FAILED, message)
+        })?;
+    let lines = line_starts(doc.text());
+    let edits = edits
+      .into_iter()
+      .map(|edit| {
+        text_edit(&lines, edit.range.start, edit.range.end, &edit.str)
+      })
+      .collect();
+    Ok(Json::object([(
+      "changes",
+      Json::Object(vec![(
+        uri.as_str().unwrap_or("").to_owned(),
+        Json::Array(edits),
+      )]),
+    )]))
+  }
+
+  fn formatting(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+    let uri = params
+      .get("textDocument")
+      .get("uri")
+      .as_str()
+      .ok_or_else(|| (INVALID_PARAMS, "missing uri".to_owned()))?;
+    let doc = self
+      .docs
+      .get_mut(uri)
+      .ok_or_else(|| (INVALID_PARAMS, format!("unknown document: {}", uri)))?;
+    let canonical = doc.to_canonical_text();
+    if canonical.as_utfstr() == doc.text() {
+      return Ok(Json::Array(vec![]));
+    }
+    let lines = line_starts(doc.text());
+    Ok(Json::Array(vec![text_edit(
+      &lines,
+      0,
+      doc.text().len(),
+      &canonical,
+    )]))
+  }
+}
+
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_KEYWORD: usize = 14;
+
+fn initialize_result() -> Json {
+  Json::object([
+    (
+      "capabilities",
+      Json::object([
+        // full synchronization
+        ("textDocumentSync", 1usize.into()),
+        ("completionProvider", Json::object([])),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("renameProvider", true.into()),
+        ("documentFormattingProvider", true.into()),
+      ]),
+    ),
+    (
+      "serverInfo",
+      Json::object([
+        ("name", "gvb_lsp".into()),
+        ("version", env!("CARGO_PKG_VERSION").into()),
+      ]),
+    ),
+  ])
+}
+
+fn publish_diagnostics(uri: &str, diags: Vec<Json>) -> Json {
+  Json::object([
+    ("jsonrpc", "2.0".into()),
+    ("method", "textDocument/publishDiagnostics".into()),
+    (
+      "params",
+      Json::object([("uri", uri.into()), ("diagnostics", Json::Array(diags))]),
+    ),
+  ])
+}
+
+fn diagnostics_to_json(doc: &mut Document) -> Vec<Json> {
+  let mut diags = vec![];
+  for (line, line_diag) in doc.diagnostics().iter().enumerate() {
+    for diag in &line_diag.diagnostics {
+      let severity: usize = match diag.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+      };
+      diags.push(Json::object([
+        (
+          "range",
+          Json::object([
+            ("start", position(line, diag.range.start)),
+            ("end", position(line, diag.range.end)),
+          ]),
+        ),
+        ("severity", severity.into()),
+        ("source", "gvb".into()),
+        ("message", diag.message.as_str().into()),
+      ]));
+    }
+  }
+  diags
+}
+
+fn position(line: usize, character: usize) -> Json {
+  Json::object([("line", line.into()), ("character", character.into())])
+}
+
+fn text_edit(
+  lines: &[usize],
+  start: usize,
+  end: usize,
+  text: &Utf16Str,
+) -> Json {
+  Json::object([
+    ("range", range_to_json(lines, start, end)),
+    ("newText", text.to_string().into()),
+  ])
+}
+
+fn range_to_json(lines: &[usize], start: usize, end: usize) -> Json {
+  let (start_line, start_char) = offset_to_position(lines, start);
+  let (end_line, end_char) = offset_to_position(lines, end);
+  Json::object([
+    ("start", position(start_line, start_char)),
+    ("end", position(end_line, end_char)),
+  ])
+}
+
+/// Returns offsets of the starts of lines. Positions of the protocol count
+/// UTF-16 code units, the same as offsets of documents.
+fn line_starts(text: &Utf16Str) -> Vec<usize> {
+  let mut lines = vec![0];
+  for (i, &c) in text.as_slice().iter().enumerate() {
+    if c == b'\n' as u16 {
+      lines.push(i + 1);
+    }
+  }
+  lines
+}
+
+fn offset_to_position(lines: &[usize], offset: usize) -> (usize, usize) {
+  let line = match lines.binary_search(&offset) {
+    Ok(line) => line,
+    Err(line) => line - 1,
+  };
+  (line, offset - lines[line])
+}
+
+fn position_to_offset(text: &Utf16Str, line: usize, character: usize) -> usize {
+  let lines = line_starts(text);
+  match lines.get(line) {
+    Some(&start) => {
+      let end = lines.get(line + 1).map_or(text.len(), |&next| next - 1);
+      (start + character).min(end)
+    }
+    None => text.len(),
+  }
+}
+
+fn is_word_char(c: u16) -> bool {
+  c < 0x80 && ((c as u8).is_ascii_alphanumeric() || c == b'$' as u16)
+}
+
+fn word_start(text: &Utf16Str, offset: usize) -> usize {
+  let text = text.as_slice();
+  let mut start = offset;
+  while start > 0 && is_word_char(text[start - 1]) {
+    start -= 1;
+  }
+  start
+}
+
+fn word_end(text: &Utf16Str, offset: usize) -> usize {
+  let text = text.as_slice();
+  let mut end = offset;
+  while end < text.len() && is_word_char(text[end]) {
+    end += 1;
+  }
+  end
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use std::sync::Once;
+
+  static INIT: Once = Once::new();
+
+  const URI: &str = "file:///a.txt";
+
+  fn open(text: &str) -> (Server, Json) {
+    INIT.call_once(|| {
+      gvb_interp::machine::init_machines_from_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../gvb_interp/machines.yaml"
+      ))
+      .unwrap();
+    });
+    let mut server = Server::new();
+    let mut out = server.handle(&notification(
+      "textDocument/didOpen",
+      Json::object([(
+        "textDocument",
+        Json::object([
+          ("uri", URI.into()),
+          ("languageId", "gvbasic".into()),
+          ("version", 1usize.into()),
+          ("text", text.into()),
+        ]),
+      )]),
+    ));
+    assert_eq!(out.len(), 1);
+    (server, out.pop().unwrap())
+  }
+
+  fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+      ("jsonrpc", "2.0".into()),
+      ("method", method.into()),
+      ("params", params),
+    ])
+  }
+
+  fn request(server: &mut Server, method: &str, params: Json) -> Json {
+    let msg = Json::object([
+      ("jsonrpc", "2.0".into()),
+      ("id", 7usize.into()),
+      ("method", method.into()),
+      ("params", params),
+    ]);
+    let mut out = server.handle(&msg);
+    assert_eq!(out.len(), 1);
+    let response = out.pop().unwrap();
+    assert_eq!(response.get("id"), &Json::from(7usize));
+    response
+  }
+
+  fn at(line: usize, character: usize) -> Json {
+    Json::object([
+      ("textDocument", Json::object([("uri", URI.into())])),
+      ("position", position(line, character)),
+    ])
+  }
+
+  #[test]
+  fn lifecycle() {
+    let mut server = Server::new();
+    let response = request(&mut server, "initialize", Json::object([]));
+    assert_eq!(
+      response
+        .get("result")
+        .get("capabilities")
+        .get("textDocumentSync"),
+      &Json::from(1usize)
+    );
+    assert_eq!(server.handle(&notification("initialized", Json::Null)), []);
+    assert_eq!(server.exit_code(), None);
+    assert_eq!(
+      request(&mut server, "shutdown", Json::Null).get("result"),
+      &Json::Null
+    );
+    server.handle(&notification("exit", Json::Null));
+    assert_eq!(server.exit_code(), Some(0));
+
+    let response = request(&mut server, "foo", Json::Null);
+    assert_eq!(
+      response.get("error").get("code"),
+      &Json::from(METHOD_NOT_FOUND)
+    );
+  }
+
+  #[test]
+  fn diagnostics() {
+    let (mut server, diags) = open("10 print 1\n20 goto 40\n30 print (1");
+    assert_eq!(
+      diags.to_string(),
+      r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.txt","diagnostics":[{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":10}},"severity":1,"source":"gvb","message":"行号不存在"},{"range":{"start":{"line":2,"character":9},"end":{"line":2,"character":10}},"severity":1,"source":"gvb","message":"缺少匹配的右括号"}]}}"#
+    );
+
+    let out = server.handle(&notification(
+      "textDocument/didChange",
+      Json::object([
+        (
+          "textDocument",
+          Json::object([("uri", URI.into()), ("version", 2usize.into())]),
+        ),
+        (
+          "contentChanges",
+          Json::Array(vec![Json::object([("text", "10 end".into())])]),
+        ),
+      ]),
+    ));
+    assert_eq!(
+      out[0].get("params").get("diagnostics"),
+      &Json::Array(vec![])
+    );
+
+    let out = server.handle(&notification(
+      "textDocument/didClose",
+      Json::object([("textDocument", Json::object([("uri", URI.into())]))]),
+    ));
+    assert_eq!(
+      out[0].get("params").get("diagnostics"),
+      &Json::Array(vec![])
+    );
+    assert!(server.docs.is_empty());
+  }
+
+  #[test]
+  fn completion() {
+    let (mut server, _) = open("10 pri");
+    let response = request(&mut server, "textDocument/completion", at(0, 6));
+    let labels: Vec<_> = response
+      .get("result")
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|item| item.get("label").as_str().unwrap())
+      .collect();
+    assert_eq!(labels, ["PRINT"]);
+  }
+
+  #[test]
+  fn hover() {
+    let (mut server, _) = open("10 a$=mid$(\"abc\",2)");
+    let response = request(&mut server, "textDocument/hover", at(0, 8));
+    let result = response.get("result");
+    assert!(result
+      .get("contents")
+      .get("value")
+      .as_str()
+      .unwrap()
+      .starts_with("```\nMID$(X$, N[, M])\n```\n\n"));
+    assert_eq!(
+      result.get("range").to_string(),
+      r#"{"start":{"line":0,"character":6},"end":{"line":0,"character":10}}"#
+    );
+
+    let response = request(&mut server, "textDocument/hover", at(0, 3));
+    assert_eq!(response.get("result"), &Json::Null);
+
+    let (mut server, _) = open("10 poke 199,0");
+    let response = request(&mut server, "textDocument/hover", at(0, 9));
+    assert_eq!(
+      response.get("result").to_string(),
+      r#"{"contents":{"kind":"markdown","value":"地址 199\n- 按键缓冲区\n- RAM：零页"},"range":{"start":{"line":0,"character":8},"end":{"line":0,"character":11}}}"#
+    );
+  }
+
+  #[test]
+  fn definition() {
+    let (mut server, _) = open("10 goto 20\r\n20 end");
+    let response = request(&mut server, "textDocument/definition", at(0, 9));
+    assert_eq!(
+      response.get("result").to_string(),
+      r#"{"uri":"file:///a.txt","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":2}}}"#
+    );
+
+    let response = request(&mut server, "textDocument/definition", at(0, 4));
+    assert_eq!(response.get("result"), &Json::Null);
+  }
+
+  #[test]
+  fn rename() {
+    let (mut server, _) = open("10 goto 20\r\n20 goto 10");
+    let mut params = at(1, 1);
+    let Json::Object(members) = &mut params else {
+      unreachable!()
+    };
+    members.push(("newName".to_owned(), "100".into()));
+    let response = request(&mut server, "textDocument/rename", params.clone());
+    assert_eq!(
+      response.get("result").to_string(),
+      r#"{"changes":{"file:///a.txt":[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":2}},"newText":"100"},{"range":{"start":{"line":0,"character":8},"end":{"line":0,"character":10}},"newText":"100"}]}}"#
+    );
+
+    let Json::Object(members) = &mut params else {
+      unreachable!()
+    };
+    members.last_mut().unwrap().1 = "10".into();
+    let response = request(&mut server, "textDocument/rename", params);
+    assert_eq!(
+      response.get("error").to_string(),
+      r#"{"code":-32803,"message":"行号 10 已存在"}"#
+    );
+
+    let mut params = at(0, 1);
+    let Json::Object(members) = &mut params else {
+      unreachable!()
+    };
+    members.push(("newName".to_owned(), "30".into()));
+    let response = request(&mut server, "textDocument/rename", params);
+    assert_eq!(
+      response.get("error").to_string(),
+      r#"{"code":-32803,"message":"行号必须小于 20"}"#
+    );
+  }
+
+  #[test]
+  fn formatting() {
+    let (mut server, _) = open("10 print  a\n20 end\n");
+    let params =
+      Json::object([("textDocument", Json::object([("uri", URI.into())]))]);
+    let response =
+      request(&mut server, "textDocument/formatting", params.clone());
+    assert_eq!(
+      response.get("result").to_string(),
+      r#"[{"range":{"start":{"line":0,"character":0},"end":{"line":2,"character":0}},"newText":"10 PRINT A\r\n20 END\r\n"}]"#
+    );
+  }
+
+  #[test]
+  fn positions() {
+    let text = Utf16String::from_str("ab\r\n中\nc");
+    let lines = line_starts(&text);
+    assert_eq!(lines, [0, 4, 6]);
+    assert_eq!(offset_to_position(&lines, 3), (0, 3));
+    assert_eq!(offset_to_position(&lines, 5), (1, 1));
+    assert_eq!(offset_to_position(&lines, 7), (2, 1));
+    assert_eq!(position_to_offset(&text, 1, 1), 5);
+    assert_eq!(position_to_offset(&text, 1, 9), 5);
+    assert_eq!(position_to_offset(&text, 2, 9), 7);
+    assert_eq!(position_to_offset(&text, 5, 0), 7);
+  }
+}
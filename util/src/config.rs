@@ -7,14 +7,23 @@ use std::path::{Path, PathBuf};
 /// - working directory
 /// - executable path
 pub fn load_config_file<P>(p: P) -> io::Result<String>
+where
+  P: AsRef<Path>,
+{
+  std::fs::read_to_string(config_file_path(p)?)
+}
+
+/// Returns the path of the config file `p`, searched in the same order as
+/// `load_config_file`. If the file does not exist, the path in the executable
+/// directory is returned.
+pub fn config_file_path<P>(p: P) -> io::Result<PathBuf>
 where
   P: AsRef<Path>,
 {
   let p = p.as_ref();
-  let path = if fs::try_exists(p)? {
-    PathBuf::from(p)
+  if fs::try_exists(p)? {
+    Ok(PathBuf::from(p))
   } else {
-    env::current_exe()?.parent().unwrap().join(p)
-  };
-  std::fs::read_to_string(path)
+    Ok(env::current_exe()?.parent().unwrap().join(p))
+  }
 }
@@ -0,0 +1,38 @@
+use std::fmt::{self, Write};
+
+/// Writes `s` as a JSON string literal, with quotes.
+pub fn write_string<W>(out: &mut W, s: &str) -> fmt::Result
+where
+  W: Write + ?Sized,
+{
+  out.write_char('"')?;
+  for c in s.chars() {
+    match c {
+      '"' => out.write_str("\\\"")?,
+      '\\' => out.write_str("\\\\")?,
+      '\n' => out.write_str("\\n")?,
+      '\r' => out.write_str("\\r")?,
+      '\t' => out.write_str("\\t")?,
+      c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+      c => out.write_char(c)?,
+    }
+  }
+  out.write_char('"')
+}
+
+/// Returns `s` as a JSON string literal, with quotes.
+pub fn to_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  write_string(&mut out, s).unwrap();
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn escape() {
+    assert_eq!(to_string("a\"\\\n\u{1}好"), "\"a\\\"\\\\\\n\\u0001好\"");
+  }
+}
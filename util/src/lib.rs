@@ -1,3 +1,4 @@
 #![feature(fs_try_exists)]
 
 pub mod config;
+pub mod json;